@@ -0,0 +1,387 @@
+//! Python bindings of the replay and analysis API of
+//! [embedded-hal-vcd](embedded_hal_vcd).
+//!
+//! The module `embedded_hal_vcd_py` wraps the [VcdReader], [Waveform], the
+//! UART, SPI and I2C decoders and the bit rate, frequency and latency
+//! analyzers, so notebooks decode captures with the same code as the Rust
+//! tests. Times are integers in nanoseconds, pin states are the strings
+//! `"low"`, `"high"` and `"floating"` and decoded transactions are dicts:
+//!
+//! ```python
+//! import embedded_hal_vcd_py as vcd
+//!
+//! data = open("capture.vcd", "rb").read()
+//! tx = vcd.Waveform.read(data, ["top", "tx"])
+//! for byte in vcd.decode_uart(tx, vcd.detect_baud(tx)):
+//!     print(byte["time"], hex(byte["value"]))
+//! ```
+//!
+//! Build the module with `maturin develop` in this directory.
+
+use embedded_hal::digital::InputPin;
+use embedded_hal::spi::{MODE_0, MODE_1, MODE_2, MODE_3};
+use embedded_hal_vcd::analysis::{
+    BitRate, FrequencySeries, I2cDecoder, LatencyAnalyzer, SpiDecoder, Uart, UartEvent, Waveform,
+};
+use embedded_hal_vcd::devices::Edge;
+use embedded_hal_vcd::pins::{self, PinState};
+use embedded_hal_vcd::reader;
+use embedded_time::duration::*;
+use pyo3::exceptions::{PyKeyError, PyRuntimeError, PyValueError};
+use pyo3::prelude::*;
+use pyo3::types::{PyBytes, PyDict};
+use std::io::Cursor;
+
+fn state_name(state: &PinState) -> &'static str {
+    match state {
+        PinState::Low => "low",
+        PinState::High => "high",
+        PinState::Floating => "floating",
+    }
+}
+
+fn parse_state(name: &str) -> PyResult<PinState> {
+    match name {
+        "low" => Ok(PinState::Low),
+        "high" => Ok(PinState::High),
+        "floating" => Ok(PinState::Floating),
+        _ => Err(PyValueError::new_err(format!("unknown pin state {}", name))),
+    }
+}
+
+fn parse_edge(name: &str) -> PyResult<Edge> {
+    match name {
+        "rising" => Ok(Edge::Rising),
+        "falling" => Ok(Edge::Falling),
+        "released" => Ok(Edge::Released),
+        _ => Err(PyValueError::new_err(format!("unknown edge {}", name))),
+    }
+}
+
+fn pin_error<E: std::fmt::Debug>(error: E) -> PyErr {
+    PyRuntimeError::new_err(format!("pin error: {:?}", error))
+}
+
+/// The changes of a signal of a capture.
+#[pyclass(name = "Waveform", frozen)]
+struct PyWaveform(Waveform);
+
+#[pymethods]
+impl PyWaveform {
+    /// Create a waveform from a list of `(time, state)` changes.
+    #[new]
+    fn new(changes: Vec<(u64, String)>) -> PyResult<Self> {
+        let changes = changes
+            .into_iter()
+            .map(|(t, state)| Ok((Nanoseconds(t), parse_state(&state)?)))
+            .collect::<PyResult<_>>()?;
+        Ok(PyWaveform(Waveform::new(changes)))
+    }
+
+    /// Read the waveform of a scalar variable of a VCD file.
+    #[staticmethod]
+    fn read(data: &[u8], path: Vec<String>) -> PyResult<Self> {
+        Ok(PyWaveform(Waveform::read(data, &path)?))
+    }
+
+    /// Read the waveforms of all scalar variables with their full names.
+    #[staticmethod]
+    fn read_all(data: &[u8]) -> PyResult<Vec<(String, Self)>> {
+        let signals = Waveform::read_all(data)?;
+        Ok(signals
+            .into_iter()
+            .map(|(name, waveform)| (name, PyWaveform(waveform)))
+            .collect())
+    }
+
+    /// Return the `(time, state)` changes.
+    fn changes(&self) -> Vec<(u64, &'static str)> {
+        self.0
+            .changes()
+            .iter()
+            .map(|(t, state)| (t.0, state_name(state)))
+            .collect()
+    }
+
+    /// Return the state at a time, `None` before the first change.
+    fn state_at(&self, time: u64) -> Option<&'static str> {
+        self.0.state_at(Nanoseconds(time)).as_ref().map(state_name)
+    }
+
+    /// Return the `(start, width)` of the complete pulses of a state.
+    fn pulses(&self, state: &str) -> PyResult<Vec<(u64, u64)>> {
+        let pulses = self.0.pulses(parse_state(state)?);
+        Ok(pulses.iter().map(|(t, width)| (t.0, width.0)).collect())
+    }
+
+    fn __len__(&self) -> usize {
+        self.0.changes().len()
+    }
+}
+
+/// An input pin of a replay.
+#[pyclass(name = "Pin", unsendable)]
+struct PyPin(pins::InputPin);
+
+#[pymethods]
+impl PyPin {
+    /// Return whether the pin is high.
+    fn is_high(&mut self) -> PyResult<bool> {
+        self.0.is_high().map_err(pin_error)
+    }
+
+    /// Return whether the pin is low.
+    fn is_low(&mut self) -> PyResult<bool> {
+        self.0.is_low().map_err(pin_error)
+    }
+}
+
+/// A replay of a VCD file, an iterator of its timestamps in nanoseconds.
+///
+/// The pins have the values of a timestamp after the next one was read.
+#[pyclass(name = "VcdReader", unsendable)]
+struct PyVcdReader(reader::VcdReader<Cursor<Vec<u8>>>);
+
+#[pymethods]
+impl PyVcdReader {
+    /// Parse the header of a VCD file.
+    #[new]
+    fn new(data: Vec<u8>) -> PyResult<Self> {
+        Ok(PyVcdReader(reader::VcdReader::new(Cursor::new(data))?))
+    }
+
+    /// Return an input pin for a scalar variable.
+    fn pin(&mut self, path: Vec<String>) -> PyResult<PyPin> {
+        let pin = self.0.get_pin(&path);
+        pin.map(PyPin)
+            .ok_or_else(|| PyKeyError::new_err(path.join(".")))
+    }
+
+    /// Return the last timestamp read, `None` before the first one.
+    #[getter]
+    fn time(&self) -> Option<u64> {
+        let time = self.0.time()?;
+        Nanoseconds::<u64>::try_from(time).ok().map(|t| t.0)
+    }
+
+    fn __iter__(slf: PyRef<'_, Self>) -> PyRef<'_, Self> {
+        slf
+    }
+
+    fn __next__(&mut self) -> Option<u64> {
+        let time = self.0.next()?;
+        Nanoseconds::<u64>::try_from(time).ok().map(|t| t.0)
+    }
+}
+
+/// Decode the bytes and breaks of a UART line with 8 data bits and no
+/// parity.
+///
+/// Bytes are dicts with `time`, `value` and `stop`, which is `False` for a
+/// framing error, breaks are dicts with `time` and `width`.
+#[pyfunction]
+#[pyo3(signature = (line, baud, stop_bits = 1))]
+fn decode_uart<'py>(
+    py: Python<'py>,
+    line: &PyWaveform,
+    baud: u32,
+    stop_bits: u8,
+) -> PyResult<Vec<Bound<'py, PyDict>>> {
+    if baud == 0 {
+        return Err(PyValueError::new_err("the baud rate must not be 0"));
+    }
+    let uart = Uart::new(baud).with_stop_bits(stop_bits);
+    uart.decode(&line.0)
+        .into_iter()
+        .map(|event| {
+            let dict = PyDict::new(py);
+            match event {
+                UartEvent::Byte(byte) => {
+                    dict.set_item("time", byte.time.0)?;
+                    dict.set_item("value", byte.value)?;
+                    dict.set_item("stop", byte.stop)?;
+                }
+                UartEvent::Break { time, width } => {
+                    dict.set_item("time", time.0)?;
+                    dict.set_item("width", width.0)?;
+                }
+            }
+            Ok(dict)
+        })
+        .collect()
+}
+
+/// Decode the frames of an SPI bus in modes 0 to 3.
+///
+/// Frames are dicts with `start`, `end` and the bytes of `mosi` and `miso`.
+/// Without `miso` its bytes are read from an idle line.
+#[pyfunction]
+#[pyo3(signature = (sck, mosi, cs, miso = None, mode = 0))]
+fn decode_spi<'py>(
+    py: Python<'py>,
+    sck: &PyWaveform,
+    mosi: &PyWaveform,
+    cs: &PyWaveform,
+    miso: Option<&PyWaveform>,
+    mode: u8,
+) -> PyResult<Vec<Bound<'py, PyDict>>> {
+    let mode = match mode {
+        0 => MODE_0,
+        1 => MODE_1,
+        2 => MODE_2,
+        3 => MODE_3,
+        _ => return Err(PyValueError::new_err("the SPI mode must be 0 to 3")),
+    };
+    let idle = Waveform::default();
+    let miso = miso.map_or(&idle, |miso| &miso.0);
+    SpiDecoder::new(mode)
+        .decode(&sck.0, &mosi.0, miso, &cs.0)
+        .into_iter()
+        .map(|frame| {
+            let dict = PyDict::new(py);
+            dict.set_item("start", frame.start.0)?;
+            dict.set_item("end", frame.end.0)?;
+            dict.set_item("mosi", PyBytes::new(py, &frame.mosi))?;
+            dict.set_item("miso", PyBytes::new(py, &frame.miso))?;
+            Ok(dict)
+        })
+        .collect()
+}
+
+/// Decode the transfers of an I2C bus.
+///
+/// Transfers are dicts with `time`, the 7-bit `address`, `read`,
+/// `address_ack`, the `data` bytes, the `acks` of the bytes and `stop`,
+/// which is `False` for a repeated start.
+#[pyfunction]
+fn decode_i2c<'py>(
+    py: Python<'py>,
+    scl: &PyWaveform,
+    sda: &PyWaveform,
+) -> PyResult<Vec<Bound<'py, PyDict>>> {
+    I2cDecoder::new()
+        .decode(&scl.0, &sda.0)
+        .into_iter()
+        .map(|transfer| {
+            let dict = PyDict::new(py);
+            let acks: Vec<bool> = transfer.data.iter().map(|(_, ack)| *ack).collect();
+            dict.set_item("time", transfer.time.0)?;
+            dict.set_item("address", transfer.address)?;
+            dict.set_item("read", transfer.read)?;
+            dict.set_item("address_ack", transfer.address_ack)?;
+            dict.set_item("data", PyBytes::new(py, &transfer.bytes()))?;
+            dict.set_item("acks", acks)?;
+            dict.set_item("stop", transfer.stop)?;
+            Ok(dict)
+        })
+        .collect()
+}
+
+/// Detect the baud rate of a serial line from its shortest pulses, `None`
+/// if the line has too few changes.
+#[pyfunction]
+fn detect_baud(line: &PyWaveform) -> Option<u32> {
+    BitRate::detect(&line.0).map(|rate| rate.baud())
+}
+
+/// Return the `(time, frequency)` of each period between edges of a kind,
+/// in Hz.
+#[pyfunction]
+#[pyo3(signature = (waveform, edge = "rising"))]
+fn frequencies(waveform: &PyWaveform, edge: &str) -> PyResult<Vec<(u64, f64)>> {
+    let series = FrequencySeries::measure(&waveform.0, parse_edge(edge)?);
+    Ok(series.frequencies().map(|(t, hz)| (t.0, hz)).collect())
+}
+
+/// Return the `(time, latency)` from each trigger edge to the next response
+/// edge, any edge if no kind is given.
+#[pyfunction]
+#[pyo3(signature = (trigger, response, trigger_edge = None, response_edge = None))]
+fn latencies(
+    trigger: &PyWaveform,
+    response: &PyWaveform,
+    trigger_edge: Option<&str>,
+    response_edge: Option<&str>,
+) -> PyResult<Vec<(u64, u64)>> {
+    let mut analyzer = LatencyAnalyzer::new();
+    if let Some(edge) = trigger_edge {
+        analyzer = analyzer.with_trigger_edge(parse_edge(edge)?);
+    }
+    if let Some(edge) = response_edge {
+        analyzer = analyzer.with_response_edge(parse_edge(edge)?);
+    }
+    let latencies = analyzer.analyze(&trigger.0, &response.0);
+    Ok(latencies.samples.iter().map(|(t, l)| (t.0, l.0)).collect())
+}
+
+#[pymodule]
+fn embedded_hal_vcd_py(module: &Bound<'_, PyModule>) -> PyResult<()> {
+    module.add_class::<PyWaveform>()?;
+    module.add_class::<PyPin>()?;
+    module.add_class::<PyVcdReader>()?;
+    module.add_function(wrap_pyfunction!(decode_uart, module)?)?;
+    module.add_function(wrap_pyfunction!(decode_spi, module)?)?;
+    module.add_function(wrap_pyfunction!(decode_i2c, module)?)?;
+    module.add_function(wrap_pyfunction!(detect_baud, module)?)?;
+    module.add_function(wrap_pyfunction!(frequencies, module)?)?;
+    module.add_function(wrap_pyfunction!(latencies, module)?)?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use pyo3::ffi::c_str;
+
+    #[test]
+    fn python_api() {
+        let vcd = std::fs::read(concat!(
+            env!("CARGO_MANIFEST_DIR"),
+            "/../examples/data/test.vcd"
+        ));
+        Python::attach(|py| {
+            let module = pyo3::wrap_pymodule!(embedded_hal_vcd_py)(py);
+            let globals = PyDict::new(py);
+            globals.set_item("vcd", module).unwrap();
+            globals
+                .set_item("data", PyBytes::new(py, &vcd.unwrap()))
+                .unwrap();
+            py.run(
+                c_str!(
+                    r#"
+line = vcd.Waveform.read(data, ["libsigrok", "data"])
+assert line.changes()[:2] == [(0, "high"), (5000, "low")], line.changes()[:2]
+baud = vcd.detect_baud(line)
+bytes = vcd.decode_uart(line, baud)
+assert [b["value"] for b in bytes[:2]] == [0x35, 0x66]
+assert bytes[0]["time"] == 5000 and bytes[0]["stop"]
+
+reader = vcd.VcdReader(data)
+pin = reader.pin(["libsigrok", "data"])
+assert next(reader) == 0 and reader.time == 0
+next(reader)
+assert pin.is_high() and not pin.is_low()
+try:
+    reader.pin(["libsigrok", "missing"])
+    assert False
+except KeyError:
+    pass
+
+clk = vcd.Waveform([(t * 500, "high" if t % 2 else "low") for t in range(9)])
+sda = vcd.Waveform([(0, "high")])
+assert vcd.frequencies(clk)[0][1] == 1e6
+assert vcd.latencies(clk, clk, "rising", "falling")[0] == (500, 500)
+assert vcd.decode_i2c(clk, sda) == []
+sck = vcd.Waveform([(t * 500, "high" if t % 2 else "low") for t in range(17)])
+frames = vcd.decode_spi(sck, sda, vcd.Waveform([(0, "high"), (100, "low"), (8100, "high")]))
+assert frames[0]["mosi"] == b"\xff", frames
+"#
+                ),
+                Some(&globals),
+                None,
+            )
+            .map_err(|e| e.display(py))
+            .unwrap();
+        });
+    }
+}
@@ -0,0 +1,439 @@
+//! Analyze recorded signals.
+//!
+//! A [Waveform] holds the changes of a single signal, e.g. read from a VCD
+//! file recorded with a [VcdWriter](crate::writer::VcdWriter). The analyzers
+//! in this module check waveforms against the timing of common peripherals
+//! and extract the values they encode.
+
+use crate::pins::*;
+use crate::reader::{timestamp_to_ns, SharedRead, TimestampError};
+use core::borrow::Borrow;
+use embedded_time::duration::*;
+use std::io::Result as IOResult;
+use std::io::{Error, ErrorKind, Read};
+
+mod align;
+mod audio;
+mod baud;
+mod ber;
+mod dali;
+mod dmx;
+mod frequency;
+mod i2c;
+mod ir;
+mod jtag;
+mod latency;
+mod lin;
+mod manchester;
+mod ps2;
+mod sd;
+mod sent;
+mod sequence;
+mod serial;
+mod servo;
+mod smbus;
+mod spi;
+mod stepper;
+mod swd;
+mod uart;
+mod watchdog;
+
+pub use align::{align, find_offset, Alignment};
+pub use audio::{I2s, PcmFrame, Pdm};
+pub use baud::{Baud, BitRate};
+pub use ber::{BitComparator, BitError, BitErrors};
+pub use dali::{Dali, DaliFrame, DaliFrameKind, DaliViolation};
+pub use dmx::{Dmx512, DmxPacket, DmxViolation};
+pub use frequency::FrequencySeries;
+pub use i2c::{I2cDecoder, I2cTransfer};
+pub use ir::{IrCommand, IrDecoder, IrEncoder, IrFrame, IrProtocol};
+pub use jtag::{JtagDecoder, JtagScan, TapState};
+pub use latency::{Latencies, LatencyAnalyzer};
+pub use lin::{lin_pid, LinChecksum, LinDecoder, LinFrame};
+pub use manchester::{Manchester, ManchesterCoding, ManchesterFrame};
+pub use ps2::{Ps2Decoder, Ps2Frame, Ps2Key};
+pub use sd::{sd_crc16, sd_crc7, SdBlock, SdCommand, SdDecoder};
+pub use sent::{sent_crc, SentDecoder, SentFrame};
+pub use sequence::{PowerSequence, SequenceViolation};
+pub use serial::{BitOrder, SerialConfig};
+pub use servo::{ServoAnalyzer, ServoPosition, ServoViolation};
+pub use smbus::{smbus_pec, SmbusDecoder, SmbusExpect, SmbusMismatch, SmbusTransaction};
+pub use spi::{SpiDecoder, SpiFrame, SpiReplay, SpiReplayBus, SpiReplayError, SpiWords};
+pub use stepper::{StepperAnalyzer, StepperPosition, StepperViolation};
+pub use swd::{SwdAck, SwdDecoder, SwdEvent, SwdTransaction};
+pub use uart::{Uart, UartByte, UartEvent};
+pub use watchdog::{WatchdogMonitor, WatchdogViolation};
+
+/// The changes of a single signal over time.
+#[derive(Clone, Debug, Default, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct Waveform {
+    #[cfg_attr(feature = "serde", serde(with = "crate::pins::serde_states::changes"))]
+    changes: Vec<(Nanoseconds<u64>, PinState)>,
+}
+
+impl Waveform {
+    /// Create a new waveform from a list of changes ordered by time.
+    ///
+    /// Changes that do not change the state are removed.
+    pub fn new(changes: Vec<(Nanoseconds<u64>, PinState)>) -> Self {
+        let mut waveform = Waveform::default();
+        for (t, state) in changes {
+            waveform.push(t, state);
+        }
+        waveform
+    }
+
+    /// Read the waveform of a named variable from a VCD file.
+    pub fn read<R, S>(read: R, path: &[S]) -> IOResult<Self>
+    where
+        R: Read,
+        S: Borrow<str>,
+    {
+        let input = SharedRead::new(read);
+        let mut parser = vcd::Parser::new(input.clone());
+        let header = parser.parse_header().map_err(|e| input.error(e, None))?;
+        let code = header
+            .find_var(path)
+            .ok_or_else(|| Error::new(ErrorKind::NotFound, "variable not found"))?
+            .code;
+        let to_ns = |t: u64| timestamp_to_ns(header.timescale, t);
+        let mut waveform = Waveform::default();
+        let mut time = Nanoseconds(0);
+        let mut previous = None;
+        for command in parser {
+            match command.map_err(|e| input.error(e, previous))? {
+                vcd::Command::Timestamp(t) => {
+                    previous = check_order(previous, t)?;
+                    time = to_ns(t)?;
+                }
+                vcd::Command::ChangeScalar(id, value) if id == code => {
+                    waveform.push(time, value.into());
+                }
+                _ => {}
+            }
+        }
+        Ok(waveform)
+    }
+
+    /// Read the waveforms of all scalar variables from a VCD file.
+    ///
+    /// The waveforms are returned with the full path of the variable, the
+    /// scopes separated by dots, in the order of the VCD header.
+    pub fn read_all<R: Read>(read: R) -> IOResult<Vec<(String, Self)>> {
+        let input = SharedRead::new(read);
+        let mut parser = vcd::Parser::new(input.clone());
+        let header = parser.parse_header().map_err(|e| input.error(e, None))?;
+        let mut vars = vec![];
+        scalar_vars(&header.items, "", &mut vars);
+        let to_ns = |t: u64| timestamp_to_ns(header.timescale, t);
+        let mut waveforms = vec![Waveform::default(); vars.len()];
+        let mut time = Nanoseconds(0);
+        let mut previous = None;
+        for command in parser {
+            match command.map_err(|e| input.error(e, previous))? {
+                vcd::Command::Timestamp(t) => {
+                    previous = check_order(previous, t)?;
+                    time = to_ns(t)?;
+                }
+                vcd::Command::ChangeScalar(id, value) => {
+                    for (i, _) in vars.iter().enumerate().filter(|(_, (_, code))| *code == id) {
+                        waveforms[i].push(time, value.into());
+                    }
+                }
+                _ => {}
+            }
+        }
+        Ok(vars
+            .into_iter()
+            .map(|(path, _)| path)
+            .zip(waveforms)
+            .collect())
+    }
+
+    /// Write named waveforms to a VCD file, e.g. to replay them with a
+    /// [VcdReader](crate::reader::VcdReader).
+    ///
+    /// The dots in the names separate the scopes, like the names returned by
+    /// [read_all](Waveform::read_all).
+    pub fn write_all<W: std::io::Write>(signals: &[(String, Self)], output: W) -> IOResult<()> {
+        let mut header = vcd::Header::default();
+        header.timescale = Some((1, vcd::TimescaleUnit::NS));
+        let mut changes = vec![];
+        for (index, (name, waveform)) in signals.iter().enumerate() {
+            let code = vcd::IdCode::from(index as u64);
+            let path: Vec<&str> = name.split('.').collect();
+            let (reference, scopes) = path.split_last().unwrap();
+            let var = vcd::Var {
+                var_type: vcd::VarType::Wire,
+                size: 1,
+                code,
+                reference: reference.to_string(),
+                index: None,
+            };
+            crate::export::jsonl::insert_var(&mut header.items, scopes, var);
+            changes.extend(waveform.changes.iter().map(|(t, s)| (*t, code, s.clone())));
+        }
+        changes.sort_by_key(|(t, _, _)| *t);
+        let mut writer = vcd::Writer::new(output);
+        writer.header(&header)?;
+        let mut time = None;
+        for (t, code, state) in changes {
+            if time != Some(t) {
+                writer.timestamp(t.0)?;
+                time = Some(t);
+            }
+            writer.change_scalar(code, state)?;
+        }
+        Ok(())
+    }
+
+    fn push(&mut self, t: Nanoseconds<u64>, state: PinState) {
+        if self
+            .changes
+            .last()
+            .map(|(_, s)| *s != state)
+            .unwrap_or(true)
+        {
+            self.changes.push((t, state));
+        }
+    }
+
+    /// Return the changes of the waveform.
+    pub fn changes(&self) -> &[(Nanoseconds<u64>, PinState)] {
+        &self.changes
+    }
+
+    /// Return the state at time `t`, `None` before the first change.
+    pub fn state_at(&self, t: Nanoseconds<u64>) -> Option<PinState> {
+        let index = self.changes.partition_point(|(time, _)| *time <= t);
+        index.checked_sub(1).map(|i| self.changes[i].1.clone())
+    }
+
+    /// Return the waveform with `offset` added to the time of all changes.
+    ///
+    /// Changes that would be before time zero are moved to time zero.
+    pub fn shifted(&self, offset: i64) -> Self {
+        let mut shifted = Waveform::default();
+        for (t, state) in self.changes.iter() {
+            let t = Nanoseconds((t.0 as i128 + offset as i128).max(0) as u64);
+            if shifted
+                .changes
+                .last()
+                .map(|(last, _)| *last == t)
+                .unwrap_or(false)
+            {
+                shifted.changes.pop();
+            }
+            shifted.push(t, state.clone());
+        }
+        shifted
+    }
+
+    /// Return the start and width of all complete pulses with the given state.
+    pub fn pulses(&self, state: PinState) -> Vec<(Nanoseconds<u64>, Nanoseconds<u64>)> {
+        self.changes
+            .windows(2)
+            .filter(|w| w[0].1 == state)
+            .map(|w| (w[0].0, Nanoseconds(w[1].0 .0 - w[0].0 .0)))
+            .collect()
+    }
+}
+
+/// Return the timestamp as the new previous one, or an error if it is before
+/// the previous one.
+fn check_order(previous: Option<u64>, timestamp: u64) -> Result<Option<u64>, TimestampError> {
+    match previous {
+        Some(previous) if timestamp < previous => Err(TimestampError::Backwards {
+            timestamp,
+            previous,
+        }),
+        _ => Ok(Some(timestamp)),
+    }
+}
+
+/// Report the decoded transactions of a decoder as `tracing` events in a
+/// `decode` span.
+#[cfg(feature = "tracing")]
+fn traced<T: core::fmt::Debug>(decoder: &str, transactions: Vec<T>) -> Vec<T> {
+    let _span = tracing::debug_span!("decode", decoder, count = transactions.len()).entered();
+    for transaction in &transactions {
+        tracing::debug!(?transaction);
+    }
+    transactions
+}
+
+#[cfg(not(feature = "tracing"))]
+fn traced<T>(_decoder: &str, transactions: Vec<T>) -> Vec<T> {
+    transactions
+}
+
+/// Collect the full paths and identifiers of all scalar variables.
+fn scalar_vars(items: &[vcd::ScopeItem], prefix: &str, vars: &mut Vec<(String, vcd::IdCode)>) {
+    for item in items {
+        match item {
+            vcd::ScopeItem::Var(var)
+                if var.size == 1
+                    && !matches!(
+                        var.var_type,
+                        vcd::VarType::String | vcd::VarType::Real | vcd::VarType::Event
+                    ) =>
+            {
+                vars.push((format!("{}{}", prefix, var.reference), var.code))
+            }
+            vcd::ScopeItem::Var(_) => {}
+            vcd::ScopeItem::Scope(scope) => {
+                let prefix = format!("{}{}.", prefix, scope.identifier);
+                scalar_vars(&scope.children, &prefix, vars);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn read_waveform() {
+        let vcd = "
+$timescale 10us $end
+$scope module logic $end
+$var wire 1 t test $end
+$var wire 1 u other $end
+$upscope $end
+$enddefinitions $end
+#0
+0t
+0u
+#1
+1t
+1u
+#3
+1t
+#4
+0t
+"
+        .as_bytes();
+        let waveform = Waveform::read(vcd, &["logic", "test"]).unwrap();
+        assert_eq!(
+            waveform.changes(),
+            &[
+                (Nanoseconds(0), PinState::Low),
+                (Nanoseconds(10_000), PinState::High),
+                (Nanoseconds(40_000), PinState::Low),
+            ]
+        );
+        assert_eq!(waveform.state_at(Nanoseconds(39_999)), Some(PinState::High));
+        assert_eq!(
+            waveform.pulses(PinState::High),
+            vec![(Nanoseconds(10_000), Nanoseconds(30_000))]
+        );
+        let all = Waveform::read_all(vcd).unwrap();
+        assert_eq!(all.len(), 2);
+        assert_eq!(all[0], ("logic.test".to_string(), waveform));
+        assert_eq!(all[1].1.changes().len(), 2);
+    }
+
+    #[cfg(feature = "tracing")]
+    #[test]
+    fn traced_decoders() {
+        use std::fmt;
+        use std::sync::{Arc, Mutex};
+        use tracing::field::{Field, Visit};
+        use tracing::span::{Attributes, Id, Record};
+        use tracing::{Event, Metadata};
+
+        /// Collect the spans and events as lines of their fields.
+        #[derive(Clone, Default)]
+        struct Lines(Arc<Mutex<Vec<String>>>);
+
+        struct Fields(String);
+
+        impl Visit for Fields {
+            fn record_debug(&mut self, field: &Field, value: &dyn fmt::Debug) {
+                if !self.0.is_empty() {
+                    self.0.push(' ');
+                }
+                self.0 += &format!("{}={:?}", field.name(), value);
+            }
+        }
+
+        impl tracing::Subscriber for Lines {
+            fn enabled(&self, _metadata: &Metadata) -> bool {
+                true
+            }
+            fn new_span(&self, span: &Attributes) -> Id {
+                let mut fields = Fields(format!("{}:", span.metadata().name()));
+                span.record(&mut fields);
+                self.0.lock().unwrap().push(fields.0);
+                Id::from_u64(1)
+            }
+            fn record(&self, _span: &Id, _values: &Record) {}
+            fn record_follows_from(&self, _span: &Id, _follows: &Id) {}
+            fn event(&self, event: &Event) {
+                let mut fields = Fields(String::new());
+                event.record(&mut fields);
+                self.0.lock().unwrap().push(fields.0);
+            }
+            fn enter(&self, _span: &Id) {}
+            fn exit(&self, _span: &Id) {}
+        }
+
+        let uart = Uart::new(1_000_000);
+        let waveform = uart.encode(&[UartEvent::Break {
+            time: Nanoseconds(1_000),
+            width: Nanoseconds(20_000),
+        }]);
+        let lines = Lines::default();
+        tracing::subscriber::with_default(lines.clone(), || uart.decode(&waveform));
+        pretty_assertions::assert_eq!(
+            *lines.0.lock().unwrap(),
+            vec![
+                "decode: decoder=\"uart\" count=1",
+                "transaction=Break { time: Nanoseconds(1000), width: Nanoseconds(20000) }",
+            ]
+        );
+    }
+
+    #[cfg(feature = "serde")]
+    #[test]
+    fn json_reports() {
+        let uart = Uart::new(1_000_000);
+        let waveform = Waveform::new(vec![
+            (Nanoseconds(0), PinState::High),
+            (Nanoseconds(1_000), PinState::Low),
+            (Nanoseconds(3_000), PinState::High),
+            (Nanoseconds(4_000), PinState::Low),
+            (Nanoseconds(5_000), PinState::High),
+            (Nanoseconds(6_000), PinState::Low),
+            (Nanoseconds(7_000), PinState::High),
+        ]);
+        let events = uart.decode(&waveform);
+        let json = serde_json::to_string(&events).unwrap();
+        assert_eq!(json, r#"[{"Byte":{"time":1000,"value":234,"stop":true}}]"#);
+        assert_eq!(
+            serde_json::from_str::<Vec<UartEvent>>(&json).unwrap(),
+            events
+        );
+
+        let json = serde_json::to_string(&waveform).unwrap();
+        assert_eq!(
+            json,
+            r#"{"changes":[[0,"High"],[1000,"Low"],[3000,"High"],[4000,"Low"],[5000,"High"],[6000,"Low"],[7000,"High"]]}"#
+        );
+        assert_eq!(serde_json::from_str::<Waveform>(&json).unwrap(), waveform);
+
+        // the data sampled with itself as clock is always high
+        let reference = Waveform::new(vec![(Nanoseconds(0), PinState::Low)]);
+        let comparator = BitComparator::new(crate::devices::Edge::Rising);
+        let errors = comparator.compare(&waveform, &waveform, &reference);
+        let json = serde_json::to_string(&errors.errors[0]).unwrap();
+        assert_eq!(
+            json,
+            r#"{"time":3000,"index":0,"expected":"Low","actual":"High"}"#
+        );
+        let json = serde_json::to_string(&errors).unwrap();
+        assert_eq!(serde_json::from_str::<BitErrors>(&json).unwrap(), errors);
+    }
+}
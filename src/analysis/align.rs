@@ -0,0 +1,171 @@
+//! Automatic alignment of two captures of the same system.
+
+use super::Waveform;
+use embedded_time::duration::*;
+
+/// Return the agreement of `reference` and `other` shifted by `offset`.
+///
+/// The agreement is the time both waveforms have the same state minus the
+/// time they differ, from the first change until the last change of both.
+fn agreement(reference: &Waveform, other: &Waveform, offset: i64) -> i128 {
+    let shift = |t: Nanoseconds<u64>| t.0 as i128 + offset as i128;
+    let mut points: Vec<i128> = reference
+        .changes()
+        .iter()
+        .map(|(t, _)| t.0 as i128)
+        .chain(other.changes().iter().map(|(t, _)| shift(*t)))
+        .collect();
+    points.sort_unstable();
+    points.dedup();
+    let start = match (reference.changes().first(), other.changes().first()) {
+        (Some((a, _)), Some((b, _))) => (a.0 as i128).max(shift(*b)),
+        _ => return 0,
+    };
+    let mut score = 0;
+    for w in points.windows(2).filter(|w| w[0] >= start) {
+        let a = reference.state_at(Nanoseconds(w[0] as u64));
+        let b = match u64::try_from(w[0] - offset as i128) {
+            Ok(t) => other.state_at(Nanoseconds(t)),
+            Err(_) => None,
+        };
+        if a == b {
+            score += w[1] - w[0];
+        } else {
+            score -= w[1] - w[0];
+        }
+    }
+    score
+}
+
+/// Find the offset that aligns `other` with `reference` best.
+///
+/// The offset is added to the times of `other` and is at most `max_offset`
+/// in either direction. Only offsets that align an edge of `other` with an
+/// edge of the same direction in `reference` are tried, so a small
+/// `max_offset` keeps the search fast for long periodic signals. Returns
+/// `None` if a waveform has no changes.
+pub fn find_offset(
+    reference: &Waveform,
+    other: &Waveform,
+    max_offset: Nanoseconds<u64>,
+) -> Option<i64> {
+    if reference.changes().is_empty() || other.changes().is_empty() {
+        return None;
+    }
+    let max = max_offset.0 as i128;
+    let mut candidates = vec![0i64];
+    for (ta, sa) in reference.changes() {
+        for (tb, sb) in other.changes() {
+            let offset = ta.0 as i128 - tb.0 as i128;
+            if sa == sb && offset.abs() <= max {
+                candidates.push(offset as i64);
+            }
+        }
+    }
+    candidates.sort_unstable_by_key(|offset| (offset.unsigned_abs(), *offset));
+    candidates.dedup();
+    candidates
+        .into_iter()
+        .map(|offset| (agreement(reference, other, offset), offset))
+        .fold(
+            None,
+            |best: Option<(i128, i64)>, (score, offset)| match best {
+                Some((best_score, _)) if best_score >= score => best,
+                _ => Some((score, offset)),
+            },
+        )
+        .map(|(_, offset)| offset)
+}
+
+/// Two captures merged into one timeline.
+#[derive(Clone, Debug, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct Alignment {
+    /// The offset that was added to the times of the other capture.
+    pub offset: i64,
+    /// The signals of the reference capture followed by the shifted signals
+    /// of the other capture.
+    pub signals: Vec<(String, Waveform)>,
+}
+
+/// Align two captures on a signal that is contained in both.
+///
+/// The captures are sets of waveforms as returned by
+/// [Waveform::read_all], e.g. a simulation and a logic analyzer capture.
+/// The offset is found with [find_offset] on `signal`, the signals of
+/// `other` are shifted and prefixed with `prefix` in the merged view.
+/// Returns `None` if the signal is missing in one of the captures.
+pub fn align(
+    reference: Vec<(String, Waveform)>,
+    other: Vec<(String, Waveform)>,
+    signal: &str,
+    prefix: &str,
+    max_offset: Nanoseconds<u64>,
+) -> Option<Alignment> {
+    let find = |captures: &[(String, Waveform)]| {
+        captures
+            .iter()
+            .find(|(name, _)| name == signal)
+            .map(|(_, waveform)| waveform.clone())
+    };
+    let offset = find_offset(&find(&reference)?, &find(&other)?, max_offset)?;
+    let mut signals = reference;
+    signals.extend(
+        other
+            .into_iter()
+            .map(|(name, waveform)| (format!("{}{}", prefix, name), waveform.shifted(offset))),
+    );
+    Some(Alignment { offset, signals })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::pins::PinState;
+
+    #[test]
+    fn align_captures() {
+        let clock = |start: u64, count: u64| {
+            Waveform::new(
+                (0..count)
+                    .map(|i| {
+                        let state = if i % 2 == 0 {
+                            PinState::High
+                        } else {
+                            PinState::Low
+                        };
+                        // an irregular pattern to have a single best offset
+                        (Nanoseconds(start + i * 100 + (i % 3) * 30), state)
+                    })
+                    .collect(),
+            )
+        };
+        let reference = vec![("top.clk".to_string(), clock(1_000, 20))];
+        let other = vec![
+            ("clk".to_string(), clock(250, 20)),
+            (
+                "cs".to_string(),
+                Waveform::new(vec![(Nanoseconds(300), PinState::Low)]),
+            ),
+        ];
+        let alignment = align(reference, other, "top.clk", "la.", Nanoseconds(2_000));
+        assert!(alignment.is_none());
+
+        let reference = vec![("clk".to_string(), clock(1_000, 20))];
+        let other = vec![
+            ("clk".to_string(), clock(250, 20)),
+            (
+                "cs".to_string(),
+                Waveform::new(vec![(Nanoseconds(300), PinState::Low)]),
+            ),
+        ];
+        let alignment = align(reference, other, "clk", "la.", Nanoseconds(2_000)).unwrap();
+        assert_eq!(alignment.offset, 750);
+        assert_eq!(alignment.signals[1].1, alignment.signals[0].1);
+        assert_eq!(alignment.signals[2].0, "la.cs");
+        assert_eq!(
+            alignment.signals[2].1.changes(),
+            &[(Nanoseconds(1_050), PinState::Low)]
+        );
+    }
+}
@@ -0,0 +1,240 @@
+//! I2S and PDM audio signals converted from and to PCM samples.
+
+use super::Waveform;
+use crate::devices::Edge;
+use crate::pins::*;
+use embedded_time::duration::*;
+
+/// A stereo PCM sample.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct PcmFrame {
+    /// The start of the left word.
+    pub time: Nanoseconds<u64>,
+    /// The left sample, sign extended.
+    pub left: i32,
+    /// The right sample, sign extended.
+    pub right: i32,
+}
+
+/// An encoder and decoder for I2S audio with a bit clock (BCLK), a word
+/// select (LRCLK) and a data signal.
+///
+/// Data is sent MSB first in two's complement and sampled on the rising
+/// edges of the bit clock, word select is low for the left channel. In the
+/// Philips format the MSB follows one clock after the change of the word
+/// select. Bits beyond the sample width are ignored, so the slot may be
+/// wider than the samples.
+///
+/// ```
+/// use embedded_hal_vcd::analysis::I2s;
+/// use embedded_time::duration::*;
+///
+/// let i2s = I2s::new(16);
+/// let [bclk, lrclk, sd] = i2s.encode(Nanoseconds(300u64), &[(1000, -1000), (-32768, 32767)]);
+/// let frames = i2s.decode(&bclk, &lrclk, &sd);
+/// assert_eq!((frames[1].left, frames[1].right), (-32768, 32767));
+/// ```
+#[derive(Clone, Debug)]
+pub struct I2s {
+    bits: u8,
+    delay: usize,
+}
+
+impl I2s {
+    /// Create a new codec for samples of the given width in the Philips
+    /// format.
+    pub fn new(bits: u8) -> Self {
+        I2s { bits, delay: 1 }
+    }
+
+    /// Use the left justified format, the MSB is sent with the change of
+    /// the word select.
+    pub fn with_left_justified(mut self) -> Self {
+        self.delay = 0;
+        self
+    }
+
+    /// Generate the bit clock, word select and data signals of stereo
+    /// samples, with slots of the sample width and the given bit clock
+    /// period.
+    pub fn encode(&self, period: Nanoseconds<u64>, samples: &[(i32, i32)]) -> [Waveform; 3] {
+        let bits = self.bits as usize;
+        let level = |high: bool| if high { PinState::High } else { PinState::Low };
+        // an empty right word first, so the first left word starts with a
+        // change of the word select
+        let words: Vec<i32> = [0]
+            .into_iter()
+            .chain(samples.iter().flat_map(|(l, r)| [*l, *r]))
+            .collect();
+        // one more word select change terminates the last word
+        let clocks = (words.len() + 1) * bits + self.delay;
+        let (mut bclk, mut ws, mut sd) = (vec![], vec![], vec![]);
+        for n in 0..clocks {
+            let t = n as u64 * period.0;
+            bclk.push((Nanoseconds(t), PinState::Low));
+            bclk.push((Nanoseconds(t + period.0 / 2), PinState::High));
+            ws.push((Nanoseconds(t), level((n / bits).is_multiple_of(2))));
+            let bit = n
+                .checked_sub(self.delay)
+                .and_then(|k| {
+                    words
+                        .get(k / bits)
+                        .map(|word| word >> (bits - 1 - k % bits) & 1)
+                })
+                .unwrap_or(0);
+            sd.push((Nanoseconds(t), level(bit == 1)));
+        }
+        [bclk, ws, sd].map(Waveform::new)
+    }
+
+    /// Decode all complete stereo samples.
+    pub fn decode(&self, bclk: &Waveform, lrclk: &Waveform, sd: &Waveform) -> Vec<PcmFrame> {
+        let high = |waveform: &Waveform, t| waveform.state_at(t) == Some(PinState::High);
+        let edges: Vec<(Nanoseconds<u64>, bool, bool)> = bclk
+            .changes()
+            .iter()
+            .skip(1)
+            .filter(|(_, state)| Edge::to_state(state) == Edge::Rising)
+            .map(|(t, _)| (*t, high(lrclk, *t), high(sd, *t)))
+            .collect();
+        // the words with their channel, start time and bits
+        let mut words: Vec<(bool, Nanoseconds<u64>, Vec<bool>)> = vec![];
+        for i in self.delay..edges.len() {
+            let channel = edges[i - self.delay].1;
+            match words.last_mut() {
+                Some((last, _, bits)) if *last == channel => bits.push(edges[i].2),
+                _ => words.push((channel, edges[i].0, vec![edges[i].2])),
+            }
+        }
+        // the first word may be incomplete, the last one is not terminated
+        if !words.is_empty() {
+            words.remove(0);
+        }
+        words.pop();
+        let value = |bits: &[bool]| {
+            let word = bits
+                .iter()
+                .take(self.bits as usize)
+                .fold(0u32, |v, bit| v << 1 | *bit as u32);
+            let shift = 32 - self.bits.min(32) as u32;
+            (word << shift) as i32 >> shift
+        };
+        let frames = words
+            .windows(2)
+            .filter(|w| !w[0].0 && w[1].0)
+            .map(|w| PcmFrame {
+                time: w[0].1,
+                left: value(&w[0].2),
+                right: value(&w[1].2),
+            })
+            .collect();
+        super::traced("i2s", frames)
+    }
+}
+
+/// An encoder and decoder for pulse density modulated audio with a clock
+/// and a data signal, e.g. of a PDM microphone.
+///
+/// Decoding averages the bits of each block of `decimation` clocks, so a
+/// sample is the density of ones scaled to `-1.0..=1.0`. Encoding uses a
+/// first order sigma delta modulator.
+#[derive(Clone, Debug)]
+pub struct Pdm {
+    decimation: usize,
+    edge: Edge,
+}
+
+impl Pdm {
+    /// Create a new codec with the given number of clocks per sample, data
+    /// is sampled on the rising clock edges (left channel).
+    pub fn new(decimation: usize) -> Self {
+        Pdm {
+            decimation,
+            edge: Edge::Rising,
+        }
+    }
+
+    /// Sample the data on the falling clock edges (right channel).
+    pub fn with_falling_edge(mut self) -> Self {
+        self.edge = Edge::Falling;
+        self
+    }
+
+    /// Generate the clock and data signals of samples in `-1.0..=1.0` with
+    /// the given clock period.
+    pub fn encode(&self, period: Nanoseconds<u64>, samples: &[f32]) -> [Waveform; 2] {
+        let (mut clk, mut data) = (vec![], vec![]);
+        let mut error = 0.0;
+        let values = samples
+            .iter()
+            .flat_map(|sample| std::iter::repeat_n(sample.clamp(-1.0, 1.0), self.decimation));
+        for (n, value) in values.enumerate() {
+            let t = n as u64 * period.0;
+            let (first, second) = match self.edge {
+                Edge::Falling => (PinState::High, PinState::Low),
+                _ => (PinState::Low, PinState::High),
+            };
+            clk.push((Nanoseconds(t), first));
+            clk.push((Nanoseconds(t + period.0 / 2), second));
+            error += value;
+            let bit = error >= 0.0;
+            error -= if bit { 1.0 } else { -1.0 };
+            let state = if bit { PinState::High } else { PinState::Low };
+            data.push((Nanoseconds(t), state));
+        }
+        [clk, data].map(Waveform::new)
+    }
+
+    /// Decode all complete samples.
+    pub fn decode(&self, clk: &Waveform, data: &Waveform) -> Vec<f32> {
+        let bits: Vec<bool> = clk
+            .changes()
+            .iter()
+            .skip(1)
+            .filter(|(_, state)| Edge::to_state(state) == self.edge)
+            .map(|(t, _)| data.state_at(*t) == Some(PinState::High))
+            .collect();
+        bits.chunks_exact(self.decimation)
+            .map(|block| {
+                let ones = block.iter().filter(|bit| **bit).count();
+                (2 * ones) as f32 / self.decimation as f32 - 1.0
+            })
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn i2s_and_pdm() {
+        let samples: Vec<(i32, i32)> = (0..32)
+            .map(|i| {
+                let x = (i as f32 * 0.4).sin();
+                ((x * 8_000_000.0) as i32, (x * -8_388_608.0) as i32)
+            })
+            .collect();
+        for i2s in [I2s::new(24), I2s::new(24).with_left_justified()] {
+            let [bclk, lrclk, sd] = i2s.encode(Nanoseconds(326u64), &samples);
+            let frames = i2s.decode(&bclk, &lrclk, &sd);
+            let decoded: Vec<_> = frames.iter().map(|f| (f.left, f.right)).collect();
+            assert_eq!(decoded, samples);
+            assert_eq!(
+                frames[1].time,
+                Nanoseconds((72 + i2s.delay as u64) * 326 + 163)
+            );
+        }
+
+        let pdm = Pdm::new(64);
+        let samples: Vec<f32> = (0..100).map(|i| (i as f32 * 0.1).sin() * 0.8).collect();
+        let [clk, data] = pdm.encode(Nanoseconds(325u64), &samples);
+        let decoded = pdm.decode(&clk, &data);
+        assert_eq!(decoded.len(), samples.len());
+        assert!(decoded
+            .iter()
+            .zip(&samples)
+            .all(|(d, s)| (d - s).abs() < 0.05));
+    }
+}
@@ -0,0 +1,210 @@
+//! Bit rates estimated from recorded signals.
+
+use super::Waveform;
+use crate::devices::Edge;
+
+/// Common baud rates, a detected rate within 2% is reported as one of them.
+const STANDARD_RATES: [u32; 22] = [
+    300, 600, 1200, 2400, 4800, 9600, 10_400, 14_400, 19_200, 28_800, 38_400, 57_600, 76_800,
+    100_000, 115_200, 230_400, 250_000, 460_800, 500_000, 921_600, 1_000_000, 2_000_000,
+];
+
+/// The bit rate of a signal, estimated from the intervals between its edges.
+#[derive(Clone, Debug, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct BitRate {
+    /// The time of a bit in ns.
+    pub bit_time: f64,
+    /// The number of intervals the bit time was averaged over.
+    pub intervals: usize,
+    /// The closest common baud rate, if any is within 2%.
+    pub standard: Option<u32>,
+}
+
+impl BitRate {
+    /// Estimate the bit rate of a data signal, e.g. a UART line.
+    ///
+    /// The intervals between all edges are collected in a histogram. The
+    /// shortest interval that occurs in at least 2% of all intervals (and at
+    /// least twice) is taken as the bit time, so single glitches are
+    /// ignored. The estimate is refined with all intervals that are within
+    /// a quarter bit of a multiple of up to 12 bit times. Returns `None` if
+    /// the waveform has too few edges.
+    pub fn detect(waveform: &Waveform) -> Option<Self> {
+        let intervals: Vec<u64> = waveform
+            .changes()
+            .windows(2)
+            .skip(1)
+            .map(|w| w[1].0 .0 - w[0].0 .0)
+            .collect();
+        Self::estimate(intervals, 12)
+    }
+
+    /// Estimate the clock rate of a clock signal, e.g. the SCLK of SPI.
+    ///
+    /// Only the periods between consecutive rising edges are used, so the
+    /// duty cycle of the clock does not matter and pauses between bursts
+    /// are ignored.
+    pub fn detect_clock(clock: &Waveform) -> Option<Self> {
+        let rising: Vec<u64> = clock
+            .changes()
+            .iter()
+            .skip(1)
+            .filter(|(_, state)| Edge::to_state(state) == Edge::Rising)
+            .map(|(t, _)| t.0)
+            .collect();
+        Self::estimate(rising.windows(2).map(|w| w[1] - w[0]).collect(), 1)
+    }
+
+    fn estimate(mut intervals: Vec<u64>, max_multiple: u64) -> Option<Self> {
+        intervals.retain(|interval| *interval > 0);
+        intervals.sort_unstable();
+        let min_count = (intervals.len() / 50).max(2);
+        // the shortest cluster of intervals within ±20%
+        let unit = intervals.iter().enumerate().find_map(|(i, interval)| {
+            let end = intervals.partition_point(|d| *d * 5 <= *interval * 6);
+            (end - i >= min_count).then_some(*interval as f64)
+        })?;
+        let (mut sum, mut bits, mut count) = (0.0, 0, 0);
+        for interval in intervals.iter().map(|d| *d as f64) {
+            let multiple = (interval / unit).round() as u64;
+            if (1..=max_multiple).contains(&multiple)
+                && (interval - (multiple as f64 * unit)).abs() < unit * 0.25
+            {
+                sum += interval;
+                bits += multiple;
+                count += 1;
+            }
+        }
+        let bit_time = sum / bits as f64;
+        let rate = 1e9 / bit_time;
+        // slower rates would round to 0 bit/s
+        if rate < 0.5 {
+            return None;
+        }
+        let standard = STANDARD_RATES
+            .iter()
+            .copied()
+            .find(|standard| (rate - *standard as f64).abs() < *standard as f64 * 0.02);
+        Some(BitRate {
+            bit_time,
+            intervals: count,
+            standard,
+        })
+    }
+
+    /// Return the measured rate in bit/s.
+    pub fn rate(&self) -> f64 {
+        1e9 / self.bit_time
+    }
+
+    /// Return the common baud rate if one was found, the measured rate
+    /// rounded otherwise.
+    pub fn baud(&self) -> u32 {
+        self.standard.unwrap_or_else(|| self.rate().round() as u32)
+    }
+}
+
+/// The baud rate of a decoder, either known or detected from the signal.
+///
+/// ```
+/// use embedded_hal_vcd::analysis::{Baud, Uart, UartByte, UartEvent};
+/// use embedded_time::duration::*;
+///
+/// let rx = Uart::new(9600).encode(&[UartEvent::Byte(UartByte {
+///     time: Nanoseconds(1_000_000),
+///     value: 0x55,
+///     stop: true,
+/// })]);
+/// let uart = Uart::new(Baud::Auto.resolve(&rx).unwrap());
+/// assert_eq!(Baud::Auto.resolve(&rx), Some(9600));
+/// assert_eq!(uart.decode(&rx).len(), 1);
+/// ```
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum Baud {
+    /// A known baud rate.
+    Fixed(u32),
+    /// Detect the rate with [BitRate::detect].
+    Auto,
+}
+
+impl From<u32> for Baud {
+    fn from(baud: u32) -> Self {
+        Baud::Fixed(baud)
+    }
+}
+
+impl Baud {
+    /// Return the baud rate, detected from the waveform for [Baud::Auto].
+    ///
+    /// Returns `None` if no rate is detected or the rate is 0, so the result
+    /// can be passed to [Uart::new](crate::analysis::Uart::new).
+    pub fn resolve(&self, waveform: &Waveform) -> Option<u32> {
+        match self {
+            Baud::Fixed(0) => None,
+            Baud::Fixed(baud) => Some(*baud),
+            Baud::Auto => BitRate::detect(waveform).map(|rate| rate.baud()),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::analysis::{Uart, UartByte, UartEvent};
+    use crate::pins::*;
+    use embedded_time::duration::*;
+
+    #[test]
+    fn detect_rates() {
+        let uart = Uart::new(115_200);
+        let mut time = Nanoseconds(10_000);
+        let mut events = vec![];
+        for value in b"Hello, world!" {
+            events.push(UartEvent::Byte(UartByte {
+                time,
+                value: *value,
+                stop: true,
+            }));
+            time = Nanoseconds(uart.byte_end(time).0 + 3_000);
+        }
+        let mut changes = uart.encode(&events).changes().to_vec();
+        // a glitch
+        let end = changes.last().unwrap().0 .0;
+        changes.push((Nanoseconds(end + 100_000), PinState::Low));
+        changes.push((Nanoseconds(end + 100_050), PinState::High));
+        let rate = BitRate::detect(&Waveform::new(changes)).unwrap();
+        assert_eq!(rate.standard, Some(115_200));
+        assert!((rate.bit_time - 8680.6).abs() < 5.0);
+        assert_eq!(Baud::Fixed(9600).resolve(&Waveform::default()), Some(9600));
+        assert_eq!(Baud::Auto.resolve(&Waveform::default()), None);
+        assert_eq!(Baud::Fixed(0).resolve(&Waveform::default()), None);
+        // pulses of 3 s
+        let slow = (0..6u64)
+            .map(|i| {
+                let state = if i % 2 == 0 {
+                    PinState::Low
+                } else {
+                    PinState::High
+                };
+                (Nanoseconds(i * 3_000_000_000), state)
+            })
+            .collect();
+        assert_eq!(Baud::Auto.resolve(&Waveform::new(slow)), None);
+
+        // bursts of an uneven 3.3 MHz clock
+        let mut clock = vec![(Nanoseconds(0), PinState::Low)];
+        for burst in 0..4u64 {
+            for bit in 0..8u64 {
+                let t = burst * 10_000 + bit * 303;
+                clock.push((Nanoseconds(t + 100), PinState::High));
+                clock.push((Nanoseconds(t + 200), PinState::Low));
+            }
+        }
+        let rate = BitRate::detect_clock(&Waveform::new(clock)).unwrap();
+        assert_eq!(rate.baud(), 3_300_330);
+        assert_eq!(rate.intervals, 28);
+        assert_eq!(rate.standard, None);
+    }
+}
@@ -0,0 +1,136 @@
+//! Bit error rate of a data signal compared to a reference signal.
+
+use super::Waveform;
+use crate::devices::Edge;
+use crate::pins::*;
+use embedded_time::duration::*;
+
+/// A bit that differs between the data and the reference signal.
+#[derive(Clone, Debug, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct BitError {
+    /// The time of the clock edge the bit was sampled on.
+    pub time: Nanoseconds<u64>,
+    /// The index of the bit, counted from the first clock edge.
+    pub index: usize,
+    /// The state of the reference signal, `None` before its first change.
+    #[cfg_attr(feature = "serde", serde(with = "crate::pins::serde_states::option"))]
+    pub expected: Option<PinState>,
+    /// The state of the data signal, `None` before its first change.
+    #[cfg_attr(feature = "serde", serde(with = "crate::pins::serde_states::option"))]
+    pub actual: Option<PinState>,
+}
+
+/// The result of a [BitComparator].
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct BitErrors {
+    /// The number of compared bits.
+    pub bits: usize,
+    /// The mismatched bits.
+    pub errors: Vec<BitError>,
+}
+
+impl BitErrors {
+    /// Return the ratio of mismatched bits, `0.0` if no bits were compared.
+    pub fn rate(&self) -> f64 {
+        if self.bits == 0 {
+            return 0.0;
+        }
+        self.errors.len() as f64 / self.bits as f64
+    }
+}
+
+/// A comparator that samples a data signal and a reference signal on the
+/// edges of a clock, e.g. to validate a bit-banged transmitter against the
+/// expected data stream.
+#[derive(Clone, Debug)]
+pub struct BitComparator {
+    edge: Edge,
+}
+
+impl BitComparator {
+    /// Create a new comparator that samples on the given clock edge.
+    pub fn new(edge: Edge) -> Self {
+        BitComparator { edge }
+    }
+
+    /// Compare the signals on each clock edge.
+    ///
+    /// Changes at the time of a clock edge are included, so the data has to
+    /// be stable before the edge. The first state of the clock is not an
+    /// edge.
+    pub fn compare(&self, clock: &Waveform, data: &Waveform, reference: &Waveform) -> BitErrors {
+        let mut result = BitErrors::default();
+        let edges = clock
+            .changes()
+            .iter()
+            .skip(1)
+            .filter(|(_, state)| Edge::to_state(state) == self.edge);
+        for (index, (time, _)) in edges.enumerate() {
+            result.bits += 1;
+            let expected = reference.state_at(*time);
+            let actual = data.state_at(*time);
+            if expected != actual {
+                result.errors.push(BitError {
+                    time: *time,
+                    index,
+                    expected,
+                    actual,
+                });
+            }
+        }
+        result
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn bit_errors() {
+        let bits = |pattern: &[u8], offset: u64| {
+            Waveform::new(
+                pattern
+                    .iter()
+                    .enumerate()
+                    .map(|(i, bit)| {
+                        let state = if *bit == 1 {
+                            PinState::High
+                        } else {
+                            PinState::Low
+                        };
+                        (Nanoseconds(i as u64 * 100 + offset), state)
+                    })
+                    .collect(),
+            )
+        };
+        let clock = Waveform::new(
+            (0..9u64)
+                .map(|i| {
+                    let state = if i % 2 == 0 {
+                        PinState::Low
+                    } else {
+                        PinState::High
+                    };
+                    (Nanoseconds(i * 50), state)
+                })
+                .collect(),
+        );
+        let reference = bits(&[1, 0, 1, 1], 0);
+        let data = bits(&[1, 0, 0, 1], 10);
+        let result = BitComparator::new(Edge::Rising).compare(&clock, &data, &reference);
+        assert_eq!(result.bits, 4);
+        assert_eq!(
+            result.errors,
+            vec![BitError {
+                time: Nanoseconds(250),
+                index: 2,
+                expected: Some(PinState::High),
+                actual: Some(PinState::Low),
+            }]
+        );
+        assert_eq!(result.rate(), 0.25);
+    }
+}
@@ -0,0 +1,227 @@
+//! DALI forward and backward frames decoded from and encoded to a single
+//! signal.
+
+use super::{Manchester, ManchesterCoding, ManchesterFrame, Waveform};
+use crate::pins::*;
+use embedded_time::duration::*;
+use std::fmt;
+
+/// The half bit time Te of DALI at 1200 baud.
+const TE: u64 = 416_667;
+
+/// The direction of a DALI frame.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum DaliFrameKind {
+    /// A command to control gear, 16 or 24 bits.
+    Forward,
+    /// An answer of a control gear, 8 bits.
+    Backward,
+}
+
+/// A DALI frame without start and stop bits.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct DaliFrame {
+    /// The start of the start bit.
+    pub time: Nanoseconds<u64>,
+    /// The direction of the frame.
+    pub kind: DaliFrameKind,
+    /// The number of data bits.
+    pub bits: u8,
+    /// The data bits, the first bit is the most significant.
+    pub data: u32,
+}
+
+impl DaliFrame {
+    /// Create a 16-bit forward frame of an address and an opcode byte.
+    pub fn forward(time: Nanoseconds<u64>, address: u8, opcode: u8) -> Self {
+        DaliFrame {
+            time,
+            kind: DaliFrameKind::Forward,
+            bits: 16,
+            data: (address as u32) << 8 | opcode as u32,
+        }
+    }
+
+    /// Create a backward frame.
+    pub fn backward(time: Nanoseconds<u64>, answer: u8) -> Self {
+        DaliFrame {
+            time,
+            kind: DaliFrameKind::Backward,
+            bits: 8,
+            data: answer as u32,
+        }
+    }
+
+    /// Return the time after the stop bits.
+    pub fn end(&self) -> Nanoseconds<u64> {
+        Nanoseconds(self.time.0 + (self.bits as u64 + 1) * 2 * TE + 4 * TE)
+    }
+}
+
+/// A violation of the DALI timing or framing found by a [Dali].
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum DaliViolation {
+    /// The frame starting at `time` has an invalid number of bits.
+    Length {
+        /// The start of the frame.
+        time: Nanoseconds<u64>,
+        /// The number of bits without the start bit.
+        bits: usize,
+    },
+    /// The frame starting at `time` follows the previous frame too early or,
+    /// for a backward frame, too late.
+    Settling {
+        /// The start of the frame.
+        time: Nanoseconds<u64>,
+        /// The time since the end of the stop bits of the previous frame.
+        settling: Nanoseconds<u64>,
+    },
+}
+
+impl fmt::Display for DaliViolation {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            DaliViolation::Length { time, bits } => {
+                write!(f, "frame at {} has {} bits", time, bits)
+            }
+            DaliViolation::Settling { time, settling } => {
+                write!(f, "frame at {} has a settling time of {}", time, settling)
+            }
+        }
+    }
+}
+
+impl std::error::Error for DaliViolation {}
+
+/// An encoder and decoder for DALI frames.
+///
+/// Frames are Manchester coded with 1200 baud (a rising edge in the middle
+/// of a bit is a one), the bus idles high. A backward frame has to start 7
+/// to 22 Te after the forward frame, forward frames 22 Te after the previous
+/// frame, with a half bit time Te of 416.7 µs.
+///
+/// ```
+/// use embedded_hal_vcd::analysis::{Dali, DaliFrame};
+/// use embedded_time::duration::*;
+///
+/// let dali = Dali::new();
+/// // query actual level of short address 1, answered with 254
+/// let query = DaliFrame::forward(Nanoseconds(0), 0x03, 0xa0);
+/// let answer = DaliFrame::backward(Nanoseconds(query.end().0 + 4_000_000), 254);
+/// let waveform = dali.encode(&[query, answer]);
+/// assert_eq!(dali.decode(&waveform), Ok(vec![query, answer]));
+/// ```
+#[derive(Clone, Debug)]
+pub struct Dali {
+    codec: Manchester,
+}
+
+impl Default for Dali {
+    fn default() -> Self {
+        Dali {
+            codec: Manchester::new(ManchesterCoding::Ieee, Nanoseconds(2 * TE))
+                .with_idle(PinState::High),
+        }
+    }
+}
+
+impl Dali {
+    /// Create a new codec.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Encode frames, which have to be in chronological order and must not
+    /// overlap.
+    pub fn encode(&self, frames: &[DaliFrame]) -> Waveform {
+        let frames: Vec<ManchesterFrame> = frames
+            .iter()
+            .map(|frame| ManchesterFrame {
+                time: frame.time,
+                bits: [true]
+                    .into_iter()
+                    .chain((0..frame.bits).rev().map(|i| frame.data >> i & 1 == 1))
+                    .collect(),
+            })
+            .collect();
+        self.codec.encode(&frames)
+    }
+
+    /// Check the framing and timing of all frames and return them.
+    pub fn decode(&self, waveform: &Waveform) -> Result<Vec<DaliFrame>, DaliViolation> {
+        let mut frames: Vec<DaliFrame> = vec![];
+        for frame in self.codec.decode(waveform) {
+            let bits = frame.bits.len() - 1;
+            let kind = match bits {
+                8 => DaliFrameKind::Backward,
+                16 | 24 => DaliFrameKind::Forward,
+                _ => {
+                    return Err(DaliViolation::Length {
+                        time: frame.time,
+                        bits,
+                    })
+                }
+            };
+            let current = DaliFrame {
+                time: frame.time,
+                kind,
+                bits: bits as u8,
+                data: (frame.value() & ((1 << bits) - 1)) as u32,
+            };
+            if let Some(last) = frames.last() {
+                let settling = current.time.0.saturating_sub(last.end().0);
+                let valid = match (last.kind, kind) {
+                    (DaliFrameKind::Forward, DaliFrameKind::Backward) => {
+                        (7 * TE..=22 * TE).contains(&settling)
+                    }
+                    (_, DaliFrameKind::Forward) => settling >= 22 * TE,
+                    _ => false,
+                };
+                if !valid {
+                    return Err(DaliViolation::Settling {
+                        time: current.time,
+                        settling: Nanoseconds(settling),
+                    });
+                }
+            }
+            frames.push(current);
+        }
+        Ok(super::traced("dali", frames))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn settling_times() {
+        let dali = Dali::new();
+        let broadcast = DaliFrame::forward(Nanoseconds(1_000_000), 0xfe, 0x80);
+        let query = DaliFrame::forward(Nanoseconds(broadcast.end().0 + 10_000_000), 0x03, 0xa0);
+        let answer = DaliFrame::backward(Nanoseconds(query.end().0 + 3_000_000), 0x80);
+        let frames = [broadcast, query, answer];
+        assert_eq!(dali.decode(&dali.encode(&frames)), Ok(frames.to_vec()));
+
+        // an answer after 10 ms is too late
+        let late = DaliFrame::backward(Nanoseconds(query.end().0 + 10_000_000), 0x80);
+        assert_eq!(
+            dali.decode(&dali.encode(&[query, late])),
+            Err(DaliViolation::Settling {
+                time: late.time,
+                settling: Nanoseconds(10_000_000),
+            })
+        );
+        let short = DaliFrame { bits: 12, ..query };
+        assert_eq!(
+            dali.decode(&dali.encode(&[short])),
+            Err(DaliViolation::Length {
+                time: query.time,
+                bits: 12
+            })
+        );
+    }
+}
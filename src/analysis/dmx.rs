@@ -0,0 +1,225 @@
+//! DMX512 packets decoded from and encoded to a single signal.
+
+use super::{Uart, UartByte, UartEvent, Waveform};
+use embedded_time::duration::*;
+use std::fmt;
+
+/// A DMX512 packet.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct DmxPacket {
+    /// The start of the break.
+    pub time: Nanoseconds<u64>,
+    /// The width of the break.
+    pub break_width: Nanoseconds<u64>,
+    /// The width of the mark after the break.
+    pub mark_after_break: Nanoseconds<u64>,
+    /// The start code, `0` for dimmer levels.
+    pub start_code: u8,
+    /// The slot values.
+    pub slots: Vec<u8>,
+}
+
+/// A violation of the DMX512 timing or framing found by a [Dmx512].
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum DmxViolation {
+    /// The break of the packet starting at `time` is too short.
+    Break {
+        /// The start of the packet.
+        time: Nanoseconds<u64>,
+        /// The width of the break.
+        width: Nanoseconds<u64>,
+    },
+    /// The mark after the break of the packet starting at `time` is too
+    /// short.
+    MarkAfterBreak {
+        /// The start of the packet.
+        time: Nanoseconds<u64>,
+        /// The width of the mark.
+        width: Nanoseconds<u64>,
+    },
+    /// The stop bits of the slot starting at `time` were not high.
+    Framing {
+        /// The start of the slot.
+        time: Nanoseconds<u64>,
+    },
+    /// The packet starting at `time` has more than 512 slots.
+    Slots {
+        /// The start of the packet.
+        time: Nanoseconds<u64>,
+        /// The number of slots.
+        count: usize,
+    },
+}
+
+impl fmt::Display for DmxViolation {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            DmxViolation::Break { time, width } => {
+                write!(f, "packet at {} has a break of {}", time, width)
+            }
+            DmxViolation::MarkAfterBreak { time, width } => {
+                write!(f, "packet at {} has a mark after break of {}", time, width)
+            }
+            DmxViolation::Framing { time } => write!(f, "slot at {} has a framing error", time),
+            DmxViolation::Slots { time, count } => {
+                write!(f, "packet at {} has {} slots", time, count)
+            }
+        }
+    }
+}
+
+impl std::error::Error for DmxViolation {}
+
+/// An encoder and decoder for DMX512 packets.
+///
+/// Slots are sent at 250 kbaud with two stop bits. Received packets need a
+/// break of at least 88 µs and a mark after break of at least 8 µs, bytes
+/// before the first break are ignored.
+///
+/// ```
+/// use embedded_hal_vcd::analysis::{Dmx512, DmxPacket};
+/// use embedded_time::duration::*;
+///
+/// let dmx = Dmx512::new();
+/// let packet = dmx.packet(Nanoseconds(0), vec![255, 128, 0]);
+/// let waveform = dmx.encode(&[packet.clone()]);
+/// assert_eq!(dmx.decode(&waveform), Ok(vec![packet]));
+/// ```
+#[derive(Clone, Debug)]
+pub struct Dmx512 {
+    uart: Uart,
+}
+
+impl Default for Dmx512 {
+    fn default() -> Self {
+        Dmx512 {
+            uart: Uart::new(250_000).with_stop_bits(2),
+        }
+    }
+}
+
+impl Dmx512 {
+    /// Create a new codec.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Create a dimmer level packet with a break of 176 µs and a mark after
+    /// break of 12 µs, the minimum a transmitter has to send.
+    pub fn packet(&self, time: Nanoseconds<u64>, slots: Vec<u8>) -> DmxPacket {
+        DmxPacket {
+            time,
+            break_width: Nanoseconds(176_000),
+            mark_after_break: Nanoseconds(12_000),
+            start_code: 0,
+            slots,
+        }
+    }
+
+    /// Encode packets, which have to be in chronological order and must not
+    /// overlap. The slots are sent back to back.
+    pub fn encode(&self, packets: &[DmxPacket]) -> Waveform {
+        let mut events = vec![];
+        for packet in packets {
+            events.push(UartEvent::Break {
+                time: packet.time,
+                width: packet.break_width,
+            });
+            let mut t =
+                Nanoseconds(packet.time.0 + packet.break_width.0 + packet.mark_after_break.0);
+            for value in [packet.start_code].iter().chain(&packet.slots) {
+                events.push(UartEvent::Byte(UartByte {
+                    time: t,
+                    value: *value,
+                    stop: true,
+                }));
+                t = self.uart.byte_end(t);
+            }
+        }
+        self.uart.encode(&events)
+    }
+
+    /// Check the timing of all packets and return them.
+    pub fn decode(&self, waveform: &Waveform) -> Result<Vec<DmxPacket>, DmxViolation> {
+        let mut packets: Vec<DmxPacket> = vec![];
+        let mut mark_start = None;
+        for event in self.uart.decode(waveform) {
+            match event {
+                UartEvent::Break { time, width } => {
+                    if width.0 < 88_000 {
+                        return Err(DmxViolation::Break { time, width });
+                    }
+                    mark_start = Some(time.0 + width.0);
+                    packets.push(DmxPacket {
+                        time,
+                        break_width: width,
+                        ..DmxPacket::default()
+                    });
+                }
+                UartEvent::Byte(byte) => {
+                    let packet = match packets.last_mut() {
+                        Some(packet) => packet,
+                        None => continue,
+                    };
+                    if !byte.stop {
+                        return Err(DmxViolation::Framing { time: byte.time });
+                    }
+                    match mark_start.take() {
+                        Some(start) => {
+                            let width = Nanoseconds(byte.time.0 - start);
+                            if width.0 < 8_000 {
+                                return Err(DmxViolation::MarkAfterBreak {
+                                    time: packet.time,
+                                    width,
+                                });
+                            }
+                            packet.mark_after_break = width;
+                            packet.start_code = byte.value;
+                        }
+                        None if packet.slots.len() == 512 => {
+                            return Err(DmxViolation::Slots {
+                                time: packet.time,
+                                count: 513,
+                            })
+                        }
+                        None => packet.slots.push(byte.value),
+                    }
+                }
+            }
+        }
+        Ok(super::traced("dmx", packets))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn packets() {
+        let dmx = Dmx512::new();
+        let mut first = dmx.packet(Nanoseconds(0), (0..=255).collect());
+        first.start_code = 0xcc;
+        let second = dmx.packet(Nanoseconds(50_000_000), vec![0; 512]);
+        let waveform = dmx.encode(&[first.clone(), second.clone()]);
+        assert_eq!(dmx.decode(&waveform), Ok(vec![first.clone(), second]));
+
+        let mut short = first.clone();
+        short.break_width = Nanoseconds(80_000);
+        assert_eq!(
+            dmx.decode(&dmx.encode(&[short])),
+            Err(DmxViolation::Break {
+                time: Nanoseconds(0),
+                width: Nanoseconds(80_000)
+            })
+        );
+        let mut short = first;
+        short.mark_after_break = Nanoseconds(4_000);
+        assert!(matches!(
+            dmx.decode(&dmx.encode(&[short])),
+            Err(DmxViolation::MarkAfterBreak { .. })
+        ));
+    }
+}
@@ -0,0 +1,121 @@
+//! Instantaneous frequency of a signal over time.
+
+use super::Waveform;
+use crate::devices::Edge;
+use embedded_time::duration::*;
+use std::io::Result as IOResult;
+
+/// The periods of a signal over time, e.g. to verify the lock ramp of a
+/// PLL or a PWM frequency sweep.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct FrequencySeries {
+    periods: Vec<(Nanoseconds<u64>, Nanoseconds<u64>)>,
+}
+
+impl FrequencySeries {
+    /// Measure the time between consecutive edges of the given kind.
+    ///
+    /// Each period is placed at the time of the edge that ends it. The
+    /// first state of the waveform is not an edge.
+    pub fn measure(waveform: &Waveform, edge: Edge) -> Self {
+        let edges: Vec<_> = waveform
+            .changes()
+            .iter()
+            .skip(1)
+            .filter(|(_, state)| Edge::to_state(state) == edge)
+            .map(|(t, _)| *t)
+            .collect();
+        let periods = edges
+            .windows(2)
+            .map(|w| (w[1], Nanoseconds(w[1].0 - w[0].0)))
+            .collect();
+        FrequencySeries { periods }
+    }
+
+    /// Return the end and length of each period.
+    pub fn periods(&self) -> &[(Nanoseconds<u64>, Nanoseconds<u64>)] {
+        &self.periods
+    }
+
+    /// Return the end of each period and the frequency in Hz.
+    pub fn frequencies(&self) -> impl Iterator<Item = (Nanoseconds<u64>, f64)> + '_ {
+        self.periods
+            .iter()
+            .map(|(t, period)| (*t, 1e9 / period.0 as f64))
+    }
+
+    /// Write the series as CSV with the columns `time_ns`, `period_ns` and
+    /// `frequency_hz`.
+    pub fn write_csv<W: std::io::Write>(&self, mut output: W) -> IOResult<()> {
+        writeln!(output, "time_ns,period_ns,frequency_hz")?;
+        for ((t, period), (_, frequency)) in self.periods.iter().zip(self.frequencies()) {
+            writeln!(output, "{},{},{}", t.0, period.0, frequency)?;
+        }
+        Ok(())
+    }
+
+    /// Write the frequency in Hz as real variable to a VCD file.
+    ///
+    /// The dots in `name` separate the scopes, like the names of
+    /// [Waveform::write_all].
+    pub fn write_vcd<W: std::io::Write>(&self, name: &str, output: W) -> IOResult<()> {
+        let mut header = vcd::Header::default();
+        header.timescale = Some((1, vcd::TimescaleUnit::NS));
+        let path: Vec<&str> = name.split('.').collect();
+        let (reference, scopes) = path.split_last().unwrap();
+        let code = vcd::IdCode::FIRST;
+        let var = vcd::Var {
+            var_type: vcd::VarType::Real,
+            size: 64,
+            code,
+            reference: reference.to_string(),
+            index: None,
+        };
+        crate::export::jsonl::insert_var(&mut header.items, scopes, var);
+        let mut writer = vcd::Writer::new(output);
+        writer.header(&header)?;
+        for (t, frequency) in self.frequencies() {
+            writer.timestamp(t.0)?;
+            writer.change_real(code, frequency)?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::pins::PinState;
+
+    #[test]
+    fn frequency_sweep() {
+        let mut changes = vec![];
+        let mut t = 0;
+        for period in [1_000u64, 1_000, 500, 250] {
+            changes.push((Nanoseconds(t), PinState::High));
+            changes.push((Nanoseconds(t + period / 2), PinState::Low));
+            t += period;
+        }
+        changes.push((Nanoseconds(t), PinState::High));
+        let series = FrequencySeries::measure(&Waveform::new(changes), Edge::Rising);
+        assert_eq!(
+            series.periods(),
+            &[
+                (Nanoseconds(2_000), Nanoseconds(1_000)),
+                (Nanoseconds(2_500), Nanoseconds(500)),
+                (Nanoseconds(2_750), Nanoseconds(250)),
+            ]
+        );
+        let mut csv = vec![];
+        series.write_csv(&mut csv).unwrap();
+        assert!(String::from_utf8(csv)
+            .unwrap()
+            .ends_with("2500,500,2000000\n2750,250,4000000\n"));
+        let mut vcd = vec![];
+        series.write_vcd("pll.freq", &mut vcd).unwrap();
+        assert!(String::from_utf8(vcd)
+            .unwrap()
+            .ends_with("#2500\nr2000000 !\n#2750\nr4000000 !\n"));
+    }
+}
@@ -0,0 +1,200 @@
+//! I2C transfers decoded from recorded signals.
+
+use super::Waveform;
+use crate::pins::*;
+use embedded_time::duration::*;
+
+/// A transfer from a (repeated) start condition to the next start or stop
+/// condition.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct I2cTransfer {
+    /// The time of the start condition.
+    pub time: Nanoseconds<u64>,
+    /// The 7-bit address.
+    pub address: u8,
+    /// Whether the master reads from the device.
+    pub read: bool,
+    /// Whether the address was acknowledged.
+    pub address_ack: bool,
+    /// The data bytes with their acknowledge bits.
+    pub data: Vec<(u8, bool)>,
+    /// Whether the transfer ended with a stop condition, `false` for a
+    /// repeated start or the end of the recording.
+    pub stop: bool,
+}
+
+impl I2cTransfer {
+    /// Return the data bytes without the acknowledge bits.
+    pub fn bytes(&self) -> Vec<u8> {
+        self.data.iter().map(|(byte, _)| *byte).collect()
+    }
+}
+
+/// A decoder for I2C transfers.
+///
+/// The bus lines are open drain, so a floating line is read as high. Bits
+/// are sampled on the rising edges of SCL, so the decoder doesn't depend
+/// on the clock timing: clocks stretched by a device just delay the next
+/// rising edge. Use [I2cDecoder::stretches] to find them.
+#[derive(Clone, Debug, Default)]
+pub struct I2cDecoder {}
+
+impl I2cDecoder {
+    /// Create a new decoder.
+    pub fn new() -> Self {
+        I2cDecoder {}
+    }
+
+    /// Decode all transfers, incomplete bytes are dropped.
+    pub fn decode(&self, scl: &Waveform, sda: &Waveform) -> Vec<I2cTransfer> {
+        let high = |state: Option<PinState>| state != Some(PinState::Low);
+        let mut times: Vec<_> = scl
+            .changes()
+            .iter()
+            .chain(sda.changes())
+            .map(|(t, _)| *t)
+            .collect();
+        times.sort_unstable();
+        times.dedup();
+        let mut transfers: Vec<I2cTransfer> = vec![];
+        let mut current: Option<I2cTransfer> = None;
+        let mut bits: Vec<bool> = vec![];
+        let mut addressed = false;
+        let mut last_scl = true;
+        let mut last_sda = true;
+        for t in times {
+            let (scl_high, sda_high) = (high(scl.state_at(t)), high(sda.state_at(t)));
+            if last_scl && scl_high && last_sda != sda_high {
+                // start (SDA falling) or stop (SDA rising) while SCL is high
+                if let Some(transfer) = current.take() {
+                    transfers.push(I2cTransfer {
+                        stop: sda_high,
+                        ..transfer
+                    });
+                }
+                if !sda_high {
+                    current = Some(I2cTransfer {
+                        time: t,
+                        ..I2cTransfer::default()
+                    });
+                }
+                bits.clear();
+                addressed = false;
+            } else if !last_scl && scl_high && current.is_some() {
+                bits.push(sda_high);
+                if bits.len() == 9 {
+                    let byte = bits[..8].iter().fold(0, |b, bit| b << 1 | *bit as u8);
+                    let ack = !bits[8];
+                    let transfer = current.as_mut().unwrap();
+                    if addressed {
+                        transfer.data.push((byte, ack));
+                    } else {
+                        transfer.address = byte >> 1;
+                        transfer.read = byte & 1 == 1;
+                        transfer.address_ack = ack;
+                        addressed = true;
+                    }
+                    bits.clear();
+                }
+            }
+            last_scl = scl_high;
+            last_sda = sda_high;
+        }
+        transfers.extend(current);
+        super::traced("i2c", transfers)
+    }
+
+    /// Return the start and width of all low phases of SCL that are longer
+    /// than `threshold`, e.g. half of the clock period of the master.
+    pub fn stretches(
+        &self,
+        scl: &Waveform,
+        threshold: Nanoseconds<u64>,
+    ) -> Vec<(Nanoseconds<u64>, Nanoseconds<u64>)> {
+        scl.pulses(PinState::Low)
+            .into_iter()
+            .filter(|(_, width)| *width > threshold)
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Record the lines of an I2C master, SCL low phases are 500 ns unless
+    /// stretched.
+    #[derive(Default)]
+    struct Bus {
+        t: u64,
+        scl: Vec<(Nanoseconds<u64>, PinState)>,
+        sda: Vec<(Nanoseconds<u64>, PinState)>,
+    }
+
+    impl Bus {
+        fn set(&mut self, scl: bool, sda: bool, wait: u64) {
+            let level = |high| {
+                if high {
+                    PinState::Floating
+                } else {
+                    PinState::Low
+                }
+            };
+            self.scl.push((Nanoseconds(self.t), level(scl)));
+            self.sda.push((Nanoseconds(self.t), level(sda)));
+            self.t += wait;
+        }
+
+        fn start(&mut self) {
+            self.set(false, true, 500);
+            self.set(true, true, 500);
+            self.set(true, false, 500);
+        }
+
+        fn stop(&mut self) {
+            self.set(false, false, 500);
+            self.set(true, false, 500);
+            self.set(true, true, 500);
+        }
+
+        fn byte(&mut self, byte: u8, ack: bool, stretch: u64) {
+            let bits = (0..8).rev().map(|i| byte >> i & 1 == 1).chain([!ack]);
+            for (i, bit) in bits.enumerate() {
+                let low = if i == 0 { 500 + stretch } else { 500 };
+                self.set(false, bit, low);
+                self.set(true, bit, 500);
+            }
+        }
+    }
+
+    #[test]
+    fn decode_stretched() {
+        let mut bus = Bus::default();
+        bus.start();
+        bus.byte(0xa0, true, 0);
+        bus.byte(0x10, true, 20_000);
+        bus.start();
+        bus.byte(0xa1, true, 0);
+        bus.byte(0x12, true, 3000);
+        bus.byte(0x34, false, 0);
+        bus.stop();
+        bus.start();
+        bus.byte(0xa2, false, 0);
+        bus.stop();
+        let (scl, sda) = (Waveform::new(bus.scl), Waveform::new(bus.sda));
+        let decoder = I2cDecoder::new();
+        let transfers = decoder.decode(&scl, &sda);
+        assert_eq!(transfers.len(), 3);
+        assert_eq!(transfers[0].address, 0x50);
+        assert!(!transfers[0].read && !transfers[0].stop);
+        assert_eq!(transfers[0].bytes(), vec![0x10]);
+        assert!(transfers[1].read && transfers[1].stop);
+        assert_eq!(transfers[1].data, vec![(0x12, true), (0x34, false)]);
+        assert_eq!(transfers[2].address, 0x51);
+        assert!(!transfers[2].address_ack);
+        let stretches = decoder.stretches(&scl, Nanoseconds(1000u64));
+        let widths: Vec<_> = stretches.iter().map(|(_, w)| w.0).collect();
+        assert_eq!(widths, vec![20_500, 3500]);
+    }
+}
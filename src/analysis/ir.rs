@@ -0,0 +1,435 @@
+//! Infrared remote control commands (NEC and RC5) decoded from and encoded
+//! to a single signal.
+//!
+//! A signal is either the demodulated output of an IR receiver, which is
+//! active low, or the modulated signal of a transmitter LED, which is high
+//! while the carrier is on. Carrier bursts are called marks, the pauses
+//! between them spaces. Encoded waveforms can be written to VCD with
+//! [Waveform::write_all] or driven in a simulation with
+//! [play](crate::testing::stimulus::play).
+
+use super::Waveform;
+use crate::pins::*;
+use embedded_time::duration::*;
+
+/// The NEC time unit, the length of a bit mark.
+const NEC_UNIT: u64 = 562_500;
+/// Half of a RC5 bit.
+const RC5_HALF: u64 = 889_000;
+
+/// A remote control protocol.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum IrProtocol {
+    /// NEC with pulse distance coding and a 9 ms leader.
+    Nec,
+    /// Philips RC5 with Manchester coding.
+    Rc5,
+}
+
+/// A remote control command.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum IrCommand {
+    /// A NEC command with an 8-bit address.
+    Nec {
+        /// The address.
+        address: u8,
+        /// The command.
+        command: u8,
+    },
+    /// A NEC command with a 16-bit address, the address is not followed by
+    /// its inverse.
+    NecExtended {
+        /// The address.
+        address: u16,
+        /// The command.
+        command: u8,
+    },
+    /// A NEC repeat code, sent while a key is held.
+    NecRepeat,
+    /// A RC5 command.
+    Rc5 {
+        /// The toggle bit, which changes with each key press.
+        toggle: bool,
+        /// The 5-bit address.
+        address: u8,
+        /// The 7-bit command.
+        command: u8,
+    },
+}
+
+/// A command sent at a time.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct IrFrame {
+    /// The start of the first mark.
+    pub time: Nanoseconds<u64>,
+    /// The command.
+    pub command: IrCommand,
+}
+
+impl IrFrame {
+    /// Return the alternating mark and space durations, starting with a
+    /// mark and ending with a mark.
+    fn durations(&self) -> Vec<u64> {
+        let mut durations = vec![];
+        match self.command {
+            IrCommand::Nec { address, command } => {
+                let address = address as u16 | (!address as u16) << 8;
+                return IrFrame {
+                    command: IrCommand::NecExtended { address, command },
+                    ..*self
+                }
+                .durations();
+            }
+            IrCommand::NecExtended { address, command } => {
+                let bits = address as u32 | (command as u32) << 16 | (!command as u32) << 24;
+                durations.extend([16 * NEC_UNIT, 8 * NEC_UNIT]);
+                for i in 0..32 {
+                    let space = if bits >> i & 1 == 1 { 3 } else { 1 };
+                    durations.extend([NEC_UNIT, space * NEC_UNIT]);
+                }
+                durations.push(NEC_UNIT);
+            }
+            IrCommand::NecRepeat => durations.extend([16 * NEC_UNIT, 4 * NEC_UNIT, NEC_UNIT]),
+            IrCommand::Rc5 {
+                toggle,
+                address,
+                command,
+            } => {
+                let field = command & 0x40 == 0;
+                let bits = [true, field, toggle]
+                    .into_iter()
+                    .chain((0..5).rev().map(|i| address >> i & 1 == 1))
+                    .chain((0..6).rev().map(|i| command >> i & 1 == 1));
+                // a one is a space followed by a mark, the first space is idle
+                let halves: Vec<bool> = bits.flat_map(|bit| [!bit, bit]).skip(1).collect();
+                let mut last = None;
+                for half in halves {
+                    if last == Some(half) {
+                        *durations.last_mut().unwrap() += RC5_HALF;
+                    } else {
+                        durations.push(RC5_HALF);
+                    }
+                    last = Some(half);
+                }
+                if last == Some(false) {
+                    durations.pop();
+                }
+            }
+        }
+        durations
+    }
+}
+
+/// A generator for IR commands.
+///
+/// The carrier is generated with a duty cycle of 1/3.
+///
+/// ```
+/// use embedded_hal_vcd::analysis::{IrCommand, IrDecoder, IrEncoder, IrFrame, IrProtocol};
+/// use embedded_time::duration::*;
+///
+/// let frames = [IrFrame {
+///     time: Nanoseconds(1_000_000),
+///     command: IrCommand::Nec { address: 0x04, command: 0x08 },
+/// }];
+/// let waveform = IrEncoder::new().with_carrier(38_000).encode(&frames);
+/// let decoded = IrDecoder::new(IrProtocol::Nec).with_carrier(38_000).decode(&waveform);
+/// assert_eq!(decoded[0].command, frames[0].command);
+/// ```
+#[derive(Clone, Debug, Default)]
+pub struct IrEncoder {
+    carrier: Option<u32>,
+}
+
+impl IrEncoder {
+    /// Create a new encoder for the demodulated signal of a receiver.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Generate the modulated signal of a transmitter with a carrier of the
+    /// given frequency in Hz, e.g. 38 kHz for NEC or 36 kHz for RC5.
+    pub fn with_carrier(mut self, frequency: u32) -> Self {
+        self.carrier = Some(frequency);
+        self
+    }
+
+    /// Encode frames, which have to be in chronological order and must not
+    /// overlap.
+    pub fn encode(&self, frames: &[IrFrame]) -> Waveform {
+        let (idle, active) = match self.carrier {
+            Some(_) => (PinState::Low, PinState::High),
+            None => (PinState::High, PinState::Low),
+        };
+        let mut changes = vec![(Nanoseconds(0), idle.clone())];
+        for frame in frames {
+            let mut t = frame.time.0;
+            for (i, duration) in frame.durations().into_iter().enumerate() {
+                if i % 2 == 0 {
+                    match self.carrier {
+                        Some(frequency) => {
+                            let period = 1_000_000_000 / frequency as u64;
+                            for start in (t..t + duration).step_by(period as usize) {
+                                changes.push((Nanoseconds(start), active.clone()));
+                                changes.push((Nanoseconds(start + period / 3), idle.clone()));
+                            }
+                        }
+                        None => {
+                            changes.push((Nanoseconds(t), active.clone()));
+                            changes.push((Nanoseconds(t + duration), idle.clone()));
+                        }
+                    }
+                }
+                t += duration;
+            }
+        }
+        Waveform::new(changes)
+    }
+}
+
+/// A decoder for IR commands of a protocol.
+///
+/// Marks and spaces may deviate by 25% from their nominal length by
+/// default. Frames that don't match the protocol are skipped.
+#[derive(Clone, Debug)]
+pub struct IrDecoder {
+    protocol: IrProtocol,
+    carrier: Option<u32>,
+    tolerance: u64,
+}
+
+impl IrDecoder {
+    /// Create a new decoder for the demodulated signal of a receiver.
+    pub fn new(protocol: IrProtocol) -> Self {
+        IrDecoder {
+            protocol,
+            carrier: None,
+            tolerance: 25,
+        }
+    }
+
+    /// Decode the modulated signal of a transmitter with a carrier of the
+    /// given frequency in Hz.
+    pub fn with_carrier(mut self, frequency: u32) -> Self {
+        self.carrier = Some(frequency);
+        self
+    }
+
+    /// Set the allowed deviation of marks and spaces in percent.
+    pub fn with_tolerance(mut self, percent: u8) -> Self {
+        self.tolerance = percent as u64;
+        self
+    }
+
+    /// Return the start and end of all marks.
+    fn marks(&self, waveform: &Waveform) -> Vec<(u64, u64)> {
+        let (active, gap) = match self.carrier {
+            // bursts of the carrier are merged if they are closer than three
+            // carrier periods
+            Some(frequency) => (PinState::High, 3_000_000_000 / frequency as u64),
+            None => (PinState::Low, 0),
+        };
+        let mut marks: Vec<(u64, u64)> = vec![];
+        for (start, width) in waveform.pulses(active) {
+            let end = start.0 + width.0;
+            match marks.last_mut() {
+                Some(last) if start.0 - last.1 < gap => last.1 = end,
+                _ => marks.push((start.0, end)),
+            }
+        }
+        marks
+    }
+
+    fn matches(&self, duration: u64, nominal: u64) -> bool {
+        duration.abs_diff(nominal) <= nominal * self.tolerance / 100
+    }
+
+    /// Decode all commands.
+    pub fn decode(&self, waveform: &Waveform) -> Vec<IrFrame> {
+        let marks = self.marks(waveform);
+        // the mark and the following space, the last space is endless
+        let pairs: Vec<(u64, u64, u64)> = marks
+            .iter()
+            .enumerate()
+            .map(|(i, (start, end))| {
+                let next = marks.get(i + 1).map(|(next, _)| next - end);
+                (*start, end - start, next.unwrap_or(u64::MAX))
+            })
+            .collect();
+        let mut frames = vec![];
+        let mut i = 0;
+        while i < pairs.len() {
+            let decoded = match self.protocol {
+                IrProtocol::Nec => self.nec(&pairs[i..]),
+                IrProtocol::Rc5 => self.rc5(&pairs[i..]),
+            };
+            match decoded {
+                Some((command, used)) => {
+                    frames.push(IrFrame {
+                        time: Nanoseconds(pairs[i].0),
+                        command,
+                    });
+                    i += used;
+                }
+                None => i += 1,
+            }
+        }
+        super::traced("ir", frames)
+    }
+
+    /// Decode a NEC command, return it with the number of used marks.
+    fn nec(&self, pairs: &[(u64, u64, u64)]) -> Option<(IrCommand, usize)> {
+        let (_, leader, space) = pairs[0];
+        if !self.matches(leader, 16 * NEC_UNIT) {
+            return None;
+        }
+        if self.matches(space, 4 * NEC_UNIT) {
+            let (_, mark, _) = pairs.get(1)?;
+            return self
+                .matches(*mark, NEC_UNIT)
+                .then_some((IrCommand::NecRepeat, 2));
+        }
+        if !self.matches(space, 8 * NEC_UNIT) || pairs.len() < 34 {
+            return None;
+        }
+        let mut bits = 0u32;
+        for (i, (_, mark, space)) in pairs[1..33].iter().enumerate() {
+            if !self.matches(*mark, NEC_UNIT) {
+                return None;
+            }
+            if self.matches(*space, 3 * NEC_UNIT) {
+                bits |= 1 << i;
+            } else if !self.matches(*space, NEC_UNIT) {
+                return None;
+            }
+        }
+        if !self.matches(pairs[33].1, NEC_UNIT) {
+            return None;
+        }
+        let [low, high, command, inverse] = bits.to_le_bytes();
+        if command != !inverse {
+            return None;
+        }
+        let command = if low == !high {
+            IrCommand::Nec {
+                address: low,
+                command,
+            }
+        } else {
+            IrCommand::NecExtended {
+                address: u16::from_le_bytes([low, high]),
+                command,
+            }
+        };
+        Some((command, 34))
+    }
+
+    /// Decode a RC5 command, return it with the number of used marks.
+    fn rc5(&self, pairs: &[(u64, u64, u64)]) -> Option<(IrCommand, usize)> {
+        let halves = |duration| (1..=2usize).find(|n| self.matches(duration, *n as u64 * RC5_HALF));
+        // the first half of the start bit is idle
+        let mut levels = vec![false];
+        let mut used = 0;
+        for (_, mark, space) in pairs {
+            used += 1;
+            levels.extend(vec![true; halves(*mark)?]);
+            if levels.len() >= 28 {
+                break;
+            }
+            match halves(*space) {
+                Some(n) => levels.extend(vec![false; n]),
+                // the frame ends with a space
+                None if levels.len() >= 27 => break,
+                None => return None,
+            }
+        }
+        levels.resize(28, false);
+        let mut bits = 0u16;
+        for half in levels.chunks(2) {
+            let bit = match half {
+                [false, true] => 1,
+                [true, false] => 0,
+                _ => return None,
+            };
+            bits = bits << 1 | bit;
+        }
+        let command = IrCommand::Rc5 {
+            toggle: bits & 1 << 11 != 0,
+            address: (bits >> 6 & 0x1f) as u8,
+            command: (bits & 0x3f) as u8 | if bits & 1 << 12 == 0 { 0x40 } else { 0 },
+        };
+        // the start bit is always one
+        (bits & 1 << 13 != 0).then_some((command, used))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn round_trip() {
+        let frame = |time: u64, command| IrFrame {
+            time: Nanoseconds(time),
+            command,
+        };
+        let nec = [
+            frame(
+                1_000_000,
+                IrCommand::Nec {
+                    address: 0x04,
+                    command: 0x08,
+                },
+            ),
+            frame(109_000_000, IrCommand::NecRepeat),
+            frame(
+                220_000_000,
+                IrCommand::NecExtended {
+                    address: 0x1234,
+                    command: 0xff,
+                },
+            ),
+        ];
+        let rc5 = [
+            frame(
+                1_000_000,
+                IrCommand::Rc5 {
+                    toggle: false,
+                    address: 0x05,
+                    command: 0x35,
+                },
+            ),
+            frame(
+                120_000_000,
+                IrCommand::Rc5 {
+                    toggle: true,
+                    address: 0x1f,
+                    command: 0x40,
+                },
+            ),
+        ];
+        for carrier in [None, Some(38_000)] {
+            let encoder = match carrier {
+                Some(frequency) => IrEncoder::new().with_carrier(frequency),
+                None => IrEncoder::new(),
+            };
+            for (protocol, frames) in [(IrProtocol::Nec, &nec[..]), (IrProtocol::Rc5, &rc5[..])] {
+                let mut decoder = IrDecoder::new(protocol);
+                if let Some(frequency) = carrier {
+                    decoder = decoder.with_carrier(frequency);
+                }
+                let waveform = encoder.encode(frames);
+                assert_eq!(decoder.decode(&waveform), frames);
+            }
+        }
+        // the leader is too short
+        let waveform = IrEncoder::new().encode(&nec[..1]);
+        let decoder = IrDecoder::new(IrProtocol::Nec).with_tolerance(5);
+        let mut changes = waveform.changes().to_vec();
+        changes[2].0 = Nanoseconds(changes[2].0 .0 - 2_000_000);
+        assert!(decoder.decode(&Waveform::new(changes)).is_empty());
+    }
+}
@@ -0,0 +1,213 @@
+//! JTAG scans decoded from recorded signals.
+
+use super::Waveform;
+use crate::devices::Edge;
+use crate::pins::*;
+use embedded_time::duration::*;
+
+/// A state of the JTAG TAP controller.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum TapState {
+    /// Test-Logic-Reset
+    TestLogicReset,
+    /// Run-Test/Idle
+    RunTestIdle,
+    /// Select-DR-Scan
+    SelectDrScan,
+    /// Capture-DR
+    CaptureDr,
+    /// Shift-DR
+    ShiftDr,
+    /// Exit1-DR
+    Exit1Dr,
+    /// Pause-DR
+    PauseDr,
+    /// Exit2-DR
+    Exit2Dr,
+    /// Update-DR
+    UpdateDr,
+    /// Select-IR-Scan
+    SelectIrScan,
+    /// Capture-IR
+    CaptureIr,
+    /// Shift-IR
+    ShiftIr,
+    /// Exit1-IR
+    Exit1Ir,
+    /// Pause-IR
+    PauseIr,
+    /// Exit2-IR
+    Exit2Ir,
+    /// Update-IR
+    UpdateIr,
+}
+
+impl TapState {
+    /// Return the next state for the value of TMS on a rising clock edge.
+    pub fn next(self, tms: bool) -> Self {
+        use TapState::*;
+        match (self, tms) {
+            (TestLogicReset, true) => TestLogicReset,
+            (TestLogicReset, false) => RunTestIdle,
+            (RunTestIdle, true) | (UpdateDr, true) | (UpdateIr, true) => SelectDrScan,
+            (RunTestIdle, false) | (UpdateDr, false) | (UpdateIr, false) => RunTestIdle,
+            (SelectDrScan, true) => SelectIrScan,
+            (SelectDrScan, false) => CaptureDr,
+            (CaptureDr, true) | (ShiftDr, true) => Exit1Dr,
+            (CaptureDr, false) | (ShiftDr, false) | (Exit2Dr, false) => ShiftDr,
+            (Exit1Dr, true) | (Exit2Dr, true) => UpdateDr,
+            (Exit1Dr, false) | (PauseDr, false) => PauseDr,
+            (PauseDr, true) => Exit2Dr,
+            (SelectIrScan, true) => TestLogicReset,
+            (SelectIrScan, false) => CaptureIr,
+            (CaptureIr, true) | (ShiftIr, true) => Exit1Ir,
+            (CaptureIr, false) | (ShiftIr, false) | (Exit2Ir, false) => ShiftIr,
+            (Exit1Ir, true) | (Exit2Ir, true) => UpdateIr,
+            (Exit1Ir, false) | (PauseIr, false) => PauseIr,
+            (PauseIr, true) => Exit2Ir,
+        }
+    }
+}
+
+/// A scan of the instruction or a data register.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct JtagScan {
+    /// The time of the first shifted bit.
+    pub time: Nanoseconds<u64>,
+    /// Whether the instruction register (`true`) or a data register was
+    /// scanned.
+    pub ir: bool,
+    /// The number of shifted bits.
+    pub bits: usize,
+    /// The bits shifted in on TDI, LSB first.
+    pub tdi: Vec<u8>,
+    /// The bits shifted out on TDO, LSB first.
+    pub tdo: Vec<u8>,
+}
+
+impl JtagScan {
+    fn push(&mut self, tdi: bool, tdo: bool) {
+        if self.bits.is_multiple_of(8) {
+            self.tdi.push(0);
+            self.tdo.push(0);
+        }
+        let (byte, bit) = (self.bits / 8, self.bits % 8);
+        self.tdi[byte] |= (tdi as u8) << bit;
+        self.tdo[byte] |= (tdo as u8) << bit;
+        self.bits += 1;
+    }
+}
+
+/// A decoder for JTAG captures of TCK, TMS, TDI and TDO.
+///
+/// The TAP controller starts in Test-Logic-Reset, so the capture should
+/// start with a reset (five clocks with TMS high). All signals are sampled
+/// on the rising edges of TCK. A scan ends with the Update state, scans
+/// paused in between are a single scan.
+#[derive(Clone, Debug, Default)]
+pub struct JtagDecoder {}
+
+impl JtagDecoder {
+    /// Create a new decoder.
+    pub fn new() -> Self {
+        JtagDecoder {}
+    }
+
+    /// Decode all complete scans.
+    pub fn decode(
+        &self,
+        tck: &Waveform,
+        tms: &Waveform,
+        tdi: &Waveform,
+        tdo: &Waveform,
+    ) -> Vec<JtagScan> {
+        let high = |waveform: &Waveform, t| waveform.state_at(t) == Some(PinState::High);
+        let mut scans = vec![];
+        let mut scan: Option<JtagScan> = None;
+        let mut state = TapState::TestLogicReset;
+        let edges = tck
+            .changes()
+            .iter()
+            .skip(1)
+            .filter(|(_, state)| Edge::to_state(state) == Edge::Rising);
+        for (t, _) in edges {
+            if matches!(state, TapState::ShiftDr | TapState::ShiftIr) {
+                scan.get_or_insert_with(|| JtagScan {
+                    time: *t,
+                    ir: state == TapState::ShiftIr,
+                    ..JtagScan::default()
+                })
+                .push(high(tdi, *t), high(tdo, *t));
+            }
+            state = state.next(high(tms, *t));
+            match state {
+                TapState::UpdateDr | TapState::UpdateIr => scans.extend(scan.take()),
+                TapState::TestLogicReset => scan = None,
+                _ => {}
+            }
+        }
+        super::traced("jtag", scans)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn decode_jtag() {
+        // (tms, tdi, tdo) per clock
+        let mut clocks = vec![(true, false, false); 5];
+        // Run-Test/Idle, Select-DR, Select-IR, Capture-IR, Shift-IR
+        clocks.extend([(false, false, false), (true, false, false)]);
+        clocks.extend([
+            (true, false, false),
+            (false, false, false),
+            (false, false, false),
+        ]);
+        // shift IDCODE instruction 0b1110, TDO returns 0b0001
+        clocks.extend([
+            (false, false, true),
+            (false, true, false),
+            (false, true, false),
+        ]);
+        clocks.extend([
+            (true, true, false),
+            (true, false, false),
+            (false, false, false),
+        ]);
+        // Select-DR, Capture-DR, Shift-DR, shift out 12 bits of 0x477
+        clocks.extend([
+            (true, false, false),
+            (false, false, false),
+            (false, false, false),
+        ]);
+        for i in 0..12 {
+            clocks.push((i == 11, false, 0x477 >> i & 1 == 1));
+        }
+        clocks.push((true, false, false));
+        let level = |high: bool| if high { PinState::High } else { PinState::Low };
+        let mut signals = [vec![], vec![], vec![], vec![]];
+        for (i, (tms, tdi, tdo)) in clocks.iter().enumerate() {
+            let t = i as u64 * 100;
+            signals[0].push((Nanoseconds(t), PinState::Low));
+            signals[1].push((Nanoseconds(t), level(*tms)));
+            signals[2].push((Nanoseconds(t), level(*tdi)));
+            signals[3].push((Nanoseconds(t), level(*tdo)));
+            signals[0].push((Nanoseconds(t + 50), PinState::High));
+        }
+        let [tck, tms, tdi, tdo] = signals.map(Waveform::new);
+        let scans = JtagDecoder::new().decode(&tck, &tms, &tdi, &tdo);
+        assert_eq!(scans.len(), 2);
+        assert!(scans[0].ir);
+        assert_eq!(
+            (scans[0].bits, scans[0].tdi[0], scans[0].tdo[0]),
+            (4, 0b1110, 0b0001)
+        );
+        assert!(!scans[1].ir);
+        assert_eq!(scans[1].bits, 12);
+        assert_eq!(scans[1].tdo, vec![0x77, 0x04]);
+    }
+}
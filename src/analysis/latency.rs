@@ -0,0 +1,137 @@
+//! Latency between edges of two signals.
+
+use super::Waveform;
+use crate::devices::Edge;
+use embedded_time::duration::*;
+
+/// The latencies measured by a [LatencyAnalyzer].
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct Latencies {
+    /// The time of each trigger edge and the delay until the response edge.
+    pub samples: Vec<(Nanoseconds<u64>, Nanoseconds<u64>)>,
+    /// The number of trigger edges without a response edge afterwards.
+    pub missed: usize,
+}
+
+impl Latencies {
+    /// Return the shortest latency.
+    pub fn min(&self) -> Option<Nanoseconds<u64>> {
+        self.samples.iter().map(|(_, l)| *l).min()
+    }
+
+    /// Return the longest latency.
+    pub fn max(&self) -> Option<Nanoseconds<u64>> {
+        self.samples.iter().map(|(_, l)| *l).max()
+    }
+
+    /// Return the mean latency, rounded down.
+    pub fn mean(&self) -> Option<Nanoseconds<u64>> {
+        if self.samples.is_empty() {
+            return None;
+        }
+        let sum: u128 = self.samples.iter().map(|(_, l)| l.0 as u128).sum();
+        Some(Nanoseconds((sum / self.samples.len() as u128) as u64))
+    }
+
+    /// Return the latency below or equal to which `percent` of the latencies
+    /// are, e.g. `99.0` for the 99th percentile.
+    ///
+    /// Uses the nearest rank, so the result is always a measured latency.
+    pub fn percentile(&self, percent: f32) -> Option<Nanoseconds<u64>> {
+        if self.samples.is_empty() {
+            return None;
+        }
+        let mut sorted: Vec<_> = self.samples.iter().map(|(_, l)| *l).collect();
+        sorted.sort_unstable();
+        let rank = (percent.clamp(0.0, 100.0) / 100.0 * sorted.len() as f32).ceil() as usize;
+        Some(sorted[rank.clamp(1, sorted.len()) - 1])
+    }
+}
+
+/// An analyzer for the delay between an edge of a trigger signal and the
+/// next edge of a response signal, e.g. the latency from an interrupt to
+/// the response of the firmware.
+#[derive(Clone, Debug, Default)]
+pub struct LatencyAnalyzer {
+    trigger: Option<Edge>,
+    response: Option<Edge>,
+}
+
+impl LatencyAnalyzer {
+    /// Create a new analyzer that measures from any edge to any edge.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Only measure from edges of the trigger signal of the given kind.
+    pub fn with_trigger_edge(mut self, edge: Edge) -> Self {
+        self.trigger = Some(edge);
+        self
+    }
+
+    /// Only measure to edges of the response signal of the given kind.
+    pub fn with_response_edge(mut self, edge: Edge) -> Self {
+        self.response = Some(edge);
+        self
+    }
+
+    /// Measure the latency of each trigger edge over the whole capture.
+    ///
+    /// A response edge at the same time as the trigger edge counts with a
+    /// latency of zero. The first state of a waveform is not an edge.
+    pub fn analyze(&self, trigger: &Waveform, response: &Waveform) -> Latencies {
+        let edges = |waveform: &Waveform, kind: Option<Edge>| -> Vec<Nanoseconds<u64>> {
+            waveform
+                .changes()
+                .iter()
+                .skip(1)
+                .filter(|(_, state)| kind.is_none_or(|kind| Edge::to_state(state) == kind))
+                .map(|(t, _)| *t)
+                .collect()
+        };
+        let responses = edges(response, self.response);
+        let mut latencies = Latencies::default();
+        for t in edges(trigger, self.trigger) {
+            let next = responses.partition_point(|r| *r < t);
+            match responses.get(next) {
+                Some(r) => latencies.samples.push((t, Nanoseconds(r.0 - t.0))),
+                None => latencies.missed += 1,
+            }
+        }
+        latencies
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::pins::PinState;
+
+    #[test]
+    fn irq_latency() {
+        let mut irq = vec![(Nanoseconds(0), PinState::Low)];
+        let mut led = vec![(Nanoseconds(0), PinState::Low)];
+        for (i, latency) in [100u64, 300, 200, 400].iter().enumerate() {
+            let t = 1_000 * (i as u64 + 1);
+            irq.push((Nanoseconds(t), PinState::High));
+            irq.push((Nanoseconds(t + 50), PinState::Low));
+            let state = if i % 2 == 0 {
+                PinState::High
+            } else {
+                PinState::Low
+            };
+            led.push((Nanoseconds(t + latency), state));
+        }
+        irq.push((Nanoseconds(9_000), PinState::High));
+        let analyzer = LatencyAnalyzer::new().with_trigger_edge(Edge::Rising);
+        let latencies = analyzer.analyze(&Waveform::new(irq), &Waveform::new(led));
+        assert_eq!(latencies.samples.len(), 4);
+        assert_eq!(latencies.missed, 1);
+        assert_eq!(latencies.min(), Some(Nanoseconds(100)));
+        assert_eq!(latencies.max(), Some(Nanoseconds(400)));
+        assert_eq!(latencies.mean(), Some(Nanoseconds(250)));
+        assert_eq!(latencies.percentile(50.0), Some(Nanoseconds(200)));
+        assert_eq!(latencies.percentile(99.0), Some(Nanoseconds(400)));
+    }
+}
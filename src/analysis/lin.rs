@@ -0,0 +1,140 @@
+//! LIN frames decoded from recorded single wire captures.
+
+use super::{Uart, UartEvent, Waveform};
+use embedded_time::duration::*;
+
+/// Return the protected identifier of a frame identifier, the 6-bit
+/// identifier with its two parity bits.
+pub fn lin_pid(id: u8) -> u8 {
+    let id = id & 0x3f;
+    let bit = |i: u8| id >> i & 1;
+    let p0 = bit(0) ^ bit(1) ^ bit(2) ^ bit(4);
+    let p1 = !(bit(1) ^ bit(3) ^ bit(4) ^ bit(5)) & 1;
+    id | p0 << 6 | p1 << 7
+}
+
+/// The checksum model of a LIN frame.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum LinChecksum {
+    /// The checksum covers the data bytes only (LIN 1.x and diagnostic
+    /// frames).
+    Classic,
+    /// The checksum covers the protected identifier and the data bytes
+    /// (LIN 2.x).
+    Enhanced,
+}
+
+impl LinChecksum {
+    /// Calculate the checksum, the inverted sum with carry of the bytes.
+    pub fn compute(self, pid: u8, data: &[u8]) -> u8 {
+        let pid = match self {
+            LinChecksum::Classic => None,
+            LinChecksum::Enhanced => Some(pid),
+        };
+        let sum = pid.iter().chain(data).fold(0u16, |sum, byte| {
+            let sum = sum + *byte as u16;
+            if sum > 0xff {
+                sum - 0xff
+            } else {
+                sum
+            }
+        });
+        !(sum as u8)
+    }
+}
+
+/// A LIN frame, a header of the master with an optional response.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct LinFrame {
+    /// The start of the break field.
+    pub time: Nanoseconds<u64>,
+    /// The received protected identifier.
+    pub pid: u8,
+    /// The data bytes of the response.
+    pub data: Vec<u8>,
+    /// The checksum of the response, `None` if nobody responded.
+    pub checksum: Option<u8>,
+}
+
+impl LinFrame {
+    /// Return the 6-bit frame identifier.
+    pub fn id(&self) -> u8 {
+        self.pid & 0x3f
+    }
+
+    /// Return `true` if the parity bits of the protected identifier are
+    /// correct.
+    pub fn parity(&self) -> bool {
+        lin_pid(self.pid) == self.pid
+    }
+
+    /// Return `true` if the response has a valid checksum of the given
+    /// model.
+    pub fn checksum_valid(&self, model: LinChecksum) -> bool {
+        self.checksum == Some(model.compute(self.pid, &self.data))
+    }
+
+    /// Return the response bytes including the checksum.
+    pub fn response(&self) -> Vec<u8> {
+        self.data.iter().copied().chain(self.checksum).collect()
+    }
+}
+
+/// A decoder for LIN captures.
+///
+/// A frame starts with a break of at least 11 bits (13 bits sent by the
+/// master), followed by the sync byte `0x55` and the protected identifier.
+/// All bytes up to the next break are the response, the last one is the
+/// checksum. Headers with a wrong sync byte are skipped.
+#[derive(Clone, Debug)]
+pub struct LinDecoder {
+    baud: u32,
+    uart: Uart,
+}
+
+impl LinDecoder {
+    /// Create a new decoder for the given baud rate, e.g. 19200.
+    pub fn new(baud: u32) -> Self {
+        LinDecoder {
+            baud,
+            uart: Uart::new(baud),
+        }
+    }
+
+    /// Decode all frames.
+    pub fn decode(&self, waveform: &Waveform) -> Vec<LinFrame> {
+        let min_break = 11_000_000_000 / self.baud as u64;
+        let mut frames = vec![];
+        // the frame and the number of received header bytes
+        let mut current: Option<(LinFrame, usize)> = None;
+        for event in self.uart.decode(waveform) {
+            match event {
+                UartEvent::Break { time, width } => {
+                    frames.extend(current.take().filter(|(_, n)| *n == 2).map(|(f, _)| f));
+                    if width.0 >= min_break {
+                        let frame = LinFrame {
+                            time,
+                            ..LinFrame::default()
+                        };
+                        current = Some((frame, 0));
+                    }
+                }
+                UartEvent::Byte(byte) => match current.as_mut() {
+                    Some((_, 0)) if byte.value != 0x55 => current = None,
+                    Some((_, n @ 0)) => *n = 1,
+                    Some((frame, n @ 1)) => {
+                        frame.pid = byte.value;
+                        *n = 2;
+                    }
+                    Some((frame, _)) => {
+                        frame.data.extend(frame.checksum.replace(byte.value));
+                    }
+                    None => {}
+                },
+            }
+        }
+        frames.extend(current.filter(|(_, n)| *n == 2).map(|(f, _)| f));
+        super::traced("lin", frames)
+    }
+}
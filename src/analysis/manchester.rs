@@ -0,0 +1,236 @@
+//! Manchester and differential Manchester coded bits on a single signal.
+
+use super::Waveform;
+use crate::devices::{Signal, Simulation};
+use crate::pins::*;
+use embedded_time::duration::*;
+
+/// The line code of a [Manchester] codec.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ManchesterCoding {
+    /// A one is a rising edge in the middle of the bit, a zero a falling
+    /// edge (IEEE 802.3, DALI).
+    Ieee,
+    /// A one is a falling edge in the middle of the bit, a zero a rising
+    /// edge (G.E. Thomas).
+    Thomas,
+    /// The level changes at the start of each bit, a one has an additional
+    /// change in the middle of the bit (biphase mark code). The polarity
+    /// doesn't matter.
+    Differential,
+}
+
+/// A group of bits sent without a pause.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct ManchesterFrame {
+    /// The start of the first bit.
+    pub time: Nanoseconds<u64>,
+    /// The bits in the order they are sent.
+    pub bits: Vec<bool>,
+}
+
+impl ManchesterFrame {
+    /// Return the bits as number, the first bit is the most significant.
+    pub fn value(&self) -> u64 {
+        self.bits.iter().fold(0, |v, bit| v << 1 | *bit as u64)
+    }
+}
+
+/// An encoder and decoder for Manchester coded frames.
+///
+/// The line idles low by default. With [ManchesterCoding::Ieee] and
+/// [ManchesterCoding::Thomas] the first bit of a frame has to start with a
+/// change of the idle level, e.g. a start bit, otherwise the first edge
+/// can't be told apart from the middle of a bit. Frames of
+/// [ManchesterCoding::Differential] end with a change of the level, after
+/// two bit periods the line returns to idle.
+///
+/// Edges are expected within a quarter of the bit period of their nominal
+/// time. The decoder synchronizes on each edge, so slow drifts of the bit
+/// rate are tolerated.
+///
+/// ```
+/// use embedded_hal_vcd::analysis::{Manchester, ManchesterCoding, ManchesterFrame};
+/// use embedded_time::duration::*;
+///
+/// let codec = Manchester::new(ManchesterCoding::Ieee, Nanoseconds(1_000u64));
+/// let frame = ManchesterFrame {
+///     time: Nanoseconds(5_000),
+///     bits: vec![false, true, true, false],
+/// };
+/// let waveform = codec.encode(&[frame.clone()]);
+/// assert_eq!(codec.decode(&waveform), vec![frame]);
+/// ```
+#[derive(Clone, Debug)]
+pub struct Manchester {
+    coding: ManchesterCoding,
+    period: u64,
+    idle: PinState,
+}
+
+impl Manchester {
+    /// Create a new codec with the given bit period.
+    pub fn new(coding: ManchesterCoding, period: Nanoseconds<u64>) -> Self {
+        Manchester {
+            coding,
+            period: period.0,
+            idle: PinState::Low,
+        }
+    }
+
+    /// Set the level of the idle line.
+    pub fn with_idle(mut self, idle: PinState) -> Self {
+        self.idle = idle;
+        self
+    }
+
+    fn active(&self) -> PinState {
+        match self.idle {
+            PinState::Low => PinState::High,
+            _ => PinState::Low,
+        }
+    }
+
+    /// Encode frames, which have to be in chronological order and separated
+    /// by at least two bit periods.
+    pub fn encode(&self, frames: &[ManchesterFrame]) -> Waveform {
+        let half = self.period / 2;
+        let level = |high: bool| if high { PinState::High } else { PinState::Low };
+        let mut changes = vec![(Nanoseconds(0), self.idle.clone())];
+        for frame in frames {
+            let mut t = frame.time.0;
+            let mut high = self.idle == PinState::High;
+            for bit in frame.bits.iter() {
+                let (first, second) = match self.coding {
+                    ManchesterCoding::Ieee => (!bit, *bit),
+                    ManchesterCoding::Thomas => (*bit, !bit),
+                    ManchesterCoding::Differential => (!high, high ^ !bit),
+                };
+                changes.push((Nanoseconds(t), level(first)));
+                changes.push((Nanoseconds(t + half), level(second)));
+                high = second;
+                t += self.period;
+            }
+            if self.coding == ManchesterCoding::Differential {
+                changes.push((Nanoseconds(t), level(!high)));
+                t += 2 * self.period;
+            }
+            changes.push((Nanoseconds(t), self.idle.clone()));
+        }
+        Waveform::new(changes)
+    }
+
+    /// Schedule frames on a signal of a [Simulation].
+    pub fn play(&self, sim: &mut Simulation, signal: Signal, frames: &[ManchesterFrame]) {
+        crate::testing::stimulus::play(&self.encode(frames), sim, signal);
+    }
+
+    /// Decode all frames with at least one bit.
+    pub fn decode(&self, waveform: &Waveform) -> Vec<ManchesterFrame> {
+        let high = |state: &PinState| *state == PinState::High;
+        let edges: Vec<(u64, bool)> = waveform
+            .changes()
+            .iter()
+            .skip(1)
+            .map(|(t, state)| (t.0, high(state)))
+            .collect();
+        let (half, window) = (self.period / 2, self.period / 4);
+        // find the next edge within the window around `t`, starting at `i`
+        let find = |i: usize, t: u64| {
+            edges[i..]
+                .iter()
+                .take_while(|(e, _)| *e <= t + window)
+                .position(|(e, _)| *e + window >= t)
+                .map(|j| i + j)
+        };
+        let mut frames = vec![];
+        let mut i = 0;
+        while i < edges.len() {
+            let (start, rising) = edges[i];
+            let mut frame = ManchesterFrame {
+                time: Nanoseconds(start),
+                bits: vec![],
+            };
+            if self.coding == ManchesterCoding::Differential {
+                let mut boundary = (i, start);
+                loop {
+                    let mid = find(boundary.0 + 1, boundary.1 + half);
+                    let next = mid.unwrap_or(boundary.0) + 1;
+                    match find(next, boundary.1 + self.period) {
+                        Some(end) => {
+                            frame.bits.push(mid.is_some());
+                            boundary = (end, edges[end].0);
+                        }
+                        None => break,
+                    }
+                }
+                i = boundary.0 + 1;
+            } else {
+                // a frame starts with a change of the idle level
+                if rising != (self.active() == PinState::High) {
+                    i += 1;
+                    continue;
+                }
+                let mut expected = start + half;
+                let mut next = i + 1;
+                while let Some(mid) = find(next, expected) {
+                    let (t, rising) = edges[mid];
+                    frame
+                        .bits
+                        .push(rising == (self.coding == ManchesterCoding::Ieee));
+                    expected = t + self.period;
+                    next = mid + 1;
+                }
+                i = next;
+            }
+            if !frame.bits.is_empty() {
+                frames.push(frame);
+            }
+        }
+        super::traced("manchester", frames)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn round_trip_with_drift() {
+        let frames = vec![
+            ManchesterFrame {
+                time: Nanoseconds(10_000),
+                bits: vec![true, true, false, true, false, false, false, true],
+            },
+            ManchesterFrame {
+                time: Nanoseconds(50_000),
+                bits: vec![true, false, true, true],
+            },
+        ];
+        for coding in [
+            ManchesterCoding::Ieee,
+            ManchesterCoding::Thomas,
+            ManchesterCoding::Differential,
+        ] {
+            for idle in [PinState::Low, PinState::High] {
+                let mut frames = frames.clone();
+                // the first bit has to leave the idle level
+                if coding != ManchesterCoding::Differential {
+                    let first = (coding == ManchesterCoding::Ieee) == (idle == PinState::High);
+                    frames.iter_mut().for_each(|f| f.bits[0] = first);
+                }
+                let codec = Manchester::new(coding, Nanoseconds(2000u64)).with_idle(idle.clone());
+                let waveform = codec.encode(&frames);
+                assert_eq!(codec.decode(&waveform), frames, "{:?} {:?}", coding, idle);
+                // decoding a 10% slower transmitter
+                let slow = Manchester::new(coding, Nanoseconds(2200u64)).with_idle(idle);
+                let decoded = codec.decode(&slow.encode(&frames));
+                let bits: Vec<_> = decoded.iter().map(|f| f.bits.clone()).collect();
+                let expected: Vec<_> = frames.iter().map(|f| f.bits.clone()).collect();
+                assert_eq!(bits, expected);
+            }
+        }
+        assert_eq!(frames[1].value(), 0b1011);
+    }
+}
@@ -0,0 +1,146 @@
+//! PS/2 keyboard frames and scancodes decoded from recorded signals.
+
+use super::Waveform;
+use crate::devices::Edge;
+use crate::pins::*;
+use embedded_time::duration::*;
+
+/// A byte sent by the device.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct Ps2Frame {
+    /// The time of the start bit.
+    pub time: Nanoseconds<u64>,
+    /// The data byte.
+    pub byte: u8,
+    /// Whether the (odd) parity bit was correct.
+    pub parity: bool,
+    /// Whether the stop bit was high.
+    pub stop: bool,
+}
+
+/// A key event of scancode set 2.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct Ps2Key {
+    /// The time of the first frame of the scancode.
+    pub time: Nanoseconds<u64>,
+    /// The scancode without prefixes.
+    pub code: u8,
+    /// Whether the scancode had the `E0` prefix.
+    pub extended: bool,
+    /// Whether the key was released (`F0` prefix).
+    pub released: bool,
+}
+
+impl Ps2Key {
+    /// Return the bytes the keyboard sends for this event.
+    pub fn bytes(&self) -> Vec<u8> {
+        let mut bytes = vec![];
+        if self.extended {
+            bytes.push(0xe0);
+        }
+        if self.released {
+            bytes.push(0xf0);
+        }
+        bytes.push(self.code);
+        bytes
+    }
+}
+
+/// A decoder for PS/2 captures of clock and data.
+///
+/// Both lines are open collector, so a floating line is read as high. Data
+/// is sampled on the falling edges of the clock, a frame has a start bit
+/// (low), eight data bits LSB first, an odd parity bit and a stop bit
+/// (high). A partial frame is dropped if the clock is idle for longer than
+/// the timeout, 500 µs by default.
+#[derive(Clone, Debug)]
+pub struct Ps2Decoder {
+    timeout: u64,
+}
+
+impl Default for Ps2Decoder {
+    fn default() -> Self {
+        Ps2Decoder { timeout: 500_000 }
+    }
+}
+
+impl Ps2Decoder {
+    /// Create a new decoder.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Set the idle time after which a partial frame is dropped.
+    pub fn with_timeout(mut self, timeout: Nanoseconds<u64>) -> Self {
+        self.timeout = timeout.0;
+        self
+    }
+
+    /// Decode all complete frames.
+    pub fn decode(&self, clock: &Waveform, data: &Waveform) -> Vec<Ps2Frame> {
+        let mut frames = vec![];
+        let mut bits: Vec<(Nanoseconds<u64>, bool)> = vec![];
+        let edges = clock
+            .changes()
+            .iter()
+            .skip(1)
+            .filter(|(_, state)| Edge::to_state(state) == Edge::Falling);
+        for (t, _) in edges {
+            if matches!(bits.last(), Some((last, _)) if t.0 - last.0 > self.timeout) {
+                bits.clear();
+            }
+            let bit = data.state_at(*t) != Some(PinState::Low);
+            // wait for a start bit
+            if bits.is_empty() && bit {
+                continue;
+            }
+            bits.push((*t, bit));
+            if bits.len() == 11 {
+                let byte = bits[1..9]
+                    .iter()
+                    .rev()
+                    .fold(0u8, |b, (_, bit)| b << 1 | *bit as u8);
+                frames.push(Ps2Frame {
+                    time: bits[0].0,
+                    byte,
+                    parity: (byte.count_ones() % 2 == 0) == bits[9].1,
+                    stop: bits[10].1,
+                });
+                bits.clear();
+            }
+        }
+        super::traced("ps2", frames)
+    }
+
+    /// Interpret frames as scancode set 2 key events.
+    ///
+    /// Frames with a wrong parity or stop bit are skipped along with the
+    /// prefixes before them.
+    pub fn keys(&self, frames: &[Ps2Frame]) -> Vec<Ps2Key> {
+        let mut keys = vec![];
+        let mut key: Option<Ps2Key> = None;
+        for frame in frames {
+            if !(frame.parity && frame.stop) {
+                key = None;
+                continue;
+            }
+            let current = key.get_or_insert(Ps2Key {
+                time: frame.time,
+                code: 0,
+                extended: false,
+                released: false,
+            });
+            match frame.byte {
+                0xe0 => current.extended = true,
+                0xf0 => current.released = true,
+                code => {
+                    current.code = code;
+                    keys.extend(key.take());
+                }
+            }
+        }
+        keys
+    }
+}
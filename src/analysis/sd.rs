@@ -0,0 +1,219 @@
+//! SD card commands decoded from SPI mode transactions.
+
+use super::SpiFrame;
+use embedded_time::duration::*;
+
+/// Calculate the 7-bit CRC of a command (polynomial `0x09`), the
+/// transmitted last byte is `crc << 1 | 1`.
+pub fn sd_crc7(bytes: &[u8]) -> u8 {
+    bytes.iter().fold(0u8, |crc, byte| {
+        (0..8).rev().fold(crc, |crc, bit| {
+            let feedback = (crc >> 6 ^ byte >> bit) & 1;
+            let crc = crc << 1 & 0x7f;
+            if feedback != 0 {
+                crc ^ 0x09
+            } else {
+                crc
+            }
+        })
+    })
+}
+
+/// Calculate the 16-bit CRC of a data block (CCITT polynomial `0x1021`,
+/// seed `0`).
+pub fn sd_crc16(data: &[u8]) -> u16 {
+    data.iter().fold(0u16, |crc, byte| {
+        (0..8).fold(crc ^ (*byte as u16) << 8, |crc, _| {
+            if crc & 0x8000 != 0 {
+                crc << 1 ^ 0x1021
+            } else {
+                crc << 1
+            }
+        })
+    })
+}
+
+/// A data block read from or written to the card.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct SdBlock {
+    /// The data bytes.
+    pub data: Vec<u8>,
+    /// Whether the transmitted CRC matches the data.
+    pub crc_ok: bool,
+    /// The data response token of the card to a written block.
+    pub response: Option<u8>,
+}
+
+/// A command sent to an SD card in SPI mode.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct SdCommand {
+    /// The start of the SPI frame that contains the command.
+    pub time: Nanoseconds<u64>,
+    /// The command index, e.g. `17` for CMD17.
+    pub index: u8,
+    /// Whether this is an application specific command following CMD55,
+    /// e.g. ACMD41.
+    pub app: bool,
+    /// The 32-bit argument.
+    pub argument: u32,
+    /// Whether the CRC of the command is correct. Cards ignore the CRC of
+    /// most commands in SPI mode, except for CMD0 and CMD8.
+    pub crc_ok: bool,
+    /// The response, the R1 byte followed by the rest of an R2, R3 or R7
+    /// response. Empty if the card did not respond.
+    pub response: Vec<u8>,
+    /// The data blocks transferred after the response.
+    pub blocks: Vec<SdBlock>,
+}
+
+impl SdCommand {
+    /// Return the R1 response byte.
+    pub fn r1(&self) -> Option<u8> {
+        self.response.first().copied()
+    }
+
+    /// Return the name of the command, e.g. `CMD17` or `ACMD41`.
+    pub fn name(&self) -> String {
+        format!("{}CMD{}", if self.app { "A" } else { "" }, self.index)
+    }
+}
+
+/// The block size of a read command, `None` for commands without data.
+fn read_size(command: &SdCommand) -> Option<usize> {
+    match (command.app, command.index) {
+        (false, 17 | 18) => Some(512),
+        (false, 9 | 10) => Some(16),
+        (true, 13) => Some(64),
+        (true, 51) => Some(8),
+        _ => None,
+    }
+}
+
+/// A decoder for SD card commands in SPI transactions.
+///
+/// A command is a 6 byte frame on MOSI starting with the bits `01` and
+/// ending with a stop bit. The card answers with R1 within 8 bytes, R7
+/// (CMD8) and R3 (CMD58) carry 4 more bytes, R2 (CMD13) one more byte.
+/// After a successful read command the data blocks start with the token
+/// `0xFE` on MISO, after a write command with `0xFE` (CMD24) or `0xFC`
+/// (CMD25) on MOSI, each followed by the data and its CRC16. The bytes of
+/// consecutive SPI frames are joined, so the chip select may be released
+/// between command and data.
+#[derive(Clone, Debug, Default)]
+pub struct SdDecoder {}
+
+impl SdDecoder {
+    /// Create a new decoder.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Decode all commands.
+    pub fn decode(&self, frames: &[SpiFrame]) -> Vec<SdCommand> {
+        let bytes: Vec<(Nanoseconds<u64>, u8, u8)> = frames
+            .iter()
+            .flat_map(|frame| {
+                frame
+                    .mosi
+                    .iter()
+                    .zip(&frame.miso)
+                    .map(move |(mosi, miso)| (frame.start, *mosi, *miso))
+            })
+            .collect();
+        let mosi: Vec<u8> = bytes.iter().map(|(_, mosi, _)| *mosi).collect();
+        let miso: Vec<u8> = bytes.iter().map(|(_, _, miso)| *miso).collect();
+        let is_command =
+            |i: usize| i + 6 <= mosi.len() && mosi[i] & 0xc0 == 0x40 && mosi[i + 5] & 1 == 1;
+        // read a block of `size` bytes with its CRC after the token at `i`
+        let block = |data: &[u8], i: usize, size: usize| {
+            let block = data.get(i + 1..i + 3 + size)?;
+            let (data, crc) = block.split_at(size);
+            Some(SdBlock {
+                data: data.to_vec(),
+                crc_ok: sd_crc16(data) == u16::from_be_bytes([crc[0], crc[1]]),
+                response: None,
+            })
+        };
+
+        let mut commands = vec![];
+        let mut app = false;
+        let mut i = 0;
+        while i < mosi.len() {
+            if !is_command(i) {
+                i += 1;
+                continue;
+            }
+            let mut command = SdCommand {
+                time: bytes[i].0,
+                index: mosi[i] & 0x3f,
+                app,
+                argument: u32::from_be_bytes([mosi[i + 1], mosi[i + 2], mosi[i + 3], mosi[i + 4]]),
+                crc_ok: sd_crc7(&mosi[i..i + 5]) == mosi[i + 5] >> 1,
+                ..SdCommand::default()
+            };
+            app = command.index == 55 && !command.app;
+            i += 6;
+            let r1 = (i..(i + 9).min(miso.len())).find(|k| miso[*k] & 0x80 == 0);
+            if let Some(k) = r1 {
+                // errors are answered with R1 only
+                let extra = match (command.app, command.index, miso[k] & 0xfe) {
+                    (false, 8 | 58, 0) => 4,
+                    (_, 13, _) => 1,
+                    _ => 0,
+                };
+                let end = (k + 1 + extra).min(miso.len());
+                command.response = miso[k..end].to_vec();
+                i = end;
+            }
+            if command.r1() == Some(0) {
+                if let Some(size) = read_size(&command) {
+                    // read data until the next command, e.g. CMD12
+                    while let Some(token) = (i..miso.len())
+                        .take_while(|k| !is_command(*k))
+                        .find(|k| miso[*k] == 0xfe)
+                    {
+                        match block(&miso, token, size) {
+                            Some(block) => command.blocks.push(block),
+                            None => break,
+                        }
+                        i = token + 3 + size;
+                        if command.index != 18 {
+                            break;
+                        }
+                    }
+                } else if !command.app && matches!(command.index, 24 | 25) {
+                    let data_token = if command.index == 24 { 0xfe } else { 0xfc };
+                    while let Some(token) = (i..mosi.len())
+                        .take_while(|k| !is_command(*k))
+                        .find(|k| mosi[*k] != 0xff)
+                    {
+                        i = token + 1;
+                        if mosi[token] != data_token {
+                            // the stop token of a multiple block write
+                            break;
+                        }
+                        let mut block = match block(&mosi, token, 512) {
+                            Some(block) => block,
+                            None => break,
+                        };
+                        i = token + 515;
+                        block.response = (i..(i + 8).min(miso.len()))
+                            .find(|k| miso[*k] & 0x11 == 0x01)
+                            .map(|k| {
+                                i = k + 1;
+                                miso[k] & 0x1f
+                            });
+                        command.blocks.push(block);
+                        if command.index == 24 {
+                            break;
+                        }
+                    }
+                }
+            }
+            commands.push(command);
+        }
+        super::traced("sd", commands)
+    }
+}
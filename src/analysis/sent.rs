@@ -0,0 +1,187 @@
+//! SENT (SAE J2716) frames decoded from recorded pulses.
+
+use super::Waveform;
+use crate::devices::Edge;
+use embedded_time::duration::*;
+
+/// Calculate the 4-bit CRC of SENT data nibbles (polynomial `0x1d`, seed
+/// `5`), as recommended since J2716 2010 with an augmenting zero nibble.
+pub fn sent_crc(nibbles: &[u8]) -> u8 {
+    crc4(nibbles.iter().chain([0].iter()))
+}
+
+/// Calculate the CRC of nibbles without augmentation.
+fn crc4<'a, I: Iterator<Item = &'a u8>>(nibbles: I) -> u8 {
+    nibbles.fold(5, |crc, nibble| {
+        let crc = (0..4).fold(crc, |crc, _| {
+            let crc = crc << 1;
+            if crc & 0x10 != 0 {
+                crc ^ 0x1d
+            } else {
+                crc
+            }
+        });
+        crc ^ (nibble & 0x0f)
+    })
+}
+
+/// A SENT frame.
+#[derive(Clone, Debug, Default, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct SentFrame {
+    /// The falling edge that starts the sync pulse.
+    pub time: Nanoseconds<u64>,
+    /// The clock tick measured from the sync pulse in ns.
+    pub tick: f64,
+    /// The status and communication nibble.
+    pub status: u8,
+    /// The data nibbles.
+    pub data: Vec<u8>,
+    /// The received CRC nibble.
+    pub crc: u8,
+    /// Whether the CRC matches the data nibbles.
+    pub crc_ok: bool,
+}
+
+impl SentFrame {
+    /// Return the data nibbles as number, the first nibble is the most
+    /// significant.
+    pub fn value(&self) -> u32 {
+        self.data
+            .iter()
+            .fold(0, |v, nibble| v << 4 | *nibble as u32)
+    }
+}
+
+/// A decoder for SENT captures.
+///
+/// Nibbles are the times between falling edges: a sync pulse of 56 ticks,
+/// a status nibble, the data nibbles and the CRC nibble, each of 12 to 27
+/// ticks for the values 0 to 15. The tick of each frame is measured from
+/// its sync pulse, which may deviate by 20% from the nominal tick. An
+/// optional pause pulse after the CRC nibble is skipped.
+#[derive(Clone, Debug)]
+pub struct SentDecoder {
+    tick: u64,
+    nibbles: usize,
+    legacy: bool,
+}
+
+impl SentDecoder {
+    /// Create a new decoder for a nominal clock tick, e.g. 3 µs, and a
+    /// number of data nibbles, usually 6.
+    pub fn new(tick: Nanoseconds<u64>, nibbles: usize) -> Self {
+        SentDecoder {
+            tick: tick.0,
+            nibbles,
+            legacy: false,
+        }
+    }
+
+    /// Check the CRC without the augmenting zero nibble, as specified
+    /// before J2716 2010.
+    pub fn with_legacy_crc(mut self) -> Self {
+        self.legacy = true;
+        self
+    }
+
+    fn crc(&self, nibbles: &[u8]) -> u8 {
+        if self.legacy {
+            crc4(nibbles.iter())
+        } else {
+            sent_crc(nibbles)
+        }
+    }
+
+    /// Decode all frames.
+    pub fn decode(&self, waveform: &Waveform) -> Vec<SentFrame> {
+        let falling: Vec<u64> = waveform
+            .changes()
+            .iter()
+            .skip(1)
+            .filter(|(_, state)| Edge::to_state(state) == Edge::Falling)
+            .map(|(t, _)| t.0)
+            .collect();
+        let intervals: Vec<u64> = falling.windows(2).map(|w| w[1] - w[0]).collect();
+        let sync = 56 * self.tick;
+        let mut frames = vec![];
+        let mut i = 0;
+        while i + self.nibbles + 3 <= intervals.len() {
+            if intervals[i].abs_diff(sync) * 5 > sync {
+                i += 1;
+                continue;
+            }
+            let tick = intervals[i] as f64 / 56.0;
+            let nibbles: Option<Vec<u8>> = intervals[i + 1..i + self.nibbles + 3]
+                .iter()
+                .map(|interval| {
+                    let ticks = (*interval as f64 / tick).round() as i64 - 12;
+                    (0..16).contains(&ticks).then_some(ticks as u8)
+                })
+                .collect();
+            let nibbles = match nibbles {
+                Some(nibbles) => nibbles,
+                None => {
+                    i += 1;
+                    continue;
+                }
+            };
+            let (crc, data) = nibbles[1..].split_last().unwrap();
+            frames.push(SentFrame {
+                time: Nanoseconds(falling[i]),
+                tick,
+                status: nibbles[0],
+                data: data.to_vec(),
+                crc: *crc,
+                crc_ok: self.crc(data) == *crc,
+            });
+            i += self.nibbles + 3;
+        }
+        super::traced("sent", frames)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::pins::*;
+
+    /// Build frames with a pause pulse, each pulse starts with 5 ticks low.
+    fn frames(tick: f64, frames: &[(u8, [u8; 6], Option<u8>)]) -> Waveform {
+        let mut changes = vec![(Nanoseconds(0), PinState::High)];
+        let mut t = 1000.0;
+        let mut pulse = |ticks: u64| {
+            changes.push((Nanoseconds(t as u64), PinState::Low));
+            changes.push((Nanoseconds((t + 5.0 * tick) as u64), PinState::High));
+            t += ticks as f64 * tick;
+        };
+        for (status, data, crc) in frames {
+            pulse(56);
+            pulse(12 + *status as u64);
+            for nibble in data {
+                pulse(12 + *nibble as u64);
+            }
+            pulse(12 + crc.unwrap_or_else(|| sent_crc(data)) as u64);
+            pulse(100);
+        }
+        pulse(56);
+        Waveform::new(changes)
+    }
+
+    #[test]
+    fn decode_sent() {
+        let data = [0x1, 0x2, 0x3, 0xa, 0xb, 0xc];
+        // a 15% fast transmitter
+        let waveform = frames(2550.0, &[(0x0, data, None), (0x8, data, Some(0x0))]);
+        let decoded = SentDecoder::new(Nanoseconds(3000u64), 6).decode(&waveform);
+        assert_eq!(decoded.len(), 2);
+        assert_eq!(decoded[0].value(), 0x123abc);
+        assert!((decoded[0].tick - 2550.0).abs() < 1.0);
+        assert!(decoded[0].crc_ok);
+        assert_eq!(decoded[1].status, 0x8);
+        assert_eq!(decoded[1].crc_ok, sent_crc(&data) == 0);
+        // the same data, checked with the legacy CRC
+        let legacy = SentDecoder::new(Nanoseconds(3000u64), 6).with_legacy_crc();
+        assert!(!legacy.decode(&waveform)[0].crc_ok);
+    }
+}
@@ -0,0 +1,263 @@
+//! Power and reset sequencing of multiple rails.
+
+use super::Waveform;
+use crate::pins::*;
+use embedded_time::duration::*;
+use std::fmt;
+
+/// A violation of a [PowerSequence].
+#[derive(Clone, Debug, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum SequenceViolation {
+    /// The signal was never activated, or is missing in the capture.
+    Missing {
+        /// The name of the signal.
+        name: String,
+    },
+    /// The signal `after` was activated before the signal `before`.
+    Order {
+        /// The signal that must be activated first.
+        before: String,
+        /// The signal that was activated too early.
+        after: String,
+        /// The time `after` was activated.
+        time: Nanoseconds<u64>,
+    },
+    /// The delay between the activation of `before` and `after` is out of
+    /// range.
+    Delay {
+        /// The signal that is activated first.
+        before: String,
+        /// The signal that is activated second.
+        after: String,
+        /// The time `after` was activated.
+        time: Nanoseconds<u64>,
+        /// The measured delay.
+        delay: Nanoseconds<u64>,
+    },
+}
+
+impl fmt::Display for SequenceViolation {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            SequenceViolation::Missing { name } => write!(f, "{} was never activated", name),
+            SequenceViolation::Order {
+                before,
+                after,
+                time,
+            } => write!(f, "{} was activated at {} before {}", after, time, before),
+            SequenceViolation::Delay {
+                before,
+                after,
+                time,
+                delay,
+            } => write!(
+                f,
+                "{} was activated at {}, {} after {}",
+                after, time, delay, before
+            ),
+        }
+    }
+}
+
+impl std::error::Error for SequenceViolation {}
+
+/// A rule of a [PowerSequence].
+#[derive(Clone, Debug)]
+struct Step {
+    before: String,
+    after: String,
+    min: Nanoseconds<u64>,
+    max: Option<Nanoseconds<u64>>,
+}
+
+/// A checker for the order and timing in which power rails, enables and
+/// resets are activated, e.g. during the bring-up of a PMIC.
+///
+/// A signal is activated when it first changes to its active state, high by
+/// default. A signal that is active from the start of the capture counts as
+/// activated at its first change.
+///
+/// ```
+/// use embedded_hal_vcd::analysis::{PowerSequence, SequenceViolation, Waveform};
+/// use embedded_hal_vcd::pins::PinState;
+/// use embedded_time::duration::*;
+///
+/// let step = |t: u64| {
+///     Waveform::new(vec![
+///         (Nanoseconds(0), PinState::Low),
+///         (Nanoseconds(t), PinState::High),
+///     ])
+/// };
+/// let signals = vec![
+///     ("vcore".to_string(), step(1_000_000)),
+///     ("vio".to_string(), step(2_500_000)),
+///     ("nreset".to_string(), step(3_000_000)),
+/// ];
+/// let sequence = PowerSequence::new()
+///     .with_step("vcore", "vio", Nanoseconds(2_000_000), None)
+///     .with_step("vio", "nreset", Nanoseconds(0), Some(Nanoseconds(1_000_000)));
+/// assert_eq!(
+///     sequence.check(&signals),
+///     vec![SequenceViolation::Delay {
+///         before: "vcore".to_string(),
+///         after: "vio".to_string(),
+///         time: Nanoseconds(2_500_000),
+///         delay: Nanoseconds(1_500_000),
+///     }]
+/// );
+/// ```
+#[derive(Clone, Debug, Default)]
+pub struct PowerSequence {
+    steps: Vec<Step>,
+    active: Vec<(String, PinState)>,
+}
+
+impl PowerSequence {
+    /// Create a new sequence without steps.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Require `after` to be activated at least `min` and at most `max`
+    /// after `before`.
+    pub fn with_step(
+        mut self,
+        before: &str,
+        after: &str,
+        min: Nanoseconds<u64>,
+        max: Option<Nanoseconds<u64>>,
+    ) -> Self {
+        self.steps.push(Step {
+            before: before.to_string(),
+            after: after.to_string(),
+            min,
+            max,
+        });
+        self
+    }
+
+    /// Set the active state of a signal, e.g. low for an active low enable.
+    pub fn with_active(mut self, name: &str, state: PinState) -> Self {
+        self.active.push((name.to_string(), state));
+        self
+    }
+
+    /// Return the time a signal is activated.
+    pub fn activation(&self, name: &str, waveform: &Waveform) -> Option<Nanoseconds<u64>> {
+        let active = self
+            .active
+            .iter()
+            .rev()
+            .find(|(signal, _)| signal == name)
+            .map_or(PinState::High, |(_, state)| state.clone());
+        waveform
+            .changes()
+            .iter()
+            .find(|(_, state)| *state == active)
+            .map(|(t, _)| *t)
+    }
+
+    /// Check all steps against named waveforms, e.g. read with
+    /// [Waveform::read_all].
+    ///
+    /// Each missing signal is reported once, steps with a missing signal are
+    /// not checked.
+    pub fn check(&self, signals: &[(String, Waveform)]) -> Vec<SequenceViolation> {
+        let mut violations = vec![];
+        let mut activation = |name: &String| {
+            let time = signals
+                .iter()
+                .find(|(signal, _)| signal == name)
+                .and_then(|(_, waveform)| self.activation(name, waveform));
+            let missing = SequenceViolation::Missing { name: name.clone() };
+            if time.is_none() && !violations.contains(&missing) {
+                violations.push(missing);
+            }
+            time
+        };
+        let mut times = vec![];
+        for step in self.steps.iter() {
+            times.push((activation(&step.before), activation(&step.after)));
+        }
+        for (step, times) in self.steps.iter().zip(times) {
+            let (first, second) = match times {
+                (Some(first), Some(second)) => (first, second),
+                _ => continue,
+            };
+            if second < first {
+                violations.push(SequenceViolation::Order {
+                    before: step.before.clone(),
+                    after: step.after.clone(),
+                    time: second,
+                });
+                continue;
+            }
+            let delay = Nanoseconds(second.0 - first.0);
+            if delay < step.min || step.max.is_some_and(|max| delay > max) {
+                violations.push(SequenceViolation::Delay {
+                    before: step.before.clone(),
+                    after: step.after.clone(),
+                    time: second,
+                    delay,
+                });
+            }
+        }
+        violations
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn rail_order() {
+        let signals = vec![
+            (
+                "pmic.en_3v3".to_string(),
+                Waveform::new(vec![
+                    (Nanoseconds(0), PinState::High),
+                    (Nanoseconds(500_000), PinState::Low),
+                ]),
+            ),
+            (
+                "pmic.en_1v8".to_string(),
+                Waveform::new(vec![
+                    (Nanoseconds(0), PinState::High),
+                    (Nanoseconds(300_000), PinState::Low),
+                ]),
+            ),
+            (
+                "nreset".to_string(),
+                Waveform::new(vec![(Nanoseconds(0), PinState::Low)]),
+            ),
+        ];
+        let sequence = PowerSequence::new()
+            .with_active("pmic.en_3v3", PinState::Low)
+            .with_active("pmic.en_1v8", PinState::Low)
+            .with_step("pmic.en_3v3", "pmic.en_1v8", Nanoseconds(0), None)
+            .with_step("pmic.en_1v8", "nreset", Nanoseconds(1_000_000), None)
+            .with_step("nreset", "boot", Nanoseconds(0), None);
+        assert_eq!(
+            sequence.check(&signals),
+            vec![
+                SequenceViolation::Missing {
+                    name: "nreset".to_string()
+                },
+                SequenceViolation::Missing {
+                    name: "boot".to_string()
+                },
+                SequenceViolation::Order {
+                    before: "pmic.en_3v3".to_string(),
+                    after: "pmic.en_1v8".to_string(),
+                    time: Nanoseconds(300_000),
+                },
+            ]
+        );
+        assert_eq!(
+            sequence.check(&signals)[2].to_string(),
+            "pmic.en_1v8 was activated at 300000 before pmic.en_3v3"
+        );
+    }
+}
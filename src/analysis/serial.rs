@@ -0,0 +1,135 @@
+//! The framing of clocked serial protocols.
+
+use crate::devices::Edge;
+use crate::pins::*;
+use embedded_hal::spi::{Mode, Phase, Polarity, MODE_0};
+
+/// The order in which the bits of a word are shifted.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum BitOrder {
+    /// The most significant bit is shifted first.
+    MsbFirst,
+    /// The least significant bit is shifted first.
+    LsbFirst,
+}
+
+/// The clock polarity and phase, bit order, word size and chip select level
+/// of a clocked serial signal, e.g. of SPI or a shift register.
+///
+/// The default is SPI mode 0 with 8 bit words shifted MSB first and an
+/// active low chip select.
+///
+/// ```
+/// use embedded_hal::spi::MODE_3;
+/// use embedded_hal_vcd::analysis::{BitOrder, SerialConfig};
+///
+/// let config = SerialConfig::new(MODE_3)
+///     .with_bit_order(BitOrder::LsbFirst)
+///     .with_word_size(12);
+/// let bits = config.bits(0x123);
+/// assert_eq!((bits.len(), bits[0]), (12, true));
+/// assert_eq!(config.word(&bits), 0x123);
+/// ```
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct SerialConfig {
+    /// The clock polarity and phase.
+    pub mode: Mode,
+    /// The bit order of the words.
+    pub bit_order: BitOrder,
+    /// The number of bits per word, 1 to 32.
+    pub word_size: u8,
+    /// The state of the chip select while a frame is transferred.
+    pub cs_active: PinState,
+}
+
+impl Default for SerialConfig {
+    fn default() -> Self {
+        SerialConfig::new(MODE_0)
+    }
+}
+
+impl SerialConfig {
+    /// Create a new configuration of the given SPI mode with 8 bit words
+    /// shifted MSB first and an active low chip select.
+    pub fn new(mode: Mode) -> Self {
+        SerialConfig {
+            mode,
+            bit_order: BitOrder::MsbFirst,
+            word_size: 8,
+            cs_active: PinState::Low,
+        }
+    }
+
+    /// Set the bit order.
+    pub fn with_bit_order(mut self, bit_order: BitOrder) -> Self {
+        self.bit_order = bit_order;
+        self
+    }
+
+    /// Set the number of bits per word.
+    ///
+    /// # Panics
+    ///
+    /// Panics if the word size is not between 1 and 32 bits.
+    pub fn with_word_size(mut self, word_size: u8) -> Self {
+        assert!(
+            (1..=32).contains(&word_size),
+            "word size must be 1 to 32 bits"
+        );
+        self.word_size = word_size;
+        self
+    }
+
+    /// Set the state of an active chip select, e.g. high for the latch of a
+    /// shift register.
+    pub fn with_cs_active(mut self, cs_active: PinState) -> Self {
+        self.cs_active = cs_active;
+        self
+    }
+
+    /// Return the clock edge the data is sampled on.
+    pub fn sample_edge(&self) -> Edge {
+        let first = self.mode.phase == Phase::CaptureOnFirstTransition;
+        let idle_low = self.mode.polarity == Polarity::IdleLow;
+        if first == idle_low {
+            Edge::Rising
+        } else {
+            Edge::Falling
+        }
+    }
+
+    /// Return the clock edge the data is changed on.
+    pub fn shift_edge(&self) -> Edge {
+        match self.sample_edge() {
+            Edge::Rising => Edge::Falling,
+            _ => Edge::Rising,
+        }
+    }
+
+    /// Return the idle state of the clock.
+    pub fn clock_idle(&self) -> PinState {
+        match self.mode.polarity {
+            Polarity::IdleLow => PinState::Low,
+            Polarity::IdleHigh => PinState::High,
+        }
+    }
+
+    /// Assemble a word from bits in the order they were shifted.
+    pub fn word(&self, bits: &[bool]) -> u32 {
+        let fold = |word: u32, bit: &bool| word << 1 | *bit as u32;
+        match self.bit_order {
+            BitOrder::MsbFirst => bits.iter().fold(0, fold),
+            BitOrder::LsbFirst => bits.iter().rev().fold(0, fold),
+        }
+    }
+
+    /// Return the bits of a word in the order they are shifted.
+    pub fn bits(&self, word: u32) -> Vec<bool> {
+        let size = self.word_size as u32;
+        let bit = |i: u32| word >> i & 1 == 1;
+        match self.bit_order {
+            BitOrder::MsbFirst => (0..size).rev().map(bit).collect(),
+            BitOrder::LsbFirst => (0..size).map(bit).collect(),
+        }
+    }
+}
@@ -0,0 +1,185 @@
+//! RC servo and ESC pulse width analysis.
+
+use super::Waveform;
+use crate::pins::*;
+use embedded_time::duration::*;
+use std::fmt;
+
+/// A position commanded by a servo pulse.
+#[derive(Clone, Copy, Debug, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct ServoPosition {
+    /// The start of the pulse.
+    pub time: Nanoseconds<u64>,
+    /// The width of the pulse.
+    pub pulse: Nanoseconds<u64>,
+    /// The position from `0.0` (minimum pulse) to `1.0` (maximum pulse).
+    pub position: f32,
+}
+
+/// A violation of the servo timing found by a [ServoAnalyzer].
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum ServoViolation {
+    /// The frame starting at `time` has a period out of tolerance.
+    Period {
+        /// The start of the frame.
+        time: Nanoseconds<u64>,
+        /// The measured period.
+        period: Nanoseconds<u64>,
+    },
+    /// The pulse starting at `time` has a width out of tolerance.
+    Pulse {
+        /// The start of the pulse.
+        time: Nanoseconds<u64>,
+        /// The measured pulse width.
+        width: Nanoseconds<u64>,
+    },
+}
+
+impl fmt::Display for ServoViolation {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ServoViolation::Period { time, period } => {
+                write!(f, "frame at {} has a period of {}", time, period)
+            }
+            ServoViolation::Pulse { time, width } => {
+                write!(f, "pulse at {} has a width of {}", time, width)
+            }
+        }
+    }
+}
+
+impl std::error::Error for ServoViolation {}
+
+/// An analyzer for RC servo and ESC control signals.
+///
+/// The signal consists of frames with a fixed period (50 Hz by default),
+/// each starting with a high pulse whose width (1000 µs to 2000 µs by
+/// default) encodes the commanded position.
+#[derive(Clone, Debug)]
+pub struct ServoAnalyzer {
+    period: Nanoseconds<u64>,
+    period_tolerance: Nanoseconds<u64>,
+    min_pulse: Nanoseconds<u64>,
+    max_pulse: Nanoseconds<u64>,
+    pulse_tolerance: Nanoseconds<u64>,
+}
+
+impl ServoAnalyzer {
+    /// Create a new analyzer with a period of 20 ms ± 1 ms and pulses of
+    /// 1000 µs to 2000 µs ± 10 µs.
+    pub fn new() -> Self {
+        ServoAnalyzer {
+            period: Nanoseconds(20_000_000),
+            period_tolerance: Nanoseconds(1_000_000),
+            min_pulse: Nanoseconds(1_000_000),
+            max_pulse: Nanoseconds(2_000_000),
+            pulse_tolerance: Nanoseconds(10_000),
+        }
+    }
+
+    /// Set the expected frame period and its tolerance.
+    pub fn with_period(mut self, period: Nanoseconds<u64>, tolerance: Nanoseconds<u64>) -> Self {
+        self.period = period;
+        self.period_tolerance = tolerance;
+        self
+    }
+
+    /// Set the range of pulse widths and the tolerance for widths outside of it.
+    pub fn with_pulse_range(
+        mut self,
+        min: Nanoseconds<u64>,
+        max: Nanoseconds<u64>,
+        tolerance: Nanoseconds<u64>,
+    ) -> Self {
+        self.min_pulse = min;
+        self.max_pulse = max;
+        self.pulse_tolerance = tolerance;
+        self
+    }
+
+    /// Check all frames of the waveform and return the commanded positions.
+    ///
+    /// The period of the last frame is not checked, as its end is unknown.
+    pub fn analyze(&self, waveform: &Waveform) -> Result<Vec<ServoPosition>, ServoViolation> {
+        let pulses = waveform.pulses(PinState::High);
+        let mut positions = vec![];
+        for (i, (time, width)) in pulses.iter().enumerate() {
+            if let Some((next, _)) = pulses.get(i + 1) {
+                let period = Nanoseconds(next.0 - time.0);
+                if period.0.abs_diff(self.period.0) > self.period_tolerance.0 {
+                    return Err(ServoViolation::Period {
+                        time: *time,
+                        period,
+                    });
+                }
+            }
+            if width.0 + self.pulse_tolerance.0 < self.min_pulse.0
+                || width.0 > self.max_pulse.0 + self.pulse_tolerance.0
+            {
+                return Err(ServoViolation::Pulse {
+                    time: *time,
+                    width: *width,
+                });
+            }
+            let range = (self.max_pulse.0 - self.min_pulse.0) as f32;
+            let position = (width.0 as f32 - self.min_pulse.0 as f32) / range;
+            positions.push(ServoPosition {
+                time: *time,
+                pulse: *width,
+                position: position.clamp(0.0, 1.0),
+            });
+        }
+        Ok(positions)
+    }
+}
+
+impl Default for ServoAnalyzer {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn frames(widths: &[u64], period: u64) -> Waveform {
+        let mut changes = vec![];
+        for (i, width) in widths.iter().enumerate() {
+            let start = i as u64 * period;
+            changes.push((Nanoseconds(start), PinState::High));
+            changes.push((Nanoseconds(start + width), PinState::Low));
+        }
+        Waveform::new(changes)
+    }
+
+    #[test]
+    fn positions() {
+        let analyzer = ServoAnalyzer::new();
+        let waveform = frames(&[1_000_000, 1_500_000, 2_005_000], 20_000_000);
+        let positions: Vec<f32> = analyzer
+            .analyze(&waveform)
+            .unwrap()
+            .iter()
+            .map(|p| p.position)
+            .collect();
+        assert_eq!(positions, vec![0.0, 0.5, 1.0]);
+
+        let waveform = frames(&[1_500_000, 2_100_000], 20_000_000);
+        assert_eq!(
+            analyzer.analyze(&waveform),
+            Err(ServoViolation::Pulse {
+                time: Nanoseconds(20_000_000),
+                width: Nanoseconds(2_100_000),
+            })
+        );
+
+        let waveform = frames(&[1_500_000, 1_500_000], 10_000_000);
+        assert!(matches!(
+            analyzer.analyze(&waveform),
+            Err(ServoViolation::Period { .. })
+        ));
+    }
+}
@@ -0,0 +1,318 @@
+//! SMBus and PMBus transactions interpreted from decoded I2C transfers.
+
+use super::I2cTransfer;
+use embedded_time::duration::*;
+use std::fmt;
+
+/// Calculate the SMBus packet error code (CRC-8, polynomial `0x07`).
+pub fn smbus_pec(bytes: &[u8]) -> u8 {
+    bytes.iter().fold(0, |crc, byte| {
+        (0..8).fold(crc ^ byte, |crc, _| {
+            if crc & 0x80 != 0 {
+                crc << 1 ^ 0x07
+            } else {
+                crc << 1
+            }
+        })
+    })
+}
+
+/// A single SMBus transaction: a write, a read or a write followed by a
+/// read with a repeated start.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct SmbusTransaction {
+    /// The time of the start condition.
+    pub time: Nanoseconds<u64>,
+    /// The 7-bit address.
+    pub address: u8,
+    /// The written bytes, starting with the command code.
+    pub write: Vec<u8>,
+    /// The read bytes.
+    pub read: Vec<u8>,
+    /// Whether the packet error code was valid, `None` without PEC.
+    pub pec: Option<bool>,
+    /// Whether a byte was not acknowledged by the device.
+    pub nak: bool,
+}
+
+impl SmbusTransaction {
+    /// Return the command code, `None` for quick commands and receive byte.
+    pub fn command(&self) -> Option<u8> {
+        self.write.first().copied()
+    }
+
+    /// Return the data of a block write or block read without the byte
+    /// count, `None` if the byte count doesn't match.
+    pub fn block(&self) -> Option<&[u8]> {
+        let data = if self.read.is_empty() {
+            self.write.get(1..)?
+        } else {
+            &self.read[..]
+        };
+        let (count, data) = data.split_first()?;
+        (*count as usize == data.len()).then_some(data)
+    }
+}
+
+/// An interpreter for SMBus transactions.
+#[derive(Clone, Debug, Default)]
+pub struct SmbusDecoder {
+    pec: bool,
+}
+
+impl SmbusDecoder {
+    /// Create a new decoder for transactions without packet error codes.
+    pub fn new() -> Self {
+        SmbusDecoder::default()
+    }
+
+    /// Expect a packet error code as last byte of each transaction with
+    /// data.
+    pub fn with_pec(mut self) -> Self {
+        self.pec = true;
+        self
+    }
+
+    /// Interpret decoded I2C transfers as SMBus transactions.
+    ///
+    /// A write that ends with a repeated start, followed by a read from the
+    /// same address, is a single transaction.
+    pub fn decode(&self, transfers: &[I2cTransfer]) -> Vec<SmbusTransaction> {
+        let mut transactions = vec![];
+        let mut transfers = transfers.iter().peekable();
+        while let Some(first) = transfers.next() {
+            let mut transaction = SmbusTransaction {
+                time: first.time,
+                address: first.address,
+                // read bytes are acknowledged by the master, the last one not
+                nak: !first.address_ack || (!first.read && first.data.iter().any(|(_, ack)| !ack)),
+                ..SmbusTransaction::default()
+            };
+            let mut message = vec![first.address << 1 | first.read as u8];
+            let bytes = first.bytes();
+            message.extend(&bytes);
+            if first.read {
+                transaction.read = bytes;
+            } else {
+                transaction.write = bytes;
+                let read = transfers
+                    .next_if(|next| !first.stop && next.read && next.address == first.address);
+                if let Some(read) = read {
+                    transaction.nak |= !read.address_ack;
+                    transaction.read = read.bytes();
+                    message.push(read.address << 1 | 1);
+                    message.extend(&transaction.read);
+                }
+            }
+            if self.pec && message.len() > 1 {
+                let code = message.pop().unwrap();
+                if transaction.read.is_empty() {
+                    transaction.write.pop();
+                } else {
+                    transaction.read.pop();
+                }
+                transaction.pec = Some(smbus_pec(&message) == code);
+            }
+            transactions.push(transaction);
+        }
+        super::traced("smbus", transactions)
+    }
+}
+
+/// A transaction that didn't match the expectation of a [SmbusExpect].
+#[derive(Clone, Debug, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct SmbusMismatch {
+    /// The index of the transaction.
+    pub index: usize,
+    /// A description of the expected transaction.
+    pub expected: String,
+    /// The recorded transaction, `None` if there are no more transactions.
+    pub actual: Option<SmbusTransaction>,
+}
+
+impl fmt::Display for SmbusMismatch {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match &self.actual {
+            Some(actual) => write!(
+                f,
+                "transaction {} is {:?}, expected {}",
+                self.index, actual, self.expected
+            ),
+            None => write!(
+                f,
+                "transaction {} is missing, expected {}",
+                self.index, self.expected
+            ),
+        }
+    }
+}
+
+impl std::error::Error for SmbusMismatch {}
+
+/// Check recorded transactions in order, e.g. the commands a power
+/// management driver sends.
+///
+/// Transactions with a `nak` or an invalid packet error code never match.
+///
+/// ```
+/// use embedded_hal_vcd::analysis::{SmbusExpect, SmbusTransaction};
+///
+/// let transactions = vec![SmbusTransaction {
+///     address: 0x40,
+///     write: vec![0x21, 0x00, 0x10],
+///     ..SmbusTransaction::default()
+/// }];
+/// let mut expect = SmbusExpect::new(&transactions);
+/// expect.expect_write(0x40, 0x21, &[0x00, 0x10]).unwrap();
+/// assert!(expect.is_done());
+/// ```
+pub struct SmbusExpect<'a> {
+    transactions: &'a [SmbusTransaction],
+    index: usize,
+}
+
+impl<'a> SmbusExpect<'a> {
+    /// Create a new check of the given transactions.
+    pub fn new(transactions: &'a [SmbusTransaction]) -> Self {
+        SmbusExpect {
+            transactions,
+            index: 0,
+        }
+    }
+
+    /// Return `true` if all transactions were checked.
+    pub fn is_done(&self) -> bool {
+        self.index >= self.transactions.len()
+    }
+
+    fn expect(&mut self, address: u8, write: &[u8], read: &[u8]) -> Result<(), SmbusMismatch> {
+        let index = self.index;
+        let actual = self.transactions.get(index);
+        self.index += 1;
+        match actual {
+            Some(t)
+                if t.address == address
+                    && t.write == write
+                    && t.read == read
+                    && !t.nak
+                    && t.pec != Some(false) =>
+            {
+                Ok(())
+            }
+            _ => Err(SmbusMismatch {
+                index,
+                expected: format!(
+                    "address {:#04x} write {:02x?} read {:02x?}",
+                    address, write, read
+                ),
+                actual: actual.cloned(),
+            }),
+        }
+    }
+
+    /// Expect a write of `data` to the command `command`, e.g. write byte
+    /// or write word.
+    pub fn expect_write(
+        &mut self,
+        address: u8,
+        command: u8,
+        data: &[u8],
+    ) -> Result<(), SmbusMismatch> {
+        let write: Vec<u8> = [command].iter().chain(data).copied().collect();
+        self.expect(address, &write, &[])
+    }
+
+    /// Expect a read of `data` from the command `command`, e.g. read byte
+    /// or read word.
+    pub fn expect_read(
+        &mut self,
+        address: u8,
+        command: u8,
+        data: &[u8],
+    ) -> Result<(), SmbusMismatch> {
+        self.expect(address, &[command], data)
+    }
+
+    /// Expect a block write of `data` to the command `command`.
+    pub fn expect_block_write(
+        &mut self,
+        address: u8,
+        command: u8,
+        data: &[u8],
+    ) -> Result<(), SmbusMismatch> {
+        let write: Vec<u8> = [command, data.len() as u8]
+            .iter()
+            .chain(data)
+            .copied()
+            .collect();
+        self.expect(address, &write, &[])
+    }
+
+    /// Expect a block read of `data` from the command `command`.
+    pub fn expect_block_read(
+        &mut self,
+        address: u8,
+        command: u8,
+        data: &[u8],
+    ) -> Result<(), SmbusMismatch> {
+        let read: Vec<u8> = [data.len() as u8].iter().chain(data).copied().collect();
+        self.expect(address, &[command], &read)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn transfer(address: u8, read: bool, data: &[u8], stop: bool) -> I2cTransfer {
+        let last = data.len().saturating_sub(1);
+        I2cTransfer {
+            address,
+            read,
+            address_ack: true,
+            data: data
+                .iter()
+                .enumerate()
+                .map(|(i, byte)| (*byte, !read || i < last))
+                .collect(),
+            stop,
+            ..I2cTransfer::default()
+        }
+    }
+
+    #[test]
+    fn block_read_with_pec() {
+        // PMBus MFR_ID block read from 0x40 with PEC
+        let data = [0x03, b'A', b'B', b'C'];
+        let mut message = vec![0x80, 0x99, 0x81];
+        message.extend(data);
+        let mut read = data.to_vec();
+        read.push(smbus_pec(&message));
+        let mut write = vec![0x01, 0x80];
+        write.push(smbus_pec(&[0x80, 0x01, 0x80]));
+        let transfers = [
+            transfer(0x40, false, &[0x99], false),
+            transfer(0x40, true, &read, true),
+            transfer(0x40, false, &write, true),
+        ];
+        let transactions = SmbusDecoder::new().with_pec().decode(&transfers);
+        assert_eq!(transactions.len(), 2);
+        assert_eq!(transactions[0].pec, Some(true));
+        assert_eq!(transactions[0].block(), Some(&b"ABC"[..]));
+        let mut expect = SmbusExpect::new(&transactions);
+        expect.expect_block_read(0x40, 0x99, b"ABC").unwrap();
+        let mismatch = expect.expect_write(0x40, 0x01, &[0x00]).unwrap_err();
+        assert_eq!(mismatch.index, 1);
+        assert!(expect.is_done());
+
+        let mut corrupted = transfers.clone();
+        corrupted[1].data[2].0 ^= 0x01;
+        let transactions = SmbusDecoder::new().with_pec().decode(&corrupted);
+        assert_eq!(transactions[0].pec, Some(false));
+        assert!(SmbusExpect::new(&transactions)
+            .expect_block_read(0x40, 0x99, b"ABC")
+            .is_err());
+    }
+}
@@ -0,0 +1,483 @@
+//! SPI frames decoded from recorded signals and replayed to drivers.
+
+use super::{SerialConfig, Waveform};
+use crate::devices::Edge;
+use crate::pins::*;
+use embedded_hal::spi::{self, Mode};
+use embedded_time::duration::*;
+use std::fmt;
+
+/// The bytes exchanged while the chip select signal was asserted.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct SpiFrame {
+    /// The time the chip select signal was asserted.
+    pub start: Nanoseconds<u64>,
+    /// The time the chip select signal was released.
+    pub end: Nanoseconds<u64>,
+    /// The bytes sent by the controller.
+    pub mosi: Vec<u8>,
+    /// The bytes sent by the device.
+    pub miso: Vec<u8>,
+}
+
+/// The words exchanged while the chip select signal was asserted, for
+/// words that are not 8 bits wide.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct SpiWords {
+    /// The time the chip select signal was asserted.
+    pub start: Nanoseconds<u64>,
+    /// The time the chip select signal was released.
+    pub end: Nanoseconds<u64>,
+    /// The words sent by the controller.
+    pub mosi: Vec<u32>,
+    /// The words sent by the device.
+    pub miso: Vec<u32>,
+}
+
+/// A decoder for SPI frames.
+///
+/// By default bytes are shifted MSB first with an active low chip select,
+/// other framings are set with a [SerialConfig]. Incomplete words at the
+/// end of a frame are dropped. Data lines that float are read as `0`.
+#[derive(Clone, Debug)]
+pub struct SpiDecoder {
+    config: SerialConfig,
+}
+
+impl SpiDecoder {
+    /// Create a new decoder for the given SPI mode.
+    pub fn new(mode: Mode) -> Self {
+        Self::new_with_config(SerialConfig::new(mode))
+    }
+
+    /// Create a new decoder for the given framing.
+    pub fn new_with_config(config: SerialConfig) -> Self {
+        SpiDecoder { config }
+    }
+
+    /// Decode all complete frames.
+    ///
+    /// Words wider than 8 bits are split into bytes, the most significant
+    /// byte first.
+    pub fn decode(
+        &self,
+        sclk: &Waveform,
+        mosi: &Waveform,
+        miso: &Waveform,
+        cs: &Waveform,
+    ) -> Vec<SpiFrame> {
+        let bytes = (self.config.word_size as usize).div_ceil(8);
+        let split = |words: &[u32]| -> Vec<u8> {
+            words
+                .iter()
+                .flat_map(|word| word.to_be_bytes()[4 - bytes..].to_vec())
+                .collect()
+        };
+        self.decode_words(sclk, mosi, miso, cs)
+            .into_iter()
+            .map(|frame| SpiFrame {
+                start: frame.start,
+                end: frame.end,
+                mosi: split(&frame.mosi),
+                miso: split(&frame.miso),
+            })
+            .collect()
+    }
+
+    /// Decode all complete frames as words of the configured size.
+    pub fn decode_words(
+        &self,
+        sclk: &Waveform,
+        mosi: &Waveform,
+        miso: &Waveform,
+        cs: &Waveform,
+    ) -> Vec<SpiWords> {
+        let edge = self.config.sample_edge();
+        let bit = |waveform: &Waveform, t| waveform.state_at(t) == Some(PinState::High);
+        let frames = cs
+            .pulses(self.config.cs_active.clone())
+            .into_iter()
+            .map(|(start, width)| {
+                let end = Nanoseconds(start.0 + width.0);
+                let mut frame = SpiWords {
+                    start,
+                    end,
+                    ..SpiWords::default()
+                };
+                let samples: Vec<_> = sclk
+                    .changes()
+                    .iter()
+                    .filter(|(t, state)| *t > start && *t < end && Edge::to_state(state) == edge)
+                    .map(|(t, _)| *t)
+                    .collect();
+                for word in samples.chunks_exact(self.config.word_size as usize) {
+                    let shift = |waveform| {
+                        let bits: Vec<bool> = word.iter().map(|t| bit(waveform, *t)).collect();
+                        self.config.word(&bits)
+                    };
+                    frame.mosi.push(shift(mosi));
+                    frame.miso.push(shift(miso));
+                }
+                frame
+            })
+            .collect();
+        super::traced("spi", frames)
+    }
+}
+
+/// An error of a [SpiReplay].
+#[derive(Clone, Debug, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum SpiReplayError {
+    /// A transaction was started after the last recorded frame.
+    NoFrame,
+    /// The driver wrote a different byte than recorded.
+    Mismatch {
+        /// The index of the frame.
+        frame: usize,
+        /// The index of the byte in the frame.
+        index: usize,
+        /// The recorded byte.
+        expected: u8,
+        /// The byte written by the driver.
+        actual: u8,
+    },
+    /// The driver transferred more bytes than recorded in the frame.
+    Overrun {
+        /// The index of the frame.
+        frame: usize,
+    },
+    /// The transaction ended before all bytes of the frame were transferred,
+    /// so the chip select timing does not match the driver.
+    Incomplete {
+        /// The index of the frame.
+        frame: usize,
+        /// The number of transferred bytes.
+        transferred: usize,
+        /// The number of recorded bytes.
+        recorded: usize,
+    },
+}
+
+impl fmt::Display for SpiReplayError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            SpiReplayError::NoFrame => write!(f, "no recorded frame left"),
+            SpiReplayError::Mismatch {
+                frame,
+                index,
+                expected,
+                actual,
+            } => write!(
+                f,
+                "frame {} byte {} is {:#04x}, expected {:#04x}",
+                frame, index, actual, expected
+            ),
+            SpiReplayError::Overrun { frame } => {
+                write!(f, "frame {} has more bytes than recorded", frame)
+            }
+            SpiReplayError::Incomplete {
+                frame,
+                transferred,
+                recorded,
+            } => write!(
+                f,
+                "frame {} ended after {} of {} bytes",
+                frame, transferred, recorded
+            ),
+        }
+    }
+}
+
+impl std::error::Error for SpiReplayError {}
+
+impl spi::Error for SpiReplayError {
+    fn kind(&self) -> spi::ErrorKind {
+        match self {
+            SpiReplayError::Overrun { .. } => spi::ErrorKind::Overrun,
+            SpiReplayError::Incomplete { .. } => spi::ErrorKind::ChipSelectFault,
+            _ => spi::ErrorKind::Other,
+        }
+    }
+}
+
+/// A [SpiDevice](spi::SpiDevice) that replays recorded frames.
+///
+/// Each transaction of the driver has to match the next recorded frame:
+/// written bytes are compared with the recorded MOSI bytes, read bytes are
+/// taken from the recorded MISO bytes and the transaction has to transfer
+/// exactly the bytes of the frame.
+///
+/// ```
+/// use embedded_hal::spi::{SpiDevice, MODE_0};
+/// use embedded_hal_vcd::analysis::{SpiFrame, SpiReplay};
+/// use embedded_time::duration::*;
+///
+/// let mut device = SpiReplay::new(vec![SpiFrame {
+///     start: Nanoseconds(0),
+///     end: Nanoseconds(2000),
+///     mosi: vec![0x9f, 0x00],
+///     miso: vec![0x00, 0xef],
+/// }]);
+/// let mut id = [0x9f, 0x00];
+/// device.transfer_in_place(&mut id).unwrap();
+/// assert_eq!(id[1], 0xef);
+/// assert!(device.is_done());
+/// ```
+#[derive(Clone, Debug)]
+pub struct SpiReplay {
+    frames: Vec<SpiFrame>,
+    bus: SpiReplayBus,
+}
+
+impl SpiReplay {
+    /// Create a new replay of the given frames.
+    pub fn new(frames: Vec<SpiFrame>) -> Self {
+        SpiReplay {
+            frames,
+            bus: SpiReplayBus::default(),
+        }
+    }
+
+    /// Return `true` if all recorded frames were replayed.
+    pub fn is_done(&self) -> bool {
+        self.bus.frame >= self.frames.len()
+    }
+}
+
+impl spi::ErrorType for SpiReplay {
+    type Error = SpiReplayError;
+}
+
+impl spi::SpiDevice for SpiReplay {
+    type Bus = SpiReplayBus;
+
+    fn transaction<R>(
+        &mut self,
+        f: impl FnOnce(&mut Self::Bus) -> Result<R, SpiReplayError>,
+    ) -> Result<R, Self::Error> {
+        let frame = self
+            .frames
+            .get(self.bus.frame)
+            .ok_or(SpiReplayError::NoFrame)?;
+        self.bus.current = frame.clone();
+        self.bus.index = 0;
+        let result = f(&mut self.bus);
+        let bus = &mut self.bus;
+        let index = bus.frame;
+        bus.frame += 1;
+        let result = result?;
+        if bus.index != bus.current.mosi.len() {
+            return Err(SpiReplayError::Incomplete {
+                frame: index,
+                transferred: bus.index,
+                recorded: bus.current.mosi.len(),
+            });
+        }
+        Ok(result)
+    }
+}
+
+/// The bus of a [SpiReplay] during a transaction.
+#[derive(Clone, Debug, Default)]
+pub struct SpiReplayBus {
+    /// The index of the current frame.
+    frame: usize,
+    current: SpiFrame,
+    /// The index of the next byte in the current frame.
+    index: usize,
+}
+
+impl SpiReplayBus {
+    /// Transfer a single byte, writes are checked if `write` is set.
+    fn exchange(&mut self, write: Option<u8>) -> Result<u8, SpiReplayError> {
+        let index = self.index;
+        let expected = *self
+            .current
+            .mosi
+            .get(index)
+            .ok_or(SpiReplayError::Overrun { frame: self.frame })?;
+        match write {
+            Some(actual) if actual != expected => {
+                return Err(SpiReplayError::Mismatch {
+                    frame: self.frame,
+                    index,
+                    expected,
+                    actual,
+                })
+            }
+            _ => {}
+        }
+        self.index += 1;
+        Ok(self.current.miso.get(index).copied().unwrap_or(0))
+    }
+}
+
+impl spi::ErrorType for SpiReplayBus {
+    type Error = SpiReplayError;
+}
+
+impl spi::SpiBusFlush for SpiReplayBus {
+    fn flush(&mut self) -> Result<(), Self::Error> {
+        Ok(())
+    }
+}
+
+impl spi::SpiBusRead for SpiReplayBus {
+    fn read(&mut self, words: &mut [u8]) -> Result<(), Self::Error> {
+        for word in words.iter_mut() {
+            *word = self.exchange(None)?;
+        }
+        Ok(())
+    }
+}
+
+impl spi::SpiBusWrite for SpiReplayBus {
+    fn write(&mut self, words: &[u8]) -> Result<(), Self::Error> {
+        for word in words.iter() {
+            self.exchange(Some(*word))?;
+        }
+        Ok(())
+    }
+}
+
+impl spi::SpiBus for SpiReplayBus {
+    fn transfer(&mut self, read: &mut [u8], write: &[u8]) -> Result<(), Self::Error> {
+        for i in 0..read.len().max(write.len()) {
+            let word = self.exchange(write.get(i).copied())?;
+            if let Some(r) = read.get_mut(i) {
+                *r = word;
+            }
+        }
+        Ok(())
+    }
+
+    fn transfer_in_place(&mut self, words: &mut [u8]) -> Result<(), Self::Error> {
+        for word in words.iter_mut() {
+            *word = self.exchange(Some(*word))?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::analysis::BitOrder;
+    use embedded_hal::spi::{SpiBusRead, SpiBusWrite, SpiDevice, MODE_0, MODE_3};
+
+    /// Build the waveforms of a frame per chunk of words.
+    fn record_words(config: &SerialConfig, frames: &[(&[u32], &[u32])]) -> [Waveform; 4] {
+        let (mut sclk, mut mosi, mut miso, mut cs) = (vec![], vec![], vec![], vec![]);
+        let level = |high: bool| if high { PinState::High } else { PinState::Low };
+        let idle = config.clock_idle();
+        let active = if idle == PinState::Low {
+            PinState::High
+        } else {
+            PinState::Low
+        };
+        let inactive = level(config.cs_active == PinState::Low);
+        let mut t = 0;
+        for (out, inp) in frames {
+            cs.push((Nanoseconds(t), inactive.clone()));
+            sclk.push((Nanoseconds(t), idle.clone()));
+            t += 100;
+            cs.push((Nanoseconds(t), config.cs_active.clone()));
+            for (o, i) in out.iter().zip(inp.iter()) {
+                for (o, i) in config.bits(*o).into_iter().zip(config.bits(*i)) {
+                    // data changes before the leading edge for the first
+                    // phase, before the trailing edge for the second phase
+                    let change = if config.shift_edge() == Edge::to_state(&active) {
+                        t + 60
+                    } else {
+                        t + 10
+                    };
+                    mosi.push((Nanoseconds(change), level(o)));
+                    miso.push((Nanoseconds(change), level(i)));
+                    sclk.push((Nanoseconds(t + 50), active.clone()));
+                    sclk.push((Nanoseconds(t + 100), idle.clone()));
+                    t += 100;
+                }
+            }
+            t += 100;
+        }
+        cs.push((Nanoseconds(t), inactive));
+        [sclk, mosi, miso, cs].map(Waveform::new)
+    }
+
+    /// Build the waveforms of a mode 0 frame per chunk of bytes.
+    fn record(frames: &[(&[u8], &[u8])]) -> [Waveform; 4] {
+        let words: Vec<(Vec<u32>, Vec<u32>)> = frames
+            .iter()
+            .map(|(o, i)| {
+                let words = |bytes: &[u8]| bytes.iter().map(|b| *b as u32).collect();
+                (words(o), words(i))
+            })
+            .collect();
+        let frames: Vec<(&[u32], &[u32])> = words
+            .iter()
+            .map(|(o, i)| (o.as_slice(), i.as_slice()))
+            .collect();
+        record_words(&SerialConfig::default(), &frames)
+    }
+
+    #[test]
+    fn decode_configured() {
+        let config = SerialConfig::new(MODE_3)
+            .with_bit_order(BitOrder::LsbFirst)
+            .with_word_size(12)
+            .with_cs_active(PinState::High);
+        let [sclk, mosi, miso, cs] = record_words(&config, &[(&[0x123, 0xabc], &[0xfff, 0x001])]);
+        let decoder = SpiDecoder::new_with_config(config);
+        let words = decoder.decode_words(&sclk, &mosi, &miso, &cs);
+        assert_eq!(words.len(), 1);
+        assert_eq!(words[0].mosi, vec![0x123, 0xabc]);
+        assert_eq!(words[0].miso, vec![0xfff, 0x001]);
+        let frames = decoder.decode(&sclk, &mosi, &miso, &cs);
+        assert_eq!(frames[0].mosi, vec![0x01, 0x23, 0x0a, 0xbc]);
+        // the default configuration does not see the frame
+        let frames = SpiDecoder::new(MODE_3).decode(&sclk, &mosi, &miso, &cs);
+        assert!(frames.iter().all(|frame| frame.mosi != vec![0x01, 0x23]));
+    }
+
+    #[test]
+    fn replay_frames() {
+        let [sclk, mosi, miso, cs] = record(&[(&[0x03, 0x00], &[0xff, 0xa5]), (&[0x06], &[0xff])]);
+        let frames = SpiDecoder::new(MODE_0).decode(&sclk, &mosi, &miso, &cs);
+        assert_eq!(frames.len(), 2);
+        assert_eq!(frames[0].mosi, vec![0x03, 0x00]);
+        assert_eq!(frames[0].miso, vec![0xff, 0xa5]);
+
+        let mut device = SpiReplay::new(frames.clone());
+        let mut data = [0];
+        device
+            .transaction(|bus| {
+                bus.write(&[0x03])?;
+                bus.read(&mut data)
+            })
+            .unwrap();
+        assert_eq!(data, [0xa5]);
+        assert_eq!(
+            device.write(&[0x04]),
+            Err(SpiReplayError::Mismatch {
+                frame: 1,
+                index: 0,
+                expected: 0x06,
+                actual: 0x04
+            })
+        );
+        assert_eq!(device.write(&[0x06]), Err(SpiReplayError::NoFrame));
+
+        let mut device = SpiReplay::new(frames);
+        assert_eq!(
+            device.write(&[0x03]),
+            Err(SpiReplayError::Incomplete {
+                frame: 0,
+                transferred: 1,
+                recorded: 2
+            })
+        );
+    }
+}
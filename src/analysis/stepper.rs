@@ -0,0 +1,201 @@
+//! Stepper motor driver STEP/DIR interpretation.
+
+use super::Waveform;
+use crate::pins::*;
+use embedded_time::duration::*;
+use std::fmt;
+
+/// The position of a stepper motor after a step.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct StepperPosition {
+    /// The time of the step.
+    pub time: Nanoseconds<u64>,
+    /// The position in steps, positive is forward.
+    pub position: i64,
+}
+
+/// A violation of the stepper driver timing found by a [StepperAnalyzer].
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum StepperViolation {
+    /// The step at `time` followed the previous step too fast.
+    StepRate {
+        /// The time of the step.
+        time: Nanoseconds<u64>,
+        /// The time since the previous step.
+        interval: Nanoseconds<u64>,
+    },
+    /// The step at `time` followed a direction change too fast.
+    DirectionSetup {
+        /// The time of the step.
+        time: Nanoseconds<u64>,
+        /// The time since the direction change.
+        setup: Nanoseconds<u64>,
+    },
+}
+
+impl fmt::Display for StepperViolation {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            StepperViolation::StepRate { time, interval } => {
+                write!(
+                    f,
+                    "step at {} only {} after the previous step",
+                    time, interval
+                )
+            }
+            StepperViolation::DirectionSetup { time, setup } => {
+                write!(
+                    f,
+                    "step at {} only {} after a direction change",
+                    time, setup
+                )
+            }
+        }
+    }
+}
+
+impl std::error::Error for StepperViolation {}
+
+/// An analyzer for STEP/DIR stepper motor drivers.
+///
+/// A step is taken on each rising edge of STEP, forward if DIR is high and
+/// backward otherwise. If an enable waveform is given, steps are only
+/// counted while the active low EN signal is low.
+#[derive(Clone, Debug, Default)]
+pub struct StepperAnalyzer {
+    min_interval: Option<Nanoseconds<u64>>,
+    direction_setup: Nanoseconds<u64>,
+}
+
+impl StepperAnalyzer {
+    /// Create a new analyzer without timing checks.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Set the maximum step rate in steps per second.
+    pub fn with_max_step_rate(mut self, rate: u32) -> Self {
+        self.min_interval = Some(Nanoseconds(1_000_000_000 / rate as u64));
+        self
+    }
+
+    /// Set the minimum time between a direction change and the next step.
+    pub fn with_direction_setup(mut self, setup: Nanoseconds<u64>) -> Self {
+        self.direction_setup = setup;
+        self
+    }
+
+    /// Integrate the steps into the position after each step.
+    pub fn analyze(
+        &self,
+        step: &Waveform,
+        dir: &Waveform,
+        enable: Option<&Waveform>,
+    ) -> Result<Vec<StepperPosition>, StepperViolation> {
+        let mut positions = vec![];
+        let mut position = 0;
+        let mut last: Option<Nanoseconds<u64>> = None;
+        for (time, state) in step.changes() {
+            if *state != PinState::High {
+                continue;
+            }
+            if let Some(enable) = enable {
+                if enable.state_at(*time) != Some(PinState::Low) {
+                    continue;
+                }
+            }
+            if let (Some(last), Some(min)) = (last, self.min_interval) {
+                let interval = Nanoseconds(time.0 - last.0);
+                if interval < min {
+                    return Err(StepperViolation::StepRate {
+                        time: *time,
+                        interval,
+                    });
+                }
+            }
+            // the initial state of DIR is not a direction change
+            let index = dir.changes().partition_point(|(t, _)| t <= time);
+            if index > 1 {
+                let setup = Nanoseconds(time.0 - dir.changes()[index - 1].0 .0);
+                if setup < self.direction_setup {
+                    return Err(StepperViolation::DirectionSetup { time: *time, setup });
+                }
+            }
+            position += match dir.state_at(*time) {
+                Some(PinState::High) => 1,
+                _ => -1,
+            };
+            last = Some(*time);
+            positions.push(StepperPosition {
+                time: *time,
+                position,
+            });
+        }
+        Ok(positions)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn steps(count: u64, start: u64, interval: u64) -> Vec<(Nanoseconds<u64>, PinState)> {
+        (0..count)
+            .flat_map(|i| {
+                let t = start + i * interval;
+                [
+                    (Nanoseconds(t), PinState::High),
+                    (Nanoseconds(t + interval / 2), PinState::Low),
+                ]
+            })
+            .collect()
+    }
+
+    #[test]
+    fn forward_backward() {
+        let analyzer = StepperAnalyzer::new()
+            .with_max_step_rate(10_000)
+            .with_direction_setup(Nanoseconds(1_000));
+        let mut changes = vec![(Nanoseconds(0), PinState::Low)];
+        changes.extend(steps(1600, 10_000, 100_000));
+        let step = Waveform::new(changes);
+        let dir = Waveform::new(vec![(Nanoseconds(0), PinState::High)]);
+        let positions = analyzer.analyze(&step, &dir, None).unwrap();
+        assert_eq!(positions.len(), 1600);
+        assert_eq!(positions.last().unwrap().position, 1600);
+
+        // disabled steps are not counted, backward after direction change
+        let dir = Waveform::new(vec![
+            (Nanoseconds(0), PinState::High),
+            (Nanoseconds(50_005_000), PinState::Low),
+        ]);
+        let enable = Waveform::new(vec![
+            (Nanoseconds(0), PinState::High),
+            (Nanoseconds(5_000), PinState::Low),
+            (Nanoseconds(100_005_000), PinState::High),
+        ]);
+        let positions = analyzer.analyze(&step, &dir, Some(&enable)).unwrap();
+        assert_eq!(positions.len(), 1000);
+        assert_eq!(positions.last().unwrap().position, 0);
+
+        let dir = Waveform::new(vec![
+            (Nanoseconds(0), PinState::High),
+            (Nanoseconds(50_009_500), PinState::Low),
+        ]);
+        assert_eq!(
+            analyzer.analyze(&step, &dir, None),
+            Err(StepperViolation::DirectionSetup {
+                time: Nanoseconds(50_010_000),
+                setup: Nanoseconds(500),
+            })
+        );
+
+        let step = Waveform::new(steps(2, 0, 50_000));
+        assert!(matches!(
+            analyzer.analyze(&step, &dir, None),
+            Err(StepperViolation::StepRate { .. })
+        ));
+    }
+}
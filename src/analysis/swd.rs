@@ -0,0 +1,229 @@
+//! ARM Serial Wire Debug transactions decoded from recorded signals.
+
+use super::Waveform;
+use crate::devices::Edge;
+use crate::pins::*;
+use embedded_time::duration::*;
+
+/// The acknowledge of a SWD transaction.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum SwdAck {
+    /// The transaction was accepted.
+    Ok,
+    /// The target is busy, the transaction has to be repeated.
+    Wait,
+    /// The target reported an error.
+    Fault,
+    /// An invalid acknowledge, e.g. no target driving the line.
+    Invalid(u8),
+}
+
+/// A SWD transaction, a request of the host with the acknowledge of the
+/// target and the transferred data.
+#[derive(Clone, Debug, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct SwdTransaction {
+    /// The time of the start bit.
+    pub time: Nanoseconds<u64>,
+    /// Whether the access port (`true`) or the debug port is accessed.
+    pub ap: bool,
+    /// Whether the register is read.
+    pub read: bool,
+    /// The register address, `A[3:2]` of the request as byte address.
+    pub address: u8,
+    /// The acknowledge of the target.
+    pub ack: SwdAck,
+    /// The transferred data, `None` if the transaction was not accepted or
+    /// the recording ended early.
+    pub data: Option<u32>,
+    /// Whether the parity of the data was correct.
+    pub parity: bool,
+}
+
+/// An event on the SWD bus.
+#[derive(Clone, Debug, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum SwdEvent {
+    /// At least 50 clocks with SWDIO high, ending at `time`.
+    LineReset(Nanoseconds<u64>),
+    /// A transaction.
+    Transaction(SwdTransaction),
+}
+
+/// A decoder for SWD captures of SWCLK and SWDIO.
+///
+/// SWDIO is sampled on the rising edges of SWCLK, a turnaround takes a
+/// single clock. A request starts right after a transaction or after an
+/// idle (low) bit, bits that do not form a valid request (start, stop and
+/// park bit and parity) are skipped, e.g. line resets and the JTAG to SWD
+/// switching sequence.
+#[derive(Clone, Debug, Default)]
+pub struct SwdDecoder {}
+
+impl SwdDecoder {
+    /// Create a new decoder.
+    pub fn new() -> Self {
+        SwdDecoder {}
+    }
+
+    /// Decode all events.
+    pub fn decode(&self, swclk: &Waveform, swdio: &Waveform) -> Vec<SwdEvent> {
+        let bits: Vec<(Nanoseconds<u64>, bool)> = swclk
+            .changes()
+            .iter()
+            .skip(1)
+            .filter(|(_, state)| Edge::to_state(state) == Edge::Rising)
+            .map(|(t, _)| (*t, swdio.state_at(*t) == Some(PinState::High)))
+            .collect();
+        let value = |range: std::ops::Range<usize>| {
+            bits[range]
+                .iter()
+                .rev()
+                .fold(0u64, |v, (_, bit)| v << 1 | *bit as u64)
+        };
+        let mut events = vec![];
+        let mut ones = 0;
+        let mut i = 0;
+        let mut idle = true;
+        while i < bits.len() {
+            let (time, bit) = bits[i];
+            if bit {
+                ones += 1;
+            } else {
+                if ones >= 50 {
+                    events.push(SwdEvent::LineReset(bits[i - 1].0));
+                }
+                ones = 0;
+            }
+            let request = match bits.get(i..i + 8) {
+                Some(request) => request.iter().map(|(_, b)| *b).collect::<Vec<_>>(),
+                None => break,
+            };
+            let parity = request[1..5].iter().filter(|b| **b).count() % 2 == 1;
+            if !(idle && request[0] && !request[6] && request[7] && request[5] == parity) {
+                idle = !bit;
+                i += 1;
+                continue;
+            }
+            ones = 0;
+            let read = request[2];
+            let ack = match bits.get(i + 9..i + 12) {
+                Some(_) => match value(i + 9..i + 12) {
+                    0b001 => SwdAck::Ok,
+                    0b010 => SwdAck::Wait,
+                    0b100 => SwdAck::Fault,
+                    ack => SwdAck::Invalid(ack as u8),
+                },
+                None => break,
+            };
+            let mut transaction = SwdTransaction {
+                time,
+                ap: request[1],
+                read,
+                address: (value(i + 3..i + 5) as u8) << 2,
+                ack,
+                data: None,
+                parity: true,
+            };
+            // request, turnaround and acknowledge
+            i += 12;
+            if ack == SwdAck::Ok {
+                // a write has a turnaround before the data, a read after it
+                let start = if read { i } else { i + 1 };
+                if bits.len() >= start + 33 {
+                    let data = value(start..start + 32) as u32;
+                    transaction.data = Some(data);
+                    transaction.parity = (data.count_ones() % 2 == 1) == bits[start + 32].1;
+                }
+                i = start + 33 + read as usize;
+            } else {
+                i += 1;
+            }
+            events.push(SwdEvent::Transaction(transaction));
+            idle = true;
+        }
+        if ones >= 50 {
+            events.push(SwdEvent::LineReset(bits[bits.len() - 1].0));
+        }
+        super::traced("swd", events)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[derive(Default)]
+    struct Host {
+        bits: Vec<bool>,
+    }
+
+    impl Host {
+        fn value(&mut self, value: u64, bits: usize) {
+            self.bits.extend((0..bits).map(|i| value >> i & 1 == 1));
+        }
+
+        fn request(&mut self, ap: bool, read: bool, a: u8) {
+            let req = [ap, read, a & 1 == 1, a & 2 == 2];
+            let parity = req.iter().filter(|b| **b).count() % 2 == 1;
+            self.bits.push(true);
+            self.bits.extend(req);
+            self.bits.extend([parity, false, true]);
+        }
+
+        fn waveforms(&self) -> (Waveform, Waveform) {
+            let level = |high: bool| if high { PinState::High } else { PinState::Low };
+            let mut clk = vec![];
+            let mut dio = vec![];
+            for (i, bit) in self.bits.iter().enumerate() {
+                let t = i as u64 * 100;
+                clk.push((Nanoseconds(t), PinState::Low));
+                dio.push((Nanoseconds(t), level(*bit)));
+                clk.push((Nanoseconds(t + 50), PinState::High));
+            }
+            (Waveform::new(clk), Waveform::new(dio))
+        }
+    }
+
+    #[test]
+    fn decode_swd() {
+        let mut host = Host::default();
+        host.value(u64::MAX, 52);
+        host.value(0, 2);
+        // read DPIDR
+        host.request(false, true, 0);
+        host.value(0b0010, 4);
+        host.value(0x2ba0_1477, 32);
+        host.value(0, 1);
+        host.value(0, 1);
+        // write to AP register 0x4, answered with WAIT
+        host.request(true, false, 1);
+        host.value(0b0100, 4);
+        host.value(0, 1);
+        // write with wrong parity
+        host.request(true, false, 1);
+        host.value(0b0010, 5);
+        host.value(0x1234, 32);
+        host.value(0, 1);
+        host.value(0, 8);
+        let (clk, dio) = host.waveforms();
+        let events = SwdDecoder::new().decode(&clk, &dio);
+        assert!(matches!(events[0], SwdEvent::LineReset(_)));
+        let transactions: Vec<_> = events[1..]
+            .iter()
+            .map(|e| match e {
+                SwdEvent::Transaction(t) => (t.ap, t.read, t.address, t.ack, t.data, t.parity),
+                e => panic!("unexpected {:?}", e),
+            })
+            .collect();
+        assert_eq!(
+            transactions,
+            vec![
+                (false, true, 0x0, SwdAck::Ok, Some(0x2ba0_1477), true),
+                (true, false, 0x4, SwdAck::Wait, None, true),
+                (true, false, 0x4, SwdAck::Ok, Some(0x1234), false),
+            ]
+        );
+    }
+}
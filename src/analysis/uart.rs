@@ -0,0 +1,210 @@
+//! UART bytes and breaks decoded from and encoded to a single signal.
+
+use super::Waveform;
+use crate::pins::*;
+use embedded_time::duration::*;
+
+/// A byte received by a UART.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct UartByte {
+    /// The start of the start bit.
+    pub time: Nanoseconds<u64>,
+    /// The data bits, LSB first on the line.
+    pub value: u8,
+    /// Whether all stop bits were high, `false` for a framing error.
+    pub stop: bool,
+}
+
+/// An event on a UART line.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum UartEvent {
+    /// A byte.
+    Byte(UartByte),
+    /// The line was low for longer than a byte.
+    Break {
+        /// The start of the break.
+        time: Nanoseconds<u64>,
+        /// The width of the break.
+        width: Nanoseconds<u64>,
+    },
+}
+
+/// An encoder and decoder for UART lines with 8 data bits and no parity.
+///
+/// The line idles high. Bits are sampled in their middle, timed from the
+/// falling edge of the start bit.
+#[derive(Clone, Debug)]
+pub struct Uart {
+    baud: u32,
+    stop_bits: u8,
+}
+
+impl Uart {
+    /// Create a new UART with the given baud rate and one stop bit.
+    ///
+    /// # Panics
+    ///
+    /// Panics if the baud rate is 0.
+    pub fn new(baud: u32) -> Self {
+        assert!(baud > 0, "baud rate must not be 0");
+        Uart { baud, stop_bits: 1 }
+    }
+
+    /// Set the number of stop bits.
+    pub fn with_stop_bits(mut self, stop_bits: u8) -> Self {
+        self.stop_bits = stop_bits;
+        self
+    }
+
+    /// Return the time `halves` half bits after `t`.
+    fn at(&self, t: u64, halves: u64) -> u64 {
+        t + (halves as u128 * 500_000_000 / self.baud as u128) as u64
+    }
+
+    /// Return the number of bits of a byte including start and stop bits.
+    fn frame_bits(&self) -> u64 {
+        9 + self.stop_bits as u64
+    }
+
+    /// Encode events, which have to be in chronological order and must not
+    /// overlap.
+    pub fn encode(&self, events: &[UartEvent]) -> Waveform {
+        let level = |high: bool| if high { PinState::High } else { PinState::Low };
+        let mut changes = vec![(Nanoseconds(0), PinState::High)];
+        for event in events {
+            match event {
+                UartEvent::Byte(byte) => {
+                    let t = byte.time.0;
+                    let bits = [false]
+                        .into_iter()
+                        .chain((0..8).map(|i| byte.value >> i & 1 == 1))
+                        .chain(std::iter::repeat_n(byte.stop, self.stop_bits as usize));
+                    for (k, bit) in bits.enumerate() {
+                        changes.push((Nanoseconds(self.at(t, 2 * k as u64)), level(bit)));
+                    }
+                    let end = self.at(t, 2 * self.frame_bits());
+                    changes.push((Nanoseconds(end), PinState::High));
+                }
+                UartEvent::Break { time, width } => {
+                    changes.push((*time, PinState::Low));
+                    changes.push((Nanoseconds(time.0 + width.0), PinState::High));
+                }
+            }
+        }
+        Waveform::new(changes)
+    }
+
+    /// Return the time after the last stop bit of a byte starting at `time`.
+    pub fn byte_end(&self, time: Nanoseconds<u64>) -> Nanoseconds<u64> {
+        Nanoseconds(self.at(time.0, 2 * self.frame_bits()))
+    }
+
+    /// Decode all bytes and breaks.
+    ///
+    /// A start bit that is high in its middle is a glitch and skipped, a
+    /// break at the end of the recording is dropped.
+    pub fn decode(&self, waveform: &Waveform) -> Vec<UartEvent> {
+        let changes = waveform.changes();
+        let low = |t| waveform.state_at(Nanoseconds(t)) == Some(PinState::Low);
+        let mut events = vec![];
+        let mut busy = 0;
+        for (i, (t, state)) in changes.iter().enumerate() {
+            let last = i.checked_sub(1).map(|i| &changes[i].1);
+            if *state != PinState::Low || last == Some(&PinState::Low) || t.0 < busy {
+                continue;
+            }
+            let t = t.0;
+            let width = match changes.get(i + 1) {
+                Some((end, _)) => end.0 - t,
+                None => u64::MAX,
+            };
+            if width >= self.at(0, 2 * self.frame_bits()) {
+                if width != u64::MAX {
+                    events.push(UartEvent::Break {
+                        time: Nanoseconds(t),
+                        width: Nanoseconds(width),
+                    });
+                }
+                busy = t.saturating_add(width);
+                continue;
+            }
+            if !low(self.at(t, 1)) {
+                continue;
+            }
+            let value = (0..8).fold(0u8, |v, k| v | (!low(self.at(t, 2 * k + 3)) as u8) << k);
+            let stop = (9..self.frame_bits()).all(|k| !low(self.at(t, 2 * k + 1)));
+            events.push(UartEvent::Byte(UartByte {
+                time: Nanoseconds(t),
+                value,
+                stop,
+            }));
+            // the middle of the last stop bit
+            busy = self.at(t, 2 * self.frame_bits() - 1);
+        }
+        super::traced("uart", events)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn bytes_and_break() {
+        let uart = Uart::new(19_200);
+        let byte = |time: u64, value, stop| {
+            UartEvent::Byte(UartByte {
+                time: Nanoseconds(time),
+                value,
+                stop,
+            })
+        };
+        let events = vec![
+            UartEvent::Break {
+                time: Nanoseconds(100_000),
+                width: Nanoseconds(700_000),
+            },
+            byte(850_000, 0x55, true),
+            // back to back
+            byte(uart.byte_end(Nanoseconds(850_000)).0, 0x00, true),
+            byte(2_000_000, 0xa5, false),
+            byte(3_000_000, 0xff, true),
+        ];
+        let waveform = uart.encode(&events);
+        assert_eq!(uart.decode(&waveform), events);
+        // the same line decoded at a wrong baud rate
+        let decoded = Uart::new(9600).decode(&waveform);
+        assert_ne!(decoded, events);
+    }
+
+    #[test]
+    fn two_stop_bits() {
+        let uart = Uart::new(1_000_000).with_stop_bits(2);
+        let byte = |time: u64, value, stop| {
+            UartEvent::Byte(UartByte {
+                time: Nanoseconds(time),
+                value,
+                stop,
+            })
+        };
+        let events = vec![
+            byte(1_000, 0x3c, true),
+            byte(uart.byte_end(Nanoseconds(1_000)).0, 0x81, false),
+            byte(30_000, 0x7e, true),
+        ];
+        let waveform = uart.encode(&events);
+        assert_eq!(uart.decode(&waveform), events);
+
+        // only the second stop bit is low, which does not start a new byte
+        let waveform = Waveform::new(vec![
+            (Nanoseconds(0), PinState::High),
+            (Nanoseconds(1_000), PinState::Low),
+            (Nanoseconds(10_000), PinState::High),
+            (Nanoseconds(11_000), PinState::Low),
+            (Nanoseconds(12_000), PinState::High),
+        ]);
+        assert_eq!(uart.decode(&waveform), vec![byte(1_000, 0x00, false)]);
+    }
+}
@@ -0,0 +1,157 @@
+//! Watchdog kick intervals.
+
+use super::Waveform;
+use crate::devices::Edge;
+use embedded_time::duration::*;
+use std::fmt;
+
+/// A kick interval longer than the timeout of a watchdog.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct WatchdogViolation {
+    /// The last kick before the watchdog expired, or the start of the
+    /// capture.
+    pub last_kick: Nanoseconds<u64>,
+    /// The time the watchdog expired, the last kick plus the timeout.
+    pub time: Nanoseconds<u64>,
+    /// The next kick, `None` if the watchdog was not kicked anymore.
+    pub next_kick: Option<Nanoseconds<u64>>,
+}
+
+impl fmt::Display for WatchdogViolation {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "watchdog expired at {} after the kick at {}",
+            self.time, self.last_kick
+        )?;
+        match self.next_kick {
+            Some(next) => write!(f, ", next kick at {}", next),
+            None => write!(f, ", not kicked anymore"),
+        }
+    }
+}
+
+impl std::error::Error for WatchdogViolation {}
+
+/// A monitor for the kicks of a watchdog, e.g. the toggling pin of an
+/// external supervisor IC.
+///
+/// Every edge of the kick signal restarts the watchdog, the interval between
+/// two kicks must not exceed the timeout. The watchdog starts with the first
+/// change of the waveform, which usually is the start of the capture.
+///
+/// ```
+/// use embedded_hal_vcd::analysis::{WatchdogMonitor, Waveform};
+/// use embedded_hal_vcd::pins::PinState;
+/// use embedded_time::duration::*;
+///
+/// let kick = Waveform::new(vec![
+///     (Nanoseconds(0), PinState::Low),
+///     (Nanoseconds(800_000), PinState::High),
+///     (Nanoseconds(2_000_000), PinState::Low),
+/// ]);
+/// let monitor = WatchdogMonitor::new(Nanoseconds(1_000_000));
+/// let violations = monitor.check(&kick, Nanoseconds(2_500_000));
+/// assert_eq!(violations.len(), 1);
+/// assert_eq!(violations[0].time, Nanoseconds(1_800_000u64));
+/// ```
+#[derive(Clone, Debug)]
+pub struct WatchdogMonitor {
+    timeout: Nanoseconds<u64>,
+    kick: Option<Edge>,
+}
+
+impl WatchdogMonitor {
+    /// Create a new monitor with a timeout, kicked by any edge.
+    pub fn new(timeout: Nanoseconds<u64>) -> Self {
+        WatchdogMonitor {
+            timeout,
+            kick: None,
+        }
+    }
+
+    /// Only count edges of the given kind as kicks, e.g. the rising edges of
+    /// a pulsed kick pin.
+    pub fn with_kick_edge(mut self, edge: Edge) -> Self {
+        self.kick = Some(edge);
+        self
+    }
+
+    /// Return the timeout.
+    pub fn timeout(&self) -> Nanoseconds<u64> {
+        self.timeout
+    }
+
+    /// Return whether an edge kicks the watchdog.
+    pub(crate) fn is_kick(&self, edge: Edge) -> bool {
+        self.kick.is_none_or(|kick| kick == edge)
+    }
+
+    /// Check all kick intervals until the end of the capture.
+    ///
+    /// A kick exactly at the timeout is still in time. Returns one
+    /// violation per interval that is too long.
+    pub fn check(&self, kick: &Waveform, end: Nanoseconds<u64>) -> Vec<WatchdogViolation> {
+        let start = match kick.changes().first() {
+            Some((t, _)) => *t,
+            None => return vec![],
+        };
+        let kicks = kick
+            .changes()
+            .iter()
+            .skip(1)
+            .filter(|(_, state)| self.is_kick(Edge::to_state(state)))
+            .map(|(t, _)| Some(*t))
+            .chain([None]);
+        let mut violations = vec![];
+        let mut last = start;
+        for next in kicks {
+            let time = Nanoseconds(last.0 + self.timeout.0);
+            if next.unwrap_or(end) > time {
+                violations.push(WatchdogViolation {
+                    last_kick: last,
+                    time,
+                    next_kick: next,
+                });
+            }
+            last = next.unwrap_or(end);
+        }
+        violations
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::pins::PinState;
+
+    #[test]
+    fn kick_intervals() {
+        // rising kicks every 100 µs, one missed at 500 µs, low pulses of 5 µs
+        let mut changes = vec![(Nanoseconds(0), PinState::Low)];
+        for t in (100_000..1_000_000u64).step_by(100_000) {
+            if t != 500_000 {
+                changes.push((Nanoseconds(t), PinState::High));
+                changes.push((Nanoseconds(t + 5_000), PinState::Low));
+            }
+        }
+        let kick = Waveform::new(changes);
+        let monitor = WatchdogMonitor::new(Nanoseconds(150_000)).with_kick_edge(Edge::Rising);
+        assert_eq!(
+            monitor.check(&kick, Nanoseconds(1_000_000)),
+            vec![WatchdogViolation {
+                last_kick: Nanoseconds(400_000),
+                time: Nanoseconds(550_000),
+                next_kick: Some(Nanoseconds(600_000)),
+            }]
+        );
+        let violations = monitor.check(&kick, Nanoseconds(2_000_000));
+        assert_eq!(violations.len(), 2);
+        assert_eq!(violations[1].time, Nanoseconds(1_050_000u64));
+        assert_eq!(violations[1].next_kick, None);
+        // every edge kicks
+        let monitor = WatchdogMonitor::new(Nanoseconds(200_000));
+        assert!(monitor.check(&kick, Nanoseconds(1_000_000)).is_empty());
+    }
+}
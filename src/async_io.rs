@@ -0,0 +1,419 @@
+//! Read and write VCD data over the asynchronous I/O of [tokio].
+//!
+//! An [AsyncVcdReader] replays a VCD stream as a [Stream] of timestamps. It
+//! reads from its input only while the next timestamp is polled, so a slow
+//! consumer applies backpressure to the sender of a capture, e.g. over a
+//! network connection. An [AsyncVcdWriter] records to an [AsyncWrite] and
+//! waits until the output accepted the data of each sample.
+//!
+//! ```
+//! use embedded_hal::digital::{InputPin, OutputPin};
+//! use embedded_hal_vcd::async_io::{AsyncVcdReader, AsyncVcdWriter};
+//! use embedded_time::duration::*;
+//!
+//! # tokio::runtime::Builder::new_current_thread().build().unwrap().block_on(async {
+//! let (output, input) = tokio::io::duplex(64);
+//! let record = async {
+//!     let (mut writer, mut led) =
+//!         AsyncVcdWriter::new(output, |builder| builder.add_push_pull_pin("led")).unwrap();
+//!     for i in 0..100u64 {
+//!         led.set_state((i % 2 == 0).into()).unwrap();
+//!         writer.timestamp(Nanoseconds(i * 1_000)).await.unwrap();
+//!         writer.sample().await.unwrap();
+//!     }
+//!     writer.shutdown().await.unwrap();
+//! };
+//! let replay = async {
+//!     let mut reader = AsyncVcdReader::new(input).await.unwrap();
+//!     let mut led = reader.reader().get_pin(&["top", "led"]).unwrap();
+//!     let mut highs = 0;
+//!     while let Some(time) = reader.next_timestamp().await {
+//!         time.unwrap();
+//!         highs += led.is_high().unwrap() as usize;
+//!     }
+//!     highs
+//! };
+//! let ((), highs) = tokio::join!(record, replay);
+//! assert_eq!(highs, 50);
+//! # });
+//! ```
+
+use crate::reader::VcdReader;
+use crate::writer::{VcdWriter, VcdWriterBuilder};
+use embedded_time::duration::*;
+use futures_core::Stream;
+use std::collections::VecDeque;
+use std::future::poll_fn;
+use std::io::Result as IOResult;
+use std::io::{Error, ErrorKind, Read, Write};
+use std::pin::Pin;
+use std::sync::{Arc, Mutex};
+use std::task::{ready, Context, Poll};
+use tokio::io::{AsyncRead, AsyncWrite, ReadBuf};
+
+/// The size of the chunks read from and written to the asynchronous I/O.
+const CHUNK_SIZE: usize = 8 * 1024;
+
+#[derive(Default)]
+struct Received {
+    data: VecDeque<u8>,
+    closed: bool,
+}
+
+/// The input of the [VcdReader] of an [AsyncVcdReader], the data received
+/// but not parsed yet.
+///
+/// Reading beyond the received data fails with [ErrorKind::WouldBlock]
+/// until the input is closed.
+#[derive(Clone, Default)]
+pub struct Feed {
+    received: Arc<Mutex<Received>>,
+}
+
+impl Feed {
+    fn push(&self, data: &[u8]) {
+        self.received.lock().unwrap().data.extend(data);
+    }
+
+    fn close(&self) {
+        self.received.lock().unwrap().closed = true;
+    }
+
+    fn is_closed(&self) -> bool {
+        self.received.lock().unwrap().closed
+    }
+}
+
+impl Read for Feed {
+    fn read(&mut self, buf: &mut [u8]) -> IOResult<usize> {
+        let mut received = self.received.lock().unwrap();
+        if received.data.is_empty() && !received.closed {
+            return Err(Error::new(
+                ErrorKind::WouldBlock,
+                "the data was not received yet",
+            ));
+        }
+        received.data.read(buf)
+    }
+}
+
+/// Finds the end of the header and the timestamps in the received data.
+///
+/// The parser only reads a token once it is followed by whitespace, so the
+/// data up to the next complete timestamp can be parsed without waiting.
+#[derive(Default)]
+struct Scanner {
+    token: Vec<u8>,
+    enddefinitions: bool,
+    header: bool,
+    comment: bool,
+    /// Whether the next token is the identifier of a vector, real or string
+    /// value, which may start with `#`.
+    identifier: bool,
+    timestamps: usize,
+}
+
+impl Scanner {
+    fn scan(&mut self, data: &[u8]) {
+        for byte in data {
+            if !byte.is_ascii_whitespace() {
+                self.token.push(*byte);
+            } else if !self.token.is_empty() {
+                self.complete();
+                self.token.clear();
+            }
+        }
+    }
+
+    fn complete(&mut self) {
+        let token = self.token.as_slice();
+        if self.comment {
+            self.comment = token != b"$end";
+        } else if token == b"$comment" {
+            self.comment = true;
+        } else if !self.header {
+            match token {
+                b"$enddefinitions" => self.enddefinitions = true,
+                b"$end" => self.header = self.enddefinitions,
+                _ => {}
+            }
+        } else if self.identifier {
+            self.identifier = false;
+        } else {
+            match token {
+                [b'b' | b'B' | b'r' | b'R' | b's' | b'S', ..] => self.identifier = true,
+                [b'#', time @ ..] if !time.is_empty() && time.iter().all(u8::is_ascii_digit) => {
+                    self.timestamps += 1
+                }
+                _ => {}
+            }
+        }
+    }
+}
+
+/// Read the next chunk of the input into the feed, `0` at the end of the
+/// input.
+fn poll_fill<R: AsyncRead + Unpin>(
+    input: &mut R,
+    cx: &mut Context<'_>,
+    feed: &Feed,
+    scanner: &mut Scanner,
+) -> Poll<IOResult<usize>> {
+    let mut chunk = [0; CHUNK_SIZE];
+    let mut buf = ReadBuf::new(&mut chunk);
+    ready!(Pin::new(input).poll_read(cx, &mut buf))?;
+    let data = buf.filled();
+    if data.is_empty() {
+        feed.close();
+    } else {
+        feed.push(data);
+        scanner.scan(data);
+    }
+    Poll::Ready(Ok(data.len()))
+}
+
+/// A reader for VCD data from an [AsyncRead], a [Stream] of the timestamps.
+///
+/// The pins and values are read from the synchronous [VcdReader] returned
+/// by [reader](Self::reader) and change like with its iterator: the changes
+/// of a timestamp are applied when the next timestamp is read. The replay
+/// must only be advanced by the stream.
+pub struct AsyncVcdReader<R> {
+    input: R,
+    feed: Feed,
+    scanner: Scanner,
+    reader: VcdReader<Feed>,
+    /// The number of timestamps read by the reader.
+    steps: usize,
+    done: bool,
+}
+
+impl<R: AsyncRead + Unpin> AsyncVcdReader<R> {
+    /// Create a new reader and read the header of the VCD data.
+    pub async fn new(mut input: R) -> IOResult<Self> {
+        let feed = Feed::default();
+        let mut scanner = Scanner::default();
+        while !scanner.header {
+            let read = poll_fn(|cx| poll_fill(&mut input, cx, &feed, &mut scanner)).await?;
+            if read == 0 {
+                break;
+            }
+        }
+        let reader = VcdReader::new(feed.clone())?;
+        Ok(AsyncVcdReader {
+            input,
+            feed,
+            scanner,
+            reader,
+            steps: 0,
+            done: false,
+        })
+    }
+
+    /// Return the synchronous reader, e.g. to get pins and state labels.
+    pub fn reader(&mut self) -> &mut VcdReader<Feed> {
+        &mut self.reader
+    }
+
+    /// Read the next timestamp, `None` at the end of the input.
+    pub async fn next_timestamp(&mut self) -> Option<IOResult<Generic<u64>>> {
+        poll_fn(|cx| Pin::new(&mut *self).poll_next(cx)).await
+    }
+}
+
+impl<R: AsyncRead + Unpin> Stream for AsyncVcdReader<R> {
+    type Item = IOResult<Generic<u64>>;
+
+    fn poll_next(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Self::Item>> {
+        let this = self.get_mut();
+        if this.done {
+            return Poll::Ready(None);
+        }
+        while this.scanner.timestamps <= this.steps && !this.feed.is_closed() {
+            let read = poll_fill(&mut this.input, cx, &this.feed, &mut this.scanner);
+            if let Err(error) = ready!(read) {
+                this.done = true;
+                return Poll::Ready(Some(Err(error)));
+            }
+        }
+        this.steps += 1;
+        if let Some(time) = this.reader.next() {
+            return Poll::Ready(Some(Ok(time)));
+        }
+        this.done = true;
+        let error = this.reader.parse_error();
+        Poll::Ready(error.map(|error| Err(Error::new(ErrorKind::InvalidData, error.to_string()))))
+    }
+}
+
+/// The output of the [VcdWriter] of an [AsyncVcdWriter], the data that was
+/// not sent yet.
+#[derive(Clone, Default)]
+pub struct Pending {
+    data: Arc<Mutex<Vec<u8>>>,
+}
+
+impl Write for Pending {
+    fn write(&mut self, buf: &[u8]) -> IOResult<usize> {
+        self.data.lock().unwrap().extend_from_slice(buf);
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> IOResult<()> {
+        Ok(())
+    }
+}
+
+/// A writer for VCD data to an [AsyncWrite].
+///
+/// The values are recorded by a synchronous [VcdWriter], the asynchronous
+/// methods wait until the output accepted the written data. They are
+/// cancel safe, data that was not sent is sent by the next call.
+pub struct AsyncVcdWriter<W> {
+    writer: VcdWriter<Pending>,
+    pending: Pending,
+    output: W,
+}
+
+impl<W: AsyncWrite + Unpin> AsyncVcdWriter<W> {
+    /// Create a new writer, the variables are added to the builder by
+    /// `build`, which returns their pins.
+    pub fn new<F, T>(output: W, build: F) -> IOResult<(Self, T)>
+    where
+        F: FnOnce(&mut VcdWriterBuilder<Pending>) -> IOResult<T>,
+    {
+        let pending = Pending::default();
+        let mut builder = VcdWriterBuilder::new(pending.clone())?;
+        let pins = build(&mut builder)?;
+        let writer = AsyncVcdWriter {
+            writer: builder.build()?,
+            pending,
+            output,
+        };
+        Ok((writer, pins))
+    }
+
+    /// Return the synchronous writer, e.g. to add annotations. Its data is
+    /// sent with the next asynchronous call.
+    pub fn writer(&mut self) -> &mut VcdWriter<Pending> {
+        &mut self.writer
+    }
+
+    /// Write a timestamp, see [VcdWriter::timestamp].
+    pub async fn timestamp<D: TryInto<Nanoseconds<u64>>>(&mut self, timestamp: D) -> IOResult<()> {
+        self.writer.timestamp(timestamp)?;
+        self.send().await
+    }
+
+    /// Sample all pins, see [VcdWriter::sample].
+    pub async fn sample(&mut self) -> IOResult<()> {
+        self.writer.sample()?;
+        self.send().await
+    }
+
+    /// Send the pending data and flush the output.
+    pub async fn flush(&mut self) -> IOResult<()> {
+        self.send().await?;
+        poll_fn(|cx| Pin::new(&mut self.output).poll_flush(cx)).await
+    }
+
+    /// Send the pending data, shut the output down and return it.
+    pub async fn shutdown(mut self) -> IOResult<W> {
+        self.send().await?;
+        poll_fn(|cx| Pin::new(&mut self.output).poll_shutdown(cx)).await?;
+        Ok(self.output)
+    }
+
+    async fn send(&mut self) -> IOResult<()> {
+        loop {
+            let chunk = {
+                let data = self.pending.data.lock().unwrap();
+                if data.is_empty() {
+                    return Ok(());
+                }
+                data[..data.len().min(CHUNK_SIZE)].to_vec()
+            };
+            let written = poll_fn(|cx| Pin::new(&mut self.output).poll_write(cx, &chunk)).await?;
+            if written == 0 {
+                return Err(ErrorKind::WriteZero.into());
+            }
+            self.pending.data.lock().unwrap().drain(..written);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use embedded_hal::digital::{InputPin as _, OutputPin as _};
+
+    #[tokio::test]
+    async fn replay_with_backpressure() {
+        // a small buffer, so the writer waits for the reader
+        let (output, input) = tokio::io::duplex(16);
+        let record = async {
+            let (mut writer, (mut a, b, c)) = AsyncVcdWriter::new(output, |builder| {
+                let a = builder.add_push_pull_pin("a")?;
+                let b = builder.add_pin_group("b", 4)?;
+                // the identifier of the third variable is `#`
+                let c = builder.add_pin_group("c", 4)?;
+                Ok((a, b, c))
+            })
+            .unwrap();
+            for i in 0..200u64 {
+                writer.timestamp(Nanoseconds(i * 10)).await.unwrap();
+                a.set_state((i % 3 == 0).into()).unwrap();
+                b.write(i % 16);
+                c.write(15 - i % 16);
+                if i == 100 {
+                    writer.writer().annotate("#1 b0 # $dumpvars").unwrap();
+                }
+                writer.sample().await.unwrap();
+            }
+            writer.shutdown().await.unwrap();
+        };
+        let replay = async {
+            let mut reader = AsyncVcdReader::new(input).await.unwrap();
+            let a = reader.reader().get_pin(&["top", "a"]).unwrap();
+            let b = reader.reader().get_bus(&["top", "b"]).unwrap();
+            let c = reader.reader().get_bus(&["top", "c"]).unwrap();
+            let mut times = vec![];
+            while let Some(time) = reader.next_timestamp().await {
+                let time = Nanoseconds::<u64>::try_from(time.unwrap()).unwrap();
+                // the values of the previous timestamp
+                if let Some(i) = (time.0 / 10).checked_sub(1) {
+                    assert_eq!(a.is_high(), Ok(i % 3 == 0));
+                    assert_eq!(b.read(), Some(i % 16));
+                    assert_eq!(c.read(), Some(15 - i % 16));
+                }
+                times.push(time.0);
+            }
+            assert_eq!(c.read(), Some(15 - 199 % 16));
+            times
+        };
+        let ((), times) = tokio::join!(record, replay);
+        assert_eq!(times, (0..200).map(|i| i * 10).collect::<Vec<_>>());
+    }
+
+    #[tokio::test]
+    async fn read_errors() {
+        let vcd = "$timescale 1 ns $end
+$scope module top $end
+$var wire 1 ! a $end
+$upscope $end
+$enddefinitions $end
+#0
+1!
+#10
+x
+";
+        let mut reader = AsyncVcdReader::new(vcd.as_bytes()).await.unwrap();
+        assert!(matches!(reader.next_timestamp().await, Some(Ok(_))));
+        assert!(matches!(reader.next_timestamp().await, Some(Ok(_))));
+        let error = reader.next_timestamp().await.unwrap().unwrap_err();
+        assert_eq!(error.kind(), ErrorKind::InvalidData);
+        assert!(reader.next_timestamp().await.is_none());
+
+        assert!(AsyncVcdReader::new(&b"$timescale 1 ns"[..]).await.is_err());
+    }
+}
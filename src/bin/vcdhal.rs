@@ -0,0 +1,203 @@
+//! Command line access to the converters and analyzers of embedded-hal-vcd.
+
+use embedded_hal::spi::{MODE_0, MODE_1, MODE_2, MODE_3};
+use embedded_hal_vcd::analysis::{
+    BitRate, FrequencySeries, I2cDecoder, LatencyAnalyzer, SpiDecoder, Uart, UartEvent, Waveform,
+};
+use embedded_hal_vcd::devices::Edge;
+use embedded_hal_vcd::export::{csv, jsonl, wavedrom};
+use embedded_hal_vcd::import::{ScopeCapture, Threshold};
+use embedded_hal_vcd::testing::compare_with_tolerance;
+use embedded_hal_vcd::testing::coverage::ToggleCoverage;
+use embedded_time::duration::*;
+use std::io::Result as IOResult;
+use std::io::{BufRead, BufReader, Error, ErrorKind, Write};
+use std::process::ExitCode;
+
+const USAGE: &str = "usage: vcdhal <command> [arguments]
+
+commands:
+  csv <file.vcd>                         convert a VCD file to CSV
+  from-csv <file.csv>                    convert a CSV file to VCD
+  jsonl <file.vcd>                       convert a VCD file to JSON Lines
+  from-jsonl <file.jsonl>                convert JSON Lines to VCD
+  wavedrom <file.vcd> <period>           convert a VCD file to a WaveDrom
+                                         diagram sampled every period
+  from-wavedrom <file.json> <period>     convert a WaveDrom diagram to VCD
+  from-scope <file.csv> <low> <high>     convert an oscilloscope CSV export to
+                                         VCD with thresholds in volts
+  stats <file.vcd>                       report the toggles of all signals
+  diff <expected> <actual> [tolerance]   compare the signals of two VCD files
+  frequency <file.vcd> <signal>          list the periods between rising edges
+  latency <file.vcd> <trigger> <response>
+                                         measure the delay between edges
+  decode uart <file.vcd> <signal> [baud] decode UART bytes, the baud rate is
+                                         detected if not given
+  decode spi <file.vcd> <sck> <mosi> <miso> <cs> [mode]
+                                         decode SPI frames, mode 0 to 3
+  decode i2c <file.vcd> <scl> <sda>      decode I2C transfers
+
+Files can be `-` to read from stdin, output is written to stdout. Signals
+are full paths with the scopes separated by dots, e.g. `top.spi.sck`.
+Times are given in nanoseconds.";
+
+fn usage() -> Error {
+    Error::new(ErrorKind::InvalidInput, USAGE)
+}
+
+fn open(path: &str) -> IOResult<Box<dyn BufRead>> {
+    if path == "-" {
+        Ok(Box::new(BufReader::new(std::io::stdin())))
+    } else {
+        Ok(Box::new(BufReader::new(std::fs::File::open(path)?)))
+    }
+}
+
+fn waveform(path: &str, signal: &str) -> IOResult<Waveform> {
+    let signal: Vec<&str> = signal.split('.').collect();
+    Waveform::read(open(path)?, &signal)
+}
+
+/// Format bytes as hex numbers separated by spaces.
+fn hex(bytes: &[u8]) -> String {
+    let bytes: Vec<String> = bytes.iter().map(|byte| format!("{:02x}", byte)).collect();
+    bytes.join(" ")
+}
+
+fn decode(args: &[&str], output: &mut dyn Write) -> IOResult<()> {
+    match args {
+        ["uart", input, signal, rest @ ..] => {
+            let line = waveform(input, signal)?;
+            let baud = match rest {
+                [] => BitRate::detect(&line)
+                    .map(|rate| rate.baud())
+                    .ok_or_else(|| {
+                        Error::new(ErrorKind::InvalidData, "can't detect the baud rate")
+                    })?,
+                [baud] => match baud.parse() {
+                    Ok(0) | Err(_) => return Err(usage()),
+                    Ok(baud) => baud,
+                },
+                _ => return Err(usage()),
+            };
+            for event in Uart::new(baud).decode(&line) {
+                match event {
+                    UartEvent::Byte(byte) if byte.stop => {
+                        writeln!(output, "{} {:02x}", byte.time.0, byte.value)?
+                    }
+                    UartEvent::Byte(byte) => {
+                        writeln!(output, "{} {:02x} framing error", byte.time.0, byte.value)?
+                    }
+                    UartEvent::Break { time, width } => {
+                        writeln!(output, "{} break {}", time.0, width.0)?
+                    }
+                }
+            }
+        }
+        ["spi", input, sck, mosi, miso, cs, rest @ ..] => {
+            let mode = match rest {
+                [] | ["0"] => MODE_0,
+                ["1"] => MODE_1,
+                ["2"] => MODE_2,
+                ["3"] => MODE_3,
+                _ => return Err(usage()),
+            };
+            let (sck, mosi) = (waveform(input, sck)?, waveform(input, mosi)?);
+            let (miso, cs) = (waveform(input, miso)?, waveform(input, cs)?);
+            for frame in SpiDecoder::new(mode).decode(&sck, &mosi, &miso, &cs) {
+                writeln!(
+                    output,
+                    "{} {} mosi {} miso {}",
+                    frame.start.0,
+                    frame.end.0,
+                    hex(&frame.mosi),
+                    hex(&frame.miso)
+                )?;
+            }
+        }
+        ["i2c", input, scl, sda] => {
+            let (scl, sda) = (waveform(input, scl)?, waveform(input, sda)?);
+            for transfer in I2cDecoder::new().decode(&scl, &sda) {
+                writeln!(
+                    output,
+                    "{} {:#04x} {} {} {} {}",
+                    transfer.time.0,
+                    transfer.address,
+                    if transfer.read { "read" } else { "write" },
+                    if transfer.address_ack { "ack" } else { "nack" },
+                    hex(&transfer.bytes()),
+                    if transfer.stop { "stop" } else { "restart" }
+                )?;
+            }
+        }
+        _ => return Err(usage()),
+    }
+    Ok(())
+}
+
+fn run(args: &[String], output: &mut dyn Write) -> IOResult<ExitCode> {
+    let args: Vec<&str> = args.iter().map(String::as_str).collect();
+    match args.as_slice() {
+        ["csv", input] => csv::export(open(input)?, output)?,
+        ["from-csv", input] => csv::import(open(input)?, output)?,
+        ["jsonl", input] => jsonl::export(open(input)?, output)?,
+        ["from-jsonl", input] => jsonl::import(open(input)?, output)?,
+        ["wavedrom", input, period] => {
+            let period = period.parse().map_err(|_| usage())?;
+            wavedrom::export(open(input)?, output, Nanoseconds(period))?
+        }
+        ["from-wavedrom", input, period] => {
+            let period = period.parse().map_err(|_| usage())?;
+            wavedrom::import(open(input)?, output, Nanoseconds(period))?
+        }
+        ["from-scope", input, low, high] => {
+            let volts = |v: &str| v.parse::<f64>().map_err(|_| usage());
+            let threshold = Threshold::new(volts(low)?, volts(high)?);
+            ScopeCapture::parse_csv(open(input)?)?.write_vcd(threshold, output)?
+        }
+        ["stats", input] => ToggleCoverage::read(open(input)?)?.write_report(output)?,
+        ["diff", expected, actual, rest @ ..] => {
+            let tolerance = match rest {
+                [] => 0,
+                [tolerance] => tolerance.parse().map_err(|_| usage())?,
+                _ => return Err(usage()),
+            };
+            let result =
+                compare_with_tolerance(open(expected)?, open(actual)?, Nanoseconds(tolerance))?;
+            if let Err(mismatch) = result {
+                writeln!(output, "{}", mismatch)?;
+                return Ok(ExitCode::FAILURE);
+            }
+        }
+        ["frequency", input, signal] => {
+            FrequencySeries::measure(&waveform(input, signal)?, Edge::Rising).write_csv(output)?
+        }
+        ["latency", input, trigger, response] => {
+            let trigger = waveform(input, trigger)?;
+            let response = waveform(input, response)?;
+            let latencies = LatencyAnalyzer::new().analyze(&trigger, &response);
+            let ns = |t: Option<Nanoseconds<u64>>| t.map_or("-".to_string(), |t| t.0.to_string());
+            writeln!(output, "samples {}", latencies.samples.len())?;
+            writeln!(output, "missed {}", latencies.missed)?;
+            writeln!(output, "min {}", ns(latencies.min()))?;
+            writeln!(output, "mean {}", ns(latencies.mean()))?;
+            writeln!(output, "p99 {}", ns(latencies.percentile(99.0)))?;
+            writeln!(output, "max {}", ns(latencies.max()))?;
+        }
+        ["decode", args @ ..] => decode(args, output)?,
+        _ => return Err(usage()),
+    }
+    Ok(ExitCode::SUCCESS)
+}
+
+fn main() -> ExitCode {
+    let args: Vec<String> = std::env::args().skip(1).collect();
+    let stdout = std::io::stdout();
+    match run(&args, &mut stdout.lock()) {
+        Ok(code) => code,
+        Err(e) => {
+            eprintln!("{}", e);
+            ExitCode::from(2)
+        }
+    }
+}
@@ -0,0 +1,317 @@
+//! Record captures of real hardware into VCD files.
+//!
+//! A [HardwareCapture] records the lines of a test rig through a
+//! [VcdWriter], so the dumps can be checked with the [analysis](crate::analysis)
+//! module like recordings of the simulation. The capture is fed with
+//! [LineEvent]s from an [EdgeSource], e.g. the edge events of the lines of a
+//! Linux gpiochip, which carry the kernel timestamp of each edge. With the
+//! `gpiocdev` feature the `gpiochip` module requests the lines of a chip as
+//! an edge source.
+//!
+//! Logic analyzers and FTDI chips like the FT2232H in synchronous bitbang
+//! mode deliver samples instead, one byte per sample at a fixed rate with a
+//! bit for each of up to 8 channels. These are recorded from any [Read]
+//! with [HardwareCapture::run_samples], e.g. a raw dump of them. With the
+//! `ftdi` feature the `ftdi` module samples the lines of an FTDI chip.
+
+use crate::pins::*;
+use crate::writer::{VcdWriter, VcdWriterBuilder};
+use embedded_hal::digital::OutputPin;
+use embedded_time::duration::*;
+use std::io::Result as IOResult;
+use std::io::{Error, ErrorKind, Read, Write};
+
+#[cfg(feature = "ftdi")]
+pub mod ftdi;
+#[cfg(feature = "gpiocdev")]
+pub mod gpiochip;
+
+/// A change of a captured line.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct LineEvent {
+    /// The index of the line, in the order the names were passed to
+    /// [HardwareCapture::new].
+    pub line: usize,
+    /// The state of the line after the change.
+    pub state: PinState,
+    /// The timestamp of the change, e.g. of a monotonic clock of the kernel.
+    pub timestamp: Nanoseconds<u64>,
+}
+
+/// A source of line changes in the order they happened.
+pub trait EdgeSource {
+    /// Return the next change, `None` if the capture ended.
+    fn read_event(&mut self) -> IOResult<Option<LineEvent>>;
+}
+
+impl<I> EdgeSource for I
+where
+    I: Iterator<Item = LineEvent>,
+{
+    fn read_event(&mut self) -> IOResult<Option<LineEvent>> {
+        Ok(self.next())
+    }
+}
+
+/// A recorder for the lines of a test rig.
+///
+/// The timestamps of the events are recorded relative to the start of the
+/// capture, by default the timestamp of the first event. The initial states
+/// of the lines are recorded at time zero.
+///
+/// ```
+/// use embedded_hal_vcd::analysis::Waveform;
+/// use embedded_hal_vcd::capture::{HardwareCapture, LineEvent};
+/// use embedded_hal_vcd::pins::PinState;
+/// use embedded_hal_vcd::writer::VcdWriterBuilder;
+/// use embedded_time::duration::*;
+///
+/// let mut vcd = Vec::new();
+/// let builder = VcdWriterBuilder::new_with_module(&mut vcd, "rig").unwrap();
+/// let mut capture = HardwareCapture::new(builder, &["button", "led"]).unwrap();
+/// capture.set_initial(0, PinState::High).unwrap();
+/// let events = [(0, PinState::Low, 5_000_000), (1, PinState::High, 5_200_000)];
+/// let mut source = events.iter().map(|(line, state, t)| LineEvent {
+///     line: *line,
+///     state: state.clone(),
+///     timestamp: Nanoseconds(1_000_000_000 + *t),
+/// });
+/// capture.set_start(Nanoseconds(1_000_000_000u64));
+/// capture.run(&mut source).unwrap();
+/// drop(capture);
+///
+/// let led = Waveform::read(&vcd[..], &["rig", "led"]).unwrap();
+/// assert_eq!(led.state_at(Nanoseconds(5_200_000)), Some(PinState::High));
+/// ```
+pub struct HardwareCapture<W>
+where
+    W: Write,
+{
+    writer: VcdWriter<W>,
+    pins: Vec<PushPullPin>,
+    start: Option<u64>,
+    started: bool,
+}
+
+impl<W> HardwareCapture<W>
+where
+    W: Write,
+{
+    /// Create a new capture with a named variable for each line.
+    pub fn new(mut builder: VcdWriterBuilder<W>, lines: &[&str]) -> IOResult<Self> {
+        let pins = lines
+            .iter()
+            .map(|name| builder.add_push_pull_pin(name))
+            .collect::<IOResult<_>>()?;
+        Ok(HardwareCapture {
+            writer: builder.build()?,
+            pins,
+            start: None,
+            started: false,
+        })
+    }
+
+    /// Set the timestamp of the start of the capture, e.g. the time the
+    /// lines were requested. Events before the start are recorded at time
+    /// zero.
+    pub fn set_start(&mut self, start: Nanoseconds<u64>) {
+        self.start = Some(start.0);
+    }
+
+    /// Set the initial state of a line, e.g. the value read when the line
+    /// was requested. Lines start low otherwise.
+    pub fn set_initial(&mut self, line: usize, state: PinState) -> IOResult<()> {
+        self.set(line, state)
+    }
+
+    fn set(&mut self, line: usize, state: PinState) -> IOResult<()> {
+        let pin = self
+            .pins
+            .get_mut(line)
+            .ok_or_else(|| Error::new(ErrorKind::InvalidInput, "unknown line"))?;
+        match state {
+            PinState::High => pin.set_high().unwrap(),
+            _ => pin.set_low().unwrap(),
+        }
+        Ok(())
+    }
+
+    /// Record the initial states at time zero, unless the first event is at
+    /// time zero.
+    fn begin(&mut self, time: u64) -> IOResult<()> {
+        if !self.started && time > 0 {
+            self.writer.timestamp(Nanoseconds(0u64))?;
+            self.writer.sample()?;
+        }
+        self.started = true;
+        Ok(())
+    }
+
+    /// Record a change of a line.
+    pub fn record(&mut self, event: LineEvent) -> IOResult<()> {
+        let start = *self.start.get_or_insert(event.timestamp.0);
+        let time = event.timestamp.0.saturating_sub(start);
+        if event.line >= self.pins.len() {
+            return Err(Error::new(ErrorKind::InvalidInput, "unknown line"));
+        }
+        self.begin(time)?;
+        self.set(event.line, event.state)?;
+        self.writer.timestamp(Nanoseconds(time))?;
+        self.writer.sample()
+    }
+
+    /// Record all events of a source until it ends.
+    pub fn run<S: EdgeSource>(&mut self, source: &mut S) -> IOResult<()> {
+        while let Some(event) = source.read_event()? {
+            self.record(event)?;
+        }
+        if !self.started {
+            // no event, record the initial states only
+            self.started = true;
+            self.writer.timestamp(Nanoseconds(0u64))?;
+            self.writer.sample()?;
+        }
+        Ok(())
+    }
+
+    /// Record bitbang samples until the end of the input.
+    ///
+    /// Each byte is a sample of `period`, bit `n` is the state of line `n`,
+    /// so at most 8 lines are recorded. Only changes are recorded, the first
+    /// sample is recorded at time zero, or relative to
+    /// [set_start](HardwareCapture::set_start) like an event at timestamp
+    /// zero.
+    pub fn run_samples<R: Read>(&mut self, mut read: R, period: Nanoseconds<u64>) -> IOResult<()> {
+        let lines = self.pins.len().min(8);
+        let mut last: Option<u8> = None;
+        let mut buf = [0u8; 4096];
+        let mut index = 0u64;
+        let start = *self.start.get_or_insert(0);
+        loop {
+            let len = match read.read(&mut buf) {
+                Ok(0) => break,
+                Ok(len) => len,
+                Err(e) if e.kind() == ErrorKind::Interrupted => continue,
+                Err(e) => return Err(e),
+            };
+            for sample in buf[..len].iter() {
+                let time = (index * period.0).saturating_sub(start);
+                index += 1;
+                let changed = match last {
+                    Some(last) => last ^ sample,
+                    None => 0xff,
+                };
+                last = Some(*sample);
+                if changed & ((1u16 << lines) - 1) as u8 == 0 {
+                    continue;
+                }
+                self.begin(time)?;
+                for line in (0..lines).filter(|line| changed >> line & 1 == 1) {
+                    let state = if sample >> line & 1 == 1 {
+                        PinState::High
+                    } else {
+                        PinState::Low
+                    };
+                    self.set(line, state)?;
+                }
+                self.writer.timestamp(Nanoseconds(time))?;
+                self.writer.sample()?;
+            }
+        }
+        self.run(&mut std::iter::empty())
+    }
+
+    /// Return the writer, e.g. to annotate the capture.
+    pub fn into_writer(self) -> VcdWriter<W> {
+        self.writer
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::analysis::Waveform;
+
+    #[test]
+    fn record_events() {
+        let mut vcd = vec![];
+        let builder = VcdWriterBuilder::new(&mut vcd).unwrap();
+        let mut capture = HardwareCapture::new(builder, &["clk", "data"]).unwrap();
+        // a clock with monotonic timestamps of the kernel
+        let mut events = vec![];
+        for i in 0..8u64 {
+            let state = if i % 2 == 0 {
+                PinState::High
+            } else {
+                PinState::Low
+            };
+            let timestamp = Nanoseconds(3_600_000_000_000 + i * 1_000);
+            events.push(LineEvent {
+                line: 0,
+                state,
+                timestamp,
+            });
+        }
+        events.insert(
+            3,
+            LineEvent {
+                line: 1,
+                state: PinState::High,
+                timestamp: Nanoseconds(3_600_000_002_500),
+            },
+        );
+        capture.run(&mut events.clone().into_iter()).unwrap();
+        let error = capture.record(LineEvent {
+            line: 2,
+            ..events[0].clone()
+        });
+        assert_eq!(error.unwrap_err().kind(), ErrorKind::InvalidInput);
+        drop(capture.into_writer());
+
+        let clk = Waveform::read(&vcd[..], &["top", "clk"]).unwrap();
+        assert_eq!(clk.changes().len(), 8);
+        assert_eq!(clk.changes()[1], (Nanoseconds(1_000), PinState::Low));
+        let data = Waveform::read(&vcd[..], &["top", "data"]).unwrap();
+        assert_eq!(
+            data.changes(),
+            &[
+                (Nanoseconds(0), PinState::Low),
+                (Nanoseconds(2_500), PinState::High)
+            ]
+        );
+    }
+
+    #[test]
+    fn record_bitbang_samples() {
+        // an SPI byte 0xa5 sampled at 4 MHz, bit 0 is SCK, bit 1 is MOSI,
+        // bit 2 is CS
+        let mut samples = vec![0b100u8; 4];
+        samples.push(0b000);
+        for bit in (0..8).rev() {
+            let mosi = (0xa5u8 >> bit & 1) << 1;
+            samples.extend([mosi, mosi, mosi | 1, mosi | 1]);
+        }
+        samples.extend([0b000, 0b100, 0b100]);
+        let mut vcd = vec![];
+        let builder = VcdWriterBuilder::new(&mut vcd).unwrap();
+        let mut capture = HardwareCapture::new(builder, &["sck", "mosi", "cs"]).unwrap();
+        capture
+            .run_samples(&samples[..], Nanoseconds(250u64))
+            .unwrap();
+        drop(capture);
+
+        let signals = Waveform::read_all(&vcd[..]).unwrap();
+        let sck = &signals[0].1;
+        assert_eq!(sck.pulses(PinState::High).len(), 8);
+        assert_eq!(sck.changes()[1], (Nanoseconds(7 * 250), PinState::High));
+        let cs = &signals[2].1;
+        assert_eq!(
+            cs.changes(),
+            &[
+                (Nanoseconds(0), PinState::High),
+                (Nanoseconds(1_000), PinState::Low),
+                (Nanoseconds(38 * 250), PinState::High)
+            ]
+        );
+    }
+}
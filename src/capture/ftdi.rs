@@ -0,0 +1,192 @@
+//! Sampling of the lines of an FTDI chip in synchronous bitbang mode.
+//!
+//! In synchronous bitbang mode an FTDI chip like the FT2232H samples its 8
+//! data lines once for each byte written to it and returns the samples in
+//! order, so the host has to write a byte for every sample it reads.
+//! [SyncBitbang] writes these bytes and reads the samples, so it can be
+//! recorded with [HardwareCapture::run_samples](super::HardwareCapture::run_samples).
+//! All lines are configured as inputs, so the written bytes don't drive the
+//! lines.
+//!
+//! ```no_run
+//! use embedded_hal_vcd::capture::ftdi::SyncBitbang;
+//! use embedded_hal_vcd::capture::HardwareCapture;
+//! use embedded_hal_vcd::writer::VcdWriterBuilder;
+//! use embedded_time::duration::*;
+//! use std::fs::File;
+//!
+//! let device = ftdi::find_by_vid_pid(0x0403, 0x6010)
+//!     .interface(ftdi::Interface::A)
+//!     .open()
+//!     .unwrap();
+//! let samples = SyncBitbang::configure(device, 62_500)?.with_samples(1_000_000);
+//! // the sample period of the chip at this baud rate
+//! let period = Nanoseconds(1_000u64);
+//! let builder = VcdWriterBuilder::new_with_module(File::create("rig.vcd")?, "rig")?;
+//! let mut capture = HardwareCapture::new(builder, &["sck", "mosi", "miso", "cs"])?;
+//! capture.run_samples(samples, period)?;
+//! # Ok::<(), std::io::Error>(())
+//! ```
+
+use std::io::Result as IOResult;
+use std::io::{Error, ErrorKind, Read, Write};
+
+/// The number of samples requested at once.
+const CHUNK: usize = 4096;
+
+fn io_error(error: ftdi::Error) -> Error {
+    Error::other(error)
+}
+
+/// The samples of a device in synchronous bitbang mode, a byte per sample
+/// with bit `n` the state of line `n`.
+pub struct SyncBitbang<D> {
+    device: D,
+    remaining: Option<u64>,
+    pending: usize,
+}
+
+impl SyncBitbang<ftdi::Device> {
+    /// Configure all lines of a device as inputs in synchronous bitbang
+    /// mode.
+    ///
+    /// The sample rate is derived from `baud_rate` and depends on the chip,
+    /// see its data sheet.
+    pub fn configure(mut device: ftdi::Device, baud_rate: u32) -> IOResult<Self> {
+        device.usb_reset().map_err(io_error)?;
+        device
+            .set_bitmode(0x00, ftdi::BitMode::Reset)
+            .map_err(io_error)?;
+        device
+            .set_bitmode(0x00, ftdi::BitMode::SyncBB)
+            .map_err(io_error)?;
+        device.set_baud_rate(baud_rate).map_err(io_error)?;
+        device.set_latency_timer(1).map_err(io_error)?;
+        device.usb_purge_buffers().map_err(io_error)?;
+        Ok(Self::new(device))
+    }
+}
+
+impl<D> SyncBitbang<D>
+where
+    D: Read + Write,
+{
+    /// Sample a device that is already in synchronous bitbang mode.
+    pub fn new(device: D) -> Self {
+        SyncBitbang {
+            device,
+            remaining: None,
+            pending: 0,
+        }
+    }
+
+    /// End after `samples` samples. Samples until an error of the device
+    /// otherwise.
+    pub fn with_samples(mut self, samples: u64) -> Self {
+        self.remaining = Some(samples);
+        self
+    }
+
+    /// Return the device.
+    pub fn into_inner(self) -> D {
+        self.device
+    }
+}
+
+impl<D> Read for SyncBitbang<D>
+where
+    D: Read + Write,
+{
+    fn read(&mut self, buf: &mut [u8]) -> IOResult<usize> {
+        let remaining = self.remaining.unwrap_or(u64::MAX);
+        let len = buf.len().min(CHUNK).min(remaining as usize);
+        if len == 0 {
+            return Ok(0);
+        }
+        if self.pending == 0 {
+            self.device.write_all(&[0; CHUNK][..len])?;
+            self.pending = len;
+        }
+        let len = len.min(self.pending);
+        loop {
+            // the device returns nothing until the first samples arrive
+            match self.device.read(&mut buf[..len]) {
+                Ok(0) => continue,
+                Ok(read) => {
+                    self.pending -= read;
+                    self.remaining = self.remaining.map(|samples| samples - read as u64);
+                    return Ok(read);
+                }
+                Err(e) if e.kind() == ErrorKind::Interrupted => continue,
+                Err(e) => return Err(e),
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::analysis::Waveform;
+    use crate::capture::HardwareCapture;
+    use crate::pins::PinState;
+    use crate::writer::VcdWriterBuilder;
+    use embedded_time::duration::*;
+    use std::collections::VecDeque;
+
+    /// A device that returns a sample of a counter for each written byte,
+    /// at most 3 at a time.
+    struct Counter {
+        clocked: usize,
+        samples: VecDeque<u8>,
+        empty_reads: usize,
+    }
+
+    impl Read for Counter {
+        fn read(&mut self, buf: &mut [u8]) -> IOResult<usize> {
+            if self.empty_reads > 0 {
+                self.empty_reads -= 1;
+                return Ok(0);
+            }
+            let len = buf.len().min(self.clocked).min(3);
+            for byte in buf[..len].iter_mut() {
+                *byte = self.samples.pop_front().unwrap();
+            }
+            self.clocked -= len;
+            Ok(len)
+        }
+    }
+
+    impl Write for Counter {
+        fn write(&mut self, buf: &[u8]) -> IOResult<usize> {
+            self.clocked += buf.len();
+            Ok(buf.len())
+        }
+
+        fn flush(&mut self) -> IOResult<()> {
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn sync_bitbang_samples() {
+        let device = Counter {
+            clocked: 0,
+            samples: (0..=255).collect(),
+            empty_reads: 2,
+        };
+        let samples = SyncBitbang::new(device).with_samples(16);
+        let mut vcd = vec![];
+        let builder = VcdWriterBuilder::new(&mut vcd).unwrap();
+        let mut capture = HardwareCapture::new(builder, &["d0", "d1"]).unwrap();
+        capture.run_samples(samples, Nanoseconds(100u64)).unwrap();
+        drop(capture);
+
+        let signals = Waveform::read_all(&vcd[..]).unwrap();
+        assert_eq!(signals[0].1.changes().len(), 16);
+        assert_eq!(
+            signals[1].1.changes().last(),
+            Some(&(Nanoseconds(1_400), PinState::High))
+        );
+    }
+}
@@ -0,0 +1,163 @@
+//! Edge events of the lines of a Linux gpiochip.
+//!
+//! [GpioLines] requests input lines with edge detection on both edges from
+//! the GPIO character device with the [gpiocdev] crate. The kernel stamps
+//! each edge with its monotonic clock when the interrupt fires, so the
+//! recorded timing doesn't depend on how fast the events are read.
+//!
+//! ```no_run
+//! use embedded_hal_vcd::capture::gpiochip::GpioLines;
+//! use embedded_hal_vcd::capture::HardwareCapture;
+//! use embedded_hal_vcd::writer::VcdWriterBuilder;
+//! use std::fs::File;
+//! use std::time::Duration;
+//!
+//! // lines 17 and 27 of a Raspberry Pi, until the lines are idle for a second
+//! let mut lines = GpioLines::request("/dev/gpiochip0", &[17, 27])?
+//!     .with_timeout(Duration::from_secs(1));
+//! let builder = VcdWriterBuilder::new_with_module(File::create("rig.vcd")?, "rig")?;
+//! let mut capture = HardwareCapture::new(builder, &["sck", "mosi"])?;
+//! lines.start(&mut capture)?;
+//! capture.run(&mut lines)?;
+//! # Ok::<(), std::io::Error>(())
+//! ```
+
+use super::{EdgeSource, HardwareCapture, LineEvent};
+use crate::pins::PinState;
+use embedded_time::duration::*;
+use gpiocdev::line::{EdgeDetection, EdgeEvent, EdgeKind, EventClock, Value};
+use gpiocdev::Request;
+use std::io::Result as IOResult;
+use std::io::{Error, ErrorKind, Write};
+use std::path::Path;
+use std::time::Duration;
+
+fn io_error(error: gpiocdev::Error) -> Error {
+    Error::other(error)
+}
+
+/// The requested lines of a gpiochip, an [EdgeSource] of their edges.
+///
+/// The lines are recorded in the order of their offsets. A rising edge is a
+/// change to the active state, so lines requested as active low are
+/// recorded inverted.
+pub struct GpioLines {
+    request: Request,
+    offsets: Vec<u32>,
+    timeout: Option<Duration>,
+}
+
+impl GpioLines {
+    /// Request input lines of a chip, e.g. `/dev/gpiochip0`, with edge
+    /// detection on both edges and timestamps of the monotonic clock.
+    pub fn request<P: AsRef<Path>>(chip: P, offsets: &[u32]) -> IOResult<Self> {
+        let request = Request::builder()
+            .on_chip(chip.as_ref())
+            .with_consumer("embedded-hal-vcd")
+            .with_lines(offsets)
+            .as_input()
+            .with_edge_detection(EdgeDetection::BothEdges)
+            .with_event_clock(EventClock::Monotonic)
+            .request()
+            .map_err(io_error)?;
+        Ok(Self::from_request(request, offsets))
+    }
+
+    /// Record the lines of a request with a custom configuration, e.g. with
+    /// a debounce period or a bias. Edge detection must be enabled for the
+    /// lines.
+    pub fn from_request(request: Request, offsets: &[u32]) -> Self {
+        GpioLines {
+            request,
+            offsets: offsets.to_vec(),
+            timeout: None,
+        }
+    }
+
+    /// End the capture if no edge occurs within `timeout`. Waits for edges
+    /// forever otherwise.
+    pub fn with_timeout(mut self, timeout: Duration) -> Self {
+        self.timeout = Some(timeout);
+        self
+    }
+
+    /// Read the current states of the lines.
+    pub fn states(&self) -> IOResult<Vec<PinState>> {
+        self.offsets
+            .iter()
+            .map(
+                |offset| match self.request.value(*offset).map_err(io_error)? {
+                    Value::Active => Ok(PinState::High),
+                    Value::Inactive => Ok(PinState::Low),
+                },
+            )
+            .collect()
+    }
+
+    /// Set the current states of the lines as the initial states of a
+    /// capture.
+    pub fn start<W: Write>(&self, capture: &mut HardwareCapture<W>) -> IOResult<()> {
+        for (line, state) in self.states()?.into_iter().enumerate() {
+            capture.set_initial(line, state)?;
+        }
+        Ok(())
+    }
+}
+
+/// Convert an edge event of the kernel to a change of a line.
+fn line_event(offsets: &[u32], event: &EdgeEvent) -> IOResult<LineEvent> {
+    let line = offsets
+        .iter()
+        .position(|offset| *offset == event.offset)
+        .ok_or_else(|| Error::new(ErrorKind::InvalidData, "edge of an unknown line"))?;
+    let state = match event.kind {
+        EdgeKind::Rising => PinState::High,
+        EdgeKind::Falling => PinState::Low,
+    };
+    Ok(LineEvent {
+        line,
+        state,
+        timestamp: Nanoseconds(event.timestamp_ns),
+    })
+}
+
+impl EdgeSource for GpioLines {
+    fn read_event(&mut self) -> IOResult<Option<LineEvent>> {
+        if let Some(timeout) = self.timeout {
+            if !self.request.wait_edge_event(timeout).map_err(io_error)? {
+                return Ok(None);
+            }
+        }
+        let event = self.request.read_edge_event().map_err(io_error)?;
+        line_event(&self.offsets, &event).map(Some)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn edge_events() {
+        let event = |offset, kind| EdgeEvent {
+            timestamp_ns: 42_000,
+            kind,
+            offset,
+            seqno: 0,
+            line_seqno: 0,
+        };
+        let offsets = [17, 27];
+        assert_eq!(
+            line_event(&offsets, &event(27, EdgeKind::Rising)).unwrap(),
+            LineEvent {
+                line: 1,
+                state: PinState::High,
+                timestamp: Nanoseconds(42_000),
+            }
+        );
+        let low = line_event(&offsets, &event(17, EdgeKind::Falling)).unwrap();
+        assert_eq!((low.line, low.state), (0, PinState::Low));
+        let error = line_event(&offsets, &event(4, EdgeKind::Rising)).unwrap_err();
+        assert_eq!(error.kind(), ErrorKind::InvalidData);
+    }
+}
@@ -0,0 +1,680 @@
+//! Simulated external devices.
+//!
+//! A [SimDevice] reacts to edges on [Signal]s and answers with state changes
+//! on other signals after a delay. The [Simulation] executor wires devices
+//! between pins driven by the code under test and pins read by it, so
+//! drivers can be tested in a closed loop in virtual time instead of only
+//! replaying recorded VCD files.
+//!
+//! Signals are based on shared [AtomicPinState]s, so the same state can be
+//! recorded with [VcdWriterBuilder::add_pin_state](crate::writer::VcdWriterBuilder::add_pin_state).
+
+use crate::pins::*;
+use crate::writer::{VcdWriter, VcdWriterBuilder};
+use embedded_hal::delay::DelayUs;
+use embedded_time::duration::*;
+use fnv::FnvHashMap;
+use std::cmp::{Ordering as CmpOrdering, Reverse};
+use std::collections::BinaryHeap;
+use std::fmt;
+use std::io::Result as IOResult;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Mutex};
+
+mod delay;
+mod dht22;
+mod eeprom;
+mod encoder;
+mod flash;
+mod hc595;
+mod hd44780;
+mod keypad;
+mod lin;
+mod ps2;
+mod sdcard;
+mod watchdog;
+
+pub use delay::PropagationDelay;
+pub use dht22::Dht22;
+pub use eeprom::I2cEeprom;
+pub use encoder::{PositionProfile, QuadratureEncoder, SsiEncoder};
+pub use flash::SpiFlash;
+pub use hc595::Hc595;
+pub use hd44780::{Hd44780Display, Hd44780Monitor};
+pub use keypad::Keypad;
+pub use lin::LinSlave;
+pub use ps2::Ps2Keyboard;
+pub use sdcard::SdCard;
+pub use watchdog::Watchdog;
+
+/// A handle to a signal of a [Simulation].
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub struct Signal(usize);
+
+/// An edge on a [Signal].
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Edge {
+    /// The signal changed to high.
+    Rising,
+    /// The signal changed to low.
+    Falling,
+    /// The signal changed to floating (released).
+    Released,
+}
+
+impl Edge {
+    /// Return the edge that leads to the given state.
+    pub fn to_state(state: &PinState) -> Self {
+        match state {
+            PinState::High => Edge::Rising,
+            PinState::Low => Edge::Falling,
+            PinState::Floating => Edge::Released,
+        }
+    }
+
+    /// Return the state the edge leads to.
+    pub fn state(self) -> PinState {
+        match self {
+            Edge::Rising => PinState::High,
+            Edge::Falling => PinState::Low,
+            Edge::Released => PinState::Floating,
+        }
+    }
+}
+
+/// A state change a [SimDevice] drives on a [Signal] after a delay.
+pub type Response = (Signal, PinState, Nanoseconds<u64>);
+
+/// A simulated external device.
+pub trait SimDevice {
+    /// Called for every edge on any signal of the [Simulation].
+    ///
+    /// `t` is the current virtual time. Returns the state changes the device
+    /// drives in response, each after the given delay relative to `t`.
+    fn on_edge(&mut self, signal: Signal, edge: Edge, t: Nanoseconds<u64>) -> Vec<Response>;
+}
+
+/// The drivers of the devices on a bus signal, by device index.
+type Drivers = FnvHashMap<Option<usize>, BusPin>;
+
+/// A signal of the [Simulation].
+struct SignalEntry {
+    state: Arc<AtomicPinState>,
+    last: PinState,
+    /// The bus and the drivers of the devices for bus signals.
+    bus: Option<(Arc<Bus>, Drivers)>,
+}
+
+/// A [Contention] on a bus signal of a [Simulation].
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct ContentionError {
+    /// The virtual time the contention was detected.
+    pub time: Nanoseconds<u64>,
+    /// The bus signal.
+    pub signal: Signal,
+    /// The conflicting drivers.
+    pub contention: Contention,
+}
+
+impl fmt::Display for ContentionError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "contention on signal {} at {}: {}",
+            self.signal.0, self.time, self.contention
+        )
+    }
+}
+
+impl std::error::Error for ContentionError {}
+
+/// A state change scheduled by the [Simulation].
+struct Scheduled {
+    time: u64,
+    sequence: u64,
+    /// The index of the device that scheduled the change.
+    source: Option<usize>,
+    signal: Signal,
+    state: PinState,
+}
+
+impl PartialEq for Scheduled {
+    fn eq(&self, other: &Self) -> bool {
+        self.cmp(other) == CmpOrdering::Equal
+    }
+}
+
+impl Eq for Scheduled {}
+
+impl PartialOrd for Scheduled {
+    fn partial_cmp(&self, other: &Self) -> Option<CmpOrdering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl Ord for Scheduled {
+    fn cmp(&self, other: &Self) -> CmpOrdering {
+        (self.time, self.sequence).cmp(&(other.time, other.sequence))
+    }
+}
+
+/// An executor for [SimDevice]s in virtual time.
+///
+/// Changes made to signal states by the code under test are detected
+/// whenever the simulation is advanced and are attributed to the current
+/// virtual time. Note that devices responding without delay to their own
+/// changes will prevent the simulation from settling.
+pub struct Simulation {
+    time: Nanoseconds<u64>,
+    recorded: Option<Nanoseconds<u64>>,
+    signals: Vec<SignalEntry>,
+    devices: Vec<Box<dyn SimDevice + Send>>,
+    queue: BinaryHeap<Reverse<Scheduled>>,
+    sequence: u64,
+    contentions: Vec<ContentionError>,
+}
+
+impl Simulation {
+    /// Create a new simulation starting at time zero.
+    pub fn new() -> Self {
+        Simulation {
+            time: Nanoseconds(0),
+            recorded: None,
+            signals: vec![],
+            devices: vec![],
+            queue: BinaryHeap::new(),
+            sequence: 0,
+            contentions: vec![],
+        }
+    }
+
+    /// Return the current virtual time.
+    pub fn time(&self) -> Nanoseconds<u64> {
+        self.time
+    }
+
+    /// Add a signal based on a shared pin state.
+    ///
+    /// The current state of the pin is taken as initial state, so no edge is
+    /// reported for it.
+    pub fn add_signal(&mut self, state: Arc<AtomicPinState>) -> Signal {
+        let last = state.load(Ordering::SeqCst);
+        self.signals.push(SignalEntry {
+            state,
+            last,
+            bus: None,
+        });
+        Signal(self.signals.len() - 1)
+    }
+
+    /// Add a signal based on a [Bus] with multiple drivers.
+    ///
+    /// Each device driving the signal gets its own driver on the bus, so
+    /// e.g. an open drain line can be shared between the code under test
+    /// (see [Simulation::bus_pin]) and devices.
+    pub fn add_bus(&mut self, bus: Arc<Bus>) -> Signal {
+        let state = bus.line();
+        let last = state.load(Ordering::SeqCst);
+        self.signals.push(SignalEntry {
+            state,
+            last,
+            bus: Some((bus, FnvHashMap::default())),
+        });
+        Signal(self.signals.len() - 1)
+    }
+
+    /// Return all contentions on bus signals detected so far.
+    ///
+    /// Drivers of devices are named `device <n>` by the index of the device,
+    /// changes scheduled with [Simulation::schedule] are named `schedule`.
+    pub fn contentions(&self) -> &[ContentionError] {
+        &self.contentions
+    }
+
+    /// Return the first contention on a bus signal as error.
+    pub fn check_contention(&self) -> Result<(), ContentionError> {
+        match self.contentions.first() {
+            Some(contention) => Err(contention.clone()),
+            None => Ok(()),
+        }
+    }
+
+    /// Add a new bus signal with a pull state that is also recorded with
+    /// a corresponding named VCD variable.
+    pub fn add_recorded_bus<W>(
+        &mut self,
+        builder: &mut VcdWriterBuilder<W>,
+        reference: &str,
+        pull: PinState,
+    ) -> IOResult<Signal>
+    where
+        W: std::io::Write,
+    {
+        let bus = Arc::new(Bus::new(pull));
+        builder.add_pin_state(reference, bus.line())?;
+        Ok(self.add_bus(bus))
+    }
+
+    /// Add a driver for a signal with a propagation delay.
+    ///
+    /// Pins of the returned signal (e.g. from [Simulation::push_pull_pin])
+    /// drive `signal` after `delay`, so devices and recordings of `signal`
+    /// see changes made by the code under test that much later. The driver
+    /// starts with the current state of `signal`.
+    pub fn add_delayed_driver<D>(&mut self, signal: Signal, delay: D) -> Signal
+    where
+        D: Into<Nanoseconds<u64>>,
+    {
+        let state = Arc::new(AtomicPinState::new_with_state(self.state(signal)));
+        let driver = self.add_signal(state);
+        self.add_device(PropagationDelay::new(driver, signal, delay.into()));
+        driver
+    }
+
+    /// Return a [BidirPin] that drives a signal in output mode.
+    ///
+    /// The pin starts in input mode.
+    pub fn bidir_pin(&self, signal: Signal) -> BidirPin {
+        BidirPin::new(
+            self.signals[signal.0].state.clone(),
+            Arc::new(AtomicBool::new(false)),
+        )
+    }
+
+    /// Return a new [BusPin] that drives a bus signal.
+    ///
+    /// # Panics
+    ///
+    /// Panics if the signal has not been added with [Simulation::add_bus].
+    pub fn bus_pin(&self, signal: Signal) -> BusPin {
+        let (bus, _) = self.signals[signal.0]
+            .bus
+            .as_ref()
+            .expect("signal is not a bus");
+        BusPin::new(bus.clone())
+    }
+
+    /// Return a new named [BusPin] that drives a bus signal, see
+    /// [Simulation::bus_pin].
+    pub fn bus_pin_named(&self, signal: Signal, name: &str) -> BusPin {
+        let (bus, _) = self.signals[signal.0]
+            .bus
+            .as_ref()
+            .expect("signal is not a bus");
+        BusPin::new_named(bus.clone(), name)
+    }
+
+    /// Add a new signal with an initial state that is also recorded with
+    /// a corresponding named VCD variable.
+    pub fn add_recorded_signal<W>(
+        &mut self,
+        builder: &mut VcdWriterBuilder<W>,
+        reference: &str,
+        initial: PinState,
+    ) -> IOResult<Signal>
+    where
+        W: std::io::Write,
+    {
+        let state = Arc::new(AtomicPinState::new_with_state(initial));
+        builder.add_pin_state(reference, state.clone())?;
+        Ok(self.add_signal(state))
+    }
+
+    /// Return an [InputPin] that reads the state of a signal.
+    pub fn input_pin(&self, signal: Signal) -> InputPin {
+        InputPin::new(self.signals[signal.0].state.clone())
+    }
+
+    /// Return a [PushPullPin] that drives a signal.
+    pub fn push_pull_pin(&self, signal: Signal) -> PushPullPin {
+        PushPullPin::new(self.signals[signal.0].state.clone())
+    }
+
+    /// Return an [OpenDrainPin] that drives a signal.
+    pub fn open_drain_pin(&self, signal: Signal) -> OpenDrainPin {
+        OpenDrainPin::new(self.signals[signal.0].state.clone())
+    }
+
+    /// Return the current state of a signal.
+    pub fn state(&self, signal: Signal) -> PinState {
+        self.signals[signal.0].state.load(Ordering::SeqCst)
+    }
+
+    /// Add a device that gets notified about edges on all signals.
+    pub fn add_device<D>(&mut self, device: D)
+    where
+        D: SimDevice + Send + 'static,
+    {
+        self.devices.push(Box::new(device));
+    }
+
+    /// Schedule a state change on a signal at an absolute virtual time.
+    ///
+    /// Changes scheduled in the past are applied at the current time.
+    pub fn schedule<D>(&mut self, signal: Signal, state: PinState, at: D)
+    where
+        D: Into<Nanoseconds<u64>>,
+    {
+        self.schedule_from(None, signal, state, at.into());
+    }
+
+    fn schedule_from(
+        &mut self,
+        source: Option<usize>,
+        signal: Signal,
+        state: PinState,
+        at: Nanoseconds<u64>,
+    ) {
+        self.sequence += 1;
+        self.queue.push(Reverse(Scheduled {
+            time: at.0,
+            sequence: self.sequence,
+            source,
+            signal,
+            state,
+        }));
+    }
+
+    /// Advance the virtual time to `t`.
+    ///
+    /// All scheduled changes before `t` are applied and devices are notified
+    /// about the resulting edges. Changes scheduled exactly at `t` are
+    /// applied on the next call.
+    pub fn advance_to<D>(&mut self, t: D)
+    where
+        D: Into<Nanoseconds<u64>>,
+    {
+        // running without recording can't fail
+        let _ = self.run(t.into(), |_| Ok(()));
+    }
+
+    /// Advance the virtual time to `t` and record all changes.
+    ///
+    /// Works like [Simulation::advance_to], but samples the writer at the
+    /// current time and after every change of the simulation.
+    pub fn advance_to_recorded<D, W>(&mut self, t: D, writer: &mut VcdWriter<W>) -> IOResult<()>
+    where
+        D: Into<Nanoseconds<u64>>,
+        W: std::io::Write,
+    {
+        self.run(t.into(), |new| {
+            if let Some(time) = new {
+                writer.timestamp(time)?;
+            }
+            writer.sample()
+        })
+    }
+
+    fn run<F>(&mut self, t: Nanoseconds<u64>, mut record: F) -> IOResult<()>
+    where
+        F: FnMut(Option<Nanoseconds<u64>>) -> IOResult<()>,
+    {
+        self.settle();
+        self.record(&mut record)?;
+        while let Some(next) = self.next_time().filter(|next| *next < t.0) {
+            self.time = Nanoseconds(next.max(self.time.0));
+            self.settle();
+            self.record(&mut record)?;
+        }
+        if t > self.time {
+            self.time = t;
+        }
+        Ok(())
+    }
+
+    /// Record the current state, the timestamp is only passed if it
+    /// has not been recorded yet.
+    fn record<F>(&mut self, record: &mut F) -> IOResult<()>
+    where
+        F: FnMut(Option<Nanoseconds<u64>>) -> IOResult<()>,
+    {
+        let new = if self.recorded == Some(self.time) {
+            None
+        } else {
+            self.recorded = Some(self.time);
+            Some(self.time)
+        };
+        record(new)
+    }
+
+    fn next_time(&self) -> Option<u64> {
+        self.queue.peek().map(|scheduled| scheduled.0.time)
+    }
+
+    /// Dispatch edges and apply due changes until nothing changes anymore.
+    fn settle(&mut self) {
+        loop {
+            let edges = self.poll();
+            for (signal, edge) in edges.iter() {
+                for i in 0..self.devices.len() {
+                    let responses = self.devices[i].on_edge(*signal, *edge, self.time);
+                    for (signal, state, delay) in responses {
+                        self.schedule_from(Some(i), signal, state, self.time + delay);
+                    }
+                }
+            }
+            let mut applied = false;
+            while self
+                .next_time()
+                .filter(|next| *next <= self.time.0)
+                .is_some()
+            {
+                let Reverse(scheduled) = self.queue.pop().unwrap();
+                self.apply(scheduled);
+                applied = true;
+            }
+            if edges.is_empty() && !applied {
+                break;
+            }
+        }
+    }
+
+    /// Apply a scheduled change, bus signals are driven by the driver of
+    /// the device that scheduled the change.
+    fn apply(&mut self, scheduled: Scheduled) {
+        let entry = &mut self.signals[scheduled.signal.0];
+        match entry.bus.as_mut() {
+            Some((bus, drivers)) => drivers
+                .entry(scheduled.source)
+                .or_insert_with(|| {
+                    let name = match scheduled.source {
+                        Some(i) => format!("device {}", i),
+                        None => "schedule".to_string(),
+                    };
+                    BusPin::new_named(bus.clone(), &name)
+                })
+                .drive(scheduled.state),
+            None => entry.state.store(scheduled.state, Ordering::SeqCst),
+        }
+    }
+
+    /// Detect all signals that changed since the last poll.
+    fn poll(&mut self) -> Vec<(Signal, Edge)> {
+        let mut edges = vec![];
+        for (i, entry) in self.signals.iter_mut().enumerate() {
+            if let Some((bus, _)) = entry.bus.as_ref() {
+                self.contentions
+                    .extend(
+                        bus.take_contentions()
+                            .into_iter()
+                            .map(|contention| ContentionError {
+                                time: self.time,
+                                signal: Signal(i),
+                                contention,
+                            }),
+                    );
+            }
+            let current = entry.state.load(Ordering::SeqCst);
+            if current != entry.last {
+                edges.push((Signal(i), Edge::to_state(&current)));
+                entry.last = current;
+            }
+        }
+        edges
+    }
+}
+
+impl Default for Simulation {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// A [delay](DelayUs) that advances the virtual time of a shared [Simulation].
+///
+/// Drivers that use delays for timing can be run against simulated devices
+/// this way. Optionally the simulation is recorded to a shared writer, see
+/// [Simulation::advance_to_recorded].
+pub struct SimDelay<W = std::io::Sink>
+where
+    W: std::io::Write,
+{
+    sim: Arc<Mutex<Simulation>>,
+    writer: Option<Arc<Mutex<VcdWriter<W>>>>,
+}
+
+impl SimDelay {
+    /// Create a new delay for a shared simulation.
+    pub fn new(sim: Arc<Mutex<Simulation>>) -> Self {
+        SimDelay { sim, writer: None }
+    }
+}
+
+impl<W> SimDelay<W>
+where
+    W: std::io::Write,
+{
+    /// Create a new delay for a shared simulation that records all changes
+    /// to a shared writer.
+    pub fn new_recorded(sim: Arc<Mutex<Simulation>>, writer: Arc<Mutex<VcdWriter<W>>>) -> Self {
+        SimDelay {
+            sim,
+            writer: Some(writer),
+        }
+    }
+}
+
+impl<W> DelayUs for SimDelay<W>
+where
+    W: std::io::Write,
+{
+    type Error = std::io::Error;
+
+    fn delay_us(&mut self, us: u32) -> Result<(), Self::Error> {
+        let mut sim = self.sim.lock().unwrap();
+        let t = sim.time() + Nanoseconds(us as u64 * 1000);
+        match self.writer.as_ref() {
+            Some(writer) => sim.advance_to_recorded(t, &mut writer.lock().unwrap()),
+            None => {
+                sim.advance_to(t);
+                Ok(())
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::writer::VcdWriterBuilder;
+    use embedded_hal::digital::{InputPin as _, OutputPin};
+
+    /// An inverter with a propagation delay.
+    struct Inverter {
+        input: Signal,
+        output: Signal,
+    }
+
+    impl SimDevice for Inverter {
+        fn on_edge(&mut self, signal: Signal, edge: Edge, _t: Nanoseconds<u64>) -> Vec<Response> {
+            match (signal == self.input, edge) {
+                (true, Edge::Rising) => vec![(self.output, PinState::Low, Nanoseconds(10))],
+                (true, Edge::Falling) => vec![(self.output, PinState::High, Nanoseconds(10))],
+                _ => vec![],
+            }
+        }
+    }
+
+    #[test]
+    fn inverter() {
+        let mut buf = Vec::new();
+        let mut builder = VcdWriterBuilder::new_with_module(&mut buf, "logic").unwrap();
+        let input = Arc::new(AtomicPinState::new_with_state(PinState::Low));
+        let output = Arc::new(AtomicPinState::new_with_state(PinState::High));
+        builder.add_pin_state("in", input.clone()).unwrap();
+        builder.add_pin_state("out", output.clone()).unwrap();
+        let mut writer = builder.build().unwrap();
+
+        let mut sim = Simulation::new();
+        let mut in_pin = PushPullPin::new(input.clone());
+        let in_signal = sim.add_signal(input);
+        let out_signal = sim.add_signal(output);
+        let out_pin = sim.input_pin(out_signal);
+        sim.add_device(Inverter {
+            input: in_signal,
+            output: out_signal,
+        });
+
+        sim.advance_to_recorded(100.nanoseconds(), &mut writer)
+            .unwrap();
+        in_pin.set_high().unwrap();
+        sim.advance_to_recorded(105.nanoseconds(), &mut writer)
+            .unwrap();
+        assert_eq!(out_pin.is_high(), Ok(true));
+        sim.advance_to_recorded(200.nanoseconds(), &mut writer)
+            .unwrap();
+        assert_eq!(out_pin.is_low(), Ok(true));
+        assert_eq!(sim.time(), 200.nanoseconds());
+        drop(writer);
+
+        let vcd = String::from_utf8(buf).unwrap();
+        assert!(vcd.ends_with("#0\n0!\n1\"\n#100\n1!\n1\"\n#105\n1!\n1\"\n#110\n1!\n0\"\n"));
+    }
+
+    #[test]
+    fn propagation_delay() {
+        let mut buf = Vec::new();
+        let mut builder = VcdWriterBuilder::new_with_module(&mut buf, "logic").unwrap();
+        let mut sim = Simulation::new();
+        let line = sim
+            .add_recorded_signal(&mut builder, "line", PinState::Low)
+            .unwrap();
+        let mut writer = builder.build().unwrap();
+        let driver = sim.add_delayed_driver(line, 50.nanoseconds());
+        let mut pin = sim.push_pull_pin(driver);
+
+        sim.advance_to_recorded(100.nanoseconds(), &mut writer)
+            .unwrap();
+        pin.set_high().unwrap();
+        sim.advance_to_recorded(149.nanoseconds(), &mut writer)
+            .unwrap();
+        assert_eq!(sim.state(line), PinState::Low);
+        sim.advance_to_recorded(200.nanoseconds(), &mut writer)
+            .unwrap();
+        assert_eq!(sim.state(line), PinState::High);
+        drop(writer);
+
+        let vcd = String::from_utf8(buf).unwrap();
+        assert!(vcd.ends_with("#0\n0!\n#100\n0!\n#149\n0!\n#150\n1!\n"));
+    }
+
+    #[test]
+    fn contention() {
+        let mut sim = Simulation::new();
+        let line = sim.add_bus(Arc::new(Bus::new(PinState::High)));
+        let mut mcu = sim.bus_pin_named(line, "mcu");
+        sim.schedule(line, PinState::Low, 100.nanoseconds());
+        sim.schedule(line, PinState::Floating, 200.nanoseconds());
+        mcu.drive(PinState::High);
+        sim.advance_to(50.nanoseconds());
+        assert!(sim.check_contention().is_ok());
+        sim.advance_to(300.nanoseconds());
+        let error = sim.check_contention().unwrap_err();
+        assert_eq!(error.time, 100.nanoseconds());
+        assert_eq!(
+            error.to_string(),
+            "contention on signal 0 at 100: mcu drive high while schedule pull low"
+        );
+        assert_eq!(sim.contentions().len(), 1);
+    }
+}
@@ -0,0 +1,32 @@
+//! Propagation delay of a line.
+
+use super::{Edge, Response, Signal, SimDevice};
+use embedded_time::duration::*;
+
+/// A line that forwards each change of one signal to another after a delay.
+///
+/// This models the propagation delay of a pin, e.g. the output stage of a
+/// GPIO, a level shifter or a long cable. Use
+/// [Simulation::add_delayed_driver](super::Simulation::add_delayed_driver)
+/// to delay a pin of the code under test.
+pub struct PropagationDelay {
+    from: Signal,
+    to: Signal,
+    delay: Nanoseconds<u64>,
+}
+
+impl PropagationDelay {
+    /// Create a new delay from the signal `from` to the signal `to`.
+    pub fn new(from: Signal, to: Signal, delay: Nanoseconds<u64>) -> Self {
+        PropagationDelay { from, to, delay }
+    }
+}
+
+impl SimDevice for PropagationDelay {
+    fn on_edge(&mut self, signal: Signal, edge: Edge, _t: Nanoseconds<u64>) -> Vec<Response> {
+        if signal != self.from {
+            return vec![];
+        }
+        vec![(self.to, edge.state(), self.delay)]
+    }
+}
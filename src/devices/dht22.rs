@@ -0,0 +1,177 @@
+//! A DHT22 (AM2302) temperature and humidity sensor.
+
+use super::{Edge, Response, Signal, SimDevice};
+use crate::pins::*;
+use embedded_time::duration::*;
+
+/// The minimum time the host has to pull the line low to start a measurement.
+const START_MIN: u64 = 500_000;
+
+/// A simulated DHT22 temperature and humidity sensor.
+///
+/// The sensor is attached to a single data line. The host starts a
+/// measurement by pulling the line low for at least 1 ms (500 µs are
+/// accepted) and releasing it afterwards. The sensor answers after 30 µs
+/// with 80 µs low, 80 µs high and 40 data bits, each starting with 50 µs low
+/// followed by 27 µs (`0`) or 70 µs (`1`) high, and releases the line after a
+/// final 50 µs low.
+///
+/// The line is expected to be released (floating) when idle, which is
+/// treated like a pull up. While answering the sensor drives the line high
+/// and low, so the host should switch its pin to input mode, e.g. using a
+/// [BidirPin].
+pub struct Dht22 {
+    line: Signal,
+    readings: Vec<(f32, f32)>,
+    next: usize,
+    start: Option<u64>,
+    busy_until: u64,
+}
+
+impl Dht22 {
+    /// Create a new sensor that answers with a sequence of readings.
+    ///
+    /// Each reading is a pair of relative humidity in percent and
+    /// temperature in degree celsius. The readings are returned in order,
+    /// the last reading is repeated once the sequence is exhausted.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `readings` is empty.
+    pub fn new(line: Signal, readings: &[(f32, f32)]) -> Self {
+        assert!(!readings.is_empty(), "DHT22 needs at least one reading");
+        Dht22 {
+            line,
+            readings: readings.to_vec(),
+            next: 0,
+            start: None,
+            busy_until: 0,
+        }
+    }
+
+    /// Encode a reading as the 40 bit frame of the sensor.
+    fn frame(humidity: f32, temperature: f32) -> [u8; 5] {
+        let humidity = (humidity * 10.0).round() as u16;
+        let mut temperature_raw = (temperature.abs() * 10.0).round() as u16 & 0x7FFF;
+        if temperature < 0.0 {
+            temperature_raw |= 0x8000;
+        }
+        let [h1, h0] = humidity.to_be_bytes();
+        let [t1, t0] = temperature_raw.to_be_bytes();
+        let checksum = h1.wrapping_add(h0).wrapping_add(t1).wrapping_add(t0);
+        [h1, h0, t1, t0, checksum]
+    }
+
+    fn respond(&mut self) -> Vec<Response> {
+        let (humidity, temperature) = self.readings[self.next];
+        if self.next + 1 < self.readings.len() {
+            self.next += 1;
+        }
+        let mut responses = vec![];
+        let mut t = 30_000;
+        let mut push = |state, duration: u64| {
+            responses.push((self.line, state, Nanoseconds(t)));
+            t += duration;
+        };
+        push(PinState::Low, 80_000);
+        push(PinState::High, 80_000);
+        for byte in Self::frame(humidity, temperature) {
+            for bit in (0..8).rev() {
+                push(PinState::Low, 50_000);
+                let high = if byte & (1 << bit) != 0 {
+                    70_000
+                } else {
+                    27_000
+                };
+                push(PinState::High, high);
+            }
+        }
+        push(PinState::Low, 50_000);
+        push(PinState::Floating, 0);
+        responses
+    }
+}
+
+impl SimDevice for Dht22 {
+    fn on_edge(&mut self, signal: Signal, edge: Edge, t: Nanoseconds<u64>) -> Vec<Response> {
+        if signal != self.line || t.0 < self.busy_until {
+            return vec![];
+        }
+        match (edge, self.start) {
+            (Edge::Falling, _) => {
+                self.start = Some(t.0);
+                vec![]
+            }
+            (_, Some(start)) => {
+                self.start = None;
+                if t.0 - start < START_MIN {
+                    return vec![];
+                }
+                let responses = self.respond();
+                self.busy_until = t.0 + responses.last().map(|r| r.2 .0).unwrap_or(0) + 1;
+                responses
+            }
+            _ => vec![],
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::devices::{SimDelay, Simulation};
+    use embedded_hal::delay::DelayUs;
+    use embedded_hal::digital::{InputPin as _, OutputPin};
+    use std::sync::{Arc, Mutex};
+
+    /// Read the sensor like a typical driver, polling the line every µs.
+    fn read(pin: &mut BidirPin, delay: &mut SimDelay) -> Option<(f32, f32)> {
+        pin.set_as_output();
+        pin.set_low().unwrap();
+        delay.delay_ms(1).unwrap();
+        pin.set_as_input();
+        // measure the duration of the current level
+        let mut pulse = |high: bool| {
+            let mut duration = 0;
+            while pin.is_high().unwrap() == high && duration < 200 {
+                delay.delay_us(1).unwrap();
+                duration += 1;
+            }
+            duration
+        };
+        // response of the sensor
+        pulse(false);
+        pulse(true);
+        let mut data = [0u8; 5];
+        for byte in data.iter_mut() {
+            for _ in 0..8 {
+                pulse(false);
+                *byte = (*byte << 1) | (pulse(true) > 40) as u8;
+            }
+        }
+        let checksum = data[..4].iter().fold(0u8, |s, b| s.wrapping_add(*b));
+        if checksum != data[4] {
+            return None;
+        }
+        let humidity = u16::from_be_bytes([data[0], data[1]]) as f32 / 10.0;
+        let temperature = u16::from_be_bytes([data[2] & 0x7F, data[3]]) as f32 / 10.0;
+        let sign = if data[2] & 0x80 != 0 { -1.0 } else { 1.0 };
+        Some((humidity, sign * temperature))
+    }
+
+    #[test]
+    fn measure() {
+        let mut sim = Simulation::new();
+        let line = sim.add_signal(Arc::new(AtomicPinState::new()));
+        let mut pin = sim.bidir_pin(line);
+        sim.add_device(Dht22::new(line, &[(45.5, 21.3), (50.0, -3.2)]));
+        let sim = Arc::new(Mutex::new(sim));
+        let mut delay = SimDelay::new(sim.clone());
+
+        assert_eq!(read(&mut pin, &mut delay), Some((45.5, 21.3)));
+        delay.delay_ms(2000).unwrap();
+        assert_eq!(read(&mut pin, &mut delay), Some((50.0, -3.2)));
+        delay.delay_ms(2000).unwrap();
+        assert_eq!(read(&mut pin, &mut delay), Some((50.0, -3.2)));
+    }
+}
@@ -0,0 +1,466 @@
+//! A 24LC-style I2C EEPROM.
+
+use super::{Edge, Response, Signal, SimDevice};
+use crate::pins::*;
+use embedded_hal::digital::InputPin as _;
+use embedded_time::duration::*;
+use std::sync::{Arc, Mutex};
+
+/// The part of a transfer the EEPROM expects next.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+enum Phase {
+    /// Waiting for a start condition.
+    Idle,
+    /// Receiving the device address.
+    Address,
+    /// Receiving the word address, with the number of bytes left.
+    WordAddress(usize),
+    /// Receiving data to write.
+    Write,
+    /// Transmitting data.
+    Read,
+}
+
+/// A simulated 24LC-style I2C EEPROM.
+///
+/// The EEPROM is attached to SDA and SCL [bus signals](super::Simulation::add_bus)
+/// and supports byte and page writes, current address reads, random reads
+/// (with a repeated start) and sequential reads. Writes are committed
+/// immediately, the write cycle time is not modelled.
+///
+/// Optionally the EEPROM stretches the clock after each acknowledge bit,
+/// so clock stretching aware masters can be tested. The stretch durations
+/// can vary, e.g. to model a device that is busy for different times.
+pub struct I2cEeprom {
+    sda: InputPin,
+    scl: InputPin,
+    sda_signal: Signal,
+    scl_signal: Signal,
+    address: u8,
+    address_bytes: usize,
+    page_size: usize,
+    memory: Arc<Mutex<Vec<u8>>>,
+    pointer: usize,
+    /// The stretch durations, used in turn.
+    stretch: Vec<Nanoseconds<u64>>,
+    stretches: usize,
+    delay: Nanoseconds<u64>,
+    phase: Phase,
+    /// The number of bits received or transmitted in the current byte.
+    bits: u8,
+    byte: u8,
+    /// Whether the current clock is the acknowledge clock.
+    ack: bool,
+    /// Whether the master acknowledged the last transmitted byte.
+    master_ack: bool,
+    read: bool,
+}
+
+impl I2cEeprom {
+    /// Create a new EEPROM with a 7-bit `address` and `size` bytes of memory.
+    ///
+    /// `sda` and `scl` are used to read the bus lines, `sda_signal` and
+    /// `scl_signal` are the corresponding bus signals. The memory is erased
+    /// (all bytes `0xFF`), uses one word address byte and a page size of
+    /// 8 bytes like a 24LC02.
+    pub fn new(
+        sda: InputPin,
+        scl: InputPin,
+        sda_signal: Signal,
+        scl_signal: Signal,
+        address: u8,
+        size: usize,
+    ) -> Self {
+        I2cEeprom {
+            sda,
+            scl,
+            sda_signal,
+            scl_signal,
+            address,
+            address_bytes: 1,
+            page_size: 8,
+            memory: Arc::new(Mutex::new(vec![0xFF; size])),
+            pointer: 0,
+            stretch: vec![],
+            stretches: 0,
+            delay: Nanoseconds(100),
+            phase: Phase::Idle,
+            bits: 0,
+            byte: 0,
+            ack: false,
+            master_ack: false,
+            read: false,
+        }
+    }
+
+    /// Set the initial memory contents.
+    ///
+    /// The memory is resized to the size of the contents.
+    pub fn with_contents(self, contents: &[u8]) -> Self {
+        *self.memory.lock().unwrap() = contents.to_vec();
+        self
+    }
+
+    /// Set the number of word address bytes, e.g. 2 for a 24LC256.
+    pub fn with_address_bytes(mut self, address_bytes: usize) -> Self {
+        self.address_bytes = address_bytes;
+        self
+    }
+
+    /// Set the page size, page writes wrap around at page boundaries.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `page_size` is zero.
+    pub fn with_page_size(mut self, page_size: usize) -> Self {
+        assert!(page_size > 0, "page size must not be zero");
+        self.page_size = page_size;
+        self
+    }
+
+    /// Stretch the clock for the given duration after each acknowledge bit.
+    pub fn with_clock_stretching(self, stretch: Nanoseconds<u64>) -> Self {
+        self.with_clock_stretching_sequence(&[stretch])
+    }
+
+    /// Stretch the clock after each acknowledge bit, using the given
+    /// durations in turn and starting over after the last one.
+    ///
+    /// A duration of zero doesn't stretch the clock.
+    pub fn with_clock_stretching_sequence(mut self, stretch: &[Nanoseconds<u64>]) -> Self {
+        self.stretch = stretch.to_vec();
+        self.stretches = 0;
+        self
+    }
+
+    /// Set the delay after a falling clock edge until SDA is changed.
+    ///
+    /// The default is 100 ns.
+    pub fn with_delay(mut self, delay: Nanoseconds<u64>) -> Self {
+        self.delay = delay;
+        self
+    }
+
+    /// Return a handle to the memory, to inspect it after a test.
+    pub fn memory(&self) -> Arc<Mutex<Vec<u8>>> {
+        self.memory.clone()
+    }
+
+    fn drive_sda(&self, low: bool) -> Response {
+        let state = if low {
+            PinState::Low
+        } else {
+            PinState::Floating
+        };
+        (self.sda_signal, state, self.delay)
+    }
+
+    fn drive_bit(&self) -> Response {
+        let memory = self.memory.lock().unwrap();
+        let byte = memory.get(self.pointer).copied().unwrap_or(0xFF);
+        self.drive_sda(byte & (0x80 >> self.bits) == 0)
+    }
+
+    /// Handle a received byte, returns whether it is acknowledged.
+    fn receive(&mut self) -> bool {
+        let byte = self.byte;
+        match self.phase {
+            Phase::Address => {
+                if byte >> 1 != self.address {
+                    self.phase = Phase::Idle;
+                    return false;
+                }
+                self.read = byte & 1 == 1;
+                if !self.read {
+                    self.phase = Phase::WordAddress(self.address_bytes);
+                }
+            }
+            Phase::WordAddress(left) => {
+                if left == self.address_bytes {
+                    self.pointer = 0;
+                }
+                self.pointer = (self.pointer << 8) | byte as usize;
+                self.phase = if left > 1 {
+                    Phase::WordAddress(left - 1)
+                } else {
+                    Phase::Write
+                };
+            }
+            Phase::Write => {
+                let mut memory = self.memory.lock().unwrap();
+                let size = memory.len();
+                if size > 0 {
+                    self.pointer %= size;
+                    memory[self.pointer] = byte;
+                }
+                let page = self.pointer - self.pointer % self.page_size;
+                self.pointer = page + (self.pointer + 1) % self.page_size;
+            }
+            Phase::Idle | Phase::Read => {}
+        }
+        true
+    }
+
+    fn on_scl_rising(&mut self) {
+        match self.phase {
+            Phase::Idle => {}
+            Phase::Read if self.ack => {
+                self.master_ack = self.sda.is_low().unwrap();
+            }
+            Phase::Read => {}
+            _ if !self.ack => {
+                self.byte = (self.byte << 1) | self.sda.is_high().unwrap() as u8;
+                self.bits += 1;
+            }
+            _ => {}
+        }
+    }
+
+    fn on_scl_falling(&mut self) -> Vec<Response> {
+        let mut responses = vec![];
+        let phase = self.phase;
+        match phase {
+            Phase::Idle => {}
+            Phase::Read if self.ack => {
+                // end of the acknowledge clock of the master
+                self.ack = false;
+                self.bits = 0;
+                let size = self.memory.lock().unwrap().len().max(1);
+                self.pointer = (self.pointer + 1) % size;
+                if self.master_ack {
+                    responses.push(self.drive_bit());
+                    self.bits = 1;
+                    self.stretch(&mut responses);
+                } else {
+                    self.phase = Phase::Idle;
+                    responses.push(self.drive_sda(false));
+                }
+            }
+            Phase::Read if self.bits < 8 => {
+                responses.push(self.drive_bit());
+                self.bits += 1;
+            }
+            Phase::Read => {
+                // release the line for the acknowledge of the master
+                self.ack = true;
+                responses.push(self.drive_sda(false));
+            }
+            _ if self.ack => {
+                // end of the acknowledge clock
+                self.ack = false;
+                self.bits = 0;
+                self.byte = 0;
+                if self.phase == Phase::Address && self.read {
+                    self.phase = Phase::Read;
+                    responses.push(self.drive_bit());
+                    self.bits = 1;
+                } else {
+                    responses.push(self.drive_sda(false));
+                }
+                self.stretch(&mut responses);
+            }
+            _ if self.bits == 8 && self.receive() => {
+                self.ack = true;
+                responses.push(self.drive_sda(true));
+            }
+            _ => {}
+        }
+        responses
+    }
+
+    fn stretch(&mut self, responses: &mut Vec<Response>) {
+        if self.stretch.is_empty() {
+            return;
+        }
+        let stretch = self.stretch[self.stretches % self.stretch.len()];
+        self.stretches += 1;
+        if stretch.0 > 0 {
+            responses.push((self.scl_signal, PinState::Low, Nanoseconds(0)));
+            responses.push((self.scl_signal, PinState::Floating, stretch));
+        }
+    }
+}
+
+impl SimDevice for I2cEeprom {
+    fn on_edge(&mut self, signal: Signal, edge: Edge, _t: Nanoseconds<u64>) -> Vec<Response> {
+        if signal == self.sda_signal && self.scl.is_high().unwrap() {
+            match edge {
+                Edge::Falling => {
+                    // (repeated) start condition
+                    self.phase = Phase::Address;
+                    self.bits = 0;
+                    self.byte = 0;
+                    self.ack = false;
+                }
+                _ => {
+                    // stop condition
+                    self.phase = Phase::Idle;
+                }
+            }
+            vec![]
+        } else if signal == self.scl_signal {
+            match edge {
+                Edge::Falling => self.on_scl_falling(),
+                _ => {
+                    self.on_scl_rising();
+                    vec![]
+                }
+            }
+        } else {
+            vec![]
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::devices::Simulation;
+    use crate::writer::VcdWriterBuilder;
+    use embedded_hal::digital::OutputPin;
+
+    /// A bit banged I2C master with a quarter clock period of 1 µs.
+    struct Master<'a> {
+        sim: &'a mut Simulation,
+        scl: BusPin,
+        sda: BusPin,
+        t: u64,
+    }
+
+    impl<'a> Master<'a> {
+        fn wait(&mut self) {
+            self.t += 1000;
+            self.sim.advance_to(Nanoseconds(self.t));
+        }
+
+        fn scl_high(&mut self) {
+            self.scl.set_high().unwrap();
+            self.wait();
+            while self.scl.is_low().unwrap() {
+                self.wait();
+            }
+        }
+
+        fn start(&mut self) {
+            self.sda.set_high().unwrap();
+            self.scl_high();
+            self.sda.set_low().unwrap();
+            self.wait();
+            self.scl.set_low().unwrap();
+            self.wait();
+        }
+
+        fn stop(&mut self) {
+            self.sda.set_low().unwrap();
+            self.wait();
+            self.scl_high();
+            self.sda.set_high().unwrap();
+            self.wait();
+        }
+
+        fn write_bit(&mut self, bit: bool) {
+            if bit {
+                self.sda.set_high().unwrap();
+            } else {
+                self.sda.set_low().unwrap();
+            }
+            self.wait();
+            self.scl_high();
+            self.scl.set_low().unwrap();
+            self.wait();
+        }
+
+        fn read_bit(&mut self) -> bool {
+            self.sda.set_high().unwrap();
+            self.wait();
+            self.scl_high();
+            let bit = self.sda.is_high().unwrap();
+            self.scl.set_low().unwrap();
+            self.wait();
+            bit
+        }
+
+        fn write(&mut self, byte: u8) -> bool {
+            for i in (0..8).rev() {
+                self.write_bit(byte & (1 << i) != 0);
+            }
+            !self.read_bit()
+        }
+
+        fn read(&mut self, ack: bool) -> u8 {
+            let byte = (0..8).fold(0, |byte, _| (byte << 1) | self.read_bit() as u8);
+            self.write_bit(!ack);
+            byte
+        }
+    }
+
+    #[test]
+    fn write_read() {
+        let mut builder = VcdWriterBuilder::new(std::io::sink()).unwrap();
+        let mut sim = Simulation::new();
+        let sda = sim
+            .add_recorded_bus(&mut builder, "sda", PinState::High)
+            .unwrap();
+        let scl = sim
+            .add_recorded_bus(&mut builder, "scl", PinState::High)
+            .unwrap();
+        let eeprom = I2cEeprom::new(sim.input_pin(sda), sim.input_pin(scl), sda, scl, 0x50, 256)
+            .with_clock_stretching(Nanoseconds(5000));
+        let memory = eeprom.memory();
+        sim.add_device(eeprom);
+        let mut master = Master {
+            scl: sim.bus_pin(scl),
+            sda: sim.bus_pin(sda),
+            sim: &mut sim,
+            t: 0,
+        };
+
+        master.start();
+        assert!(master.write(0xA0));
+        assert!(master.write(0x10));
+        assert!(master.write(0x12));
+        assert!(master.write(0x34));
+        master.stop();
+        assert_eq!(&memory.lock().unwrap()[0x10..0x12], &[0x12, 0x34]);
+
+        // wrong address is not acknowledged
+        master.start();
+        assert!(!master.write(0xA2));
+        master.stop();
+
+        // random read
+        master.start();
+        assert!(master.write(0xA0));
+        assert!(master.write(0x10));
+        master.start();
+        assert!(master.write(0xA1));
+        assert_eq!(master.read(true), 0x12);
+        assert_eq!(master.read(true), 0x34);
+        assert_eq!(master.read(false), 0xFF);
+        master.stop();
+
+        // the address counter rolls over after the last byte is read
+        master.start();
+        assert!(master.write(0xA0));
+        assert!(master.write(0xFF));
+        master.start();
+        assert!(master.write(0xA1));
+        assert_eq!(master.read(false), 0xFF);
+        master.stop();
+        memory.lock().unwrap()[0] = 0x42;
+        master.start();
+        assert!(master.write(0xA1));
+        assert_eq!(master.read(false), 0x42);
+        master.stop();
+    }
+
+    #[test]
+    #[should_panic(expected = "page size must not be zero")]
+    fn zero_page_size() {
+        let mut sim = Simulation::new();
+        let sda = sim.add_bus(Arc::new(Bus::new(PinState::High)));
+        let scl = sim.add_bus(Arc::new(Bus::new(PinState::High)));
+        I2cEeprom::new(sim.input_pin(sda), sim.input_pin(scl), sda, scl, 0x50, 256)
+            .with_page_size(0);
+    }
+}
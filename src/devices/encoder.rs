@@ -0,0 +1,278 @@
+//! Position encoders driven by a position profile.
+
+use super::{Edge, Response, Signal, SimDevice, Simulation};
+use crate::pins::*;
+use embedded_time::duration::*;
+
+/// A position over time, linearly interpolated between points.
+#[derive(Clone, Debug, Default)]
+pub struct PositionProfile {
+    points: Vec<(u64, i64)>,
+}
+
+impl PositionProfile {
+    /// Create a new profile that starts at position 0.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Reach `position` at time `at`.
+    ///
+    /// Points have to be added in chronological order. The position before
+    /// the first point is the position of the first point.
+    pub fn with_point(mut self, at: Nanoseconds<u64>, position: i64) -> Self {
+        self.points.push((at.0, position));
+        self
+    }
+
+    /// Return the position at time `t`, rounded towards the previous point.
+    pub fn position_at(&self, t: Nanoseconds<u64>) -> i64 {
+        let index = self.points.partition_point(|(time, _)| *time <= t.0);
+        match (index.checked_sub(1), self.points.get(index)) {
+            (None, Some((_, p))) => *p,
+            (None, None) => 0,
+            (Some(i), None) => self.points[i].1,
+            (Some(i), Some((t1, p1))) => {
+                let (t0, p0) = self.points[i];
+                let dt = (t.0 - t0) as i128;
+                let delta = (*p1 - p0) as i128 * dt / (*t1 - t0) as i128;
+                p0 + delta as i64
+            }
+        }
+    }
+
+    /// Return the times and positions of all single count changes.
+    fn counts(&self) -> Vec<(u64, i64)> {
+        let mut counts = vec![];
+        for w in self.points.windows(2) {
+            let ((t0, p0), (t1, p1)) = (w[0], w[1]);
+            let steps = (p1 - p0).unsigned_abs();
+            for k in 1..=steps {
+                let t = t0 + ((t1 - t0) as u128 * k as u128 / steps as u128) as u64;
+                counts.push((t, p0 + (p1 - p0).signum() * k as i64));
+            }
+        }
+        counts
+    }
+}
+
+/// An incremental encoder with quadrature A/B outputs.
+///
+/// Each count is one quadrature state change (X4 decoding), A leads B when
+/// moving forward. The outputs are scheduled in advance on a [Simulation]
+/// with [play](QuadratureEncoder::play).
+pub struct QuadratureEncoder {
+    a: Signal,
+    b: Signal,
+}
+
+impl QuadratureEncoder {
+    /// Create a new encoder that drives the signals `a` and `b`.
+    pub fn new(a: Signal, b: Signal) -> Self {
+        QuadratureEncoder { a, b }
+    }
+
+    fn states(position: i64) -> (PinState, PinState) {
+        use PinState::*;
+        match position.rem_euclid(4) {
+            0 => (Low, Low),
+            1 => (High, Low),
+            2 => (High, High),
+            _ => (Low, High),
+        }
+    }
+
+    /// Schedule the output signals for a position profile.
+    pub fn play(&self, sim: &mut Simulation, profile: &PositionProfile) {
+        let start = profile.points.first().map(|(t, _)| *t).unwrap_or(0);
+        let mut last = Self::states(profile.position_at(Nanoseconds(start)));
+        sim.schedule(self.a, last.0.clone(), Nanoseconds(start));
+        sim.schedule(self.b, last.1.clone(), Nanoseconds(start));
+        for (t, position) in profile.counts() {
+            let (a, b) = Self::states(position);
+            if a != last.0 {
+                sim.schedule(self.a, a.clone(), Nanoseconds(t));
+            }
+            if b != last.1 {
+                sim.schedule(self.b, b.clone(), Nanoseconds(t));
+            }
+            last = (a, b);
+        }
+    }
+}
+
+/// An absolute encoder with a synchronous serial interface (SSI).
+///
+/// The master idles with the clock high. The first falling clock edge of a
+/// frame latches the position, with each rising clock edge the encoder
+/// shifts out the next bit (MSB first), so the master reads it before the
+/// next falling edge. The data line returns high after the monoflop timeout
+/// at the end of the frame and a new frame starts once the clock was idle
+/// for the timeout.
+pub struct SsiEncoder {
+    clock: Signal,
+    data: Signal,
+    profile: PositionProfile,
+    bits: u8,
+    gray: bool,
+    timeout: u64,
+    last_edge: Option<u64>,
+    frame: Option<(u64, u8)>,
+}
+
+impl SsiEncoder {
+    /// Create a new encoder with `bits` bits of resolution.
+    ///
+    /// Positions are sent in binary, the monoflop timeout is 20 µs.
+    pub fn new(clock: Signal, data: Signal, bits: u8, profile: PositionProfile) -> Self {
+        SsiEncoder {
+            clock,
+            data,
+            profile,
+            bits,
+            gray: false,
+            timeout: 20_000,
+            last_edge: None,
+            frame: None,
+        }
+    }
+
+    /// Send positions gray coded.
+    pub fn with_gray_code(mut self) -> Self {
+        self.gray = true;
+        self
+    }
+
+    /// Set the monoflop timeout.
+    pub fn with_timeout(mut self, timeout: Nanoseconds<u64>) -> Self {
+        self.timeout = timeout.0;
+        self
+    }
+}
+
+impl SimDevice for SsiEncoder {
+    fn on_edge(&mut self, signal: Signal, edge: Edge, t: Nanoseconds<u64>) -> Vec<Response> {
+        if signal != self.clock {
+            return vec![];
+        }
+        let idle = self
+            .last_edge
+            .map(|last| t.0 - last >= self.timeout)
+            .unwrap_or(true);
+        self.last_edge = Some(t.0);
+        if idle {
+            self.frame = None;
+        }
+        match (edge, self.frame) {
+            (Edge::Falling, None) if idle => {
+                let mask = (1u64 << self.bits) - 1;
+                let mut value = self.profile.position_at(t).rem_euclid(1 << self.bits) as u64;
+                if self.gray {
+                    value ^= value >> 1;
+                }
+                self.frame = Some((value & mask, 0));
+                vec![]
+            }
+            (Edge::Rising, Some((value, sent))) => {
+                // extra clocks after the last bit read high
+                let state = if sent < self.bits && value & (1 << (self.bits - sent - 1)) == 0 {
+                    PinState::Low
+                } else {
+                    PinState::High
+                };
+                let sent = (sent + 1).min(self.bits);
+                self.frame = Some((value, sent));
+                let mut responses = vec![(self.data, state, Nanoseconds(0))];
+                if sent == self.bits {
+                    responses.push((self.data, PinState::High, Nanoseconds(self.timeout)));
+                }
+                responses
+            }
+            _ => vec![],
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use embedded_hal::digital::{InputPin as _, OutputPin};
+    use std::sync::Arc;
+
+    #[test]
+    fn quadrature() {
+        let mut sim = Simulation::new();
+        let a = sim.add_signal(Arc::new(AtomicPinState::new()));
+        let b = sim.add_signal(Arc::new(AtomicPinState::new()));
+        let profile = PositionProfile::new()
+            .with_point(Nanoseconds(0), 0)
+            .with_point(Nanoseconds(10_000), 10)
+            .with_point(Nanoseconds(20_000), 7);
+        QuadratureEncoder::new(a, b).play(&mut sim, &profile);
+        let (pa, pb) = (sim.input_pin(a), sim.input_pin(b));
+
+        // decode like a firmware polling the inputs every 100 ns
+        let mut position = 0i64;
+        let mut last = (false, false);
+        for t in (0..30_000u64).step_by(100) {
+            sim.advance_to(Nanoseconds(t + 1));
+            let now = (pa.is_high().unwrap(), pb.is_high().unwrap());
+            let code = |(a, b): (bool, bool)| match (a, b) {
+                (false, false) => 0i64,
+                (true, false) => 1,
+                (true, true) => 2,
+                (false, true) => 3,
+            };
+            match (code(now) - code(last)).rem_euclid(4) {
+                1 => position += 1,
+                3 => position -= 1,
+                _ => {}
+            }
+            last = now;
+            if t == 10_000 {
+                assert_eq!(position, 10);
+            }
+        }
+        assert_eq!(position, 7);
+    }
+
+    #[test]
+    fn ssi() {
+        let mut sim = Simulation::new();
+        let clock = sim.add_signal(Arc::new(AtomicPinState::new_with_state(PinState::High)));
+        let data = sim.add_signal(Arc::new(AtomicPinState::new_with_state(PinState::High)));
+        let profile = PositionProfile::new()
+            .with_point(Nanoseconds(0), 1000)
+            .with_point(Nanoseconds(1_000_000), 2000);
+        sim.add_device(SsiEncoder::new(clock, data, 13, profile).with_gray_code());
+        let mut clk = sim.push_pull_pin(clock);
+        let input = sim.input_pin(data);
+
+        let mut t = 500_000u64;
+        sim.advance_to(Nanoseconds(t));
+        let mut read = || {
+            let mut value = 0u64;
+            for _ in 0..13 {
+                clk.set_low().unwrap();
+                t += 500;
+                sim.advance_to(Nanoseconds(t));
+                clk.set_high().unwrap();
+                t += 500;
+                sim.advance_to(Nanoseconds(t));
+                value = (value << 1) | input.is_high().unwrap() as u64;
+            }
+            t += 30_000;
+            sim.advance_to(Nanoseconds(t));
+            // decode gray code
+            let mut binary = value;
+            let mut shift = value >> 1;
+            while shift != 0 {
+                binary ^= shift;
+                shift >>= 1;
+            }
+            binary
+        };
+        assert_eq!(read(), 1500);
+        assert_eq!(read(), 1543);
+    }
+}
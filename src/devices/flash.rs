@@ -0,0 +1,357 @@
+//! A W25Q-style SPI NOR flash.
+
+use super::{Edge, Response, Signal, SimDevice};
+use crate::pins::*;
+use embedded_hal::digital::InputPin as _;
+use embedded_time::duration::*;
+use std::sync::{Arc, Mutex};
+
+const WRITE_ENABLE: u8 = 0x06;
+const WRITE_DISABLE: u8 = 0x04;
+const READ_STATUS: u8 = 0x05;
+const READ_DATA: u8 = 0x03;
+const PAGE_PROGRAM: u8 = 0x02;
+const SECTOR_ERASE: u8 = 0x20;
+const JEDEC_ID: u8 = 0x9F;
+
+const STATUS_BUSY: u8 = 1 << 0;
+const STATUS_WEL: u8 = 1 << 1;
+
+const PAGE_SIZE: usize = 256;
+const SECTOR_SIZE: usize = 4096;
+
+/// A simulated W25Q-style SPI NOR flash.
+///
+/// The flash is attached to SCLK, MOSI, MISO and an active low CS signal and
+/// uses SPI mode 0: MOSI is sampled on rising clock edges, MISO is changed
+/// on falling clock edges. MISO is released while no data is shifted out.
+///
+/// Supported commands are write enable (`0x06`), write disable (`0x04`),
+/// read status register 1 (`0x05`), read data (`0x03`), page program
+/// (`0x02`), 4 KiB sector erase (`0x20`) and JEDEC ID (`0x9F`), all with
+/// 24-bit addresses. Page program and sector erase set the BUSY bit for a
+/// configurable time in virtual time, commands other than reading the
+/// status register are ignored while busy.
+pub struct SpiFlash {
+    mosi: InputPin,
+    sclk: Signal,
+    cs: Signal,
+    miso: Signal,
+    jedec_id: [u8; 3],
+    memory: Arc<Mutex<Vec<u8>>>,
+    program_time: Nanoseconds<u64>,
+    erase_time: Nanoseconds<u64>,
+    busy_until: u64,
+    write_enabled: bool,
+    selected: bool,
+    command: Option<u8>,
+    /// The number of bytes received in the current transaction.
+    bytes: usize,
+    /// The number of bits received in the current byte.
+    bits: u8,
+    input: u8,
+    /// The byte that is currently shifted out, if any.
+    output: Option<u8>,
+    address: usize,
+    page: Vec<(usize, u8)>,
+}
+
+impl SpiFlash {
+    /// Create a new flash with `size` bytes of memory.
+    ///
+    /// `mosi` is used to read the data input, `miso` is driven by the flash.
+    /// The memory is erased (all bytes `0xFF`), the JEDEC ID is the one of a
+    /// W25Q32 (`EF 40 16`), page program takes 700 µs and sector erase 45 ms.
+    pub fn new(mosi: InputPin, sclk: Signal, cs: Signal, miso: Signal, size: usize) -> Self {
+        SpiFlash {
+            mosi,
+            sclk,
+            cs,
+            miso,
+            jedec_id: [0xEF, 0x40, 0x16],
+            memory: Arc::new(Mutex::new(vec![0xFF; size])),
+            program_time: Nanoseconds(700_000),
+            erase_time: Nanoseconds(45_000_000),
+            busy_until: 0,
+            write_enabled: false,
+            selected: false,
+            command: None,
+            bytes: 0,
+            bits: 0,
+            input: 0,
+            output: None,
+            address: 0,
+            page: vec![],
+        }
+    }
+
+    /// Set the JEDEC ID (manufacturer, memory type, capacity).
+    pub fn with_jedec_id(mut self, jedec_id: [u8; 3]) -> Self {
+        self.jedec_id = jedec_id;
+        self
+    }
+
+    /// Set the initial memory contents.
+    ///
+    /// The memory is resized to the size of the contents.
+    pub fn with_contents(self, contents: &[u8]) -> Self {
+        *self.memory.lock().unwrap() = contents.to_vec();
+        self
+    }
+
+    /// Set the time a page program keeps the flash busy.
+    pub fn with_program_time(mut self, program_time: Nanoseconds<u64>) -> Self {
+        self.program_time = program_time;
+        self
+    }
+
+    /// Set the time a sector erase keeps the flash busy.
+    pub fn with_erase_time(mut self, erase_time: Nanoseconds<u64>) -> Self {
+        self.erase_time = erase_time;
+        self
+    }
+
+    /// Return a handle to the memory, to inspect it after a test.
+    pub fn memory(&self) -> Arc<Mutex<Vec<u8>>> {
+        self.memory.clone()
+    }
+
+    fn status(&self, t: u64) -> u8 {
+        let mut status = 0;
+        if t < self.busy_until {
+            status |= STATUS_BUSY;
+        }
+        if self.write_enabled {
+            status |= STATUS_WEL;
+        }
+        status
+    }
+
+    fn read(&mut self) -> u8 {
+        let memory = self.memory.lock().unwrap();
+        let byte = memory.get(self.address).copied().unwrap_or(0xFF);
+        self.address = (self.address + 1) % memory.len().max(1);
+        byte
+    }
+
+    /// Handle a received byte, returns the next byte to shift out.
+    fn receive(&mut self, byte: u8, t: u64) -> Option<u8> {
+        let index = self.bytes;
+        self.bytes += 1;
+        if index == 0 {
+            if t < self.busy_until && byte != READ_STATUS {
+                return None;
+            }
+            self.command = Some(byte);
+        }
+        match (self.command?, index) {
+            (WRITE_ENABLE, 0) => {
+                self.write_enabled = true;
+                None
+            }
+            (WRITE_DISABLE, 0) => {
+                self.write_enabled = false;
+                None
+            }
+            (READ_STATUS, _) => Some(self.status(t)),
+            (JEDEC_ID, i) => self.jedec_id.get(i).copied(),
+            (_, 0) => None,
+            (READ_DATA | PAGE_PROGRAM | SECTOR_ERASE, 1..=3) => {
+                self.address = ((self.address << 8) | byte as usize) & 0xFF_FFFF;
+                if (self.command, index) == (Some(READ_DATA), 3) {
+                    Some(self.read())
+                } else {
+                    None
+                }
+            }
+            (READ_DATA, _) => Some(self.read()),
+            (PAGE_PROGRAM, _) => {
+                let page = self.address - self.address % PAGE_SIZE;
+                let offset = (self.address + self.page.len()) % PAGE_SIZE;
+                self.page.push((page + offset, byte));
+                None
+            }
+            _ => None,
+        }
+    }
+
+    /// Execute program and erase commands at the end of a transaction.
+    fn finish(&mut self, t: u64) {
+        if !self.write_enabled {
+            return;
+        }
+        let mut memory = self.memory.lock().unwrap();
+        match (self.command, self.bytes) {
+            (Some(PAGE_PROGRAM), 5..) => {
+                for (address, byte) in self.page.iter() {
+                    if let Some(cell) = memory.get_mut(*address) {
+                        // programming can only clear bits
+                        *cell &= byte;
+                    }
+                }
+                self.busy_until = t + self.program_time.0;
+            }
+            (Some(SECTOR_ERASE), 4) => {
+                let sector = self.address - self.address % SECTOR_SIZE;
+                let end = (sector + SECTOR_SIZE).min(memory.len());
+                for cell in memory.iter_mut().take(end).skip(sector) {
+                    *cell = 0xFF;
+                }
+                self.busy_until = t + self.erase_time.0;
+            }
+            _ => return,
+        }
+        self.write_enabled = false;
+    }
+
+    fn drive_miso(&self) -> Response {
+        let state = match self.output {
+            Some(byte) if byte & (0x80 >> self.bits) != 0 => PinState::High,
+            Some(_) => PinState::Low,
+            None => PinState::Floating,
+        };
+        (self.miso, state, Nanoseconds(0))
+    }
+}
+
+impl SimDevice for SpiFlash {
+    fn on_edge(&mut self, signal: Signal, edge: Edge, t: Nanoseconds<u64>) -> Vec<Response> {
+        if signal == self.cs {
+            if edge == Edge::Falling {
+                self.selected = true;
+                self.command = None;
+                self.bytes = 0;
+                self.bits = 0;
+                self.input = 0;
+                self.output = None;
+                self.address = 0;
+                self.page.clear();
+            } else if self.selected {
+                self.selected = false;
+                self.finish(t.0);
+                self.output = None;
+                return vec![self.drive_miso()];
+            }
+        } else if signal == self.sclk && self.selected {
+            match edge {
+                Edge::Rising => {
+                    self.input = (self.input << 1) | self.mosi.is_high().unwrap() as u8;
+                    self.bits += 1;
+                    if self.bits == 8 {
+                        self.output = self.receive(self.input, t.0);
+                        self.bits = 0;
+                        self.input = 0;
+                    }
+                }
+                _ => return vec![self.drive_miso()],
+            }
+        }
+        vec![]
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::devices::Simulation;
+    use crate::writer::VcdWriterBuilder;
+    use embedded_hal::digital::OutputPin;
+
+    /// A bit banged SPI master in mode 0 with a clock period of 1 µs.
+    struct Master<'a> {
+        sim: &'a mut Simulation,
+        sclk: PushPullPin,
+        mosi: PushPullPin,
+        cs: PushPullPin,
+        miso: InputPin,
+        t: u64,
+    }
+
+    impl<'a> Master<'a> {
+        fn wait(&mut self) {
+            self.t += 500;
+            self.sim.advance_to(Nanoseconds(self.t));
+        }
+
+        fn transfer(&mut self, data: &mut [u8]) {
+            self.cs.set_low().unwrap();
+            self.wait();
+            for byte in data.iter_mut() {
+                let mut read = 0;
+                for bit in (0..8).rev() {
+                    if *byte & (1 << bit) != 0 {
+                        self.mosi.set_high().unwrap();
+                    } else {
+                        self.mosi.set_low().unwrap();
+                    }
+                    self.wait();
+                    self.sclk.set_high().unwrap();
+                    read = (read << 1) | self.miso.is_high().unwrap() as u8;
+                    self.wait();
+                    self.sclk.set_low().unwrap();
+                }
+                *byte = read;
+            }
+            self.wait();
+            self.cs.set_high().unwrap();
+            self.wait();
+        }
+    }
+
+    #[test]
+    fn program_read() {
+        let mut builder = VcdWriterBuilder::new(std::io::sink()).unwrap();
+        let mut sim = Simulation::new();
+        let sclk = sim
+            .add_recorded_signal(&mut builder, "sclk", PinState::Low)
+            .unwrap();
+        let mosi = sim
+            .add_recorded_signal(&mut builder, "mosi", PinState::Low)
+            .unwrap();
+        let miso = sim
+            .add_recorded_signal(&mut builder, "miso", PinState::Floating)
+            .unwrap();
+        let cs = sim
+            .add_recorded_signal(&mut builder, "cs", PinState::High)
+            .unwrap();
+        let flash = SpiFlash::new(sim.input_pin(mosi), sclk, cs, miso, 64 * 1024)
+            .with_program_time(Nanoseconds(100_000));
+        let memory = flash.memory();
+        sim.add_device(flash);
+        let mut master = Master {
+            sclk: sim.push_pull_pin(sclk),
+            mosi: sim.push_pull_pin(mosi),
+            cs: sim.push_pull_pin(cs),
+            miso: sim.input_pin(miso),
+            sim: &mut sim,
+            t: 0,
+        };
+
+        let mut id = [JEDEC_ID, 0, 0, 0];
+        master.transfer(&mut id);
+        assert_eq!(&id[1..], &[0xEF, 0x40, 0x16]);
+
+        // programming without write enable is ignored
+        master.transfer(&mut [PAGE_PROGRAM, 0x00, 0x01, 0x00, 0x00]);
+        assert_eq!(memory.lock().unwrap()[0x100], 0xFF);
+
+        master.transfer(&mut [WRITE_ENABLE]);
+        master.transfer(&mut [PAGE_PROGRAM, 0x00, 0x01, 0xFE, 0x12, 0x34, 0x56]);
+        let mut polls = 0;
+        loop {
+            let mut status = [READ_STATUS, 0];
+            master.transfer(&mut status);
+            if status[1] & STATUS_BUSY == 0 {
+                break;
+            }
+            polls += 1;
+        }
+        assert!(polls > 0);
+
+        let mut data = [READ_DATA, 0x00, 0x01, 0xFE, 0, 0];
+        master.transfer(&mut data);
+        assert_eq!(&data[4..], &[0x12, 0x34]);
+        // page program wraps around at the page boundary
+        assert_eq!(memory.lock().unwrap()[0x100], 0x56);
+    }
+}
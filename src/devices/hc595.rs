@@ -0,0 +1,164 @@
+//! A 74HC595 8-bit shift register with output latches.
+
+use super::{Edge, Response, Signal, SimDevice};
+use crate::pins::*;
+use embedded_hal::digital::InputPin as _;
+use embedded_time::duration::*;
+
+/// A simulated 74HC595 shift register.
+///
+/// On a rising edge of `srclk` the level of `ser` is shifted into the shift
+/// register, on a rising edge of `rclk` the shift register is copied to the
+/// output latches, which drive the parallel outputs `QA` to `QH`.
+/// `QA` is the stage that receives `ser`, so after shifting in a byte MSB
+/// first, `QH` holds the MSB.
+///
+/// The output enable and clear inputs are not modelled, outputs are always
+/// enabled.
+pub struct Hc595 {
+    ser: InputPin,
+    srclk: Signal,
+    rclk: Signal,
+    outputs: [Signal; 8],
+    serial_out: Option<Signal>,
+    shift: u8,
+    latch: u8,
+    delay: Nanoseconds<u64>,
+}
+
+impl Hc595 {
+    /// Create a new shift register.
+    ///
+    /// `ser` is used to read the serial data input, `outputs` are the
+    /// parallel outputs `QA` to `QH`. Shift register and latches start
+    /// cleared.
+    pub fn new(ser: InputPin, srclk: Signal, rclk: Signal, outputs: [Signal; 8]) -> Self {
+        Hc595 {
+            ser,
+            srclk,
+            rclk,
+            outputs,
+            serial_out: None,
+            shift: 0,
+            latch: 0,
+            delay: Nanoseconds(20),
+        }
+    }
+
+    /// Drive the serial output `QH'` on the given signal, e.g. to cascade
+    /// multiple shift registers.
+    pub fn with_serial_output(mut self, signal: Signal) -> Self {
+        self.serial_out = Some(signal);
+        self
+    }
+
+    /// Set the propagation delay from a clock edge to the outputs.
+    ///
+    /// The default is 20 ns.
+    pub fn with_delay(mut self, delay: Nanoseconds<u64>) -> Self {
+        self.delay = delay;
+        self
+    }
+
+    fn level(value: u8, bit: u8) -> PinState {
+        if value & (1 << bit) != 0 {
+            PinState::High
+        } else {
+            PinState::Low
+        }
+    }
+}
+
+impl SimDevice for Hc595 {
+    fn on_edge(&mut self, signal: Signal, edge: Edge, _t: Nanoseconds<u64>) -> Vec<Response> {
+        let mut responses = vec![];
+        if edge != Edge::Rising {
+            return responses;
+        }
+        if signal == self.srclk {
+            let old = self.shift;
+            self.shift = (self.shift << 1) | self.ser.is_high().unwrap() as u8;
+            if let Some(serial_out) = self.serial_out {
+                if (old ^ self.shift) & 0x80 != 0 {
+                    responses.push((serial_out, Self::level(self.shift, 7), self.delay));
+                }
+            }
+        } else if signal == self.rclk {
+            let changed = self.latch ^ self.shift;
+            self.latch = self.shift;
+            for (bit, output) in self.outputs.iter().enumerate() {
+                if changed & (1 << bit) != 0 {
+                    responses.push((*output, Self::level(self.latch, bit as u8), self.delay));
+                }
+            }
+        }
+        responses
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::devices::Simulation;
+    use crate::writer::VcdWriterBuilder;
+    use embedded_hal::digital::OutputPin;
+
+    #[test]
+    fn shift_and_latch() {
+        let mut builder = VcdWriterBuilder::new(std::io::sink()).unwrap();
+        let mut sim = Simulation::new();
+        let ser = sim
+            .add_recorded_signal(&mut builder, "ser", PinState::Low)
+            .unwrap();
+        let srclk = sim
+            .add_recorded_signal(&mut builder, "srclk", PinState::Low)
+            .unwrap();
+        let rclk = sim
+            .add_recorded_signal(&mut builder, "rclk", PinState::Low)
+            .unwrap();
+        let mut outputs = vec![];
+        for i in 0..8 {
+            outputs.push(
+                sim.add_recorded_signal(&mut builder, &format!("q{}", i), PinState::Low)
+                    .unwrap(),
+            );
+        }
+        let outputs: [Signal; 8] = outputs.try_into().unwrap();
+        sim.add_device(Hc595::new(sim.input_pin(ser), srclk, rclk, outputs));
+        let mut writer = builder.build().unwrap();
+
+        let mut ser = sim.push_pull_pin(ser);
+        let mut srclk = sim.push_pull_pin(srclk);
+        let mut rclk = sim.push_pull_pin(rclk);
+        let mut t = 0u64;
+        let mut step = |sim: &mut Simulation| {
+            t += 100;
+            sim.advance_to_recorded(Nanoseconds(t), &mut writer)
+                .unwrap();
+        };
+        for bit in (0..8).rev() {
+            if 0xA5u8 & (1 << bit) != 0 {
+                ser.set_high().unwrap();
+            } else {
+                ser.set_low().unwrap();
+            }
+            step(&mut sim);
+            srclk.set_high().unwrap();
+            step(&mut sim);
+            srclk.set_low().unwrap();
+        }
+        // nothing is latched yet
+        assert!(outputs.iter().all(|q| sim.state(*q) == PinState::Low));
+        rclk.set_high().unwrap();
+        step(&mut sim);
+        step(&mut sim);
+        let value = outputs
+            .iter()
+            .enumerate()
+            .fold(0u8, |v, (bit, q)| match sim.state(*q) {
+                PinState::High => v | (1 << bit),
+                _ => v,
+            });
+        assert_eq!(value, 0xA5);
+    }
+}
@@ -0,0 +1,306 @@
+//! A monitor for the parallel bus of HD44780 character LCDs.
+
+use super::{Edge, Response, Signal, SimDevice};
+use crate::pins::*;
+use embedded_hal::digital::InputPin as _;
+use embedded_time::duration::*;
+use std::sync::{Arc, Mutex};
+
+const DDRAM_SIZE: usize = 0x80;
+
+/// The state of the display controller.
+#[derive(Debug)]
+struct Controller {
+    columns: usize,
+    rows: usize,
+    ddram: [u8; DDRAM_SIZE],
+    address: u8,
+    increment: bool,
+    cgram: bool,
+    four_bit: bool,
+    /// The high nibble in 4-bit mode, if received.
+    nibble: Option<u8>,
+    display_on: bool,
+}
+
+impl Controller {
+    fn command(&mut self, command: u8) {
+        if command & 0x80 != 0 {
+            self.address = command & 0x7F;
+            self.cgram = false;
+        } else if command & 0x40 != 0 {
+            self.cgram = true;
+        } else if command & 0x20 != 0 {
+            self.four_bit = command & 0x10 == 0;
+        } else if command & 0x10 != 0 {
+            // cursor or display shift, only cursor moves are tracked
+            if command & 0x08 == 0 {
+                self.step(command & 0x04 != 0);
+            }
+        } else if command & 0x08 != 0 {
+            self.display_on = command & 0x04 != 0;
+        } else if command & 0x04 != 0 {
+            self.increment = command & 0x02 != 0;
+        } else if command & 0x02 != 0 {
+            self.address = 0;
+            self.cgram = false;
+        } else if command & 0x01 != 0 {
+            self.ddram = [b' '; DDRAM_SIZE];
+            self.address = 0;
+            self.increment = true;
+            self.cgram = false;
+        }
+    }
+
+    fn data(&mut self, data: u8) {
+        if self.cgram {
+            // custom characters are not tracked
+            return;
+        }
+        self.ddram[self.address as usize] = data;
+        self.step(self.increment);
+    }
+
+    fn step(&mut self, increment: bool) {
+        self.address = if increment {
+            (self.address + 1) % DDRAM_SIZE as u8
+        } else {
+            self.address.wrapping_sub(1) % DDRAM_SIZE as u8
+        };
+    }
+
+    fn lines(&self) -> Vec<String> {
+        (0..self.rows)
+            .map(|row| {
+                let start = (row % 2) * 0x40 + (row / 2) * self.columns;
+                self.ddram[start..start + self.columns]
+                    .iter()
+                    .map(|c| *c as char)
+                    .collect()
+            })
+            .collect()
+    }
+}
+
+/// A handle to the reconstructed contents of a display monitored by a
+/// [Hd44780Monitor].
+#[derive(Clone, Debug)]
+pub struct Hd44780Display {
+    controller: Arc<Mutex<Controller>>,
+}
+
+impl Hd44780Display {
+    /// Return the characters shown on each line of the display.
+    pub fn lines(&self) -> Vec<String> {
+        self.controller.lock().unwrap().lines()
+    }
+
+    /// Return whether the display has been switched on.
+    pub fn is_on(&self) -> bool {
+        self.controller.lock().unwrap().display_on
+    }
+
+    /// Assert that the display shows the expected text.
+    ///
+    /// Lines of the expected text are separated by `\n` and compared to the
+    /// display lines with trailing spaces removed. Missing lines are
+    /// expected to be empty.
+    ///
+    /// # Panics
+    ///
+    /// Panics if the display shows a different text.
+    pub fn expect_display(&self, expected: &str) {
+        let lines: Vec<String> = self
+            .lines()
+            .iter()
+            .map(|line| line.trim_end().to_string())
+            .collect();
+        let mut expected: Vec<&str> = expected.split('\n').collect();
+        expected.resize(lines.len().max(expected.len()), "");
+        assert_eq!(
+            lines, expected,
+            "display shows {:?}, expected {:?}",
+            lines, expected
+        );
+    }
+}
+
+/// A monitor for the 4-bit parallel bus of HD44780 character LCDs.
+///
+/// The monitor watches the RS, E and D4 to D7 lines driven by the code under
+/// test and reconstructs the display data RAM. Data is latched on the
+/// falling edge of E. The controller starts in 8-bit mode (with D0 to D3
+/// read as low) and switches to 4-bit mode with a function set command like
+/// the real controller, so the usual initialization sequence is supported.
+/// Reading from the controller is not supported.
+pub struct Hd44780Monitor {
+    rs: InputPin,
+    e: Signal,
+    data: [InputPin; 4],
+    controller: Arc<Mutex<Controller>>,
+}
+
+impl Hd44780Monitor {
+    /// Create a new monitor for a display with 16 columns and 2 rows.
+    ///
+    /// `data` are the data lines D4 to D7.
+    pub fn new(rs: InputPin, e: Signal, data: [InputPin; 4]) -> Self {
+        let controller = Controller {
+            columns: 16,
+            rows: 2,
+            ddram: [b' '; DDRAM_SIZE],
+            address: 0,
+            increment: true,
+            cgram: false,
+            four_bit: false,
+            nibble: None,
+            display_on: false,
+        };
+        Hd44780Monitor {
+            rs,
+            e,
+            data,
+            controller: Arc::new(Mutex::new(controller)),
+        }
+    }
+
+    /// Set the size of the display, e.g. 20 columns and 4 rows.
+    pub fn with_size(self, columns: usize, rows: usize) -> Self {
+        {
+            let mut controller = self.controller.lock().unwrap();
+            controller.columns = columns;
+            controller.rows = rows;
+        }
+        self
+    }
+
+    /// Return a handle to the display contents, to inspect it after a test.
+    pub fn display(&self) -> Hd44780Display {
+        Hd44780Display {
+            controller: self.controller.clone(),
+        }
+    }
+
+    fn nibble(&self) -> u8 {
+        self.data
+            .iter()
+            .enumerate()
+            .fold(0, |n, (bit, pin)| n | (pin.is_high().unwrap() as u8) << bit)
+    }
+}
+
+impl SimDevice for Hd44780Monitor {
+    fn on_edge(&mut self, signal: Signal, edge: Edge, _t: Nanoseconds<u64>) -> Vec<Response> {
+        if signal != self.e || edge != Edge::Falling {
+            return vec![];
+        }
+        let nibble = self.nibble();
+        let rs = self.rs.is_high().unwrap();
+        let mut controller = self.controller.lock().unwrap();
+        let byte = if controller.four_bit {
+            match controller.nibble.take() {
+                Some(high) => (high << 4) | nibble,
+                None => {
+                    controller.nibble = Some(nibble);
+                    return vec![];
+                }
+            }
+        } else {
+            nibble << 4
+        };
+        if rs {
+            controller.data(byte);
+        } else {
+            controller.command(byte);
+        }
+        vec![]
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::devices::Simulation;
+    use embedded_hal::digital::OutputPin;
+
+    /// A bit banged 4-bit LCD interface with an enable pulse of 1 µs.
+    struct Master<'a> {
+        sim: &'a mut Simulation,
+        rs: PushPullPin,
+        e: PushPullPin,
+        data: [PushPullPin; 4],
+        t: u64,
+    }
+
+    impl<'a> Master<'a> {
+        fn wait(&mut self) {
+            self.t += 1000;
+            self.sim.advance_to(Nanoseconds(self.t));
+        }
+
+        fn nibble(&mut self, rs: bool, nibble: u8) {
+            if rs {
+                self.rs.set_high().unwrap();
+            } else {
+                self.rs.set_low().unwrap();
+            }
+            for (bit, pin) in self.data.iter_mut().enumerate() {
+                if nibble & (1 << bit) != 0 {
+                    pin.set_high().unwrap();
+                } else {
+                    pin.set_low().unwrap();
+                }
+            }
+            self.e.set_high().unwrap();
+            self.wait();
+            self.e.set_low().unwrap();
+            self.wait();
+        }
+
+        fn write(&mut self, rs: bool, byte: u8) {
+            self.nibble(rs, byte >> 4);
+            self.nibble(rs, byte & 0x0F);
+        }
+    }
+
+    #[test]
+    fn hello() {
+        let mut sim = Simulation::new();
+        let mut signal = || sim.add_signal(Arc::new(AtomicPinState::new()));
+        let rs = signal();
+        let e = signal();
+        let data = [signal(), signal(), signal(), signal()];
+        let monitor = Hd44780Monitor::new(
+            sim.input_pin(rs),
+            e,
+            data.map(|signal| sim.input_pin(signal)),
+        );
+        let display = monitor.display();
+        sim.add_device(monitor);
+        let mut master = Master {
+            rs: sim.push_pull_pin(rs),
+            e: sim.push_pull_pin(e),
+            data: data.map(|signal| sim.push_pull_pin(signal)),
+            sim: &mut sim,
+            t: 0,
+        };
+
+        // initialization in 8-bit mode and switch to 4-bit mode
+        for nibble in [0x3, 0x3, 0x3, 0x2] {
+            master.nibble(false, nibble);
+        }
+        for command in [0x28, 0x0C, 0x01, 0x06] {
+            master.write(false, command);
+        }
+        for c in "Hello".bytes() {
+            master.write(true, c);
+        }
+        master.write(false, 0xC2);
+        for c in "World".bytes() {
+            master.write(true, c);
+        }
+
+        assert!(display.is_on());
+        display.expect_display("Hello\n  World");
+    }
+}
@@ -0,0 +1,165 @@
+//! A row/column matrix keypad.
+
+use super::{Edge, Response, Signal, SimDevice};
+use crate::pins::*;
+use embedded_hal::digital::InputPin as _;
+use embedded_time::duration::*;
+
+/// A scripted key press.
+#[derive(Clone, Copy, Debug)]
+struct Press {
+    row: usize,
+    column: usize,
+    from: u64,
+    until: u64,
+}
+
+/// A simulated matrix keypad.
+///
+/// The code under test drives the row lines and reads the column lines. A
+/// pressed key connects its row to its column, so a column reads low while a
+/// key in an active (low) row is pressed. Otherwise the column is pulled
+/// high.
+///
+/// Keys are pressed according to a timeline given with
+/// [with_press](Keypad::with_press). The columns are updated whenever a row
+/// line changes, which is when scanning code looks at them, so a press that
+/// starts or ends while the rows stay unchanged becomes visible with the
+/// next row change.
+pub struct Keypad {
+    rows: Vec<InputPin>,
+    columns: Vec<Signal>,
+    presses: Vec<Press>,
+    states: Vec<Option<bool>>,
+}
+
+impl Keypad {
+    /// Create a new keypad with the given row and column lines.
+    ///
+    /// `rows` are used to read the rows driven by the code under test,
+    /// `columns` are driven by the keypad.
+    pub fn new(rows: Vec<InputPin>, columns: Vec<Signal>) -> Self {
+        let states = vec![None; columns.len()];
+        Keypad {
+            rows,
+            columns,
+            presses: vec![],
+            states,
+        }
+    }
+
+    /// Press the key at `row` and `column` at time `at` for `duration`.
+    ///
+    /// # Panics
+    ///
+    /// Panics if the key is outside of the matrix.
+    pub fn with_press(
+        mut self,
+        row: usize,
+        column: usize,
+        at: Nanoseconds<u64>,
+        duration: Nanoseconds<u64>,
+    ) -> Self {
+        assert!(
+            row < self.rows.len() && column < self.columns.len(),
+            "key outside of the keypad matrix"
+        );
+        self.presses.push(Press {
+            row,
+            column,
+            from: at.0,
+            until: at.0 + duration.0,
+        });
+        self
+    }
+
+    /// Return whether the key at `row` and `column` is pressed at time `t`.
+    pub fn is_pressed(&self, row: usize, column: usize, t: Nanoseconds<u64>) -> bool {
+        self.presses.iter().any(|press| {
+            press.row == row && press.column == column && press.from <= t.0 && t.0 < press.until
+        })
+    }
+}
+
+impl SimDevice for Keypad {
+    fn on_edge(&mut self, signal: Signal, _edge: Edge, t: Nanoseconds<u64>) -> Vec<Response> {
+        if self.columns.contains(&signal) {
+            return vec![];
+        }
+        let active: Vec<bool> = self.rows.iter().map(|row| row.is_low().unwrap()).collect();
+        let mut responses = vec![];
+        for column in 0..self.columns.len() {
+            let low = active
+                .iter()
+                .enumerate()
+                .any(|(row, active)| *active && self.is_pressed(row, column, t));
+            if self.states[column] != Some(low) {
+                self.states[column] = Some(low);
+                let state = if low { PinState::Low } else { PinState::High };
+                responses.push((self.columns[column], state, Nanoseconds(0)));
+            }
+        }
+        responses
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::devices::Simulation;
+    use embedded_hal::digital::OutputPin;
+    use std::sync::Arc;
+
+    #[test]
+    fn scan() {
+        let mut sim = Simulation::new();
+        let rows: Vec<Signal> = (0..4)
+            .map(|_| sim.add_signal(Arc::new(AtomicPinState::new())))
+            .collect();
+        let columns: Vec<Signal> = (0..3)
+            .map(|_| sim.add_signal(Arc::new(AtomicPinState::new())))
+            .collect();
+        let keypad = Keypad::new(
+            rows.iter().map(|row| sim.input_pin(*row)).collect(),
+            columns.clone(),
+        )
+        .with_press(1, 2, Nanoseconds(10_000), Nanoseconds(40_000))
+        .with_press(3, 0, Nanoseconds(30_000), Nanoseconds(40_000));
+        sim.add_device(keypad);
+        let mut row_pins: Vec<PushPullPin> =
+            rows.iter().map(|row| sim.push_pull_pin(*row)).collect();
+        let column_pins: Vec<InputPin> = columns.iter().map(|c| sim.input_pin(*c)).collect();
+
+        // scan the keypad every 10 µs, each row is driven low for 1 µs
+        let mut t = 0;
+        let mut scans = vec![];
+        while t < 100_000 {
+            let mut pressed = vec![];
+            for row in 0..row_pins.len() {
+                for (i, pin) in row_pins.iter_mut().enumerate() {
+                    if i == row {
+                        pin.set_low().unwrap();
+                    } else {
+                        pin.set_high().unwrap();
+                    }
+                }
+                t += 1000;
+                sim.advance_to(Nanoseconds::<u64>(t));
+                for (column, pin) in column_pins.iter().enumerate() {
+                    if pin.is_low().unwrap() {
+                        pressed.push((row, column));
+                    }
+                }
+            }
+            scans.push(pressed);
+            t += 6000;
+            sim.advance_to(Nanoseconds::<u64>(t));
+        }
+
+        assert_eq!(scans[0], vec![]);
+        assert_eq!(scans[1], vec![(1, 2)]);
+        assert_eq!(scans[3], vec![(1, 2), (3, 0)]);
+        assert_eq!(scans[5], vec![(3, 0)]);
+        assert_eq!(scans[8], vec![]);
+    }
+}
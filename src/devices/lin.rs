@@ -0,0 +1,211 @@
+//! A LIN slave replaying recorded responses.
+
+use super::{Edge, Response, Signal, SimDevice};
+use crate::analysis::{lin_pid, LinFrame, Uart, UartByte, UartEvent, Waveform};
+use crate::pins::*;
+use embedded_time::duration::*;
+use fnv::FnvHashMap;
+use std::collections::VecDeque;
+
+/// A LIN slave that answers headers of the master with recorded responses.
+///
+/// The bus should be a bus signal pulled high (see
+/// [Simulation::add_bus](super::Simulation::add_bus)), the slave pulls it
+/// low for dominant bits. A header is a break of at least 11 bits, the sync
+/// byte and the protected identifier. The responses of each identifier are
+/// replayed in the order they were recorded, one bit time after the
+/// identifier. Headers with a wrong parity or without a response left are
+/// not answered.
+///
+/// To get notified at the end of the identifier, which may end without an
+/// edge, the slave toggles the otherwise unused signal `timer`.
+pub struct LinSlave {
+    bus: Signal,
+    timer: Signal,
+    baud: u32,
+    responses: FnvHashMap<u8, VecDeque<Vec<u8>>>,
+    last_fall: Option<u64>,
+    header: Option<(Option<u64>, Option<u64>)>,
+    changes: Vec<(Nanoseconds<u64>, PinState)>,
+    timer_high: bool,
+}
+
+impl LinSlave {
+    /// Create a new slave for the given baud rate that replays the responses
+    /// of recorded frames, e.g. decoded with a
+    /// [LinDecoder](crate::analysis::LinDecoder).
+    pub fn new(bus: Signal, timer: Signal, baud: u32, frames: &[LinFrame]) -> Self {
+        let mut responses: FnvHashMap<u8, VecDeque<Vec<u8>>> = FnvHashMap::default();
+        for frame in frames.iter().filter(|frame| frame.checksum.is_some()) {
+            responses
+                .entry(frame.id())
+                .or_default()
+                .push_back(frame.response());
+        }
+        LinSlave {
+            bus,
+            timer,
+            baud,
+            responses,
+            last_fall: None,
+            header: None,
+            changes: vec![],
+            timer_high: false,
+        }
+    }
+
+    /// Return the time of `bits` bits.
+    fn bits(&self, bits: u64) -> u64 {
+        bits * 1_000_000_000 / self.baud as u64
+    }
+
+    /// Answer the header whose identifier was received completely.
+    fn respond(&mut self, t: u64) -> Vec<Response> {
+        let uart = Uart::new(self.baud);
+        let pid = match uart.decode(&Waveform::new(self.changes.clone())).first() {
+            Some(UartEvent::Byte(byte)) if byte.stop => byte.value,
+            _ => return vec![],
+        };
+        let response = match self.responses.get_mut(&(pid & 0x3f)) {
+            Some(responses) if lin_pid(pid) == pid => responses.pop_front(),
+            _ => None,
+        };
+        let mut events = vec![];
+        let mut time = Nanoseconds(t + self.bits(1));
+        for value in response.into_iter().flatten() {
+            events.push(UartEvent::Byte(UartByte {
+                time,
+                value,
+                stop: true,
+            }));
+            time = uart.byte_end(time);
+        }
+        uart.encode(&events)
+            .changes()
+            .iter()
+            .skip(1)
+            .map(|(at, state)| {
+                let state = match state {
+                    PinState::Low => PinState::Low,
+                    _ => PinState::Floating,
+                };
+                (self.bus, state, Nanoseconds(at.0 - t))
+            })
+            .collect()
+    }
+}
+
+impl SimDevice for LinSlave {
+    fn on_edge(&mut self, signal: Signal, edge: Edge, t: Nanoseconds<u64>) -> Vec<Response> {
+        if signal == self.timer {
+            self.header = None;
+            return self.respond(t.0);
+        }
+        if signal != self.bus {
+            return vec![];
+        }
+        self.changes.push((t, edge.state()));
+        match edge {
+            Edge::Falling => {
+                self.last_fall = Some(t.0);
+                match self.header {
+                    Some((None, None)) => self.header = Some((Some(t.0), None)),
+                    Some((Some(sync), None)) if t.0 * 2 >= sync * 2 + self.bits(19) => {
+                        self.header = Some((Some(sync), Some(t.0)));
+                        self.changes = vec![(t, PinState::Low)];
+                        self.timer_high = !self.timer_high;
+                        let state = if self.timer_high {
+                            PinState::High
+                        } else {
+                            PinState::Low
+                        };
+                        return vec![(self.timer, state, Nanoseconds(self.bits(10)))];
+                    }
+                    _ => {}
+                }
+            }
+            _ => {
+                let low = self.last_fall.map(|fall| t.0 - fall).unwrap_or(0);
+                if low >= self.bits(11) {
+                    self.header = Some((None, None));
+                    self.changes.clear();
+                }
+            }
+        }
+        vec![]
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::analysis::{LinChecksum, LinDecoder};
+    use crate::devices::Simulation;
+    use embedded_hal::digital::InputPin as _;
+    use std::sync::Arc;
+
+    #[test]
+    fn replay_responses() {
+        let baud = 19_200;
+        let uart = Uart::new(baud);
+        let pid = lin_pid(0x10);
+        let data = vec![0x12, 0x34];
+        let recorded = [LinFrame {
+            pid,
+            checksum: Some(LinChecksum::Enhanced.compute(pid, &data)),
+            data,
+            ..LinFrame::default()
+        }];
+
+        let mut sim = Simulation::new();
+        let bus = sim.add_bus(Arc::new(Bus::new(PinState::High)));
+        let timer = sim.add_signal(Arc::new(AtomicPinState::new()));
+        sim.add_device(LinSlave::new(bus, timer, baud, &recorded));
+        // two headers of the master with a 13 bit break, the second one
+        // has no recorded response left
+        let mut events = vec![];
+        for start in [1_000_000, 20_000_000] {
+            events.push(UartEvent::Break {
+                time: Nanoseconds(start),
+                width: Nanoseconds(13 * 52_083),
+            });
+            let sync = Nanoseconds(start + 14 * 52_083);
+            let pid_start = uart.byte_end(sync);
+            for (time, value) in [(sync, 0x55), (pid_start, pid)] {
+                events.push(UartEvent::Byte(UartByte {
+                    time,
+                    value,
+                    stop: true,
+                }));
+            }
+        }
+        for (t, state) in uart.encode(&events).changes() {
+            let state = match state {
+                PinState::Low => PinState::Low,
+                _ => PinState::Floating,
+            };
+            sim.schedule(bus, state, *t);
+        }
+
+        let line = sim.input_pin(bus);
+        let mut changes = vec![];
+        for t in (0..40_000_000u64).step_by(1000) {
+            sim.advance_to(Nanoseconds(t + 1));
+            let state = if line.is_high().unwrap() {
+                PinState::High
+            } else {
+                PinState::Low
+            };
+            changes.push((Nanoseconds(t), state));
+        }
+        let frames = LinDecoder::new(baud).decode(&Waveform::new(changes));
+        assert_eq!(frames.len(), 2);
+        assert!(frames
+            .iter()
+            .all(|frame| frame.parity() && frame.id() == 0x10));
+        assert_eq!(frames[0].data, recorded[0].data);
+        assert!(frames[0].checksum_valid(LinChecksum::Enhanced));
+        assert!(!frames[0].checksum_valid(LinChecksum::Classic));
+        assert_eq!(frames[1].checksum, None);
+    }
+}
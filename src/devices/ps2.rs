@@ -0,0 +1,140 @@
+//! A PS/2 keyboard sending scripted scancodes.
+
+use super::{Signal, Simulation};
+use crate::analysis::Ps2Key;
+use crate::pins::*;
+use embedded_time::duration::*;
+
+/// A PS/2 keyboard that sends bytes to the host.
+///
+/// The keyboard pulls the open collector clock and data lines low and
+/// releases them, so both should be buses pulled high (see
+/// [Simulation::add_bus]). The data line changes in the middle of the high
+/// clock phase, so the host samples it on the falling clock edges. The
+/// frames are scheduled in advance with [send](Ps2Keyboard::send) or
+/// [play](Ps2Keyboard::play), host requests and inhibits are not modelled.
+pub struct Ps2Keyboard {
+    clock: Signal,
+    data: Signal,
+    period: u64,
+}
+
+impl Ps2Keyboard {
+    /// Create a new keyboard with a clock of 12.5 kHz (80 µs per bit).
+    pub fn new(clock: Signal, data: Signal) -> Self {
+        Ps2Keyboard {
+            clock,
+            data,
+            period: 80_000,
+        }
+    }
+
+    /// Set the clock period.
+    pub fn with_bit_period(mut self, period: Nanoseconds<u64>) -> Self {
+        self.period = period.0;
+        self
+    }
+
+    /// Schedule `bytes` starting at `at` and return the time the lines are
+    /// idle again.
+    ///
+    /// Bytes are separated by two bit periods.
+    pub fn send(
+        &self,
+        sim: &mut Simulation,
+        at: Nanoseconds<u64>,
+        bytes: &[u8],
+    ) -> Nanoseconds<u64> {
+        let level = |high: bool| {
+            if high {
+                PinState::Floating
+            } else {
+                PinState::Low
+            }
+        };
+        let quarter = self.period / 4;
+        let mut t = at.0;
+        for byte in bytes {
+            let parity = byte.count_ones() % 2 == 0;
+            let bits = [false]
+                .into_iter()
+                .chain((0..8).map(|i| byte >> i & 1 == 1))
+                .chain([parity, true]);
+            for bit in bits {
+                sim.schedule(self.data, level(bit), Nanoseconds(t));
+                sim.schedule(self.clock, PinState::Low, Nanoseconds(t + quarter));
+                sim.schedule(self.clock, PinState::Floating, Nanoseconds(t + 3 * quarter));
+                t += self.period;
+            }
+            sim.schedule(self.data, PinState::Floating, Nanoseconds(t));
+            t += 2 * self.period;
+        }
+        Nanoseconds(t)
+    }
+
+    /// Schedule the scancodes of key events at their times.
+    ///
+    /// An event that starts before the previous one was sent is delayed.
+    pub fn play(&self, sim: &mut Simulation, keys: &[Ps2Key]) -> Nanoseconds<u64> {
+        keys.iter().fold(Nanoseconds(0), |idle, key| {
+            self.send(sim, key.time.max(idle), &key.bytes())
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::analysis::{Ps2Decoder, Waveform};
+    use embedded_hal::digital::InputPin as _;
+    use std::sync::Arc;
+
+    #[test]
+    fn replay_keys() {
+        let mut sim = Simulation::new();
+        let clock = sim.add_bus(Arc::new(Bus::new(PinState::High)));
+        let data = sim.add_bus(Arc::new(Bus::new(PinState::High)));
+        let key = |time: u64, code, extended, released| Ps2Key {
+            time: Nanoseconds(time),
+            code,
+            extended,
+            released,
+        };
+        // press and release 'A' and right ctrl
+        let keys = [
+            key(1_000_000, 0x1c, false, false),
+            key(3_000_000, 0x1c, false, true),
+            key(6_000_000, 0x14, true, false),
+            key(6_000_000, 0x14, true, true),
+        ];
+        let keyboard = Ps2Keyboard::new(clock, data);
+        let end = keyboard.play(&mut sim, &keys);
+
+        // sample the lines like a logic analyzer
+        let (clk, dat) = (sim.input_pin(clock), sim.input_pin(data));
+        let (mut clock_changes, mut data_changes) = (vec![], vec![]);
+        let level = |high: bool| if high { PinState::High } else { PinState::Low };
+        for t in (0..end.0 + 100_000).step_by(1000) {
+            sim.advance_to(Nanoseconds(t + 1));
+            clock_changes.push((Nanoseconds(t), level(clk.is_high().unwrap())));
+            data_changes.push((Nanoseconds(t), level(dat.is_high().unwrap())));
+        }
+        let decoder = Ps2Decoder::new();
+        let frames = decoder.decode(&Waveform::new(clock_changes), &Waveform::new(data_changes));
+        let bytes: Vec<_> = frames.iter().map(|f| f.byte).collect();
+        assert_eq!(bytes, vec![0x1c, 0xf0, 0x1c, 0xe0, 0x14, 0xe0, 0xf0, 0x14]);
+        assert!(frames.iter().all(|f| f.parity && f.stop));
+        let decoded = decoder.keys(&frames);
+        let times: Vec<_> = decoded.iter().map(|k| k.time.0 / 1000).collect();
+        assert_eq!(times, vec![1020, 3020, 6020, 8100]);
+        let codes: Vec<_> = decoded
+            .iter()
+            .map(|k| (k.code, k.extended, k.released))
+            .collect();
+        let expected: Vec<_> = keys
+            .iter()
+            .map(|k| (k.code, k.extended, k.released))
+            .collect();
+        assert_eq!(codes, expected);
+    }
+}
@@ -0,0 +1,465 @@
+//! An SD card in SPI mode.
+
+use super::{Edge, Response, Signal, SimDevice};
+use crate::analysis::{sd_crc16, sd_crc7};
+use crate::pins::*;
+use embedded_hal::digital::InputPin as _;
+use embedded_time::duration::*;
+use std::collections::VecDeque;
+use std::sync::{Arc, Mutex};
+
+const BLOCK_SIZE: usize = 512;
+/// The number of bytes the card signals busy after a written block.
+const BUSY_BYTES: usize = 8;
+
+const R1_IDLE: u8 = 1 << 0;
+const R1_ILLEGAL_COMMAND: u8 = 1 << 2;
+const R1_CRC_ERROR: u8 = 1 << 3;
+const R1_ADDRESS_ERROR: u8 = 1 << 5;
+
+const DATA_ACCEPTED: u8 = 0x05;
+const DATA_CRC_ERROR: u8 = 0x0b;
+
+/// The data transfer following a read or write command.
+enum Transfer {
+    None,
+    Read {
+        block: usize,
+        multiple: bool,
+    },
+    Write {
+        block: usize,
+        multiple: bool,
+        data: Option<Vec<u8>>,
+    },
+}
+
+/// A simulated SDHC card in SPI mode.
+///
+/// The card is attached to SCLK, MOSI, MISO and an active low CS signal and
+/// uses SPI mode 0 like a [SpiFlash](super::SpiFlash). While selected, MISO
+/// is high if the card has nothing to send, it is released while the card
+/// is not selected. Responses are sent one byte after a command.
+///
+/// Supported commands are CMD0 (reset), CMD8 (interface condition), CMD12
+/// (stop transmission), CMD13 (status), CMD16 (block length), CMD17 and
+/// CMD18 (read blocks), CMD24 and CMD25 (write blocks), CMD55 (application
+/// command), CMD58 (read OCR), CMD59 (CRC on/off) and ACMD41 (initialize),
+/// other commands are answered as illegal. The card leaves the idle state
+/// after a configurable number of ACMD41 polls. Blocks are addressed by
+/// their index and always 512 bytes long. The CRC of CMD0 and CMD8 is
+/// always checked, other CRCs only after enabling them with CMD59.
+pub struct SdCard {
+    mosi: InputPin,
+    sclk: Signal,
+    cs: Signal,
+    miso: Signal,
+    memory: Arc<Mutex<Vec<u8>>>,
+    init_polls: usize,
+    polls: usize,
+    idle: bool,
+    app: bool,
+    crc: bool,
+    selected: bool,
+    /// The number of bits received in the current byte.
+    bits: u8,
+    input: u8,
+    /// The byte that is currently shifted out.
+    output: u8,
+    queue: VecDeque<u8>,
+    command: Vec<u8>,
+    transfer: Transfer,
+}
+
+impl SdCard {
+    /// Create a new card with `blocks` blocks of 512 bytes.
+    ///
+    /// `mosi` is used to read the data input, `miso` is driven by the card.
+    /// The memory is cleared to `0x00`, the card is ready after two ACMD41
+    /// polls that answer with the idle state.
+    pub fn new(mosi: InputPin, sclk: Signal, cs: Signal, miso: Signal, blocks: usize) -> Self {
+        SdCard {
+            mosi,
+            sclk,
+            cs,
+            miso,
+            memory: Arc::new(Mutex::new(vec![0; blocks * BLOCK_SIZE])),
+            init_polls: 2,
+            polls: 0,
+            idle: true,
+            app: false,
+            crc: false,
+            selected: false,
+            bits: 0,
+            input: 0,
+            output: 0xFF,
+            queue: VecDeque::new(),
+            command: vec![],
+            transfer: Transfer::None,
+        }
+    }
+
+    /// Set the number of ACMD41 polls that answer with the idle state.
+    pub fn with_init_polls(mut self, init_polls: usize) -> Self {
+        self.init_polls = init_polls;
+        self
+    }
+
+    /// Set the initial memory contents.
+    ///
+    /// The memory is resized to the size of the contents.
+    pub fn with_contents(self, contents: &[u8]) -> Self {
+        *self.memory.lock().unwrap() = contents.to_vec();
+        self
+    }
+
+    /// Return a handle to the memory, to inspect it after a test.
+    pub fn memory(&self) -> Arc<Mutex<Vec<u8>>> {
+        self.memory.clone()
+    }
+
+    fn blocks(&self) -> usize {
+        self.memory.lock().unwrap().len() / BLOCK_SIZE
+    }
+
+    /// Return the next byte to shift out.
+    fn next_output(&mut self) -> u8 {
+        if self.queue.is_empty() {
+            if let Transfer::Read { block, multiple } = self.transfer {
+                let memory = self.memory.lock().unwrap();
+                let data = &memory[block * BLOCK_SIZE..(block + 1) * BLOCK_SIZE];
+                self.queue.extend([0xFF, 0xFE]);
+                self.queue.extend(data);
+                self.queue.extend(sd_crc16(data).to_be_bytes());
+                drop(memory);
+                self.transfer = if multiple && block + 1 < self.blocks() {
+                    Transfer::Read {
+                        block: block + 1,
+                        multiple,
+                    }
+                } else {
+                    Transfer::None
+                };
+            }
+        }
+        self.queue.pop_front().unwrap_or(0xFF)
+    }
+
+    /// Handle a received byte.
+    fn receive(&mut self, byte: u8) {
+        let blocks = self.blocks();
+        if let Transfer::Write {
+            block,
+            multiple,
+            data,
+        } = &mut self.transfer
+        {
+            let token = if *multiple { 0xFC } else { 0xFE };
+            match data {
+                None if byte == token => {
+                    *data = Some(vec![]);
+                    return;
+                }
+                None if byte == 0xFD && *multiple => {
+                    self.transfer = Transfer::None;
+                    self.queue.push_back(0xFF);
+                    self.queue.extend([0; BUSY_BYTES]);
+                    return;
+                }
+                Some(bytes) => {
+                    bytes.push(byte);
+                    if bytes.len() < BLOCK_SIZE + 2 {
+                        return;
+                    }
+                    let (bytes, crc) = bytes.split_at(BLOCK_SIZE);
+                    let response =
+                        if self.crc && sd_crc16(bytes) != u16::from_be_bytes([crc[0], crc[1]]) {
+                            DATA_CRC_ERROR
+                        } else {
+                            let mut memory = self.memory.lock().unwrap();
+                            memory[*block * BLOCK_SIZE..(*block + 1) * BLOCK_SIZE]
+                                .copy_from_slice(bytes);
+                            DATA_ACCEPTED
+                        };
+                    self.queue.push_back(response);
+                    self.queue.extend([0; BUSY_BYTES]);
+                    *data = None;
+                    *block += 1;
+                    if !*multiple || response != DATA_ACCEPTED || *block >= blocks {
+                        self.transfer = Transfer::None;
+                    }
+                    return;
+                }
+                None => {}
+            }
+        }
+        if self.command.is_empty() && byte & 0xC0 != 0x40 {
+            return;
+        }
+        self.command.push(byte);
+        if self.command.len() == 6 {
+            let command = std::mem::take(&mut self.command);
+            let response = self.execute(&command);
+            self.queue.clear();
+            self.queue.push_back(0xFF);
+            self.queue.extend(response);
+        }
+    }
+
+    /// Execute a command and return its response.
+    fn execute(&mut self, command: &[u8]) -> Vec<u8> {
+        let index = command[0] & 0x3F;
+        let argument = u32::from_be_bytes([command[1], command[2], command[3], command[4]]);
+        let crc_ok = command[5] & 1 == 1 && sd_crc7(&command[..5]) == command[5] >> 1;
+        let app = std::mem::take(&mut self.app);
+        let r1 = if self.idle { R1_IDLE } else { 0 };
+        if !crc_ok && (self.crc || (!app && matches!(index, 0 | 8))) {
+            return vec![r1 | R1_CRC_ERROR];
+        }
+        match (app, index) {
+            (false, 0) => {
+                self.idle = true;
+                self.polls = 0;
+                self.crc = false;
+                self.transfer = Transfer::None;
+                vec![R1_IDLE]
+            }
+            (false, 8) => vec![r1, 0, 0, (argument >> 8) as u8 & 0x0F, argument as u8],
+            (false, 12) => {
+                self.transfer = Transfer::None;
+                vec![r1]
+            }
+            (false, 13) => vec![r1, 0],
+            (false, 16) => vec![r1],
+            (false, 55) => {
+                self.app = true;
+                vec![r1]
+            }
+            (false, 58) => {
+                let ocr: u32 = if self.idle { 0x00FF_8000 } else { 0xC0FF_8000 };
+                let mut response = vec![r1];
+                response.extend(ocr.to_be_bytes());
+                response
+            }
+            (false, 59) => {
+                self.crc = argument & 1 != 0;
+                vec![r1]
+            }
+            (true, 41) => {
+                if self.polls >= self.init_polls {
+                    self.idle = false;
+                } else {
+                    self.polls += 1;
+                }
+                vec![if self.idle { R1_IDLE } else { 0 }]
+            }
+            (false, 17 | 18 | 24 | 25) if self.idle => vec![r1 | R1_ILLEGAL_COMMAND],
+            (false, 17 | 18 | 24 | 25) if argument as usize >= self.blocks() => {
+                vec![R1_ADDRESS_ERROR]
+            }
+            (false, 17 | 18) => {
+                self.transfer = Transfer::Read {
+                    block: argument as usize,
+                    multiple: index == 18,
+                };
+                vec![0]
+            }
+            (false, 24 | 25) => {
+                self.transfer = Transfer::Write {
+                    block: argument as usize,
+                    multiple: index == 25,
+                    data: None,
+                };
+                vec![0]
+            }
+            _ => vec![r1 | R1_ILLEGAL_COMMAND],
+        }
+    }
+
+    fn drive_miso(&self) -> Response {
+        let state = if self.output & (0x80 >> self.bits) != 0 {
+            PinState::High
+        } else {
+            PinState::Low
+        };
+        (self.miso, state, Nanoseconds(0))
+    }
+}
+
+impl SimDevice for SdCard {
+    fn on_edge(&mut self, signal: Signal, edge: Edge, _t: Nanoseconds<u64>) -> Vec<Response> {
+        if signal == self.cs {
+            if edge == Edge::Falling {
+                self.selected = true;
+                self.bits = 0;
+                self.input = 0;
+                self.command.clear();
+                self.output = self.next_output();
+                return vec![self.drive_miso()];
+            } else if self.selected {
+                self.selected = false;
+                return vec![(self.miso, PinState::Floating, Nanoseconds(0))];
+            }
+        } else if signal == self.sclk && self.selected {
+            match edge {
+                Edge::Rising => {
+                    self.input = (self.input << 1) | self.mosi.is_high().unwrap() as u8;
+                    self.bits += 1;
+                    if self.bits == 8 {
+                        self.receive(self.input);
+                        self.output = self.next_output();
+                        self.bits = 0;
+                        self.input = 0;
+                    }
+                }
+                _ => return vec![self.drive_miso()],
+            }
+        }
+        vec![]
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::analysis::{SdDecoder, SpiFrame};
+    use crate::devices::Simulation;
+    use embedded_hal::digital::OutputPin;
+
+    /// A bit banged SPI master in mode 0 with a clock period of 1 µs that
+    /// keeps the transferred frames.
+    struct Master<'a> {
+        sim: &'a mut Simulation,
+        sclk: PushPullPin,
+        mosi: PushPullPin,
+        cs: PushPullPin,
+        miso: InputPin,
+        t: u64,
+        frames: Vec<SpiFrame>,
+    }
+
+    impl<'a> Master<'a> {
+        fn wait(&mut self) {
+            self.t += 500;
+            self.sim.advance_to(Nanoseconds(self.t));
+        }
+
+        fn transfer(&mut self, data: &[u8]) -> Vec<u8> {
+            let start = Nanoseconds(self.t);
+            self.cs.set_low().unwrap();
+            self.wait();
+            let mut received = vec![];
+            for byte in data {
+                let mut read = 0;
+                for bit in (0..8).rev() {
+                    if *byte & (1 << bit) != 0 {
+                        self.mosi.set_high().unwrap();
+                    } else {
+                        self.mosi.set_low().unwrap();
+                    }
+                    self.wait();
+                    self.sclk.set_high().unwrap();
+                    read = (read << 1) | self.miso.is_high().unwrap() as u8;
+                    self.wait();
+                    self.sclk.set_low().unwrap();
+                }
+                received.push(read);
+            }
+            self.wait();
+            self.cs.set_high().unwrap();
+            self.frames.push(SpiFrame {
+                start,
+                end: Nanoseconds(self.t),
+                mosi: data.to_vec(),
+                miso: received.clone(),
+            });
+            self.wait();
+            received
+        }
+
+        /// Send a command followed by `extra` bytes and return the bytes
+        /// received after the command.
+        fn command(&mut self, index: u8, argument: u32, extra: usize) -> Vec<u8> {
+            let mut data = vec![0x40 | index];
+            data.extend(argument.to_be_bytes());
+            data.push(sd_crc7(&data) << 1 | 1);
+            data.extend(std::iter::repeat_n(0xFF, extra));
+            self.transfer(&data).split_off(6)
+        }
+    }
+
+    #[test]
+    fn initialize_write_read() {
+        let mut sim = Simulation::new();
+        let sclk = sim.add_signal(Arc::new(AtomicPinState::new_with_state(PinState::Low)));
+        let mosi = sim.add_signal(Arc::new(AtomicPinState::new_with_state(PinState::Low)));
+        let miso = sim.add_signal(Arc::new(AtomicPinState::new()));
+        let cs = sim.add_signal(Arc::new(AtomicPinState::new_with_state(PinState::High)));
+        let card = SdCard::new(sim.input_pin(mosi), sclk, cs, miso, 16);
+        let memory = card.memory();
+        sim.add_device(card);
+        let mut master = Master {
+            sclk: sim.push_pull_pin(sclk),
+            mosi: sim.push_pull_pin(mosi),
+            cs: sim.push_pull_pin(cs),
+            miso: sim.input_pin(miso),
+            sim: &mut sim,
+            t: 0,
+            frames: vec![],
+        };
+
+        assert_eq!(master.command(0, 0, 2), [0xFF, R1_IDLE]);
+        assert_eq!(
+            master.command(8, 0x1AA, 6)[1..],
+            [R1_IDLE, 0, 0, 0x01, 0xAA]
+        );
+        // CMD8 with a wrong CRC
+        assert_eq!(
+            master.transfer(&[0x48, 0, 0, 0x01, 0xAA, 0x01, 0xFF, 0xFF])[7],
+            R1_IDLE | R1_CRC_ERROR
+        );
+        // reading is not allowed before the initialization finished
+        assert_eq!(master.command(17, 0, 2)[1], R1_IDLE | R1_ILLEGAL_COMMAND);
+        let mut polls = 0;
+        loop {
+            master.command(55, 0, 2);
+            polls += 1;
+            if master.command(41, 1 << 30, 2)[1] == 0 {
+                break;
+            }
+        }
+        assert_eq!(polls, 3);
+        assert_eq!(master.command(58, 0, 6)[2] & 0x40, 0x40);
+
+        let data: Vec<u8> = (0..512).map(|i| (i * 7) as u8).collect();
+        assert_eq!(master.command(24, 3, 2), [0xFF, 0]);
+        let mut block = vec![0xFF, 0xFE];
+        block.extend(&data);
+        block.extend(sd_crc16(&data).to_be_bytes());
+        block.extend([0xFF; 10]);
+        let received = master.transfer(&block);
+        assert_eq!(received[516] & 0x1F, DATA_ACCEPTED);
+        assert_eq!(&memory.lock().unwrap()[3 * 512..4 * 512], &data[..]);
+
+        let received = master.command(17, 3, 520);
+        let token = received.iter().position(|b| *b == 0xFE).unwrap();
+        assert_eq!(&received[token + 1..token + 513], &data[..]);
+
+        let commands = SdDecoder::new().decode(&master.frames);
+        let names: Vec<String> = commands.iter().map(|c| c.name()).collect();
+        assert_eq!(
+            names,
+            [
+                "CMD0", "CMD8", "CMD8", "CMD17", "CMD55", "ACMD41", "CMD55", "ACMD41", "CMD55",
+                "ACMD41", "CMD58", "CMD24", "CMD17"
+            ]
+        );
+        assert!(!commands[2].crc_ok);
+        assert_eq!(commands[1].response, [R1_IDLE, 0, 0, 0x01, 0xAA]);
+        assert_eq!(commands[11].blocks.len(), 1);
+        assert_eq!(commands[11].blocks[0].data, data);
+        assert_eq!(commands[11].blocks[0].response, Some(DATA_ACCEPTED));
+        assert!(commands[12].blocks[0].crc_ok);
+        assert_eq!(commands[12].blocks[0].data, data);
+    }
+}
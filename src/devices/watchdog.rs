@@ -0,0 +1,153 @@
+//! A watchdog checking the kicks of the code under test.
+
+use super::{Edge, Response, Signal, SimDevice};
+use crate::analysis::{WatchdogMonitor, WatchdogViolation};
+use crate::pins::*;
+use embedded_time::duration::*;
+use std::sync::{Arc, Mutex};
+
+/// A watchdog that expires if the code under test does not kick it in time.
+///
+/// The watchdog starts with the first kick or with a change scheduled on the
+/// otherwise unused signal `timer`, which it toggles to get notified at the
+/// timeout. Each expiry is recorded as a [WatchdogViolation], which gets the
+/// time of the next kick once the watchdog is kicked again. With
+/// [Watchdog::with_panic] it panics at the expiry instead, so a test fails
+/// at the point in time the firmware stalled.
+pub struct Watchdog {
+    kick: Signal,
+    timer: Signal,
+    monitor: WatchdogMonitor,
+    panic: bool,
+    /// The time of the last kick, `None` before the start and after an
+    /// expiry.
+    last_kick: Option<u64>,
+    timer_high: bool,
+    violations: Arc<Mutex<Vec<WatchdogViolation>>>,
+}
+
+impl Watchdog {
+    /// Create a new watchdog kicked by the edges the monitor counts as
+    /// kicks.
+    pub fn new(kick: Signal, timer: Signal, monitor: WatchdogMonitor) -> Self {
+        Watchdog {
+            kick,
+            timer,
+            monitor,
+            panic: false,
+            last_kick: None,
+            timer_high: false,
+            violations: Arc::new(Mutex::new(vec![])),
+        }
+    }
+
+    /// Panic when the watchdog expires.
+    pub fn with_panic(mut self) -> Self {
+        self.panic = true;
+        self
+    }
+
+    /// Return a handle to the violations, to inspect them after a test.
+    pub fn violations(&self) -> Arc<Mutex<Vec<WatchdogViolation>>> {
+        self.violations.clone()
+    }
+
+    /// Restart the watchdog at `t`.
+    fn restart(&mut self, t: u64) -> Vec<Response> {
+        self.last_kick = Some(t);
+        self.timer_high = !self.timer_high;
+        let state = if self.timer_high {
+            PinState::High
+        } else {
+            PinState::Low
+        };
+        vec![(self.timer, state, self.monitor.timeout())]
+    }
+}
+
+impl SimDevice for Watchdog {
+    fn on_edge(&mut self, signal: Signal, edge: Edge, t: Nanoseconds<u64>) -> Vec<Response> {
+        if signal == self.kick && self.monitor.is_kick(edge) {
+            if self.last_kick.is_none() {
+                if let Some(violation) = self.violations.lock().unwrap().last_mut() {
+                    violation.next_kick.get_or_insert(t);
+                }
+            }
+            return self.restart(t.0);
+        }
+        if signal != self.timer {
+            return vec![];
+        }
+        match self.last_kick {
+            Some(last) if last + self.monitor.timeout().0 == t.0 => {
+                self.last_kick = None;
+                let violation = WatchdogViolation {
+                    last_kick: Nanoseconds(last),
+                    time: t,
+                    next_kick: None,
+                };
+                if self.panic {
+                    panic!("{}", violation);
+                }
+                self.violations.lock().unwrap().push(violation);
+                vec![]
+            }
+            // a timeout of an earlier kick
+            Some(_) => vec![],
+            None if self.violations.lock().unwrap().is_empty() => {
+                self.timer_high = edge.state() == PinState::High;
+                self.restart(t.0)
+            }
+            None => vec![],
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::devices::Simulation;
+    use embedded_hal::digital::OutputPin;
+
+    #[test]
+    fn expire_and_recover() {
+        let mut sim = Simulation::new();
+        let kick = sim.add_signal(Arc::new(AtomicPinState::new_with_state(PinState::Low)));
+        let timer = sim.add_signal(Arc::new(AtomicPinState::new()));
+        let watchdog = Watchdog::new(
+            kick,
+            timer,
+            WatchdogMonitor::new(Nanoseconds(1_000_000)).with_kick_edge(Edge::Rising),
+        );
+        let violations = watchdog.violations();
+        sim.add_device(watchdog);
+        sim.schedule(timer, PinState::Low, Nanoseconds(0u64));
+
+        // a main loop that stalls for 3 ms after 5 ms
+        let mut pin = sim.push_pull_pin(kick);
+        for t in (500_000..12_000_000u64).step_by(500_000) {
+            if !(5_000_000..8_000_000).contains(&t) {
+                sim.advance_to(Nanoseconds(t));
+                pin.set_high().unwrap();
+                sim.advance_to(Nanoseconds(t + 10_000));
+                pin.set_low().unwrap();
+            }
+        }
+        sim.advance_to(Nanoseconds(20_000_000u64));
+        assert_eq!(
+            *violations.lock().unwrap(),
+            vec![
+                WatchdogViolation {
+                    last_kick: Nanoseconds(4_500_000),
+                    time: Nanoseconds(5_500_000),
+                    next_kick: Some(Nanoseconds(8_000_000)),
+                },
+                WatchdogViolation {
+                    last_kick: Nanoseconds(11_500_000),
+                    time: Nanoseconds(12_500_000),
+                    next_kick: None,
+                },
+            ]
+        );
+    }
+}
@@ -0,0 +1,241 @@
+//! Connect the GPIOs of an emulated device to pins.
+//!
+//! The [QtestBridge] talks the [qtest protocol] of QEMU, so firmware that
+//! runs in a full emulation produces the same waveforms as host-side unit
+//! tests: output lines of the emulated device drive pins of a
+//! [VcdWriter](crate::writer::VcdWriter) and input pins are forwarded to the
+//! input lines of the device.
+//!
+//! [qtest protocol]: https://www.qemu.org/docs/master/devel/testing/qtest.html
+
+use core::convert::Infallible;
+use embedded_hal::digital::{InputPin, OutputPin};
+use embedded_time::duration::*;
+use fnv::FnvHashMap;
+use std::io::Result as IOResult;
+use std::io::{BufRead, Error, ErrorKind, Write};
+
+struct Input {
+    path: String,
+    name: String,
+    line: usize,
+    pin: Box<dyn InputPin<Error = Infallible> + Send>,
+    /// The level last sent to the device.
+    high: Option<bool>,
+}
+
+/// A bridge between the GPIO lines of a QEMU machine and pins.
+///
+/// QEMU has to be started with `-qtest` connected to the `read` and `write`
+/// streams, e.g. `-qtest unix:/tmp/qtest.sock` and a
+/// [UnixStream](std::os::unix::net::UnixStream) on the other side. Output
+/// lines that were captured with [QtestBridge::intercept_out] raise
+/// asynchronous `IRQ` messages, which are applied to the mapped pins while
+/// waiting for the responses of commands or during [QtestBridge::poll].
+///
+/// ```no_run
+/// use embedded_hal_vcd::emulator::QtestBridge;
+/// use embedded_hal_vcd::writer::VcdWriterBuilder;
+/// use embedded_time::duration::*;
+/// use std::io::BufReader;
+/// use std::os::unix::net::UnixListener;
+///
+/// let listener = UnixListener::bind("/tmp/qtest.sock").unwrap();
+/// // start QEMU with `-qtest unix:/tmp/qtest.sock`
+/// let (stream, _) = listener.accept().unwrap();
+/// let mut bridge = QtestBridge::new(BufReader::new(stream.try_clone().unwrap()), stream);
+///
+/// let mut builder = VcdWriterBuilder::new(std::io::stdout()).unwrap();
+/// bridge.map_output(0, builder.add_push_pull_pin("led").unwrap());
+/// let mut writer = builder.build().unwrap();
+/// bridge.intercept_out("/machine/soc/gpio").unwrap();
+/// loop {
+///     let time = bridge.clock_step(Nanoseconds(1000u64)).unwrap();
+///     writer.timestamp(time).unwrap();
+///     writer.sample().unwrap();
+/// }
+/// ```
+pub struct QtestBridge<R, W> {
+    read: R,
+    write: W,
+    outputs: FnvHashMap<usize, Box<dyn OutputPin<Error = Infallible> + Send>>,
+    inputs: Vec<Input>,
+}
+
+impl<R, W> QtestBridge<R, W>
+where
+    R: BufRead,
+    W: Write,
+{
+    /// Create a new bridge from the streams of a qtest connection.
+    pub fn new(read: R, write: W) -> Self {
+        QtestBridge {
+            read,
+            write,
+            outputs: FnvHashMap::default(),
+            inputs: vec![],
+        }
+    }
+
+    /// Drive a pin with the intercepted output line `line`.
+    pub fn map_output<P>(&mut self, line: usize, pin: P)
+    where
+        P: OutputPin<Error = Infallible> + Send + 'static,
+    {
+        self.outputs.insert(line, Box::new(pin));
+    }
+
+    /// Forward the state of a pin to the input line `line` of the GPIO
+    /// list `name` of the device at `path`, see [QtestBridge::sync_inputs].
+    ///
+    /// The name of the default GPIO list of QEMU devices is
+    /// `unnamed-gpio-in`.
+    pub fn map_input<P>(&mut self, path: &str, name: &str, line: usize, pin: P)
+    where
+        P: InputPin<Error = Infallible> + Send + 'static,
+    {
+        self.inputs.push(Input {
+            path: path.to_string(),
+            name: name.to_string(),
+            line,
+            pin: Box::new(pin),
+            high: None,
+        });
+    }
+
+    /// Capture the output lines of the device at `path`.
+    ///
+    /// QEMU can only intercept the outputs of a single device.
+    pub fn intercept_out(&mut self, path: &str) -> IOResult<()> {
+        self.command(&format!("irq_intercept_out {}", path))
+            .map(|_| ())
+    }
+
+    /// Send the pin states that changed since the last call to the mapped
+    /// input lines.
+    pub fn sync_inputs(&mut self) -> IOResult<()> {
+        for i in 0..self.inputs.len() {
+            let input = &self.inputs[i];
+            let high = input.pin.is_high().unwrap_or_else(|e| match e {});
+            if input.high == Some(high) {
+                continue;
+            }
+            let command = format!(
+                "set_irq_in {} {} {} {}",
+                input.path, input.name, input.line, high as u8
+            );
+            self.command(&command)?;
+            self.inputs[i].high = Some(high);
+        }
+        Ok(())
+    }
+
+    /// Forward the inputs and advance the virtual clock of the machine.
+    ///
+    /// Returns the virtual time after the step, to be used as timestamp of
+    /// the next sample.
+    pub fn clock_step(&mut self, step: Nanoseconds<u64>) -> IOResult<Nanoseconds<u64>> {
+        self.sync_inputs()?;
+        let response = self.command(&format!("clock_step {}", step.0))?;
+        response
+            .trim()
+            .parse()
+            .map(Nanoseconds)
+            .map_err(|_| Error::new(ErrorKind::InvalidData, "invalid clock value"))
+    }
+
+    /// Read a single message and apply it if it is an `IRQ` message.
+    ///
+    /// Returns `false` if the connection was closed.
+    pub fn poll(&mut self) -> IOResult<bool> {
+        let mut line = String::new();
+        if self.read.read_line(&mut line)? == 0 {
+            return Ok(false);
+        }
+        self.apply(line.trim());
+        Ok(true)
+    }
+
+    fn apply(&mut self, message: &str) {
+        let mut words = message.split_whitespace();
+        if words.next() != Some("IRQ") {
+            return;
+        }
+        let high = match words.next() {
+            Some("raise") => true,
+            Some("lower") => false,
+            _ => return,
+        };
+        let pin = words
+            .next()
+            .and_then(|line| line.parse().ok())
+            .and_then(|line: usize| self.outputs.get_mut(&line));
+        if let Some(pin) = pin {
+            let result = if high { pin.set_high() } else { pin.set_low() };
+            result.unwrap_or_else(|e| match e {});
+        }
+    }
+
+    /// Send a command and return the text after `OK` of the response.
+    fn command(&mut self, command: &str) -> IOResult<String> {
+        writeln!(self.write, "{}", command)?;
+        self.write.flush()?;
+        loop {
+            let mut line = String::new();
+            if self.read.read_line(&mut line)? == 0 {
+                return Err(Error::new(
+                    ErrorKind::UnexpectedEof,
+                    "qtest connection closed",
+                ));
+            }
+            let message = line.trim();
+            if let Some(rest) = message.strip_prefix("OK") {
+                return Ok(rest.to_string());
+            }
+            if message.starts_with("FAIL") || message.starts_with("ERR") {
+                return Err(Error::other(format!("{} failed: {}", command, message)));
+            }
+            self.apply(message);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::pins::{AtomicPinState, PinState, PushPullPin};
+    use std::sync::atomic::Ordering;
+    use std::sync::Arc;
+
+    #[test]
+    fn qtest_bridge() {
+        let responses = "OK\nIRQ raise 1\nOK\nOK 1000\nIRQ lower 1\nIRQ raise 7\n";
+        let mut commands = vec![];
+        let led = Arc::new(AtomicPinState::new());
+        let button = Arc::new(AtomicPinState::new_with_state(PinState::High));
+        let mut bridge = QtestBridge::new(responses.as_bytes(), &mut commands);
+        bridge.map_output(1, PushPullPin::new(led.clone()));
+        bridge.map_input(
+            "/machine/soc/gpio",
+            "unnamed-gpio-in",
+            3,
+            crate::pins::InputPin::new(button),
+        );
+        bridge.intercept_out("/machine/soc/led").unwrap();
+        let time = bridge.clock_step(Nanoseconds(1000u64)).unwrap();
+        assert_eq!(time, Nanoseconds(1000u64));
+        assert_eq!(led.load(Ordering::SeqCst), PinState::High);
+        assert!(bridge.poll().unwrap());
+        assert_eq!(led.load(Ordering::SeqCst), PinState::Low);
+        assert!(bridge.poll().unwrap());
+        assert!(!bridge.poll().unwrap());
+        drop(bridge);
+        assert_eq!(
+            String::from_utf8(commands).unwrap(),
+            "irq_intercept_out /machine/soc/led
+set_irq_in /machine/soc/gpio unnamed-gpio-in 3 1
+clock_step 1000
+"
+        );
+    }
+}
@@ -0,0 +1,10 @@
+//! Export recorded VCD files to other formats.
+//!
+//! Each format has its own module with an exporter and a matching importer,
+//! e.g. [jsonl] for JSON Lines that web-based viewers and ad-hoc scripts can
+//! consume easily, [csv] for spreadsheets and scope software or [wavedrom]
+//! for timing diagrams in documentation.
+
+pub mod csv;
+pub mod jsonl;
+pub mod wavedrom;
@@ -0,0 +1,267 @@
+//! CSV tables of scalar signals.
+//!
+//! The first column is the time in nanoseconds, followed by a column for
+//! each scalar signal with its full path as header. A row is written for
+//! each time a signal changes, with the states `0`, `1` and `z` of all
+//! signals at that time:
+//!
+//! ```text
+//! time_ns,top.clk,top.cs
+//! 0,0,1
+//! 100,1,0
+//! ```
+//!
+//! Vector and real signals are added as columns of physical values with
+//! [export_with_units].
+
+use crate::analysis::Waveform;
+use crate::pins::PinState;
+use crate::reader::timestamp_to_ns;
+use crate::units::{rounded, Scale, Units};
+use embedded_time::duration::*;
+use std::fmt::Write as _;
+use std::io::Result as IOResult;
+use std::io::{BufRead, Error, ErrorKind, Read};
+
+fn state_char(state: Option<PinState>) -> char {
+    match state {
+        Some(PinState::Low) => '0',
+        Some(PinState::High) => '1',
+        Some(PinState::Floating) => 'z',
+        None => 'x',
+    }
+}
+
+/// The physical values of a vector or real signal, `None` if unknown.
+type Series = Vec<(Nanoseconds<u64>, Option<f64>)>;
+
+/// Read the physical values of the vector and real signals with a scale.
+fn read_scaled<R: Read>(read: R, units: &Units) -> IOResult<Vec<(String, &Scale, Series)>> {
+    let mut parser = vcd::Parser::new(read);
+    let header = parser.parse_header()?;
+    let mut signals = vec![];
+    scaled_vars(&header.items, "", units, &mut signals);
+    let mut series: Vec<Series> = vec![vec![]; signals.len()];
+    let mut time = Nanoseconds(0);
+    for command in parser {
+        let (id, change) = match command? {
+            vcd::Command::Timestamp(t) => {
+                time = timestamp_to_ns(header.timescale, t)?;
+                continue;
+            }
+            vcd::Command::ChangeVector(id, bits) => (id, Ok(bits)),
+            vcd::Command::ChangeReal(id, value) => (id, Err(value)),
+            _ => continue,
+        };
+        for (i, (_, code, scale)) in signals.iter().enumerate() {
+            if *code == id {
+                let raw = match &change {
+                    Ok(bits) => scale.raw(bits),
+                    Err(value) => Some(*value),
+                };
+                series[i].push((time, raw.map(|raw| scale.apply(raw))));
+            }
+        }
+    }
+    Ok(signals
+        .into_iter()
+        .zip(series)
+        .map(|((name, _, scale), series)| (name, scale, series))
+        .collect())
+}
+
+/// Collect the vector and real variables with a scale.
+fn scaled_vars<'a>(
+    items: &[vcd::ScopeItem],
+    prefix: &str,
+    units: &'a Units,
+    vars: &mut Vec<(String, vcd::IdCode, &'a Scale)>,
+) {
+    for item in items {
+        match item {
+            vcd::ScopeItem::Var(var) if var.size > 1 || var.var_type == vcd::VarType::Real => {
+                let name = format!("{}{}", prefix, var.reference);
+                if let Some(scale) = units.get(&name) {
+                    vars.push((name, var.code, scale));
+                }
+            }
+            vcd::ScopeItem::Var(_) => {}
+            vcd::ScopeItem::Scope(scope) => {
+                let prefix = format!("{}{}.", prefix, scope.identifier);
+                scaled_vars(&scope.children, &prefix, units, vars);
+            }
+        }
+    }
+}
+
+/// Write the scalar signals of a VCD file as CSV table.
+///
+/// States before the first change of a signal are written as `x`.
+pub fn export<R, W>(read: R, output: W) -> IOResult<()>
+where
+    R: Read,
+    W: std::io::Write,
+{
+    export_with_units(read, output, &Units::default())
+}
+
+/// Write the scalar signals of a VCD file and the physical values of
+/// signals with a [Scale] as CSV table.
+///
+/// The vector and real signals with a scale are written after the scalar
+/// signals, with the unit in brackets after the path. Unknown values are
+/// written as `x`, like the states before the first change of a signal.
+///
+/// ```
+/// use embedded_hal_vcd::export::csv;
+/// use embedded_hal_vcd::units::{Scale, Units};
+///
+/// let vcd = b"$timescale 1 ns $end
+/// $scope module top $end
+/// $var wire 1 ! cs $end
+/// $var wire 12 \" adc $end
+/// $upscope $end
+/// $enddefinitions $end
+/// #0
+/// 0!
+/// #100
+/// b100000000000 \"
+/// #200
+/// 1!
+/// ";
+/// let mut units = Units::new();
+/// units.insert("top.adc", Scale::new(3.3 / 4096.0, "V"));
+/// let mut table = vec![];
+/// csv::export_with_units(&vcd[..], &mut table, &units).unwrap();
+/// assert_eq!(
+///     String::from_utf8(table).unwrap(),
+///     "time_ns,top.cs,top.adc [V]\n0,0,x\n100,0,1.65\n200,1,1.65\n"
+/// );
+/// ```
+pub fn export_with_units<R, W>(mut read: R, mut output: W, units: &Units) -> IOResult<()>
+where
+    R: Read,
+    W: std::io::Write,
+{
+    let mut vcd = vec![];
+    read.read_to_end(&mut vcd)?;
+    let signals = Waveform::read_all(&vcd[..])?;
+    let scaled = read_scaled(&vcd[..], units)?;
+    write!(output, "time_ns")?;
+    for (name, _) in signals.iter() {
+        write!(output, ",{}", name)?;
+    }
+    for (name, scale, _) in scaled.iter() {
+        write!(output, ",{} [{}]", name, scale.unit())?;
+    }
+    writeln!(output)?;
+    let mut times: Vec<_> = signals
+        .iter()
+        .flat_map(|(_, w)| w.changes().iter().map(|(t, _)| *t))
+        .chain(
+            scaled
+                .iter()
+                .flat_map(|(_, _, s)| s.iter().map(|(t, _)| *t)),
+        )
+        .collect();
+    times.sort_unstable();
+    times.dedup();
+    for t in times {
+        let mut row = t.0.to_string();
+        for (_, waveform) in signals.iter() {
+            row.push(',');
+            row.push(state_char(waveform.state_at(t)));
+        }
+        for (_, _, series) in scaled.iter() {
+            let index = series.partition_point(|(time, _)| *time <= t);
+            match index.checked_sub(1).and_then(|i| series[i].1) {
+                Some(value) => write!(row, ",{}", rounded(value)).unwrap(),
+                None => row.push_str(",x"),
+            }
+        }
+        writeln!(output, "{}", row)?;
+    }
+    Ok(())
+}
+
+/// Convert a CSV table back to a VCD file.
+///
+/// Values other than `0`, `1` and `z` (e.g. `x`) leave the signal
+/// unchanged, empty lines are skipped.
+pub fn import<R, W>(read: R, output: W) -> IOResult<()>
+where
+    R: BufRead,
+    W: std::io::Write,
+{
+    let invalid = |line: usize, msg: &str| {
+        Error::new(ErrorKind::InvalidData, format!("line {}: {}", line, msg))
+    };
+    let mut lines = read.lines();
+    let header = match lines.next() {
+        Some(header) => header?,
+        None => return Err(invalid(1, "missing header")),
+    };
+    let mut signals: Vec<(String, Vec<_>)> = header
+        .trim()
+        .split(',')
+        .skip(1)
+        .map(|name| (name.trim().to_string(), vec![]))
+        .collect();
+    for (i, line) in lines.enumerate() {
+        let line = line?;
+        if line.trim().is_empty() {
+            continue;
+        }
+        let mut columns = line.trim().split(',');
+        let time = columns
+            .next()
+            .and_then(|t| t.trim().parse().ok())
+            .ok_or_else(|| invalid(i + 2, "invalid time"))?;
+        for ((_, changes), value) in signals.iter_mut().zip(columns) {
+            let state = match value.trim() {
+                "0" => PinState::Low,
+                "1" => PinState::High,
+                "z" | "Z" => PinState::Floating,
+                _ => continue,
+            };
+            changes.push((Nanoseconds(time), state));
+        }
+    }
+    let signals: Vec<_> = signals
+        .into_iter()
+        .map(|(name, changes)| (name, Waveform::new(changes)))
+        .collect();
+    Waveform::write_all(&signals, output)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn export_import() {
+        let vcd = "$timescale 1 ns $end
+$scope module top $end
+$var wire 1 ! clk $end
+$var wire 1 \" cs $end
+$upscope $end
+$enddefinitions $end
+#0
+0!
+#50
+z\"
+#100
+1!
+0\"
+";
+        let mut csv = vec![];
+        export(vcd.as_bytes(), &mut csv).unwrap();
+        let csv = String::from_utf8(csv).unwrap();
+        assert_eq!(csv, "time_ns,top.clk,top.cs\n0,0,x\n50,0,z\n100,1,0\n");
+        let mut imported = vec![];
+        import(csv.as_bytes(), &mut imported).unwrap();
+        let mut again = vec![];
+        export(imported.as_slice(), &mut again).unwrap();
+        assert_eq!(String::from_utf8(again).unwrap(), csv);
+    }
+}
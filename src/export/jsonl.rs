@@ -0,0 +1,485 @@
+//! JSON Lines change streams.
+//!
+//! Each line is a JSON object for a single change with the time `t` in
+//! nanoseconds, the full path of the signal `sig` and the new value `v`:
+//!
+//! ```text
+//! {"t":100,"sig":"top.spi.sck","v":1}
+//! {"t":100,"sig":"top.spi.data","v":"0101"}
+//! ```
+//!
+//! Scalars are written as `0` and `1` or as the strings `"x"` and `"z"`,
+//! vectors as strings of bits (MSB first), reals as numbers and string
+//! variables as strings.
+
+use crate::reader::timestamp_to_ns;
+use crate::units::{rounded, Scale, Units};
+use fnv::FnvHashMap;
+use std::fmt::Write as _;
+use std::io::Result as IOResult;
+use std::io::{BufRead, Error, ErrorKind, Read};
+
+pub(super) fn invalid(msg: &str) -> Error {
+    Error::new(ErrorKind::InvalidData, msg)
+}
+
+/// Quote and escape a JSON string.
+pub(super) fn quote(text: &str) -> String {
+    let mut quoted = String::from("\"");
+    for c in text.chars() {
+        match c {
+            '"' => quoted.push_str("\\\""),
+            '\\' => quoted.push_str("\\\\"),
+            '\n' => quoted.push_str("\\n"),
+            '\r' => quoted.push_str("\\r"),
+            '\t' => quoted.push_str("\\t"),
+            c if (c as u32) < 0x20 => {
+                let _ = write!(quoted, "\\u{:04x}", c as u32);
+            }
+            c => quoted.push(c),
+        }
+    }
+    quoted.push('"');
+    quoted
+}
+
+/// Collect the full paths of all variables by identifier.
+fn collect_names(
+    items: &[vcd::ScopeItem],
+    prefix: &str,
+    names: &mut FnvHashMap<vcd::IdCode, Vec<String>>,
+) {
+    for item in items {
+        match item {
+            vcd::ScopeItem::Var(var) => names
+                .entry(var.code)
+                .or_default()
+                .push(format!("{}{}", prefix, var.reference)),
+            vcd::ScopeItem::Scope(scope) => {
+                let prefix = format!("{}{}.", prefix, scope.identifier);
+                collect_names(&scope.children, &prefix, names);
+            }
+        }
+    }
+}
+
+/// A value of a change to export.
+enum Value {
+    /// A value that is written as is.
+    Text(String),
+    Vector(Vec<vcd::Value>),
+    Real(f64),
+}
+
+impl Value {
+    /// Return the JSON of the value and its unit.
+    fn scaled<'a>(&self, scale: Option<&'a Scale>) -> (String, Option<&'a str>) {
+        let raw = match (self, scale) {
+            (Value::Vector(bits), Some(scale)) => scale.raw(bits),
+            (Value::Real(value), Some(_)) => Some(*value),
+            _ => None,
+        };
+        match (raw, scale) {
+            (Some(raw), Some(scale)) => {
+                let value = rounded(scale.apply(raw));
+                (value.to_string(), Some(scale.unit()))
+            }
+            _ => match self {
+                Value::Text(text) => (text.clone(), None),
+                Value::Vector(bits) => {
+                    let bits: String = bits.iter().map(|v| v.to_string()).collect();
+                    (quote(&bits), None)
+                }
+                Value::Real(value) => (value.to_string(), None),
+            },
+        }
+    }
+}
+
+/// Write each change of a VCD file as a line of JSON.
+pub fn export<R, W>(read: R, output: W) -> IOResult<()>
+where
+    R: Read,
+    W: std::io::Write,
+{
+    export_with_units(read, output, &Units::default())
+}
+
+/// Write each change of a VCD file as a line of JSON with physical values.
+///
+/// The vectors and reals of signals with a [Scale]
+/// are written as numbers in their unit, with the unit as field `unit`.
+/// Vectors with `x` or `z` bits are written as strings of bits.
+///
+/// ```
+/// use embedded_hal_vcd::export::jsonl;
+/// use embedded_hal_vcd::units::{Scale, Units};
+///
+/// let vcd = b"$timescale 1 us $end
+/// $scope module top $end
+/// $var wire 12 ! adc $end
+/// $upscope $end
+/// $enddefinitions $end
+/// #2
+/// b100000000000 !
+/// ";
+/// let mut units = Units::new();
+/// units.insert("top.adc", Scale::new(3.3 / 4096.0, "V"));
+/// let mut json = vec![];
+/// jsonl::export_with_units(&vcd[..], &mut json, &units).unwrap();
+/// assert_eq!(
+///     String::from_utf8(json).unwrap(),
+///     "{\"t\":2000,\"sig\":\"top.adc\",\"v\":1.65,\"unit\":\"V\"}\n"
+/// );
+/// ```
+pub fn export_with_units<R, W>(read: R, mut output: W, units: &Units) -> IOResult<()>
+where
+    R: Read,
+    W: std::io::Write,
+{
+    let mut parser = vcd::Parser::new(read);
+    let header = parser.parse_header()?;
+    let mut names = FnvHashMap::default();
+    collect_names(&header.items, "", &mut names);
+    let mut time = 0;
+    for command in parser {
+        let (id, value) = match command? {
+            vcd::Command::Timestamp(t) => {
+                time = timestamp_to_ns(header.timescale, t)?.0;
+                continue;
+            }
+            vcd::Command::ChangeScalar(id, vcd::Value::V0) => (id, Value::Text("0".to_string())),
+            vcd::Command::ChangeScalar(id, vcd::Value::V1) => (id, Value::Text("1".to_string())),
+            vcd::Command::ChangeScalar(id, value) => (id, Value::Text(quote(&value.to_string()))),
+            vcd::Command::ChangeVector(id, values) => (id, Value::Vector(values)),
+            vcd::Command::ChangeReal(id, value) if value.is_finite() => (id, Value::Real(value)),
+            vcd::Command::ChangeString(id, value) => (id, Value::Text(quote(&value))),
+            _ => continue,
+        };
+        for name in names.get(&id).into_iter().flatten() {
+            let (value, unit) = value.scaled(units.get(name));
+            write!(
+                output,
+                "{{\"t\":{},\"sig\":{},\"v\":{}",
+                time,
+                quote(name),
+                value
+            )?;
+            match unit {
+                Some(unit) => writeln!(output, ",\"unit\":{}}}", quote(unit))?,
+                None => writeln!(output, "}}")?,
+            }
+        }
+    }
+    Ok(())
+}
+
+/// A JSON value of a flat object, numbers are kept as text.
+#[derive(Clone, Debug, PartialEq)]
+enum Json {
+    Number(String),
+    String(String),
+}
+
+/// Parse a flat JSON object with string and number values.
+fn parse_object(line: &str) -> IOResult<Vec<(String, Json)>> {
+    let mut chars = line.trim().chars().peekable();
+    let mut fields = vec![];
+    let skip_ws = |chars: &mut std::iter::Peekable<std::str::Chars>| {
+        while chars.next_if(|c| c.is_whitespace()).is_some() {}
+    };
+    if chars.next() != Some('{') {
+        return Err(invalid("expected JSON object"));
+    }
+    skip_ws(&mut chars);
+    if chars.next_if_eq(&'}').is_some() {
+        return Ok(fields);
+    }
+    loop {
+        skip_ws(&mut chars);
+        let key = parse_string(&mut chars)?;
+        skip_ws(&mut chars);
+        if chars.next() != Some(':') {
+            return Err(invalid("expected ':'"));
+        }
+        skip_ws(&mut chars);
+        let value = if chars.peek() == Some(&'"') {
+            Json::String(parse_string(&mut chars)?)
+        } else {
+            let mut number = String::new();
+            while let Some(c) = chars.next_if(|c| c.is_ascii_digit() || "+-.eE".contains(*c)) {
+                number.push(c);
+            }
+            if number.is_empty() {
+                return Err(invalid("expected string or number"));
+            }
+            Json::Number(number)
+        };
+        fields.push((key, value));
+        skip_ws(&mut chars);
+        match chars.next() {
+            Some(',') => {}
+            Some('}') => return Ok(fields),
+            _ => return Err(invalid("expected ',' or '}'")),
+        }
+    }
+}
+
+pub(super) fn parse_string(chars: &mut std::iter::Peekable<std::str::Chars>) -> IOResult<String> {
+    if chars.next() != Some('"') {
+        return Err(invalid("expected string"));
+    }
+    let mut text = String::new();
+    loop {
+        match chars.next().ok_or_else(|| invalid("unterminated string"))? {
+            '"' => return Ok(text),
+            '\\' => match chars.next().ok_or_else(|| invalid("unterminated string"))? {
+                'n' => text.push('\n'),
+                'r' => text.push('\r'),
+                't' => text.push('\t'),
+                'b' => text.push('\u{8}'),
+                'f' => text.push('\u{c}'),
+                'u' => {
+                    let mut code = parse_code_unit(chars)?;
+                    // characters outside the BMP are escaped as surrogate pair
+                    if (0xd800..0xdc00).contains(&code) {
+                        let low = match (chars.next(), chars.next()) {
+                            (Some('\\'), Some('u')) => parse_code_unit(chars)?,
+                            _ => return Err(invalid("unpaired surrogate")),
+                        };
+                        if !(0xdc00..0xe000).contains(&low) {
+                            return Err(invalid("unpaired surrogate"));
+                        }
+                        code = 0x10000 + ((code - 0xd800) << 10) + (low - 0xdc00);
+                    }
+                    let c = char::from_u32(code).ok_or_else(|| invalid("unpaired surrogate"))?;
+                    text.push(c);
+                }
+                c => text.push(c),
+            },
+            c => text.push(c),
+        }
+    }
+}
+
+/// Read the four hex digits of a `\u` escape.
+fn parse_code_unit(chars: &mut std::iter::Peekable<std::str::Chars>) -> IOResult<u32> {
+    let code: String = chars.by_ref().take(4).collect();
+    u32::from_str_radix(&code, 16).map_err(|_| invalid("invalid unicode escape"))
+}
+
+/// A change read from a JSON Lines stream.
+#[derive(Clone, Debug, PartialEq)]
+enum Change {
+    Scalar(vcd::Value),
+    Vector(Vec<vcd::Value>),
+    Real(f64),
+    String(String),
+}
+
+impl Change {
+    fn from_json(value: Json) -> IOResult<Self> {
+        match value {
+            Json::Number(n) if n == "0" => Ok(Change::Scalar(vcd::Value::V0)),
+            Json::Number(n) if n == "1" => Ok(Change::Scalar(vcd::Value::V1)),
+            Json::Number(n) => n
+                .parse()
+                .map(Change::Real)
+                .map_err(|_| invalid("invalid number")),
+            Json::String(s) => {
+                let bits: Option<Vec<vcd::Value>> =
+                    s.chars().map(|c| c.to_string().parse().ok()).collect();
+                match bits {
+                    Some(bits) if bits.len() == 1 => Ok(Change::Scalar(bits[0])),
+                    Some(bits) if !bits.is_empty() => Ok(Change::Vector(bits)),
+                    _ => Ok(Change::String(s)),
+                }
+            }
+        }
+    }
+
+    fn var_type(&self) -> (vcd::VarType, u32) {
+        match self {
+            Change::Scalar(_) => (vcd::VarType::Wire, 1),
+            Change::Vector(bits) => (vcd::VarType::Wire, bits.len() as u32),
+            Change::Real(_) => (vcd::VarType::Real, 64),
+            Change::String(_) => (vcd::VarType::String, 1),
+        }
+    }
+}
+
+/// Add a variable to the scope at `path`, creating missing scopes.
+pub(crate) fn insert_var(items: &mut Vec<vcd::ScopeItem>, path: &[&str], var: vcd::Var) {
+    let (first, rest) = match path.split_first() {
+        Some(split) => split,
+        None => return items.push(vcd::ScopeItem::Var(var)),
+    };
+    let index = items.iter().position(
+        |item| matches!(item, vcd::ScopeItem::Scope(scope) if scope.identifier == *first),
+    );
+    let index = index.unwrap_or_else(|| {
+        items.push(vcd::ScopeItem::Scope(vcd::Scope {
+            scope_type: vcd::ScopeType::Module,
+            identifier: first.to_string(),
+            children: vec![],
+        }));
+        items.len() - 1
+    });
+    if let vcd::ScopeItem::Scope(scope) = &mut items[index] {
+        insert_var(&mut scope.children, rest, var);
+    }
+}
+
+/// Convert a JSON Lines change stream back to a VCD file.
+///
+/// The variable types are derived from the first value of each signal,
+/// strings of `0`, `1`, `x` and `z` are read as vectors. The dots in the
+/// signal paths separate the scopes. Changes are sorted by
+/// time, empty lines are skipped.
+pub fn import<R, W>(read: R, output: W) -> IOResult<()>
+where
+    R: BufRead,
+    W: std::io::Write,
+{
+    let mut signals: Vec<(String, vcd::IdCode)> = vec![];
+    let mut header = vcd::Header::default();
+    header.timescale = Some((1, vcd::TimescaleUnit::NS));
+    let mut changes = vec![];
+    for line in read.lines() {
+        let line = line?;
+        if line.trim().is_empty() {
+            continue;
+        }
+        let (mut t, mut sig, mut v) = (None, None, None);
+        for (key, value) in parse_object(&line)? {
+            match (key.as_str(), value) {
+                ("t", Json::Number(n)) => {
+                    t = Some(n.parse::<u64>().map_err(|_| invalid("invalid time"))?)
+                }
+                ("sig", Json::String(s)) => sig = Some(s),
+                ("v", value) => v = Some(Change::from_json(value)?),
+                _ => {}
+            }
+        }
+        let (t, sig, v) = match (t, sig, v) {
+            (Some(t), Some(sig), Some(v)) => (t, sig, v),
+            _ => return Err(invalid("expected fields t, sig and v")),
+        };
+        let id = match signals.iter().find(|(name, _)| *name == sig) {
+            Some((_, id)) => *id,
+            None => {
+                let id = vcd::IdCode::from(signals.len() as u64);
+                let path: Vec<&str> = sig.split('.').collect();
+                let (reference, scopes) = path.split_last().unwrap();
+                let (var_type, size) = v.var_type();
+                let var = vcd::Var {
+                    var_type,
+                    size,
+                    code: id,
+                    reference: reference.to_string(),
+                    index: None,
+                };
+                insert_var(&mut header.items, scopes, var);
+                signals.push((sig, id));
+                id
+            }
+        };
+        changes.push((t, id, v));
+    }
+    changes.sort_by_key(|(t, _, _)| *t);
+
+    let mut writer = vcd::Writer::new(output);
+    writer.header(&header)?;
+    let mut time = None;
+    for (t, id, change) in changes {
+        if time != Some(t) {
+            writer.timestamp(t)?;
+            time = Some(t);
+        }
+        match change {
+            Change::Scalar(value) => writer.change_scalar(id, value)?,
+            Change::Vector(values) => writer.change_vector(id, &values)?,
+            Change::Real(value) => writer.change_real(id, value)?,
+            Change::String(value) => {
+                writer.change_string(id, &crate::writer::escape_string(&value))?
+            }
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn export_import() {
+        let vcd = "$timescale 10 ns $end
+$scope module top $end
+$scope module spi $end
+$var wire 1 ! sck $end
+$var wire 4 \" data $end
+$upscope $end
+$var string 1 # state $end
+$upscope $end
+$enddefinitions $end
+#0
+0!
+bx010 \"
+sIdle #
+#10
+1!
+sTx #
+";
+        let mut jsonl = vec![];
+        export(vcd.as_bytes(), &mut jsonl).unwrap();
+        let jsonl = String::from_utf8(jsonl).unwrap();
+        assert_eq!(
+            jsonl,
+            r#"{"t":0,"sig":"top.spi.sck","v":0}
+{"t":0,"sig":"top.spi.data","v":"x010"}
+{"t":0,"sig":"top.state","v":"Idle"}
+{"t":100,"sig":"top.spi.sck","v":1}
+{"t":100,"sig":"top.state","v":"Tx"}
+"#
+        );
+
+        let mut output = vec![];
+        import(jsonl.as_bytes(), &mut output).unwrap();
+        let output = String::from_utf8(output).unwrap();
+        assert_eq!(
+            output,
+            "$timescale 1 ns $end
+$scope module top $end
+$scope module spi $end
+$var wire 1 ! sck $end
+$var wire 4 \" data $end
+$upscope $end
+$var string 1 # state $end
+$upscope $end
+$enddefinitions $end
+#0
+0!
+bx010 \"
+sIdle #
+#100
+1!
+sTx #
+"
+        );
+        assert!(import(&b"{\"t\":1}\n"[..], std::io::sink()).is_err());
+        assert_eq!(
+            parse_object(r#"{ "a" : "q\"A" }"#).unwrap(),
+            vec![("a".to_string(), Json::String("q\"A".to_string()))]
+        );
+        assert_eq!(
+            parse_object(r#"{"a":"\uD83D\uDE00\u00e4"}"#).unwrap(),
+            vec![("a".to_string(), Json::String("\u{1f600}ä".to_string()))]
+        );
+        assert!(parse_object(r#"{"a":"\uD83D"}"#).is_err());
+        assert!(parse_object(r#"{"a":"\uDE00\uD83D"}"#).is_err());
+        let overflow = vcd.replace("10 ns", "1 s").replace("#10", "#18446744074");
+        let error = export(overflow.as_bytes(), std::io::sink()).unwrap_err();
+        assert_eq!(error.kind(), ErrorKind::InvalidData);
+    }
+}
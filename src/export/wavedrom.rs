@@ -0,0 +1,332 @@
+//! WaveDrom timing diagrams of scalar signals.
+//!
+//! [WaveDrom](https://wavedrom.com/) describes each signal of a diagram in
+//! WaveJSON as a string of states, one character per sample period: `0`,
+//! `1`, `z` and `x`, or `.` if the state did not change:
+//!
+//! ```text
+//! {"signal": [
+//!   {"name": "top.clk", "wave": "01010101"},
+//!   {"name": "top.cs", "wave": "10.....1"}
+//! ]}
+//! ```
+//!
+//! The sample period is not part of the diagram, so it is an argument of
+//! the exporter and the importer.
+
+use super::jsonl::{invalid, parse_string, quote};
+use crate::analysis::Waveform;
+use crate::pins::PinState;
+use embedded_time::duration::*;
+use std::io::Read;
+use std::io::Result as IOResult;
+
+fn wave_char(state: Option<PinState>) -> char {
+    match state {
+        Some(PinState::Low) => '0',
+        Some(PinState::High) => '1',
+        Some(PinState::Floating) => 'z',
+        None => 'x',
+    }
+}
+
+fn check_period(period: Nanoseconds<u64>) -> IOResult<()> {
+    match period.0 {
+        0 => Err(invalid("the sample period must not be zero")),
+        _ => Ok(()),
+    }
+}
+
+/// Write the scalar signals of a VCD file as WaveDrom diagram, sampled every
+/// `period` from zero to the last change.
+///
+/// Changes within a period are not shown, states before the first change
+/// of a signal are written as `x`.
+///
+/// ```
+/// use embedded_hal_vcd::export::wavedrom;
+/// use embedded_time::duration::*;
+///
+/// let vcd = b"$timescale 1 ns $end
+/// $scope module top $end
+/// $var wire 1 ! clk $end
+/// $var wire 1 \" cs $end
+/// $upscope $end
+/// $enddefinitions $end
+/// #0
+/// 0!
+/// 1\"
+/// #100
+/// 1!
+/// 0\"
+/// #200
+/// 0!
+/// #300
+/// 1!
+/// 1\"
+/// ";
+/// let mut json = vec![];
+/// wavedrom::export(&vcd[..], &mut json, Nanoseconds(100)).unwrap();
+/// assert_eq!(
+///     String::from_utf8(json).unwrap(),
+///     r#"{"signal": [
+///   {"name": "top.clk", "wave": "0101"},
+///   {"name": "top.cs", "wave": "10.1"}
+/// ]}
+/// "#
+/// );
+/// ```
+pub fn export<R, W>(read: R, mut output: W, period: Nanoseconds<u64>) -> IOResult<()>
+where
+    R: Read,
+    W: std::io::Write,
+{
+    check_period(period)?;
+    let signals = Waveform::read_all(read)?;
+    let end = signals
+        .iter()
+        .filter_map(|(_, waveform)| waveform.changes().last())
+        .map(|(t, _)| t.0)
+        .max();
+    let samples = end.map_or(0, |end| end / period.0 + 1);
+    let lines: Vec<String> = signals
+        .iter()
+        .map(|(name, waveform)| {
+            let mut wave = String::new();
+            let mut last = None;
+            for i in 0..samples {
+                let c = wave_char(waveform.state_at(Nanoseconds(i * period.0)));
+                wave.push(if last == Some(c) { '.' } else { c });
+                last = Some(c);
+            }
+            format!(
+                "  {{\"name\": {}, \"wave\": {}}}",
+                quote(name),
+                quote(&wave)
+            )
+        })
+        .collect();
+    writeln!(output, "{{\"signal\": [\n{}\n]}}", lines.join(",\n"))
+}
+
+/// A value of a WaveJSON diagram.
+enum Json {
+    String(String),
+    Array(Vec<Json>),
+    Object(Vec<(String, Json)>),
+    /// A number, `true`, `false` or `null`.
+    Other,
+}
+
+type Chars<'a> = std::iter::Peekable<std::str::Chars<'a>>;
+
+fn skip_ws(chars: &mut Chars) {
+    while chars.next_if(|c| c.is_whitespace()).is_some() {}
+}
+
+fn parse_value(chars: &mut Chars) -> IOResult<Json> {
+    skip_ws(chars);
+    let (open, close) = match chars.peek() {
+        Some('"') => return parse_string(chars).map(Json::String),
+        Some('[') => ('[', ']'),
+        Some('{') => ('{', '}'),
+        Some(c) if c.is_ascii_alphanumeric() || *c == '-' => {
+            while chars
+                .next_if(|c| c.is_ascii_alphanumeric() || "+-.".contains(*c))
+                .is_some()
+            {}
+            return Ok(Json::Other);
+        }
+        _ => return Err(invalid("expected JSON value")),
+    };
+    chars.next();
+    let mut items = vec![];
+    let mut fields = vec![];
+    skip_ws(chars);
+    if chars.next_if_eq(&close).is_none() {
+        loop {
+            skip_ws(chars);
+            if open == '{' {
+                let key = parse_string(chars)?;
+                skip_ws(chars);
+                if chars.next() != Some(':') {
+                    return Err(invalid("expected ':'"));
+                }
+                fields.push((key, parse_value(chars)?));
+            } else {
+                items.push(parse_value(chars)?);
+            }
+            skip_ws(chars);
+            match chars.next() {
+                Some(',') => {}
+                Some(c) if c == close => break,
+                _ => return Err(invalid("expected ',' or the end of a list")),
+            }
+        }
+    }
+    Ok(match open {
+        '[' => Json::Array(items),
+        _ => Json::Object(fields),
+    })
+}
+
+/// Collect the signals of a list of signals and groups, the names of the
+/// groups are prefixed to the names of their signals.
+fn collect_signals(
+    items: &[Json],
+    prefix: &str,
+    period: u64,
+    signals: &mut Vec<(String, Waveform)>,
+) -> IOResult<()> {
+    for item in items {
+        match item {
+            Json::Object(fields) => {
+                let field = |key| {
+                    fields.iter().find_map(|(k, v)| match v {
+                        Json::String(s) if k == key => Some(s.as_str()),
+                        _ => None,
+                    })
+                };
+                // objects without a wave are spacers
+                if let (Some(name), Some(wave)) = (field("name"), field("wave")) {
+                    let name = format!("{}{}", prefix, name);
+                    signals.push((name, parse_wave(wave, period)?));
+                }
+            }
+            Json::Array(group) => {
+                let prefix = match group.first() {
+                    Some(Json::String(name)) => format!("{}{}.", prefix, name),
+                    _ => prefix.to_string(),
+                };
+                collect_signals(group, &prefix, period, signals)?;
+            }
+            _ => {}
+        }
+    }
+    Ok(())
+}
+
+/// Convert a wave string to a waveform.
+///
+/// Clocks (`p`, `n` and upper case with an arrow) are high or low for the
+/// first half of a period and toggle in the middle of it.
+fn parse_wave(wave: &str, period: u64) -> IOResult<Waveform> {
+    let mut changes = vec![];
+    let mut last = None;
+    for (i, c) in wave.chars().enumerate() {
+        let c = match c {
+            '.' | '|' => last.ok_or_else(|| invalid("the wave starts with a repetition"))?,
+            c => c,
+        };
+        let t = i as u64 * period;
+        let (first, second) = match c {
+            '0' | 'l' | 'L' | 'd' => (PinState::Low, None),
+            '1' | 'h' | 'H' | 'u' => (PinState::High, None),
+            'z' => (PinState::Floating, None),
+            'p' | 'P' => (PinState::High, Some(PinState::Low)),
+            'n' | 'N' => (PinState::Low, Some(PinState::High)),
+            // a state before the first change is unknown
+            'x' if changes.is_empty() => {
+                last = Some(c);
+                continue;
+            }
+            _ => return Err(invalid("unsupported wave character")),
+        };
+        changes.push((Nanoseconds(t), first));
+        if let Some(second) = second {
+            changes.push((Nanoseconds(t + period / 2), second));
+        }
+        last = Some(c);
+    }
+    Ok(Waveform::new(changes))
+}
+
+/// Convert the signals of a WaveDrom diagram to a VCD file, with a
+/// character of a wave every `period`.
+///
+/// The signals are added to the scope `top`, with the names of groups as
+/// scopes within it. The states `0`, `1`, `z`, `l`, `h`, `d` and `u` and
+/// the clocks `p`, `n`, `P` and `N` are supported, `x` only before the
+/// first state. Data (`=` and `2` to `9`) is rejected.
+///
+/// ```
+/// use embedded_hal_vcd::analysis::Waveform;
+/// use embedded_hal_vcd::export::wavedrom;
+/// use embedded_time::duration::*;
+///
+/// let json = r#"{"signal": [["spi", {"name": "sck", "wave": "lp.."}]]}"#;
+/// let mut vcd = vec![];
+/// wavedrom::import(json.as_bytes(), &mut vcd, Nanoseconds(100)).unwrap();
+/// let sck = Waveform::read(&vcd[..], &["top", "spi", "sck"]).unwrap();
+/// assert_eq!(sck.pulses(embedded_hal_vcd::pins::PinState::High).len(), 3);
+/// ```
+pub fn import<R, W>(mut read: R, output: W, period: Nanoseconds<u64>) -> IOResult<()>
+where
+    R: Read,
+    W: std::io::Write,
+{
+    check_period(period)?;
+    let mut text = String::new();
+    read.read_to_string(&mut text)?;
+    let mut chars = text.chars().peekable();
+    let fields = match parse_value(&mut chars)? {
+        Json::Object(fields) => fields,
+        _ => return Err(invalid("expected JSON object")),
+    };
+    let items = fields.iter().find_map(|(key, value)| match value {
+        Json::Array(items) if key == "signal" => Some(items),
+        _ => None,
+    });
+    let items = items.ok_or_else(|| invalid("expected a signal list"))?;
+    let mut signals = vec![];
+    collect_signals(items, "top.", period.0, &mut signals)?;
+    Waveform::write_all(&signals, output)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn export_import() {
+        let json = r#"{
+  "signal": [
+    {"name": "clk", "wave": "p...."},
+    {},
+    ["bus",
+      {"name": "cs", "wave": "10..1", "node": ".a"},
+      {"name": "data", "wave": "x0h.z", "phase": 0.5}
+    ]
+  ],
+  "config": {"hscale": 2}
+}"#;
+        let mut vcd = vec![];
+        import(json.as_bytes(), &mut vcd, Nanoseconds(100)).unwrap();
+        let signals = Waveform::read_all(&vcd[..]).unwrap();
+        let names: Vec<&str> = signals.iter().map(|(name, _)| name.as_str()).collect();
+        assert_eq!(names, ["top.clk", "top.bus.cs", "top.bus.data"]);
+        assert_eq!(signals[0].1.pulses(PinState::High).len(), 5);
+        assert_eq!(signals[2].1.state_at(Nanoseconds(50)), None);
+
+        let mut exported = vec![];
+        export(&vcd[..], &mut exported, Nanoseconds(50)).unwrap();
+        assert_eq!(
+            String::from_utf8(exported).unwrap(),
+            r#"{"signal": [
+  {"name": "top.clk", "wave": "1010101010"},
+  {"name": "top.bus.cs", "wave": "1.0.....1."},
+  {"name": "top.bus.data", "wave": "x.0.1...z."}
+]}
+"#
+        );
+
+        let mut vcd = vec![];
+        let error = import(
+            &br#"{"signal": [{"name": "d", "wave": "=.2"}]}"#[..],
+            &mut vcd,
+            Nanoseconds(1),
+        );
+        assert_eq!(error.unwrap_err().kind(), std::io::ErrorKind::InvalidData);
+        assert!(export(&b""[..], &mut vcd, Nanoseconds(0)).is_err());
+    }
+}
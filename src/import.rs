@@ -0,0 +1,600 @@
+//! Import data of other tools onto the timeline of VCD files.
+//!
+//! A [DefmtLog] holds the messages printed by `defmt-print` (or `probe-rs`)
+//! and merges them into a recorded VCD file, so log messages and waveforms
+//! can be correlated in one viewer.
+//!
+//! A [ScopeCapture] holds the analog samples exported by an oscilloscope and
+//! converts them to digital [Waveform]s with hysteresis thresholds, so scope
+//! captures can be replayed and analyzed like logic analyzer recordings.
+
+use crate::analysis::Waveform;
+use crate::pins::*;
+use crate::writer::escape_string;
+use embedded_time::duration::*;
+use std::io::Result as IOResult;
+use std::io::{Error, ErrorKind, Read};
+
+/// A single log message.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct LogEntry {
+    /// The timestamp of the message.
+    pub time: Nanoseconds<u64>,
+    /// The log level, e.g. `INFO`.
+    pub level: Option<String>,
+    /// The message text.
+    pub message: String,
+    /// The event tag, if the message starts with `@<tag>`.
+    pub tag: Option<String>,
+}
+
+const LEVELS: [&str; 5] = ["TRACE", "DEBUG", "INFO", "WARN", "ERROR"];
+
+/// Parse a timestamp, either in seconds with a fraction or in ticks.
+fn parse_time(text: &str, tick: Nanoseconds<u64>) -> Option<Nanoseconds<u64>> {
+    match text.split_once('.') {
+        Some((seconds, fraction)) => {
+            if fraction.is_empty() || fraction.len() > 9 {
+                return None;
+            }
+            let scale = 10u64.pow(9 - fraction.len() as u32);
+            let seconds: u64 = seconds.parse().ok()?;
+            let fraction: u64 = fraction.parse().ok()?;
+            let ns = seconds
+                .checked_mul(1_000_000_000)?
+                .checked_add(fraction * scale)?;
+            Some(Nanoseconds(ns))
+        }
+        None => text
+            .parse::<u64>()
+            .ok()?
+            .checked_mul(tick.0)
+            .map(Nanoseconds),
+    }
+}
+
+/// Messages of a `defmt` log with timestamps.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct DefmtLog {
+    entries: Vec<LogEntry>,
+}
+
+impl DefmtLog {
+    /// Parse the text output of `defmt-print`.
+    ///
+    /// Each message has to start with a timestamp, optionally in brackets,
+    /// followed by an optional log level and the message:
+    ///
+    /// ```text
+    /// 0.000100 INFO  @tx_start len=4
+    /// [1234] WARN retry
+    /// ```
+    ///
+    /// Timestamps with a fraction are seconds, others are counted in
+    /// `tick`s. Lines without a timestamp, like locations, are skipped.
+    pub fn parse(text: &str, tick: Nanoseconds<u64>) -> Self {
+        let mut entries = vec![];
+        for line in text.lines() {
+            let line = line.trim();
+            let (time, rest) = line.split_once(char::is_whitespace).unwrap_or((line, ""));
+            let time = time.trim_start_matches('[').trim_end_matches(']');
+            let time = match parse_time(time, tick) {
+                Some(time) => time,
+                None => continue,
+            };
+            let rest = rest.trim_start();
+            let (level, message) = match rest.split_once(char::is_whitespace) {
+                Some((level, message)) if LEVELS.contains(&level) => {
+                    (Some(level.to_string()), message.trim())
+                }
+                _ if LEVELS.contains(&rest) => (Some(rest.to_string()), ""),
+                _ => (None, rest),
+            };
+            let tag = message
+                .strip_prefix('@')
+                .and_then(|m| m.split_whitespace().next())
+                .map(|tag| tag.to_string());
+            entries.push(LogEntry {
+                time,
+                level,
+                message: message.to_string(),
+                tag,
+            });
+        }
+        entries.sort_by_key(|e| e.time);
+        DefmtLog { entries }
+    }
+
+    /// Return the messages ordered by time.
+    pub fn entries(&self) -> &[LogEntry] {
+        &self.entries
+    }
+
+    /// Copy a VCD file and add the messages of the log.
+    ///
+    /// The messages are added to a scope `log` with the string variables
+    /// `message` and `level` and an event variable for each tag, which
+    /// fires at every message with that tag. The log times are expected to
+    /// start at the same time as the VCD file.
+    pub fn merge<R, W>(&self, read: R, output: W) -> IOResult<()>
+    where
+        R: Read,
+        W: std::io::Write,
+    {
+        let mut parser = vcd::Parser::new(read);
+        let mut header = parser.parse_header()?;
+        let (scale, unit) = header.timescale.unwrap_or((1, vcd::TimescaleUnit::NS));
+        let to_ticks = |e: &LogEntry| {
+            let ticks = (e.time.0 as u128 * unit.divisor() as u128)
+                .checked_div(scale as u128 * 1_000_000_000)
+                .and_then(|ticks| u64::try_from(ticks).ok());
+            ticks.ok_or_else(|| {
+                let message = format!("the log time {} ns exceeds the timescale", e.time.0);
+                Error::new(ErrorKind::InvalidData, message)
+            })
+        };
+        let entries = self
+            .entries
+            .iter()
+            .map(|e| Ok((to_ticks(e)?, e)))
+            .collect::<IOResult<Vec<_>>>()?;
+
+        let mut code = max_code(&header.items)
+            .map(|c| c.next())
+            .unwrap_or(vcd::IdCode::FIRST);
+        let mut var = |var_type, reference: &str| {
+            let id = code;
+            code = code.next();
+            vcd::Var {
+                var_type,
+                size: 1,
+                code: id,
+                reference: reference.to_string(),
+                index: None,
+            }
+        };
+        let message = var(vcd::VarType::String, "message");
+        let level = var(vcd::VarType::String, "level");
+        let mut tags: Vec<vcd::Var> = vec![];
+        for tag in self.entries.iter().filter_map(|e| e.tag.as_ref()) {
+            if !tags.iter().any(|v| &v.reference == tag) {
+                tags.push(var(vcd::VarType::Event, tag));
+            }
+        }
+        let mut children = vec![
+            vcd::ScopeItem::Var(message.clone()),
+            vcd::ScopeItem::Var(level.clone()),
+        ];
+        children.extend(tags.iter().cloned().map(vcd::ScopeItem::Var));
+        header.items.push(vcd::ScopeItem::Scope(vcd::Scope {
+            scope_type: vcd::ScopeType::Module,
+            identifier: "log".to_string(),
+            children,
+        }));
+
+        let mut writer = vcd::Writer::new(output);
+        writer.header(&header)?;
+        let mut time = None;
+        let write_entry =
+            |writer: &mut vcd::Writer<W>, time: &mut Option<u64>, t: u64, e: &LogEntry| {
+                if *time != Some(t) {
+                    writer.timestamp(t)?;
+                    *time = Some(t);
+                }
+                writer.change_string(message.code, &escape_string(&e.message))?;
+                if let Some(l) = &e.level {
+                    writer.change_string(level.code, l)?;
+                }
+                if let Some(tag) = &e.tag {
+                    let event = tags.iter().find(|v| &v.reference == tag).unwrap();
+                    writer.change_scalar(event.code, vcd::Value::V1)?;
+                }
+                IOResult::Ok(())
+            };
+        let mut entries = entries.into_iter().peekable();
+        for command in parser {
+            let command = command?;
+            if let vcd::Command::Timestamp(t) = command {
+                while let Some((ticks, e)) = entries.next_if(|(ticks, _)| *ticks < t) {
+                    write_entry(&mut writer, &mut time, ticks, e)?;
+                }
+                writer.timestamp(t)?;
+                time = Some(t);
+                while let Some((ticks, e)) = entries.next_if(|(ticks, _)| *ticks == t) {
+                    write_entry(&mut writer, &mut time, ticks, e)?;
+                }
+            } else {
+                writer.command(&command)?;
+            }
+        }
+        for (ticks, e) in entries {
+            write_entry(&mut writer, &mut time, ticks, e)?;
+        }
+        Ok(())
+    }
+}
+
+/// Return the highest identifier used by the variables of a header.
+fn max_code(items: &[vcd::ScopeItem]) -> Option<vcd::IdCode> {
+    items
+        .iter()
+        .filter_map(|item| match item {
+            vcd::ScopeItem::Var(v) => Some(v.code),
+            vcd::ScopeItem::Scope(s) => max_code(&s.children),
+        })
+        .max()
+}
+
+impl std::str::FromStr for DefmtLog {
+    type Err = Error;
+
+    /// Parse a log with timestamps in microseconds, see [DefmtLog::parse].
+    fn from_str(text: &str) -> Result<Self, Self::Err> {
+        let log = Self::parse(text, Nanoseconds(1_000));
+        if log.entries.is_empty() && !text.trim().is_empty() {
+            return Err(Error::new(ErrorKind::InvalidData, "no log messages found"));
+        }
+        Ok(log)
+    }
+}
+
+/// The hysteresis thresholds of a digital input.
+///
+/// The input changes to high when the voltage rises to `high` and to low
+/// when it falls to `low`, noise between the thresholds is ignored.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Threshold {
+    /// The voltage that has to be reached to change to low.
+    pub low: f64,
+    /// The voltage that has to be reached to change to high.
+    pub high: f64,
+}
+
+impl Threshold {
+    /// Create new thresholds.
+    pub fn new(low: f64, high: f64) -> Self {
+        Threshold { low, high }
+    }
+
+    /// Create the thresholds of a CMOS input, 30% and 70% of the supply
+    /// voltage.
+    pub fn cmos(vdd: f64) -> Self {
+        Threshold::new(0.3 * vdd, 0.7 * vdd)
+    }
+}
+
+/// Return `true` if a CSV header names the time column.
+fn is_time_column(name: &str) -> bool {
+    let name = name.to_ascii_lowercase();
+    name.starts_with("time") || name == "x"
+}
+
+/// The analog samples of an oscilloscope capture.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct ScopeCapture {
+    /// The sample times in ns, relative to the first sample.
+    times: Vec<f64>,
+    channels: Vec<(String, Vec<f64>)>,
+}
+
+impl ScopeCapture {
+    /// Create a capture of channels sampled at the same times in ns.
+    ///
+    /// Shorter channels are padded with missing samples (`NaN`).
+    pub fn new(times: Vec<f64>, channels: Vec<(String, Vec<f64>)>) -> Self {
+        let start = times.first().copied().unwrap_or(0.0);
+        let times: Vec<f64> = times.into_iter().map(|t| t - start).collect();
+        let channels = channels
+            .into_iter()
+            .map(|(name, mut values)| {
+                values.resize(times.len(), f64::NAN);
+                (name, values)
+            })
+            .collect();
+        ScopeCapture { times, channels }
+    }
+
+    /// Parse a CSV export of an oscilloscope.
+    ///
+    /// The first column is the time in seconds, the other columns are the
+    /// voltages of the channels. Lines before the first line of numbers are
+    /// metadata, a line starting with a `Time` or `X` column names the
+    /// channels; channels without a name are called `CH1`, `CH2` and so on.
+    /// Empty or invalid values are missing samples that keep the state of
+    /// the input. Times may start before zero, e.g. at a pre-trigger, they
+    /// are shifted to start at zero.
+    pub fn parse_csv<R: Read>(mut read: R) -> IOResult<Self> {
+        let mut text = String::new();
+        read.read_to_string(&mut text)?;
+        let mut names: Vec<String> = vec![];
+        let mut times = vec![];
+        let mut channels: Vec<Vec<f64>> = vec![];
+        for line in text.lines().map(str::trim).filter(|line| !line.is_empty()) {
+            let fields: Vec<&str> = line
+                .split([',', ';', '\t'])
+                .map(|field| field.trim().trim_matches('"'))
+                .collect();
+            let time = match fields[0].parse::<f64>() {
+                Ok(time) if fields.len() > 1 => time,
+                _ if times.is_empty() && is_time_column(fields[0]) => {
+                    names = fields.iter().skip(1).map(|name| name.to_string()).collect();
+                    continue;
+                }
+                _ => continue,
+            };
+            if channels.is_empty() {
+                channels = vec![vec![]; fields.len() - 1];
+            }
+            times.push(time * 1e9);
+            for (i, values) in channels.iter_mut().enumerate() {
+                let value = fields.get(i + 1).and_then(|v| v.parse().ok());
+                values.push(value.unwrap_or(f64::NAN));
+            }
+        }
+        if times.is_empty() {
+            return Err(Error::new(ErrorKind::InvalidData, "no samples found"));
+        }
+        let channels = channels
+            .into_iter()
+            .enumerate()
+            .map(|(i, values)| {
+                let name = match names.get(i) {
+                    Some(name) if !name.is_empty() => name.clone(),
+                    _ => format!("CH{}", i + 1),
+                };
+                (name, values)
+            })
+            .collect();
+        Ok(Self::new(times, channels))
+    }
+
+    /// Parse a NumPy `.npy` file of a one dimensional array with the samples
+    /// of one channel or a two dimensional array with one column per
+    /// channel, in little endian `f4` or `f8`. The channels are called
+    /// `CH1`, `CH2` and so on.
+    #[cfg(feature = "npy")]
+    pub fn parse_npy<R: Read>(mut read: R, period: Nanoseconds<u64>) -> IOResult<Self> {
+        let invalid = |message| Error::new(ErrorKind::InvalidData, message);
+        let mut data = vec![];
+        read.read_to_end(&mut data)?;
+        if data.len() < 10 || &data[..6] != b"\x93NUMPY" {
+            return Err(invalid("not a npy file"));
+        }
+        let (length, start) = match data[6] {
+            1 => (u16::from_le_bytes([data[8], data[9]]) as usize, 10),
+            _ if data.len() >= 12 => (
+                u32::from_le_bytes([data[8], data[9], data[10], data[11]]) as usize,
+                12,
+            ),
+            _ => return Err(invalid("not a npy file")),
+        };
+        let header = data
+            .get(start..start + length)
+            .and_then(|header| std::str::from_utf8(header).ok())
+            .ok_or_else(|| invalid("invalid npy header"))?;
+        let size = if header.contains("'<f8'") {
+            8
+        } else if header.contains("'<f4'") {
+            4
+        } else {
+            return Err(invalid("only little endian f4 and f8 arrays are supported"));
+        };
+        if header.contains("'fortran_order': True") {
+            return Err(invalid("fortran order is not supported"));
+        }
+        let shape: Vec<usize> = header
+            .split_once("'shape':")
+            .and_then(|(_, shape)| shape.split_once('(')?.1.split_once(')'))
+            .map(|(shape, _)| {
+                shape
+                    .split(',')
+                    .filter_map(|n| n.trim().parse().ok())
+                    .collect()
+            })
+            .ok_or_else(|| invalid("invalid npy shape"))?;
+        let columns = match shape.as_slice() {
+            [_] => 1,
+            [_, columns] => *columns,
+            _ => return Err(invalid("only one and two dimensional arrays are supported")),
+        };
+        let values: Vec<f64> = data[start + length..]
+            .chunks_exact(size)
+            .map(|bytes| match bytes.try_into() {
+                Ok(bytes) => f64::from_le_bytes(bytes),
+                Err(_) => f32::from_le_bytes(bytes.try_into().unwrap()) as f64,
+            })
+            .collect();
+        let rows = values.len() / columns.max(1);
+        let times = (0..rows).map(|i| (i as u64 * period.0) as f64).collect();
+        let channels = (0..columns)
+            .map(|c| {
+                let values = values.iter().skip(c).step_by(columns).copied().collect();
+                (format!("CH{}", c + 1), values)
+            })
+            .collect();
+        Ok(Self::new(times, channels))
+    }
+
+    /// Return the names of the channels.
+    pub fn channels(&self) -> Vec<&str> {
+        self.channels
+            .iter()
+            .map(|(name, _)| name.as_str())
+            .collect()
+    }
+
+    /// Convert a channel to a digital waveform, `None` if there is no
+    /// channel with that name.
+    ///
+    /// The state is set by the first sample outside of the thresholds, the
+    /// time of a change is interpolated between the two samples around the
+    /// crossed threshold.
+    pub fn digitize(&self, channel: &str, threshold: Threshold) -> Option<Waveform> {
+        let (_, values) = self.channels.iter().find(|(name, _)| name == channel)?;
+        let mut changes = vec![];
+        let mut state: Option<PinState> = None;
+        let mut last: Option<(f64, f64)> = None;
+        for (t, v) in self.times.iter().zip(values).filter(|(_, v)| !v.is_nan()) {
+            let next = if *v >= threshold.high {
+                PinState::High
+            } else if *v <= threshold.low {
+                PinState::Low
+            } else {
+                last = Some((*t, *v));
+                continue;
+            };
+            if state.as_ref() != Some(&next) {
+                let level = match next {
+                    PinState::High => threshold.high,
+                    _ => threshold.low,
+                };
+                let time = match (state.is_some(), last) {
+                    (true, Some((t0, v0))) if v0 != *v => t0 + (t - t0) * (level - v0) / (v - v0),
+                    (true, _) => *t,
+                    (false, _) => 0.0,
+                };
+                changes.push((Nanoseconds(time.max(0.0).round() as u64), next.clone()));
+                state = Some(next);
+            }
+            last = Some((*t, *v));
+        }
+        Some(Waveform::new(changes))
+    }
+
+    /// Convert all channels with the same thresholds.
+    pub fn digitize_all(&self, threshold: Threshold) -> Vec<(String, Waveform)> {
+        self.channels
+            .iter()
+            .filter_map(|(name, _)| Some((name.clone(), self.digitize(name, threshold)?)))
+            .collect()
+    }
+
+    /// Convert all channels and write them to a VCD file in the scope
+    /// `scope`, e.g. to replay them with a
+    /// [VcdReader](crate::reader::VcdReader).
+    pub fn write_vcd<W: std::io::Write>(&self, threshold: Threshold, output: W) -> IOResult<()> {
+        let signals: Vec<(String, Waveform)> = self
+            .digitize_all(threshold)
+            .into_iter()
+            .map(|(name, waveform)| (format!("scope.{}", name), waveform))
+            .collect();
+        Waveform::write_all(&signals, output)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn merge_defmt_log() {
+        let log: DefmtLog = "0.000000150 INFO  @tx_start len=4
+└─ app::uart @ src/main.rs:12
+[0.000000300] WARN retry
+400 @tx_start again"
+            .parse()
+            .unwrap();
+        assert_eq!(log.entries().len(), 3);
+        assert_eq!(log.entries()[0].time, Nanoseconds(150u64));
+        assert_eq!(log.entries()[0].tag.as_deref(), Some("tx_start"));
+        assert_eq!(log.entries()[2].time, Nanoseconds(400_000u64));
+
+        let vcd = "$timescale 1 ns $end
+$scope module top $end
+$var wire 1 ! tx $end
+$upscope $end
+$enddefinitions $end
+#0
+1!
+#300
+0!
+";
+        let mut output = vec![];
+        log.merge(vcd.as_bytes(), &mut output).unwrap();
+        let merged = String::from_utf8(output).unwrap();
+        assert!(merged.contains(
+            "$scope module log $end
+$var string 1 \" message $end
+$var string 1 # level $end
+$var event 1 $ tx_start $end
+$upscope $end"
+        ));
+        assert!(merged.ends_with(
+            "#0
+1!
+#150
+s@tx_start_len=4 \"
+sINFO #
+1$
+#300
+sretry \"
+sWARN #
+0!
+#400000
+s@tx_start_again \"
+1$
+"
+        ));
+
+        // times that overflow are skipped or rejected
+        let log = DefmtLog::parse(
+            "18446744074.0 INFO late\n18446744073709552 WARN late",
+            Nanoseconds(1_000),
+        );
+        assert!(log.entries().is_empty());
+        let log: DefmtLog = "20000.0 INFO late".parse().unwrap();
+        let vcd = vcd.replace("1 ns", "1 fs");
+        let error = log.merge(vcd.as_bytes(), &mut vec![]).unwrap_err();
+        assert_eq!(error.kind(), ErrorKind::InvalidData);
+    }
+
+    #[test]
+    fn digitize_scope_capture() {
+        let csv = "Model,DS1054Z
+Time,CH1,CH2
+s,V,V
+-1.0e-06,0.1,3.3
+-0.5e-06,1.5,3.2
+0.0e-06,3.1,
+0.5e-06,1.4,0.2
+1.0e-06,2.4,0.0
+1.5e-06,0.4,0.1
+";
+        let capture = ScopeCapture::parse_csv(csv.as_bytes()).unwrap();
+        assert_eq!(capture.channels(), vec!["CH1", "CH2"]);
+        let ch1 = capture.digitize("CH1", Threshold::cmos(3.3)).unwrap();
+        // 2.31 V is crossed between 1.5 V and 3.1 V, 1.4 V is between the
+        // thresholds
+        assert_eq!(
+            ch1.changes(),
+            &[
+                (Nanoseconds(0), PinState::Low),
+                (Nanoseconds(753), PinState::High),
+                (Nanoseconds(2_353), PinState::Low),
+            ]
+        );
+        let ch2 = capture.digitize("CH2", Threshold::new(0.8, 2.0)).unwrap();
+        // the missing sample is skipped
+        assert_eq!(ch2.changes()[1], (Nanoseconds(1_300), PinState::Low));
+        assert!(capture.digitize("CH3", Threshold::cmos(3.3)).is_none());
+        assert!(ScopeCapture::parse_csv("Time,CH1\n".as_bytes()).is_err());
+
+        let mut vcd = vec![];
+        capture.write_vcd(Threshold::cmos(3.3), &mut vcd).unwrap();
+        let waveforms = Waveform::read_all(vcd.as_slice()).unwrap();
+        assert_eq!(waveforms[0], ("scope.CH1".to_string(), ch1.clone()));
+
+        #[cfg(feature = "npy")]
+        {
+            let header = "{'descr': '<f8', 'fortran_order': False, 'shape': (6, 1), }";
+            let mut npy = b"\x93NUMPY\x01\x00".to_vec();
+            npy.extend((header.len() as u16).to_le_bytes());
+            npy.extend(header.as_bytes());
+            for v in [0.1, 1.5, 3.1, 1.4, 2.4, 0.4f64] {
+                npy.extend(v.to_le_bytes());
+            }
+            let capture = ScopeCapture::parse_npy(npy.as_slice(), Nanoseconds(500)).unwrap();
+            assert_eq!(capture.digitize("CH1", Threshold::cmos(3.3)), Some(ch1));
+        }
+    }
+}
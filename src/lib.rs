@@ -1,7 +1,42 @@
 //! Read and Write VCD (Value Change Dump) files and provide [embedded_hal] pin
 //! implementations that reflect the VCD state.
+//!
+//! All modules except [trace] need the `std` feature, which is enabled by
+//! default. Without it the crate is `no_std` and only needs `alloc`.
 
+#![cfg_attr(not(feature = "std"), no_std)]
 #![warn(missing_docs)]
-pub use embedded_hal_sync_pins::pins;
+
+extern crate alloc;
+
+#[cfg(feature = "std")]
+pub mod analysis;
+#[cfg(feature = "tokio")]
+pub mod async_io;
+#[cfg(feature = "std")]
+pub mod capture;
+#[cfg(feature = "std")]
+pub mod devices;
+#[cfg(feature = "std")]
+pub mod emulator;
+#[cfg(feature = "std")]
+pub mod export;
+#[cfg(feature = "std")]
+pub mod import;
+#[cfg(feature = "std")]
+pub mod models;
+#[cfg(feature = "std")]
+pub mod pins;
+#[cfg(feature = "std")]
+pub mod player;
+#[cfg(feature = "std")]
 pub mod reader;
+#[cfg(feature = "std")]
+pub mod stream;
+#[cfg(feature = "std")]
+pub mod testing;
+pub mod trace;
+#[cfg(feature = "std")]
+pub mod units;
+#[cfg(feature = "std")]
 pub mod writer;
@@ -0,0 +1,485 @@
+//! First order plant models for closed loop tests.
+//!
+//! A plant is a [SimDevice] that is driven by a digital [Signal] of the code
+//! under test, e.g. a PWM pin or a heater switch, and produces an [Analog]
+//! value. The value follows an exponential step response towards the target
+//! of the current input state, which is evaluated exactly at any time, so
+//! the models need no time step. The value can be recorded with
+//! [VcdWriterBuilder::add_real](crate::writer::VcdWriterBuilder::add_real)
+//! or read by the code under test in the loop.
+//!
+//! ```
+//! use embedded_hal::digital::OutputPin;
+//! use embedded_hal_vcd::devices::Simulation;
+//! use embedded_hal_vcd::models::RcFilter;
+//! use embedded_hal_vcd::pins::*;
+//! use embedded_time::duration::*;
+//! use std::sync::Arc;
+//!
+//! let mut sim = Simulation::new();
+//! let pwm = sim.add_signal(Arc::new(AtomicPinState::new_with_state(PinState::Low)));
+//! // 10 kΩ and 100 nF, a time constant of 1 ms
+//! let filter = RcFilter::new(pwm, 10e3, 100e-9, 3.3);
+//! let output = filter.output();
+//! sim.add_device(filter);
+//! sim.push_pull_pin(pwm).set_high().unwrap();
+//! sim.advance_to(Nanoseconds(1_000_000u64));
+//! assert!((output.at(sim.time()) - 3.3 * 0.632).abs() < 0.01);
+//! ```
+
+use crate::devices::{Edge, Response, Signal, SimDevice};
+use crate::pins::*;
+use embedded_time::duration::*;
+use std::collections::BTreeSet;
+use std::sync::{Arc, Mutex};
+
+/// The state of a first order system with a piecewise constant target.
+#[derive(Clone, Debug)]
+struct FirstOrder {
+    /// The time constant in ns.
+    tau: f64,
+    /// The time of the last change of the target.
+    time: u64,
+    /// The value at `time`.
+    value: f64,
+    target: f64,
+}
+
+impl FirstOrder {
+    fn at(&self, t: u64) -> f64 {
+        let dt = t.saturating_sub(self.time) as f64;
+        self.target + (self.value - self.target) * (-dt / self.tau).exp()
+    }
+
+    fn set_target(&mut self, t: u64, target: f64) {
+        self.value = self.at(t);
+        self.time = t;
+        self.target = target;
+    }
+}
+
+/// A shared analog value of a plant model.
+///
+/// Clones share the value with the model.
+#[derive(Clone, Debug)]
+pub struct Analog(Arc<Mutex<FirstOrder>>);
+
+impl Analog {
+    fn new(tau: f64, value: f64) -> Self {
+        Analog(Arc::new(Mutex::new(FirstOrder {
+            tau,
+            time: 0,
+            value,
+            target: value,
+        })))
+    }
+
+    /// Return the value at time `t`.
+    ///
+    /// The value is extrapolated from the last change of the input, so `t`
+    /// should not be before the current time of the simulation.
+    pub fn at(&self, t: Nanoseconds<u64>) -> f64 {
+        self.0.lock().unwrap().at(t.0)
+    }
+
+    /// Return the value the model is settling to.
+    pub fn target(&self) -> f64 {
+        self.0.lock().unwrap().target
+    }
+
+    /// Return the time after `t` at which the value reaches `level`, if
+    /// the input does not change until then.
+    pub fn next_crossing(&self, t: Nanoseconds<u64>, level: f64) -> Option<Nanoseconds<u64>> {
+        let state = self.0.lock().unwrap();
+        let value = state.at(t.0);
+        // the ratio of the remaining distances, in (0, 1) if the value
+        // approaches the level
+        let ratio = (level - state.target) / (value - state.target);
+        if !(ratio > 0.0 && ratio < 1.0) {
+            return None;
+        }
+        let dt = -state.tau * ratio.ln();
+        Some(Nanoseconds(t.0 + dt.ceil() as u64))
+    }
+
+    fn set_target(&self, t: u64, target: f64) {
+        self.0.lock().unwrap().set_target(t, target);
+    }
+}
+
+/// An RC low pass filter at a digital output, e.g. to convert a PWM signal
+/// to a voltage.
+///
+/// The input drives the filter with 0 V while low and with the supply
+/// voltage while high. While the input floats the capacitor keeps its
+/// charge.
+pub struct RcFilter {
+    input: Signal,
+    vdd: f64,
+    output: Analog,
+}
+
+impl RcFilter {
+    /// Create a new filter with a resistance in Ω, a capacitance in F and
+    /// the supply voltage of the input, the capacitor is discharged.
+    pub fn new(input: Signal, resistance: f64, capacitance: f64, vdd: f64) -> Self {
+        RcFilter {
+            input,
+            vdd,
+            output: Analog::new(resistance * capacitance * 1e9, 0.0),
+        }
+    }
+
+    /// Return the voltage of the capacitor.
+    pub fn output(&self) -> Analog {
+        self.output.clone()
+    }
+}
+
+impl SimDevice for RcFilter {
+    fn on_edge(&mut self, signal: Signal, edge: Edge, t: Nanoseconds<u64>) -> Vec<Response> {
+        if signal == self.input {
+            let target = match edge.state() {
+                PinState::High => self.vdd,
+                PinState::Low => 0.0,
+                _ => self.output.at(t),
+            };
+            self.output.set_target(t.0, target);
+        }
+        vec![]
+    }
+}
+
+/// A heater with a thermal mass, switched by a digital output.
+///
+/// While the input is high the heater dissipates its power, the temperature
+/// rises towards `ambient + power * resistance`. Otherwise it cools down to
+/// the ambient temperature. The time constant is the product of the thermal
+/// resistance and the heat capacity.
+pub struct Heater {
+    input: Signal,
+    ambient: f64,
+    power: f64,
+    resistance: f64,
+    temperature: Analog,
+}
+
+impl Heater {
+    /// Create a new heater with a power in W, a thermal resistance to the
+    /// ambient in K/W and a heat capacity in J/K, starting at the ambient
+    /// temperature in °C.
+    pub fn new(input: Signal, ambient: f64, power: f64, resistance: f64, capacity: f64) -> Self {
+        Heater {
+            input,
+            ambient,
+            power,
+            resistance,
+            temperature: Analog::new(resistance * capacity * 1e9, ambient),
+        }
+    }
+
+    /// Return the temperature in °C.
+    pub fn temperature(&self) -> Analog {
+        self.temperature.clone()
+    }
+}
+
+impl SimDevice for Heater {
+    fn on_edge(&mut self, signal: Signal, edge: Edge, t: Nanoseconds<u64>) -> Vec<Response> {
+        if signal == self.input {
+            let target = match edge.state() {
+                PinState::High => self.ambient + self.power * self.resistance,
+                _ => self.ambient,
+            };
+            self.temperature.set_target(t.0, target);
+        }
+        vec![]
+    }
+}
+
+/// A comparator or window comparator that converts an [Analog] value to a
+/// digital signal, e.g. the interrupt line of an undervoltage monitor.
+///
+/// A comparator drives its output high while the value is above the
+/// threshold, a window comparator while the value is inside the window. With
+/// hysteresis each level is split into a level for rising values, half the
+/// hysteresis higher, and a level for falling values, half the hysteresis
+/// lower.
+///
+/// The comparator evaluates the value at every edge of the simulation and
+/// predicts when the value will cross a level next. To get notified at that
+/// time it toggles the otherwise unused signal `timer`. Add the comparator
+/// after the plant model, so the model handled an edge before the comparator
+/// evaluates it. The output is driven from the first edge on, e.g. a change
+/// scheduled on the timer.
+pub struct Comparator {
+    input: Analog,
+    output: Signal,
+    timer: Signal,
+    /// The lower and the optional upper level of a window.
+    levels: (f64, Option<f64>),
+    hysteresis: f64,
+    inverted: bool,
+    /// Whether the value is above the threshold or inside the window.
+    active: Option<bool>,
+    timer_high: bool,
+    /// The times of the scheduled timer changes.
+    wakes: BTreeSet<u64>,
+}
+
+impl Comparator {
+    /// Create a new comparator with a threshold.
+    pub fn new(input: Analog, output: Signal, timer: Signal, threshold: f64) -> Self {
+        Comparator {
+            input,
+            output,
+            timer,
+            levels: (threshold, None),
+            hysteresis: 0.0,
+            inverted: false,
+            active: None,
+            timer_high: false,
+            wakes: BTreeSet::new(),
+        }
+    }
+
+    /// Create a new window comparator with a lower and an upper threshold.
+    pub fn window(input: Analog, output: Signal, timer: Signal, low: f64, high: f64) -> Self {
+        Comparator {
+            levels: (low, Some(high)),
+            ..Self::new(input, output, timer, low)
+        }
+    }
+
+    /// Set the hysteresis, the difference between the levels for rising and
+    /// falling values.
+    pub fn with_hysteresis(mut self, hysteresis: f64) -> Self {
+        self.hysteresis = hysteresis;
+        self
+    }
+
+    /// Drive the output low instead of high while the value is above the
+    /// threshold or inside the window, e.g. for an active low interrupt.
+    pub fn with_inverted(mut self) -> Self {
+        self.inverted = true;
+        self
+    }
+
+    /// Return the levels at which the state changes, given the current
+    /// state.
+    fn switching_levels(&self, active: bool) -> Vec<f64> {
+        let half = self.hysteresis / 2.0;
+        match (self.levels, active) {
+            ((low, None), false) => vec![low + half],
+            ((low, None), true) => vec![low - half],
+            ((low, Some(high)), false) => vec![low + half, high - half],
+            ((low, Some(high)), true) => vec![low - half, high + half],
+        }
+    }
+
+    /// Return the state for a value.
+    fn evaluate(&self, value: f64) -> bool {
+        let half = self.hysteresis / 2.0;
+        let (low, high) = self.levels;
+        match (self.active, high) {
+            (None, None) => value >= low,
+            (None, Some(high)) => value >= low && value <= high,
+            (Some(false), None) => value >= low + half,
+            (Some(true), None) => value > low - half,
+            (Some(false), Some(high)) => value >= low + half && value <= high - half,
+            (Some(true), Some(high)) => value > low - half && value < high + half,
+        }
+    }
+
+    /// Toggle the timer at `at`, unless it is toggled at that time already,
+    /// which would cancel both changes.
+    fn wake_at(&mut self, t: u64, at: u64) -> Option<Response> {
+        if !self.wakes.insert(at) {
+            return None;
+        }
+        self.timer_high = !self.timer_high;
+        let state = if self.timer_high {
+            PinState::High
+        } else {
+            PinState::Low
+        };
+        Some((self.timer, state, Nanoseconds(at - t)))
+    }
+}
+
+impl SimDevice for Comparator {
+    fn on_edge(&mut self, signal: Signal, edge: Edge, t: Nanoseconds<u64>) -> Vec<Response> {
+        if signal == self.output {
+            return vec![];
+        }
+        if signal == self.timer {
+            self.wakes = self.wakes.split_off(&(t.0 + 1));
+            if self.wakes.is_empty() {
+                // the timer may also be changed from outside
+                self.timer_high = edge.state() == PinState::High;
+            }
+        }
+        let mut responses = vec![];
+        let active = self.evaluate(self.input.at(t));
+        if self.active != Some(active) {
+            self.active = Some(active);
+            let state = if active != self.inverted {
+                PinState::High
+            } else {
+                PinState::Low
+            };
+            responses.push((self.output, state, Nanoseconds(0)));
+        }
+        let next = self
+            .switching_levels(active)
+            .into_iter()
+            .filter_map(|level| self.input.next_crossing(t, level))
+            .min();
+        responses.extend(next.and_then(|next| self.wake_at(t.0, next.0)));
+        responses
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::devices::Simulation;
+    use crate::writer::VcdWriterBuilder;
+    use embedded_hal::digital::OutputPin;
+
+    #[test]
+    fn pwm_and_heater_loop() {
+        let mut sim = Simulation::new();
+        let pwm = sim.add_signal(Arc::new(AtomicPinState::new_with_state(PinState::Low)));
+        let heat = sim.add_signal(Arc::new(AtomicPinState::new_with_state(PinState::Low)));
+        // a time constant of 1 ms and a PWM period of 10 µs
+        let filter = RcFilter::new(pwm, 1e3, 1e-6, 3.3);
+        let voltage = filter.output();
+        sim.add_device(filter);
+        // 10 W heating up to 70 °C with a time constant of 5 s
+        let heater = Heater::new(heat, 20.0, 10.0, 5.0, 1.0);
+        let temperature = heater.temperature();
+        sim.add_device(heater);
+
+        let mut builder = VcdWriterBuilder::new(Vec::new()).unwrap();
+        let recorded = temperature.clone();
+        builder
+            .add_real("temperature", move |t| recorded.at(t))
+            .unwrap();
+        let mut writer = builder.build().unwrap();
+
+        // 25% duty cycle for 10 ms
+        let mut pin = sim.push_pull_pin(pwm);
+        for period in 0..1000u64 {
+            let t = period * 10_000;
+            sim.advance_to(Nanoseconds(t));
+            pin.set_high().unwrap();
+            sim.advance_to(Nanoseconds(t + 2_500));
+            pin.set_low().unwrap();
+        }
+        sim.advance_to(Nanoseconds(10_000_000u64));
+        let v = voltage.at(sim.time());
+        assert!((v - 0.825).abs() < 0.05, "{}", v);
+
+        // a two point controller between 40 °C and 41 °C polled every 10 ms
+        let mut heater = sim.push_pull_pin(heat);
+        let mut switches = 0;
+        let mut on = false;
+        for step in 1..3000u64 {
+            let t = Nanoseconds(10_000_000 + step * 10_000_000);
+            sim.advance_to_recorded(t, &mut writer).unwrap();
+            let temperature = temperature.at(t);
+            assert!((19.9..41.1).contains(&temperature));
+            if temperature < 40.0 && !on {
+                heater.set_high().unwrap();
+                on = true;
+                switches += 1;
+            } else if temperature > 41.0 && on {
+                heater.set_low().unwrap();
+                on = false;
+            }
+        }
+        assert!(switches > 1);
+        let time = temperature.next_crossing(sim.time(), if on { 41.0 } else { 40.0 });
+        assert!(time.is_some());
+        assert_eq!(temperature.next_crossing(sim.time(), 80.0), None);
+    }
+
+    #[test]
+    fn window_comparator() {
+        let mut sim = Simulation::new();
+        let pwm = sim.add_signal(Arc::new(AtomicPinState::new_with_state(PinState::Low)));
+        let above = sim.add_signal(Arc::new(AtomicPinState::new()));
+        let inside = sim.add_signal(Arc::new(AtomicPinState::new()));
+        let timers = [(); 2].map(|_| sim.add_signal(Arc::new(AtomicPinState::new())));
+        let filter = RcFilter::new(pwm, 1e3, 1e-6, 3.3);
+        let voltage = filter.output();
+        sim.add_device(filter);
+        sim.add_device(
+            Comparator::new(voltage.clone(), above, timers[0], 2.0)
+                .with_hysteresis(0.2)
+                .with_inverted(),
+        );
+        sim.add_device(Comparator::window(
+            voltage.clone(),
+            inside,
+            timers[1],
+            1.0,
+            2.0,
+        ));
+        for timer in timers {
+            sim.schedule(timer, PinState::High, Nanoseconds(0u64));
+        }
+
+        // charge for 3 ms, discharge afterwards
+        sim.schedule(pwm, PinState::High, Nanoseconds(1_000_000u64));
+        sim.schedule(pwm, PinState::Low, Nanoseconds(4_000_000u64));
+        let mut changes: Vec<(u64, PinState, PinState)> = vec![];
+        for t in (0..8_000_000u64).step_by(1_000) {
+            sim.advance_to(Nanoseconds(t + 1));
+            let states = (sim.state(above), sim.state(inside));
+            if changes.last().map(|c| (c.1.clone(), c.2.clone())) != Some(states.clone()) {
+                changes.push((t, states.0, states.1));
+            }
+        }
+        let at = |level: f64, from: f64, to: f64| {
+            // the time a step from `from` to `to` reaches `level` in ns
+            (-1e6 * ((level - to) / (from - to)).ln()).ceil() as u64
+        };
+        let rising = 1_000_000 + at(2.1, 0.0, 3.3);
+        let v = voltage.at(Nanoseconds(4_000_000u64));
+        let falling = 4_000_000 + at(1.9, v, 0.0);
+        assert_eq!(
+            changes,
+            [
+                (0, PinState::High, PinState::Low),
+                (
+                    (1_000_000 + at(1.0, 0.0, 3.3)).div_ceil(1000) * 1000,
+                    PinState::High,
+                    PinState::High
+                ),
+                (
+                    (1_000_000 + at(2.0, 0.0, 3.3)).div_ceil(1000) * 1000,
+                    PinState::High,
+                    PinState::Low
+                ),
+                (rising.div_ceil(1000) * 1000, PinState::Low, PinState::Low),
+                (
+                    (4_000_000 + at(2.0, v, 0.0)).div_ceil(1000) * 1000,
+                    PinState::Low,
+                    PinState::High
+                ),
+                (
+                    falling.div_ceil(1000) * 1000,
+                    PinState::High,
+                    PinState::High
+                ),
+                (
+                    (4_000_000 + at(1.0, v, 0.0)).div_ceil(1000) * 1000,
+                    PinState::High,
+                    PinState::Low
+                ),
+            ]
+        );
+    }
+}
@@ -0,0 +1,1612 @@
+//! Pin types that can be shared between threads.
+//!
+//! Re-exports all pins from [embedded_hal_sync_pins::pins] and adds pin
+//! types that are specific to VCD tracing.
+
+use core::convert::Infallible;
+use core::fmt;
+use embedded_hal::digital as hal;
+use embedded_hal::digital::ErrorType;
+pub use embedded_hal_sync_pins::pins::*;
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+use std::sync::{Arc, Mutex};
+
+/// The direction of a [BidirPin].
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Direction {
+    /// The pin does not drive the line, the line state can be read.
+    Input,
+    /// The pin drives the line.
+    Output,
+}
+
+/// A bidirectional pin that can switch between input and output mode.
+///
+/// In output mode the pin drives the shared [AtomicPinState]. In input mode
+/// the pin releases the line (the state is set to
+/// [`Floating`](`PinState::Floating`)), so that another party can drive it.
+/// The output level is latched while in input mode and applied as soon as the
+/// pin is switched back to output mode, like the output register of a GPIO
+/// peripheral.
+///
+/// # Examples
+///
+/// ```
+/// use embedded_hal_vcd::pins::{AtomicPinState, BidirPin, Direction, PinState};
+/// use embedded_hal::digital::{InputPin, OutputPin};
+/// use std::sync::{Arc, atomic::{AtomicBool, Ordering}};
+///
+/// let state = Arc::new(AtomicPinState::new());
+/// let mut pin = BidirPin::new(state.clone(), Arc::new(AtomicBool::new(false)));
+/// pin.set_low().unwrap();
+/// assert_eq!(PinState::Floating, state.load(Ordering::SeqCst));
+/// pin.set_direction(Direction::Output);
+/// assert_eq!(Ok(true), pin.is_low());
+/// pin.set_direction(Direction::Input);
+/// state.store(PinState::High, Ordering::SeqCst);
+/// assert_eq!(Ok(true), pin.is_high());
+/// ```
+#[derive(Clone, Debug)]
+pub struct BidirPin {
+    state: Arc<AtomicPinState>,
+    output: Arc<AtomicBool>,
+    latch: Arc<AtomicPinState>,
+}
+
+impl BidirPin {
+    /// Creates a new bidirectional pin.
+    ///
+    /// `output` holds the direction of the pin and is `true` in output mode.
+    pub fn new(state: Arc<AtomicPinState>, output: Arc<AtomicBool>) -> Self {
+        let latch = Arc::new(AtomicPinState::new_with_state(PinState::Low));
+        let pin = BidirPin {
+            state,
+            output,
+            latch,
+        };
+        if pin.direction() == Direction::Output {
+            pin.drive();
+        }
+        pin
+    }
+
+    /// Returns the current direction of the pin.
+    pub fn direction(&self) -> Direction {
+        if self.output.load(Ordering::SeqCst) {
+            Direction::Output
+        } else {
+            Direction::Input
+        }
+    }
+
+    /// Switches the pin to the given direction.
+    pub fn set_direction(&mut self, direction: Direction) {
+        match direction {
+            Direction::Output => {
+                self.output.store(true, Ordering::SeqCst);
+                self.drive();
+            }
+            Direction::Input => {
+                self.output.store(false, Ordering::SeqCst);
+                self.state.store(PinState::Floating, Ordering::SeqCst);
+            }
+        }
+    }
+
+    /// Switches the pin to input mode.
+    pub fn set_as_input(&mut self) {
+        self.set_direction(Direction::Input);
+    }
+
+    /// Switches the pin to output mode.
+    pub fn set_as_output(&mut self) {
+        self.set_direction(Direction::Output);
+    }
+
+    fn drive(&self) {
+        self.state
+            .store(self.latch.load(Ordering::SeqCst), Ordering::SeqCst);
+    }
+
+    fn set(&mut self, state: PinState) {
+        self.latch.store(state, Ordering::SeqCst);
+        if self.direction() == Direction::Output {
+            self.drive();
+        }
+    }
+}
+
+impl ErrorType for BidirPin {
+    type Error = Infallible;
+}
+
+impl hal::OutputPin for BidirPin {
+    fn set_high(&mut self) -> Result<(), Self::Error> {
+        self.set(PinState::High);
+        Ok(())
+    }
+
+    fn set_low(&mut self) -> Result<(), Self::Error> {
+        self.set(PinState::Low);
+        Ok(())
+    }
+}
+
+impl hal::StatefulOutputPin for BidirPin {
+    fn is_set_high(&self) -> Result<bool, Self::Error> {
+        Ok(self.latch.load(Ordering::SeqCst) == PinState::High)
+    }
+
+    fn is_set_low(&self) -> Result<bool, Self::Error> {
+        Ok(self.latch.load(Ordering::SeqCst) == PinState::Low)
+    }
+}
+
+impl hal::ToggleableOutputPin for BidirPin {
+    fn toggle(&mut self) -> Result<(), Self::Error> {
+        match self.latch.load(Ordering::SeqCst) {
+            PinState::High => self.set(PinState::Low),
+            _ => self.set(PinState::High),
+        }
+        Ok(())
+    }
+}
+
+impl hal::InputPin for BidirPin {
+    fn is_high(&self) -> Result<bool, Self::Error> {
+        Ok(self.state.load(Ordering::SeqCst) == PinState::High)
+    }
+
+    fn is_low(&self) -> Result<bool, Self::Error> {
+        Ok(self.state.load(Ordering::SeqCst) == PinState::Low)
+    }
+}
+
+/// A pin with inverted logic levels.
+///
+/// Created with [Invert::inverted]. Setting the pin high sets the wrapped
+/// pin low and vice versa, reading the pin returns the inverted state of
+/// the wrapped pin. This models active-low signals like chip selects and
+/// resets, so the driver code can assert the signal with `set_high`.
+///
+/// # Examples
+///
+/// ```
+/// use embedded_hal_vcd::pins::{AtomicPinState, Invert, InputPin, PinState, PushPullPin};
+/// use embedded_hal::digital::{InputPin as _, OutputPin};
+/// use std::sync::{Arc, atomic::Ordering};
+///
+/// let state = Arc::new(AtomicPinState::new_with_state(PinState::High));
+/// let mut cs = PushPullPin::new(state.clone()).inverted();
+/// cs.set_high().unwrap();
+/// assert_eq!(PinState::Low, state.load(Ordering::SeqCst));
+/// let selected = InputPin::new(state).inverted();
+/// assert_eq!(Ok(true), selected.is_high());
+/// ```
+#[derive(Clone, Debug)]
+pub struct Inverted<P> {
+    pin: P,
+}
+
+impl<P> Inverted<P> {
+    /// Return the wrapped pin.
+    pub fn into_inner(self) -> P {
+        self.pin
+    }
+}
+
+/// Extension trait to invert the logic levels of a pin.
+pub trait Invert: Sized {
+    /// Wrap the pin in an [Inverted] pin.
+    fn inverted(self) -> Inverted<Self> {
+        Inverted { pin: self }
+    }
+}
+
+impl<P: ErrorType> Invert for P {}
+
+impl<P: ErrorType> ErrorType for Inverted<P> {
+    type Error = P::Error;
+}
+
+impl<P: hal::OutputPin> hal::OutputPin for Inverted<P> {
+    fn set_high(&mut self) -> Result<(), Self::Error> {
+        self.pin.set_low()
+    }
+
+    fn set_low(&mut self) -> Result<(), Self::Error> {
+        self.pin.set_high()
+    }
+}
+
+impl<P: hal::StatefulOutputPin> hal::StatefulOutputPin for Inverted<P> {
+    fn is_set_high(&self) -> Result<bool, Self::Error> {
+        self.pin.is_set_low()
+    }
+
+    fn is_set_low(&self) -> Result<bool, Self::Error> {
+        self.pin.is_set_high()
+    }
+}
+
+impl<P: hal::ToggleableOutputPin> hal::ToggleableOutputPin for Inverted<P> {
+    fn toggle(&mut self) -> Result<(), Self::Error> {
+        self.pin.toggle()
+    }
+}
+
+impl<P: hal::InputPin> hal::InputPin for Inverted<P> {
+    fn is_high(&self) -> Result<bool, Self::Error> {
+        self.pin.is_low()
+    }
+
+    fn is_low(&self) -> Result<bool, Self::Error> {
+        self.pin.is_high()
+    }
+}
+
+/// An access of a [Counted] pin.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum PinAccess {
+    /// The state of the pin was read.
+    Read,
+    /// The pin was set, to the same or a different level.
+    Write,
+    /// The pin was set to a different level than before.
+    Transition,
+}
+
+type Hook = Box<dyn Fn(PinAccess) + Send + Sync>;
+
+/// Counters of the accesses of a [Counted] pin.
+///
+/// A hook is called for each access, e.g. to log the accesses while a long
+/// running simulation is monitored, see also [PinCounters::with_metrics]:
+///
+/// ```
+/// use embedded_hal_vcd::pins::{AtomicPinState, Count, PinAccess, PinCounters, PushPullPin};
+/// use embedded_hal::digital::OutputPin;
+/// use std::sync::atomic::{AtomicU64, Ordering};
+/// use std::sync::Arc;
+///
+/// let transitions = Arc::new(AtomicU64::new(0));
+/// let exported = transitions.clone();
+/// let counters = PinCounters::with_hook(move |access| {
+///     if access == PinAccess::Transition {
+///         exported.fetch_add(1, Ordering::Relaxed);
+///     }
+/// });
+/// let pin = PushPullPin::new(Arc::new(AtomicPinState::new()));
+/// let mut led = pin.counted_with(Arc::new(counters));
+/// led.set_high().unwrap();
+/// led.set_high().unwrap();
+/// assert_eq!(transitions.load(Ordering::Relaxed), 1);
+/// ```
+#[derive(Default)]
+pub struct PinCounters {
+    reads: AtomicU64,
+    writes: AtomicU64,
+    transitions: AtomicU64,
+    hook: Option<Hook>,
+}
+
+impl PinCounters {
+    /// Create new counters starting at zero.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Create new counters that call `hook` for each access.
+    pub fn with_hook<F>(hook: F) -> Self
+    where
+        F: Fn(PinAccess) + Send + Sync + 'static,
+    {
+        PinCounters {
+            hook: Some(Box::new(hook)),
+            ..Self::default()
+        }
+    }
+
+    /// Create new counters that also count each access with the `metrics`
+    /// crate, as the counters `pin_reads`, `pin_writes` and `pin_transitions`
+    /// with the label `pin`.
+    ///
+    /// The metrics are registered with the recorder installed when the
+    /// counters are created.
+    ///
+    /// ```
+    /// use embedded_hal_vcd::pins::{AtomicPinState, Count, PinCounters, PushPullPin};
+    /// use embedded_hal::digital::OutputPin;
+    /// use metrics::{Counter, Gauge, Histogram, Key, KeyName, Metadata, SharedString, Unit};
+    /// use std::collections::HashMap;
+    /// use std::sync::atomic::{AtomicU64, Ordering};
+    /// use std::sync::{Arc, Mutex};
+    ///
+    /// /// A recorder that keeps the counters by name and labels.
+    /// #[derive(Default)]
+    /// struct Counters(Mutex<HashMap<String, Arc<AtomicU64>>>);
+    ///
+    /// impl metrics::Recorder for Counters {
+    ///     fn register_counter(&self, key: &Key, _metadata: &Metadata<'_>) -> Counter {
+    ///         let labels: Vec<_> = key.labels().map(|l| format!("{}={}", l.key(), l.value())).collect();
+    ///         let name = format!("{}{{{}}}", key.name(), labels.join(","));
+    ///         Counter::from_arc(self.0.lock().unwrap().entry(name).or_default().clone())
+    ///     }
+    /// #   fn describe_counter(&self, _: KeyName, _: Option<Unit>, _: SharedString) {}
+    /// #   fn describe_gauge(&self, _: KeyName, _: Option<Unit>, _: SharedString) {}
+    /// #   fn describe_histogram(&self, _: KeyName, _: Option<Unit>, _: SharedString) {}
+    /// #   fn register_gauge(&self, _: &Key, _: &Metadata<'_>) -> Gauge {
+    /// #       Gauge::noop()
+    /// #   }
+    /// #   fn register_histogram(&self, _: &Key, _: &Metadata<'_>) -> Histogram {
+    /// #       Histogram::noop()
+    /// #   }
+    /// }
+    ///
+    /// let recorder = Counters::default();
+    /// let counters = metrics::with_local_recorder(&recorder, || PinCounters::with_metrics("led"));
+    /// let mut led = PushPullPin::new(Arc::new(AtomicPinState::new())).counted_with(Arc::new(counters));
+    /// led.set_high().unwrap();
+    /// led.set_high().unwrap();
+    /// let metric = |name: &str| recorder.0.lock().unwrap()[name].load(Ordering::Relaxed);
+    /// assert_eq!(metric("pin_writes{pin=led}"), 2);
+    /// assert_eq!(metric("pin_transitions{pin=led}"), 1);
+    /// ```
+    #[cfg(feature = "metrics")]
+    pub fn with_metrics(pin: &str) -> Self {
+        let counter = |name: &'static str| metrics::counter!(name, "pin" => pin.to_string());
+        let (reads, writes, transitions) = (
+            counter("pin_reads"),
+            counter("pin_writes"),
+            counter("pin_transitions"),
+        );
+        Self::with_hook(move |access| match access {
+            PinAccess::Read => reads.increment(1),
+            PinAccess::Write => writes.increment(1),
+            PinAccess::Transition => transitions.increment(1),
+        })
+    }
+
+    /// Return the number of reads.
+    pub fn reads(&self) -> u64 {
+        self.reads.load(Ordering::SeqCst)
+    }
+
+    /// Return the number of writes, including writes of the same level.
+    pub fn writes(&self) -> u64 {
+        self.writes.load(Ordering::SeqCst)
+    }
+
+    /// Return the number of writes that changed the level, the first write
+    /// counts as a transition.
+    pub fn transitions(&self) -> u64 {
+        self.transitions.load(Ordering::SeqCst)
+    }
+
+    /// Reset all counters to zero.
+    pub fn reset(&self) {
+        self.reads.store(0, Ordering::SeqCst);
+        self.writes.store(0, Ordering::SeqCst);
+        self.transitions.store(0, Ordering::SeqCst);
+    }
+
+    fn count(&self, access: PinAccess) {
+        let counter = match access {
+            PinAccess::Read => &self.reads,
+            PinAccess::Write => &self.writes,
+            PinAccess::Transition => &self.transitions,
+        };
+        counter.fetch_add(1, Ordering::SeqCst);
+        if let Some(hook) = self.hook.as_ref() {
+            hook(access);
+        }
+    }
+}
+
+impl fmt::Debug for PinCounters {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("PinCounters")
+            .field("reads", &self.reads())
+            .field("writes", &self.writes())
+            .field("transitions", &self.transitions())
+            .finish()
+    }
+}
+
+/// A pin that counts its reads, writes and transitions.
+///
+/// Created with [Count::counted]. The counters are shared, so they can be
+/// checked after the pin was moved into a driver, e.g. to assert that a
+/// driver does not poll a busy line more often than needed.
+///
+/// # Examples
+///
+/// ```
+/// use embedded_hal_vcd::pins::{AtomicPinState, Count, PinState, PushPullPin};
+/// use embedded_hal::digital::{OutputPin, ToggleableOutputPin};
+/// use std::sync::Arc;
+///
+/// let mut led = PushPullPin::new(Arc::new(AtomicPinState::new())).counted();
+/// let counters = led.counters();
+/// led.set_high().unwrap();
+/// led.set_high().unwrap();
+/// led.set_low().unwrap();
+/// assert_eq!((counters.writes(), counters.transitions()), (3, 2));
+///
+/// // a toggle writes the level it toggled to
+/// let state = Arc::new(AtomicPinState::new_with_state(PinState::High));
+/// let mut cs = PushPullPin::new(state).counted();
+/// cs.toggle().unwrap();
+/// cs.set_low().unwrap();
+/// assert_eq!((cs.counters().writes(), cs.counters().transitions()), (2, 1));
+/// ```
+#[derive(Debug)]
+pub struct Counted<P> {
+    pin: P,
+    counters: Arc<PinCounters>,
+    /// The last level written, to count the transitions.
+    level: Option<bool>,
+}
+
+impl<P> Counted<P> {
+    /// Return the shared counters of the pin.
+    pub fn counters(&self) -> Arc<PinCounters> {
+        self.counters.clone()
+    }
+
+    /// Return the wrapped pin.
+    pub fn into_inner(self) -> P {
+        self.pin
+    }
+
+    fn write(&mut self, high: bool) {
+        self.counters.count(PinAccess::Write);
+        if self.level != Some(high) {
+            self.counters.count(PinAccess::Transition);
+        }
+        self.level = Some(high);
+    }
+}
+
+/// Extension trait to count the accesses of a pin.
+pub trait Count: Sized {
+    /// Wrap the pin in a [Counted] pin with new counters.
+    fn counted(self) -> Counted<Self> {
+        self.counted_with(Arc::new(PinCounters::new()))
+    }
+
+    /// Wrap the pin in a [Counted] pin with existing counters, e.g. with a
+    /// hook or shared by several pins.
+    fn counted_with(self, counters: Arc<PinCounters>) -> Counted<Self> {
+        Counted {
+            pin: self,
+            counters,
+            level: None,
+        }
+    }
+}
+
+impl<P: ErrorType> Count for P {}
+
+impl<P: ErrorType> ErrorType for Counted<P> {
+    type Error = P::Error;
+}
+
+impl<P: hal::OutputPin> hal::OutputPin for Counted<P> {
+    fn set_high(&mut self) -> Result<(), Self::Error> {
+        self.pin.set_high()?;
+        self.write(true);
+        Ok(())
+    }
+
+    fn set_low(&mut self) -> Result<(), Self::Error> {
+        self.pin.set_low()?;
+        self.write(false);
+        Ok(())
+    }
+}
+
+impl<P: hal::StatefulOutputPin> hal::StatefulOutputPin for Counted<P> {
+    fn is_set_high(&self) -> Result<bool, Self::Error> {
+        self.counters.count(PinAccess::Read);
+        self.pin.is_set_high()
+    }
+
+    fn is_set_low(&self) -> Result<bool, Self::Error> {
+        self.counters.count(PinAccess::Read);
+        self.pin.is_set_low()
+    }
+}
+
+/// A toggle is counted as a write of the level after the toggle, which is
+/// read from the wrapped pin without counting a read. So the first toggle
+/// of a pin that was not written before counts as a transition like the
+/// first write.
+impl<P> hal::ToggleableOutputPin for Counted<P>
+where
+    P: hal::ToggleableOutputPin + hal::StatefulOutputPin,
+{
+    fn toggle(&mut self) -> Result<(), Self::Error> {
+        self.pin.toggle()?;
+        let high = self.pin.is_set_high()?;
+        self.write(high);
+        Ok(())
+    }
+}
+
+impl<P: hal::InputPin> hal::InputPin for Counted<P> {
+    fn is_high(&self) -> Result<bool, Self::Error> {
+        self.counters.count(PinAccess::Read);
+        self.pin.is_high()
+    }
+
+    fn is_low(&self) -> Result<bool, Self::Error> {
+        self.counters.count(PinAccess::Read);
+        self.pin.is_low()
+    }
+}
+
+/// A pin that marks instantaneous events, e.g. interrupts.
+///
+/// Events are recorded as VCD event variables, see
+/// [VcdWriterBuilder::add_event](crate::writer::VcdWriterBuilder::add_event).
+/// Setting the pin high fires an event, so it can be passed to drivers that
+/// expect an output pin, e.g. for a debug strobe. Setting it low does
+/// nothing.
+///
+/// # Examples
+///
+/// ```
+/// use embedded_hal_vcd::pins::EventPin;
+/// use std::sync::{Arc, atomic::{AtomicBool, Ordering}};
+///
+/// let fired = Arc::new(AtomicBool::new(false));
+/// let irq = EventPin::new(fired.clone());
+/// irq.fire();
+/// assert!(fired.load(Ordering::SeqCst));
+/// ```
+#[derive(Clone, Debug)]
+pub struct EventPin {
+    fired: Arc<AtomicBool>,
+}
+
+impl EventPin {
+    /// Create a new event pin that sets `fired` when an event fires.
+    pub fn new(fired: Arc<AtomicBool>) -> Self {
+        EventPin { fired }
+    }
+
+    /// Fire an event.
+    pub fn fire(&self) {
+        self.fired.store(true, Ordering::SeqCst);
+    }
+}
+
+impl ErrorType for EventPin {
+    type Error = Infallible;
+}
+
+impl hal::OutputPin for EventPin {
+    fn set_high(&mut self) -> Result<(), Self::Error> {
+        self.fire();
+        Ok(())
+    }
+
+    fn set_low(&mut self) -> Result<(), Self::Error> {
+        Ok(())
+    }
+}
+
+/// The level and strength a driver drives a line with.
+///
+/// Strong drivers (e.g. push pull outputs) override weak drivers (e.g. bus
+/// keepers or internal pull resistors). Conflicting drivers of the same
+/// strength are a contention, see [DriveLevel::resolve]. VCD files only
+/// have four states, so weak levels are recorded like strong ones.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum DriveLevel {
+    /// Actively driven low.
+    StrongLow,
+    /// Actively driven high.
+    StrongHigh,
+    /// Weakly pulled low.
+    WeakLow,
+    /// Weakly pulled high.
+    WeakHigh,
+    /// Not driven (high impedance).
+    HighZ,
+}
+
+impl DriveLevel {
+    /// Return the strength of the level, higher is stronger.
+    fn strength(self) -> u8 {
+        match self {
+            DriveLevel::StrongLow | DriveLevel::StrongHigh => 2,
+            DriveLevel::WeakLow | DriveLevel::WeakHigh => 1,
+            DriveLevel::HighZ => 0,
+        }
+    }
+
+    /// Return `true` if the level is high.
+    fn is_high(self) -> bool {
+        matches!(self, DriveLevel::StrongHigh | DriveLevel::WeakHigh)
+    }
+
+    /// Resolve the level of a line with multiple drivers.
+    ///
+    /// The strongest drivers determine the level. Returns the level and
+    /// whether the strongest drivers conflict, in which case low wins like
+    /// on a wired-AND line.
+    ///
+    /// ```
+    /// use embedded_hal_vcd::pins::DriveLevel::*;
+    /// use embedded_hal_vcd::pins::DriveLevel;
+    ///
+    /// assert_eq!(DriveLevel::resolve([WeakHigh, StrongLow]), (StrongLow, false));
+    /// assert_eq!(DriveLevel::resolve([HighZ, WeakHigh]), (WeakHigh, false));
+    /// assert_eq!(DriveLevel::resolve([StrongHigh, StrongLow]), (StrongLow, true));
+    /// assert_eq!(DriveLevel::resolve([]), (HighZ, false));
+    /// ```
+    pub fn resolve<I>(levels: I) -> (DriveLevel, bool)
+    where
+        I: IntoIterator<Item = DriveLevel>,
+    {
+        let mut resolved = DriveLevel::HighZ;
+        let mut conflict = false;
+        for level in levels {
+            if level.strength() > resolved.strength() {
+                resolved = level;
+                conflict = false;
+            } else if level.strength() == resolved.strength() && level != resolved {
+                conflict = true;
+                if !level.is_high() {
+                    resolved = level;
+                }
+            }
+        }
+        (resolved, conflict)
+    }
+}
+
+impl From<PinState> for DriveLevel {
+    fn from(state: PinState) -> Self {
+        match state {
+            PinState::Low => DriveLevel::StrongLow,
+            PinState::High => DriveLevel::StrongHigh,
+            PinState::Floating => DriveLevel::HighZ,
+        }
+    }
+}
+
+impl From<DriveLevel> for PinState {
+    fn from(level: DriveLevel) -> Self {
+        match level {
+            DriveLevel::StrongLow | DriveLevel::WeakLow => PinState::Low,
+            DriveLevel::StrongHigh | DriveLevel::WeakHigh => PinState::High,
+            DriveLevel::HighZ => PinState::Floating,
+        }
+    }
+}
+
+impl From<vcd::Value> for DriveLevel {
+    fn from(value: vcd::Value) -> Self {
+        PinState::from(value).into()
+    }
+}
+
+impl From<DriveLevel> for vcd::Value {
+    fn from(level: DriveLevel) -> Self {
+        PinState::from(level).into()
+    }
+}
+
+/// A line shared by multiple drivers with a pull resistor.
+///
+/// Each [BusPin] connected to the bus is a separate driver. The state of the
+/// line is resolved whenever a driver changes: if any driver pulls the line
+/// low it is low, otherwise it is high if any driver drives it high. If
+/// all drivers are released, the line takes the state of the pull resistor.
+/// This models open drain buses like I2C or one-wire.
+///
+/// Drivers can also drive with a [DriveLevel], weak drivers override the
+/// pull resistor, strong drivers override weak drivers. If drivers of the
+/// same strength conflict, the line is low and the conflict is recorded as
+/// a [Contention].
+///
+/// # Examples
+///
+/// ```
+/// use embedded_hal_vcd::pins::{Bus, BusPin, PinState};
+/// use embedded_hal::digital::{InputPin, OutputPin};
+/// use std::sync::Arc;
+///
+/// let bus = Arc::new(Bus::new(PinState::High));
+/// let mut a = BusPin::new(bus.clone());
+/// let mut b = BusPin::new(bus.clone());
+/// assert_eq!(PinState::High, bus.state());
+/// a.set_low().unwrap();
+/// assert_eq!(Ok(true), b.is_low());
+/// a.set_high().unwrap();
+/// assert_eq!(Ok(true), b.is_high());
+/// ```
+#[derive(Debug)]
+pub struct Bus {
+    pull: PinState,
+    line: Arc<AtomicPinState>,
+    drivers: Mutex<Vec<(String, DriveLevel)>>,
+    contentions: Mutex<Vec<Contention>>,
+}
+
+/// Conflicting drivers of the same strength on a [Bus].
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Contention {
+    /// The names of the drivers driving the line high.
+    pub high: Vec<String>,
+    /// The names of the drivers pulling the line low.
+    pub low: Vec<String>,
+}
+
+impl fmt::Display for Contention {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{} drive high while {} pull low",
+            self.high.join(", "),
+            self.low.join(", ")
+        )
+    }
+}
+
+impl std::error::Error for Contention {}
+
+impl Bus {
+    /// Creates a new bus with the given pull state.
+    ///
+    /// Use [`Floating`](`PinState::Floating`) for a line without pull resistor.
+    pub fn new(pull: PinState) -> Self {
+        Bus {
+            line: Arc::new(AtomicPinState::new_with_state(pull.clone())),
+            pull,
+            drivers: Mutex::new(vec![]),
+            contentions: Mutex::new(vec![]),
+        }
+    }
+
+    /// Returns the shared state of the resolved line.
+    ///
+    /// The state can be used to read or record the line, but should not be
+    /// modified directly.
+    pub fn line(&self) -> Arc<AtomicPinState> {
+        self.line.clone()
+    }
+
+    /// Returns the resolved state of the line.
+    pub fn state(&self) -> PinState {
+        self.line.load(Ordering::SeqCst)
+    }
+
+    /// Returns the current conflict of drivers, if any.
+    pub fn contention(&self) -> Option<Contention> {
+        Self::conflict(&self.drivers.lock().unwrap())
+    }
+
+    fn conflict(drivers: &[(String, DriveLevel)]) -> Option<Contention> {
+        let (resolved, _) = DriveLevel::resolve(drivers.iter().map(|(_, level)| *level));
+        let names = |high: bool| -> Vec<String> {
+            drivers
+                .iter()
+                .filter(|(_, l)| l.strength() == resolved.strength() && l.is_high() == high)
+                .map(|(name, _)| name.clone())
+                .collect()
+        };
+        let (high, low) = (names(true), names(false));
+        if resolved == DriveLevel::HighZ || high.is_empty() || low.is_empty() {
+            None
+        } else {
+            Some(Contention { high, low })
+        }
+    }
+
+    /// Returns and clears all conflicts since the last call.
+    ///
+    /// A conflict is recorded whenever a driver change results in a
+    /// conflicting state.
+    pub fn take_contentions(&self) -> Vec<Contention> {
+        std::mem::take(&mut self.contentions.lock().unwrap())
+    }
+
+    fn connect(&self, name: Option<&str>) -> usize {
+        let mut drivers = self.drivers.lock().unwrap();
+        let name = name
+            .map(|name| name.to_string())
+            .unwrap_or_else(|| format!("driver {}", drivers.len()));
+        drivers.push((name, DriveLevel::HighZ));
+        drivers.len() - 1
+    }
+
+    fn driver(&self, driver: usize) -> PinState {
+        self.drivers.lock().unwrap()[driver].1.into()
+    }
+
+    fn drive(&self, driver: usize, level: DriveLevel) {
+        let mut drivers = self.drivers.lock().unwrap();
+        drivers[driver].1 = level;
+        let (resolved, _) = DriveLevel::resolve(drivers.iter().map(|(_, level)| *level));
+        let resolved = match resolved {
+            DriveLevel::HighZ => self.pull.clone(),
+            level => level.into(),
+        };
+        // the line is stored while the drivers are locked, so concurrent
+        // drivers can't store a state resolved from an older snapshot
+        self.line.store(resolved, Ordering::SeqCst);
+        if let Some(contention) = Self::conflict(&drivers) {
+            self.contentions.lock().unwrap().push(contention);
+        }
+    }
+}
+
+/// A pin that drives a [Bus].
+///
+/// The pin behaves like an open drain output: setting the pin low pulls the
+/// line low, setting it high releases the line. Note that this differs from
+/// [OpenDrainPin], which pulls the line low when set high. Reading the pin
+/// returns the resolved state of the line.
+///
+/// The pin starts released.
+#[derive(Debug)]
+pub struct BusPin {
+    bus: Arc<Bus>,
+    driver: usize,
+}
+
+impl BusPin {
+    /// Creates a new pin connected to a bus.
+    ///
+    /// The driver is named `driver <n>` in [Contention]s.
+    pub fn new(bus: Arc<Bus>) -> Self {
+        let driver = bus.connect(None);
+        BusPin { bus, driver }
+    }
+
+    /// Creates a new named pin connected to a bus.
+    ///
+    /// ```
+    /// use embedded_hal_vcd::pins::{Bus, BusPin, PinState};
+    /// use std::sync::Arc;
+    ///
+    /// let bus = Arc::new(Bus::new(PinState::High));
+    /// let mut mcu = BusPin::new_named(bus.clone(), "mcu");
+    /// let mut sensor = BusPin::new_named(bus.clone(), "sensor");
+    /// mcu.drive(PinState::High);
+    /// sensor.drive(PinState::Low);
+    /// let contention = bus.take_contentions().pop().unwrap();
+    /// assert_eq!(contention.to_string(), "mcu drive high while sensor pull low");
+    /// ```
+    pub fn new_named(bus: Arc<Bus>, name: &str) -> Self {
+        let driver = bus.connect(Some(name));
+        BusPin { bus, driver }
+    }
+
+    /// Drives the bus with the given state.
+    ///
+    /// In contrast to the [OutputPin](hal::OutputPin) implementation this
+    /// allows to actively drive the line high, i.e. to model a push pull
+    /// driver connected to the bus.
+    pub fn drive(&mut self, state: PinState) {
+        self.bus.drive(self.driver, state.into());
+    }
+
+    /// Drives the bus with the given level and strength.
+    ///
+    /// ```
+    /// use embedded_hal_vcd::pins::{Bus, BusPin, DriveLevel, PinState};
+    /// use std::sync::Arc;
+    ///
+    /// let bus = Arc::new(Bus::new(PinState::Floating));
+    /// let mut keeper = BusPin::new(bus.clone());
+    /// let mut driver = BusPin::new(bus.clone());
+    /// keeper.drive_level(DriveLevel::WeakHigh);
+    /// assert_eq!(PinState::High, bus.state());
+    /// driver.drive(PinState::Low);
+    /// assert_eq!(PinState::Low, bus.state());
+    /// assert!(bus.take_contentions().is_empty());
+    /// ```
+    pub fn drive_level(&mut self, level: DriveLevel) {
+        self.bus.drive(self.driver, level);
+    }
+}
+
+impl ErrorType for BusPin {
+    type Error = Infallible;
+}
+
+impl hal::OutputPin for BusPin {
+    fn set_high(&mut self) -> Result<(), Self::Error> {
+        self.drive(PinState::Floating);
+        Ok(())
+    }
+
+    fn set_low(&mut self) -> Result<(), Self::Error> {
+        self.drive(PinState::Low);
+        Ok(())
+    }
+}
+
+impl hal::StatefulOutputPin for BusPin {
+    fn is_set_high(&self) -> Result<bool, Self::Error> {
+        Ok(self.bus.driver(self.driver) != PinState::Low)
+    }
+
+    fn is_set_low(&self) -> Result<bool, Self::Error> {
+        Ok(self.bus.driver(self.driver) == PinState::Low)
+    }
+}
+
+impl hal::InputPin for BusPin {
+    fn is_high(&self) -> Result<bool, Self::Error> {
+        Ok(self.bus.state() == PinState::High)
+    }
+
+    fn is_low(&self) -> Result<bool, Self::Error> {
+        Ok(self.bus.state() == PinState::Low)
+    }
+}
+
+/// A shared text label, e.g. the state of a state machine.
+///
+/// Labels are recorded as VCD string variables, see
+/// [VcdWriterBuilder::add_state_label](crate::writer::VcdWriterBuilder::add_state_label)
+/// and [VcdReader::get_state_label](crate::reader::VcdReader::get_state_label).
+///
+/// # Examples
+///
+/// ```
+/// use embedded_hal_vcd::pins::AtomicStateLabel;
+///
+/// let state = AtomicStateLabel::new("IDLE");
+/// state.store("TX");
+/// assert_eq!(state.load(), "TX");
+/// ```
+#[derive(Debug, Default)]
+pub struct AtomicStateLabel {
+    label: Mutex<String>,
+}
+
+impl AtomicStateLabel {
+    /// Create a new label.
+    pub fn new(label: &str) -> Self {
+        AtomicStateLabel {
+            label: Mutex::new(label.to_string()),
+        }
+    }
+
+    /// Return the current label.
+    pub fn load(&self) -> String {
+        self.label.lock().unwrap().clone()
+    }
+
+    /// Change the label.
+    pub fn store(&self, label: &str) {
+        *self.label.lock().unwrap() = label.to_string();
+    }
+}
+
+/// A shared integer, e.g. a loop counter or a register snapshot.
+///
+/// Counters are recorded as VCD integer variables, see
+/// [VcdWriterBuilder::add_integer](crate::writer::VcdWriterBuilder::add_integer).
+///
+/// # Examples
+///
+/// ```
+/// use embedded_hal_vcd::pins::AtomicCounter;
+///
+/// let errors = AtomicCounter::new(0);
+/// errors.increment();
+/// errors.add(2);
+/// assert_eq!(errors.load(), 3);
+/// ```
+#[derive(Debug, Default)]
+pub struct AtomicCounter {
+    value: AtomicU64,
+}
+
+impl AtomicCounter {
+    /// Create a new counter.
+    pub fn new(value: u64) -> Self {
+        AtomicCounter {
+            value: AtomicU64::new(value),
+        }
+    }
+
+    /// Return the current value.
+    pub fn load(&self) -> u64 {
+        self.value.load(Ordering::SeqCst)
+    }
+
+    /// Change the value.
+    pub fn store(&self, value: u64) {
+        self.value.store(value, Ordering::SeqCst);
+    }
+
+    /// Add to the value, wrapping around on overflow.
+    pub fn add(&self, value: u64) {
+        self.value.fetch_add(value, Ordering::SeqCst);
+    }
+
+    /// Increment the value by one.
+    pub fn increment(&self) {
+        self.add(1);
+    }
+}
+
+/// The reason a bit pattern of a [PinGroup] can't be read as an integer.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum BusValueError {
+    /// A pin of the group is floating.
+    Floating,
+    /// A bit is set that does not fit into an integer of `bits` bits.
+    Overflow {
+        /// The number of bits of the integer.
+        bits: u32,
+    },
+}
+
+impl fmt::Display for BusValueError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            BusValueError::Floating => write!(f, "a pin of the group is floating"),
+            BusValueError::Overflow { bits } => {
+                write!(f, "the bit pattern does not fit into {} bits", bits)
+            }
+        }
+    }
+}
+
+impl std::error::Error for BusValueError {}
+
+/// The encoding of the sign of a [PinGroup] read with [PinGroup::as_signed].
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum SignConvention {
+    /// The most significant bit has a negative weight.
+    #[default]
+    TwosComplement,
+    /// Negative numbers have all bits inverted.
+    OnesComplement,
+    /// The most significant bit is the sign of the remaining bits.
+    SignMagnitude,
+    /// The pattern is offset by half the range, common for DACs and ADCs
+    /// with a bipolar range.
+    OffsetBinary,
+}
+
+impl SignConvention {
+    /// Decode a bit pattern of `width` bits, the most significant bit is
+    /// the sign. Higher bits are ignored.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `width` is more than 128 bits.
+    pub fn decode(self, raw: u128, width: u32) -> i128 {
+        assert!(width <= u128::BITS, "width must be at most 128 bits");
+        if width == 0 {
+            return 0;
+        }
+        let sign = 1u128 << (width - 1);
+        // sign extend the two's complement to 128 bits
+        let shift = u128::BITS - width;
+        let twos = |raw: u128| ((raw << shift) as i128) >> shift;
+        let raw = raw & (u128::MAX >> shift);
+        match self {
+            SignConvention::TwosComplement => twos(raw),
+            SignConvention::OnesComplement if raw & sign != 0 => twos(raw) + 1,
+            SignConvention::OnesComplement => twos(raw),
+            SignConvention::SignMagnitude if raw & sign != 0 => -((raw & !sign) as i128),
+            SignConvention::SignMagnitude => raw as i128,
+            SignConvention::OffsetBinary => twos(raw ^ sign),
+        }
+    }
+}
+
+/// A fixed-point format for [PinGroup::as_fixed].
+pub trait FixedPoint {
+    /// The number of fractional bits.
+    const FRAC_BITS: u32;
+    /// Whether the pattern is a two's complement.
+    const SIGNED: bool;
+}
+
+/// A signed fixed-point format with `FRAC_BITS` fractional bits.
+#[derive(Clone, Copy, Debug)]
+pub struct Q<const FRAC_BITS: u32>;
+
+impl<const N: u32> FixedPoint for Q<N> {
+    const FRAC_BITS: u32 = N;
+    const SIGNED: bool = true;
+}
+
+/// An unsigned fixed-point format with `FRAC_BITS` fractional bits.
+#[derive(Clone, Copy, Debug)]
+pub struct UQ<const FRAC_BITS: u32>;
+
+impl<const N: u32> FixedPoint for UQ<N> {
+    const FRAC_BITS: u32 = N;
+    const SIGNED: bool = false;
+}
+
+/// The signed Q7 format of 8-bit samples.
+pub type Q7 = Q<7>;
+/// The signed Q15 format of 16-bit samples.
+pub type Q15 = Q<15>;
+/// The signed Q31 format of 32-bit samples.
+pub type Q31 = Q<31>;
+
+/// Several pins that are read and written together as a bit pattern.
+///
+/// Bit `i` of a pattern is the state of pin `i`. Changing the pattern
+/// updates all pins at once, so a sampling [VcdWriter](crate::writer::VcdWriter)
+/// never records a half updated pattern. Groups are recorded as VCD vector
+/// variables, see
+/// [VcdWriterBuilder::add_pin_group](crate::writer::VcdWriterBuilder::add_pin_group).
+/// This is useful for e.g. the 4-bit data bus of an LCD or a bank of DIP
+/// switches.
+///
+/// # Examples
+///
+/// ```
+/// use embedded_hal_vcd::pins::{InputPin, PinGroup};
+/// use embedded_hal::digital::InputPin as _;
+///
+/// let data = PinGroup::new(4);
+/// data.write(0b0101);
+/// assert_eq!(data.read(), Some(0b0101));
+/// assert_eq!(Ok(true), InputPin::new(data.pin(2)).is_high());
+/// ```
+#[derive(Debug)]
+pub struct PinGroup {
+    pins: Vec<Arc<AtomicPinState>>,
+    /// Shared with the slices of the group.
+    lock: Arc<Mutex<()>>,
+}
+
+impl PinGroup {
+    /// Create a new group of `width` pins that are low.
+    pub fn new(width: usize) -> Self {
+        Self::from_states(
+            (0..width)
+                .map(|_| Arc::new(AtomicPinState::new_with_state(PinState::Low)))
+                .collect(),
+        )
+    }
+
+    /// Create a new group from existing pin states, the first is bit `0`.
+    pub fn from_states(pins: Vec<Arc<AtomicPinState>>) -> Self {
+        PinGroup {
+            pins,
+            lock: Arc::new(Mutex::new(())),
+        }
+    }
+
+    /// Return the number of pins.
+    pub fn width(&self) -> usize {
+        self.pins.len()
+    }
+
+    /// Return the shared state of pin `index`.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `index` is not less than the width of the group.
+    pub fn pin(&self, index: usize) -> Arc<AtomicPinState> {
+        self.pins[index].clone()
+    }
+
+    /// Return an input pin that reads bit `index`.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `index` is not less than the width of the group.
+    pub fn bit(&self, index: usize) -> InputPin {
+        InputPin::new(self.pin(index))
+    }
+
+    /// Return a view of some pins of the group, e.g. a nibble of a port.
+    ///
+    /// The view shares the pins with the group, bit `0` of the view is the
+    /// first pin of the range.
+    ///
+    /// ```
+    /// use embedded_hal_vcd::pins::PinGroup;
+    ///
+    /// let port = PinGroup::new(8);
+    /// port.write(0xa5);
+    /// assert_eq!(port.slice(4..8).read(), Some(0xa));
+    /// port.slice(0..4).write(0x3);
+    /// assert_eq!(port.read(), Some(0xa3));
+    /// ```
+    ///
+    /// # Panics
+    ///
+    /// Panics if the range is out of bounds.
+    pub fn slice(&self, range: core::ops::Range<usize>) -> PinGroup {
+        PinGroup {
+            pins: self.pins[range].to_vec(),
+            lock: self.lock.clone(),
+        }
+    }
+
+    /// Return the states of all pins, starting with bit `0`.
+    pub fn states(&self) -> Vec<PinState> {
+        let _lock = self.lock.lock().unwrap();
+        self.pins
+            .iter()
+            .map(|pin| pin.load(Ordering::SeqCst))
+            .collect()
+    }
+
+    /// Return the bit pattern, `None` if a pin is floating.
+    pub fn read(&self) -> Option<u64> {
+        self.states()
+            .iter()
+            .enumerate()
+            .try_fold(0u64, |value, (bit, state)| match state {
+                PinState::High if bit < 64 => Some(value | 1 << bit),
+                PinState::Floating => None,
+                _ => Some(value),
+            })
+    }
+
+    /// Return the states of all pins as bits, starting with bit `0`, `None`
+    /// if a pin is floating.
+    ///
+    /// Unlike the integer accessors this works for buses of any width, e.g.
+    /// the 128-bit or wider buses of RTL dumps.
+    ///
+    /// ```
+    /// use embedded_hal_vcd::pins::PinGroup;
+    ///
+    /// let data = PinGroup::new(3);
+    /// data.write(0b110);
+    /// assert_eq!(data.bits(), Some(vec![false, true, true]));
+    /// data.release();
+    /// assert_eq!(data.bits(), None);
+    /// ```
+    pub fn bits(&self) -> Option<Vec<bool>> {
+        self.states()
+            .iter()
+            .map(|state| match state {
+                PinState::High => Some(true),
+                PinState::Low => Some(false),
+                PinState::Floating => None,
+            })
+            .collect()
+    }
+
+    /// Return the bit pattern if it fits into `bits` bits.
+    fn read_checked(&self, bits: u32) -> Result<u128, BusValueError> {
+        self.states()
+            .iter()
+            .enumerate()
+            .try_fold(0u128, |value, (bit, state)| match state {
+                PinState::High if bit < bits as usize => Ok(value | 1 << bit),
+                PinState::High => Err(BusValueError::Overflow { bits }),
+                PinState::Floating => Err(BusValueError::Floating),
+                PinState::Low => Ok(value),
+            })
+    }
+
+    /// Return the bit pattern as [u8].
+    ///
+    /// Returns an error if a pin is floating or if a bit above bit 7 is set,
+    /// so a wider bus can be read as long as its value fits.
+    ///
+    /// ```
+    /// use embedded_hal_vcd::pins::{BusValueError, PinGroup};
+    ///
+    /// let data = PinGroup::new(16);
+    /// data.write(0xa5);
+    /// assert_eq!(data.as_u8(), Ok(0xa5));
+    /// data.write(0x1a5);
+    /// assert_eq!(data.as_u8(), Err(BusValueError::Overflow { bits: 8 }));
+    /// assert_eq!(data.as_u16(), Ok(0x1a5));
+    /// ```
+    pub fn as_u8(&self) -> Result<u8, BusValueError> {
+        self.read_checked(u8::BITS).map(|value| value as u8)
+    }
+
+    /// Return the bit pattern as [u16], see [as_u8](Self::as_u8).
+    pub fn as_u16(&self) -> Result<u16, BusValueError> {
+        self.read_checked(u16::BITS).map(|value| value as u16)
+    }
+
+    /// Return the bit pattern as [u32], see [as_u8](Self::as_u8).
+    pub fn as_u32(&self) -> Result<u32, BusValueError> {
+        self.read_checked(u32::BITS).map(|value| value as u32)
+    }
+
+    /// Return the bit pattern as [u64], see [as_u8](Self::as_u8).
+    ///
+    /// Unlike [read](Self::read) bits above bit 63 are not ignored.
+    pub fn as_u64(&self) -> Result<u64, BusValueError> {
+        self.read_checked(u64::BITS).map(|value| value as u64)
+    }
+
+    /// Return the bit pattern as [u128], see [as_u8](Self::as_u8).
+    pub fn as_u128(&self) -> Result<u128, BusValueError> {
+        self.read_checked(u128::BITS)
+    }
+
+    /// Return the bit pattern as a signed number of the width of the group.
+    ///
+    /// The most significant pin is the sign, encoded with the given
+    /// convention. Returns an error if a pin is floating or if the group is
+    /// wider than 128 bits.
+    ///
+    /// ```
+    /// use embedded_hal_vcd::pins::{PinGroup, SignConvention};
+    ///
+    /// // a 12-bit ADC register
+    /// let adc = PinGroup::new(12);
+    /// adc.write(0xffd);
+    /// assert_eq!(adc.as_signed(SignConvention::TwosComplement), Ok(-3));
+    /// assert_eq!(adc.as_signed(SignConvention::OnesComplement), Ok(-2));
+    /// assert_eq!(adc.as_signed(SignConvention::SignMagnitude), Ok(-2045));
+    /// assert_eq!(adc.as_signed(SignConvention::OffsetBinary), Ok(2045));
+    /// ```
+    pub fn as_signed(&self, convention: SignConvention) -> Result<i128, BusValueError> {
+        let width = self.width() as u32;
+        if width > u128::BITS {
+            return Err(BusValueError::Overflow { bits: u128::BITS });
+        }
+        Ok(convention.decode(self.read_checked(u128::BITS)?, width))
+    }
+
+    /// Return the two's complement of the group as [i8].
+    ///
+    /// The most significant pin of the group is the sign, so a narrower
+    /// group is sign extended. Returns an error if a pin is floating or if
+    /// the number does not fit.
+    ///
+    /// ```
+    /// use embedded_hal_vcd::pins::{BusValueError, PinGroup};
+    ///
+    /// let data = PinGroup::new(12);
+    /// data.write(0xf80);
+    /// assert_eq!(data.as_i8(), Ok(-128));
+    /// data.write(0xf7f);
+    /// assert_eq!(data.as_i8(), Err(BusValueError::Overflow { bits: 8 }));
+    /// assert_eq!(data.as_i16(), Ok(-129));
+    /// ```
+    pub fn as_i8(&self) -> Result<i8, BusValueError> {
+        let value = self.as_signed(SignConvention::TwosComplement)?;
+        i8::try_from(value).map_err(|_| BusValueError::Overflow { bits: i8::BITS })
+    }
+
+    /// Return the two's complement of the group as [i16], see
+    /// [as_i8](Self::as_i8).
+    pub fn as_i16(&self) -> Result<i16, BusValueError> {
+        let value = self.as_signed(SignConvention::TwosComplement)?;
+        i16::try_from(value).map_err(|_| BusValueError::Overflow { bits: i16::BITS })
+    }
+
+    /// Return the two's complement of the group as [i32], see
+    /// [as_i8](Self::as_i8).
+    pub fn as_i32(&self) -> Result<i32, BusValueError> {
+        let value = self.as_signed(SignConvention::TwosComplement)?;
+        i32::try_from(value).map_err(|_| BusValueError::Overflow { bits: i32::BITS })
+    }
+
+    /// Return the two's complement of the group as [i64], see
+    /// [as_i8](Self::as_i8).
+    pub fn as_i64(&self) -> Result<i64, BusValueError> {
+        let value = self.as_signed(SignConvention::TwosComplement)?;
+        i64::try_from(value).map_err(|_| BusValueError::Overflow { bits: i64::BITS })
+    }
+
+    /// Return the bit pattern as a fixed-point number, e.g. a sample of an
+    /// audio codec in [Q15].
+    ///
+    /// ```
+    /// use embedded_hal_vcd::pins::{PinGroup, Q15, UQ};
+    ///
+    /// let sample = PinGroup::new(16);
+    /// sample.write(0xc000);
+    /// assert_eq!(sample.as_fixed::<Q15>(), Ok(-0.5));
+    /// // an 8.8 unsigned gain register
+    /// sample.write(0x0180);
+    /// assert_eq!(sample.as_fixed::<UQ<8>>(), Ok(1.5));
+    /// ```
+    pub fn as_fixed<F: FixedPoint>(&self) -> Result<f64, BusValueError> {
+        let value = if F::SIGNED {
+            self.as_signed(SignConvention::TwosComplement)? as f64
+        } else {
+            self.read_checked(u128::BITS)? as f64
+        };
+        Ok(value / (F::FRAC_BITS as f64).exp2())
+    }
+
+    /// Drive all pins with a bit pattern, higher bits are ignored.
+    pub fn write(&self, value: u64) {
+        let _lock = self.lock.lock().unwrap();
+        for (bit, pin) in self.pins.iter().enumerate() {
+            let state = if bit < 64 && value & (1 << bit) != 0 {
+                PinState::High
+            } else {
+                PinState::Low
+            };
+            pin.store(state, Ordering::SeqCst);
+        }
+    }
+
+    /// Drive all pins with a bit pattern of up to 128 bits, higher bits are
+    /// ignored.
+    pub fn write_u128(&self, value: u128) {
+        let _lock = self.lock.lock().unwrap();
+        for (bit, pin) in self.pins.iter().enumerate() {
+            let state = if bit < 128 && value & (1 << bit) != 0 {
+                PinState::High
+            } else {
+                PinState::Low
+            };
+            pin.store(state, Ordering::SeqCst);
+        }
+    }
+
+    /// Drive all pins with bits, starting with bit `0`, for buses of any
+    /// width.
+    ///
+    /// Pins without a bit keep their state.
+    pub fn write_bits(&self, bits: &[bool]) {
+        let states: Vec<_> = bits
+            .iter()
+            .map(|bit| if *bit { PinState::High } else { PinState::Low })
+            .collect();
+        self.write_states(&states);
+    }
+
+    /// Drive all pins with the given states, starting with bit `0`.
+    ///
+    /// Pins without a state keep their state.
+    pub fn write_states(&self, states: &[PinState]) {
+        let _lock = self.lock.lock().unwrap();
+        for (pin, state) in self.pins.iter().zip(states) {
+            pin.store(state.clone(), Ordering::SeqCst);
+        }
+    }
+
+    /// Release all pins, e.g. to read from a bidirectional data bus.
+    pub fn release(&self) {
+        let _lock = self.lock.lock().unwrap();
+        for pin in self.pins.iter() {
+            pin.store(PinState::Floating, Ordering::SeqCst);
+        }
+    }
+}
+
+/// Serialize and deserialize pin states by their names, e.g. `"High"`, for
+/// fields of the reports with the `serde` feature.
+#[cfg(feature = "serde")]
+pub(crate) mod serde_states {
+    use super::PinState;
+    use embedded_time::duration::Nanoseconds;
+    use serde::{Deserialize, Deserializer, Serialize, Serializer};
+
+    #[derive(Serialize, Deserialize)]
+    #[serde(remote = "PinState")]
+    enum Remote {
+        High,
+        Low,
+        Floating,
+    }
+
+    #[derive(Serialize, Deserialize)]
+    struct State(#[serde(with = "Remote")] PinState);
+
+    /// An optional state, e.g. before the first change of a signal.
+    pub(crate) mod option {
+        use super::*;
+
+        pub(crate) fn serialize<S>(
+            state: &Option<PinState>,
+            serializer: S,
+        ) -> Result<S::Ok, S::Error>
+        where
+            S: Serializer,
+        {
+            state.clone().map(State).serialize(serializer)
+        }
+
+        pub(crate) fn deserialize<'de, D>(deserializer: D) -> Result<Option<PinState>, D::Error>
+        where
+            D: Deserializer<'de>,
+        {
+            Ok(Option::<State>::deserialize(deserializer)?.map(|state| state.0))
+        }
+    }
+
+    /// A list of states.
+    pub(crate) mod vec {
+        use super::*;
+
+        pub(crate) fn serialize<S>(states: &[PinState], serializer: S) -> Result<S::Ok, S::Error>
+        where
+            S: Serializer,
+        {
+            serializer.collect_seq(states.iter().cloned().map(State))
+        }
+
+        pub(crate) fn deserialize<'de, D>(deserializer: D) -> Result<Vec<PinState>, D::Error>
+        where
+            D: Deserializer<'de>,
+        {
+            let states = Vec::<State>::deserialize(deserializer)?;
+            Ok(states.into_iter().map(|state| state.0).collect())
+        }
+    }
+
+    /// A list of changes with their times.
+    pub(crate) mod changes {
+        use super::*;
+
+        pub(crate) fn serialize<S>(
+            changes: &[(Nanoseconds<u64>, PinState)],
+            serializer: S,
+        ) -> Result<S::Ok, S::Error>
+        where
+            S: Serializer,
+        {
+            let changes = changes.iter().map(|(t, state)| (t, State(state.clone())));
+            serializer.collect_seq(changes)
+        }
+
+        pub(crate) fn deserialize<'de, D>(
+            deserializer: D,
+        ) -> Result<Vec<(Nanoseconds<u64>, PinState)>, D::Error>
+        where
+            D: Deserializer<'de>,
+        {
+            let changes = Vec::<(Nanoseconds<u64>, State)>::deserialize(deserializer)?;
+            Ok(changes.into_iter().map(|(t, state)| (t, state.0)).collect())
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use embedded_hal::digital::OutputPin;
+    use std::thread;
+
+    #[test]
+    fn bus_line_follows_drivers() {
+        let bus = Arc::new(Bus::new(PinState::High));
+        let threads: Vec<_> = (0..2)
+            .map(|n| {
+                let mut pin = BusPin::new(bus.clone());
+                thread::spawn(move || {
+                    for i in 0..10_000 {
+                        if (i + n) % 2 == 0 {
+                            pin.set_low().unwrap();
+                        } else {
+                            pin.set_high().unwrap();
+                        }
+                    }
+                    pin.set_high().unwrap();
+                })
+            })
+            .collect();
+        while !threads.iter().all(|thread| thread.is_finished()) {
+            let drivers = bus.drivers.lock().unwrap();
+            let (resolved, _) = DriveLevel::resolve(drivers.iter().map(|(_, level)| *level));
+            let expected = match resolved {
+                DriveLevel::HighZ => PinState::High,
+                level => level.into(),
+            };
+            assert_eq!(bus.state(), expected);
+        }
+        for thread in threads {
+            thread.join().unwrap();
+        }
+        assert_eq!(bus.state(), PinState::High);
+    }
+}
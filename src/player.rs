@@ -0,0 +1,225 @@
+//! Replay VCD files on real GPIOs in real time.
+//!
+//! A [RealtimePlayer] turns the crate into a pattern generator for
+//! hardware-in-the-loop rigs: each variable of a VCD file is mapped to an
+//! [OutputPin] of a HAL, e.g. a `CdevPin` of `linux-embedded-hal`, which is
+//! set when the recorded time is reached.
+//! A speed factor stretches or compresses the recording, e.g. to replay a
+//! capture of a fast bus slowly on GPIOs that can't keep up.
+//!
+//! The timing is as good as the sleep of the operating system, usually in
+//! the range of 10 µs to 100 µs on Linux. How far behind the player fell is
+//! reported, so a rig can reject runs with too much delay.
+
+use crate::pins::*;
+use crate::reader::VcdReader;
+use embedded_hal::digital::{InputPin as _, OutputPin};
+use embedded_time::duration::*;
+use std::io::Result as IOResult;
+use std::io::{Error, ErrorKind, Read};
+use std::time::{Duration, Instant};
+
+/// The result of a [RealtimePlayer::run].
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct PlayStats {
+    /// The number of timestamps replayed.
+    pub steps: usize,
+    /// The number of pin writes.
+    pub writes: usize,
+    /// The longest delay of a step after its scheduled wall clock time.
+    pub max_lateness: Duration,
+}
+
+/// A player that sets output pins from a VCD file in real time.
+///
+/// ```
+/// use embedded_hal_vcd::pins::*;
+/// use embedded_hal_vcd::player::RealtimePlayer;
+/// use embedded_hal_vcd::reader::VcdReader;
+/// use std::sync::Arc;
+///
+/// let vcd = b"$timescale 1 us $end
+/// $scope module top $end
+/// $var wire 1 ! led $end
+/// $upscope $end
+/// $enddefinitions $end
+/// #0
+/// 0!
+/// #100
+/// 1!
+/// #200
+/// ";
+/// let led = Arc::new(AtomicPinState::new());
+/// let mut player = RealtimePlayer::new(VcdReader::new(&vcd[..]).unwrap());
+/// player.add_output(&["top", "led"], PushPullPin::new(led.clone())).unwrap();
+/// let stats = player.with_speed(10.0).run().unwrap();
+/// assert_eq!(led.load(std::sync::atomic::Ordering::SeqCst), PinState::High);
+/// assert_eq!(stats.writes, 2);
+/// ```
+pub struct RealtimePlayer<R, P>
+where
+    R: Read,
+{
+    reader: VcdReader<R>,
+    outputs: Vec<(InputPin, P, Option<bool>)>,
+    speed: f64,
+}
+
+impl<R, P> RealtimePlayer<R, P>
+where
+    R: Read,
+    P: OutputPin,
+{
+    /// Create a new player that replays at the recorded speed.
+    pub fn new(reader: VcdReader<R>) -> Self {
+        RealtimePlayer {
+            reader,
+            outputs: vec![],
+            speed: 1.0,
+        }
+    }
+
+    /// Set the speed factor, e.g. `0.001` to replay a 1 MHz bus at 1 kHz.
+    ///
+    /// # Panics
+    ///
+    /// Panics if the factor is not positive.
+    pub fn with_speed(mut self, speed: f64) -> Self {
+        assert!(speed > 0.0, "speed must be positive");
+        self.speed = speed;
+        self
+    }
+
+    /// Drive a pin with the value of a variable.
+    ///
+    /// The pin is set high or low at each change of the variable, floating
+    /// and unknown values keep the previous level.
+    pub fn add_output<S>(&mut self, path: &[S], pin: P) -> IOResult<()>
+    where
+        S: core::borrow::Borrow<str>,
+    {
+        let input = self
+            .reader
+            .get_pin(path)
+            .ok_or_else(|| Error::new(ErrorKind::NotFound, "variable not found"))?;
+        self.outputs.push((input, pin, None));
+        Ok(())
+    }
+
+    /// Set the pins whose variable changed, returns the number of writes.
+    fn write(&mut self) -> Result<usize, P::Error> {
+        let mut writes = 0;
+        for (input, pin, last) in self.outputs.iter_mut() {
+            let high = match (input.is_high(), input.is_low()) {
+                (Ok(true), _) => true,
+                (_, Ok(true)) => false,
+                _ => continue,
+            };
+            if *last != Some(high) {
+                if high {
+                    pin.set_high()?;
+                } else {
+                    pin.set_low()?;
+                }
+                *last = Some(high);
+                writes += 1;
+            }
+        }
+        Ok(writes)
+    }
+
+    /// Replay the whole file, blocking until its last timestamp.
+    ///
+    /// The first timestamp of the file is replayed right away. Stops at the
+    /// first error of a pin.
+    pub fn run(mut self) -> Result<PlayStats, P::Error> {
+        let start = Instant::now();
+        let mut stats = PlayStats::default();
+        let mut first = None;
+        let mut previous: Option<u64> = None;
+        loop {
+            // the reader applies the changes of a timestamp when it reads the
+            // next one, so the pins are set at the previous timestamp
+            let next = self.reader.next();
+            if let Some(previous) = previous {
+                let offset = previous - *first.get_or_insert(previous);
+                let due = start + Duration::from_secs_f64(offset as f64 / 1e9 / self.speed);
+                let now = Instant::now();
+                if due > now {
+                    std::thread::sleep(due - now);
+                }
+                stats.max_lateness = stats.max_lateness.max(Instant::now() - due);
+                stats.writes += self.write()?;
+                stats.steps += 1;
+            }
+            match next.and_then(|t| Nanoseconds::<u64>::try_from(t).ok()) {
+                Some(t) => previous = Some(t.0),
+                None => break,
+            }
+        }
+        Ok(stats)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::analysis::Waveform;
+    use core::convert::Infallible;
+    use std::sync::{Arc, Mutex};
+
+    /// A pin that records the wall clock time of each write.
+    struct TimedPin(Arc<Mutex<Vec<(Instant, bool)>>>);
+
+    impl embedded_hal::digital::ErrorType for TimedPin {
+        type Error = Infallible;
+    }
+
+    impl OutputPin for TimedPin {
+        fn set_low(&mut self) -> Result<(), Infallible> {
+            self.0.lock().unwrap().push((Instant::now(), false));
+            Ok(())
+        }
+
+        fn set_high(&mut self) -> Result<(), Infallible> {
+            self.0.lock().unwrap().push((Instant::now(), true));
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn scaled_replay() {
+        // a 1 kHz clock for 20 ms, replayed 4 times faster
+        let mut changes = vec![];
+        for i in 0..40u64 {
+            let state = if i % 2 == 0 {
+                PinState::High
+            } else {
+                PinState::Low
+            };
+            changes.push((Nanoseconds(1_000_000 + i * 500_000), state));
+        }
+        let mut vcd = vec![];
+        Waveform::write_all(&[("rig.clk".to_string(), Waveform::new(changes))], &mut vcd).unwrap();
+
+        let writes = Arc::new(Mutex::new(vec![]));
+        let mut player = RealtimePlayer::new(VcdReader::new(&vcd[..]).unwrap()).with_speed(4.0);
+        player
+            .add_output(&["rig", "clk"], TimedPin(writes.clone()))
+            .unwrap();
+        assert!(player
+            .add_output(&["rig", "missing"], TimedPin(writes.clone()))
+            .is_err());
+        let start = Instant::now();
+        let stats = player.run().unwrap();
+        assert_eq!((stats.steps, stats.writes), (40, 40));
+
+        let writes = writes.lock().unwrap();
+        // a late first write must not shorten the replay
+        let elapsed = writes.last().unwrap().0 - start;
+        assert!(elapsed >= Duration::from_micros(39 * 125), "{:?}", elapsed);
+        assert!(start.elapsed() < Duration::from_millis(500));
+        assert!(writes.iter().step_by(2).all(|(_, high)| *high));
+    }
+}
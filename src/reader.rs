@@ -4,21 +4,434 @@
 
 use crate::pins::*;
 use core::borrow::Borrow;
+use core::fmt;
 use embedded_time::duration::*;
 use fnv::FnvHashMap;
 use std::io::Result as IOResult;
-use std::sync::atomic::Ordering;
-use std::sync::Arc;
+use std::io::{Cursor, Read, Seek, SeekFrom};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Mutex};
+
+mod lenient;
+
+pub use lenient::{repair, ParseWarning};
+
+/// The position of a parser in a VCD file.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+struct Position {
+    offset: u64,
+    line: u64,
+    column: u64,
+}
+
+impl Default for Position {
+    fn default() -> Self {
+        Position {
+            offset: 0,
+            line: 1,
+            column: 0,
+        }
+    }
+}
+
+/// A reader that is shared between the [VcdReader] and its parser.
+///
+/// The parser takes ownership of its input, so this handle allows
+/// the reader to seek the underlying input (see [VcdReader::restore]).
+/// The parser reads byte by byte, so each handle buffers the input and
+/// only locks it to refill the buffer. The position of the parser is
+/// tracked for diagnostics.
+pub(crate) struct SharedRead<R> {
+    shared: Arc<Shared<R>>,
+    buffer: Vec<u8>,
+    consumed: usize,
+    /// The generation of the input the buffer was filled from.
+    generation: u64,
+}
+
+struct Shared<R> {
+    inner: Mutex<R>,
+    /// Incremented when the input is moved, so handles drop their buffers.
+    generation: AtomicU64,
+    offset: AtomicU64,
+    line: AtomicU64,
+    column: AtomicU64,
+    /// The offset at the end of the last filled buffer.
+    filled: AtomicU64,
+}
+
+/// The size of the buffer of a [SharedRead].
+const BUFFER_SIZE: usize = 8 * 1024;
+
+impl<R> SharedRead<R> {
+    pub(crate) fn new(read: R) -> Self {
+        let position = Position::default();
+        SharedRead {
+            shared: Arc::new(Shared {
+                inner: Mutex::new(read),
+                generation: AtomicU64::new(0),
+                offset: AtomicU64::new(position.offset),
+                line: AtomicU64::new(position.line),
+                column: AtomicU64::new(position.column),
+                filled: AtomicU64::new(position.offset),
+            }),
+            buffer: vec![],
+            consumed: 0,
+            generation: 0,
+        }
+    }
+
+    /// Return the position of the parser.
+    fn position(&self) -> Position {
+        Position {
+            offset: self.shared.offset.load(Ordering::Relaxed),
+            line: self.shared.line.load(Ordering::Relaxed),
+            column: self.shared.column.load(Ordering::Relaxed),
+        }
+    }
+
+    /// Add the position of the parser to an error.
+    pub(crate) fn error(&self, error: std::io::Error, timestamp: Option<u64>) -> ParseError {
+        let position = self.position();
+        ParseError {
+            offset: position.offset,
+            line: position.line,
+            column: position.column,
+            timestamp,
+            error,
+        }
+    }
+}
+
+impl<R: Read> SharedRead<R> {
+    /// Refill the buffer from the input.
+    fn fill(&mut self) -> IOResult<()> {
+        self.buffer.resize(BUFFER_SIZE, 0);
+        self.consumed = 0;
+        let read = self.shared.inner.lock().unwrap().read(&mut self.buffer);
+        self.buffer.truncate(*read.as_ref().unwrap_or(&0));
+        let len = read?;
+        self.shared.filled.fetch_add(len as u64, Ordering::Relaxed);
+        Ok(())
+    }
+}
+
+impl<R> Shared<R> {
+    /// Move the position of the parser over `bytes`.
+    #[inline]
+    fn advance(&self, bytes: &[u8]) {
+        let mut line = self.line.load(Ordering::Relaxed);
+        let mut column = self.column.load(Ordering::Relaxed);
+        for b in bytes {
+            if *b == b'\n' {
+                line += 1;
+                column = 0;
+            } else {
+                column += 1;
+            }
+        }
+        let offset = self.offset.load(Ordering::Relaxed);
+        self.offset
+            .store(offset + bytes.len() as u64, Ordering::Relaxed);
+        self.line.store(line, Ordering::Relaxed);
+        self.column.store(column, Ordering::Relaxed);
+    }
+}
+
+impl<R: Seek> SharedRead<R> {
+    /// Return the position of the input after the bytes read by the parser.
+    fn stream_position(&self) -> IOResult<u64> {
+        let buffered = self.shared.filled.load(Ordering::Relaxed) - self.position().offset;
+        Ok(self.shared.inner.lock().unwrap().stream_position()? - buffered)
+    }
+
+    /// Move the input, parsers have to be created again afterwards.
+    fn seek(&self, offset: u64, position: Position) -> IOResult<()> {
+        self.shared
+            .inner
+            .lock()
+            .unwrap()
+            .seek(SeekFrom::Start(offset))?;
+        self.shared.generation.fetch_add(1, Ordering::Relaxed);
+        self.shared.offset.store(position.offset, Ordering::Relaxed);
+        self.shared.line.store(position.line, Ordering::Relaxed);
+        self.shared.column.store(position.column, Ordering::Relaxed);
+        self.shared.filled.store(position.offset, Ordering::Relaxed);
+        Ok(())
+    }
+}
+
+impl<R> Clone for SharedRead<R> {
+    fn clone(&self) -> Self {
+        Self {
+            shared: self.shared.clone(),
+            buffer: vec![],
+            consumed: 0,
+            generation: self.shared.generation.load(Ordering::Relaxed),
+        }
+    }
+}
+
+impl<R> Read for SharedRead<R>
+where
+    R: Read,
+{
+    #[inline]
+    fn read(&mut self, buf: &mut [u8]) -> IOResult<usize> {
+        let generation = self.shared.generation.load(Ordering::Relaxed);
+        if generation != self.generation {
+            // the input was moved, the buffered bytes are stale
+            self.generation = generation;
+            self.buffer.clear();
+            self.consumed = 0;
+        }
+        if self.consumed == self.buffer.len() {
+            self.fill()?;
+        }
+        let len = buf.len().min(self.buffer.len() - self.consumed);
+        let bytes = &self.buffer[self.consumed..self.consumed + len];
+        buf[..len].copy_from_slice(bytes);
+        self.consumed += len;
+        self.shared.advance(bytes);
+        Ok(len)
+    }
+}
+
+/// An error of the VCD parser with its position in the file.
+#[derive(Debug)]
+pub struct ParseError {
+    /// The number of bytes read when the error was detected.
+    pub offset: u64,
+    /// The line of the last byte read, starting at 1.
+    pub line: u64,
+    /// The column of the last byte read, starting at 1, `0` at the start
+    /// of a line.
+    pub column: u64,
+    /// The last timestamp before the error in units of the timescale,
+    /// `None` in the header.
+    pub timestamp: Option<u64>,
+    /// The error of the parser.
+    pub error: std::io::Error,
+}
+
+impl fmt::Display for ParseError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "line {}, column {} (byte {})",
+            self.line, self.column, self.offset
+        )?;
+        if let Some(timestamp) = self.timestamp {
+            write!(f, " after #{}", timestamp)?;
+        }
+        write!(f, ": {}", self.error)
+    }
+}
+
+impl std::error::Error for ParseError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        Some(&self.error)
+    }
+}
+
+impl From<ParseError> for std::io::Error {
+    fn from(error: ParseError) -> Self {
+        std::io::Error::new(error.error.kind(), error)
+    }
+}
+
+/// A free-form annotation read from a `$comment` in the VCD file.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Annotation {
+    /// The time of the timestamp before the comment, `None` if the comment
+    /// is placed before the first timestamp.
+    pub time: Option<Generic<u64>>,
+    /// The text of the comment.
+    pub text: String,
+}
+
+/// A timestamp of a VCD file that can't be replayed.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum TimestampError {
+    /// The timestamp, in units of the timescale, does not fit into 64 bit
+    /// nanoseconds, e.g. a capture of more than 584 years or a corrupt file.
+    Overflow {
+        /// The timestamp.
+        timestamp: u64,
+    },
+    /// The timestamp is before the previous one. The writer reports times in
+    /// nanoseconds, the reader in units of the timescale.
+    Backwards {
+        /// The timestamp.
+        timestamp: u64,
+        /// The previous timestamp.
+        previous: u64,
+    },
+}
+
+impl fmt::Display for TimestampError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            TimestampError::Overflow { timestamp } => {
+                write!(f, "timestamp {} overflows 64 bit nanoseconds", timestamp)
+            }
+            TimestampError::Backwards {
+                timestamp,
+                previous,
+            } => write!(
+                f,
+                "timestamp {} is before the previous timestamp {}",
+                timestamp, previous
+            ),
+        }
+    }
+}
+
+impl std::error::Error for TimestampError {}
+
+impl From<TimestampError> for std::io::Error {
+    fn from(error: TimestampError) -> Self {
+        std::io::Error::new(std::io::ErrorKind::InvalidData, error)
+    }
+}
+
+/// Convert a timestamp in units of the timescale of a VCD file to
+/// nanoseconds, a missing timescale is 1 ns.
+pub(crate) fn timestamp_to_ns(
+    timescale: Option<(u32, vcd::TimescaleUnit)>,
+    timestamp: u64,
+) -> Result<Nanoseconds<u64>, TimestampError> {
+    let (scale, unit) = timescale.unwrap_or((1, vcd::TimescaleUnit::NS));
+    // at most 2^64 * 2^32 * 10^9, which fits into 128 bit
+    let ns = timestamp as u128 * scale as u128 * 1_000_000_000 / unit.divisor() as u128;
+    u64::try_from(ns)
+        .map(Nanoseconds)
+        .map_err(|_| TimestampError::Overflow { timestamp })
+}
+
+/// A snapshot of the state of a [VcdReader].
+///
+/// Created by [VcdReader::checkpoint] and used to branch a replay from a
+/// common prefix with [VcdReader::restore].
+#[derive(Clone, Debug)]
+pub struct Snapshot {
+    offset: u64,
+    position: Position,
+    time: Option<Generic<u64>>,
+    states: Vec<(vcd::IdCode, PinState)>,
+    labels: Vec<(vcd::IdCode, String)>,
+    values: FnvHashMap<vcd::IdCode, vcd::Value>,
+    vectors: FnvHashMap<vcd::IdCode, Vec<vcd::Value>>,
+    strings: FnvHashMap<vcd::IdCode, String>,
+    annotations: usize,
+    last_sync: Option<Generic<u64>>,
+}
+
+impl Snapshot {
+    /// Return the time of the last timestamp read before the snapshot was taken.
+    pub fn time(&self) -> Option<Generic<u64>> {
+        self.time
+    }
+}
+
+/// A `$dumpall` section of a VCD file, which contains the values of all
+/// variables.
+///
+/// Replaying from a sync point restores the values of all variables, which
+/// [VcdReader::seek] uses to skip the changes before it.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct SyncPoint {
+    time: Generic<u64>,
+    /// The position after the timestamp before the section.
+    position: Position,
+}
+
+impl SyncPoint {
+    /// Return the time of the timestamp of the section.
+    pub fn time(&self) -> Generic<u64> {
+        self.time
+    }
+}
+
+/// A step of a replay, see [VcdReader::set_observer].
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ReplayEvent<'a> {
+    /// A timestamp was read, the following changes are at its time.
+    Timestamp(Nanoseconds<u64>),
+    /// A `$dumpall` section was read, the following changes are the values
+    /// of all variables, see [VcdReader::last_sync].
+    Sync,
+    /// A variable changed, reported for each path of aliased variables.
+    Change {
+        /// The full path of the variable.
+        path: &'a [String],
+        /// The new value as in the VCD file, e.g. `1` or `b0101`.
+        value: &'a str,
+    },
+}
+
+/// An observer of a replay with the paths of the variables by identifier.
+struct Observer {
+    callback: Box<dyn FnMut(ReplayEvent) + Send>,
+    paths: FnvHashMap<vcd::IdCode, Vec<Vec<String>>>,
+}
+
+impl Observer {
+    fn change(&mut self, id: &vcd::IdCode, value: &dyn fmt::Display) {
+        if let Some(paths) = self.paths.get(id) {
+            let value = value.to_string();
+            for path in paths {
+                (self.callback)(ReplayEvent::Change {
+                    path,
+                    value: &value,
+                });
+            }
+        }
+    }
+}
+
+/// The changes of all scalar variables of a VCD file, in ticks of its timescale.
+type ValueIndex = FnvHashMap<vcd::IdCode, Vec<(u64, vcd::Value)>>;
 
 /// A reader for VCD files
 pub struct VcdReader<R>
 where
     R: std::io::Read,
 {
-    parser: vcd::Parser<R>,
+    input: SharedRead<R>,
+    parser: vcd::Parser<SharedRead<R>>,
     scale: Generic<u64>,
     header: vcd::Header,
+    time: Option<Generic<u64>>,
     pins: FnvHashMap<vcd::IdCode, Arc<AtomicPinState>>,
+    /// The variables of the pins in the order they were created.
+    order: Vec<vcd::IdCode>,
+    labels: FnvHashMap<vcd::IdCode, Arc<AtomicStateLabel>>,
+    buses: FnvHashMap<vcd::IdCode, Arc<PinGroup>>,
+    /// The current values of all scalar variables.
+    values: FnvHashMap<vcd::IdCode, vcd::Value>,
+    /// The current values of all vector variables.
+    vectors: FnvHashMap<vcd::IdCode, Vec<vcd::Value>>,
+    /// The current values of all string variables.
+    strings: FnvHashMap<vcd::IdCode, String>,
+    annotations: Vec<Annotation>,
+    /// Logical names of variables, see [VcdReader::alias].
+    aliases: FnvHashMap<String, Vec<String>>,
+    /// Built on the first call of [VcdReader::value_at].
+    index: Option<ValueIndex>,
+    /// The timestamp that ended the replay.
+    error: Option<TimestampError>,
+    /// The parse error that ended the replay.
+    parse_error: Option<ParseError>,
+    /// The quirks recovered by [VcdReader::new_lenient].
+    warnings: Vec<ParseWarning>,
+    /// The position after the header.
+    start: Position,
+    /// The time of the last `$dumpall` section that was replayed.
+    last_sync: Option<Generic<u64>>,
+    /// Read on the first call of [VcdReader::sync_points].
+    sync_points: Option<Vec<SyncPoint>>,
+    observer: Option<Observer>,
 }
 
 impl<R> VcdReader<R>
@@ -27,17 +440,167 @@ where
 {
     /// Create a new VCD reader from a reader that implements [std::io::Read].
     pub fn new(read: R) -> IOResult<Self> {
-        let mut parser = vcd::Parser::new(read);
-        let header = parser.parse_header()?;
-        let scale = Self::timescale_to_duration(&header).unwrap();
+        let input = SharedRead::new(read);
+        let mut parser = vcd::Parser::new(input.clone());
+        let header = parser.parse_header().map_err(|e| input.error(e, None))?;
+        if let Some((0, _)) = header.timescale {
+            return Err(std::io::Error::new(
+                std::io::ErrorKind::InvalidData,
+                "the timescale is 0",
+            ));
+        }
+        let scale = Self::timescale_to_duration(&header);
+        let start = input.position();
         Ok(Self {
+            input,
             parser,
             header,
             scale,
+            time: None,
             pins: FnvHashMap::default(),
+            order: vec![],
+            labels: FnvHashMap::default(),
+            buses: FnvHashMap::default(),
+            values: FnvHashMap::default(),
+            vectors: FnvHashMap::default(),
+            strings: FnvHashMap::default(),
+            annotations: vec![],
+            aliases: FnvHashMap::default(),
+            index: None,
+            error: None,
+            parse_error: None,
+            warnings: vec![],
+            start,
+            last_sync: None,
+            sync_points: None,
+            observer: None,
         })
     }
 
+    /// Return the time of the last timestamp read from the VCD file.
+    ///
+    /// Returns `None` if no timestamp has been read yet.
+    pub fn time(&self) -> Option<Generic<u64>> {
+        self.time
+    }
+
+    /// Return the error of the timestamp that ended the replay early.
+    ///
+    /// The replay stops at the first timestamp that can't be represented or
+    /// goes backwards, instead of continuing with a wrong timeline.
+    pub fn timestamp_error(&self) -> Option<TimestampError> {
+        self.error
+    }
+
+    /// Return the parse error that ended the replay early, with the position
+    /// of the error in the file.
+    ///
+    /// ```
+    /// use embedded_hal_vcd::reader::VcdReader;
+    ///
+    /// let vcd = b"$timescale 1 ns $end
+    /// $scope module top $end
+    /// $var wire 1 ! led $end
+    /// $upscope $end
+    /// $enddefinitions $end
+    /// #0
+    /// 0!
+    /// #100
+    /// 1!
+    /// ?!
+    /// ";
+    /// let mut reader = VcdReader::new(&vcd[..]).unwrap();
+    /// assert_eq!(reader.by_ref().count(), 2);
+    /// let error = reader.parse_error().unwrap();
+    /// assert_eq!((error.line, error.column, error.timestamp), (10, 1, Some(100)));
+    /// assert!(error.to_string().starts_with("line 10, column 1 (byte 117) after #100: "));
+    /// ```
+    pub fn parse_error(&self) -> Option<&ParseError> {
+        self.parse_error.as_ref()
+    }
+
+    /// Return the time of the last `$dumpall` section that was replayed.
+    ///
+    /// A `$dumpall` section contains the values of all variables, so all
+    /// pins, buses and labels are in sync with the file after it, see
+    /// [SyncPoint].
+    pub fn last_sync(&self) -> Option<Generic<u64>> {
+        self.last_sync
+    }
+
+    /// Report each timestamp and change of the replay to an observer, e.g.
+    /// to forward them to a logging or tracing framework when a test hangs.
+    ///
+    /// The events are reported in the order of the file while the replay
+    /// reads it, including the replay of [seek](VcdReader::seek).
+    ///
+    /// ```
+    /// use embedded_hal_vcd::reader::{ReplayEvent, VcdReader};
+    /// use std::sync::{Arc, Mutex};
+    ///
+    /// let vcd = b"$timescale 1 us $end
+    /// $scope module top $end
+    /// $var wire 1 ! led $end
+    /// $upscope $end
+    /// $enddefinitions $end
+    /// #0
+    /// 0!
+    /// #2
+    /// 1!
+    /// ";
+    /// let log = Arc::new(Mutex::new(vec![]));
+    /// let events = log.clone();
+    /// let mut reader = VcdReader::from_bytes(vcd).unwrap();
+    /// reader.set_observer(move |event| {
+    ///     let line = match event {
+    ///         ReplayEvent::Timestamp(t) => format!("at {} ns", t),
+    ///         ReplayEvent::Sync => "all values".to_string(),
+    ///         ReplayEvent::Change { path, value } => format!("{} = {}", path.join("."), value),
+    ///     };
+    ///     events.lock().unwrap().push(line);
+    /// });
+    /// reader.by_ref().count();
+    /// assert_eq!(
+    ///     *log.lock().unwrap(),
+    ///     vec!["at 0 ns", "top.led = 0", "at 2000 ns", "top.led = 1"]
+    /// );
+    /// ```
+    pub fn set_observer<F>(&mut self, observer: F)
+    where
+        F: FnMut(ReplayEvent) + Send + 'static,
+    {
+        let mut vars = vec![];
+        var_paths(&self.header.items, &mut vec![], &mut vars);
+        let mut paths: FnvHashMap<_, Vec<_>> = FnvHashMap::default();
+        for (path, code) in vars {
+            paths.entry(code).or_default().push(path);
+        }
+        self.observer = Some(Observer {
+            callback: Box::new(observer),
+            paths,
+        });
+    }
+
+    /// Return the quirks of the VCD file recovered by
+    /// [new_lenient](VcdReader::new_lenient), empty for other readers.
+    pub fn warnings(&self) -> &[ParseWarning] {
+        &self.warnings
+    }
+
+    /// Return all annotations read from the VCD file so far.
+    ///
+    /// Annotations are comments after the header, e.g. written by
+    /// [VcdWriter::annotate](crate::writer::VcdWriter::annotate).
+    pub fn annotations(&self) -> &[Annotation] {
+        &self.annotations
+    }
+
+    /// Return the text of the last annotation read, e.g. to scope assertions
+    /// to a test phase.
+    pub fn phase(&self) -> Option<&str> {
+        self.annotations.last().map(|a| a.text.as_str())
+    }
+
     /// Return the scale that is used by the VCD file.
     ///
     /// The scale defines the timescale fraction the VCD file is based on.
@@ -46,32 +609,364 @@ where
     }
 
     /// Convert the timescale fraction from the VCD header to an
-    /// [embedded_time::Generic] duration.
-    fn timescale_to_duration(header: &vcd::Header) -> Option<Generic<u64>> {
-        if let Some((scale, unit)) = header.timescale {
-            let fraction = Fraction::new(1, unit.divisor() as u32);
-            Some(Generic::new(scale as u64, fraction))
-        } else {
-            None
+    /// [embedded_time::duration::Generic] duration, a missing timescale is
+    /// 1 ns.
+    fn timescale_to_duration(header: &vcd::Header) -> Generic<u64> {
+        let (scale, unit) = header.timescale.unwrap_or((1, vcd::TimescaleUnit::NS));
+        Generic::new(scale as u64, Fraction::new(1, unit.divisor() as u32))
+    }
+
+    /// Add a logical name for a variable in the VCD file.
+    ///
+    /// Afterwards the variable can be found by the path `[name]`, e.g. with
+    /// [get_pin](Self::get_pin). This keeps tests written against logical
+    /// names working when the channel names of a capture tool change.
+    ///
+    /// ```
+    /// # use embedded_hal_vcd::reader::VcdReader;
+    /// let vcd = "$timescale 1 ns $end
+    /// $scope module la $end
+    /// $var wire 1 ! ch0 $end
+    /// $upscope $end
+    /// $enddefinitions $end
+    /// ";
+    /// let mut reader = VcdReader::new(vcd.as_bytes()).unwrap();
+    /// reader.alias("D0", &["la", "ch0"]).unwrap();
+    /// assert!(reader.alias("D1", &["la", "ch1"]).is_err());
+    /// assert!(reader.get_pin(&["D0"]).is_some());
+    /// ```
+    pub fn alias<S>(&mut self, name: &str, path: &[S]) -> IOResult<()>
+    where
+        S: Borrow<str>,
+    {
+        if self.header.find_var(path).is_none() {
+            return Err(std::io::Error::new(
+                std::io::ErrorKind::NotFound,
+                format!("variable {} not found", path.join(".")),
+            ));
+        }
+        let path = path.iter().map(|s| s.borrow().to_string()).collect();
+        self.aliases.insert(name.to_string(), path);
+        Ok(())
+    }
+
+    /// Add the logical names of a map, see [alias](Self::alias).
+    ///
+    /// Each line of the map contains a name and the path of the variable,
+    /// the scopes separated by dots, e.g. `D0 = la.ch0`. Empty lines and
+    /// lines starting with `#` are skipped.
+    pub fn load_aliases(&mut self, map: &str) -> IOResult<()> {
+        for line in map.lines().map(str::trim) {
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+            let (name, path) = line.split_once('=').ok_or_else(|| {
+                std::io::Error::new(
+                    std::io::ErrorKind::InvalidData,
+                    format!("invalid alias: {}", line),
+                )
+            })?;
+            let path: Vec<&str> = path.trim().split('.').collect();
+            self.alias(name.trim(), &path)?;
+        }
+        Ok(())
+    }
+
+    /// Return a handle for a scope of the VCD file, e.g. `["top", "spi"]`.
+    ///
+    /// An empty path returns the root of the hierarchy. Returns `None` if
+    /// the scope is not found.
+    ///
+    /// ```
+    /// # use embedded_hal_vcd::reader::VcdReader;
+    /// let vcd = "$timescale 1 ns $end
+    /// $scope module top $end
+    /// $scope module spi $end
+    /// $var wire 1 ! sck $end
+    /// $var wire 1 \" mosi $end
+    /// $upscope $end
+    /// $upscope $end
+    /// $enddefinitions $end
+    /// ";
+    /// let mut reader = VcdReader::new(vcd.as_bytes()).unwrap();
+    /// let mut top = reader.scope(&["top"]).unwrap();
+    /// assert_eq!(top.scopes().collect::<Vec<_>>(), vec!["spi"]);
+    /// let mut spi = top.scope("spi").unwrap();
+    /// assert_eq!(spi.vars().collect::<Vec<_>>(), vec!["sck", "mosi"]);
+    /// let sck = spi.pin("sck").unwrap();
+    /// ```
+    pub fn scope<S>(&mut self, path: &[S]) -> Option<Scope<'_, R>>
+    where
+        S: Borrow<str>,
+    {
+        find_scope(&self.header.items, path)?;
+        Some(Scope {
+            path: path.iter().map(|s| s.borrow().to_string()).collect(),
+            reader: self,
+        })
+    }
+
+    /// Find a variable by its path or by an alias.
+    fn find_var<S>(&self, path: &[S]) -> Option<&vcd::Var>
+    where
+        S: Borrow<str>,
+    {
+        if let [name] = path {
+            if let Some(path) = self.aliases.get(name.borrow()) {
+                return self.header.find_var(path);
+            }
         }
+        self.header.find_var(path)
+    }
+
+    /// Return the paths of all variables that share the identifier of a
+    /// variable, in the order of the header and including the variable
+    /// itself.
+    ///
+    /// VCD files can declare the same net in multiple scopes with one
+    /// identifier, e.g. a clock at each level of a design. These variables
+    /// are aliases: their pins, buses and labels share the same state.
+    /// Returns `None` if the variable is not found.
+    ///
+    /// ```
+    /// # use embedded_hal_vcd::reader::VcdReader;
+    /// let vcd = "$timescale 1 ns $end
+    /// $scope module top $end
+    /// $var wire 1 ! clk $end
+    /// $scope module uart $end
+    /// $var wire 1 ! pclk $end
+    /// $upscope $end
+    /// $upscope $end
+    /// $enddefinitions $end
+    /// ";
+    /// let reader = VcdReader::new(vcd.as_bytes()).unwrap();
+    /// assert_eq!(
+    ///     reader.shared_paths(&["top", "uart", "pclk"]).unwrap(),
+    ///     vec![vec!["top", "clk"], vec!["top", "uart", "pclk"]]
+    /// );
+    /// ```
+    pub fn shared_paths<S>(&self, path: &[S]) -> Option<Vec<Vec<String>>>
+    where
+        S: Borrow<str>,
+    {
+        let code = self.find_var(path)?.code;
+        let mut vars = vec![];
+        var_paths(&self.header.items, &mut vec![], &mut vars);
+        Some(
+            vars.into_iter()
+                .filter(|(_, c)| *c == code)
+                .map(|(path, _)| path)
+                .collect(),
+        )
     }
 
     /// Create a new pin from a named variable in the VCD file.
     ///
     /// Returns an [InputPin] that can be used for any [embedded_hal]
     /// driver implementation that needs an [embedded_hal::digital::InputPin].
+    ///
+    /// Pins can also be created while replaying, they start with the current
+    /// value of the variable. Before the first value is read, the pin is
+    /// floating. All pins of a variable share the same state, including the
+    /// pins of variables that share its identifier, see
+    /// [shared_paths](Self::shared_paths).
     pub fn get_pin<S>(&mut self, path: &[S]) -> Option<InputPin>
     where
         S: Borrow<str>,
     {
-        if let Some(v) = self.header.find_var(path) {
-            let state = Arc::new(AtomicPinState::new_with_state(PinState::Floating));
-            let pin = InputPin::new(state.clone());
-            self.pins.insert(v.code, state);
-            Some(pin)
-        } else {
-            None
+        let code = self.find_var(path)?.code;
+        Some(InputPin::new(self.bind(code)))
+    }
+
+    /// Return the shared state of a variable, created on the first call.
+    fn bind(&mut self, code: vcd::IdCode) -> Arc<AtomicPinState> {
+        if let Some(state) = self.pins.get(&code) {
+            return state.clone();
+        }
+        let state = match self.values.get(&code) {
+            Some(value) => (*value).into(),
+            None => PinState::Floating,
+        };
+        let state = Arc::new(AtomicPinState::new_with_state(state));
+        self.order.push(code);
+        self.pins.insert(code, state.clone());
+        state
+    }
+
+    /// Combine named scalar variables into a virtual bus.
+    ///
+    /// Bit `i` of the bus is the variable `paths[i]`, the variables can be
+    /// in different scopes. This allows to read the channels of a logic
+    /// analyzer capture as a parallel word. Use
+    /// [VcdWriterBuilder::add_group_state](crate::writer::VcdWriterBuilder::add_group_state)
+    /// to record the bus as vector variable. Returns `None` if a variable is
+    /// not found.
+    ///
+    /// ```
+    /// # use embedded_hal_vcd::reader::VcdReader;
+    /// let vcd = "$timescale 1 ns $end
+    /// $scope module la $end
+    /// $var wire 1 ! ch0 $end
+    /// $var wire 1 \" ch1 $end
+    /// $upscope $end
+    /// $enddefinitions $end
+    /// #0
+    /// 1!
+    /// 0\"
+    /// #100
+    /// ";
+    /// let mut reader = VcdReader::new(vcd.as_bytes()).unwrap();
+    /// let data = reader
+    ///     .get_virtual_bus(&[["la", "ch1"], ["la", "ch0"]])
+    ///     .unwrap();
+    /// reader.by_ref().count();
+    /// assert_eq!(data.read(), Some(0b10));
+    /// ```
+    pub fn get_virtual_bus<P, S>(&mut self, paths: &[P]) -> Option<Arc<PinGroup>>
+    where
+        P: AsRef<[S]>,
+        S: Borrow<str>,
+    {
+        let codes = paths
+            .iter()
+            .map(|path| Some(self.find_var(path.as_ref())?.code))
+            .collect::<Option<Vec<_>>>()?;
+        let states = codes.into_iter().map(|code| self.bind(code)).collect();
+        Some(Arc::new(PinGroup::from_states(states)))
+    }
+
+    /// Create a new bus from a named vector variable in the VCD file.
+    ///
+    /// Bit `0` of the [PinGroup] is the least significant bit of the
+    /// vector. Single bits and slices of the bus can be passed to drivers
+    /// with [PinGroup::bit] and [PinGroup::slice], e.g. to feed multiple
+    /// drivers from a GPIO port register. Like [get_pin](Self::get_pin),
+    /// the bus starts with the current value of the variable.
+    ///
+    /// ```
+    /// # use embedded_hal_vcd::reader::VcdReader;
+    /// use embedded_hal::digital::InputPin;
+    ///
+    /// let vcd = "$timescale 1 ns $end
+    /// $scope module top $end
+    /// $var wire 8 ! porta $end
+    /// $upscope $end
+    /// $enddefinitions $end
+    /// #0
+    /// b10100100 !
+    /// #100
+    /// ";
+    /// let mut reader = VcdReader::new(vcd.as_bytes()).unwrap();
+    /// let porta = reader.get_bus(&["top", "porta"]).unwrap();
+    /// let led = porta.bit(2);
+    /// reader.by_ref().count();
+    /// assert_eq!(Ok(true), led.is_high());
+    /// assert_eq!(porta.slice(4..8).read(), Some(0xa));
+    /// ```
+    pub fn get_bus<S>(&mut self, path: &[S]) -> Option<Arc<PinGroup>>
+    where
+        S: Borrow<str>,
+    {
+        let v = self.find_var(path)?.clone();
+        if let Some(group) = self.buses.get(&v.code) {
+            return Some(group.clone());
+        }
+        let group = Arc::new(PinGroup::from_states(
+            (0..v.size)
+                .map(|_| Arc::new(AtomicPinState::new()))
+                .collect(),
+        ));
+        if let Some(value) = self.vectors.get(&v.code) {
+            group.write_states(&bus_states(value, v.size));
+        }
+        self.buses.insert(v.code, group.clone());
+        Some(group)
+    }
+
+    /// Sample all pins at a fixed rate instead of at each timestamp.
+    ///
+    /// The returned iterator yields the time and the states of all pins in
+    /// the order they were created with [get_pin](Self::get_pin), every
+    /// `period` starting at the current time, until the last timestamp of
+    /// the VCD file. Changes at the time of a sample are included.
+    ///
+    /// ```
+    /// # use embedded_hal_vcd::{pins::PinState, reader::VcdReader};
+    /// use embedded_time::duration::*;
+    ///
+    /// let vcd = "$timescale 1 ns $end
+    /// $scope module top $end
+    /// $var wire 1 ! clk $end
+    /// $upscope $end
+    /// $enddefinitions $end
+    /// #0
+    /// 0!
+    /// #1500
+    /// 1!
+    /// #2000
+    /// ";
+    /// let mut reader = VcdReader::new(vcd.as_bytes()).unwrap();
+    /// let _clk = reader.get_pin(&["top", "clk"]).unwrap();
+    /// let samples: Vec<_> = reader.sampled_every(1.microseconds()).unwrap().collect();
+    /// assert_eq!(
+    ///     samples,
+    ///     vec![
+    ///         (Nanoseconds(0), vec![PinState::Low]),
+    ///         (Nanoseconds(1000), vec![PinState::Low]),
+    ///         (Nanoseconds(2000), vec![PinState::High]),
+    ///     ]
+    /// );
+    /// ```
+    pub fn sampled_every<D>(&mut self, period: D) -> IOResult<SampledEvery<'_, R>>
+    where
+        D: TryInto<Nanoseconds<u64>>,
+    {
+        let period: Nanoseconds<u64> = period.try_into().map_err(|_e| {
+            std::io::Error::new(
+                std::io::ErrorKind::InvalidInput,
+                "can't convert period to nanoseconds",
+            )
+        })?;
+        if period.0 == 0 {
+            return Err(std::io::Error::new(
+                std::io::ErrorKind::InvalidInput,
+                "period must not be zero",
+            ));
+        }
+        let pending = match self.time {
+            Some(time) => Some(time),
+            None => self.next(),
+        };
+        let to_ns = |t: Generic<u64>| Nanoseconds::<u64>::try_from(t).ok();
+        let pending = pending.and_then(to_ns);
+        Ok(SampledEvery {
+            time: pending.unwrap_or(Nanoseconds(0)),
+            end: pending,
+            pending,
+            period,
+            reader: self,
+        })
+    }
+
+    /// Create a new state label from a named string variable in the VCD file.
+    ///
+    /// The label starts with the current value of the variable and is empty
+    /// until the first value is read.
+    pub fn get_state_label<S>(&mut self, path: &[S]) -> Option<Arc<AtomicStateLabel>>
+    where
+        S: Borrow<str>,
+    {
+        let v = self.find_var(path)?.clone();
+        if let Some(label) = self.labels.get(&v.code) {
+            return Some(label.clone());
         }
+        let label = match self.strings.get(&v.code) {
+            Some(value) => AtomicStateLabel::new(value),
+            None => AtomicStateLabel::default(),
+        };
+        let label = Arc::new(label);
+        self.labels.insert(v.code, label.clone());
+        Some(label)
     }
 }
 
@@ -83,72 +978,1303 @@ where
 
     fn next(&mut self) -> Option<Self::Item> {
         use vcd::Command::*;
+        if self.error.is_some() || self.parse_error.is_some() {
+            return None;
+        }
         let mut timestamp = None;
         for cmd in self.parser.by_ref() {
             match cmd {
                 Ok(Timestamp(t)) => {
-                    timestamp = Some(Generic::new(
-                        self.scale.integer() * t,
-                        *self.scale.scaling_factor(),
-                    ));
+                    let previous = self.time.map(|time| time.integer() / self.scale.integer());
+                    let checked = match previous {
+                        Some(previous) if t < previous => Err(TimestampError::Backwards {
+                            timestamp: t,
+                            previous,
+                        }),
+                        _ => timestamp_to_ns(self.header.timescale, t),
+                    };
+                    let checked = checked.and_then(|_| {
+                        let ticks = self.scale.integer().checked_mul(t);
+                        ticks.ok_or(TimestampError::Overflow { timestamp: t })
+                    });
+                    match checked {
+                        Ok(ticks) => {
+                            let time = Generic::new(ticks, *self.scale.scaling_factor());
+                            #[cfg(feature = "tracing")]
+                            tracing::trace!(timestamp = t, ticks, "timestamp");
+                            if let (Some(observer), Ok(t)) =
+                                (self.observer.as_mut(), Nanoseconds::try_from(time))
+                            {
+                                (observer.callback)(ReplayEvent::Timestamp(t));
+                            }
+                            timestamp = Some(time);
+                        }
+                        Err(error) => self.error = Some(error),
+                    }
                     break;
                 }
                 Ok(ChangeScalar(id, val)) => {
+                    #[cfg(feature = "tracing")]
+                    tracing::trace!(id = %id, value = %val, "change");
+                    if let Some(observer) = self.observer.as_mut() {
+                        observer.change(&id, &val);
+                    }
                     if let Some(pin) = self.pins.get_mut(&id) {
                         (*pin).store(val.into(), Ordering::SeqCst);
                     }
+                    self.values.insert(id, val);
+                }
+                Ok(ChangeVector(id, val)) => {
+                    #[cfg(feature = "tracing")]
+                    tracing::trace!(id = %id, value = ?val, "change");
+                    if let Some(observer) = self.observer.as_mut() {
+                        let bits: String = val.iter().map(|v| v.to_string()).collect();
+                        observer.change(&id, &format_args!("b{}", bits));
+                    }
+                    if let Some(bus) = self.buses.get(&id) {
+                        bus.write_states(&bus_states(&val, bus.width() as u32));
+                    }
+                    self.vectors.insert(id, val);
+                }
+                Ok(ChangeString(id, val)) => {
+                    #[cfg(feature = "tracing")]
+                    tracing::trace!(id = %id, value = %val, "change");
+                    if let Some(observer) = self.observer.as_mut() {
+                        observer.change(&id, &format_args!("s{}", val));
+                    }
+                    if let Some(label) = self.labels.get(&id) {
+                        label.store(&val);
+                    }
+                    self.strings.insert(id, val);
+                }
+                Ok(ChangeReal(id, val)) => {
+                    #[cfg(feature = "tracing")]
+                    tracing::trace!(id = %id, value = val, "change");
+                    if let Some(observer) = self.observer.as_mut() {
+                        observer.change(&id, &format_args!("r{}", val));
+                    }
+                }
+                Ok(Comment(text)) => {
+                    self.annotations.push(Annotation {
+                        time: self.time,
+                        text,
+                    });
+                }
+                Ok(Begin(vcd::SimulationCommand::Dumpall)) => {
+                    if let Some(observer) = self.observer.as_mut() {
+                        (observer.callback)(ReplayEvent::Sync);
+                    }
+                    self.last_sync = self.time;
+                }
+                Err(error) => {
+                    let time = self.time.map(|time| time.integer() / self.scale.integer());
+                    self.parse_error = Some(self.input.error(error, time));
+                    break;
                 }
                 _ => {}
             }
         }
+        if timestamp.is_some() {
+            self.time = timestamp;
+        }
         timestamp
     }
 }
 
-#[cfg(test)]
-mod tests {
-    use super::*;
-    use embedded_hal::digital::InputPin;
+/// Collect the paths and identifiers of all variables.
+fn var_paths(
+    items: &[vcd::ScopeItem],
+    prefix: &mut Vec<String>,
+    vars: &mut Vec<(Vec<String>, vcd::IdCode)>,
+) {
+    for item in items {
+        match item {
+            vcd::ScopeItem::Var(var) => {
+                let mut path = prefix.clone();
+                path.push(var.reference.clone());
+                vars.push((path, var.code));
+            }
+            vcd::ScopeItem::Scope(scope) => {
+                prefix.push(scope.identifier.clone());
+                var_paths(&scope.children, prefix, vars);
+                prefix.pop();
+            }
+        }
+    }
+}
 
-    #[test]
-    fn read_simple() {
-        let vcd = "
-$timescale 1ns $end
-$scope module logic $end
-$var wire 1 t test $end
-$upscope $end
-$enddefinitions $end
-#0
-0t
-#100
-1t
-#200
-1t
-#300
-0t
-#400
-#500
-"
-        .as_bytes();
-        let states = vec![
-            (0, false),
-            (100, false),
-            (200, true),
-            (300, true),
-            (400, false),
-            (500, false),
-        ];
-        let mut reader = VcdReader::new(vcd).unwrap();
-        let pin = reader.get_pin(&["logic", "test"]).unwrap();
-        for (vcd_time, (state_time, state_pin)) in reader.zip(states) {
-            let vcd_time: Nanoseconds<u64> = vcd_time.try_into().unwrap();
-            assert_eq!(vcd_time, state_time.nanoseconds());
-            assert_eq!(
-                pin.is_high(),
+/// Return the items of the scope with `path`.
+fn find_scope<'h, S>(items: &'h [vcd::ScopeItem], path: &[S]) -> Option<&'h [vcd::ScopeItem]>
+where
+    S: Borrow<str>,
+{
+    match path.split_first() {
+        Some((name, rest)) => items.iter().find_map(|item| match item {
+            vcd::ScopeItem::Scope(scope) if scope.identifier == name.borrow() => {
+                find_scope(&scope.children, rest)
+            }
+            _ => None,
+        }),
+        None => Some(items),
+    }
+}
+
+/// A scope of the VCD file opened with [VcdReader::scope].
+///
+/// Pins and other values are created relative to the scope, which avoids
+/// repeating long paths of deep hierarchies, e.g. from RTL simulations.
+pub struct Scope<'a, R>
+where
+    R: std::io::Read,
+{
+    reader: &'a mut VcdReader<R>,
+    path: Vec<String>,
+}
+
+impl<'a, R> Scope<'a, R>
+where
+    R: std::io::Read,
+{
+    /// Return the path of the scope.
+    pub fn path(&self) -> &[String] {
+        &self.path
+    }
+
+    fn items(&self) -> &[vcd::ScopeItem] {
+        find_scope(&self.reader.header.items, &self.path).unwrap_or_default()
+    }
+
+    /// Return the names of the child scopes.
+    pub fn scopes(&self) -> impl Iterator<Item = &str> + '_ {
+        self.items().iter().filter_map(|item| match item {
+            vcd::ScopeItem::Scope(scope) => Some(scope.identifier.as_str()),
+            _ => None,
+        })
+    }
+
+    /// Return the names of the variables in the scope.
+    pub fn vars(&self) -> impl Iterator<Item = &str> + '_ {
+        self.items().iter().filter_map(|item| match item {
+            vcd::ScopeItem::Var(var) => Some(var.reference.as_str()),
+            _ => None,
+        })
+    }
+
+    /// Return a handle for a child scope.
+    pub fn scope(&mut self, name: &str) -> Option<Scope<'_, R>> {
+        let path = self.child(name);
+        self.reader.scope(&path)
+    }
+
+    fn child(&self, name: &str) -> Vec<String> {
+        let mut path = self.path.clone();
+        path.push(name.to_string());
+        path
+    }
+
+    /// Create a new pin from a variable of the scope, see [VcdReader::get_pin].
+    pub fn pin(&mut self, name: &str) -> Option<InputPin> {
+        let path = self.child(name);
+        self.reader.get_pin(&path)
+    }
+
+    /// Create a new bus from a variable of the scope, see [VcdReader::get_bus].
+    pub fn bus(&mut self, name: &str) -> Option<Arc<PinGroup>> {
+        let path = self.child(name);
+        self.reader.get_bus(&path)
+    }
+
+    /// Create a new state label from a variable of the scope, see
+    /// [VcdReader::get_state_label].
+    pub fn state_label(&mut self, name: &str) -> Option<Arc<AtomicStateLabel>> {
+        let path = self.child(name);
+        self.reader.get_state_label(&path)
+    }
+}
+
+/// Convert a vector value to the states of a bus of `width` bits, starting
+/// with bit `0`.
+///
+/// Values shorter than the bus are extended like in VCD files: with `0`,
+/// or with `x` or `z` if the leftmost bit is `x` or `z`.
+fn bus_states(value: &[vcd::Value], width: u32) -> Vec<PinState> {
+    let fill = match value.first() {
+        Some(v @ (vcd::Value::X | vcd::Value::Z)) => *v,
+        _ => vcd::Value::V0,
+    };
+    (0..width as usize)
+        .map(|bit| {
+            let v = match value.len().checked_sub(bit + 1) {
+                Some(i) => value[i],
+                None => fill,
+            };
+            v.into()
+        })
+        .collect()
+}
+
+/// An iterator that samples the pins of a [VcdReader] at a fixed rate.
+///
+/// Created with [VcdReader::sampled_every].
+pub struct SampledEvery<'a, R>
+where
+    R: std::io::Read,
+{
+    reader: &'a mut VcdReader<R>,
+    period: Nanoseconds<u64>,
+    /// The time of the next sample.
+    time: Nanoseconds<u64>,
+    /// The last timestamp read, whose changes are not applied yet.
+    pending: Option<Nanoseconds<u64>>,
+    /// The last timestamp of the file seen so far.
+    end: Option<Nanoseconds<u64>>,
+}
+
+impl<'a, R> Iterator for SampledEvery<'a, R>
+where
+    R: std::io::Read,
+{
+    type Item = (Nanoseconds<u64>, Vec<PinState>);
+
+    fn next(&mut self) -> Option<Self::Item> {
+        while let Some(pending) = self.pending.filter(|p| *p <= self.time) {
+            self.end = Some(pending);
+            self.pending = self
+                .reader
+                .next()
+                .and_then(|t| Nanoseconds::<u64>::try_from(t).ok());
+        }
+        if self.pending.is_none() && self.end.is_none_or(|end| self.time > end) {
+            return None;
+        }
+        let time = self.time;
+        self.time = time + self.period;
+        let states = self
+            .reader
+            .order
+            .iter()
+            .map(|id| self.reader.pins[id].load(Ordering::SeqCst))
+            .collect();
+        Some((time, states))
+    }
+}
+
+impl<'a> VcdReader<Cursor<&'a [u8]>> {
+    /// Create a new VCD reader from data in memory.
+    ///
+    /// This doesn't need a file system, so it can be used on targets like
+    /// `wasm32-unknown-unknown`, e.g. for a viewer that runs in a browser.
+    ///
+    /// ```
+    /// use embedded_hal_vcd::pins::PinState;
+    /// use embedded_hal_vcd::reader::VcdReader;
+    /// use embedded_time::duration::*;
+    ///
+    /// let vcd = b"$timescale 1 ns $end
+    /// $scope module top $end
+    /// $var wire 1 ! led $end
+    /// $upscope $end
+    /// $enddefinitions $end
+    /// #0
+    /// 0!
+    /// #100
+    /// 1!
+    /// ";
+    /// let mut reader = VcdReader::from_bytes(vcd).unwrap();
+    /// let state = reader.value_at(&["top", "led"], Nanoseconds(150u64)).unwrap();
+    /// assert_eq!(state, Some(PinState::High));
+    /// ```
+    pub fn from_bytes(data: &'a [u8]) -> IOResult<Self> {
+        Self::new(Cursor::new(data))
+    }
+}
+
+impl VcdReader<Cursor<Vec<u8>>> {
+    /// Create a new VCD reader that recovers from quirks of VCD files that
+    /// don't follow the standard, see [repair].
+    ///
+    /// The recovered quirks are returned by [warnings](VcdReader::warnings).
+    ///
+    /// ```
+    /// use embedded_hal::digital::InputPin;
+    /// use embedded_hal_vcd::reader::{ParseWarning, VcdReader};
+    ///
+    /// // a header without timescale and $enddefinitions
+    /// let vcd = b"$scope module la $end
+    /// $var wire 1 ! D0 $end
+    /// $upscope $end
+    /// #0\t1!
+    /// ";
+    /// let mut reader = VcdReader::new_lenient(&vcd[..]).unwrap();
+    /// let mut pin = reader.get_pin(&["la", "D0"]).unwrap();
+    /// reader.next();
+    /// reader.next();
+    /// assert!(pin.is_high().unwrap());
+    /// assert_eq!(reader.warnings()[1], ParseWarning::MissingTimescale);
+    /// ```
+    pub fn new_lenient<T: Read>(read: T) -> IOResult<Self> {
+        let (data, warnings) = repair(read)?;
+        let mut reader = Self::new(Cursor::new(data))?;
+        reader.warnings = warnings;
+        Ok(reader)
+    }
+}
+
+impl<R> VcdReader<R>
+where
+    R: Read + Seek,
+{
+    /// Capture the current position in the VCD file and the state of all
+    /// pins that have been created with [VcdReader::get_pin].
+    ///
+    /// The returned [Snapshot] can be passed to [VcdReader::restore] to
+    /// continue the replay from this point again.
+    pub fn checkpoint(&mut self) -> IOResult<Snapshot> {
+        // the parser reads the input byte by byte, so the position after
+        // the bytes read by the parser is exactly after the last parsed command
+        let offset = self.input.stream_position()?;
+        let position = self.input.position();
+        let states = self
+            .pins
+            .iter()
+            .map(|(id, pin)| (*id, pin.load(Ordering::SeqCst)))
+            .collect();
+        let labels = self
+            .labels
+            .iter()
+            .map(|(id, label)| (*id, label.load()))
+            .collect();
+        Ok(Snapshot {
+            offset,
+            position,
+            time: self.time,
+            states,
+            labels,
+            values: self.values.clone(),
+            vectors: self.vectors.clone(),
+            strings: self.strings.clone(),
+            annotations: self.annotations.len(),
+            last_sync: self.last_sync,
+        })
+    }
+
+    /// Restore the position in the VCD file and the pin states from a [Snapshot].
+    ///
+    /// Pins that have been created after the snapshot was taken keep
+    /// their current state.
+    pub fn restore(&mut self, snapshot: &Snapshot) -> IOResult<()> {
+        self.input.seek(snapshot.offset, snapshot.position)?;
+        self.parser = vcd::Parser::new(self.input.clone());
+        self.time = snapshot.time;
+        self.last_sync = snapshot.last_sync;
+        self.error = None;
+        self.parse_error = None;
+        self.annotations.truncate(snapshot.annotations);
+        self.values = snapshot.values.clone();
+        self.vectors = snapshot.vectors.clone();
+        for (id, value) in snapshot.vectors.iter() {
+            if let Some(bus) = self.buses.get(id) {
+                bus.write_states(&bus_states(value, bus.width() as u32));
+            }
+        }
+        self.strings = snapshot.strings.clone();
+        for (id, value) in snapshot.labels.iter() {
+            if let Some(label) = self.labels.get(id) {
+                label.store(value);
+            }
+        }
+        for (id, state) in snapshot.states.iter() {
+            if let Some(pin) = self.pins.get(id) {
+                pin.store(state.clone(), Ordering::SeqCst);
+            }
+        }
+        Ok(())
+    }
+
+    /// Return the state of a named variable at an arbitrary time.
+    ///
+    /// Changes at `t` are included. Returns `None` if the variable is not
+    /// found or has no value at `t`. The changes of all variables are read
+    /// into an index on the first call, the replay position is not changed.
+    pub fn value_at<S>(&mut self, path: &[S], t: Nanoseconds<u64>) -> IOResult<Option<PinState>>
+    where
+        S: Borrow<str>,
+    {
+        let code = match self.find_var(path) {
+            Some(v) => v.code,
+            None => return Ok(None),
+        };
+        let ticks = self.ticks(t);
+        let index = match self.index.take() {
+            Some(index) => index,
+            None => self.build_index()?,
+        };
+        let value = index.get(&code).and_then(|changes| {
+            let i = changes.partition_point(|(time, _)| *time <= ticks);
+            i.checked_sub(1).map(|i| changes[i].1.into())
+        });
+        self.index = Some(index);
+        Ok(value)
+    }
+
+    /// Convert a time to ticks of the timescale, rounded down.
+    fn ticks(&self, t: Nanoseconds<u64>) -> u64 {
+        let (scale, unit) = self.header.timescale.unwrap_or((1, vcd::TimescaleUnit::NS));
+        let ticks = t.0 as u128 * unit.divisor() as u128 / (scale as u128 * 1_000_000_000);
+        // a time after the last representable timestamp
+        u64::try_from(ticks).unwrap_or(u64::MAX)
+    }
+
+    /// Return the `$dumpall` sections of the VCD file.
+    ///
+    /// The sections of the whole file are read on the first call, the
+    /// replay position is not changed.
+    pub fn sync_points(&mut self) -> IOResult<Vec<SyncPoint>> {
+        if self.sync_points.is_none() {
+            self.sync_points = Some(self.scan(Self::read_sync_points)?);
+        }
+        Ok(self.sync_points.clone().unwrap_or_default())
+    }
+
+    /// Replay up to `t` and return the first timestamp after it, like
+    /// `reader.find(|time| time > t)`.
+    ///
+    /// Instead of replaying all changes, the replay continues at the last
+    /// [SyncPoint] before `t`, which can also be before the current
+    /// position. Without a sync point before `t`, the replay starts at the
+    /// beginning of the file if the current time is after `t`. Annotations
+    /// of skipped parts of the file are not returned.
+    ///
+    /// ```
+    /// use embedded_hal::digital::InputPin;
+    /// use embedded_hal_vcd::reader::VcdReader;
+    /// use embedded_time::duration::*;
+    ///
+    /// let vcd = b"$timescale 1 us $end
+    /// $scope module top $end
+    /// $var wire 1 ! led $end
+    /// $upscope $end
+    /// $enddefinitions $end
+    /// #0
+    /// $dumpall 0! $end
+    /// #500
+    /// 1!
+    /// #1000
+    /// $dumpall 1! $end
+    /// #1500
+    /// 0!
+    /// #2000
+    /// ";
+    /// let mut reader = VcdReader::from_bytes(vcd).unwrap();
+    /// let mut led = reader.get_pin(&["top", "led"]).unwrap();
+    /// let next = reader.seek(Nanoseconds(1_200_000u64)).unwrap().unwrap();
+    /// assert_eq!(Nanoseconds::<u64>::try_from(next), Ok(Nanoseconds(1_500_000)));
+    /// assert!(led.is_high().unwrap());
+    /// let _ = reader.seek(Nanoseconds(600_000u64)).unwrap();
+    /// assert!(led.is_high().unwrap());
+    /// ```
+    pub fn seek(&mut self, t: Nanoseconds<u64>) -> IOResult<Option<Generic<u64>>> {
+        let ticks = self.ticks(t);
+        let scale = self.scale.integer();
+        let current = self.time.map(|time| time.integer() / scale);
+        let points = self.sync_points()?;
+        let sync = points
+            .iter()
+            .rev()
+            .find(|point| point.time.integer() / scale <= ticks);
+        match sync {
+            Some(sync) if current.is_none_or(|c| c < sync.time.integer() / scale || c > ticks) => {
+                self.jump(sync.position, Some(sync.time))?;
+            }
+            None if current.is_some_and(|c| c > ticks) => {
+                self.jump(self.start, None)?;
+            }
+            _ => {}
+        }
+        Ok(self.find(|time| time.integer() / scale > ticks))
+    }
+
+    /// Continue the replay at a position after the timestamp `time`.
+    ///
+    /// The values read so far are forgotten, the position has to be the
+    /// start of the changes or a sync point, which sets all values again.
+    fn jump(&mut self, position: Position, time: Option<Generic<u64>>) -> IOResult<()> {
+        self.input.seek(position.offset, position)?;
+        self.parser = vcd::Parser::new(self.input.clone());
+        self.reset();
+        self.time = time;
+        self.error = None;
+        self.parse_error = None;
+        let ticks = |time: Option<Generic<u64>>| time.map(|time| time.integer());
+        self.annotations
+            .retain(|annotation| ticks(annotation.time) <= ticks(time));
+        Ok(())
+    }
+
+    /// Forget all values read so far, as before the first change.
+    fn reset(&mut self) {
+        self.values.clear();
+        self.vectors.clear();
+        self.strings.clear();
+        for pin in self.pins.values() {
+            pin.store(PinState::Floating, Ordering::SeqCst);
+        }
+        for bus in self.buses.values() {
+            bus.release();
+        }
+        for label in self.labels.values() {
+            label.store("");
+        }
+        self.last_sync = None;
+    }
+
+    fn build_index(&mut self) -> IOResult<ValueIndex> {
+        self.scan(Self::read_index)
+    }
+
+    /// Read the whole file with `read` and continue at the current position.
+    fn scan<T>(&mut self, read: fn(&mut Self) -> IOResult<T>) -> IOResult<T> {
+        let offset = self.input.stream_position()?;
+        let position = self.input.position();
+        self.input.seek(0, Position::default())?;
+        let result = read(self);
+        self.input.seek(offset, position)?;
+        self.parser = vcd::Parser::new(self.input.clone());
+        result
+    }
+
+    fn read_sync_points(&mut self) -> IOResult<Vec<SyncPoint>> {
+        let mut parser = vcd::Parser::new(self.input.clone());
+        parser
+            .parse_header()
+            .map_err(|e| self.input.error(e, None))?;
+        let mut points = vec![];
+        let mut stamp = None;
+        for command in parser {
+            match command.map_err(|e| self.input.error(e, stamp.map(|(t, _)| t)))? {
+                vcd::Command::Timestamp(t) => {
+                    stamp = Some((t, self.input.position()));
+                }
+                vcd::Command::Begin(vcd::SimulationCommand::Dumpall) => {
+                    let point = stamp.and_then(|(t, position)| {
+                        let ticks = self.scale.integer().checked_mul(t)?;
+                        let time = Generic::new(ticks, *self.scale.scaling_factor());
+                        Some(SyncPoint { time, position })
+                    });
+                    points.extend(point);
+                }
+                _ => {}
+            }
+        }
+        Ok(points)
+    }
+
+    fn read_index(&mut self) -> IOResult<ValueIndex> {
+        let mut parser = vcd::Parser::new(self.input.clone());
+        parser
+            .parse_header()
+            .map_err(|e| self.input.error(e, None))?;
+        let mut index = ValueIndex::default();
+        let mut time = None;
+        for command in parser {
+            match command.map_err(|e| self.input.error(e, time))? {
+                vcd::Command::Timestamp(t) if time.is_some_and(|previous| t < previous) => {
+                    return Err(TimestampError::Backwards {
+                        timestamp: t,
+                        previous: time.unwrap(),
+                    }
+                    .into());
+                }
+                vcd::Command::Timestamp(t) => time = Some(t),
+                vcd::Command::ChangeScalar(id, value) => {
+                    index
+                        .entry(id)
+                        .or_default()
+                        .push((time.unwrap_or(0), value));
+                }
+                _ => {}
+            }
+        }
+        Ok(index)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use embedded_hal::digital::InputPin;
+
+    #[test]
+    fn read_simple() {
+        let vcd = "
+$timescale 1ns $end
+$scope module logic $end
+$var wire 1 t test $end
+$upscope $end
+$enddefinitions $end
+#0
+0t
+#100
+1t
+#200
+1t
+#300
+0t
+#400
+#500
+"
+        .as_bytes();
+        let states = vec![
+            (0, false),
+            (100, false),
+            (200, true),
+            (300, true),
+            (400, false),
+            (500, false),
+        ];
+        let mut reader = VcdReader::new(vcd).unwrap();
+        let pin = reader.get_pin(&["logic", "test"]).unwrap();
+        for (vcd_time, (state_time, state_pin)) in reader.zip(states) {
+            let vcd_time: Nanoseconds<u64> = vcd_time.try_into().unwrap();
+            assert_eq!(vcd_time, state_time.nanoseconds());
+            assert_eq!(
+                pin.is_high(),
                 Ok(state_pin),
                 "testing pin state at timestamp {}",
                 vcd_time
             );
         }
     }
+
+    #[test]
+    fn get_pin_while_replaying() {
+        let vcd = "
+$timescale 1ns $end
+$scope module logic $end
+$var wire 1 a clk $end
+$var wire 1 b cs $end
+$upscope $end
+$enddefinitions $end
+#0
+1a
+0b
+#100
+0a
+#200
+"
+        .as_bytes();
+        let mut reader = VcdReader::new(vcd).unwrap();
+        let clk = reader.get_pin(&["logic", "clk"]).unwrap();
+        reader.next().unwrap();
+        assert_eq!(clk.is_low(), Ok(false));
+        reader.next().unwrap();
+        let cs = reader.get_pin(&["logic", "cs"]).unwrap();
+        assert_eq!(cs.is_low(), Ok(true));
+        reader.next().unwrap();
+        assert_eq!(clk.is_low(), Ok(true));
+        assert_eq!(cs.is_low(), Ok(true));
+    }
+
+    #[test]
+    fn checkpoint_restore() {
+        let vcd = "
+$timescale 1ns $end
+$scope module logic $end
+$var wire 1 t test $end
+$upscope $end
+$enddefinitions $end
+#0
+1t
+#100
+0t
+#200
+1t
+#300
+"
+        .as_bytes();
+        let mut reader = VcdReader::new(std::io::Cursor::new(vcd)).unwrap();
+        let pin = reader.get_pin(&["logic", "test"]).unwrap();
+        reader.next().unwrap();
+        reader.next().unwrap();
+        assert_eq!(pin.is_high(), Ok(true));
+        let snapshot = reader.checkpoint().unwrap();
+        let branch: Vec<_> = reader.by_ref().collect();
+        assert_eq!(pin.is_high(), Ok(true));
+        reader.restore(&snapshot).unwrap();
+        assert_eq!(reader.time(), snapshot.time());
+        assert_eq!(pin.is_high(), Ok(true));
+        reader.next().unwrap();
+        assert_eq!(pin.is_low(), Ok(true));
+        reader.restore(&snapshot).unwrap();
+        assert_eq!(reader.collect::<Vec<_>>(), branch);
+
+        // a snapshot in the middle of an input larger than the read buffer
+        let mut vcd = String::from(std::str::from_utf8(vcd).unwrap());
+        for i in 4..5000 {
+            vcd += &format!("{}t\n#{}\n", i & 1, i * 100);
+        }
+        let mut reader = VcdReader::new(std::io::Cursor::new(vcd.as_bytes())).unwrap();
+        let pin = reader.get_pin(&["logic", "test"]).unwrap();
+        reader.nth(2500).unwrap();
+        let snapshot = reader.checkpoint().unwrap();
+        let state = pin.is_high();
+        let branch: Vec<_> = reader.by_ref().collect();
+        assert_eq!(branch.len(), 2499);
+        reader.restore(&snapshot).unwrap();
+        assert_eq!(pin.is_high(), state);
+        assert_eq!(reader.collect::<Vec<_>>(), branch);
+    }
+
+    #[test]
+    fn value_at() {
+        let vcd = "
+$timescale 10ns $end
+$scope module logic $end
+$var wire 1 t test $end
+$upscope $end
+$enddefinitions $end
+#0
+0t
+#10
+1t
+#20
+0t
+#30
+"
+        .as_bytes();
+        let mut reader = VcdReader::new(std::io::Cursor::new(vcd)).unwrap();
+        let pin = reader.get_pin(&["logic", "test"]).unwrap();
+        reader.next().unwrap();
+        reader.next().unwrap();
+        let path = ["logic", "test"];
+        assert_eq!(
+            reader.value_at(&path, Nanoseconds(99)).unwrap(),
+            Some(PinState::Low)
+        );
+        assert_eq!(
+            reader.value_at(&path, Nanoseconds(100)).unwrap(),
+            Some(PinState::High)
+        );
+        assert_eq!(
+            reader.value_at(&path, Nanoseconds(250)).unwrap(),
+            Some(PinState::Low)
+        );
+        assert_eq!(
+            reader.value_at(&["logic", "x"], Nanoseconds(0)).unwrap(),
+            None
+        );
+        assert_eq!(pin.is_low(), Ok(true));
+        assert_eq!(reader.by_ref().count(), 2);
+        assert_eq!(pin.is_low(), Ok(true));
+    }
+
+    #[test]
+    fn read_aliases() {
+        let vcd = "
+$timescale 1ns $end
+$scope module la $end
+$var wire 1 a ch0 $end
+$var wire 1 b ch1 $end
+$upscope $end
+$enddefinitions $end
+#0
+1a
+0b
+#100
+"
+        .as_bytes();
+        let mut reader = VcdReader::new(vcd).unwrap();
+        reader
+            .load_aliases("# logic analyzer channels\nSCK = la.ch0\n\nMOSI = la.ch1\n")
+            .unwrap();
+        assert!(reader.load_aliases("MISO la.ch2").is_err());
+        let sck = reader.get_pin(&["SCK"]).unwrap();
+        let mosi = reader.get_pin(&["MOSI"]).unwrap();
+        reader.by_ref().count();
+        assert_eq!(sck.is_high(), Ok(true));
+        assert_eq!(mosi.is_low(), Ok(true));
+    }
+
+    #[test]
+    fn read_annotations() {
+        let vcd = "
+$timescale 1us $end
+$scope module logic $end
+$var wire 1 t test $end
+$upscope $end
+$enddefinitions $end
+#0
+$comment
+    reset device
+$end
+0t
+#100
+#200
+$comment begin OTA $end
+1t
+#300
+"
+        .as_bytes();
+        let mut reader = VcdReader::new(vcd).unwrap();
+        let _pin = reader.get_pin(&["logic", "test"]).unwrap();
+        assert_eq!(reader.phase(), None);
+        reader.next().unwrap();
+        reader.next().unwrap();
+        assert_eq!(reader.phase(), Some("reset device"));
+        assert_eq!(reader.by_ref().count(), 2);
+        let time = |t| Some(Generic::new(t, Fraction::new(1, 1_000_000)));
+        assert_eq!(
+            reader.annotations(),
+            &[
+                Annotation {
+                    time: time(0),
+                    text: "reset device".to_string(),
+                },
+                Annotation {
+                    time: time(200),
+                    text: "begin OTA".to_string(),
+                },
+            ]
+        );
+    }
+
+    #[test]
+    fn read_state_label() {
+        let vcd = "
+$timescale 1ns $end
+$scope module logic $end
+$var string 1 s state $end
+$upscope $end
+$enddefinitions $end
+#0
+sIDLE s
+#100
+sTX s
+#200
+"
+        .as_bytes();
+        let mut reader = VcdReader::new(vcd).unwrap();
+        let state = reader.get_state_label(&["logic", "state"]).unwrap();
+        let states: Vec<String> = reader.by_ref().map(|_| state.load()).collect();
+        assert_eq!(states, vec!["", "IDLE", "TX"]);
+    }
+
+    #[test]
+    fn timestamp_overflow() {
+        // the last representable second and one after it
+        let vcd = "
+$timescale 1 s $end
+$scope module logic $end
+$var wire 1 ! pin $end
+$upscope $end
+$enddefinitions $end
+#0
+0!
+#18446744073
+1!
+#18446744074
+0!
+#18446744075
+"
+        .as_bytes();
+        let mut reader = VcdReader::new(vcd).unwrap();
+        let pin = reader.get_pin(&["logic", "pin"]).unwrap();
+        let times: Vec<Nanoseconds<u64>> = reader
+            .by_ref()
+            .map(|t| Nanoseconds::try_from(t).unwrap())
+            .collect();
+        assert_eq!(
+            times,
+            vec![Nanoseconds(0u64), Nanoseconds(18_446_744_073_000_000_000)]
+        );
+        assert!(pin.is_high().unwrap());
+        assert_eq!(
+            reader.timestamp_error(),
+            Some(TimestampError::Overflow {
+                timestamp: 18_446_744_074
+            })
+        );
+        assert_eq!(reader.next(), None);
+        let error = crate::analysis::Waveform::read(vcd, &["logic", "pin"]).unwrap_err();
+        assert_eq!(error.kind(), std::io::ErrorKind::InvalidData);
+
+        // fine timescales are divided down
+        let max = format!(
+            "$timescale 1 ps $end
+$scope module logic $end
+$var wire 1 ! pin $end
+$upscope $end
+$enddefinitions $end
+#{}
+1!
+",
+            u64::MAX
+        );
+        let mut reader = VcdReader::new(max.as_bytes()).unwrap();
+        let t = reader.next().unwrap();
+        assert_eq!(t.integer(), u64::MAX);
+        assert_eq!(reader.timestamp_error(), None);
+        let mut reader = VcdReader::from_bytes(max.as_bytes()).unwrap();
+        let state = reader
+            .value_at(&["logic", "pin"], Nanoseconds(u64::MAX))
+            .unwrap();
+        assert_eq!(state, Some(PinState::High));
+    }
+
+    #[test]
+    fn missing_timescale() {
+        let vcd = "
+$scope module logic $end
+$var wire 1 ! pin $end
+$upscope $end
+$enddefinitions $end
+#0
+0!
+#5
+1!
+"
+        .as_bytes();
+        let reader = VcdReader::new(vcd).unwrap();
+        assert_eq!(
+            reader.scale(),
+            Generic::new(1, Fraction::new(1, 1_000_000_000))
+        );
+        let times: Vec<Nanoseconds<u64>> =
+            reader.map(|t| Nanoseconds::try_from(t).unwrap()).collect();
+        assert_eq!(times, vec![Nanoseconds(0u64), Nanoseconds(5u64)]);
+    }
+
+    #[test]
+    fn zero_timescale() {
+        let vcd = "
+$timescale 0 ns $end
+$scope module logic $end
+$var wire 1 ! pin $end
+$upscope $end
+$enddefinitions $end
+#0
+0!
+#5
+1!
+"
+        .as_bytes();
+        let error = VcdReader::new(vcd).err().unwrap();
+        assert_eq!(error.kind(), std::io::ErrorKind::InvalidData);
+        assert_eq!(error.to_string(), "the timescale is 0");
+    }
+
+    #[test]
+    fn timestamp_backwards() {
+        let vcd = "
+$timescale 10 ns $end
+$scope module logic $end
+$var wire 1 ! pin $end
+$upscope $end
+$enddefinitions $end
+#0
+0!
+#5
+1!
+#3
+0!
+#8
+"
+        .as_bytes();
+        let mut reader = VcdReader::new(vcd).unwrap();
+        let pin = reader.get_pin(&["logic", "pin"]).unwrap();
+        let times: Vec<Nanoseconds<u64>> = reader
+            .by_ref()
+            .map(|t| Nanoseconds::try_from(t).unwrap())
+            .collect();
+        assert_eq!(times, vec![Nanoseconds(0u64), Nanoseconds(50u64)]);
+        assert!(pin.is_high().unwrap());
+        let error = TimestampError::Backwards {
+            timestamp: 3,
+            previous: 5,
+        };
+        assert_eq!(reader.timestamp_error(), Some(error));
+        assert_eq!(
+            error.to_string(),
+            "timestamp 3 is before the previous timestamp 5"
+        );
+        let error = crate::analysis::Waveform::read_all(vcd).unwrap_err();
+        assert_eq!(error.kind(), std::io::ErrorKind::InvalidData);
+        let mut reader = VcdReader::from_bytes(vcd).unwrap();
+        assert!(reader
+            .value_at(&["logic", "pin"], Nanoseconds(40u64))
+            .is_err());
+    }
+
+    #[test]
+    fn parse_error_position() {
+        let header = "$timescale 1 ns $end
+$scope module logic $end
+$var wire 1 ! pin $end
+$upscope $end
+";
+        let error = VcdReader::new(format!("{}$upscope $end\n", header).as_bytes())
+            .err()
+            .unwrap();
+        let error = error.into_inner().unwrap();
+        let error = error.downcast_ref::<ParseError>().unwrap();
+        assert_eq!((error.line, error.column, error.timestamp), (6, 0, None));
+
+        let vcd = format!("{}$enddefinitions $end\n#0\n0!\n#10\n1!\n#20\n2!\n", header);
+        let error = crate::analysis::Waveform::read_all(vcd.as_bytes()).unwrap_err();
+        assert_eq!(error.kind(), std::io::ErrorKind::InvalidData);
+        let error = error.into_inner().unwrap();
+        let error = error.downcast_ref::<ParseError>().unwrap();
+        assert_eq!(
+            (error.line, error.column, error.timestamp),
+            (11, 1, Some(20))
+        );
+
+        // the position is restored with a snapshot
+        let mut reader = VcdReader::from_bytes(vcd.as_bytes()).unwrap();
+        reader.next();
+        let snapshot = reader.checkpoint().unwrap();
+        assert_eq!(reader.by_ref().count(), 2);
+        assert_eq!(reader.parse_error().unwrap().line, 11);
+        reader.restore(&snapshot).unwrap();
+        assert!(reader.parse_error().is_none());
+        assert_eq!(reader.by_ref().count(), 2);
+        assert_eq!(reader.parse_error().unwrap().offset, vcd.len() as u64 - 2);
+    }
+
+    #[test]
+    fn aliased_variables() {
+        let vcd = "$timescale 1 ns $end
+$scope module top $end
+$var wire 1 ! clk $end
+$var wire 4 \" data $end
+$var string 1 # state $end
+$scope module core $end
+$var wire 1 ! core_clk $end
+$var wire 4 \" bus $end
+$var string 1 # fsm $end
+$upscope $end
+$upscope $end
+$enddefinitions $end
+#0
+1!
+b1010 \"
+sidle #
+#10
+";
+        let mut reader = VcdReader::new(vcd.as_bytes()).unwrap();
+        let clk = reader.get_pin(&["top", "clk"]).unwrap();
+        let core_clk = reader.get_pin(&["top", "core", "core_clk"]).unwrap();
+        let data = reader.get_bus(&["top", "data"]).unwrap();
+        let bus = reader.get_bus(&["top", "core", "bus"]).unwrap();
+        let state = reader.get_state_label(&["top", "state"]).unwrap();
+        let fsm = reader.get_state_label(&["top", "core", "fsm"]).unwrap();
+        assert!(Arc::ptr_eq(&data, &bus));
+        assert!(Arc::ptr_eq(&state, &fsm));
+        reader.by_ref().count();
+        assert!(clk.is_high().unwrap() && core_clk.is_high().unwrap());
+        assert_eq!(data.read(), Some(0b1010));
+        assert_eq!(fsm.load(), "idle");
+        assert_eq!(
+            reader.shared_paths(&["top", "data"]).unwrap(),
+            vec![vec!["top", "data"], vec!["top", "core", "bus"]]
+        );
+        assert_eq!(reader.shared_paths(&["top", "missing"]), None);
+    }
+
+    #[test]
+    fn replay_observer() {
+        let vcd = "$timescale 10 ns $end
+$scope module top $end
+$var wire 4 ! data $end
+$var string 1 # state $end
+$scope module core $end
+$var wire 4 ! bus $end
+$upscope $end
+$upscope $end
+$enddefinitions $end
+#0
+b1010 !
+sidle #
+#3
+";
+        let log = Arc::new(Mutex::new(vec![]));
+        let events = log.clone();
+        let mut reader = VcdReader::new(vcd.as_bytes()).unwrap();
+        reader.set_observer(move |event| {
+            let line = match event {
+                ReplayEvent::Timestamp(t) => t.to_string(),
+                ReplayEvent::Sync => "sync".to_string(),
+                ReplayEvent::Change { path, value } => format!("{} {}", path.join("."), value),
+            };
+            events.lock().unwrap().push(line);
+        });
+        reader.by_ref().count();
+        assert_eq!(
+            *log.lock().unwrap(),
+            vec![
+                "0",
+                "top.data b1010",
+                "top.core.bus b1010",
+                "top.state sidle",
+                "30"
+            ]
+        );
+    }
+
+    #[test]
+    fn wide_bus() {
+        let value: u128 = 0xdead_beef_0000_0001_8000_0000_0000_00a5;
+        let mut vcd = vec![];
+        let mut builder = crate::writer::VcdWriterBuilder::new(&mut vcd).unwrap();
+        let group = builder.add_pin_group("wide", 130).unwrap();
+        let mut writer = builder.build().unwrap();
+        group.write_u128(value);
+        writer.timestamp(Nanoseconds(0u64)).unwrap();
+        writer.sample().unwrap();
+        let mut bits = vec![false; 130];
+        bits[129] = true;
+        group.write_bits(&bits);
+        writer.timestamp(Nanoseconds(10u64)).unwrap();
+        writer.sample().unwrap();
+        writer.timestamp(Nanoseconds(20u64)).unwrap();
+        drop(writer);
+
+        let mut reader = VcdReader::new(&vcd[..]).unwrap();
+        let wide = reader.get_bus(&["top", "wide"]).unwrap();
+        assert_eq!(wide.width(), 130);
+        reader.next();
+        reader.next();
+        assert_eq!(wide.as_u128(), Ok(value));
+        assert_eq!(wide.as_u64(), Err(BusValueError::Overflow { bits: 64 }));
+        assert_eq!(wide.slice(0..32).as_u32(), Ok(0xa5));
+        assert_eq!(wide.slice(64..128).as_u64(), Ok(0xdead_beef_0000_0001));
+        reader.next();
+        assert_eq!(wide.as_u128(), Err(BusValueError::Overflow { bits: 128 }));
+        assert_eq!(wide.bits(), Some(bits));
+    }
+
+    #[test]
+    fn seek_sync_points() {
+        // a counter with a $dumpall section every 10 steps, starting at 5
+        let mut vcd = String::from(
+            "$timescale 1 ns $end
+$scope module logic $end
+$var wire 1 ! b0 $end
+$var wire 1 \" b1 $end
+$upscope $end
+$enddefinitions $end
+#0
+0!
+0\"
+",
+        );
+        for i in 1..40u64 {
+            vcd += &format!("#{}\n", i * 10);
+            if i % 10 == 5 {
+                vcd += &format!("$dumpall {}! {}\" $end\n", i & 1, i >> 1 & 1);
+            } else {
+                vcd += &format!("{}!\n", i & 1);
+                if i & 1 == 0 {
+                    vcd += &format!("{}\"\n", i >> 1 & 1);
+                }
+            }
+        }
+        let mut reader = VcdReader::from_bytes(vcd.as_bytes()).unwrap();
+        let times: Vec<u64> = reader
+            .sync_points()
+            .unwrap()
+            .iter()
+            .map(|point| point.time().integer())
+            .collect();
+        assert_eq!(times, vec![50, 150, 250, 350]);
+        let b0 = reader.get_pin(&["logic", "b0"]).unwrap();
+        let b1 = reader.get_pin(&["logic", "b1"]).unwrap();
+        for t in [270u64, 120, 30, 390, 400, 155, 0] {
+            let next = reader
+                .seek(Nanoseconds(t))
+                .unwrap()
+                .map(|time| time.integer());
+            let mut linear = VcdReader::from_bytes(vcd.as_bytes()).unwrap();
+            let l0 = linear.get_pin(&["logic", "b0"]).unwrap();
+            let l1 = linear.get_pin(&["logic", "b1"]).unwrap();
+            let expected = linear.find(|time| time.integer() > t);
+            assert_eq!(next, expected.map(|time| time.integer()), "{}", t);
+            assert_eq!(b0.is_high(), l0.is_high(), "{}", t);
+            assert_eq!(b1.is_high(), l1.is_high(), "{}", t);
+        }
+        reader.seek(Nanoseconds(270u64)).unwrap();
+        assert_eq!(reader.last_sync().map(|time| time.integer()), Some(250));
+
+        // the last sync point is restored with a snapshot
+        let snapshot = reader.checkpoint().unwrap();
+        reader.seek(Nanoseconds(390u64)).unwrap();
+        assert_eq!(reader.last_sync().map(|time| time.integer()), Some(350));
+        reader.restore(&snapshot).unwrap();
+        assert_eq!(reader.last_sync().map(|time| time.integer()), Some(250));
+    }
+
+    #[test]
+    fn seek_backwards() {
+        let vcd = "$timescale 1 ns $end
+$scope module top $end
+$var wire 1 ! clk $end
+$var wire 4 \" data $end
+$var string 1 # state $end
+$upscope $end
+$enddefinitions $end
+#0
+0!
+#10
+1!
+b1010 \"
+sread #
+#20
+0!
+#30
+$dumpall 1! b0110 \" swrite # $end
+#40
+0!
+b0011 \"
+#50
+";
+        let mut reader = VcdReader::from_bytes(vcd.as_bytes()).unwrap();
+        let clk = reader.get_pin(&["top", "clk"]).unwrap();
+        let data = reader.get_bus(&["top", "data"]).unwrap();
+        let state = reader.get_state_label(&["top", "state"]).unwrap();
+        let last_sync = |reader: &VcdReader<_>| reader.last_sync().map(|time| time.integer());
+
+        reader.seek(Nanoseconds(45u64)).unwrap();
+        assert_eq!(
+            (data.read(), state.load()),
+            (Some(0x3), "write".to_string())
+        );
+        assert_eq!(last_sync(&reader), Some(30));
+        // back to the sync point
+        reader.seek(Nanoseconds(35u64)).unwrap();
+        assert_eq!(
+            (data.read(), state.load()),
+            (Some(0x6), "write".to_string())
+        );
+        assert_eq!(last_sync(&reader), Some(30));
+        reader.seek(Nanoseconds(15u64)).unwrap();
+        assert_eq!((data.read(), state.load()), (Some(0xa), "read".to_string()));
+        assert_eq!(last_sync(&reader), None);
+        // back to the start, before the bus and the label have a value
+        reader.seek(Nanoseconds(5u64)).unwrap();
+        assert_eq!(clk.is_low(), Ok(true));
+        assert_eq!((data.read(), state.load()), (None, String::new()));
+        assert_eq!(last_sync(&reader), None);
+
+        // the sync point is reported when it is replayed again
+        let syncs = Arc::new(AtomicU64::new(0));
+        let count = syncs.clone();
+        reader.set_observer(move |event| {
+            if let ReplayEvent::Sync = event {
+                count.fetch_add(1, Ordering::SeqCst);
+            }
+        });
+        reader.seek(Nanoseconds(45u64)).unwrap();
+        reader.seek(Nanoseconds(35u64)).unwrap();
+        assert_eq!(syncs.load(Ordering::SeqCst), 2);
+    }
 }
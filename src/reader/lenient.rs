@@ -0,0 +1,628 @@
+//! A lenient tokenizer for VCD files that don't follow the standard.
+
+use core::fmt;
+use std::io::Read;
+use std::io::Result as IOResult;
+
+/// A quirk of a VCD file that was recovered by [repair].
+///
+/// Lines start at `1`.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum ParseWarning {
+    /// A command that is unknown or not allowed at its position was skipped,
+    /// e.g. the `$attrbegin` of GHDL.
+    SkippedCommand {
+        /// The line of the command.
+        line: usize,
+        /// The keyword of the command.
+        keyword: String,
+    },
+    /// A token that doesn't start a command or a value change was skipped.
+    UnexpectedToken {
+        /// The line of the token.
+        line: usize,
+        /// The token.
+        token: String,
+    },
+    /// A command was not terminated with `$end`.
+    MissingEnd {
+        /// The line of the command.
+        line: usize,
+        /// The keyword of the command.
+        keyword: String,
+    },
+    /// The header has no timescale, 1 ns is assumed.
+    MissingTimescale,
+    /// The timescale could not be read, 1 ns is assumed.
+    InvalidTimescale {
+        /// The line of the timescale.
+        line: usize,
+        /// The text of the timescale.
+        text: String,
+    },
+    /// The header is not terminated with `$enddefinitions`, it ends before
+    /// the first change.
+    MissingEnddefinitions {
+        /// The line of the first change.
+        line: usize,
+    },
+    /// A scope was not closed with `$upscope` before the end of the header.
+    UnclosedScope {
+        /// The line of the scope.
+        line: usize,
+        /// The name of the scope.
+        name: String,
+    },
+    /// An `$upscope` without an open scope was skipped.
+    UnmatchedUpscope {
+        /// The line of the `$upscope`.
+        line: usize,
+    },
+    /// An `$end` without an open command was skipped.
+    UnmatchedEnd {
+        /// The line of the `$end`.
+        line: usize,
+    },
+    /// An unknown scope type, read as `module`.
+    UnknownScopeType {
+        /// The line of the scope.
+        line: usize,
+        /// The type of the scope.
+        scope_type: String,
+    },
+    /// An unknown variable type, read as `wire`.
+    UnknownVarType {
+        /// The line of the variable.
+        line: usize,
+        /// The type of the variable.
+        var_type: String,
+    },
+    /// A value of the nine-valued logic of VHDL, `l` and `h` are read as `0`
+    /// and `1`, `u`, `w` and `-` as `x`.
+    UnknownValue {
+        /// The line of the value.
+        line: usize,
+        /// The value.
+        value: char,
+    },
+}
+
+impl ParseWarning {
+    /// Return the line of the quirk, `None` if it has no position.
+    pub fn line(&self) -> Option<usize> {
+        use ParseWarning::*;
+        match self {
+            SkippedCommand { line, .. }
+            | UnexpectedToken { line, .. }
+            | MissingEnd { line, .. }
+            | InvalidTimescale { line, .. }
+            | MissingEnddefinitions { line }
+            | UnclosedScope { line, .. }
+            | UnmatchedUpscope { line }
+            | UnmatchedEnd { line }
+            | UnknownScopeType { line, .. }
+            | UnknownVarType { line, .. }
+            | UnknownValue { line, .. } => Some(*line),
+            MissingTimescale => None,
+        }
+    }
+
+    /// Return the warning with the line set to zero, to compare the kind.
+    fn without_line(&self) -> Self {
+        use ParseWarning::*;
+        let mut warning = self.clone();
+        match &mut warning {
+            SkippedCommand { line, .. }
+            | UnexpectedToken { line, .. }
+            | MissingEnd { line, .. }
+            | InvalidTimescale { line, .. }
+            | MissingEnddefinitions { line }
+            | UnclosedScope { line, .. }
+            | UnmatchedUpscope { line }
+            | UnmatchedEnd { line }
+            | UnknownScopeType { line, .. }
+            | UnknownVarType { line, .. }
+            | UnknownValue { line, .. } => *line = 0,
+            MissingTimescale => {}
+        }
+        warning
+    }
+}
+
+impl fmt::Display for ParseWarning {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        use ParseWarning::*;
+        match self {
+            SkippedCommand { line, keyword } => {
+                write!(f, "line {}: skipped command ${}", line, keyword)
+            }
+            UnexpectedToken { line, token } => {
+                write!(f, "line {}: skipped unexpected token {:?}", line, token)
+            }
+            MissingEnd { line, keyword } => {
+                write!(f, "line {}: ${} is not terminated with $end", line, keyword)
+            }
+            MissingTimescale => write!(f, "no timescale, 1 ns is assumed"),
+            InvalidTimescale { line, text } => write!(
+                f,
+                "line {}: invalid timescale {:?}, 1 ns is assumed",
+                line, text
+            ),
+            MissingEnddefinitions { line } => {
+                write!(f, "line {}: header ends without $enddefinitions", line)
+            }
+            UnclosedScope { line, name } => {
+                write!(f, "line {}: scope {} is not closed", line, name)
+            }
+            UnmatchedUpscope { line } => write!(f, "line {}: skipped unmatched $upscope", line),
+            UnmatchedEnd { line } => write!(f, "line {}: skipped unmatched $end", line),
+            UnknownScopeType { line, scope_type } => write!(
+                f,
+                "line {}: unknown scope type {}, read as module",
+                line, scope_type
+            ),
+            UnknownVarType { line, var_type } => write!(
+                f,
+                "line {}: unknown variable type {}, read as wire",
+                line, var_type
+            ),
+            UnknownValue { line, value } => write!(f, "line {}: unknown value {}", line, value),
+        }
+    }
+}
+
+/// A token of the input with its line.
+type Token = (usize, String);
+
+/// Split the input at whitespace, including vertical tabs and NUL bytes of
+/// padded captures.
+fn tokenize(data: &[u8]) -> Vec<Token> {
+    let mut tokens = vec![];
+    let mut line = 1;
+    let mut start = None;
+    for (i, b) in data.iter().enumerate() {
+        let space = b.is_ascii_whitespace() || *b == 0x0b || *b == 0;
+        match (space, start) {
+            (true, Some((first, line))) => {
+                tokens.push((line, String::from_utf8_lossy(&data[first..i]).into_owned()));
+                start = None;
+            }
+            (false, None) => start = Some((i, line)),
+            _ => {}
+        }
+        if *b == b'\n' {
+            line += 1;
+        }
+    }
+    if let Some((first, line)) = start {
+        tokens.push((line, String::from_utf8_lossy(&data[first..]).into_owned()));
+    }
+    tokens
+}
+
+/// Map a value character to a standard one, `None` if it is no value.
+fn map_value(c: char) -> Option<(char, bool)> {
+    match c {
+        '0' | '1' | 'x' | 'z' => Some((c, false)),
+        'X' | 'Z' => Some((c.to_ascii_lowercase(), false)),
+        'l' | 'L' => Some(('0', true)),
+        'h' | 'H' => Some(('1', true)),
+        'u' | 'U' | 'w' | 'W' | '-' => Some(('x', true)),
+        _ => None,
+    }
+}
+
+/// Read a timescale like `1 ns`, `1ns`, `1.0 ns` or `10 µs`.
+fn parse_timescale(text: &str) -> Option<(u32, vcd::TimescaleUnit)> {
+    let text: String = text.split_whitespace().collect();
+    let split = text
+        .find(|c: char| !c.is_ascii_digit() && c != '.')
+        .unwrap_or(text.len());
+    let (number, unit) = text.split_at(split);
+    let number: f64 = number.parse().ok()?;
+    let unit = unit.to_ascii_lowercase().replace(['µ', 'μ'], "u");
+    let unit = unit.parse().ok()?;
+    (number.fract() == 0.0 && number >= 1.0 && number <= u32::MAX as f64)
+        .then_some((number as u32, unit))
+}
+
+/// The state of [repair].
+struct Repair {
+    tokens: std::iter::Peekable<std::vec::IntoIter<Token>>,
+    out: String,
+    warnings: Vec<ParseWarning>,
+    last_line: usize,
+}
+
+impl Repair {
+    /// Record a warning, each kind of quirk is recorded once.
+    fn warn(&mut self, warning: ParseWarning) {
+        let kind = warning.without_line();
+        if !self.warnings.iter().any(|w| w.without_line() == kind) {
+            self.warnings.push(warning);
+        }
+    }
+
+    fn next(&mut self) -> Option<Token> {
+        let token = self.tokens.next();
+        if let Some((line, _)) = &token {
+            self.last_line = *line;
+        }
+        token
+    }
+
+    /// Return the arguments of a command up to its `$end`.
+    ///
+    /// A missing `$end` is recovered at the next keyword, except in free
+    /// text like comments.
+    fn arguments(&mut self, line: usize, keyword: &str, text: bool) -> Vec<String> {
+        let mut arguments = vec![];
+        loop {
+            match self.tokens.peek() {
+                Some((_, token)) if token == "$end" => {
+                    self.next();
+                    return arguments;
+                }
+                Some((_, token)) if !text && token.starts_with('$') => break,
+                Some(_) => arguments.push(self.next().unwrap().1),
+                None => break,
+            }
+        }
+        self.warn(ParseWarning::MissingEnd {
+            line,
+            keyword: keyword.to_string(),
+        });
+        arguments
+    }
+
+    /// Consume the `$end` of a command without arguments.
+    fn end(&mut self, line: usize, keyword: &str) {
+        match self.tokens.peek() {
+            Some((_, token)) if token == "$end" => {
+                self.next();
+            }
+            _ => self.warn(ParseWarning::MissingEnd {
+                line,
+                keyword: keyword.to_string(),
+            }),
+        }
+    }
+
+    /// Parse the header, returns the first token of the changes if the
+    /// header ends without `$enddefinitions`.
+    fn header(&mut self) -> Option<Token> {
+        let mut scopes: Vec<(usize, String)> = vec![];
+        let mut timescale = false;
+        let first = loop {
+            let (line, token) = match self.next() {
+                Some(token) => token,
+                None => {
+                    self.warn(ParseWarning::MissingEnddefinitions {
+                        line: self.last_line,
+                    });
+                    break None;
+                }
+            };
+            let keyword = match token.strip_prefix('$') {
+                Some(keyword) => keyword.to_string(),
+                None if token.starts_with('#')
+                    || token
+                        .chars()
+                        .next()
+                        .is_some_and(|c| map_value(c).is_some() || "bBrRsS".contains(c)) =>
+                {
+                    self.warn(ParseWarning::MissingEnddefinitions { line });
+                    break Some((line, token));
+                }
+                None => {
+                    self.warn(ParseWarning::UnexpectedToken { line, token });
+                    continue;
+                }
+            };
+            match keyword.as_str() {
+                "enddefinitions" => {
+                    self.end(line, &keyword);
+                    break None;
+                }
+                "dumpvars" | "dumpall" | "dumpon" | "dumpoff" => {
+                    self.warn(ParseWarning::MissingEnddefinitions { line });
+                    break Some((line, token));
+                }
+                "comment" | "date" | "version" => {
+                    let text = self.arguments(line, &keyword, true).join(" ");
+                    self.out += &format!("${} {} $end\n", keyword, text);
+                }
+                "timescale" => {
+                    let text = self.arguments(line, &keyword, false).join(" ");
+                    let (scale, unit) = parse_timescale(&text).unwrap_or_else(|| {
+                        self.warn(ParseWarning::InvalidTimescale { line, text });
+                        (1, vcd::TimescaleUnit::NS)
+                    });
+                    self.out += &format!("$timescale {} {} $end\n", scale, unit);
+                    timescale = true;
+                }
+                "scope" => {
+                    let mut arguments = self.arguments(line, &keyword, false).into_iter();
+                    let (scope_type, name) = match (arguments.next(), arguments.next()) {
+                        (Some(scope_type), Some(name)) => (scope_type, name),
+                        _ => {
+                            self.warn(ParseWarning::SkippedCommand { line, keyword });
+                            continue;
+                        }
+                    };
+                    let mut scope_type = scope_type.to_ascii_lowercase();
+                    if scope_type.parse::<vcd::ScopeType>().is_err() {
+                        self.warn(ParseWarning::UnknownScopeType { line, scope_type });
+                        scope_type = "module".to_string();
+                    }
+                    self.out += &format!("$scope {} {} $end\n", scope_type, name);
+                    scopes.push((line, name));
+                }
+                "upscope" => {
+                    self.end(line, &keyword);
+                    if scopes.pop().is_some() {
+                        self.out += "$upscope $end\n";
+                    } else {
+                        self.warn(ParseWarning::UnmatchedUpscope { line });
+                    }
+                }
+                "var" => {
+                    let arguments = self.arguments(line, &keyword, false);
+                    let valid = arguments.len() >= 4
+                        && arguments[1].parse::<u32>().is_ok()
+                        && arguments[2].parse::<vcd::IdCode>().is_ok()
+                        && arguments[4..]
+                            .iter()
+                            .all(|index| index.parse::<vcd::ReferenceIndex>().is_ok());
+                    if !valid {
+                        self.warn(ParseWarning::SkippedCommand { line, keyword });
+                        continue;
+                    }
+                    let mut var_type = arguments[0].to_ascii_lowercase();
+                    if var_type.parse::<vcd::VarType>().is_err() {
+                        self.warn(ParseWarning::UnknownVarType { line, var_type });
+                        var_type = "wire".to_string();
+                    }
+                    self.out += &format!("$var {} {} $end\n", var_type, arguments[1..].join(" "));
+                }
+                _ => {
+                    self.arguments(line, &keyword, false);
+                    self.warn(ParseWarning::SkippedCommand { line, keyword });
+                }
+            }
+        };
+        while let Some((line, name)) = scopes.pop() {
+            self.warn(ParseWarning::UnclosedScope { line, name });
+            self.out += "$upscope $end\n";
+        }
+        if !timescale {
+            self.warn(ParseWarning::MissingTimescale);
+            self.out += "$timescale 1 ns $end\n";
+        }
+        self.out += "$enddefinitions $end\n";
+        first
+    }
+
+    /// Return the identifier of a change, either the rest of the token or
+    /// the next token.
+    fn code(&mut self, rest: &str) -> Option<String> {
+        let code = match rest {
+            "" => self.next()?.1,
+            rest => rest.to_string(),
+        };
+        code.parse::<vcd::IdCode>().is_ok().then_some(code)
+    }
+
+    /// Map the characters of a value, warns about non-standard ones.
+    fn value(&mut self, line: usize, value: &str) -> Option<String> {
+        value
+            .chars()
+            .map(|c| {
+                let (mapped, repaired) = map_value(c)?;
+                if repaired {
+                    self.warn(ParseWarning::UnknownValue { line, value: c });
+                }
+                Some(mapped)
+            })
+            .collect()
+    }
+
+    fn changes(&mut self, mut first: Option<Token>) {
+        let mut open = false;
+        while let Some((line, token)) = first.take().or_else(|| self.next()) {
+            let unexpected = ParseWarning::UnexpectedToken {
+                line,
+                token: token.clone(),
+            };
+            if let Some(keyword) = token.strip_prefix('$') {
+                match keyword {
+                    "dumpvars" | "dumpall" | "dumpon" | "dumpoff" => {
+                        self.out += &format!("{}\n", token);
+                        open = true;
+                    }
+                    "end" if open => {
+                        self.out += "$end\n";
+                        open = false;
+                    }
+                    "end" => self.warn(ParseWarning::UnmatchedEnd { line }),
+                    "comment" => {
+                        let text = self.arguments(line, keyword, true).join(" ");
+                        self.out += &format!("$comment {} $end\n", text);
+                    }
+                    _ => {
+                        let keyword = keyword.to_string();
+                        self.arguments(line, &keyword, false);
+                        self.warn(ParseWarning::SkippedCommand { line, keyword });
+                    }
+                }
+                continue;
+            }
+            let mut chars = token.chars();
+            let change = match chars.next() {
+                Some('#') => chars
+                    .as_str()
+                    .parse::<u64>()
+                    .ok()
+                    .map(|t| format!("#{}", t)),
+                Some('b' | 'B') => {
+                    let value = self.value(line, chars.as_str());
+                    let code = self.code("");
+                    value
+                        .zip(code)
+                        .map(|(value, code)| format!("b{} {}", value, code))
+                }
+                Some('r' | 'R') => {
+                    let value = chars.as_str().parse::<f64>().ok();
+                    let code = self.code("");
+                    value
+                        .zip(code)
+                        .map(|(value, code)| format!("r{} {}", value, code))
+                }
+                Some('s' | 'S') => {
+                    let value = chars.as_str().to_string();
+                    self.code("").map(|code| format!("s{} {}", value, code))
+                }
+                Some(c) if map_value(c).is_some() => {
+                    let value = self.value(line, &c.to_string());
+                    let code = self.code(chars.as_str());
+                    value.zip(code).map(|(value, code)| value + &code)
+                }
+                _ => None,
+            };
+            match change {
+                Some(change) => self.out += &format!("{}\n", change),
+                None => self.warn(unexpected),
+            }
+        }
+    }
+}
+
+/// Repair a VCD file that doesn't follow the standard, e.g. written by
+/// sigrok or the tools of logic analyzer vendors.
+///
+/// Returns the file in standard form, which can be read by the
+/// [VcdReader](super::VcdReader) and the [analysis](crate::analysis)
+/// functions, together with the quirks that were recovered. Each kind of
+/// quirk is reported once, at its first line. The whole file is read into
+/// memory.
+///
+/// ```
+/// use embedded_hal_vcd::analysis::Waveform;
+/// use embedded_hal_vcd::reader::{repair, ParseWarning};
+///
+/// let vcd = b"$timescale 1 us $end
+/// $scope module la $end
+/// $var wire 1 ! D0 $end
+/// #0 0!
+/// #10 H!
+/// ";
+/// let (repaired, warnings) = repair(&vcd[..]).unwrap();
+/// assert!(warnings.contains(&ParseWarning::MissingEnddefinitions { line: 4 }));
+/// let waveform = Waveform::read(&repaired[..], &["la", "D0"]).unwrap();
+/// assert_eq!(waveform.changes().len(), 2);
+/// ```
+pub fn repair<R: Read>(mut read: R) -> IOResult<(Vec<u8>, Vec<ParseWarning>)> {
+    let mut data = vec![];
+    read.read_to_end(&mut data)?;
+    let mut repair = Repair {
+        tokens: tokenize(&data).into_iter().peekable(),
+        out: String::new(),
+        warnings: vec![],
+        last_line: 1,
+    };
+    let first = repair.header();
+    repair.changes(first);
+    Ok((repair.out.into_bytes(), repair.warnings))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::analysis::Waveform;
+    use crate::pins::PinState;
+    use embedded_time::duration::*;
+
+    #[test]
+    fn repair_quirks() {
+        let vcd = "$date\tMon Jan  1 2024 $end
+$version vendor tool 1.0 $end
+$timescale 1.0 NS $end
+$attrbegin misc 07 clk 1 $end
+$scope interface bus $end
+$var logic 1 ! clk $end
+$var wire 4 \" data [3:0] $end
+$upscope $end
+$upscope $end
+$enddefinitions
+#0
+$dumpvars
+U! bUUUU \"
+$end
+$end
+#10 1!
+#20 0! b10H1 \"
+?
+";
+        let (repaired, warnings) = repair(vcd.as_bytes()).unwrap();
+        assert_eq!(
+            warnings,
+            vec![
+                ParseWarning::SkippedCommand {
+                    line: 4,
+                    keyword: "attrbegin".to_string()
+                },
+                ParseWarning::UnknownScopeType {
+                    line: 5,
+                    scope_type: "interface".to_string()
+                },
+                ParseWarning::UnknownVarType {
+                    line: 6,
+                    var_type: "logic".to_string()
+                },
+                ParseWarning::UnmatchedUpscope { line: 9 },
+                ParseWarning::MissingEnd {
+                    line: 10,
+                    keyword: "enddefinitions".to_string()
+                },
+                ParseWarning::UnknownValue {
+                    line: 13,
+                    value: 'U'
+                },
+                ParseWarning::UnmatchedEnd { line: 15 },
+                ParseWarning::UnknownValue {
+                    line: 17,
+                    value: 'H'
+                },
+                ParseWarning::UnexpectedToken {
+                    line: 18,
+                    token: "?".to_string()
+                },
+            ]
+        );
+        assert_eq!(
+            warnings[3].to_string(),
+            "line 9: skipped unmatched $upscope"
+        );
+
+        let clk = Waveform::read(&repaired[..], &["bus", "clk"]).unwrap();
+        assert_eq!(
+            clk.changes(),
+            &[
+                (Nanoseconds(0), PinState::Floating),
+                (Nanoseconds(10), PinState::High),
+                (Nanoseconds(20), PinState::Low)
+            ]
+        );
+
+        // a file without any header
+        let (repaired, warnings) = repair("#0\n1!\n".as_bytes()).unwrap();
+        assert_eq!(
+            warnings,
+            vec![
+                ParseWarning::MissingEnddefinitions { line: 1 },
+                ParseWarning::MissingTimescale
+            ]
+        );
+        assert!(vcd::Parser::new(&repaired[..]).parse_header().is_ok());
+    }
+}
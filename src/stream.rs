@@ -0,0 +1,306 @@
+//! Stream VCD data to viewers over sockets while a test runs.
+//!
+//! A [StreamServer] is an output for a [VcdWriter](crate::writer::VcdWriter)
+//! that sends the written data to all connected clients, e.g. GTKWave in its
+//! interactive mode or custom tools, so waveforms can be watched live.
+//! On the other side, [VcdReader::connect_tcp] replays a stream as it
+//! arrives, so one machine can generate stimulus for tests running
+//! elsewhere, e.g. inside QEMU or a container.
+
+use crate::reader::VcdReader;
+use std::io::Result as IOResult;
+use std::io::{BufReader, ErrorKind, Write};
+use std::net::{SocketAddr, TcpListener, TcpStream, ToSocketAddrs};
+use std::sync::{Arc, Mutex};
+use std::thread::JoinHandle;
+use std::time::Duration;
+
+/// How often the nonblocking listeners check whether the server was dropped.
+const ACCEPT_POLL: Duration = Duration::from_millis(10);
+
+type Client = Box<dyn Write + Send>;
+
+#[derive(Default)]
+struct Clients {
+    /// Clients that receive the data as it is written.
+    active: Vec<Client>,
+    /// Clients that connected after the header, waiting for a timestamp.
+    pending: Vec<Client>,
+    closed: bool,
+}
+
+impl Clients {
+    /// Write to all active clients, disconnected clients are removed.
+    fn write(&mut self, buf: &[u8]) {
+        if !buf.is_empty() {
+            self.active
+                .retain_mut(|client| client.write_all(buf).is_ok());
+        }
+    }
+
+    /// Send the header to the pending clients and make them active.
+    fn promote(&mut self, header: &[u8]) {
+        for mut client in std::mem::take(&mut self.pending) {
+            if client.write_all(header).is_ok() {
+                self.active.push(client);
+            }
+        }
+    }
+}
+
+/// An output that serves the VCD data to clients connected to a socket.
+///
+/// Clients that connect once the header has been written receive the header
+/// first and then the data starting at the next timestamp. As the
+/// [VcdWriter](crate::writer::VcdWriter) writes all values on each sample,
+/// the values of late clients are complete after their first sample.
+/// Writing blocks until all clients received the data, clients that
+/// disconnect are dropped. Dropping the server closes all connections and
+/// the socket.
+///
+/// ```no_run
+/// use embedded_hal_vcd::stream::StreamServer;
+/// use embedded_hal_vcd::writer::VcdWriterBuilder;
+///
+/// let server = StreamServer::bind_tcp("127.0.0.1:9876").unwrap();
+/// let mut builder = VcdWriterBuilder::new(server).unwrap();
+/// // add pins, build the writer and run the test
+/// ```
+pub struct StreamServer {
+    clients: Arc<Mutex<Clients>>,
+    accept_thread: Option<JoinHandle<()>>,
+    addr: Option<SocketAddr>,
+    header: Vec<u8>,
+    header_done: bool,
+    line_start: bool,
+}
+
+impl StreamServer {
+    fn new<S, F>(addr: Option<SocketAddr>, mut accept: F) -> Self
+    where
+        S: Write + Send + 'static,
+        F: FnMut() -> IOResult<S> + Send + 'static,
+    {
+        let clients = Arc::new(Mutex::new(Clients::default()));
+        let shared = clients.clone();
+        // the listener is nonblocking, so the thread ends and closes the
+        // socket soon after the server is dropped
+        let accept_thread = std::thread::spawn(move || loop {
+            let client = accept();
+            let mut clients = shared.lock().unwrap();
+            if clients.closed {
+                break;
+            }
+            match client {
+                Ok(client) => clients.pending.push(Box::new(client)),
+                Err(e) if e.kind() == ErrorKind::WouldBlock => {
+                    drop(clients);
+                    std::thread::sleep(ACCEPT_POLL);
+                }
+                Err(_) => {}
+            }
+        });
+        StreamServer {
+            clients,
+            accept_thread: Some(accept_thread),
+            addr,
+            header: vec![],
+            header_done: false,
+            line_start: true,
+        }
+    }
+
+    /// Listen for TCP connections on the given address.
+    pub fn bind_tcp<A: ToSocketAddrs>(addr: A) -> IOResult<Self> {
+        let listener = TcpListener::bind(addr)?;
+        listener.set_nonblocking(true)?;
+        let addr = listener.local_addr()?;
+        Ok(Self::new(Some(addr), move || {
+            let (stream, _) = listener.accept()?;
+            stream.set_nonblocking(false)?;
+            stream.set_nodelay(true)?;
+            Ok(stream)
+        }))
+    }
+
+    /// Listen for connections on a Unix domain socket at the given path.
+    #[cfg(unix)]
+    pub fn bind_unix<P: AsRef<std::path::Path>>(path: P) -> IOResult<Self> {
+        let listener = std::os::unix::net::UnixListener::bind(path)?;
+        listener.set_nonblocking(true)?;
+        Ok(Self::new(None, move || {
+            let (stream, _) = listener.accept()?;
+            stream.set_nonblocking(false)?;
+            Ok(stream)
+        }))
+    }
+
+    /// Return the address of a TCP server, e.g. to find the port if the
+    /// server was bound to port `0`.
+    pub fn local_addr(&self) -> Option<SocketAddr> {
+        self.addr
+    }
+
+    /// Return the number of connected clients.
+    pub fn clients(&self) -> usize {
+        let clients = self.clients.lock().unwrap();
+        clients.active.len() + clients.pending.len()
+    }
+}
+
+impl Write for StreamServer {
+    fn write(&mut self, buf: &[u8]) -> IOResult<usize> {
+        let mut clients = self.clients.lock().unwrap();
+        let mut start = 0;
+        for (i, byte) in buf.iter().copied().enumerate() {
+            if !self.header_done {
+                self.header.push(byte);
+                self.header_done = self.header.ends_with(b"$enddefinitions $end\n");
+                start = i + 1;
+                if self.header_done {
+                    clients.promote(&self.header);
+                }
+            } else if self.line_start && byte == b'#' && !clients.pending.is_empty() {
+                clients.write(&buf[start..i]);
+                start = i;
+                clients.promote(&self.header);
+            }
+            self.line_start = byte == b'\n';
+        }
+        clients.write(&buf[start..]);
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> IOResult<()> {
+        let mut clients = self.clients.lock().unwrap();
+        clients.active.retain_mut(|client| client.flush().is_ok());
+        Ok(())
+    }
+}
+
+impl Drop for StreamServer {
+    fn drop(&mut self) {
+        let mut clients = self.clients.lock().unwrap();
+        clients.closed = true;
+        clients.active.clear();
+        clients.pending.clear();
+        drop(clients);
+        if let Some(thread) = self.accept_thread.take() {
+            let _ = thread.join();
+        }
+    }
+}
+
+impl VcdReader<BufReader<TcpStream>> {
+    /// Connect to a VCD stream served over TCP, e.g. by a [StreamServer].
+    ///
+    /// Blocks until the header has been received. Iterating the reader
+    /// blocks until the next timestamp arrives and applies the changes to
+    /// the pins, the iteration ends when the server closes the connection.
+    pub fn connect_tcp<A: ToSocketAddrs>(addr: A) -> IOResult<Self> {
+        let stream = TcpStream::connect(addr)?;
+        stream.set_nodelay(true)?;
+        Self::new(BufReader::new(stream))
+    }
+}
+
+#[cfg(unix)]
+impl VcdReader<BufReader<std::os::unix::net::UnixStream>> {
+    /// Connect to a VCD stream served on a Unix domain socket, see
+    /// [VcdReader::connect_tcp].
+    pub fn connect_unix<P: AsRef<std::path::Path>>(path: P) -> IOResult<Self> {
+        let stream = std::os::unix::net::UnixStream::connect(path)?;
+        Self::new(BufReader::new(stream))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::writer::VcdWriterBuilder;
+    use embedded_hal::digital::{InputPin, OutputPin};
+    use embedded_time::duration::*;
+    use std::io::Read;
+    use std::net::TcpStream;
+
+    fn wait_for_clients(clients: &Mutex<Clients>, count: usize) {
+        while clients.lock().unwrap().pending.len() < count {
+            std::thread::sleep(std::time::Duration::from_millis(1));
+        }
+    }
+
+    #[test]
+    fn stream_to_clients() {
+        let server = StreamServer::bind_tcp("127.0.0.1:0").unwrap();
+        let addr = server.local_addr().unwrap();
+        let clients = server.clients.clone();
+        let mut early = TcpStream::connect(addr).unwrap();
+        wait_for_clients(&clients, 1);
+        let mut builder = VcdWriterBuilder::new(server).unwrap();
+        let mut led = builder.add_push_pull_pin("led").unwrap();
+        let mut writer = builder.build().unwrap();
+        writer.timestamp(Nanoseconds(0u64)).unwrap();
+        writer.sample().unwrap();
+        let mut late = TcpStream::connect(addr).unwrap();
+        wait_for_clients(&clients, 1);
+        led.set_high().unwrap();
+        writer.timestamp(Nanoseconds(100u64)).unwrap();
+        writer.sample().unwrap();
+        drop(writer);
+        let mut early_data = String::new();
+        early.read_to_string(&mut early_data).unwrap();
+        let mut late_data = String::new();
+        late.read_to_string(&mut late_data).unwrap();
+        let (header, data) = early_data.split_once("$enddefinitions $end\n").unwrap();
+        assert_eq!(data, "#0\n0!\n#100\n1!\n");
+        assert_eq!(
+            late_data,
+            format!("{}$enddefinitions $end\n#100\n1!\n", header)
+        );
+    }
+
+    #[test]
+    fn remote_replay() {
+        let server = StreamServer::bind_tcp("127.0.0.1:0").unwrap();
+        let addr = server.local_addr().unwrap();
+        let clients = server.clients.clone();
+        let replay = std::thread::spawn(move || {
+            let mut reader = VcdReader::connect_tcp(addr).unwrap();
+            let led = reader.get_pin(&["top", "led"]).unwrap();
+            let mut states = vec![];
+            while reader.next().is_some() {
+                states.push(led.is_high().unwrap());
+            }
+            // changes after the last timestamp are applied at the end
+            states.push(led.is_high().unwrap());
+            states
+        });
+        wait_for_clients(&clients, 1);
+        let mut builder = VcdWriterBuilder::new(server).unwrap();
+        let mut led = builder.add_push_pull_pin("led").unwrap();
+        let mut writer = builder.build().unwrap();
+        for t in 0..4u64 {
+            if t % 2 == 1 {
+                led.set_high().unwrap();
+            } else {
+                led.set_low().unwrap();
+            }
+            writer.timestamp(Nanoseconds(t * 100)).unwrap();
+            writer.sample().unwrap();
+        }
+        drop(writer);
+        assert_eq!(
+            replay.join().unwrap(),
+            vec![false, false, true, false, true]
+        );
+    }
+
+    #[test]
+    fn drop_closes_socket() {
+        let server = StreamServer::bind_tcp("127.0.0.1:0").unwrap();
+        let addr = server.local_addr().unwrap();
+        drop(server);
+        assert!(TcpStream::connect(addr).is_err());
+        drop(StreamServer::bind_tcp(addr).unwrap());
+    }
+}
@@ -0,0 +1,397 @@
+//! Utilities for tests that record waveforms.
+//!
+//! A [FailureDump] keeps the recorded VCD data in memory. If the test fails,
+//! the data is written to a temporary file and a command line to open it in
+//! a waveform viewer is printed, so failures can be inspected right away.
+//!
+//! [assert_matches_with_tolerance] compares a recorded waveform against an
+//! expected one, allowing the edges to move within a jitter window. The
+//! [stimulus] module generates random but valid stimulus from a seed and
+//! the [faults] module corrupts recorded signals for robustness tests. With
+//! the `proptest` feature the `strategies` module generates shrinkable
+//! stimulus for property tests.
+//! The [coverage] module reports which states of a trace were exercised.
+//! [assert_near] compares analog values in physical units.
+//!
+//! With the `embedded-hal-mock` feature the `mock` module converts captures
+//! to the expectations of the mocks.
+
+use crate::analysis::Waveform;
+use crate::pins::PinState;
+use crate::units::Scale;
+use embedded_time::duration::*;
+use std::fmt;
+use std::io::Result as IOResult;
+use std::io::{Read, Write};
+
+pub mod coverage;
+pub mod faults;
+#[cfg(feature = "embedded-hal-mock")]
+pub mod mock;
+pub mod stimulus;
+#[cfg(feature = "proptest")]
+pub mod strategies;
+use std::path::PathBuf;
+use std::sync::{Arc, Mutex};
+
+/// A waveform viewer to open failure dumps with.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Viewer {
+    /// [GTKWave](https://gtkwave.sourceforge.net/), opened with a save file
+    /// that starts at the failure timestamp.
+    GtkWave,
+    /// [Surfer](https://surfer-project.org/).
+    Surfer,
+}
+
+/// An in-memory output for a [VcdWriter](crate::writer::VcdWriter).
+#[derive(Clone, Debug, Default)]
+pub struct SharedBuffer {
+    inner: Arc<Mutex<Vec<u8>>>,
+}
+
+impl SharedBuffer {
+    /// Return a copy of the data written so far.
+    pub fn contents(&self) -> Vec<u8> {
+        self.inner.lock().unwrap().clone()
+    }
+}
+
+impl Write for SharedBuffer {
+    fn write(&mut self, buf: &[u8]) -> IOResult<usize> {
+        self.inner.lock().unwrap().extend_from_slice(buf);
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> IOResult<()> {
+        Ok(())
+    }
+}
+
+/// A guard that dumps the recorded waveform when a test fails.
+///
+/// When the guard is dropped while the thread panics (e.g. because of a
+/// failed assertion), the recorded data is written to `<name>.vcd` in the
+/// temporary directory and the command line to open it is printed.
+///
+/// ```
+/// use embedded_hal_vcd::testing::{FailureDump, Viewer};
+/// use embedded_hal_vcd::writer::VcdWriterBuilder;
+///
+/// let dump = FailureDump::new("my_test", Viewer::GtkWave);
+/// let builder = VcdWriterBuilder::new(dump.buffer()).unwrap();
+/// // add pins, build the writer and run the test
+/// ```
+pub struct FailureDump {
+    name: String,
+    viewer: Viewer,
+    launch: bool,
+    buffer: SharedBuffer,
+}
+
+impl FailureDump {
+    /// Create a new guard for the test `name`.
+    pub fn new(name: &str, viewer: Viewer) -> Self {
+        FailureDump {
+            name: name.to_string(),
+            viewer,
+            launch: false,
+            buffer: SharedBuffer::default(),
+        }
+    }
+
+    /// Start the viewer on failure instead of only printing the command.
+    pub fn with_launch(mut self) -> Self {
+        self.launch = true;
+        self
+    }
+
+    /// Return the output to record to.
+    pub fn buffer(&self) -> SharedBuffer {
+        self.buffer.clone()
+    }
+
+    /// Return the path the VCD file is written to on failure.
+    pub fn path(&self) -> PathBuf {
+        std::env::temp_dir().join(format!("{}.vcd", self.name))
+    }
+
+    /// Return the last timestamp of the recorded data.
+    pub fn last_timestamp(&self) -> Option<u64> {
+        let contents = self.buffer.contents();
+        String::from_utf8_lossy(&contents)
+            .lines()
+            .rev()
+            .find_map(|line| line.strip_prefix('#')?.trim().parse().ok())
+    }
+
+    /// Write the recorded data and return the command to open it.
+    pub fn dump(&self) -> IOResult<Vec<String>> {
+        let path = self.path();
+        std::fs::write(&path, self.buffer.contents())?;
+        let mut command = vec![path.display().to_string()];
+        match self.viewer {
+            Viewer::GtkWave => {
+                let save = path.with_extension("gtkw");
+                let start = self.last_timestamp().unwrap_or(0);
+                std::fs::write(&save, format!("[timestart] {}\n", start))?;
+                command.insert(0, "gtkwave".to_string());
+                command.push(save.display().to_string());
+            }
+            Viewer::Surfer => command.insert(0, "surfer".to_string()),
+        }
+        Ok(command)
+    }
+}
+
+impl Drop for FailureDump {
+    fn drop(&mut self) {
+        if !std::thread::panicking() {
+            return;
+        }
+        match self.dump() {
+            Ok(command) => {
+                let time = self.last_timestamp().unwrap_or(0);
+                eprintln!(
+                    "waveform of failed test at #{}: {}",
+                    time,
+                    command.join(" ")
+                );
+                if self.launch {
+                    let _ = std::process::Command::new(&command[0])
+                        .args(&command[1..])
+                        .spawn();
+                }
+            }
+            Err(e) => eprintln!("failed to write waveform of failed test: {}", e),
+        }
+    }
+}
+
+/// A difference between an expected and an actual waveform.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum Mismatch {
+    /// The signal is missing in the actual waveform.
+    Missing {
+        /// The path of the signal.
+        signal: String,
+    },
+    /// The signal has a different number of changes.
+    Changes {
+        /// The path of the signal.
+        signal: String,
+        /// The number of expected changes.
+        expected: usize,
+        /// The number of actual changes.
+        actual: usize,
+    },
+    /// A change has a different state.
+    State {
+        /// The path of the signal.
+        signal: String,
+        /// The expected time of the change.
+        time: Nanoseconds<u64>,
+        /// The expected state.
+        expected: PinState,
+        /// The actual state.
+        actual: PinState,
+    },
+    /// A change is outside of the tolerance window.
+    Time {
+        /// The path of the signal.
+        signal: String,
+        /// The expected time of the change.
+        expected: Nanoseconds<u64>,
+        /// The actual time of the change.
+        actual: Nanoseconds<u64>,
+    },
+}
+
+impl fmt::Display for Mismatch {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Mismatch::Missing { signal } => write!(f, "signal {} is missing", signal),
+            Mismatch::Changes {
+                signal,
+                expected,
+                actual,
+            } => write!(
+                f,
+                "signal {} has {} changes, expected {}",
+                signal, actual, expected
+            ),
+            Mismatch::State {
+                signal,
+                time,
+                expected,
+                actual,
+            } => write!(
+                f,
+                "signal {} changes to {:?} at {}, expected {:?}",
+                signal, actual, time, expected
+            ),
+            Mismatch::Time {
+                signal,
+                expected,
+                actual,
+            } => write!(
+                f,
+                "signal {} changes at {}, expected at {}",
+                signal, actual, expected
+            ),
+        }
+    }
+}
+
+impl std::error::Error for Mismatch {}
+
+/// Compare the waveforms of two VCD files, see [assert_matches_with_tolerance].
+pub fn compare_with_tolerance<E, A>(
+    expected: E,
+    actual: A,
+    tolerance: Nanoseconds<u64>,
+) -> IOResult<Result<(), Mismatch>>
+where
+    E: Read,
+    A: Read,
+{
+    let actual = Waveform::read_all(actual)?;
+    for (signal, expected) in Waveform::read_all(expected)? {
+        let actual = match actual.iter().find(|(s, _)| *s == signal) {
+            Some((_, actual)) => actual,
+            None => return Ok(Err(Mismatch::Missing { signal })),
+        };
+        let (expected, actual) = (expected.changes(), actual.changes());
+        if expected.len() != actual.len() {
+            return Ok(Err(Mismatch::Changes {
+                signal,
+                expected: expected.len(),
+                actual: actual.len(),
+            }));
+        }
+        for ((te, se), (ta, sa)) in expected.iter().zip(actual) {
+            if se != sa {
+                return Ok(Err(Mismatch::State {
+                    signal,
+                    time: *te,
+                    expected: se.clone(),
+                    actual: sa.clone(),
+                }));
+            }
+            if te.0.abs_diff(ta.0) > tolerance.0 {
+                return Ok(Err(Mismatch::Time {
+                    signal,
+                    expected: *te,
+                    actual: *ta,
+                }));
+            }
+        }
+    }
+    Ok(Ok(()))
+}
+
+/// Assert that the actual VCD file matches the expected one.
+///
+/// All scalar signals of the expected file have to be in the actual file
+/// with the same sequence of states. Each change may be up to `tolerance`
+/// earlier or later than expected, as firmware timing varies from run to
+/// run. Additional signals in the actual file are ignored.
+///
+/// # Panics
+///
+/// Panics with a description of the first mismatch or if a file can't be
+/// read.
+pub fn assert_matches_with_tolerance<E, A>(expected: E, actual: A, tolerance: Nanoseconds<u64>)
+where
+    E: Read,
+    A: Read,
+{
+    match compare_with_tolerance(expected, actual, tolerance) {
+        Ok(Ok(())) => {}
+        Ok(Err(mismatch)) => panic!("waveforms do not match: {}", mismatch),
+        Err(e) => panic!("failed to read waveforms: {}", e),
+    }
+}
+
+/// Assert that the physical value of a raw value, e.g. of an ADC register,
+/// is within `tolerance` of the expected value, both in the unit of the
+/// scale.
+///
+/// ```
+/// use embedded_hal_vcd::testing::assert_near;
+/// use embedded_hal_vcd::units::Scale;
+///
+/// let volts = Scale::new(3.3 / 4096.0, "V");
+/// assert_near(&volts, 2048.0, 1.66, 0.02);
+/// ```
+///
+/// # Panics
+///
+/// Panics with the physical values if the value is not within the
+/// tolerance.
+pub fn assert_near(scale: &Scale, raw: f64, expected: f64, tolerance: f64) {
+    if let Err(error) = scale.check(raw, expected, tolerance) {
+        panic!("value out of tolerance: {}", error);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::writer::VcdWriterBuilder;
+
+    #[test]
+    fn dump_on_failure() {
+        let name = format!("embedded-hal-vcd-failure-{}", std::process::id());
+        let dump = FailureDump::new(&name, Viewer::GtkWave);
+        let path = dump.path();
+        let result = std::thread::spawn(move || {
+            let mut builder = VcdWriterBuilder::new(dump.buffer()).unwrap();
+            let _led = builder.add_push_pull_pin("led").unwrap();
+            let mut writer = builder.build().unwrap();
+            writer.timestamp(Nanoseconds(1200u64)).unwrap();
+            assert_eq!(dump.last_timestamp(), Some(1200));
+            panic!("test failed");
+        })
+        .join();
+        assert!(result.is_err());
+        let vcd = std::fs::read_to_string(&path).unwrap();
+        assert!(vcd.ends_with("#1200\n"));
+        let save = std::fs::read_to_string(path.with_extension("gtkw")).unwrap();
+        assert_eq!(save, "[timestart] 1200\n");
+        std::fs::remove_file(&path).unwrap();
+        std::fs::remove_file(path.with_extension("gtkw")).unwrap();
+    }
+
+    #[test]
+    fn tolerance() {
+        let expected = "$timescale 1 ns $end
+$scope module top $end
+$var wire 1 ! clk $end
+$upscope $end
+$enddefinitions $end
+#0
+0!
+#100
+1!
+#200
+0!
+";
+        let actual = expected.replace("#100", "#104").replace("#200", "#195");
+        assert_matches_with_tolerance(expected.as_bytes(), actual.as_bytes(), Nanoseconds(5u64));
+        assert_eq!(
+            compare_with_tolerance(expected.as_bytes(), actual.as_bytes(), Nanoseconds(4u64))
+                .unwrap(),
+            Err(Mismatch::Time {
+                signal: "top.clk".to_string(),
+                expected: Nanoseconds(200),
+                actual: Nanoseconds(195),
+            })
+        );
+        let actual = expected.replace("#200\n0!\n", "");
+        assert!(matches!(
+            compare_with_tolerance(expected.as_bytes(), actual.as_bytes(), Nanoseconds(5u64)),
+            Ok(Err(Mismatch::Changes { .. }))
+        ));
+    }
+}
@@ -0,0 +1,391 @@
+//! Coverage of recorded signals: the states of state machines and the
+//! toggles of all signals.
+
+use crate::analysis::Waveform;
+use crate::devices::Edge;
+use crate::pins::PinState;
+use core::borrow::Borrow;
+use std::fmt;
+use std::io::Result as IOResult;
+use std::io::{Error, ErrorKind, Read};
+
+/// States or transitions that never occurred, returned by
+/// [StateCoverage::check].
+#[derive(Clone, Debug, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct CoverageError {
+    /// The required states that were never visited.
+    pub states: Vec<String>,
+    /// The required transitions that never happened.
+    pub transitions: Vec<(String, String)>,
+}
+
+impl fmt::Display for CoverageError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "missing coverage:")?;
+        for state in self.states.iter() {
+            write!(f, " state {}", state)?;
+        }
+        for (from, to) in self.transitions.iter() {
+            write!(f, " transition {} -> {}", from, to)?;
+        }
+        Ok(())
+    }
+}
+
+impl std::error::Error for CoverageError {}
+
+/// The states and transitions of a state machine that occurred in a trace.
+///
+/// The states are read from a string variable, e.g. recorded with
+/// [VcdWriterBuilder::add_state_label](crate::writer::VcdWriterBuilder::add_state_label),
+/// or from an integer variable with a mapping of values to names. Repeated
+/// values are not a transition, so self transitions are not recorded.
+///
+/// ```
+/// use embedded_hal_vcd::testing::coverage::StateCoverage;
+///
+/// let mut coverage = StateCoverage::default();
+/// for state in ["IDLE", "TX", "IDLE", "TX", "ERROR"] {
+///     coverage.record(state);
+/// }
+/// assert_eq!(coverage.count("TX"), 2);
+/// assert!(coverage.transitioned("TX", "ERROR"));
+/// assert!(coverage.check(&["IDLE"], &[("TX", "IDLE")]).is_ok());
+/// assert!(coverage.check(&[], &[("ERROR", "IDLE")]).is_err());
+/// ```
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct StateCoverage {
+    states: Vec<(String, usize)>,
+    transitions: Vec<((String, String), usize)>,
+    last: Option<String>,
+}
+
+impl StateCoverage {
+    /// Read the states of a named string variable from a VCD file.
+    pub fn read<R, S>(read: R, path: &[S]) -> IOResult<Self>
+    where
+        R: Read,
+        S: Borrow<str>,
+    {
+        Self::read_with_names(read, path, &[])
+    }
+
+    /// Read the states of a named variable from a VCD file, with names for
+    /// the values of integer variables.
+    ///
+    /// Values without a name are recorded as decimal number, values with
+    /// `x` or `z` bits as `x`.
+    pub fn read_with_names<R, S>(read: R, path: &[S], names: &[(u64, &str)]) -> IOResult<Self>
+    where
+        R: Read,
+        S: Borrow<str>,
+    {
+        let mut parser = vcd::Parser::new(read);
+        let header = parser.parse_header()?;
+        let code = header
+            .find_var(path)
+            .ok_or_else(|| Error::new(ErrorKind::NotFound, "variable not found"))?
+            .code;
+        let mut coverage = StateCoverage::default();
+        for command in parser {
+            match command? {
+                vcd::Command::ChangeString(id, value) if id == code => coverage.record(&value),
+                vcd::Command::ChangeVector(id, value) if id == code => {
+                    let number = value.iter().try_fold(0u64, |n, bit| match bit {
+                        vcd::Value::V0 => Some(n << 1),
+                        vcd::Value::V1 => Some(n << 1 | 1),
+                        _ => None,
+                    });
+                    let state = match number {
+                        Some(n) => match names.iter().find(|(value, _)| *value == n) {
+                            Some((_, name)) => name.to_string(),
+                            None => n.to_string(),
+                        },
+                        None => "x".to_string(),
+                    };
+                    coverage.record(&state);
+                }
+                _ => {}
+            }
+        }
+        Ok(coverage)
+    }
+
+    /// Record the next state, e.g. the value of a label during a replay.
+    pub fn record(&mut self, state: &str) {
+        if self.last.as_deref() == Some(state) {
+            return;
+        }
+        match self.states.iter_mut().find(|(s, _)| s == state) {
+            Some((_, count)) => *count += 1,
+            None => self.states.push((state.to_string(), 1)),
+        }
+        if let Some(last) = self.last.take() {
+            let transition = (last, state.to_string());
+            match self.transitions.iter_mut().find(|(t, _)| *t == transition) {
+                Some((_, count)) => *count += 1,
+                None => self.transitions.push((transition, 1)),
+            }
+        }
+        self.last = Some(state.to_string());
+    }
+
+    /// Return the visited states and how often they were entered, in the
+    /// order of the first visit.
+    pub fn states(&self) -> &[(String, usize)] {
+        &self.states
+    }
+
+    /// Return the transitions and how often they happened, in the order of
+    /// their first occurrence.
+    pub fn transitions(&self) -> &[((String, String), usize)] {
+        &self.transitions
+    }
+
+    /// Return how often a state was entered.
+    pub fn count(&self, state: &str) -> usize {
+        self.states
+            .iter()
+            .find(|(s, _)| s == state)
+            .map_or(0, |(_, count)| *count)
+    }
+
+    /// Return `true` if the transition from `from` to `to` happened.
+    pub fn transitioned(&self, from: &str, to: &str) -> bool {
+        self.transitions
+            .iter()
+            .any(|((a, b), _)| a == from && b == to)
+    }
+
+    /// Check that all required states were visited and all required
+    /// transitions happened.
+    pub fn check(
+        &self,
+        states: &[&str],
+        transitions: &[(&str, &str)],
+    ) -> Result<(), CoverageError> {
+        let error = CoverageError {
+            states: states
+                .iter()
+                .filter(|s| self.count(s) == 0)
+                .map(|s| s.to_string())
+                .collect(),
+            transitions: transitions
+                .iter()
+                .filter(|(from, to)| !self.transitioned(from, to))
+                .map(|(from, to)| (from.to_string(), to.to_string()))
+                .collect(),
+        };
+        if error.states.is_empty() && error.transitions.is_empty() {
+            Ok(())
+        } else {
+            Err(error)
+        }
+    }
+
+    /// Write a coverage report with the counts of all states and transitions.
+    pub fn write_report<W: std::io::Write>(&self, mut output: W) -> IOResult<()> {
+        for (state, count) in self.states.iter() {
+            writeln!(output, "state {}: {}", state, count)?;
+        }
+        for ((from, to), count) in self.transitions.iter() {
+            writeln!(output, "transition {} -> {}: {}", from, to, count)?;
+        }
+        Ok(())
+    }
+}
+
+/// The edges and states of a single signal, see [ToggleCoverage].
+#[derive(Clone, Debug, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct SignalToggles {
+    /// The full path of the signal, the scopes separated by dots.
+    pub name: String,
+    /// The number of rising edges.
+    pub rising: usize,
+    /// The number of falling edges.
+    pub falling: usize,
+    /// The states the signal had, in the order of the first occurrence.
+    #[cfg_attr(feature = "serde", serde(with = "crate::pins::serde_states::vec"))]
+    pub states: Vec<PinState>,
+}
+
+impl SignalToggles {
+    /// Return `true` if the signal had a rising and a falling edge.
+    pub fn toggled(&self) -> bool {
+        self.rising > 0 && self.falling > 0
+    }
+}
+
+/// The toggles of all recorded signals, to find dead stimulus or outputs
+/// that were never exercised.
+///
+/// A signal is toggled once it had a rising and a falling edge. The first
+/// state of a signal is not an edge.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct ToggleCoverage {
+    signals: Vec<SignalToggles>,
+}
+
+impl ToggleCoverage {
+    /// Read the toggles of all scalar variables from a VCD file.
+    pub fn read<R: Read>(read: R) -> IOResult<Self> {
+        Ok(Self::from_waveforms(&Waveform::read_all(read)?))
+    }
+
+    /// Count the toggles of named waveforms.
+    pub fn from_waveforms(waveforms: &[(String, Waveform)]) -> Self {
+        let signals = waveforms
+            .iter()
+            .map(|(name, waveform)| {
+                let edges = || {
+                    waveform
+                        .changes()
+                        .iter()
+                        .skip(1)
+                        .map(|(_, state)| Edge::to_state(state))
+                };
+                let mut states = vec![];
+                for (_, state) in waveform.changes() {
+                    if !states.contains(state) {
+                        states.push(state.clone());
+                    }
+                }
+                SignalToggles {
+                    name: name.clone(),
+                    rising: edges().filter(|e| *e == Edge::Rising).count(),
+                    falling: edges().filter(|e| *e == Edge::Falling).count(),
+                    states,
+                }
+            })
+            .collect();
+        ToggleCoverage { signals }
+    }
+
+    /// Return the toggles of all signals.
+    pub fn signals(&self) -> &[SignalToggles] {
+        &self.signals
+    }
+
+    /// Return the names of the signals that never toggled.
+    pub fn untoggled(&self) -> Vec<&str> {
+        self.signals
+            .iter()
+            .filter(|s| !s.toggled())
+            .map(|s| s.name.as_str())
+            .collect()
+    }
+
+    /// Return the names of the signals that never had the given state.
+    pub fn never_reached(&self, state: PinState) -> Vec<&str> {
+        self.signals
+            .iter()
+            .filter(|s| !s.states.contains(&state))
+            .map(|s| s.name.as_str())
+            .collect()
+    }
+
+    /// Write a report with the edge counts of all signals, marking the
+    /// signals that never toggled.
+    pub fn write_report<W: std::io::Write>(&self, mut output: W) -> IOResult<()> {
+        let toggled = self.signals.iter().filter(|s| s.toggled()).count();
+        writeln!(output, "toggled {}/{}", toggled, self.signals.len())?;
+        for signal in self.signals.iter() {
+            let mark = if signal.toggled() { ' ' } else { '!' };
+            writeln!(
+                output,
+                "{} {} rising={} falling={}",
+                mark, signal.name, signal.rising, signal.falling
+            )?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn state_coverage() {
+        let vcd = "$timescale 1 ns $end
+$scope module top $end
+$var integer 2 ! state $end
+$upscope $end
+$enddefinitions $end
+#0
+b00 !
+#100
+b00 !
+#200
+b1 !
+#300
+b10 !
+#400
+b0 !
+#500
+bx !
+";
+        let names = [(0, "IDLE"), (1, "TX")];
+        let coverage =
+            StateCoverage::read_with_names(vcd.as_bytes(), &["top", "state"], &names).unwrap();
+        let mut report = vec![];
+        coverage.write_report(&mut report).unwrap();
+        assert_eq!(
+            String::from_utf8(report).unwrap(),
+            "state IDLE: 2
+state TX: 1
+state 2: 1
+state x: 1
+transition IDLE -> TX: 1
+transition TX -> 2: 1
+transition 2 -> IDLE: 1
+transition IDLE -> x: 1
+"
+        );
+        let error = coverage
+            .check(&["IDLE", "ERROR"], &[("TX", "IDLE")])
+            .unwrap_err();
+        assert_eq!(
+            error.to_string(),
+            "missing coverage: state ERROR transition TX -> IDLE"
+        );
+    }
+
+    #[test]
+    fn toggle_coverage() {
+        let vcd = "$timescale 1 ns $end
+$scope module top $end
+$var wire 1 ! clk $end
+$var wire 1 \" rst $end
+$var wire 1 # irq $end
+$upscope $end
+$enddefinitions $end
+#0
+0!
+1\"
+z#
+#100
+1!
+0\"
+#200
+0!
+";
+        let coverage = ToggleCoverage::read(vcd.as_bytes()).unwrap();
+        assert_eq!(coverage.untoggled(), vec!["top.rst", "top.irq"]);
+        assert_eq!(coverage.never_reached(PinState::Low), vec!["top.irq"]);
+        let mut report = vec![];
+        coverage.write_report(&mut report).unwrap();
+        assert_eq!(
+            String::from_utf8(report).unwrap(),
+            "toggled 1/3
+  top.clk rising=1 falling=1
+! top.rst rising=0 falling=1
+! top.irq rising=0 falling=0
+"
+        );
+    }
+}
@@ -0,0 +1,183 @@
+//! Fault injection on replayed signals.
+
+use super::stimulus::Rng;
+use crate::analysis::Waveform;
+use crate::pins::PinState;
+use embedded_time::duration::*;
+use std::io::Read;
+use std::io::Result as IOResult;
+
+/// A fault to inject into a waveform.
+#[derive(Clone, Debug, PartialEq)]
+pub enum Fault {
+    /// The signal is stuck at a state from the given time on.
+    StuckAt {
+        /// The state the signal is stuck at.
+        state: PinState,
+        /// The time the fault starts.
+        from: Nanoseconds<u64>,
+    },
+    /// The signal is inverted, floating stays floating.
+    Inverted,
+    /// Short pulses of the opposite state are injected, each state has a
+    /// glitch with the given probability.
+    Glitches {
+        /// The probability of a glitch per state.
+        probability: f32,
+        /// The width of a glitch.
+        width: Nanoseconds<u64>,
+    },
+    /// Changes are dropped with the given probability.
+    DroppedEdges {
+        /// The probability to drop a change.
+        probability: f32,
+    },
+    /// Each change is moved by a random time up to `max` in either
+    /// direction, the order of the changes is kept.
+    Jitter {
+        /// The maximum time a change is moved.
+        max: Nanoseconds<u64>,
+    },
+}
+
+fn invert(state: &PinState) -> PinState {
+    match state {
+        PinState::High => PinState::Low,
+        PinState::Low => PinState::High,
+        PinState::Floating => PinState::Floating,
+    }
+}
+
+impl Fault {
+    /// Return a copy of the waveform with the fault injected.
+    pub fn apply(&self, waveform: &Waveform, rng: &mut Rng) -> Waveform {
+        let changes = waveform.changes();
+        match self {
+            Fault::StuckAt { state, from } => {
+                let mut faulty: Vec<_> =
+                    changes.iter().filter(|(t, _)| t < from).cloned().collect();
+                faulty.push((*from, state.clone()));
+                Waveform::new(faulty)
+            }
+            Fault::Inverted => {
+                Waveform::new(changes.iter().map(|(t, s)| (*t, invert(s))).collect())
+            }
+            Fault::Glitches { probability, width } => {
+                let mut faulty = vec![];
+                for (i, (t, state)) in changes.iter().enumerate() {
+                    faulty.push((*t, state.clone()));
+                    let end = changes.get(i + 1).map(|(next, _)| next.0);
+                    let room = end.map(|end| end - t.0).unwrap_or(u64::MAX);
+                    if room > width.0 + 1 && rng.chance(*probability) {
+                        let max = room.min(1_000 * width.0.max(1)) - width.0 - 1;
+                        let start = t.0 + 1 + rng.range(0, max);
+                        faulty.push((Nanoseconds(start), invert(state)));
+                        faulty.push((Nanoseconds(start + width.0), state.clone()));
+                    }
+                }
+                Waveform::new(faulty)
+            }
+            Fault::DroppedEdges { probability } => Waveform::new(
+                changes
+                    .iter()
+                    .enumerate()
+                    .filter(|(i, _)| *i == 0 || !rng.chance(*probability))
+                    .map(|(_, change)| change.clone())
+                    .collect(),
+            ),
+            Fault::Jitter { max } => {
+                let mut faulty: Vec<(Nanoseconds<u64>, PinState)> = vec![];
+                for (t, state) in changes {
+                    let moved = (t.0 + rng.range(0, 2 * max.0)).saturating_sub(max.0);
+                    let moved = match faulty.last() {
+                        Some((last, _)) => moved.max(last.0 + 1),
+                        None => moved,
+                    };
+                    faulty.push((Nanoseconds(moved), state.clone()));
+                }
+                Waveform::new(faulty)
+            }
+        }
+    }
+}
+
+/// Copy the scalar signals of a VCD file and inject faults.
+///
+/// Each fault is applied to the signal with the given path, see
+/// [Waveform::read_all]. The same seed always injects the same faults.
+pub fn inject<R, W>(read: R, faults: &[(&str, Fault)], seed: u64, output: W) -> IOResult<()>
+where
+    R: Read,
+    W: std::io::Write,
+{
+    let mut rng = Rng::new(seed);
+    let mut signals = Waveform::read_all(read)?;
+    for (path, fault) in faults {
+        for (_, waveform) in signals.iter_mut().filter(|(name, _)| name == path) {
+            *waveform = fault.apply(waveform, &mut rng);
+        }
+    }
+    Waveform::write_all(&signals, output)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn inject_faults() {
+        let clock = Waveform::new(
+            (0..100u64)
+                .map(|i| {
+                    let state = if i % 2 == 0 {
+                        PinState::Low
+                    } else {
+                        PinState::High
+                    };
+                    (Nanoseconds(i * 1_000), state)
+                })
+                .collect(),
+        );
+        let mut rng = Rng::new(7);
+        let stuck = Fault::StuckAt {
+            state: PinState::High,
+            from: Nanoseconds(10_500),
+        };
+        assert_eq!(stuck.apply(&clock, &mut rng).changes().len(), 12);
+        let inverted = Fault::Inverted.apply(&clock, &mut rng);
+        assert_eq!(inverted.state_at(Nanoseconds(0)), Some(PinState::High));
+        let glitches = Fault::Glitches {
+            probability: 0.5,
+            width: Nanoseconds(10),
+        };
+        let glitched = glitches.apply(&clock, &mut rng);
+        let short = glitched
+            .pulses(PinState::High)
+            .iter()
+            .chain(glitched.pulses(PinState::Low).iter())
+            .filter(|(_, w)| w.0 == 10)
+            .count();
+        assert!(short > 20 && short < 80);
+        let dropped = Fault::DroppedEdges { probability: 0.2 }.apply(&clock, &mut rng);
+        assert!(dropped.changes().len() < 100);
+        let jittered = Fault::Jitter {
+            max: Nanoseconds(100),
+        }
+        .apply(&clock, &mut rng);
+        assert_eq!(jittered.changes().len(), 100);
+        assert!(jittered
+            .changes()
+            .iter()
+            .zip(clock.changes())
+            .all(|((a, _), (b, _))| a.0.abs_diff(b.0) <= 100));
+
+        let mut vcd = vec![];
+        Waveform::write_all(&[("top.clk".to_string(), clock)], &mut vcd).unwrap();
+        let mut a = vec![];
+        let mut b = vec![];
+        inject(&vcd[..], &[("top.clk", glitches.clone())], 3, &mut a).unwrap();
+        inject(&vcd[..], &[("top.clk", glitches)], 3, &mut b).unwrap();
+        assert_eq!(a, b);
+        assert_ne!(a, vcd);
+    }
+}
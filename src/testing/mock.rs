@@ -0,0 +1,261 @@
+//! Expectations for [embedded-hal-mock](embedded_hal_mock) from captures.
+//!
+//! The decoded transactions of a capture are converted to the transaction
+//! lists of the SPI, I2C and serial mocks, so a driver test can expect the
+//! same traffic as on the real bus:
+//!
+//! ```
+//! use embedded_hal_0_2::blocking::spi::Transfer;
+//! use embedded_hal_mock::eh0::spi::Mock;
+//! use embedded_hal_vcd::analysis::SpiFrame;
+//! use embedded_hal_vcd::testing::mock;
+//! use embedded_time::duration::*;
+//!
+//! let frames = [SpiFrame {
+//!     start: Nanoseconds(0),
+//!     end: Nanoseconds(20_000),
+//!     mosi: vec![0x9f, 0x00],
+//!     miso: vec![0xff, 0xef],
+//! }];
+//! let mut spi = Mock::new(&mock::spi_transactions(&frames));
+//! // the driver reads the JEDEC id of a flash
+//! let mut id = [0x9f, 0x00];
+//! spi.transfer(&mut id).unwrap();
+//! assert_eq!(id, [0xff, 0xef]);
+//! spi.done();
+//! ```
+//!
+//! The mocks implement the traits of embedded-hal 0.2, for drivers that
+//! are tested against both.
+//!
+//! The fields of the mock transactions are private, so expectations can't
+//! be converted back to waveforms. Instead [verify] compares expectations
+//! written by hand against the ones of a capture.
+
+use crate::analysis::{I2cTransfer, SpiFrame, UartEvent};
+use embedded_hal_mock::eh0::{i2c, serial, spi};
+use embedded_time::duration::*;
+use std::fmt::{self, Debug};
+
+/// Convert SPI frames to the transactions of an SPI mock.
+///
+/// Each frame is a transfer, or a write if no bytes were decoded from MISO.
+/// The chip select of the frames is not part of the expectations.
+pub fn spi_transactions(frames: &[SpiFrame]) -> Vec<spi::Transaction> {
+    frames
+        .iter()
+        .map(|frame| match frame.miso.is_empty() {
+            true => spi::Transaction::write(frame.mosi.clone()),
+            false => spi::Transaction::transfer(frame.mosi.clone(), frame.miso.clone()),
+        })
+        .collect()
+}
+
+/// Convert I2C transfers to the transactions of an I2C mock.
+///
+/// A write that ends with a repeated start followed by a read of the same
+/// address is a write-read. Transfers to an address that was not
+/// acknowledged are skipped, as are empty writes, since the mock can't
+/// expect address probes.
+pub fn i2c_transactions(transfers: &[I2cTransfer]) -> Vec<i2c::Transaction> {
+    let transfers: Vec<&I2cTransfer> = transfers
+        .iter()
+        .filter(|transfer| transfer.address_ack && (transfer.read || !transfer.data.is_empty()))
+        .collect();
+    let mut transactions = vec![];
+    let mut i = 0;
+    while i < transfers.len() {
+        let transfer = transfers[i];
+        let read = transfers
+            .get(i + 1)
+            .filter(|next| !transfer.read && !transfer.stop && next.read)
+            .filter(|next| next.address == transfer.address);
+        transactions.push(match (transfer.read, read) {
+            (_, Some(read)) => {
+                i += 1;
+                i2c::Transaction::write_read(transfer.address, transfer.bytes(), read.bytes())
+            }
+            (true, None) => i2c::Transaction::read(transfer.address, transfer.bytes()),
+            (false, None) => i2c::Transaction::write(transfer.address, transfer.bytes()),
+        });
+        i += 1;
+    }
+    transactions
+}
+
+/// Convert the bytes of the two lines of a serial port to the transactions
+/// of a serial mock.
+///
+/// `rx` is the line the driver reads, `tx` the line it writes. The bytes of
+/// both lines are ordered by time, with consecutive bytes of a line in one
+/// transaction. Breaks and bytes with framing errors are skipped.
+pub fn serial_transactions(rx: &[UartEvent], tx: &[UartEvent]) -> Vec<serial::Transaction<u8>> {
+    let bytes = |events: &[UartEvent], write| -> Vec<(Nanoseconds<u64>, bool, u8)> {
+        events
+            .iter()
+            .filter_map(|event| match event {
+                UartEvent::Byte(byte) if byte.stop => Some((byte.time, write, byte.value)),
+                _ => None,
+            })
+            .collect()
+    };
+    let mut bytes = [bytes(rx, false), bytes(tx, true)].concat();
+    bytes.sort_by_key(|(time, _, _)| *time);
+    let mut transactions = vec![];
+    let mut run: Vec<u8> = vec![];
+    for (k, (_, write, value)) in bytes.iter().enumerate() {
+        run.push(*value);
+        if bytes.get(k + 1).map(|(_, next, _)| next) != Some(write) {
+            transactions.push(match write {
+                true => serial::Transaction::write_many(run.drain(..)),
+                false => serial::Transaction::read_many(run.drain(..)),
+            });
+        }
+    }
+    transactions
+}
+
+/// An expectation that doesn't match the one of a capture, returned by
+/// [verify].
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct ExpectationMismatch {
+    /// The index of the transaction.
+    pub index: usize,
+    /// The expected transaction, `None` if the capture has more
+    /// transactions.
+    pub expected: Option<String>,
+    /// The captured transaction, `None` if there are no more transactions.
+    pub actual: Option<String>,
+}
+
+impl fmt::Display for ExpectationMismatch {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let or_none = |transaction: &Option<String>| match transaction {
+            Some(transaction) => transaction.clone(),
+            None => "nothing".to_string(),
+        };
+        write!(
+            f,
+            "transaction {} is {}, expected {}",
+            self.index,
+            or_none(&self.actual),
+            or_none(&self.expected)
+        )
+    }
+}
+
+impl std::error::Error for ExpectationMismatch {}
+
+/// Check that mock expectations match the ones converted from a capture,
+/// e.g. that hand written expectations of a driver test still reflect the
+/// real device.
+///
+/// ```
+/// use embedded_hal_mock::eh0::i2c::Transaction;
+/// use embedded_hal_vcd::analysis::I2cTransfer;
+/// use embedded_hal_vcd::testing::mock;
+/// use embedded_time::duration::*;
+///
+/// let transfer = |read, data: &[u8], stop| I2cTransfer {
+///     time: Nanoseconds(0),
+///     address: 0x48,
+///     read,
+///     address_ack: true,
+///     data: data.iter().map(|byte| (*byte, true)).collect(),
+///     stop,
+/// };
+/// let captured = [transfer(false, &[0x00], false), transfer(true, &[0x19, 0x80], true)];
+/// let captured = mock::i2c_transactions(&captured);
+/// let expected = [Transaction::write_read(0x48, vec![0x00], vec![0x19, 0x80])];
+/// assert!(mock::verify(&expected, &captured).is_ok());
+/// let error = mock::verify(&[], &captured).unwrap_err();
+/// assert_eq!(error.index, 0);
+/// assert_eq!(error.expected, None);
+/// ```
+pub fn verify<T>(expected: &[T], captured: &[T]) -> Result<(), ExpectationMismatch>
+where
+    T: PartialEq + Debug,
+{
+    let describe = |transaction: Option<&T>| transaction.map(|t| format!("{:?}", t));
+    for index in 0..expected.len().max(captured.len()) {
+        let (expected, actual) = (expected.get(index), captured.get(index));
+        if expected != actual {
+            return Err(ExpectationMismatch {
+                index,
+                expected: describe(expected),
+                actual: describe(actual),
+            });
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::analysis::{Uart, UartByte};
+    use embedded_hal_0_2::serial::{Read, Write};
+
+    #[test]
+    fn expectations() {
+        let transfer = |address, read, data: &[u8], stop| I2cTransfer {
+            time: Nanoseconds(0),
+            address,
+            read,
+            address_ack: address != 0x10,
+            data: data.iter().map(|byte| (*byte, true)).collect(),
+            stop,
+        };
+        let transfers = [
+            transfer(0x10, false, &[], true),
+            transfer(0x20, false, &[], true),
+            transfer(0x48, false, &[0x01, 0x60], true),
+            transfer(0x48, false, &[0x00], false),
+            transfer(0x48, true, &[0x19, 0x80], true),
+            transfer(0x48, true, &[0x19], true),
+        ];
+        assert_eq!(
+            i2c_transactions(&transfers),
+            [
+                i2c::Transaction::write(0x48, vec![0x01, 0x60]),
+                i2c::Transaction::write_read(0x48, vec![0x00], vec![0x19, 0x80]),
+                i2c::Transaction::read(0x48, vec![0x19]),
+            ]
+        );
+
+        let frame = |mosi: &[u8], miso: &[u8]| SpiFrame {
+            start: Nanoseconds(0),
+            end: Nanoseconds(0),
+            mosi: mosi.to_vec(),
+            miso: miso.to_vec(),
+        };
+        let expected = spi_transactions(&[frame(&[0x06], &[]), frame(&[0x05, 0], &[0xff, 0x02])]);
+        let mut wrong = expected.clone();
+        wrong[1] = spi::Transaction::transfer(vec![0x05, 0], vec![0xff, 0x00]);
+        let error = verify(&wrong, &expected).unwrap_err();
+        assert_eq!(error.index, 1);
+        assert!(error
+            .to_string()
+            .starts_with("transaction 1 is Transaction"));
+
+        // the driver sends a command and reads the answer
+        let uart = Uart::new(115_200);
+        let byte = |time, value| {
+            UartEvent::Byte(UartByte {
+                time: Nanoseconds(time),
+                value,
+                stop: true,
+            })
+        };
+        let tx = uart.decode(&uart.encode(&[byte(0, b'A'), byte(100_000, b'T')]));
+        let rx = [byte(300_000, b'O'), byte(400_000, b'K')];
+        let transactions = serial_transactions(&rx, &tx);
+        assert_eq!(transactions.len(), 2);
+        let mut serial = serial::Mock::new(&transactions);
+        serial.write(b'A').unwrap();
+        serial.write(b'T').unwrap();
+        assert_eq!(serial.read(), Ok(b'O'));
+        assert_eq!(serial.read(), Ok(b'K'));
+        serial.done();
+    }
+}
@@ -0,0 +1,482 @@
+//! Random but constraint respecting stimulus for driver tests.
+
+use crate::analysis::Waveform;
+use crate::devices::{Signal, Simulation};
+use crate::pins::PinState;
+use embedded_time::duration::*;
+
+/// A small seeded pseudo random number generator (xorshift64*).
+///
+/// The same seed always generates the same stimulus, so a failing seed can
+/// be replayed.
+#[derive(Clone, Debug)]
+pub struct Rng {
+    state: u64,
+}
+
+impl Rng {
+    /// Create a new generator from a seed.
+    pub fn new(seed: u64) -> Self {
+        // the state must not be zero
+        Rng {
+            state: seed ^ 0x9e37_79b9_7f4a_7c15,
+        }
+    }
+
+    /// Return the next random number.
+    pub fn next_u64(&mut self) -> u64 {
+        self.state ^= self.state >> 12;
+        self.state ^= self.state << 25;
+        self.state ^= self.state >> 27;
+        self.state.wrapping_mul(0x2545_f491_4f6c_dd1d)
+    }
+
+    /// Return a random number in `low..=high`.
+    pub fn range(&mut self, low: u64, high: u64) -> u64 {
+        match (high - low).checked_add(1) {
+            Some(span) => low + self.next_u64() % span,
+            None => self.next_u64(),
+        }
+    }
+
+    /// Return a normally distributed random number with mean 0 and standard
+    /// deviation 1.
+    pub fn gaussian(&mut self) -> f64 {
+        // Box-Muller transform, u1 must not be zero
+        let u1 = ((self.next_u64() >> 11) + 1) as f64 / (1u64 << 53) as f64;
+        let u2 = (self.next_u64() >> 11) as f64 / (1u64 << 53) as f64;
+        (-2.0 * u1.ln()).sqrt() * (2.0 * std::f64::consts::PI * u2).cos()
+    }
+
+    /// Return `true` with the given probability.
+    pub fn chance(&mut self, probability: f32) -> bool {
+        ((self.next_u64() >> 40) as f32) < probability * (1u64 << 24) as f32
+    }
+}
+
+/// Schedule the changes of a waveform on a signal of a [Simulation].
+pub fn play(waveform: &Waveform, sim: &mut Simulation, signal: Signal) {
+    for (t, state) in waveform.changes() {
+        sim.schedule(signal, state.clone(), *t);
+    }
+}
+
+/// The distribution of the jitter of a [Clock].
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Jitter {
+    /// Each edge is moved by up to the given time in either direction.
+    Uniform(Nanoseconds<u64>),
+    /// Each edge is moved by a normally distributed time with the given
+    /// standard deviation.
+    Gaussian(Nanoseconds<u64>),
+}
+
+/// A generator for clocks with jitter and drift.
+///
+/// The clock starts low at time zero and toggles every half period. Edges
+/// are never moved by more than a quarter period, so the clock keeps its
+/// shape.
+///
+/// ```
+/// use embedded_hal_vcd::testing::stimulus::{Clock, Jitter, Rng};
+/// use embedded_time::duration::*;
+///
+/// let clock = Clock::new(Nanoseconds(1_000u64))
+///     .with_jitter(Jitter::Gaussian(Nanoseconds(20)))
+///     .with_drift(-50.0)
+///     .generate(&mut Rng::new(1), 1_000);
+/// ```
+#[derive(Clone, Debug, PartialEq)]
+pub struct Clock {
+    period: Nanoseconds<u64>,
+    jitter: Option<Jitter>,
+    drift: f64,
+}
+
+impl Clock {
+    /// Create a new ideal clock with the given period.
+    pub fn new(period: Nanoseconds<u64>) -> Self {
+        Clock {
+            period,
+            jitter: None,
+            drift: 0.0,
+        }
+    }
+
+    /// Set the jitter of each edge.
+    pub fn with_jitter(mut self, jitter: Jitter) -> Self {
+        self.jitter = Some(jitter);
+        self
+    }
+
+    /// Set the drift in ppm, a positive drift makes the clock slower.
+    pub fn with_drift(mut self, ppm: f64) -> Self {
+        self.drift = ppm;
+        self
+    }
+
+    /// Generate the given number of cycles.
+    pub fn generate(&self, rng: &mut Rng, cycles: usize) -> Waveform {
+        let half = self.period.0 / 2;
+        let limit = (half / 2) as i64;
+        let scale = 1.0 + self.drift / 1_000_000.0;
+        let mut changes = vec![(Nanoseconds(0), PinState::Low)];
+        for i in 1..=(2 * cycles as u64) {
+            let nominal = if self.drift == 0.0 {
+                i * half
+            } else {
+                ((i * half) as f64 * scale).round() as u64
+            };
+            let offset = match self.jitter {
+                None => 0,
+                Some(Jitter::Uniform(max)) => {
+                    let max = max.0.min(limit as u64);
+                    rng.range(0, 2 * max) as i64 - max as i64
+                }
+                Some(Jitter::Gaussian(sigma)) => {
+                    let offset = (rng.gaussian() * sigma.0 as f64).round() as i64;
+                    offset.clamp(-limit, limit)
+                }
+            };
+            let state = if i % 2 == 1 {
+                PinState::High
+            } else {
+                PinState::Low
+            };
+            changes.push((Nanoseconds((nominal as i64 + offset) as u64), state));
+        }
+        Waveform::new(changes)
+    }
+
+    /// Generate the given number of cycles and schedule them on a signal.
+    pub fn play(&self, rng: &mut Rng, sim: &mut Simulation, signal: Signal, cycles: usize) {
+        play(&self.generate(rng, cycles), sim, signal);
+    }
+}
+
+/// Generate a clock with a random jitter on each edge.
+///
+/// The clock starts low at time zero and toggles every half `period`, each
+/// edge is moved by up to `jitter` in either direction, see [Clock].
+pub fn jittered_clock(
+    rng: &mut Rng,
+    period: Nanoseconds<u64>,
+    jitter: Nanoseconds<u64>,
+    cycles: usize,
+) -> Waveform {
+    Clock::new(period)
+        .with_jitter(Jitter::Uniform(jitter))
+        .generate(rng, cycles)
+}
+
+/// The distribution of a random parameter of a stimulus.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Distribution {
+    /// Always the same value.
+    Fixed(u64),
+    /// A value in `low..=high`.
+    Uniform(u64, u64),
+    /// A normally distributed value, rounded and clamped to zero.
+    Gaussian {
+        /// The mean value.
+        mean: f64,
+        /// The standard deviation.
+        sigma: f64,
+    },
+}
+
+impl Distribution {
+    /// Return a random value.
+    pub fn sample(&self, rng: &mut Rng) -> u64 {
+        match *self {
+            Distribution::Fixed(value) => value,
+            Distribution::Uniform(low, high) => rng.range(low, high),
+            Distribution::Gaussian { mean, sigma } => {
+                (mean + rng.gaussian() * sigma).round().max(0.0) as u64
+            }
+        }
+    }
+}
+
+/// A generator for the contact bounce of buttons and switches.
+///
+/// After each edge of an ideal signal the contact bounces a random number
+/// of times, each bounce is a short change back to the previous state at a
+/// random time within the random bounce duration. Bounces never reach the
+/// next edge, so the signal always settles.
+///
+/// ```
+/// use embedded_hal_vcd::testing::stimulus::{Bounce, Distribution, Rng};
+/// use embedded_time::duration::*;
+///
+/// let bounce = Bounce::new()
+///     .with_count(Distribution::Uniform(2, 8))
+///     .with_duration(Distribution::Gaussian {
+///         mean: 1_500_000.0,
+///         sigma: 300_000.0,
+///     });
+/// let mut rng = Rng::new(7);
+/// let button = bounce.press(&mut rng, Nanoseconds(1_000_000), Nanoseconds(100_000_000));
+/// assert!(button.changes().len() >= 2 + 2 * 2 * 2);
+/// ```
+#[derive(Clone, Debug, PartialEq)]
+pub struct Bounce {
+    count: Distribution,
+    duration: Distribution,
+}
+
+impl Default for Bounce {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Bounce {
+    /// Create a new generator with up to 5 bounces within 1 ms to 5 ms.
+    pub fn new() -> Self {
+        Bounce {
+            count: Distribution::Uniform(0, 5),
+            duration: Distribution::Uniform(1_000_000, 5_000_000),
+        }
+    }
+
+    /// Set the distribution of the number of bounces per edge.
+    pub fn with_count(mut self, count: Distribution) -> Self {
+        self.count = count;
+        self
+    }
+
+    /// Set the distribution of the time in ns in which an edge bounces.
+    pub fn with_duration(mut self, duration: Distribution) -> Self {
+        self.duration = duration;
+        self
+    }
+
+    /// Add bounces to all edges of an ideal waveform.
+    ///
+    /// The first state of the waveform is not an edge.
+    pub fn apply(&self, rng: &mut Rng, ideal: &Waveform) -> Waveform {
+        let edges = ideal.changes();
+        let mut changes = edges.first().into_iter().cloned().collect::<Vec<_>>();
+        for (i, (start, settled)) in edges.iter().enumerate().skip(1) {
+            let previous = edges[i - 1].1.clone();
+            let count = self.count.sample(rng);
+            let duration = self.duration.sample(rng).max(1);
+            let mut times: Vec<u64> = (0..2 * count)
+                .map(|_| start.0 + rng.range(1, duration))
+                .filter(|t| edges.get(i + 1).is_none_or(|(next, _)| *t < next.0))
+                .collect();
+            times.sort_unstable();
+            // end with the settled state
+            times.truncate(times.len() / 2 * 2);
+            changes.push((*start, settled.clone()));
+            for (k, t) in times.into_iter().enumerate() {
+                let state = if k % 2 == 0 {
+                    previous.clone()
+                } else {
+                    settled.clone()
+                };
+                changes.push((Nanoseconds(t), state));
+            }
+        }
+        Waveform::new(changes)
+    }
+
+    /// Generate an active low button press with contact bounce.
+    ///
+    /// The line is high until `press`, low for `hold` and high afterwards.
+    pub fn press(
+        &self,
+        rng: &mut Rng,
+        press: Nanoseconds<u64>,
+        hold: Nanoseconds<u64>,
+    ) -> Waveform {
+        let ideal = Waveform::new(vec![
+            (Nanoseconds(0), PinState::High),
+            (press, PinState::Low),
+            (Nanoseconds(press.0 + hold.0), PinState::High),
+        ]);
+        self.apply(rng, &ideal)
+    }
+
+    /// Add bounces to an ideal waveform and schedule it on a signal.
+    pub fn play(&self, rng: &mut Rng, sim: &mut Simulation, signal: Signal, ideal: &Waveform) {
+        play(&self.apply(rng, ideal), sim, signal);
+    }
+}
+
+/// Generate an active low button press with contact bounce.
+///
+/// The line is high until `press`, bounces up to `bounces` times within
+/// `max_bounce` and stays low for `hold`, then bounces again on release.
+/// See [Bounce] for other distributions.
+pub fn bouncing_button(
+    rng: &mut Rng,
+    press: Nanoseconds<u64>,
+    hold: Nanoseconds<u64>,
+    bounces: usize,
+    max_bounce: Nanoseconds<u64>,
+) -> Waveform {
+    Bounce::new()
+        .with_count(Distribution::Uniform(0, bounces as u64))
+        .with_duration(Distribution::Fixed(max_bounce.0))
+        .press(rng, press, Nanoseconds(max_bounce.0 + hold.0))
+}
+
+/// Generate a valid SPI mode 0 transaction with random data bytes.
+///
+/// Returns the waveforms of `cs`, `sck` and `mosi`, named with `prefix`,
+/// together with the sent bytes. Between 1 and `max_len` bytes are sent
+/// MSB first with the given clock period.
+pub fn spi_transaction(
+    rng: &mut Rng,
+    prefix: &str,
+    max_len: usize,
+    period: Nanoseconds<u64>,
+) -> (Vec<(String, Waveform)>, Vec<u8>) {
+    let len = rng.range(1, max_len.max(1) as u64) as usize;
+    let data: Vec<u8> = (0..len).map(|_| rng.next_u64() as u8).collect();
+    (spi_waveforms(prefix, &data, period), data)
+}
+
+/// Generate the waveforms of `cs`, `sck` and `mosi` of a SPI mode 0
+/// transaction that sends the bytes MSB first, named with `prefix`.
+pub fn spi_waveforms(
+    prefix: &str,
+    data: &[u8],
+    period: Nanoseconds<u64>,
+) -> Vec<(String, Waveform)> {
+    let half = period.0 / 2;
+    let mut cs = vec![
+        (Nanoseconds(0), PinState::High),
+        (Nanoseconds(half), PinState::Low),
+    ];
+    let mut sck = vec![(Nanoseconds(0), PinState::Low)];
+    let mut mosi = vec![(Nanoseconds(0), PinState::Low)];
+    let mut t = half;
+    for byte in data.iter() {
+        for bit in (0..8).rev() {
+            let state = if byte & (1 << bit) != 0 {
+                PinState::High
+            } else {
+                PinState::Low
+            };
+            mosi.push((Nanoseconds(t), state));
+            sck.push((Nanoseconds(t + half), PinState::High));
+            sck.push((Nanoseconds(t + 2 * half), PinState::Low));
+            t += period.0;
+        }
+    }
+    cs.push((Nanoseconds(t + half), PinState::High));
+    vec![
+        (format!("{}cs", prefix), Waveform::new(cs)),
+        (format!("{}sck", prefix), Waveform::new(sck)),
+        (format!("{}mosi", prefix), Waveform::new(mosi)),
+    ]
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::reader::VcdReader;
+    use embedded_hal::digital::InputPin as _;
+
+    #[test]
+    fn replay_spi() {
+        for seed in 0..20 {
+            let mut rng = Rng::new(seed);
+            let (signals, data) = spi_transaction(&mut rng, "spi.", 4, Nanoseconds(1_000u64));
+            let mut vcd = vec![];
+            Waveform::write_all(&signals, &mut vcd).unwrap();
+
+            let mut reader = VcdReader::new(&vcd[..]).unwrap();
+            let sck = reader.get_pin(&["spi", "sck"]).unwrap();
+            let mosi = reader.get_pin(&["spi", "mosi"]).unwrap();
+            let mut last = false;
+            let mut bits = vec![];
+            while reader.next().is_some() {
+                let high = sck.is_high().unwrap();
+                if high && !last {
+                    bits.push(mosi.is_high().unwrap());
+                }
+                last = high;
+            }
+            let bytes: Vec<u8> = bits
+                .chunks(8)
+                .map(|b| b.iter().fold(0, |byte, bit| (byte << 1) | *bit as u8))
+                .collect();
+            assert_eq!(bytes, data);
+        }
+
+        let mut rng = Rng::new(1);
+        let button = bouncing_button(
+            &mut rng,
+            Nanoseconds(1_000_000u64),
+            Nanoseconds(50_000_000u64),
+            5,
+            Nanoseconds(2_000_000u64),
+        );
+        assert_eq!(
+            button.state_at(Nanoseconds(20_000_000)),
+            Some(PinState::Low)
+        );
+        assert_eq!(button.changes().last().unwrap().1, PinState::High);
+        let clock = jittered_clock(&mut rng, Nanoseconds(100u64), Nanoseconds(10u64), 10);
+        assert_eq!(clock.pulses(PinState::High).len(), 10);
+    }
+
+    #[test]
+    fn bounce_distributions() {
+        let mut rng = Rng::new(5);
+        let bounce = Bounce::new()
+            .with_count(Distribution::Fixed(3))
+            .with_duration(Distribution::Gaussian {
+                mean: 2_000_000.0,
+                sigma: 500_000.0,
+            });
+        // a switch toggled every 10 ms
+        let ideal = Clock::new(Nanoseconds(20_000_000u64)).generate(&mut rng, 50);
+        let bouncing = bounce.apply(&mut rng, &ideal);
+        assert!(bouncing.changes().len() > ideal.changes().len() * 6);
+        for (t, state) in ideal.changes().iter().skip(1) {
+            let settled = Nanoseconds(t.0 + 9_999_999);
+            assert_eq!(bouncing.state_at(settled).as_ref(), Some(state));
+        }
+        // the edges only bounce within the duration
+        let mut gaps: Vec<u64> = bouncing
+            .changes()
+            .windows(2)
+            .map(|w| w[1].0 .0 - w[0].0 .0)
+            .collect();
+        gaps.sort_unstable();
+        assert!(gaps[gaps.len() - 101] < 5_000_000);
+
+        let bounce = Bounce::new().with_count(Distribution::Fixed(0));
+        assert_eq!(bounce.apply(&mut rng, &ideal), ideal);
+    }
+
+    #[test]
+    fn clock_drift() {
+        let mut sim = Simulation::new();
+        let signal = sim.add_signal(std::sync::Arc::new(crate::pins::AtomicPinState::new()));
+        let clock = Clock::new(Nanoseconds(1_000u64))
+            .with_jitter(Jitter::Gaussian(Nanoseconds(50)))
+            .with_drift(100.0);
+        clock.play(&mut Rng::new(3), &mut sim, signal, 10_000);
+        sim.advance_to(Nanoseconds(10_000_800u64));
+        // 100 ppm slower, the last falling edge is 1 µs late
+        assert_eq!(sim.state(signal), PinState::High);
+        sim.advance_to(Nanoseconds(10_001_300u64));
+        assert_eq!(sim.state(signal), PinState::Low);
+
+        let periods: Vec<i64> = clock
+            .generate(&mut Rng::new(3), 10_000)
+            .pulses(PinState::High)
+            .iter()
+            .map(|(_, width)| width.0 as i64 - 500)
+            .collect();
+        let var = periods.iter().map(|d| (d * d) as f64).sum::<f64>() / periods.len() as f64;
+        // the width is the difference of two edges, 50 * sqrt(2) ≈ 71 ns
+        assert!((60.0..80.0).contains(&var.sqrt()));
+    }
+}
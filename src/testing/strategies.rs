@@ -0,0 +1,210 @@
+//! Shrinkable [proptest](mod@proptest) strategies for stimulus.
+//!
+//! Unlike the seeded generators of the [stimulus](super::stimulus) module
+//! the strategies generate the parameters of a stimulus, e.g. the bytes of
+//! a transaction or the offset of each edge, so a failing case is shrunk to
+//! a minimal one. The waveforms are replayed by writing them to a VCD file
+//! with [Waveform::write_all].
+//!
+//! ```
+//! use embedded_hal_vcd::analysis::{SpiDecoder, Waveform};
+//! use embedded_hal_vcd::testing::strategies::spi_transaction;
+//! use embedded_hal::spi::MODE_0;
+//! use embedded_time::duration::*;
+//! use proptest::prelude::*;
+//!
+//! proptest!(|((signals, data) in spi_transaction("spi.", 4, Nanoseconds(1_000)))| {
+//!     let mut vcd = vec![];
+//!     Waveform::write_all(&signals, &mut vcd).unwrap();
+//!     let read = |name: &str| Waveform::read(&vcd[..], &["spi", name]).unwrap();
+//!     let idle = Waveform::default();
+//!     let frames = SpiDecoder::new(MODE_0).decode(&read("sck"), &read("mosi"), &idle, &read("cs"));
+//!     prop_assert_eq!(&frames[0].mosi, &data);
+//! });
+//! ```
+
+use super::stimulus::spi_waveforms;
+use crate::analysis::Waveform;
+use crate::pins::PinState;
+use embedded_time::duration::*;
+use proptest::collection::vec;
+use proptest::prelude::*;
+
+/// Return a strategy for a digital pin state, including floating.
+pub fn pin_state() -> impl Strategy<Value = PinState> {
+    prop_oneof![
+        Just(PinState::Low),
+        Just(PinState::High),
+        Just(PinState::Floating)
+    ]
+}
+
+/// Return a strategy for a waveform with a state at time zero and up to
+/// `max_changes` further changes, each at most `max_gap` after the
+/// previous one.
+pub fn waveform(max_changes: usize, max_gap: Nanoseconds<u64>) -> impl Strategy<Value = Waveform> {
+    let changes = vec((1..=max_gap.0.max(1), pin_state()), 0..=max_changes);
+    (pin_state(), changes).prop_map(|(initial, changes)| {
+        let mut t = 0;
+        let mut all = vec![(Nanoseconds(0), initial)];
+        for (gap, state) in changes {
+            t += gap;
+            all.push((Nanoseconds(t), state));
+        }
+        Waveform::new(all)
+    })
+}
+
+/// Return a strategy for a valid SPI mode 0 transaction of 1 to `max_len`
+/// bytes, see [stimulus::spi_transaction](super::stimulus::spi_transaction).
+///
+/// Generates the waveforms of `cs`, `sck` and `mosi`, named with `prefix`,
+/// together with the sent bytes.
+pub fn spi_transaction(
+    prefix: &str,
+    max_len: usize,
+    period: Nanoseconds<u64>,
+) -> impl Strategy<Value = (Vec<(String, Waveform)>, Vec<u8>)> {
+    let prefix = prefix.to_string();
+    vec(any::<u8>(), 1..=max_len.max(1))
+        .prop_map(move |data| (spi_waveforms(&prefix, &data, period), data))
+}
+
+/// Return a strategy for an active low button press with contact bounce.
+///
+/// The line is high until the press at up to 10 ms, bounces up to
+/// `max_bounces` times within `max_bounce` and stays low for 1 ms to
+/// 100 ms, then bounces again on release. The line is low from `max_bounce`
+/// after the press until the release.
+pub fn bouncing_button(
+    max_bounces: usize,
+    max_bounce: Nanoseconds<u64>,
+) -> impl Strategy<Value = Waveform> {
+    let max_bounce = max_bounce.0.max(1);
+    let bounces = move || vec(1..max_bounce.max(2), 0..=2 * max_bounces);
+    (
+        1..=10_000_000u64,
+        1_000_000..=100_000_000u64,
+        bounces(),
+        bounces(),
+    )
+        .prop_map(move |(press, hold, down, up)| {
+            let release = press + max_bounce + hold;
+            let mut changes = vec![(Nanoseconds(0), PinState::High)];
+            bounce(&mut changes, press, PinState::Low, down);
+            bounce(&mut changes, release, PinState::High, up);
+            Waveform::new(changes)
+        })
+}
+
+/// Add an edge to the settled state at `start` that bounces back at the
+/// given offsets.
+fn bounce(
+    changes: &mut Vec<(Nanoseconds<u64>, PinState)>,
+    start: u64,
+    settled: PinState,
+    mut offsets: Vec<u64>,
+) {
+    let previous = match settled {
+        PinState::Low => PinState::High,
+        _ => PinState::Low,
+    };
+    offsets.sort_unstable();
+    offsets.dedup();
+    // end with the settled state
+    offsets.truncate(offsets.len() / 2 * 2);
+    changes.push((Nanoseconds(start), settled.clone()));
+    for (k, offset) in offsets.into_iter().enumerate() {
+        let state = if k % 2 == 0 {
+            previous.clone()
+        } else {
+            settled.clone()
+        };
+        changes.push((Nanoseconds(start + offset), state));
+    }
+}
+
+/// Return a strategy for a clock of 1 to `max_cycles` cycles with jitter.
+///
+/// The clock starts low at time zero and toggles every half `period`, each
+/// edge is moved by up to `jitter` in either direction, but by less than a
+/// quarter period, so the clock keeps its shape.
+pub fn jittered_clock(
+    period: Nanoseconds<u64>,
+    jitter: Nanoseconds<u64>,
+    max_cycles: usize,
+) -> impl Strategy<Value = Waveform> {
+    let half = period.0 / 2;
+    let limit = jitter.0.min(half.saturating_sub(1) / 2) as i64;
+    (1..=max_cycles.max(1))
+        .prop_flat_map(move |cycles| vec(-limit..=limit, 2 * cycles))
+        .prop_map(move |offsets| {
+            let mut changes = vec![(Nanoseconds(0), PinState::Low)];
+            for (i, offset) in offsets.into_iter().enumerate() {
+                let i = i as u64 + 1;
+                let state = if i % 2 == 1 {
+                    PinState::High
+                } else {
+                    PinState::Low
+                };
+                let t = (i * half) as i64 + offset;
+                changes.push((Nanoseconds(t as u64), state));
+            }
+            Waveform::new(changes)
+        })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::analysis::SpiDecoder;
+    use embedded_hal::spi::MODE_0;
+
+    proptest! {
+        #[test]
+        fn waveform_round_trip(
+            a in waveform(50, Nanoseconds(1_000)),
+            b in waveform(50, Nanoseconds(1_000_000)),
+        ) {
+            let signals = vec![("top.a".to_string(), a), ("top.sub.b".to_string(), b)];
+            let mut vcd = vec![];
+            Waveform::write_all(&signals, &mut vcd).unwrap();
+            prop_assert_eq!(Waveform::read_all(&vcd[..]).unwrap(), signals);
+        }
+
+        #[test]
+        fn spi_round_trip((signals, data) in spi_transaction("spi.", 16, Nanoseconds(100))) {
+            let mut vcd = vec![];
+            Waveform::write_all(&signals, &mut vcd).unwrap();
+            let read = |name: &str| Waveform::read(&vcd[..], &["spi", name]).unwrap();
+            let frames = SpiDecoder::new(MODE_0).decode(
+                &read("sck"),
+                &read("mosi"),
+                &Waveform::default(),
+                &read("cs"),
+            );
+            prop_assert_eq!(frames.len(), 1);
+            prop_assert_eq!(&frames[0].mosi, &data);
+        }
+
+        #[test]
+        fn button_settles(button in bouncing_button(5, Nanoseconds(2_000_000))) {
+            let changes = button.changes();
+            let press = changes[1].0;
+            prop_assert_eq!(&changes[0].1, &PinState::High);
+            prop_assert_eq!(&changes[1].1, &PinState::Low);
+            prop_assert_eq!(button.state_at(Nanoseconds(press.0 + 2_000_000)), Some(PinState::Low));
+            prop_assert_eq!(&changes.last().unwrap().1, &PinState::High);
+        }
+
+        #[test]
+        fn clock_keeps_shape(clock in jittered_clock(Nanoseconds(100), Nanoseconds(40), 20)) {
+            let cycles = (clock.changes().len() - 1) / 2;
+            let highs = clock.pulses(PinState::High);
+            let lows = clock.pulses(PinState::Low);
+            prop_assert_eq!(clock.changes().len(), 2 * cycles + 1);
+            prop_assert_eq!(highs.len(), cycles);
+            prop_assert!(highs.iter().chain(lows.iter()).all(|(_, width)| width.0 >= 2));
+        }
+    }
+}
@@ -0,0 +1,384 @@
+//! A minimal VCD writer core that runs without the standard library.
+//!
+//! This module only needs `core` and `alloc`, so the same trace
+//! instrumentation can run on the target (e.g. writing to RTT or a UART) and
+//! on the host. Build the crate with `default-features = false` to use it in
+//! `no_std` environments. Pins are backed by plain [AtomicBool]s, which can
+//! be placed in `static`s, and the output is written to a [ByteSink].
+//!
+//! ```
+//! use embedded_hal_vcd::trace::TraceWriterBuilder;
+//! use embedded_hal::digital::OutputPin;
+//! use core::sync::atomic::AtomicBool;
+//!
+//! static LED: AtomicBool = AtomicBool::new(false);
+//!
+//! let mut builder = TraceWriterBuilder::new(Vec::new()).unwrap();
+//! let mut led = builder.add_pin("led", &LED).unwrap();
+//! let mut writer = builder.build().unwrap();
+//! writer.sample(0).unwrap();
+//! led.set_high().unwrap();
+//! writer.sample(100).unwrap();
+//! assert!(writer.sink().ends_with(b"#0\n0!\n#100\n1!\n"));
+//! ```
+//!
+//! To keep the bandwidth low, a builder created with
+//! [new_binary](TraceWriterBuilder::new_binary) writes a compact binary
+//! change stream instead, which is converted to a VCD file on the host by a
+//! [TraceDecoder].
+
+use alloc::string::String;
+use alloc::vec;
+use alloc::vec::Vec;
+use core::convert::Infallible;
+use core::fmt::Write as _;
+use core::sync::atomic::{AtomicBool, Ordering};
+use embedded_hal::digital::{ErrorType, OutputPin, StatefulOutputPin};
+
+#[cfg(feature = "std")]
+mod decode;
+#[cfg(feature = "std")]
+pub use decode::TraceDecoder;
+
+/// Tags of the records of the binary change stream.
+///
+/// A stream starts with [MAGIC](tag::MAGIC), followed by a module name, the
+/// variables and the end of the definitions. Names are prefixed by their
+/// length as a single byte, indices and time differences are LEB128
+/// encoded.
+mod tag {
+    pub const MAGIC: &[u8] = b"VCDT\x01";
+    pub const MODULE: u8 = 0x00;
+    pub const VAR: u8 = 0x01;
+    pub const END_DEFINITIONS: u8 = 0x02;
+    pub const TIME: u8 = 0x03;
+    pub const LOW: u8 = 0x04;
+    pub const HIGH: u8 = 0x05;
+}
+
+/// An output for the bytes of a [TraceWriter].
+pub trait ByteSink {
+    /// The error type of the sink.
+    type Error;
+
+    /// Write all bytes to the sink.
+    fn write_all(&mut self, bytes: &[u8]) -> Result<(), Self::Error>;
+}
+
+impl ByteSink for Vec<u8> {
+    type Error = Infallible;
+
+    fn write_all(&mut self, bytes: &[u8]) -> Result<(), Self::Error> {
+        self.extend_from_slice(bytes);
+        Ok(())
+    }
+}
+
+/// A [ByteSink] that writes to a [std::io::Write].
+#[cfg(feature = "std")]
+#[derive(Debug)]
+pub struct IoSink<W>(pub W);
+
+#[cfg(feature = "std")]
+impl<W> ByteSink for IoSink<W>
+where
+    W: std::io::Write,
+{
+    type Error = std::io::Error;
+
+    fn write_all(&mut self, bytes: &[u8]) -> Result<(), Self::Error> {
+        self.0.write_all(bytes)
+    }
+}
+
+/// A push pull output pin whose state is recorded by a [TraceWriter].
+#[derive(Clone, Copy, Debug)]
+pub struct TracePin<'a> {
+    state: &'a AtomicBool,
+}
+
+impl<'a> TracePin<'a> {
+    /// Create a new pin that drives `state`, `true` is high.
+    pub fn new(state: &'a AtomicBool) -> Self {
+        TracePin { state }
+    }
+}
+
+impl<'a> ErrorType for TracePin<'a> {
+    type Error = Infallible;
+}
+
+impl<'a> OutputPin for TracePin<'a> {
+    fn set_low(&mut self) -> Result<(), Self::Error> {
+        self.state.store(false, Ordering::SeqCst);
+        Ok(())
+    }
+
+    fn set_high(&mut self) -> Result<(), Self::Error> {
+        self.state.store(true, Ordering::SeqCst);
+        Ok(())
+    }
+}
+
+impl<'a> StatefulOutputPin for TracePin<'a> {
+    fn is_set_high(&self) -> Result<bool, Self::Error> {
+        Ok(self.state.load(Ordering::SeqCst))
+    }
+
+    fn is_set_low(&self) -> Result<bool, Self::Error> {
+        Ok(!self.state.load(Ordering::SeqCst))
+    }
+}
+
+/// Format the VCD identifier of the variable with `index`.
+///
+/// The identifiers are the same as the ones of the `vcd` crate.
+fn write_id(out: &mut String, index: usize) {
+    const CHARS: usize = (b'~' - b'!' + 1) as usize;
+    let mut i = index;
+    loop {
+        out.push((b'!' + (i % CHARS) as u8) as char);
+        if i < CHARS {
+            break;
+        }
+        i = i / CHARS - 1;
+    }
+}
+
+/// Append `value` LEB128 encoded.
+fn write_varint(out: &mut Vec<u8>, mut value: u64) {
+    loop {
+        let byte = (value & 0x7f) as u8;
+        value >>= 7;
+        if value == 0 {
+            out.push(byte);
+            break;
+        }
+        out.push(byte | 0x80);
+    }
+}
+
+/// Append a name prefixed by its length, truncated to 255 bytes.
+fn write_name(out: &mut Vec<u8>, name: &str) {
+    let name = &name.as_bytes()[..name.len().min(255)];
+    out.push(name.len() as u8);
+    out.extend_from_slice(name);
+}
+
+struct TraceVar<'a> {
+    state: &'a AtomicBool,
+    last: Option<bool>,
+}
+
+/// A builder for a [TraceWriter].
+pub struct TraceWriterBuilder<'a, S>
+where
+    S: ByteSink,
+{
+    sink: S,
+    vars: Vec<TraceVar<'a>>,
+    binary: bool,
+}
+
+impl<'a, S> TraceWriterBuilder<'a, S>
+where
+    S: ByteSink,
+{
+    /// Create a new builder that writes to `sink` with the module `top`.
+    pub fn new(sink: S) -> Result<Self, S::Error> {
+        Self::new_with_module(sink, "top")
+    }
+
+    /// Create a new builder that writes to `sink` with an explicit module name.
+    pub fn new_with_module(mut sink: S, module: &str) -> Result<Self, S::Error> {
+        sink.write_all(b"$timescale 1 ns $end\n$scope module ")?;
+        sink.write_all(module.as_bytes())?;
+        sink.write_all(b" $end\n")?;
+        Ok(TraceWriterBuilder {
+            sink,
+            vars: vec![],
+            binary: false,
+        })
+    }
+
+    /// Create a new builder that writes a binary change stream to `sink`.
+    ///
+    /// Use a [TraceDecoder] to convert the stream to a VCD file.
+    pub fn new_binary(mut sink: S, module: &str) -> Result<Self, S::Error> {
+        let mut header = Vec::from(tag::MAGIC);
+        header.push(tag::MODULE);
+        write_name(&mut header, module);
+        sink.write_all(&header)?;
+        Ok(TraceWriterBuilder {
+            sink,
+            vars: vec![],
+            binary: true,
+        })
+    }
+
+    /// Add a pin that drives `state` with a corresponding named VCD variable.
+    pub fn add_pin(
+        &mut self,
+        reference: &str,
+        state: &'a AtomicBool,
+    ) -> Result<TracePin<'a>, S::Error> {
+        if self.binary {
+            let mut record = vec![tag::VAR];
+            write_name(&mut record, reference);
+            self.sink.write_all(&record)?;
+        } else {
+            let mut line = String::from("$var wire 1 ");
+            write_id(&mut line, self.vars.len());
+            let _ = writeln!(line, " {} $end", reference);
+            self.sink.write_all(line.as_bytes())?;
+        }
+        self.vars.push(TraceVar { state, last: None });
+        Ok(TracePin::new(state))
+    }
+
+    /// Build a trace writer.
+    ///
+    /// This consumes the builder.
+    pub fn build(mut self) -> Result<TraceWriter<'a, S>, S::Error> {
+        if self.binary {
+            self.sink.write_all(&[tag::END_DEFINITIONS])?;
+        } else {
+            self.sink
+                .write_all(b"$upscope $end\n$enddefinitions $end\n")?;
+        }
+        Ok(TraceWriter {
+            sink: self.sink,
+            vars: self.vars,
+            binary: self.binary,
+            time: 0,
+        })
+    }
+}
+
+/// A minimal writer for VCD files that runs without the standard library.
+///
+/// Unlike the [VcdWriter](crate::writer::VcdWriter) only changed values are
+/// written to keep the output small.
+pub struct TraceWriter<'a, S>
+where
+    S: ByteSink,
+{
+    sink: S,
+    vars: Vec<TraceVar<'a>>,
+    binary: bool,
+    time: u64,
+}
+
+impl<'a, S> TraceWriter<'a, S>
+where
+    S: ByteSink,
+{
+    /// Sample all pins and write changed states at `time` in nanoseconds.
+    ///
+    /// Nothing is written if no pin changed since the last sample.
+    pub fn sample(&mut self, time: u64) -> Result<(), S::Error> {
+        if self.binary {
+            return self.sample_binary(time);
+        }
+        let mut line = String::new();
+        for (index, var) in self.vars.iter_mut().enumerate() {
+            let state = var.state.load(Ordering::SeqCst);
+            if var.last == Some(state) {
+                continue;
+            }
+            var.last = Some(state);
+            line.push(if state { '1' } else { '0' });
+            write_id(&mut line, index);
+            line.push('\n');
+        }
+        if line.is_empty() {
+            return Ok(());
+        }
+        let mut timestamp = String::new();
+        let _ = writeln!(timestamp, "#{}", time);
+        self.sink.write_all(timestamp.as_bytes())?;
+        self.sink.write_all(line.as_bytes())
+    }
+
+    fn sample_binary(&mut self, time: u64) -> Result<(), S::Error> {
+        let mut record = vec![tag::TIME];
+        write_varint(&mut record, time.saturating_sub(self.time));
+        let header = record.len();
+        for (index, var) in self.vars.iter_mut().enumerate() {
+            let state = var.state.load(Ordering::SeqCst);
+            if var.last == Some(state) {
+                continue;
+            }
+            var.last = Some(state);
+            record.push(if state { tag::HIGH } else { tag::LOW });
+            write_varint(&mut record, index as u64);
+        }
+        if record.len() == header {
+            return Ok(());
+        }
+        self.time = time;
+        self.sink.write_all(&record)
+    }
+
+    /// Return the sink of the writer.
+    pub fn sink(&self) -> &S {
+        &self.sink
+    }
+
+    /// Consume the writer and return the sink.
+    pub fn into_sink(self) -> S {
+        self.sink
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    #[cfg(feature = "std")]
+    fn trace_ids() {
+        let ids: Vec<_> = [0, 93, 94, 95, 9000]
+            .iter()
+            .map(|i| {
+                let mut id = String::new();
+                write_id(&mut id, *i);
+                id
+            })
+            .collect();
+        let expected: Vec<_> = [0u64, 93, 94, 95, 9000]
+            .iter()
+            .map(|i| vcd::IdCode::from(*i).to_string())
+            .collect();
+        assert_eq!(ids, expected);
+    }
+
+    #[test]
+    fn trace_changes() {
+        let (a, b) = (AtomicBool::new(false), AtomicBool::new(true));
+        let mut builder = TraceWriterBuilder::new_with_module(Vec::new(), "logic").unwrap();
+        let mut pa = builder.add_pin("a", &a).unwrap();
+        builder.add_pin("b", &b).unwrap();
+        let mut writer = builder.build().unwrap();
+        writer.sample(0).unwrap();
+        writer.sample(50).unwrap();
+        pa.set_high().unwrap();
+        writer.sample(100).unwrap();
+        let vcd = String::from_utf8(writer.into_sink()).unwrap();
+        assert_eq!(
+            vcd,
+            "$timescale 1 ns $end
+$scope module logic $end
+$var wire 1 ! a $end
+$var wire 1 \" b $end
+$upscope $end
+$enddefinitions $end
+#0
+0!
+1\"
+#100
+1!
+"
+        );
+    }
+}
@@ -0,0 +1,243 @@
+//! Convert the binary change stream of a [TraceWriter](super::TraceWriter)
+//! to a VCD file on the host.
+
+use super::tag;
+use std::io::{Error, ErrorKind, Read, Result as IOResult};
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+enum Stage {
+    Magic,
+    Definitions,
+    Changes,
+}
+
+/// A host side decoder for the binary change stream of a
+/// [TraceWriter](super::TraceWriter) created with
+/// [new_binary](super::TraceWriterBuilder::new_binary).
+///
+/// The stream can be fed in arbitrary chunks as received from RTT, ITM or a
+/// serial port. Each complete record is converted and written to the VCD
+/// output right away, so the file can be watched live with a viewer that
+/// reloads it.
+///
+/// ```
+/// use embedded_hal_vcd::trace::{TraceDecoder, TraceWriterBuilder};
+/// use core::sync::atomic::AtomicBool;
+///
+/// let led = AtomicBool::new(false);
+/// let mut builder = TraceWriterBuilder::new_binary(Vec::new(), "top").unwrap();
+/// builder.add_pin("led", &led).unwrap();
+/// let mut writer = builder.build().unwrap();
+/// writer.sample(0).unwrap();
+///
+/// let mut vcd = Vec::new();
+/// let mut decoder = TraceDecoder::new(&mut vcd).unwrap();
+/// decoder.feed(writer.sink()).unwrap();
+/// drop(decoder);
+/// assert!(String::from_utf8(vcd).unwrap().ends_with("#0\n0!\n"));
+/// ```
+pub struct TraceDecoder<W>
+where
+    W: std::io::Write,
+{
+    writer: vcd::Writer<W>,
+    buffer: Vec<u8>,
+    stage: Stage,
+    ids: Vec<vcd::IdCode>,
+    time: u64,
+}
+
+fn invalid(msg: &str) -> Error {
+    Error::new(ErrorKind::InvalidData, msg)
+}
+
+/// Read a LEB128 encoded value, `None` if the data is incomplete.
+fn read_varint(data: &[u8]) -> IOResult<Option<(u64, usize)>> {
+    let mut value = 0u64;
+    for (i, byte) in data.iter().enumerate() {
+        if i >= 10 {
+            return Err(invalid("varint too long"));
+        }
+        value |= ((byte & 0x7f) as u64) << (7 * i);
+        if byte & 0x80 == 0 {
+            return Ok(Some((value, i + 1)));
+        }
+    }
+    Ok(None)
+}
+
+/// Read a name prefixed by its length, `None` if the data is incomplete.
+fn read_name(data: &[u8]) -> IOResult<Option<(String, usize)>> {
+    let len = match data.first() {
+        Some(len) => *len as usize,
+        None => return Ok(None),
+    };
+    match data.get(1..len + 1) {
+        Some(name) => {
+            let name = std::str::from_utf8(name).map_err(|_| invalid("name is not UTF-8"))?;
+            Ok(Some((name.to_string(), len + 1)))
+        }
+        None => Ok(None),
+    }
+}
+
+impl<W> TraceDecoder<W>
+where
+    W: std::io::Write,
+{
+    /// Create a new decoder that writes the VCD file to `writer`.
+    pub fn new(writer: W) -> IOResult<Self> {
+        let mut writer = vcd::Writer::new(writer);
+        writer.timescale(1, vcd::TimescaleUnit::NS)?;
+        Ok(TraceDecoder {
+            writer,
+            buffer: vec![],
+            stage: Stage::Magic,
+            ids: vec![],
+            time: 0,
+        })
+    }
+
+    /// Decode the next chunk of the stream.
+    ///
+    /// Incomplete records at the end of the chunk are kept until the next
+    /// call.
+    pub fn feed(&mut self, bytes: &[u8]) -> IOResult<()> {
+        let mut buffer = std::mem::take(&mut self.buffer);
+        buffer.extend_from_slice(bytes);
+        let mut pos = 0;
+        while let Some(len) = self.decode(&buffer[pos..])? {
+            pos += len;
+        }
+        buffer.drain(..pos);
+        self.buffer = buffer;
+        Ok(())
+    }
+
+    /// Decode a whole stream, e.g. from a serial port, until the end.
+    pub fn read_from<R: Read>(&mut self, mut read: R) -> IOResult<()> {
+        let mut chunk = [0u8; 256];
+        loop {
+            match read.read(&mut chunk) {
+                Ok(0) => return Ok(()),
+                Ok(n) => self.feed(&chunk[..n])?,
+                Err(e) if e.kind() == ErrorKind::Interrupted => {}
+                Err(e) => return Err(e),
+            }
+        }
+    }
+
+    /// Decode a single record and return its length, `None` if incomplete.
+    fn decode(&mut self, data: &[u8]) -> IOResult<Option<usize>> {
+        if self.stage == Stage::Magic {
+            if data.len() < tag::MAGIC.len() {
+                return Ok(None);
+            }
+            if &data[..tag::MAGIC.len()] != tag::MAGIC {
+                return Err(invalid("not a binary trace stream"));
+            }
+            self.stage = Stage::Definitions;
+            return Ok(Some(tag::MAGIC.len()));
+        }
+        let (record, rest) = match data.split_first() {
+            Some((record, rest)) => (*record, rest),
+            None => return Ok(None),
+        };
+        match (self.stage, record) {
+            (Stage::Definitions, tag::MODULE) => match read_name(rest)? {
+                Some((name, len)) => {
+                    self.writer.add_module(&name)?;
+                    Ok(Some(len + 1))
+                }
+                None => Ok(None),
+            },
+            (Stage::Definitions, tag::VAR) => match read_name(rest)? {
+                Some((name, len)) => {
+                    self.ids.push(self.writer.add_wire(1, &name)?);
+                    Ok(Some(len + 1))
+                }
+                None => Ok(None),
+            },
+            (Stage::Definitions, tag::END_DEFINITIONS) => {
+                self.writer.upscope()?;
+                self.writer.enddefinitions()?;
+                self.stage = Stage::Changes;
+                Ok(Some(1))
+            }
+            (Stage::Changes, tag::TIME) => match read_varint(rest)? {
+                Some((delta, len)) => {
+                    self.time += delta;
+                    self.writer.timestamp(self.time)?;
+                    Ok(Some(len + 1))
+                }
+                None => Ok(None),
+            },
+            (Stage::Changes, tag::LOW | tag::HIGH) => match read_varint(rest)? {
+                Some((index, len)) => {
+                    let id = *self
+                        .ids
+                        .get(index as usize)
+                        .ok_or_else(|| invalid("unknown variable"))?;
+                    let value = if record == tag::HIGH {
+                        vcd::Value::V1
+                    } else {
+                        vcd::Value::V0
+                    };
+                    self.writer.change_scalar(id, value)?;
+                    Ok(Some(len + 1))
+                }
+                None => Ok(None),
+            },
+            _ => Err(invalid("unexpected record")),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::super::TraceWriterBuilder;
+    use super::*;
+    use core::sync::atomic::{AtomicBool, Ordering};
+
+    #[test]
+    fn decode_chunks() {
+        let (a, b) = (AtomicBool::new(false), AtomicBool::new(true));
+        let mut builder = TraceWriterBuilder::new_binary(Vec::new(), "logic").unwrap();
+        builder.add_pin("a", &a).unwrap();
+        builder.add_pin("b", &b).unwrap();
+        let mut writer = builder.build().unwrap();
+        writer.sample(0).unwrap();
+        writer.sample(50).unwrap();
+        a.store(true, Ordering::SeqCst);
+        writer.sample(1000).unwrap();
+        b.store(false, Ordering::SeqCst);
+        writer.sample(1_000_000).unwrap();
+        let stream = writer.into_sink();
+
+        let mut vcd = vec![];
+        let mut decoder = TraceDecoder::new(&mut vcd).unwrap();
+        for chunk in stream.chunks(3) {
+            decoder.feed(chunk).unwrap();
+        }
+        drop(decoder);
+        assert_eq!(
+            String::from_utf8(vcd).unwrap(),
+            "$timescale 1 ns $end
+$scope module logic $end
+$var wire 1 ! a $end
+$var wire 1 \" b $end
+$upscope $end
+$enddefinitions $end
+#0
+0!
+1\"
+#1000
+1!
+#1000000
+0\"
+"
+        );
+        let mut decoder = TraceDecoder::new(std::io::sink()).unwrap();
+        assert!(decoder.feed(b"VCDX\x01").is_err());
+    }
+}
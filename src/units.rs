@@ -0,0 +1,265 @@
+//! Physical units of recorded signals.
+//!
+//! A [Scale] converts the raw values of a bus or real variable to a physical
+//! quantity, e.g. the codes of a 12-bit ADC with a 3.3 V reference to volts.
+//! The scales of the signals of a recording are collected in [Units], which
+//! the exporters like [jsonl::export_with_units](crate::export::jsonl::export_with_units)
+//! use to write physical values. Analog values are compared with a
+//! tolerance in the same unit with [Scale::check] or
+//! [assert_near](crate::testing::assert_near).
+
+use crate::pins::{BusValueError, PinGroup, SignConvention};
+use fnv::FnvHashMap;
+use std::fmt;
+
+/// A linear conversion of raw values to a physical quantity.
+///
+/// ```
+/// use embedded_hal_vcd::pins::PinGroup;
+/// use embedded_hal_vcd::units::Scale;
+///
+/// let adc = PinGroup::new(12);
+/// adc.write(2048);
+/// let volts = Scale::new(3.3 / 4096.0, "V");
+/// assert_eq!(volts.read(&adc), Ok(1.65));
+/// assert_eq!(volts.quantity(2048.0).to_string(), "1.65 V");
+/// ```
+#[derive(Clone, Debug, PartialEq)]
+pub struct Scale {
+    factor: f64,
+    offset: f64,
+    unit: String,
+    sign: Option<SignConvention>,
+}
+
+impl Scale {
+    /// Create a new scale of `factor` units per raw step, e.g. per LSB.
+    ///
+    /// Bus values are read as unsigned numbers without offset.
+    pub fn new(factor: f64, unit: &str) -> Self {
+        Scale {
+            factor,
+            offset: 0.0,
+            unit: unit.to_string(),
+            sign: None,
+        }
+    }
+
+    /// Add an offset to the scaled values, e.g. `-273.15` for a sensor in
+    /// kelvin that should be shown in degrees Celsius.
+    pub fn with_offset(mut self, offset: f64) -> Self {
+        self.offset = offset;
+        self
+    }
+
+    /// Read bus values as signed numbers with the given convention.
+    pub fn with_sign(mut self, convention: SignConvention) -> Self {
+        self.sign = Some(convention);
+        self
+    }
+
+    /// Return the unit.
+    pub fn unit(&self) -> &str {
+        &self.unit
+    }
+
+    /// Convert a raw value to the physical value.
+    pub fn apply(&self, raw: f64) -> f64 {
+        raw * self.factor + self.offset
+    }
+
+    /// Convert a raw value to a physical quantity with unit.
+    pub fn quantity(&self, raw: f64) -> Quantity {
+        Quantity {
+            value: self.apply(raw),
+            unit: self.unit.clone(),
+        }
+    }
+
+    /// Read the physical value of a pin group.
+    pub fn read(&self, group: &PinGroup) -> Result<f64, BusValueError> {
+        let raw = match self.sign {
+            Some(convention) => group.as_signed(convention)? as f64,
+            None => group.as_u128()? as f64,
+        };
+        Ok(self.apply(raw))
+    }
+
+    /// Return the raw number of the bits of a VCD vector, the most
+    /// significant bit first, `None` if a bit is not `0` or `1` or if the
+    /// vector is wider than 128 bits.
+    pub fn raw(&self, bits: &[vcd::Value]) -> Option<f64> {
+        if bits.len() > u128::BITS as usize {
+            return None;
+        }
+        let pattern = bits.iter().try_fold(0u128, |pattern, bit| match bit {
+            vcd::Value::V0 => Some(pattern << 1),
+            vcd::Value::V1 => Some(pattern << 1 | 1),
+            _ => None,
+        })?;
+        Some(match self.sign {
+            Some(convention) => convention.decode(pattern, bits.len() as u32) as f64,
+            None => pattern as f64,
+        })
+    }
+
+    /// Check that the physical value of a raw value is within `tolerance`
+    /// of the expected physical value.
+    ///
+    /// ```
+    /// use embedded_hal_vcd::units::Scale;
+    ///
+    /// let volts = Scale::new(3.3 / 4096.0, "V");
+    /// assert!(volts.check(2048.0, 1.66, 0.02).is_ok());
+    /// let error = volts.check(2048.0, 1.7, 0.02).unwrap_err();
+    /// assert_eq!(error.to_string(), "1.65 V is not within 0.02 V of 1.7 V");
+    /// ```
+    pub fn check(&self, raw: f64, expected: f64, tolerance: f64) -> Result<(), OutOfTolerance> {
+        let actual = self.quantity(raw);
+        if (actual.value - expected).abs() <= tolerance {
+            return Ok(());
+        }
+        Err(OutOfTolerance {
+            actual,
+            expected,
+            tolerance,
+        })
+    }
+}
+
+/// A physical value with its unit.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Quantity {
+    /// The value in the unit.
+    pub value: f64,
+    /// The unit, e.g. `V`.
+    pub unit: String,
+}
+
+impl fmt::Display for Quantity {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{} {}", rounded(self.value), self.unit)
+    }
+}
+
+/// Round away the noise of binary scale factors like `3.3 / 4096.0`.
+pub(crate) fn rounded(value: f64) -> f64 {
+    (value * 1e9).round() / 1e9
+}
+
+/// A value that is not within the tolerance of the expected value, see
+/// [Scale::check].
+#[derive(Clone, Debug, PartialEq)]
+pub struct OutOfTolerance {
+    /// The actual physical value.
+    pub actual: Quantity,
+    /// The expected value in the unit of the actual value.
+    pub expected: f64,
+    /// The tolerance in the unit of the actual value.
+    pub tolerance: f64,
+}
+
+impl fmt::Display for OutOfTolerance {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let quantity = |value| Quantity {
+            value,
+            unit: self.actual.unit.clone(),
+        };
+        write!(
+            f,
+            "{} is not within {} of {}",
+            self.actual,
+            quantity(self.tolerance),
+            quantity(self.expected)
+        )
+    }
+}
+
+impl std::error::Error for OutOfTolerance {}
+
+/// The scales of the signals of a recording, by the full path of the signal
+/// with the scopes separated by dots.
+///
+/// ```
+/// use embedded_hal_vcd::units::{Scale, Units};
+///
+/// let mut units = Units::new();
+/// units.insert("top.adc", Scale::new(3.3 / 4096.0, "V"));
+/// assert_eq!(units.get("top.adc").map(|scale| scale.unit()), Some("V"));
+/// assert_eq!(units.get("top.clk"), None);
+/// ```
+#[derive(Clone, Debug, Default)]
+pub struct Units {
+    scales: FnvHashMap<String, Scale>,
+}
+
+impl Units {
+    /// Create a new empty set of scales.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Set the scale of a signal.
+    pub fn insert(&mut self, signal: &str, scale: Scale) {
+        self.scales.insert(signal.to_string(), scale);
+    }
+
+    /// Return the scale of a signal.
+    pub fn get(&self, signal: &str) -> Option<&Scale> {
+        self.scales.get(signal)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::export::{csv, jsonl};
+    use vcd::Value::*;
+
+    #[test]
+    fn scaled_exports() {
+        // a bipolar ±10 V DAC and a temperature in kelvin
+        let dac = Scale::new(10.0 / 128.0, "V").with_sign(SignConvention::OffsetBinary);
+        assert_eq!(dac.raw(&[V1, V0, V0, V0, V0, V0, V0, V0]), Some(0.0));
+        assert_eq!(dac.raw(&[V0, V0, V0, V0, V0, V0, V0, V0]), Some(-128.0));
+        assert_eq!(dac.raw(&[V1, X]), None);
+        let celsius = Scale::new(1.0, "°C").with_offset(-273.15);
+        assert!(celsius.check(300.0, 27.0, 0.2).is_ok());
+        let error = celsius.check(300.0, 25.0, 0.5).unwrap_err();
+        assert_eq!(error.to_string(), "26.85 °C is not within 0.5 °C of 25 °C");
+
+        let mut units = Units::new();
+        units.insert("top.dac", dac);
+        units.insert("top.temp", celsius);
+        let vcd = "$timescale 1 us $end
+$scope module top $end
+$var wire 8 ! dac $end
+$var real 64 \" temp $end
+$var wire 8 # raw $end
+$upscope $end
+$enddefinitions $end
+#0
+b11000000 !
+r300 \"
+b11000000 #
+#1
+bx0000000 !
+";
+        let mut json = vec![];
+        jsonl::export_with_units(vcd.as_bytes(), &mut json, &units).unwrap();
+        assert_eq!(
+            String::from_utf8(json).unwrap(),
+            r#"{"t":0,"sig":"top.dac","v":5,"unit":"V"}
+{"t":0,"sig":"top.temp","v":26.85,"unit":"°C"}
+{"t":0,"sig":"top.raw","v":"11000000"}
+{"t":1000,"sig":"top.dac","v":"x0000000"}
+"#
+        );
+        let mut table = vec![];
+        csv::export_with_units(vcd.as_bytes(), &mut table, &units).unwrap();
+        assert_eq!(
+            String::from_utf8(table).unwrap(),
+            "time_ns,top.dac [V],top.temp [°C]\n0,5,26.85\n1000,x,26.85\n"
+        );
+    }
+}
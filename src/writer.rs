@@ -3,19 +3,373 @@
 //!
 
 use crate::pins::*;
+use crate::reader::TimestampError;
 use embedded_time::duration::*;
+use std::collections::VecDeque;
 use std::convert::TryInto;
+use std::fs::File;
 use std::io::Result as IOResult;
-use std::sync::atomic::Ordering;
+use std::io::{BufWriter, Write};
+use std::sync::atomic::{AtomicBool, Ordering};
 use std::sync::Arc;
 
+mod clock;
+mod enums;
+mod memory;
+mod sink;
+
+pub use clock::{MonotonicClock, TimeSource, VirtualClock};
+pub use enums::{write_gtkwave_filter, BusEnum, EnumBus};
+pub use memory::MemoryRecorder;
+use sink::Backend;
+pub use sink::ChangeSink;
+
+/// A shared value that is sampled into a VCD variable.
+enum Variable {
+    /// A pin state, written as scalar value.
+    Pin(Arc<AtomicPinState>),
+    /// A flag, written as `1` if set and `0` otherwise.
+    Flag(Arc<AtomicBool>),
+    /// Whether a pin is driven, written as `0` if the pin state
+    /// is floating and `1` otherwise.
+    Driven(Arc<AtomicPinState>),
+    /// A label, written as string value.
+    Label(Arc<AtomicStateLabel>),
+    /// A counter with a width in bits, written as vector value.
+    Integer(Arc<AtomicCounter>, u32),
+    /// An event, written as `1` if fired since the last sample.
+    Event(Arc<AtomicBool>),
+    /// A group of pins, written as vector value.
+    Group(Arc<PinGroup>),
+    /// A model evaluated at the sample time, written as real value.
+    Real(Model),
+}
+
+/// A model for [VcdWriterBuilder::add_real].
+type Model = Box<dyn FnMut(Nanoseconds<u64>) -> f64 + Send>;
+
+/// A sampled value of a [Variable].
+#[derive(Debug)]
+enum Value {
+    Scalar(vcd::Value),
+    Vector(Vec<vcd::Value>),
+    String(String),
+    Real(f64),
+}
+
+impl Variable {
+    fn value(&mut self, time: Nanoseconds<u64>) -> Option<Value> {
+        let value = match self {
+            Variable::Pin(pin) => Value::Scalar(pin.load(Ordering::SeqCst).into()),
+            Variable::Flag(flag) => Value::Scalar(flag.load(Ordering::SeqCst).into()),
+            Variable::Driven(pin) => {
+                Value::Scalar((pin.load(Ordering::SeqCst) != PinState::Floating).into())
+            }
+            Variable::Label(label) => Value::String(label.load()),
+            Variable::Integer(counter, width) => {
+                let value = counter.load();
+                let bits = (0..*width)
+                    .rev()
+                    .map(|bit| (bit < 64 && value & (1 << bit) != 0).into())
+                    .collect();
+                Value::Vector(bits)
+            }
+            Variable::Group(group) => {
+                Value::Vector(group.states().into_iter().rev().map(Into::into).collect())
+            }
+            Variable::Event(fired) => {
+                return fired
+                    .swap(false, Ordering::SeqCst)
+                    .then_some(Value::Scalar(vcd::Value::V1))
+            }
+            Variable::Real(model) => Value::Real(model(time)),
+        };
+        Some(value)
+    }
+
+    /// Return the value that marks an unknown state.
+    fn unknown(&self) -> Option<Value> {
+        let value = match self {
+            Variable::Label(_) => Value::String("x".to_string()),
+            Variable::Integer(_, width) => Value::Vector(vec![vcd::Value::X; *width as usize]),
+            Variable::Group(group) => Value::Vector(vec![vcd::Value::X; group.width()]),
+            // real values have no unknown state
+            Variable::Event(_) | Variable::Real(_) => return None,
+            _ => Value::Scalar(vcd::Value::X),
+        };
+        Some(value)
+    }
+}
+
+/// The type of the VCD variables of pins, see [VcdWriterBuilder::set_var_type].
+///
+/// Some tools treat the types differently, e.g. simulators resolve `wand`
+/// and `wor` nets, while most viewers only show the type.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Default)]
+pub enum VarType {
+    /// A `wire` net, the default.
+    #[default]
+    Wire,
+    /// A `reg` variable, e.g. for registered outputs.
+    Reg,
+    /// A `tri` net.
+    Tri,
+    /// A `tri0` net, pulled low.
+    Tri0,
+    /// A `tri1` net, pulled high.
+    Tri1,
+    /// A `wand` net (wired-AND).
+    WAnd,
+    /// A `wor` net (wired-OR).
+    WOr,
+    /// A `supply0` net, e.g. ground.
+    Supply0,
+    /// A `supply1` net, e.g. a power rail.
+    Supply1,
+}
+
+impl From<VarType> for vcd::VarType {
+    fn from(var_type: VarType) -> Self {
+        match var_type {
+            VarType::Wire => vcd::VarType::Wire,
+            VarType::Reg => vcd::VarType::Reg,
+            VarType::Tri => vcd::VarType::Tri,
+            VarType::Tri0 => vcd::VarType::Tri0,
+            VarType::Tri1 => vcd::VarType::Tri1,
+            VarType::WAnd => vcd::VarType::WAnd,
+            VarType::WOr => vcd::VarType::WOr,
+            VarType::Supply0 => vcd::VarType::Supply0,
+            VarType::Supply1 => vcd::VarType::Supply1,
+        }
+    }
+}
+
+/// How a [VcdWriter] handles a timestamp before the previous one, see
+/// [VcdWriterBuilder::set_timestamp_policy].
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Default)]
+pub enum TimestampPolicy {
+    /// Return an error of kind [InvalidInput](std::io::ErrorKind::InvalidInput)
+    /// with a [TimestampError::Backwards], the default.
+    #[default]
+    Reject,
+    /// Keep the previous time, e.g. for clocks that jump back by a few
+    /// nanoseconds when they are adjusted.
+    Clamp,
+}
+
+/// A filter for [VcdWriterBuilder::set_filter].
+type Filter = Arc<dyn Fn(Nanoseconds<u64>) -> bool + Send + Sync>;
+
+/// A variable of the VCD file.
+struct Entry {
+    id: vcd::IdCode,
+    variable: Variable,
+    filter: Option<Filter>,
+    /// Whether the filter accepted the last sample.
+    recording: bool,
+}
+
+impl Entry {
+    /// Return the value to write, if any.
+    fn sample(&mut self, time: Nanoseconds<u64>) -> Option<Value> {
+        let filter = match self.filter.as_ref() {
+            Some(filter) => filter,
+            None => return self.variable.value(time),
+        };
+        let was_recording = self.recording;
+        self.recording = filter(time);
+        match (was_recording, self.recording) {
+            (_, true) => self.variable.value(time),
+            // mark the end of the recorded section
+            (true, false) => self.variable.unknown(),
+            (false, false) => None,
+        }
+    }
+}
+
+/// The name of a variable, used for [VcdWriter::write_gtkwave_save].
+struct Name {
+    module: String,
+    reference: String,
+    var_type: vcd::VarType,
+    width: u32,
+}
+
+// Trace flags of GTKWave save files, as defined in `analyzer.h` of GTKWave.
+const TR_HEX: u32 = 0x02;
+const TR_DEC: u32 = 0x04;
+const TR_BIN: u32 = 0x08;
+const TR_RJUSTIFY: u32 = 0x20;
+const TR_BLANK: u32 = 0x200;
+
+/// A trigger condition for [VcdWriterBuilder::set_trigger_capture].
+type Trigger = Box<dyn FnMut() -> bool + Send>;
+
+/// A sample kept in memory by a trigger based capture.
+struct Buffered {
+    /// The timestamp written before the sample.
+    timestamp: Option<u64>,
+    /// Annotations written before the sample.
+    annotations: Vec<String>,
+    values: Vec<Option<Value>>,
+}
+
+/// The state of a trigger based capture.
+struct Capture {
+    trigger: Trigger,
+    pre: usize,
+    post: usize,
+    /// Samples before the trigger.
+    history: VecDeque<Buffered>,
+    /// A timestamp that has not been written yet.
+    timestamp: Option<u64>,
+    /// Annotations that have not been written yet.
+    annotations: Vec<String>,
+    /// The number of samples to write after the trigger fired.
+    remaining: Option<usize>,
+}
+
+/// When a [RollingFile] starts a new file.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum RollLimit {
+    /// Start a new file once a file covers the given duration.
+    Duration(Nanoseconds<u64>),
+    /// Start a new file once a file has the given size in bytes.
+    Size(u64),
+}
+
+/// An output that splits a VCD file into multiple files.
+///
+/// Created with [VcdWriterBuilder::rolling]. New files are started at
+/// timestamps only, each file starts with the header of the VCD file, so
+/// every file can be viewed on its own. As the [VcdWriter] writes all
+/// values on each sample, the values are complete after the first sample
+/// of each file.
+pub struct RollingFile {
+    pattern: String,
+    limit: RollLimit,
+    index: usize,
+    file: BufWriter<File>,
+    header: Vec<u8>,
+    header_done: bool,
+    /// A timestamp line that has not been written yet.
+    line: Vec<u8>,
+    line_start: bool,
+    /// The first timestamp of the current file.
+    start: Option<u64>,
+    written: u64,
+}
+
+impl RollingFile {
+    fn new(pattern: &str, limit: RollLimit) -> IOResult<Self> {
+        if !pattern.contains("{}") {
+            return Err(std::io::Error::new(
+                std::io::ErrorKind::InvalidInput,
+                "path pattern needs a {} placeholder for the file index",
+            ));
+        }
+        Ok(RollingFile {
+            pattern: pattern.to_string(),
+            limit,
+            index: 0,
+            file: BufWriter::new(File::create(pattern.replace("{}", "0"))?),
+            header: vec![],
+            header_done: false,
+            line: vec![],
+            line_start: true,
+            start: None,
+            written: 0,
+        })
+    }
+
+    /// Return the path of the file that is currently written.
+    pub fn path(&self) -> String {
+        self.pattern.replace("{}", &self.index.to_string())
+    }
+
+    fn write_file(&mut self, buf: &[u8]) -> IOResult<()> {
+        self.written += buf.len() as u64;
+        self.file.write_all(buf)
+    }
+
+    fn write_timestamp(&mut self) -> IOResult<()> {
+        let line = std::mem::take(&mut self.line);
+        let t = std::str::from_utf8(&line[1..line.len() - 1])
+            .ok()
+            .and_then(|t| t.parse::<u64>().ok())
+            .unwrap_or(0);
+        let roll = match (self.start, self.limit) {
+            (None, _) => false,
+            (Some(start), RollLimit::Duration(duration)) => t.saturating_sub(start) >= duration.0,
+            (Some(_), RollLimit::Size(size)) => self.written >= size,
+        };
+        if roll {
+            self.file.flush()?;
+            self.index += 1;
+            self.file = BufWriter::new(File::create(self.path())?);
+            self.written = 0;
+            let header = std::mem::take(&mut self.header);
+            self.write_file(&header)?;
+            self.header = header;
+            self.start = None;
+        }
+        self.start.get_or_insert(t);
+        self.write_file(&line)
+    }
+}
+
+impl Write for RollingFile {
+    fn write(&mut self, buf: &[u8]) -> IOResult<usize> {
+        for byte in buf.iter().copied() {
+            if !self.header_done {
+                self.header.push(byte);
+                self.write_file(&[byte])?;
+                self.header_done = self.header.ends_with(b"$enddefinitions $end\n");
+            } else if !self.line.is_empty() || (self.line_start && byte == b'#') {
+                self.line.push(byte);
+                if byte == b'\n' {
+                    self.write_timestamp()?;
+                }
+            } else {
+                self.write_file(&[byte])?;
+            }
+            self.line_start = byte == b'\n';
+        }
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> IOResult<()> {
+        self.file.flush()
+    }
+}
+
+/// Replace whitespace, as string values are terminated by whitespace.
+pub(crate) fn escape_string(value: &str) -> String {
+    value
+        .chars()
+        .map(|c| if c.is_whitespace() { '_' } else { c })
+        .collect()
+}
+
 /// A builder for a [VcdWriter].
 pub struct VcdWriterBuilder<W>
 where
     W: std::io::Write,
 {
-    writer: vcd::Writer<W>,
-    pins: Vec<(vcd::IdCode, Arc<AtomicPinState>)>,
+    writer: Backend<W>,
+    pins: Vec<Entry>,
+    output_enable: bool,
+    var_type: VarType,
+    filter: Option<Filter>,
+    annotations: Option<vcd::IdCode>,
+    capture: Option<Capture>,
+    /// The path of the current module, separated by dots.
+    module: String,
+    /// The number of open modules.
+    depth: usize,
+    names: Vec<Name>,
+    clock: Option<Box<dyn TimeSource + Send>>,
+    timestamp_policy: TimestampPolicy,
 }
 
 impl<W> VcdWriterBuilder<W>
@@ -30,15 +384,210 @@ where
     /// Create a new builder from a writer that implements [std::io::Write] with
     /// an explicit module name.
     pub fn new_with_module(writer: W, module: &str) -> IOResult<Self> {
-        let mut writer = vcd::Writer::new(writer);
+        Self::with_backend(Backend::Vcd(vcd::Writer::new(writer)), module)
+    }
+
+    fn with_backend(mut writer: Backend<W>, module: &str) -> IOResult<Self> {
         writer.timescale(1, vcd::TimescaleUnit::NS)?;
         writer.add_module(module)?;
         Ok(VcdWriterBuilder {
             writer,
             pins: vec![],
+            output_enable: false,
+            var_type: VarType::Wire,
+            filter: None,
+            annotations: None,
+            capture: None,
+            module: module.to_string(),
+            depth: 1,
+            names: vec![],
+            clock: None,
+            timestamp_policy: TimestampPolicy::Reject,
         })
     }
 
+    /// Enable or disable output enable traces for open drain pins added hereafter.
+    ///
+    /// If enabled, each open drain pin gets a companion variable named
+    /// `<reference>_oe`, which is `1` while the pin drives the line and `0`
+    /// while the pin is released. This allows to distinguish "driving low"
+    /// from "reading low" when reviewing waveforms.
+    ///
+    /// Output enable traces are disabled by default.
+    pub fn set_output_enable_traces(&mut self, enable: bool) {
+        self.output_enable = enable;
+    }
+
+    /// Set the VCD variable type of pins and pin groups added hereafter.
+    ///
+    /// Companion variables like `<reference>_oe` are always wires. The
+    /// default type is [VarType::Wire].
+    pub fn set_var_type(&mut self, var_type: VarType) {
+        self.var_type = var_type;
+    }
+
+    /// Only record variables added hereafter while `filter` returns `true`.
+    ///
+    /// The filter is called with the time of the last timestamp when
+    /// sampling. While it returns `false`, the values of the variables are not
+    /// written to reduce the size of the VCD file. The value `x` marks the
+    /// end of each recorded section.
+    ///
+    /// ```
+    /// # use embedded_hal_vcd::{pins::*, writer::VcdWriterBuilder};
+    /// # use embedded_hal::digital::InputPin as _;
+    /// # use std::sync::Arc;
+    /// # let mut builder = VcdWriterBuilder::new(std::io::sink()).unwrap();
+    /// let capture_en = Arc::new(AtomicPinState::new_with_state(PinState::Low));
+    /// builder.add_pin_state("capture_en", capture_en.clone()).unwrap();
+    /// let enabled = InputPin::new(capture_en.clone());
+    /// builder.set_filter(move |_| enabled.is_high().unwrap());
+    /// let debug_0 = builder.add_push_pull_pin("debug_0").unwrap();
+    /// builder.clear_filter();
+    /// ```
+    pub fn set_filter<F>(&mut self, filter: F)
+    where
+        F: Fn(Nanoseconds<u64>) -> bool + Send + Sync + 'static,
+    {
+        self.filter = Some(Arc::new(filter));
+    }
+
+    /// Only record variables added hereafter within a time window.
+    ///
+    /// See [set_filter](Self::set_filter).
+    pub fn set_time_window(&mut self, from: Nanoseconds<u64>, until: Nanoseconds<u64>) {
+        self.set_filter(move |t| from <= t && t < until);
+    }
+
+    /// Record all variables added hereafter without filter.
+    pub fn clear_filter(&mut self) {
+        self.filter = None;
+    }
+
+    /// Check the variables before writing the end of the definitions.
+    fn validate(&self) -> IOResult<()> {
+        let invalid = |msg: String| std::io::Error::new(std::io::ErrorKind::InvalidInput, msg);
+        if self.names.is_empty() {
+            return Err(invalid("no variables added to the VCD writer".to_string()));
+        }
+        for (i, name) in self.names.iter().enumerate() {
+            let duplicate = self.names[..i]
+                .iter()
+                .any(|n| n.module == name.module && n.reference == name.reference);
+            if duplicate {
+                return Err(invalid(format!(
+                    "duplicate variable {} in module {}",
+                    name.reference, name.module
+                )));
+            }
+        }
+        Ok(())
+    }
+
+    /// Add a variable to the VCD header and remember it for the GTKWave save file.
+    fn add_var(
+        &mut self,
+        var_type: vcd::VarType,
+        width: u32,
+        reference: &str,
+    ) -> IOResult<vcd::IdCode> {
+        let code = self.writer.add_var(var_type, width, reference)?;
+        self.names.push(Name {
+            module: self.module.clone(),
+            reference: reference.to_string(),
+            var_type,
+            width,
+        });
+        Ok(code)
+    }
+
+    fn push(&mut self, id: vcd::IdCode, variable: Variable) {
+        self.pins.push(Entry {
+            id,
+            variable,
+            filter: self.filter.clone(),
+            recording: false,
+        });
+    }
+
+    /// Only write samples around a trigger to the VCD file.
+    ///
+    /// Instead of writing every sample, the writer keeps the last `pre`
+    /// samples in memory. Once `trigger` returns `true` when sampling, the
+    /// kept samples, the current sample and the following `post` samples are
+    /// written. Afterwards the writer waits for the trigger again.
+    pub fn set_trigger_capture<F>(&mut self, trigger: F, pre: usize, post: usize)
+    where
+        F: FnMut() -> bool + Send + 'static,
+    {
+        self.capture = Some(Capture {
+            trigger: Box::new(trigger),
+            pre,
+            post,
+            history: VecDeque::new(),
+            timestamp: None,
+            annotations: vec![],
+            remaining: None,
+        });
+    }
+
+    /// Take the timestamps of the samples from a time source.
+    ///
+    /// Each [sample](VcdWriter::sample) first writes a timestamp with the
+    /// current time of the source, unless the time did not change, so
+    /// instrumented code only needs to sample. Manual timestamps can still be
+    /// written in between.
+    ///
+    /// ```
+    /// use embedded_hal::digital::OutputPin;
+    /// use embedded_hal_vcd::writer::{VcdWriterBuilder, VirtualClock};
+    /// use embedded_time::duration::*;
+    ///
+    /// let clock = VirtualClock::new();
+    /// let mut vcd = Vec::new();
+    /// let mut builder = VcdWriterBuilder::new(&mut vcd).unwrap();
+    /// let mut led = builder.add_push_pull_pin("led").unwrap();
+    /// builder.set_time_source(clock.clone());
+    /// let mut writer = builder.build().unwrap();
+    /// writer.sample().unwrap();
+    /// clock.advance(Nanoseconds(1_500));
+    /// led.set_high().unwrap();
+    /// writer.sample().unwrap();
+    /// drop(writer);
+    /// assert!(String::from_utf8(vcd).unwrap().ends_with("#0\n0!\n#1500\n1!\n"));
+    /// ```
+    pub fn set_time_source<T>(&mut self, source: T)
+    where
+        T: TimeSource + Send + 'static,
+    {
+        self.clock = Some(Box::new(source));
+    }
+
+    /// Set how timestamps before the previous one are handled.
+    ///
+    /// Timestamps going backwards would produce a timeline that viewers
+    /// and the [reader](crate::reader) can't replay, so they are rejected
+    /// by default.
+    ///
+    /// ```
+    /// use embedded_hal_vcd::writer::{TimestampPolicy, VcdWriterBuilder};
+    /// use embedded_time::duration::*;
+    ///
+    /// let mut vcd = Vec::new();
+    /// let mut builder = VcdWriterBuilder::new(&mut vcd).unwrap();
+    /// builder.add_push_pull_pin("led").unwrap();
+    /// builder.set_timestamp_policy(TimestampPolicy::Clamp);
+    /// let mut writer = builder.build().unwrap();
+    /// writer.timestamp(Nanoseconds(100u64)).unwrap();
+    /// writer.timestamp(Nanoseconds(90u64)).unwrap();
+    /// writer.sample().unwrap();
+    /// drop(writer);
+    /// assert!(String::from_utf8(vcd).unwrap().ends_with("#100\n0!\n"));
+    /// ```
+    pub fn set_timestamp_policy(&mut self, policy: TimestampPolicy) {
+        self.timestamp_policy = policy;
+    }
+
     /// Add a push pull pin with a corresponding named VCD variable.
     ///
     /// The pin state will be written to the VCD file according to the
@@ -51,12 +600,39 @@ where
     ///
     /// The initial pin state is low.
     pub fn add_push_pull_pin(&mut self, reference: &str) -> IOResult<PushPullPin> {
-        let code = self.writer.add_wire(1, reference)?;
+        let code = self.add_var(self.var_type.into(), 1, reference)?;
         let pin = Arc::new(AtomicPinState::new_with_state(PinState::Low));
-        self.pins.push((code, pin.clone()));
+        self.push(code, Variable::Pin(pin.clone()));
         Ok(PushPullPin::new(pin))
     }
 
+    /// Add an active-low push pull pin with a corresponding named VCD variable.
+    ///
+    /// The variable is named `n_<reference>` and records the level of the
+    /// line, while the returned pin is [inverted](Inverted): setting it high
+    /// asserts the signal and drives the line low.
+    ///
+    /// The pin starts deasserted, i.e. the line is high.
+    ///
+    /// ```
+    /// # use embedded_hal_vcd::writer::VcdWriterBuilder;
+    /// # use embedded_time::duration::Nanoseconds;
+    /// use embedded_hal::digital::OutputPin;
+    ///
+    /// let mut builder = VcdWriterBuilder::new(std::io::sink()).unwrap();
+    /// let mut cs = builder.add_active_low_pin("cs").unwrap();
+    /// let mut writer = builder.build().unwrap();
+    /// writer.timestamp(Nanoseconds(0u64)).unwrap();
+    /// cs.set_high().unwrap();
+    /// writer.sample().unwrap();
+    /// ```
+    pub fn add_active_low_pin(&mut self, reference: &str) -> IOResult<Inverted<PushPullPin>> {
+        let code = self.add_var(self.var_type.into(), 1, &format!("n_{}", reference))?;
+        let pin = Arc::new(AtomicPinState::new_with_state(PinState::High));
+        self.push(code, Variable::Pin(pin.clone()));
+        Ok(PushPullPin::new(pin).inverted())
+    }
+
     /// Add an open drain pin with a corresponding named VCD variable.
     ///
     /// The pin state will be written to the VCD file according to the
@@ -68,31 +644,228 @@ where
     /// | low       | Z         |
     ///
     /// The initial pin state is floating.
+    ///
+    /// See [VcdWriterBuilder::set_output_enable_traces] to additionally
+    /// record when the pin drives the line.
     pub fn add_open_drain_pin(&mut self, reference: &str) -> IOResult<OpenDrainPin> {
-        let code = self.writer.add_wire(1, reference)?;
+        let code = self.add_var(self.var_type.into(), 1, reference)?;
         let pin = Arc::new(AtomicPinState::new_with_state(PinState::Floating));
-        self.pins.push((code, pin.clone()));
+        self.push(code, Variable::Pin(pin.clone()));
+        if self.output_enable {
+            let oe_code = self.add_var(vcd::VarType::Wire, 1, &format!("{}_oe", reference))?;
+            self.push(oe_code, Variable::Driven(pin.clone()));
+        }
         Ok(OpenDrainPin::new(pin))
     }
 
-    /// Change the module used for wires added hereafter.
+    /// Add a shared pin state with a corresponding named VCD variable.
+    ///
+    /// This allows to record pin states that are not created by the builder,
+    /// e.g. lines driven by [simulated devices](crate::devices).
+    /// The state is written like the state of a [bidirectional pin](Self::add_bidir_pin).
+    pub fn add_pin_state(&mut self, reference: &str, state: Arc<AtomicPinState>) -> IOResult<()> {
+        let code = self.add_var(self.var_type.into(), 1, reference)?;
+        self.push(code, Variable::Pin(state));
+        Ok(())
+    }
+
+    /// Add a bidirectional pin with a corresponding named VCD variable.
+    ///
+    /// The pin state will be written to the VCD file according to the
+    /// state of the line:
+    ///
+    /// | Pin state | VCD value |
+    /// |-----------|-----------|
+    /// | high      | 1         |
+    /// | low       | 0         |
+    /// | floating  | Z         |
+    ///
+    /// The direction of the pin is written to a second variable named
+    /// `<reference>_oe` (output enable), which is `1` in output mode and `0`
+    /// in input mode.
+    ///
+    /// The pin starts in input mode.
+    pub fn add_bidir_pin(&mut self, reference: &str) -> IOResult<BidirPin> {
+        let code = self.add_var(self.var_type.into(), 1, reference)?;
+        let dir_code = self.add_var(vcd::VarType::Wire, 1, &format!("{}_oe", reference))?;
+        let pin = Arc::new(AtomicPinState::new_with_state(PinState::Floating));
+        let output = Arc::new(AtomicBool::new(false));
+        self.push(code, Variable::Pin(pin.clone()));
+        self.push(dir_code, Variable::Flag(output.clone()));
+        Ok(BidirPin::new(pin, output))
+    }
+
+    /// Add an event with a corresponding named VCD event variable.
+    ///
+    /// Events [fired](EventPin::fire) with the returned handle are recorded
+    /// at the time of the current timestamp, even if no sample is taken
+    /// before the next timestamp. This allows to correlate interrupts with
+    /// pin activity.
+    ///
+    /// ```
+    /// # use embedded_hal_vcd::writer::VcdWriterBuilder;
+    /// # use embedded_time::duration::Nanoseconds;
+    /// let mut builder = VcdWriterBuilder::new(Vec::new()).unwrap();
+    /// let irq = builder.add_event("irq_exti0").unwrap();
+    /// let mut writer = builder.build().unwrap();
+    /// writer.timestamp(Nanoseconds(0u64)).unwrap();
+    /// writer.sample().unwrap();
+    /// writer.timestamp(Nanoseconds(150u64)).unwrap();
+    /// irq.fire();
+    /// writer.timestamp(Nanoseconds(200u64)).unwrap();
+    /// ```
+    pub fn add_event(&mut self, reference: &str) -> IOResult<EventPin> {
+        let code = self.add_var(vcd::VarType::Event, 1, reference)?;
+        let fired = Arc::new(AtomicBool::new(false));
+        self.push(code, Variable::Event(fired.clone()));
+        Ok(EventPin::new(fired))
+    }
+
+    /// Add a state label with a corresponding named VCD string variable.
+    ///
+    /// This allows to record the state of a state machine ("IDLE", "TX",
+    /// "ERROR") alongside pin activity. Whitespace in labels is replaced
+    /// by `_`. The initial label is empty.
+    pub fn add_state_label(&mut self, reference: &str) -> IOResult<Arc<AtomicStateLabel>> {
+        let code = self.add_var(vcd::VarType::String, 1, reference)?;
+        let label = Arc::new(AtomicStateLabel::default());
+        self.push(code, Variable::Label(label.clone()));
+        Ok(label)
+    }
+
+    /// Add a counter with a corresponding named VCD integer variable.
+    ///
+    /// The counter is written with `width` bits, higher bits are cut off.
+    /// The initial value is `0`.
+    pub fn add_integer(&mut self, reference: &str, width: u32) -> IOResult<Arc<AtomicCounter>> {
+        let code = self.add_var(vcd::VarType::Integer, width, reference)?;
+        let counter = Arc::new(AtomicCounter::default());
+        self.push(code, Variable::Integer(counter.clone(), width));
+        Ok(counter)
+    }
+
+    /// Add a model with a corresponding named VCD real variable.
+    ///
+    /// The model is evaluated with the current time on each
+    /// [sample](VcdWriter::sample), so host side models like a battery
+    /// voltage or a temperature ramp appear alongside the pins.
+    ///
+    /// ```
+    /// # use embedded_hal_vcd::writer::VcdWriterBuilder;
+    /// # use embedded_time::duration::Nanoseconds;
+    /// let mut output = Vec::new();
+    /// let mut builder = VcdWriterBuilder::new(&mut output).unwrap();
+    /// // a battery discharging by 0.1 V per ms
+    /// builder
+    ///     .add_real("vbat", |t: Nanoseconds<u64>| 4.2 - t.0 as f64 * 1e-7)
+    ///     .unwrap();
+    /// let mut writer = builder.build().unwrap();
+    /// writer.timestamp(Nanoseconds(2_000_000u64)).unwrap();
+    /// writer.sample().unwrap();
+    /// drop(writer);
+    /// assert!(String::from_utf8(output).unwrap().ends_with("r4 !\n"));
+    /// ```
+    pub fn add_real<F>(&mut self, reference: &str, model: F) -> IOResult<()>
+    where
+        F: FnMut(Nanoseconds<u64>) -> f64 + Send + 'static,
+    {
+        let code = self.add_var(vcd::VarType::Real, 64, reference)?;
+        self.push(code, Variable::Real(Box::new(model)));
+        Ok(())
+    }
+
+    /// Add a group of `width` pins with a corresponding named VCD vector variable.
+    ///
+    /// The pins are written like [bidirectional pins](Self::add_bidir_pin),
+    /// the most significant bit first. The initial pattern is `0`.
+    pub fn add_pin_group(&mut self, reference: &str, width: u32) -> IOResult<Arc<PinGroup>> {
+        let code = self.add_var(self.var_type.into(), width, reference)?;
+        let group = Arc::new(PinGroup::new(width as usize));
+        self.push(code, Variable::Group(group.clone()));
+        Ok(group)
+    }
+
+    /// Add a shared group of pins with a corresponding named VCD vector variable.
+    ///
+    /// This allows to record groups that are not created by the builder,
+    /// e.g. a [virtual bus](crate::reader::VcdReader::get_virtual_bus). The
+    /// group is written like a group added with [add_pin_group](Self::add_pin_group).
+    pub fn add_group_state(&mut self, reference: &str, group: Arc<PinGroup>) -> IOResult<()> {
+        let code = self.add_var(self.var_type.into(), group.width() as u32, reference)?;
+        self.push(code, Variable::Group(group));
+        Ok(())
+    }
+
+    /// Add a string variable that records the texts of [VcdWriter::annotate].
+    ///
+    /// Whitespace in annotations is replaced by `_` in the variable.
+    pub fn add_annotations(&mut self, reference: &str) -> IOResult<()> {
+        let code = self.add_var(vcd::VarType::String, 1, reference)?;
+        self.annotations = Some(code);
+        Ok(())
+    }
+
+    /// Add a module inside the current module for wires added hereafter.
     pub fn add_module(&mut self, identifier: &str) -> IOResult<()> {
+        self.module = format!("{}.{}", self.module, identifier);
+        self.depth += 1;
         self.writer.add_module(identifier)
     }
 
     /// Build a VCD writer.
     ///
-    /// This consumes the builder.
+    /// This consumes the builder. Returns an error if no variable was added
+    /// or if two variables of the same module have the same reference, as
+    /// viewers could not tell them apart.
     pub fn build(mut self) -> IOResult<VcdWriter<W>> {
-        self.writer.upscope()?;
+        self.validate()?;
+        for _ in 0..self.depth {
+            self.writer.upscope()?;
+        }
         self.writer.enddefinitions()?;
         Ok(VcdWriter {
             writer: self.writer,
             pins: self.pins,
+            time: Nanoseconds(0),
+            annotations: self.annotations,
+            capture: self.capture,
+            names: self.names,
+            stamped: false,
+            clock: self.clock,
+            timestamp_policy: self.timestamp_policy,
+            checkpoint: None,
         })
     }
 }
 
+impl VcdWriterBuilder<std::io::Sink> {
+    /// Create a new builder that records to a [ChangeSink] instead of a VCD
+    /// file.
+    pub fn with_sink<S: ChangeSink + Send + 'static>(sink: S) -> IOResult<Self> {
+        Self::with_sink_and_module(sink, "top")
+    }
+
+    /// Create a new builder that records to a [ChangeSink] with an explicit
+    /// module name.
+    pub fn with_sink_and_module<S: ChangeSink + Send + 'static>(
+        sink: S,
+        module: &str,
+    ) -> IOResult<Self> {
+        Self::with_backend(Backend::Sink(Box::new(sink)), module)
+    }
+}
+
+impl VcdWriterBuilder<RollingFile> {
+    /// Create a new builder that writes to multiple rolling files.
+    ///
+    /// The path of each file is `path_pattern` with `{}` replaced by the
+    /// index of the file, starting at `0`. A new file is started once the
+    /// current file reaches the given limit, see [RollingFile].
+    pub fn rolling(path_pattern: &str, limit: RollLimit) -> IOResult<Self> {
+        Self::new(RollingFile::new(path_pattern, limit)?)
+    }
+}
+
 /// A writer for VCD files.
 ///
 /// Write VCD files based on pin states.
@@ -100,8 +873,18 @@ pub struct VcdWriter<W>
 where
     W: std::io::Write,
 {
-    writer: vcd::Writer<W>,
-    pins: Vec<(vcd::IdCode, Arc<AtomicPinState>)>,
+    writer: Backend<W>,
+    pins: Vec<Entry>,
+    time: Nanoseconds<u64>,
+    annotations: Option<vcd::IdCode>,
+    capture: Option<Capture>,
+    names: Vec<Name>,
+    /// Whether a timestamp has been written.
+    stamped: bool,
+    clock: Option<Box<dyn TimeSource + Send>>,
+    timestamp_policy: TimestampPolicy,
+    /// The period and the time of the next `$dumpall` section.
+    checkpoint: Option<(u64, u64)>,
 }
 
 impl<W> VcdWriter<W>
@@ -111,7 +894,8 @@ where
     /// Write a timestamp to the VCD file.
     ///
     /// A timestamp represents a point in time that is used for the following
-    /// pin states.
+    /// pin states. Timestamps before the previous one are handled according
+    /// to the [TimestampPolicy].
     pub fn timestamp<D: TryInto<Nanoseconds<u64>>>(&mut self, timestamp: D) -> IOResult<()> {
         let ts: Nanoseconds<u64> = timestamp.try_into().map_err(|_e| {
             std::io::Error::new(
@@ -119,17 +903,238 @@ where
                 "can't convert timestamp to nanoseconds",
             )
         })?;
-        self.writer.timestamp(ts.0)
+        if ts < self.time {
+            match self.timestamp_policy {
+                TimestampPolicy::Reject => {
+                    let error = TimestampError::Backwards {
+                        timestamp: ts.0,
+                        previous: self.time.0,
+                    };
+                    return Err(std::io::Error::new(std::io::ErrorKind::InvalidInput, error));
+                }
+                // the samples are written at the previous timestamp
+                TimestampPolicy::Clamp => return Ok(()),
+            }
+        }
+        match self.capture.as_mut() {
+            Some(capture) if capture.remaining.is_none() => {
+                self.time = ts;
+                capture.timestamp = Some(ts.0);
+                Ok(())
+            }
+            _ => {
+                if self.stamped {
+                    self.write_events()?;
+                }
+                self.time = ts;
+                self.stamped = true;
+                #[cfg(feature = "tracing")]
+                tracing::trace!(time = ts.0, "timestamp");
+                self.writer.timestamp(ts.0)
+            }
+        }
+    }
+
+    /// Write the events fired since the last sample at the current time.
+    fn write_events(&mut self) -> IOResult<()> {
+        let time = self.time;
+        let values = self
+            .pins
+            .iter_mut()
+            .map(|pin| match pin.variable {
+                Variable::Event(_) => pin.sample(time),
+                _ => None,
+            })
+            .collect();
+        self.write_values(values)
     }
 
     /// Sample all pins and write their state to the VCD file.
     ///
     /// All assigned pins will be sampled and their state is written
-    /// according to the variable configuration.
+    /// according to the variable configuration. With a
+    /// [time source](VcdWriterBuilder::set_time_source) the current time is
+    /// written first.
     pub fn sample(&mut self) -> IOResult<()> {
-        for (id, pin) in self.pins.iter() {
-            let state: PinState = pin.load(Ordering::SeqCst);
-            self.writer.change_scalar(*id, vcd::Value::from(state))?;
+        if let Some(now) = self.clock.as_ref().map(|clock| clock.now()) {
+            if !self.stamped || now != self.time {
+                self.timestamp(now)?;
+            }
+        }
+        let time = self.time;
+        #[cfg(feature = "tracing")]
+        let _span = tracing::trace_span!("sample", time = time.0).entered();
+        let values: Vec<_> = self.pins.iter_mut().map(|pin| pin.sample(time)).collect();
+        let capture = match self.capture.as_mut() {
+            Some(capture) => capture,
+            None => return self.write_checkpoint(values),
+        };
+        if let Some(remaining) = capture.remaining {
+            capture.remaining = remaining.checked_sub(1).filter(|r| *r > 0);
+            return self.write_checkpoint(values);
+        }
+        let sample = Buffered {
+            timestamp: capture.timestamp.take(),
+            annotations: std::mem::take(&mut capture.annotations),
+            values,
+        };
+        if !(capture.trigger)() {
+            capture.history.push_back(sample);
+            while capture.history.len() > capture.pre {
+                capture.history.pop_front();
+            }
+            return Ok(());
+        }
+        capture.remaining = Some(capture.post).filter(|r| *r > 0);
+        let mut history: Vec<_> = capture.history.drain(..).collect();
+        history.push(sample);
+        for sample in history {
+            if let Some(timestamp) = sample.timestamp {
+                self.writer.timestamp(timestamp)?;
+                self.stamped = true;
+            }
+            for text in sample.annotations.iter() {
+                self.write_annotation(text)?;
+            }
+            self.write_values(sample.values)?;
+        }
+        Ok(())
+    }
+
+    /// Write the values of all variables in a `$dumpall` section every
+    /// `period`.
+    ///
+    /// The first sample at or after each multiple of the period is written
+    /// as `$dumpall` section. This allows the
+    /// [VcdReader](crate::reader::VcdReader) to [seek](crate::reader::VcdReader::seek)
+    /// in the file and viewers that start in the middle of a stream to show
+    /// all values. Samples before the trigger of a
+    /// [trigger based capture](VcdWriterBuilder::set_trigger_capture) are
+    /// written without sections.
+    ///
+    /// ```
+    /// use embedded_hal_vcd::writer::VcdWriterBuilder;
+    /// use embedded_time::duration::*;
+    ///
+    /// let mut vcd = Vec::new();
+    /// let mut builder = VcdWriterBuilder::new(&mut vcd).unwrap();
+    /// builder.add_push_pull_pin("led").unwrap();
+    /// let mut writer = builder.build().unwrap();
+    /// writer.checkpoint_every(1.microseconds()).unwrap();
+    /// for t in (0..2_000u64).step_by(400) {
+    ///     writer.timestamp(Nanoseconds(t)).unwrap();
+    ///     writer.sample().unwrap();
+    /// }
+    /// drop(writer);
+    /// let vcd = String::from_utf8(vcd).unwrap();
+    /// assert!(vcd.contains("#0\n$dumpall\n0!\n$end\n#400\n0!\n"));
+    /// assert!(vcd.contains("#1200\n$dumpall\n0!\n$end\n"));
+    /// ```
+    pub fn checkpoint_every<D>(&mut self, period: D) -> IOResult<()>
+    where
+        D: TryInto<Nanoseconds<u64>>,
+    {
+        let period: Nanoseconds<u64> = period.try_into().map_err(|_e| {
+            std::io::Error::new(
+                std::io::ErrorKind::InvalidInput,
+                "can't convert period to nanoseconds",
+            )
+        })?;
+        if period.0 == 0 {
+            return Err(std::io::Error::new(
+                std::io::ErrorKind::InvalidInput,
+                "period must not be zero",
+            ));
+        }
+        self.checkpoint = Some((period.0, self.time.0));
+        Ok(())
+    }
+
+    /// Write the values, in a `$dumpall` section if a checkpoint is due.
+    fn write_checkpoint(&mut self, values: Vec<Option<Value>>) -> IOResult<()> {
+        let time = self.time.0;
+        match self.checkpoint {
+            Some((period, next)) if time >= next => {
+                let next = (time / period).saturating_add(1).saturating_mul(period);
+                self.checkpoint = Some((period, next));
+                self.writer.begin(vcd::SimulationCommand::Dumpall)?;
+                self.write_values(values)?;
+                self.writer.end()
+            }
+            _ => self.write_values(values),
+        }
+    }
+
+    /// Annotate the current time with a free-form text.
+    ///
+    /// The text is written as `$comment` and to the annotation variable, if
+    /// added with [VcdWriterBuilder::add_annotations]. This allows to mark
+    /// phases of a test like "begin OTA" when reviewing waveforms.
+    pub fn annotate(&mut self, text: &str) -> IOResult<()> {
+        match self.capture.as_mut() {
+            Some(capture) if capture.remaining.is_none() => {
+                capture.annotations.push(text.to_string());
+                Ok(())
+            }
+            _ => self.write_annotation(text),
+        }
+    }
+
+    /// Write a GTKWave save file that shows all variables.
+    ///
+    /// The variables are grouped by module, scalars are shown in binary,
+    /// integers in decimal and other vectors in hexadecimal. Vectors are
+    /// named with their bit range like `top.data[7:0]`, as GTKWave names
+    /// them. `dumpfile` is the path of the VCD file to open with the save
+    /// file.
+    pub fn write_gtkwave_save<O>(&self, mut output: O, dumpfile: Option<&str>) -> IOResult<()>
+    where
+        O: std::io::Write,
+    {
+        if let Some(dumpfile) = dumpfile {
+            writeln!(output, "[dumpfile] \"{}\"", dumpfile)?;
+        }
+        let mut module = None;
+        for name in self.names.iter() {
+            if module != Some(&name.module) {
+                module = Some(&name.module);
+                writeln!(output, "@{:x}\n-{}", TR_BLANK, name.module)?;
+            }
+            let flags = match (name.var_type, name.width) {
+                (vcd::VarType::Integer, _) => TR_RJUSTIFY | TR_DEC,
+                (vcd::VarType::String, _) | (_, 1) => TR_RJUSTIFY | TR_BIN,
+                _ => TR_RJUSTIFY | TR_HEX,
+            };
+            write!(output, "@{:x}\n{}.{}", flags, name.module, name.reference)?;
+            match (name.var_type, name.width) {
+                (vcd::VarType::String | vcd::VarType::Real, _) | (_, 1) => writeln!(output)?,
+                (_, width) => writeln!(output, "[{}:0]", width - 1)?,
+            }
+        }
+        Ok(())
+    }
+
+    fn write_annotation(&mut self, text: &str) -> IOResult<()> {
+        self.writer.comment(text)?;
+        if let Some(id) = self.annotations {
+            self.writer.change_string(id, text)?;
+        }
+        Ok(())
+    }
+
+    fn write_values(&mut self, values: Vec<Option<Value>>) -> IOResult<()> {
+        for (pin, value) in self.pins.iter().zip(values) {
+            #[cfg(feature = "tracing")]
+            if let Some(value) = value.as_ref() {
+                tracing::trace!(id = %pin.id, ?value, "change");
+            }
+            match value {
+                Some(Value::Scalar(value)) => self.writer.change_scalar(pin.id, value)?,
+                Some(Value::Vector(value)) => self.writer.change_vector(pin.id, &value)?,
+                Some(Value::String(value)) => self.writer.change_string(pin.id, &value)?,
+                Some(Value::Real(value)) => self.writer.change_real(pin.id, value)?,
+                None => {}
+            }
         }
         Ok(())
     }
@@ -138,8 +1143,7 @@ where
 #[cfg(test)]
 mod tests {
     use super::*;
-    use embedded_hal::digital::OutputPin;
-    use pretty_assertions::assert_eq;
+    use embedded_hal::digital::{InputPin as _, OutputPin};
     use std::fmt;
     use std::sync::{Arc, Mutex};
     use synchronized_writer::SynchronizedWriter;
@@ -203,4 +1207,767 @@ $enddefinitions $end
         let writer_vcd = String::from_utf8((*buf.lock().unwrap()).clone()).unwrap();
         assert_eq!(&writer_vcd, &vcd);
     }
+
+    #[test]
+    fn write_bidir() {
+        let vcd = "$timescale 1 ns $end
+$scope module logic $end
+$var wire 1 ! data $end
+$var wire 1 \" data_oe $end
+$upscope $end
+$enddefinitions $end
+#0
+z!
+0\"
+#100
+0!
+1\"
+#200
+z!
+0\"
+"
+        .to_string();
+        let buf = Arc::new(Mutex::new(Vec::new()));
+        let writer = SynchronizedWriter::new(buf.clone());
+        let mut writer = VcdWriterBuilder::new_with_module(writer, "logic").unwrap();
+
+        let mut pin = writer.add_bidir_pin("data").unwrap();
+        let mut writer = writer.build().unwrap();
+        writer.timestamp(0.nanoseconds()).unwrap();
+        pin.set_low().unwrap();
+        writer.sample().unwrap();
+        writer.timestamp(100.nanoseconds()).unwrap();
+        pin.set_as_output();
+        writer.sample().unwrap();
+        writer.timestamp(200.nanoseconds()).unwrap();
+        pin.set_as_input();
+        writer.sample().unwrap();
+
+        let writer_vcd = String::from_utf8((*buf.lock().unwrap()).clone()).unwrap();
+        assert_eq!(&writer_vcd, &vcd);
+    }
+
+    #[test]
+    fn write_active_low() {
+        let vcd = "$timescale 1 ns $end
+$scope module logic $end
+$var wire 1 ! n_cs $end
+$upscope $end
+$enddefinitions $end
+#0
+1!
+#100
+0!
+"
+        .to_string();
+        let buf = Arc::new(Mutex::new(Vec::new()));
+        let writer = SynchronizedWriter::new(buf.clone());
+        let mut writer = VcdWriterBuilder::new_with_module(writer, "logic").unwrap();
+
+        let mut cs = writer.add_active_low_pin("cs").unwrap();
+        let mut writer = writer.build().unwrap();
+        writer.timestamp(Nanoseconds(0u64)).unwrap();
+        writer.sample().unwrap();
+        writer.timestamp(Nanoseconds(100u64)).unwrap();
+        cs.set_high().unwrap();
+        writer.sample().unwrap();
+
+        let writer_vcd = String::from_utf8((*buf.lock().unwrap()).clone()).unwrap();
+        assert_eq!(&writer_vcd, &vcd);
+    }
+
+    #[test]
+    fn write_output_enable() {
+        let vcd = "$timescale 1 ns $end
+$scope module logic $end
+$var wire 1 ! sda $end
+$var wire 1 \" sda_oe $end
+$var wire 1 # scl $end
+$upscope $end
+$enddefinitions $end
+#0
+z!
+0\"
+z#
+#100
+0!
+1\"
+z#
+"
+        .to_string();
+        let buf = Arc::new(Mutex::new(Vec::new()));
+        let writer = SynchronizedWriter::new(buf.clone());
+        let mut writer = VcdWriterBuilder::new_with_module(writer, "logic").unwrap();
+
+        writer.set_output_enable_traces(true);
+        let mut sda = writer.add_open_drain_pin("sda").unwrap();
+        writer.set_output_enable_traces(false);
+        let _scl = writer.add_open_drain_pin("scl").unwrap();
+        let mut writer = writer.build().unwrap();
+        writer.timestamp(0.nanoseconds()).unwrap();
+        writer.sample().unwrap();
+        writer.timestamp(100.nanoseconds()).unwrap();
+        sda.set_high().unwrap();
+        writer.sample().unwrap();
+
+        let writer_vcd = String::from_utf8((*buf.lock().unwrap()).clone()).unwrap();
+        assert_eq!(&writer_vcd, &vcd);
+    }
+
+    #[test]
+    fn write_trigger_capture() {
+        let vcd = "$timescale 1 ns $end
+$scope module logic $end
+$var wire 1 ! trigger $end
+$upscope $end
+$enddefinitions $end
+#300
+0!
+#400
+0!
+#500
+1!
+#600
+0!
+#700
+0!
+#800
+0!
+#900
+1!
+"
+        .to_string();
+        let buf = Arc::new(Mutex::new(Vec::new()));
+        let writer = SynchronizedWriter::new(buf.clone());
+        let mut writer = VcdWriterBuilder::new_with_module(writer, "logic").unwrap();
+
+        let state = Arc::new(AtomicPinState::new_with_state(PinState::Low));
+        writer.add_pin_state("trigger", state.clone()).unwrap();
+        let input = InputPin::new(state.clone());
+        writer.set_trigger_capture(move || input.is_high().unwrap(), 2, 1);
+        let mut writer = writer.build().unwrap();
+        for t in 0..10 {
+            writer.timestamp((t * 100).nanoseconds()).unwrap();
+            let triggered = t == 5 || t == 9;
+            let level = if triggered {
+                PinState::High
+            } else {
+                PinState::Low
+            };
+            state.store(level, Ordering::SeqCst);
+            writer.sample().unwrap();
+        }
+
+        let writer_vcd = String::from_utf8((*buf.lock().unwrap()).clone()).unwrap();
+        assert_eq!(&writer_vcd, &vcd);
+    }
+
+    #[test]
+    fn write_filter() {
+        let vcd = "$timescale 1 ns $end
+$scope module logic $end
+$var wire 1 ! clk $end
+$var wire 1 \" debug $end
+$upscope $end
+$enddefinitions $end
+#0
+0!
+#100
+1!
+1\"
+#200
+0!
+0\"
+#300
+1!
+x\"
+#400
+0!
+"
+        .to_string();
+        let buf = Arc::new(Mutex::new(Vec::new()));
+        let writer = SynchronizedWriter::new(buf.clone());
+        let mut writer = VcdWriterBuilder::new_with_module(writer, "logic").unwrap();
+
+        let mut clk = writer.add_push_pull_pin("clk").unwrap();
+        writer.set_time_window(Nanoseconds(100), Nanoseconds(300));
+        let mut debug = writer.add_push_pull_pin("debug").unwrap();
+        let mut writer = writer.build().unwrap();
+        for t in 0..5u64 {
+            writer.timestamp(Nanoseconds(t * 100)).unwrap();
+            if t % 2 == 0 {
+                clk.set_low().unwrap();
+                debug.set_low().unwrap();
+            } else {
+                clk.set_high().unwrap();
+                debug.set_high().unwrap();
+            }
+            writer.sample().unwrap();
+        }
+
+        let writer_vcd = String::from_utf8((*buf.lock().unwrap()).clone()).unwrap();
+        assert_eq!(&writer_vcd, &vcd);
+    }
+
+    #[test]
+    fn write_rolling() {
+        let dir = std::env::temp_dir().join(format!("embedded-hal-vcd-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let pattern = dir.join("rolling-{}.vcd");
+        let mut writer = VcdWriterBuilder::rolling(
+            pattern.to_str().unwrap(),
+            RollLimit::Duration(Nanoseconds(250)),
+        )
+        .unwrap();
+        let mut pin = writer.add_push_pull_pin("test").unwrap();
+        let mut writer = writer.build().unwrap();
+        for t in 0..7u64 {
+            writer.timestamp(Nanoseconds(t * 100)).unwrap();
+            if t % 2 == 0 {
+                pin.set_low().unwrap();
+            } else {
+                pin.set_high().unwrap();
+            }
+            writer.sample().unwrap();
+        }
+        drop(writer);
+
+        let header = "$timescale 1 ns $end
+$scope module top $end
+$var wire 1 ! test $end
+$upscope $end
+$enddefinitions $end
+";
+        let read = |i: usize| {
+            let path = dir.join(format!("rolling-{}.vcd", i));
+            std::fs::read_to_string(path).unwrap()
+        };
+        assert_eq!(&read(0), &format!("{}#0\n0!\n#100\n1!\n#200\n0!\n", header));
+        assert_eq!(
+            &read(1),
+            &format!("{}#300\n1!\n#400\n0!\n#500\n1!\n", header)
+        );
+        assert_eq!(&read(2), &format!("{}#600\n0!\n", header));
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn write_annotations() {
+        let vcd = "$timescale 1 ns $end
+$scope module logic $end
+$var wire 1 ! test $end
+$var string 1 \" phase $end
+$upscope $end
+$enddefinitions $end
+#0
+$comment
+    begin OTA
+$end
+sbegin_OTA \"
+0!
+#100
+1!
+"
+        .to_string();
+        let buf = Arc::new(Mutex::new(Vec::new()));
+        let writer = SynchronizedWriter::new(buf.clone());
+        let mut writer = VcdWriterBuilder::new_with_module(writer, "logic").unwrap();
+
+        let mut pin = writer.add_push_pull_pin("test").unwrap();
+        writer.add_annotations("phase").unwrap();
+        let mut writer = writer.build().unwrap();
+        writer.timestamp(Nanoseconds(0u64)).unwrap();
+        writer.annotate("begin OTA").unwrap();
+        writer.sample().unwrap();
+        writer.timestamp(Nanoseconds(100u64)).unwrap();
+        pin.set_high().unwrap();
+        writer.sample().unwrap();
+
+        let writer_vcd = String::from_utf8((*buf.lock().unwrap()).clone()).unwrap();
+        assert_eq!(&writer_vcd, &vcd);
+    }
+
+    #[test]
+    fn write_state_label() {
+        let vcd = "$timescale 1 ns $end
+$scope module logic $end
+$var string 1 ! state $end
+$upscope $end
+$enddefinitions $end
+#0
+sIDLE !
+#100
+sTX_DATA !
+"
+        .to_string();
+        let buf = Arc::new(Mutex::new(Vec::new()));
+        let writer = SynchronizedWriter::new(buf.clone());
+        let mut writer = VcdWriterBuilder::new_with_module(writer, "logic").unwrap();
+
+        let state = writer.add_state_label("state").unwrap();
+        let mut writer = writer.build().unwrap();
+        writer.timestamp(Nanoseconds(0u64)).unwrap();
+        state.store("IDLE");
+        writer.sample().unwrap();
+        writer.timestamp(Nanoseconds(100u64)).unwrap();
+        state.store("TX DATA");
+        writer.sample().unwrap();
+
+        let writer_vcd = String::from_utf8((*buf.lock().unwrap()).clone()).unwrap();
+        assert_eq!(&writer_vcd, &vcd);
+    }
+
+    #[test]
+    fn write_integer() {
+        let vcd = "$timescale 1 ns $end
+$scope module logic $end
+$var integer 8 ! errors $end
+$upscope $end
+$enddefinitions $end
+#0
+b00000000 !
+#100
+b00000101 !
+#200
+b00000001 !
+"
+        .to_string();
+        let buf = Arc::new(Mutex::new(Vec::new()));
+        let writer = SynchronizedWriter::new(buf.clone());
+        let mut writer = VcdWriterBuilder::new_with_module(writer, "logic").unwrap();
+
+        let errors = writer.add_integer("errors", 8).unwrap();
+        let mut writer = writer.build().unwrap();
+        for (t, value) in [(0u64, 0), (100, 5), (200, 0x101)] {
+            writer.timestamp(Nanoseconds(t)).unwrap();
+            errors.store(value);
+            writer.sample().unwrap();
+        }
+
+        let writer_vcd = String::from_utf8((*buf.lock().unwrap()).clone()).unwrap();
+        assert_eq!(&writer_vcd, &vcd);
+    }
+
+    #[test]
+    fn write_real() {
+        let vcd = "$timescale 1 ns $end
+$scope module logic $end
+$var real 64 ! temperature $end
+$upscope $end
+$enddefinitions $end
+#0
+r20 !
+#500
+r22.5 !
+#1000
+r25 !
+"
+        .to_string();
+        let buf = Arc::new(Mutex::new(Vec::new()));
+        let writer = SynchronizedWriter::new(buf.clone());
+        let mut writer = VcdWriterBuilder::new_with_module(writer, "logic").unwrap();
+
+        // a heater ramp of 5 K per µs
+        writer
+            .add_real("temperature", |t: Nanoseconds<u64>| {
+                20.0 + t.0 as f64 * 0.005
+            })
+            .unwrap();
+        let mut writer = writer.build().unwrap();
+        for t in [0u64, 500, 1000] {
+            writer.timestamp(Nanoseconds(t)).unwrap();
+            writer.sample().unwrap();
+        }
+
+        let writer_vcd = String::from_utf8((*buf.lock().unwrap()).clone()).unwrap();
+        assert_eq!(&writer_vcd, &vcd);
+    }
+
+    #[test]
+    fn write_time_source() {
+        let buf = Arc::new(Mutex::new(Vec::new()));
+        let writer = SynchronizedWriter::new(buf.clone());
+        let mut writer = VcdWriterBuilder::new_with_module(writer, "logic").unwrap();
+        let mut pin = writer.add_push_pull_pin("pin").unwrap();
+        let clock = MonotonicClock::new();
+        writer.set_time_source(clock);
+        let mut writer = writer.build().unwrap();
+        for _ in 0..10 {
+            pin.set_high().unwrap();
+            writer.sample().unwrap();
+            pin.set_low().unwrap();
+            writer.sample().unwrap();
+        }
+
+        let vcd = String::from_utf8((*buf.lock().unwrap()).clone()).unwrap();
+        let times: Vec<u64> = vcd
+            .lines()
+            .filter_map(|line| line.strip_prefix('#'))
+            .map(|t| t.parse().unwrap())
+            .collect();
+        assert!(!times.is_empty() && times.len() <= 20);
+        assert!(times.windows(2).all(|w| w[0] < w[1]));
+        assert!(times.last().unwrap() <= &clock.now().0);
+    }
+
+    #[test]
+    fn write_checkpoints() {
+        let buf = Arc::new(Mutex::new(Vec::new()));
+        let writer = SynchronizedWriter::new(buf.clone());
+        let mut writer = VcdWriterBuilder::new_with_module(writer, "logic").unwrap();
+        let mut pin = writer.add_push_pull_pin("pin").unwrap();
+        let mut writer = writer.build().unwrap();
+        assert!(writer.checkpoint_every(Nanoseconds(0u64)).is_err());
+        writer.checkpoint_every(Nanoseconds(1_000u64)).unwrap();
+        for i in 0..100u64 {
+            writer.timestamp(Nanoseconds(i * 70)).unwrap();
+            if i % 6 == 0 {
+                pin.set_high().unwrap();
+            } else if i % 6 == 3 {
+                pin.set_low().unwrap();
+            }
+            writer.sample().unwrap();
+        }
+        drop(writer);
+
+        let vcd = buf.lock().unwrap().clone();
+        let mut reader = crate::reader::VcdReader::from_bytes(&vcd).unwrap();
+        let times: Vec<u64> = reader
+            .sync_points()
+            .unwrap()
+            .iter()
+            .map(|point| Nanoseconds::<u64>::try_from(point.time()).unwrap().0)
+            .collect();
+        pretty_assertions::assert_eq!(times.len(), 7);
+        pretty_assertions::assert_eq!(times[..3], [0, 1_050, 2_030]);
+        let replayed = reader.get_pin(&["logic", "pin"]).unwrap();
+        reader.seek(Nanoseconds(5_000u64)).unwrap();
+        // the pin toggles every 210 ns, the last time at 4830 ns
+        pretty_assertions::assert_eq!(replayed.is_high().unwrap(), 4_830 / 210 % 2 == 0);
+    }
+
+    /// A sink that logs each call.
+    struct LogSink(Arc<Mutex<Vec<String>>>);
+
+    impl ChangeSink for LogSink {
+        fn add_module(&mut self, identifier: &str) -> IOResult<()> {
+            self.0
+                .lock()
+                .unwrap()
+                .push(format!("module {}", identifier));
+            Ok(())
+        }
+
+        fn add_var(
+            &mut self,
+            var_type: vcd::VarType,
+            width: u32,
+            reference: &str,
+        ) -> IOResult<vcd::IdCode> {
+            let mut log = self.0.lock().unwrap();
+            log.push(format!("var {} {} {}", var_type, width, reference));
+            Ok(vcd::IdCode::from(log.len() as u64))
+        }
+
+        fn timestamp(&mut self, time: u64) -> IOResult<()> {
+            self.0.lock().unwrap().push(format!("#{}", time));
+            Ok(())
+        }
+
+        fn change_scalar(&mut self, id: vcd::IdCode, value: vcd::Value) -> IOResult<()> {
+            self.0.lock().unwrap().push(format!("{} {}", id, value));
+            Ok(())
+        }
+
+        fn change_string(&mut self, id: vcd::IdCode, value: &str) -> IOResult<()> {
+            self.0.lock().unwrap().push(format!("{} {:?}", id, value));
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn write_custom_sink() {
+        let log = Arc::new(Mutex::new(vec![]));
+        let mut writer =
+            VcdWriterBuilder::with_sink_and_module(LogSink(log.clone()), "logic").unwrap();
+        let mut pin = writer.add_push_pull_pin("pin").unwrap();
+        let label = writer.add_state_label("state").unwrap();
+        let mut writer = writer.build().unwrap();
+        writer.timestamp(Nanoseconds(0u64)).unwrap();
+        writer.sample().unwrap();
+        pin.set_high().unwrap();
+        label.store("two words");
+        writer.timestamp(Nanoseconds(10u64)).unwrap();
+        writer.sample().unwrap();
+        let ids: Vec<String> = (2..4u64)
+            .map(|i| vcd::IdCode::from(i).to_string())
+            .collect();
+        let expected = vec![
+            "module logic".to_string(),
+            "var wire 1 pin".to_string(),
+            "var string 1 state".to_string(),
+            "#0".to_string(),
+            format!("{} 0", ids[0]),
+            format!("{} \"\"", ids[1]),
+            "#10".to_string(),
+            format!("{} 1", ids[0]),
+            // only VCD files replace whitespace
+            format!("{} \"two words\"", ids[1]),
+        ];
+        pretty_assertions::assert_eq!(*log.lock().unwrap(), expected);
+    }
+
+    #[test]
+    fn write_timestamp_backwards() {
+        let buf = Arc::new(Mutex::new(Vec::new()));
+        let writer = SynchronizedWriter::new(buf.clone());
+        let mut writer = VcdWriterBuilder::new_with_module(writer, "logic").unwrap();
+        let mut pin = writer.add_push_pull_pin("pin").unwrap();
+        let clock = VirtualClock::new();
+        writer.set_time_source(clock.clone());
+        let mut writer = writer.build().unwrap();
+        clock.set(Nanoseconds(1_000u64));
+        writer.sample().unwrap();
+        clock.set(Nanoseconds(400u64));
+        pin.set_high().unwrap();
+        let error = writer.sample().unwrap_err();
+        pretty_assertions::assert_eq!(error.kind(), std::io::ErrorKind::InvalidInput);
+        let error = error.into_inner().unwrap();
+        pretty_assertions::assert_eq!(
+            error.downcast_ref::<TimestampError>(),
+            Some(&TimestampError::Backwards {
+                timestamp: 400,
+                previous: 1_000
+            })
+        );
+        // equal timestamps are fine
+        writer.timestamp(Nanoseconds(1_000u64)).unwrap();
+        drop(writer);
+
+        let vcd = String::from_utf8((*buf.lock().unwrap()).clone()).unwrap();
+        assert!(vcd.ends_with("#1000\n0!\n#1000\n"));
+    }
+
+    #[test]
+    fn write_pin_group() {
+        let vcd = "$timescale 1 ns $end
+$scope module logic $end
+$var wire 4 ! data $end
+$upscope $end
+$enddefinitions $end
+#0
+b0000 !
+#100
+b1001 !
+#200
+bzzzz !
+"
+        .to_string();
+        let buf = Arc::new(Mutex::new(Vec::new()));
+        let writer = SynchronizedWriter::new(buf.clone());
+        let mut writer = VcdWriterBuilder::new_with_module(writer, "logic").unwrap();
+
+        let data = writer.add_pin_group("data", 4).unwrap();
+        let mut writer = writer.build().unwrap();
+        writer.timestamp(Nanoseconds(0u64)).unwrap();
+        writer.sample().unwrap();
+        writer.timestamp(Nanoseconds(100u64)).unwrap();
+        data.write(0b1001);
+        writer.sample().unwrap();
+        writer.timestamp(Nanoseconds(200u64)).unwrap();
+        data.release();
+        writer.sample().unwrap();
+
+        let writer_vcd = String::from_utf8((*buf.lock().unwrap()).clone()).unwrap();
+        assert_eq!(&writer_vcd, &vcd);
+    }
+
+    #[test]
+    fn write_var_types() {
+        let vcd = "$timescale 1 ns $end
+$scope module logic $end
+$var reg 1 ! led $end
+$var event 1 \" irq $end
+$upscope $end
+$enddefinitions $end
+#0
+0!
+#100
+0!
+1\"
+#200
+0!
+#250
+1\"
+#300
+"
+        .to_string();
+        let buf = Arc::new(Mutex::new(Vec::new()));
+        let writer = SynchronizedWriter::new(buf.clone());
+        let mut writer = VcdWriterBuilder::new_with_module(writer, "logic").unwrap();
+
+        writer.set_var_type(VarType::Reg);
+        let _led = writer.add_push_pull_pin("led").unwrap();
+        let mut irq = writer.add_event("irq").unwrap();
+        let mut writer = writer.build().unwrap();
+        for t in 0..3u64 {
+            writer.timestamp(Nanoseconds(t * 100)).unwrap();
+            if t == 1 {
+                irq.set_high().unwrap();
+            }
+            writer.sample().unwrap();
+        }
+        writer.timestamp(Nanoseconds(250u64)).unwrap();
+        irq.fire();
+        writer.timestamp(Nanoseconds(300u64)).unwrap();
+
+        let writer_vcd = String::from_utf8((*buf.lock().unwrap()).clone()).unwrap();
+        assert_eq!(&writer_vcd, &vcd);
+    }
+
+    #[test]
+    fn build_validation() {
+        let builder = VcdWriterBuilder::new(std::io::sink()).unwrap();
+        assert!(builder.build().is_err());
+
+        let mut builder = VcdWriterBuilder::new(std::io::sink()).unwrap();
+        let _cs = builder.add_push_pull_pin("cs").unwrap();
+        builder.add_module("spi").unwrap();
+        let _cs = builder.add_push_pull_pin("cs").unwrap();
+        assert!(builder.build().is_ok());
+
+        let mut builder = VcdWriterBuilder::new(std::io::sink()).unwrap();
+        builder.add_module("spi").unwrap();
+        let _cs = builder.add_push_pull_pin("cs").unwrap();
+        let _cs = builder.add_push_pull_pin("cs").unwrap();
+        let error = builder.build().err().unwrap();
+        assert_eq!(
+            &error.to_string(),
+            "duplicate variable cs in module top.spi"
+        );
+
+        let mut builder = VcdWriterBuilder::new(std::io::sink()).unwrap();
+        let _cs = builder.add_push_pull_pin("cs").unwrap();
+        let _cs = builder.add_open_drain_pin("cs").unwrap();
+        let error = builder.build().err().unwrap();
+        assert_eq!(&error.to_string(), "duplicate variable cs in module top");
+    }
+
+    #[test]
+    fn write_nested_modules() {
+        let vcd = "$timescale 1 ns $end
+$scope module board $end
+$var wire 1 ! led $end
+$scope module spi $end
+$var wire 1 \" cs $end
+$scope module flash $end
+$var wire 1 # wp $end
+$upscope $end
+$upscope $end
+$upscope $end
+$enddefinitions $end
+#0
+0!
+0\"
+0#
+";
+        let buf = Arc::new(Mutex::new(Vec::new()));
+        let writer = SynchronizedWriter::new(buf.clone());
+        let mut builder = VcdWriterBuilder::new_with_module(writer, "board").unwrap();
+        let _led = builder.add_push_pull_pin("led").unwrap();
+        builder.add_module("spi").unwrap();
+        let _cs = builder.add_push_pull_pin("cs").unwrap();
+        builder.add_module("flash").unwrap();
+        let _wp = builder.add_push_pull_pin("wp").unwrap();
+        let mut writer = builder.build().unwrap();
+        writer.timestamp(0.nanoseconds()).unwrap();
+        writer.sample().unwrap();
+
+        let writer_vcd = String::from_utf8((*buf.lock().unwrap()).clone()).unwrap();
+        assert_eq!(&writer_vcd, vcd);
+        let mut reader = crate::reader::VcdReader::new(vcd.as_bytes()).unwrap();
+        pretty_assertions::assert_eq!(
+            reader.get_pin(&["board", "spi", "flash", "wp"]).map(|_| ()),
+            Some(())
+        );
+    }
+
+    #[test]
+    fn write_gtkwave_save() {
+        let mut writer = VcdWriterBuilder::new_with_module(std::io::sink(), "logic").unwrap();
+        let _data = writer.add_bidir_pin("data").unwrap();
+        let _bus = writer.add_pin_group("bus", 12).unwrap();
+        writer.add_module("state").unwrap();
+        let _errors = writer.add_integer("errors", 8).unwrap();
+        writer.add_annotations("phase").unwrap();
+        let writer = writer.build().unwrap();
+        let mut save = vec![];
+        writer
+            .write_gtkwave_save(&mut save, Some("test.vcd"))
+            .unwrap();
+
+        // as saved by GTKWave after inserting the traces
+        let expected = "[dumpfile] \"test.vcd\"
+@200
+-logic
+@28
+logic.data
+@28
+logic.data_oe
+@22
+logic.bus[11:0]
+@200
+-logic.state
+@24
+logic.state.errors[7:0]
+@28
+logic.state.phase
+";
+        assert_eq!(&String::from_utf8(save).unwrap(), expected);
+    }
+
+    proptest::proptest! {
+        #[test]
+        fn write_read_round_trip(
+            samples in proptest::collection::vec((1..1_000u64, proptest::bool::ANY, 0..=u16::MAX), 1..100),
+        ) {
+            let buf = Arc::new(Mutex::new(Vec::new()));
+            let writer = SynchronizedWriter::new(buf.clone());
+            let mut writer = VcdWriterBuilder::new_with_module(writer, "logic").unwrap();
+            let mut pin = writer.add_push_pull_pin("pin").unwrap();
+            let bus = writer.add_pin_group("bus", 16).unwrap();
+            let mut writer = writer.build().unwrap();
+            let mut t = 0;
+            for (gap, high, value) in samples.iter() {
+                t += gap;
+                writer.timestamp(Nanoseconds(t)).unwrap();
+                pin.set_state((*high).into()).unwrap();
+                bus.write(*value as u64);
+                writer.sample().unwrap();
+            }
+            drop(writer);
+
+            let vcd = buf.lock().unwrap().clone();
+            let mut reader = crate::reader::VcdReader::new(&vcd[..]).unwrap();
+            let replayed = reader.get_pin(&["logic", "pin"]).unwrap();
+            let replayed_bus = reader.get_bus(&["logic", "bus"]).unwrap();
+            // the changes of a timestamp are applied with the next one
+            let mut t = 0;
+            let mut previous = None;
+            for (gap, high, value) in samples.iter() {
+                t += gap;
+                let time = reader.next().map(Nanoseconds::<u64>::try_from);
+                proptest::prop_assert_eq!(time, Some(Ok(Nanoseconds(t))));
+                if let Some((high, value)) = previous {
+                    proptest::prop_assert_eq!(replayed.is_high().unwrap(), high);
+                    proptest::prop_assert_eq!(replayed_bus.as_u16(), Ok(value));
+                }
+                previous = Some((*high, *value));
+            }
+            proptest::prop_assert_eq!(reader.next(), None);
+            let (high, value) = previous.unwrap();
+            proptest::prop_assert_eq!(replayed.is_high().unwrap(), high);
+            proptest::prop_assert_eq!(replayed_bus.as_u16(), Ok(value));
+        }
+    }
 }
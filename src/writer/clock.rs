@@ -0,0 +1,95 @@
+//! Time sources for automatic timestamps.
+
+use embedded_time::duration::*;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Arc;
+use std::time::Instant;
+
+/// A source of the current time, consulted by a
+/// [VcdWriter](super::VcdWriter) for each sample, see
+/// [VcdWriterBuilder::set_time_source](super::VcdWriterBuilder::set_time_source).
+///
+/// Closures returning the time implement the trait, e.g. to read a hardware
+/// counter of the target:
+///
+/// ```
+/// use embedded_hal_vcd::writer::TimeSource;
+/// use embedded_time::duration::*;
+///
+/// // a free running counter at 48 MHz
+/// let cycles = || 4_800u64;
+/// let source = move || Nanoseconds(cycles() * 1_000 / 48);
+/// assert_eq!(source.now(), Nanoseconds(100_000u64));
+/// ```
+pub trait TimeSource {
+    /// Return the current time.
+    fn now(&self) -> Nanoseconds<u64>;
+}
+
+impl<F> TimeSource for F
+where
+    F: Fn() -> Nanoseconds<u64>,
+{
+    fn now(&self) -> Nanoseconds<u64> {
+        self()
+    }
+}
+
+/// The wall clock time since the clock was created, based on [Instant].
+#[derive(Clone, Copy, Debug)]
+pub struct MonotonicClock {
+    start: Instant,
+}
+
+impl MonotonicClock {
+    /// Create a new clock starting at zero.
+    pub fn new() -> Self {
+        MonotonicClock {
+            start: Instant::now(),
+        }
+    }
+}
+
+impl Default for MonotonicClock {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl TimeSource for MonotonicClock {
+    fn now(&self) -> Nanoseconds<u64> {
+        Nanoseconds(self.start.elapsed().as_nanos() as u64)
+    }
+}
+
+/// A virtual time that is advanced explicitly, e.g. by a simulated timer.
+///
+/// Clones share the same time, so one clone can be passed to the writer and
+/// another one advanced by the test.
+#[derive(Clone, Debug, Default)]
+pub struct VirtualClock {
+    time: Arc<AtomicU64>,
+}
+
+impl VirtualClock {
+    /// Create a new clock starting at zero.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Set the time.
+    pub fn set(&self, time: Nanoseconds<u64>) {
+        self.time.store(time.0, Ordering::SeqCst);
+    }
+
+    /// Advance the time by a duration.
+    pub fn advance(&self, duration: Nanoseconds<u64>) {
+        self.time.fetch_add(duration.0, Ordering::SeqCst);
+    }
+}
+
+impl TimeSource for VirtualClock {
+    fn now(&self) -> Nanoseconds<u64> {
+        Nanoseconds(self.time.load(Ordering::SeqCst))
+    }
+}
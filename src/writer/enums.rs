@@ -0,0 +1,172 @@
+//! Record enums as buses with readable labels.
+
+use super::{Variable, VcdWriterBuilder};
+use crate::pins::*;
+use std::io::Result as IOResult;
+use std::marker::PhantomData;
+use std::sync::Arc;
+
+/// An enum that can be recorded as a bus, see [VcdWriterBuilder::add_enum].
+///
+/// Implement it with the [bus_enum](crate::bus_enum) macro.
+pub trait BusEnum {
+    /// Return the values and labels of all variants.
+    fn variants() -> &'static [(u64, &'static str)];
+
+    /// Return the value of the variant.
+    fn value(&self) -> u64;
+}
+
+/// Define an enum that implements [BusEnum].
+///
+/// Each variant needs an explicit discriminant, which is used as the bus
+/// value. The variant names are used as labels.
+///
+/// ```
+/// embedded_hal_vcd::bus_enum! {
+///     #[derive(Clone, Copy, Debug)]
+///     pub enum State {
+///         Idle = 0,
+///         Tx = 1,
+///         Error = 7,
+///     }
+/// }
+/// ```
+#[macro_export]
+macro_rules! bus_enum {
+    (
+        $(#[$meta:meta])*
+        $vis:vis enum $name:ident {
+            $($variant:ident = $value:expr),* $(,)?
+        }
+    ) => {
+        $(#[$meta])*
+        $vis enum $name {
+            $($variant = $value),*
+        }
+
+        impl $crate::writer::BusEnum for $name {
+            fn variants() -> &'static [(u64, &'static str)] {
+                &[$(($value, stringify!($variant))),*]
+            }
+
+            fn value(&self) -> u64 {
+                match self {
+                    $($name::$variant => $value),*
+                }
+            }
+        }
+    };
+}
+
+/// A handle to an enum recorded with [VcdWriterBuilder::add_enum].
+pub struct EnumBus<E> {
+    value: Arc<AtomicCounter>,
+    label: Arc<AtomicStateLabel>,
+    _enum: PhantomData<fn(E)>,
+}
+
+impl<E> EnumBus<E>
+where
+    E: BusEnum,
+{
+    /// Change the recorded variant.
+    pub fn set(&self, variant: &E) {
+        let value = variant.value();
+        self.value.store(value);
+        let label = E::variants()
+            .iter()
+            .find(|(v, _)| *v == value)
+            .map(|(_, label)| *label)
+            .unwrap_or("");
+        self.label.store(label);
+    }
+}
+
+/// Return the number of bits needed to record all variants of an enum.
+fn width<E: BusEnum>() -> u32 {
+    let max = E::variants().iter().map(|(v, _)| *v).max().unwrap_or(0);
+    (64 - max.leading_zeros()).max(1)
+}
+
+/// Write a GTKWave translate filter file for an enum.
+///
+/// Load the file in GTKWave with "Data Format → Translate Filter File" on
+/// the bus variable of [VcdWriterBuilder::add_enum] to show the labels.
+pub fn write_gtkwave_filter<E, O>(mut output: O) -> IOResult<()>
+where
+    E: BusEnum,
+    O: std::io::Write,
+{
+    let digits = width::<E>().div_ceil(4) as usize;
+    for (value, label) in E::variants() {
+        writeln!(output, "{:0digits$X} {}", value, label, digits = digits)?;
+    }
+    Ok(())
+}
+
+impl<W> VcdWriterBuilder<W>
+where
+    W: std::io::Write,
+{
+    /// Add an enum with a corresponding named VCD bus variable.
+    ///
+    /// The value of the variant is written to a bus with as many bits as
+    /// needed for all variants. The label of the variant is written to a
+    /// second string variable named `<reference>_label`, use
+    /// [write_gtkwave_filter] to show the labels on the bus itself.
+    pub fn add_enum<E>(&mut self, reference: &str) -> IOResult<EnumBus<E>>
+    where
+        E: BusEnum,
+    {
+        let width = width::<E>();
+        let code = self.add_var(vcd::VarType::Wire, width, reference)?;
+        let label_code = self.add_var(vcd::VarType::String, 1, &format!("{}_label", reference))?;
+        let value = Arc::new(AtomicCounter::default());
+        let label = Arc::new(AtomicStateLabel::default());
+        self.push(code, Variable::Integer(value.clone(), width));
+        self.push(label_code, Variable::Label(label.clone()));
+        Ok(EnumBus {
+            value,
+            label,
+            _enum: PhantomData,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    crate::bus_enum! {
+        enum State {
+            Idle = 0,
+            Tx = 1,
+            Error = 10,
+        }
+    }
+
+    #[test]
+    fn enum_bus() {
+        let mut filter = vec![];
+        write_gtkwave_filter::<State, _>(&mut filter).unwrap();
+        assert_eq!(
+            String::from_utf8(filter).unwrap(),
+            "0 Idle\n1 Tx\nA Error\n"
+        );
+
+        let mut output = vec![];
+        let mut builder = VcdWriterBuilder::new(&mut output).unwrap();
+        let state = builder.add_enum::<State>("state").unwrap();
+        let mut writer = builder.build().unwrap();
+        state.set(&State::Error);
+        writer.sample().unwrap();
+        state.set(&State::Tx);
+        writer.sample().unwrap();
+        drop(writer);
+        let vcd = String::from_utf8(output).unwrap();
+        assert!(vcd.contains("$var wire 4 ! state $end"));
+        assert!(vcd.ends_with("b1010 !\nsError \"\nb0001 !\nsTx \"\n"));
+        assert_eq!(State::Idle.value(), 0);
+    }
+}
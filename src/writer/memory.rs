@@ -0,0 +1,372 @@
+//! Recordings kept in memory.
+
+use super::ChangeSink;
+use crate::analysis::Waveform;
+use crate::pins::PinState;
+use crate::reader::VcdReader;
+use core::borrow::Borrow;
+use embedded_time::duration::*;
+use std::collections::HashMap;
+use std::io::Result as IOResult;
+use std::io::{Cursor, Error, ErrorKind, Write};
+use std::sync::{Arc, Mutex};
+
+/// A recorded value of a variable.
+#[derive(Clone, Debug, PartialEq)]
+enum Change {
+    Scalar(vcd::Value),
+    Vector(Vec<vcd::Value>),
+    Real(f64),
+    String(String),
+}
+
+/// A recorded variable with its changes ordered by time.
+#[derive(Clone, Debug)]
+struct Recorded {
+    path: Vec<String>,
+    var_type: vcd::VarType,
+    width: u32,
+    changes: Vec<(u64, Change)>,
+}
+
+#[derive(Debug, Default)]
+struct Recording {
+    scopes: Vec<String>,
+    vars: Vec<Recorded>,
+    codes: HashMap<vcd::IdCode, usize>,
+    comments: Vec<(u64, String)>,
+    time: u64,
+}
+
+/// A [ChangeSink] that keeps all changes in memory, e.g. for unit tests
+/// that should not touch the filesystem.
+///
+/// Clones share the same recording, so one clone can be passed to
+/// [VcdWriterBuilder::with_sink](super::VcdWriterBuilder::with_sink) and
+/// another one queried while or after recording. The recording can be
+/// exported to a VCD file, or replayed with a [VcdReader].
+///
+/// ```
+/// use embedded_hal::digital::OutputPin;
+/// use embedded_hal_vcd::pins::PinState;
+/// use embedded_hal_vcd::writer::{MemoryRecorder, VcdWriterBuilder};
+/// use embedded_time::duration::*;
+///
+/// let recorder = MemoryRecorder::new();
+/// let mut builder = VcdWriterBuilder::with_sink(recorder.clone()).unwrap();
+/// let mut led = builder.add_push_pull_pin("led").unwrap();
+/// let mut writer = builder.build().unwrap();
+/// for i in 0..4u64 {
+///     if i % 2 == 0 {
+///         led.set_high().unwrap();
+///     } else {
+///         led.set_low().unwrap();
+///     }
+///     writer.timestamp(Nanoseconds(i * 100)).unwrap();
+///     writer.sample().unwrap();
+/// }
+///
+/// let t = Nanoseconds(150u64);
+/// assert_eq!(recorder.value_at(&["top", "led"], t), Some(PinState::Low));
+/// let edges = recorder.edges_between(&["top", "led"], Nanoseconds(100u64), Nanoseconds(300u64));
+/// assert_eq!(
+///     edges,
+///     vec![(Nanoseconds(100), PinState::Low), (Nanoseconds(200), PinState::High)]
+/// );
+/// ```
+#[derive(Clone, Debug, Default)]
+pub struct MemoryRecorder {
+    recording: Arc<Mutex<Recording>>,
+}
+
+impl MemoryRecorder {
+    /// Create a new empty recorder.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Return the full paths of the recorded variables, in the order they
+    /// were added.
+    pub fn paths(&self) -> Vec<Vec<String>> {
+        let recording = self.recording.lock().unwrap();
+        recording.vars.iter().map(|var| var.path.clone()).collect()
+    }
+
+    /// Return the waveform of a named scalar variable, `None` if the
+    /// variable is not found.
+    pub fn waveform<S>(&self, path: &[S]) -> Option<Waveform>
+    where
+        S: Borrow<str>,
+    {
+        let recording = self.recording.lock().unwrap();
+        let var = recording.vars.iter().find(|var| {
+            var.path
+                .iter()
+                .map(String::as_str)
+                .eq(path.iter().map(Borrow::borrow))
+        })?;
+        let changes = var
+            .changes
+            .iter()
+            .filter_map(|(t, change)| match change {
+                Change::Scalar(value) => Some((Nanoseconds(*t), (*value).into())),
+                _ => None,
+            })
+            .collect();
+        Some(Waveform::new(changes))
+    }
+
+    /// Return the state of a named scalar variable at an arbitrary time.
+    ///
+    /// Changes at `t` are included. Returns `None` if the variable is not
+    /// found or has no value at `t`.
+    pub fn value_at<S>(&self, path: &[S], t: Nanoseconds<u64>) -> Option<PinState>
+    where
+        S: Borrow<str>,
+    {
+        self.waveform(path)?.state_at(t)
+    }
+
+    /// Return the changes of the state of a named scalar variable from
+    /// `from` until before `until`.
+    ///
+    /// Returns no changes if the variable is not found.
+    pub fn edges_between<S>(
+        &self,
+        path: &[S],
+        from: Nanoseconds<u64>,
+        until: Nanoseconds<u64>,
+    ) -> Vec<(Nanoseconds<u64>, PinState)>
+    where
+        S: Borrow<str>,
+    {
+        self.waveform(path)
+            .map(|waveform| {
+                waveform
+                    .changes()
+                    .iter()
+                    .filter(|(t, _)| from <= *t && *t < until)
+                    .cloned()
+                    .collect()
+            })
+            .unwrap_or_default()
+    }
+
+    /// Write the recording to a VCD file.
+    pub fn write_vcd<W: Write>(&self, output: W) -> IOResult<()> {
+        let recording = self.recording.lock().unwrap();
+        let mut header = vcd::Header::default();
+        header.timescale = Some((1, vcd::TimescaleUnit::NS));
+        let mut changes = vec![];
+        for (index, var) in recording.vars.iter().enumerate() {
+            let code = vcd::IdCode::from(index as u64);
+            let (reference, scopes) = var.path.split_last().unwrap();
+            let scopes: Vec<&str> = scopes.iter().map(String::as_str).collect();
+            let var_def = vcd::Var {
+                var_type: var.var_type,
+                size: var.width,
+                code,
+                reference: reference.clone(),
+                index: None,
+            };
+            crate::export::jsonl::insert_var(&mut header.items, &scopes, var_def);
+            changes.extend(
+                var.changes
+                    .iter()
+                    .map(|(t, change)| (*t, Some((code, change)), "")),
+            );
+        }
+        let comments = recording.comments.iter();
+        changes.extend(comments.map(|(t, text)| (*t, None, text.as_str())));
+        // a stable sort keeps the order of the changes of a variable
+        changes.sort_by_key(|(t, _, _)| *t);
+        let mut writer = vcd::Writer::new(output);
+        writer.header(&header)?;
+        let mut time = None;
+        for (t, change, text) in changes {
+            if time != Some(t) {
+                writer.timestamp(t)?;
+                time = Some(t);
+            }
+            match change {
+                Some((code, Change::Scalar(value))) => writer.change_scalar(code, *value)?,
+                Some((code, Change::Vector(value))) => writer.change_vector(code, value)?,
+                Some((code, Change::Real(value))) => writer.change_real(code, *value)?,
+                Some((code, Change::String(value))) => {
+                    ChangeSink::change_string(&mut writer, code, value)?
+                }
+                None => writer.comment(text)?,
+            }
+        }
+        Ok(())
+    }
+
+    /// Return a reader that replays the recording.
+    pub fn reader(&self) -> IOResult<VcdReader<Cursor<Vec<u8>>>> {
+        let mut vcd = vec![];
+        self.write_vcd(&mut vcd)?;
+        VcdReader::new(Cursor::new(vcd))
+    }
+
+    fn push(&self, id: vcd::IdCode, change: Change) -> IOResult<()> {
+        let mut recording = self.recording.lock().unwrap();
+        let time = recording.time;
+        let index = *recording
+            .codes
+            .get(&id)
+            .ok_or_else(|| Error::new(ErrorKind::InvalidInput, "unknown variable"))?;
+        recording.vars[index].changes.push((time, change));
+        Ok(())
+    }
+}
+
+impl ChangeSink for MemoryRecorder {
+    fn add_module(&mut self, identifier: &str) -> IOResult<()> {
+        let mut recording = self.recording.lock().unwrap();
+        recording.scopes.push(identifier.to_string());
+        Ok(())
+    }
+
+    fn upscope(&mut self) -> IOResult<()> {
+        let mut recording = self.recording.lock().unwrap();
+        recording.scopes.pop();
+        Ok(())
+    }
+
+    fn add_var(
+        &mut self,
+        var_type: vcd::VarType,
+        width: u32,
+        reference: &str,
+    ) -> IOResult<vcd::IdCode> {
+        let mut recording = self.recording.lock().unwrap();
+        let mut path = recording.scopes.clone();
+        path.push(reference.to_string());
+        let index = recording.vars.len();
+        let id = vcd::IdCode::from(index as u64);
+        recording.codes.insert(id, index);
+        recording.vars.push(Recorded {
+            path,
+            var_type,
+            width,
+            changes: vec![],
+        });
+        Ok(id)
+    }
+
+    fn timestamp(&mut self, time: u64) -> IOResult<()> {
+        self.recording.lock().unwrap().time = time;
+        Ok(())
+    }
+
+    fn change_scalar(&mut self, id: vcd::IdCode, value: vcd::Value) -> IOResult<()> {
+        self.push(id, Change::Scalar(value))
+    }
+
+    fn change_vector(&mut self, id: vcd::IdCode, value: &[vcd::Value]) -> IOResult<()> {
+        self.push(id, Change::Vector(value.to_vec()))
+    }
+
+    fn change_real(&mut self, id: vcd::IdCode, value: f64) -> IOResult<()> {
+        self.push(id, Change::Real(value))
+    }
+
+    fn change_string(&mut self, id: vcd::IdCode, value: &str) -> IOResult<()> {
+        self.push(id, Change::String(value.to_string()))
+    }
+
+    fn comment(&mut self, text: &str) -> IOResult<()> {
+        let mut recording = self.recording.lock().unwrap();
+        let time = recording.time;
+        recording.comments.push((time, text.to_string()));
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::writer::VcdWriterBuilder;
+    use embedded_hal::digital::{InputPin as _, OutputPin};
+
+    #[test]
+    fn record_in_memory() {
+        let recorder = MemoryRecorder::new();
+        let mut builder = VcdWriterBuilder::with_sink_and_module(recorder.clone(), "rig").unwrap();
+        let mut clk = builder.add_push_pull_pin("clk").unwrap();
+        let data = builder.add_pin_group("data", 4).unwrap();
+        builder.add_module("fw").unwrap();
+        let state = builder.add_state_label("state").unwrap();
+        let mut writer = builder.build().unwrap();
+        assert_eq!(
+            recorder.paths(),
+            vec![
+                vec!["rig".to_string(), "clk".to_string()],
+                vec!["rig".to_string(), "data".to_string()],
+                vec!["rig".to_string(), "fw".to_string(), "state".to_string()],
+            ]
+        );
+        state.store("idle");
+        for i in 0..10u64 {
+            if i % 2 == 0 {
+                clk.set_high().unwrap();
+            } else {
+                clk.set_low().unwrap();
+            }
+            data.write(i);
+            writer.timestamp(Nanoseconds(i * 50)).unwrap();
+            if i == 5 {
+                state.store("in reset");
+                writer.annotate("reset").unwrap();
+            }
+            writer.sample().unwrap();
+        }
+        drop(writer);
+
+        assert_eq!(
+            recorder.value_at(&["rig", "clk"], Nanoseconds(260u64)),
+            Some(PinState::Low)
+        );
+        assert_eq!(
+            recorder.value_at(&["rig", "clk"], Nanoseconds(0u64)),
+            Some(PinState::High)
+        );
+        assert_eq!(
+            recorder.value_at(&["rig", "missing"], Nanoseconds(0u64)),
+            None
+        );
+        // vectors have no scalar state
+        assert_eq!(recorder.value_at(&["rig", "data"], Nanoseconds(0u64)), None);
+        let edges =
+            recorder.edges_between(&["rig", "clk"], Nanoseconds(100u64), Nanoseconds(200u64));
+        assert_eq!(
+            edges,
+            vec![
+                (Nanoseconds(100), PinState::High),
+                (Nanoseconds(150), PinState::Low)
+            ]
+        );
+        assert!(recorder
+            .edges_between(&["rig", "clk"], Nanoseconds(500u64), Nanoseconds(600u64))
+            .is_empty());
+
+        let mut vcd = vec![];
+        recorder.write_vcd(&mut vcd).unwrap();
+        let text = String::from_utf8(vcd.clone()).unwrap();
+        assert!(text.find("#250") < text.find("reset"));
+        assert!(text.contains("sin_reset"));
+        let signals = Waveform::read_all(&vcd[..]).unwrap();
+        assert_eq!(signals[0].0, "rig.clk");
+        assert_eq!(
+            Some(signals[0].1.clone()),
+            recorder.waveform(&["rig", "clk"])
+        );
+
+        let mut reader = recorder.reader().unwrap();
+        let data = reader.get_bus(&["rig", "data"]).unwrap();
+        let clk = reader.get_pin(&["rig", "clk"]).unwrap();
+        reader.seek(Nanoseconds(150u64)).unwrap();
+        assert_eq!(data.read(), Some(3));
+        assert!(clk.is_low().unwrap());
+    }
+}
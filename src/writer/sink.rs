@@ -0,0 +1,328 @@
+//! Outputs for the values recorded by a writer.
+
+use super::escape_string;
+use std::io::Result as IOResult;
+use std::io::Write;
+
+/// An output for the variables and values recorded by a
+/// [VcdWriter](super::VcdWriter), see [VcdWriterBuilder::with_sink](super::VcdWriterBuilder::with_sink).
+///
+/// The builder declares the modules and variables, the writer writes
+/// timestamps and the values of each sample. All pins, buses, events and
+/// protocol recorders of the crate work with any sink, so the output format
+/// can be chosen at runtime, e.g. a VCD file with a [vcd::Writer] or a CSV
+/// file or database with a custom sink. Times are in nanoseconds.
+///
+/// ```
+/// use embedded_hal::digital::OutputPin;
+/// use embedded_hal_vcd::writer::{ChangeSink, VcdWriterBuilder};
+/// use embedded_time::duration::*;
+/// use std::collections::HashMap;
+/// use std::io::Result;
+/// use std::sync::{Arc, Mutex};
+///
+/// /// Print the changes of scalar variables as `time,name,value` lines.
+/// #[derive(Default)]
+/// struct Csv {
+///     names: HashMap<vcd::IdCode, String>,
+///     time: u64,
+///     lines: Arc<Mutex<Vec<String>>>,
+/// }
+///
+/// impl ChangeSink for Csv {
+///     fn add_var(
+///         &mut self,
+///         _var_type: vcd::VarType,
+///         _width: u32,
+///         reference: &str,
+///     ) -> Result<vcd::IdCode> {
+///         let id = vcd::IdCode::from(self.names.len() as u64);
+///         self.names.insert(id, reference.to_string());
+///         Ok(id)
+///     }
+///
+///     fn timestamp(&mut self, time: u64) -> Result<()> {
+///         self.time = time;
+///         Ok(())
+///     }
+///
+///     fn change_scalar(&mut self, id: vcd::IdCode, value: vcd::Value) -> Result<()> {
+///         let name = &self.names[&id];
+///         let line = format!("{},{},{}", self.time, name, value);
+///         self.lines.lock().unwrap().push(line);
+///         Ok(())
+///     }
+/// }
+///
+/// let csv = Csv::default();
+/// let lines = csv.lines.clone();
+/// let mut builder = VcdWriterBuilder::with_sink(csv).unwrap();
+/// let mut led = builder.add_push_pull_pin("led").unwrap();
+/// let mut writer = builder.build().unwrap();
+/// writer.timestamp(Nanoseconds(0u64)).unwrap();
+/// writer.sample().unwrap();
+/// led.set_high().unwrap();
+/// writer.timestamp(Nanoseconds(100u64)).unwrap();
+/// writer.sample().unwrap();
+/// assert_eq!(*lines.lock().unwrap(), vec!["0,led,0", "100,led,1"]);
+/// ```
+pub trait ChangeSink {
+    /// Set the timescale, always 1 ns for the writer. Ignored by default.
+    fn timescale(&mut self, _scale: u32, _unit: vcd::TimescaleUnit) -> IOResult<()> {
+        Ok(())
+    }
+
+    /// Open a module for the variables added hereafter. Ignored by default.
+    fn add_module(&mut self, _identifier: &str) -> IOResult<()> {
+        Ok(())
+    }
+
+    /// Close the current module. Ignored by default.
+    fn upscope(&mut self) -> IOResult<()> {
+        Ok(())
+    }
+
+    /// Declare a variable and return its unique identifier.
+    fn add_var(
+        &mut self,
+        var_type: vcd::VarType,
+        width: u32,
+        reference: &str,
+    ) -> IOResult<vcd::IdCode>;
+
+    /// End the declarations. Ignored by default.
+    fn enddefinitions(&mut self) -> IOResult<()> {
+        Ok(())
+    }
+
+    /// Set the time of the following changes.
+    fn timestamp(&mut self, time: u64) -> IOResult<()>;
+
+    /// Write the value of a scalar variable.
+    fn change_scalar(&mut self, id: vcd::IdCode, value: vcd::Value) -> IOResult<()>;
+
+    /// Write the value of a vector variable, the most significant bit first.
+    /// Ignored by default.
+    fn change_vector(&mut self, _id: vcd::IdCode, _value: &[vcd::Value]) -> IOResult<()> {
+        Ok(())
+    }
+
+    /// Write the value of a real variable. Ignored by default.
+    fn change_real(&mut self, _id: vcd::IdCode, _value: f64) -> IOResult<()> {
+        Ok(())
+    }
+
+    /// Write the value of a string variable, e.g. a state label. Ignored by
+    /// default.
+    fn change_string(&mut self, _id: vcd::IdCode, _value: &str) -> IOResult<()> {
+        Ok(())
+    }
+
+    /// Write a free-form annotation. Ignored by default.
+    fn comment(&mut self, _text: &str) -> IOResult<()> {
+        Ok(())
+    }
+
+    /// Begin a section, e.g. a `$dumpall` checkpoint. Ignored by default.
+    fn begin(&mut self, _command: vcd::SimulationCommand) -> IOResult<()> {
+        Ok(())
+    }
+
+    /// End the current section. Ignored by default.
+    fn end(&mut self) -> IOResult<()> {
+        Ok(())
+    }
+}
+
+/// Write a VCD file, string values have their whitespace replaced by `_`.
+impl<W: Write> ChangeSink for vcd::Writer<W> {
+    fn timescale(&mut self, scale: u32, unit: vcd::TimescaleUnit) -> IOResult<()> {
+        vcd::Writer::timescale(self, scale, unit)
+    }
+
+    fn add_module(&mut self, identifier: &str) -> IOResult<()> {
+        vcd::Writer::add_module(self, identifier)
+    }
+
+    fn upscope(&mut self) -> IOResult<()> {
+        vcd::Writer::upscope(self)
+    }
+
+    fn add_var(
+        &mut self,
+        var_type: vcd::VarType,
+        width: u32,
+        reference: &str,
+    ) -> IOResult<vcd::IdCode> {
+        vcd::Writer::add_var(self, var_type, width, reference, None)
+    }
+
+    fn enddefinitions(&mut self) -> IOResult<()> {
+        vcd::Writer::enddefinitions(self)
+    }
+
+    fn timestamp(&mut self, time: u64) -> IOResult<()> {
+        vcd::Writer::timestamp(self, time)
+    }
+
+    fn change_scalar(&mut self, id: vcd::IdCode, value: vcd::Value) -> IOResult<()> {
+        vcd::Writer::change_scalar(self, id, value)
+    }
+
+    fn change_vector(&mut self, id: vcd::IdCode, value: &[vcd::Value]) -> IOResult<()> {
+        vcd::Writer::change_vector(self, id, value)
+    }
+
+    fn change_real(&mut self, id: vcd::IdCode, value: f64) -> IOResult<()> {
+        vcd::Writer::change_real(self, id, value)
+    }
+
+    fn change_string(&mut self, id: vcd::IdCode, value: &str) -> IOResult<()> {
+        vcd::Writer::change_string(self, id, &escape_string(value))
+    }
+
+    fn comment(&mut self, text: &str) -> IOResult<()> {
+        vcd::Writer::comment(self, text)
+    }
+
+    fn begin(&mut self, command: vcd::SimulationCommand) -> IOResult<()> {
+        vcd::Writer::begin(self, command)
+    }
+
+    fn end(&mut self) -> IOResult<()> {
+        vcd::Writer::end(self)
+    }
+}
+
+impl<S: ChangeSink + ?Sized> ChangeSink for Box<S> {
+    fn timescale(&mut self, scale: u32, unit: vcd::TimescaleUnit) -> IOResult<()> {
+        (**self).timescale(scale, unit)
+    }
+
+    fn add_module(&mut self, identifier: &str) -> IOResult<()> {
+        (**self).add_module(identifier)
+    }
+
+    fn upscope(&mut self) -> IOResult<()> {
+        (**self).upscope()
+    }
+
+    fn add_var(
+        &mut self,
+        var_type: vcd::VarType,
+        width: u32,
+        reference: &str,
+    ) -> IOResult<vcd::IdCode> {
+        (**self).add_var(var_type, width, reference)
+    }
+
+    fn enddefinitions(&mut self) -> IOResult<()> {
+        (**self).enddefinitions()
+    }
+
+    fn timestamp(&mut self, time: u64) -> IOResult<()> {
+        (**self).timestamp(time)
+    }
+
+    fn change_scalar(&mut self, id: vcd::IdCode, value: vcd::Value) -> IOResult<()> {
+        (**self).change_scalar(id, value)
+    }
+
+    fn change_vector(&mut self, id: vcd::IdCode, value: &[vcd::Value]) -> IOResult<()> {
+        (**self).change_vector(id, value)
+    }
+
+    fn change_real(&mut self, id: vcd::IdCode, value: f64) -> IOResult<()> {
+        (**self).change_real(id, value)
+    }
+
+    fn change_string(&mut self, id: vcd::IdCode, value: &str) -> IOResult<()> {
+        (**self).change_string(id, value)
+    }
+
+    fn comment(&mut self, text: &str) -> IOResult<()> {
+        (**self).comment(text)
+    }
+
+    fn begin(&mut self, command: vcd::SimulationCommand) -> IOResult<()> {
+        (**self).begin(command)
+    }
+
+    fn end(&mut self) -> IOResult<()> {
+        (**self).end()
+    }
+}
+
+/// The output of a builder and writer, a VCD file or a custom sink.
+pub(crate) enum Backend<W: Write> {
+    Vcd(vcd::Writer<W>),
+    Sink(Box<dyn ChangeSink + Send>),
+}
+
+impl<W: Write> Backend<W> {
+    fn sink(&mut self) -> &mut dyn ChangeSink {
+        match self {
+            Backend::Vcd(writer) => writer,
+            Backend::Sink(sink) => sink.as_mut(),
+        }
+    }
+}
+
+impl<W: Write> ChangeSink for Backend<W> {
+    fn timescale(&mut self, scale: u32, unit: vcd::TimescaleUnit) -> IOResult<()> {
+        self.sink().timescale(scale, unit)
+    }
+
+    fn add_module(&mut self, identifier: &str) -> IOResult<()> {
+        self.sink().add_module(identifier)
+    }
+
+    fn upscope(&mut self) -> IOResult<()> {
+        self.sink().upscope()
+    }
+
+    fn add_var(
+        &mut self,
+        var_type: vcd::VarType,
+        width: u32,
+        reference: &str,
+    ) -> IOResult<vcd::IdCode> {
+        self.sink().add_var(var_type, width, reference)
+    }
+
+    fn enddefinitions(&mut self) -> IOResult<()> {
+        self.sink().enddefinitions()
+    }
+
+    fn timestamp(&mut self, time: u64) -> IOResult<()> {
+        self.sink().timestamp(time)
+    }
+
+    fn change_scalar(&mut self, id: vcd::IdCode, value: vcd::Value) -> IOResult<()> {
+        self.sink().change_scalar(id, value)
+    }
+
+    fn change_vector(&mut self, id: vcd::IdCode, value: &[vcd::Value]) -> IOResult<()> {
+        self.sink().change_vector(id, value)
+    }
+
+    fn change_real(&mut self, id: vcd::IdCode, value: f64) -> IOResult<()> {
+        self.sink().change_real(id, value)
+    }
+
+    fn change_string(&mut self, id: vcd::IdCode, value: &str) -> IOResult<()> {
+        self.sink().change_string(id, value)
+    }
+
+    fn comment(&mut self, text: &str) -> IOResult<()> {
+        self.sink().comment(text)
+    }
+
+    fn begin(&mut self, command: vcd::SimulationCommand) -> IOResult<()> {
+        self.sink().begin(command)
+    }
+
+    fn end(&mut self) -> IOResult<()> {
+        self.sink().end()
+    }
+}
@@ -0,0 +1,77 @@
+//! Run the `vcdhal` binary on the example data.
+
+use std::io::Write;
+use std::process::{Command, Output, Stdio};
+
+const TEST_VCD: &str = "examples/data/test.vcd";
+
+fn vcdhal(args: &[&str], input: Option<&[u8]>) -> Output {
+    let mut child = Command::new(env!("CARGO_BIN_EXE_vcdhal"))
+        .args(args)
+        .current_dir(env!("CARGO_MANIFEST_DIR"))
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .spawn()
+        .unwrap();
+    let mut stdin = child.stdin.take().unwrap();
+    stdin.write_all(input.unwrap_or_default()).unwrap();
+    drop(stdin);
+    child.wait_with_output().unwrap()
+}
+
+fn stdout(output: Output) -> String {
+    assert!(output.status.success(), "{:?}", output);
+    String::from_utf8(output.stdout).unwrap()
+}
+
+#[test]
+fn stats() {
+    assert_eq!(
+        stdout(vcdhal(&["stats", TEST_VCD], None)),
+        "toggled 1/1\n  libsigrok.data rising=35879 falling=35879\n"
+    );
+}
+
+#[test]
+fn decode_uart() {
+    let bytes = stdout(vcdhal(
+        &["decode", "uart", TEST_VCD, "libsigrok.data"],
+        None,
+    ));
+    let lines: Vec<&str> = bytes.lines().take(4).collect();
+    assert_eq!(lines, ["5000 35", "599000 66", "1160000 33", "1722000 66"]);
+    let output = vcdhal(&["decode", "uart", TEST_VCD, "libsigrok.missing"], None);
+    assert_eq!(output.status.code(), Some(2));
+}
+
+#[test]
+fn wavedrom_round_trip() {
+    let diagram = stdout(vcdhal(&["wavedrom", TEST_VCD, "10000000"], None));
+    assert!(diagram.starts_with(
+        "{\"signal\": [\n  {\"name\": \"libsigrok.data\", \"wave\": \"10.1..0.1..0..1...0101"
+    ));
+    let vcd = stdout(vcdhal(
+        &["from-wavedrom", "-", "10000000"],
+        Some(diagram.as_bytes()),
+    ));
+    let again = stdout(vcdhal(&["wavedrom", "-", "10000000"], Some(vcd.as_bytes())));
+    let wave = |diagram: &str| {
+        let start = diagram.find("\"wave\": \"").unwrap() + 9;
+        let wave = &diagram[start..];
+        wave[..wave.find('"').unwrap()]
+            .trim_end_matches('.')
+            .to_string()
+    };
+    assert!(again.contains("\"name\": \"top.libsigrok.data\""));
+    assert_eq!(wave(&again), wave(&diagram));
+}
+
+#[test]
+fn usage() {
+    let output = vcdhal(&["decode", "can", TEST_VCD], None);
+    assert_eq!(output.status.code(), Some(2));
+    let usage = String::from_utf8(output.stderr).unwrap();
+    assert!(usage.starts_with("usage: vcdhal <command> [arguments]"));
+    assert!(usage.contains("decode uart <file.vcd> <signal> [baud]"));
+}